@@ -0,0 +1,171 @@
+use rweb::{
+    http::{
+        header::{HeaderValue, CONTENT_TYPE},
+        uri::{PathAndQuery, Uri},
+        StatusCode,
+    },
+    hyper::{Body, Request, Response},
+    openapi::{Server, Spec},
+};
+use serde_json::json;
+use stack_string::{format_sstr, StackString};
+use std::fmt::Display;
+
+use diary_app_lib::config::ConfigInner;
+
+use crate::errors::ErrorCode;
+
+/// Version served by `/api/...` paths without a version
+pub const API_VERSION: u32 = 1;
+
+/// Versions `/api/v{n}/...` paths and the `Api-Version` header may ask for
+pub const SUPPORTED_VERSIONS: [u32; 1] = [1];
+
+/// Sent by clients to pick a version without changing the path, and set on
+/// every response to the version which served it
+pub const VERSION_HEADER: &str = "api-version";
+
+fn parse_version(version: &str) -> Option<u32> {
+    let version = version.trim();
+    version.strip_prefix('v').unwrap_or(version).parse().ok()
+}
+
+fn unsupported(version: impl Display) -> StackString {
+    format_sstr!("Unsupported api version {version}")
+}
+
+/// Version serving `path` and the path of the route to serve it with,
+/// `/api/v1/search` is served by `/api/search`, `None` if `path` doesn't
+/// need rewriting
+/// # Errors
+/// Return error message if the version in the path or header isn't
+/// supported
+pub fn negotiate(
+    path: &str,
+    requested: Option<&str>,
+) -> Result<(u32, Option<StackString>), StackString> {
+    if let Some(rest) = path.strip_prefix("/api/v") {
+        let (version, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        if let Ok(version) = version.parse::<u32>() {
+            if !SUPPORTED_VERSIONS.contains(&version) {
+                return Err(unsupported(format_sstr!("v{version}")));
+            }
+            return Ok((version, Some(format_sstr!("/api/{rest}"))));
+        }
+    }
+    match requested {
+        None => Ok((API_VERSION, None)),
+        Some(requested) => match parse_version(requested) {
+            Some(version) if SUPPORTED_VERSIONS.contains(&version) => Ok((version, None)),
+            _ => Err(unsupported(requested)),
+        },
+    }
+}
+
+fn error_response(status: StatusCode, error_code: ErrorCode, message: &str) -> Response<Body> {
+    let body = json!({
+        "code": status.as_u16(),
+        "error_code": error_code,
+        "message": message,
+    })
+    .to_string();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap_or_default()
+}
+
+fn bad_request(e: impl Display) -> Response<Body> {
+    error_response(
+        StatusCode::BAD_REQUEST,
+        ErrorCode::BadRequest,
+        &e.to_string(),
+    )
+}
+
+/// Point versioned requests at the unversioned routes
+/// # Errors
+/// Return the response to send if the requested version isn't supported
+pub fn route_request(mut req: Request<Body>) -> Result<(u32, Request<Body>), Response<Body>> {
+    let requested = req
+        .headers()
+        .get(VERSION_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let (version, path) = negotiate(req.uri().path(), requested)
+        .map_err(|message| error_response(StatusCode::NOT_FOUND, ErrorCode::NotFound, &message))?;
+    if let Some(path) = path {
+        let path_and_query = match req.uri().query() {
+            Some(query) => format_sstr!("{path}?{query}"),
+            None => path,
+        };
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query =
+            Some(PathAndQuery::try_from(path_and_query.as_str()).map_err(bad_request)?);
+        *req.uri_mut() = Uri::from_parts(parts).map_err(bad_request)?;
+    }
+    Ok((version, req))
+}
+
+/// Tell the client which version served the request
+#[must_use]
+pub fn with_version(mut resp: Response<Body>, version: u32) -> Response<Body> {
+    resp.headers_mut()
+        .insert(VERSION_HEADER, HeaderValue::from(version));
+    resp
+}
+
+/// Document the api relative to `/api/v{API_VERSION}` on the public domain
+/// and on the local port, generated clients then use the versioned paths
+pub fn set_servers(spec: &mut Spec, config: &ConfigInner, port: u32) {
+    let paths = std::mem::take(&mut spec.paths);
+    spec.paths = paths
+        .into_iter()
+        .map(|(path, item)| {
+            let stripped = path.strip_prefix("/api").map(ToString::to_string);
+            (stripped.map_or(path, Into::into), item)
+        })
+        .collect();
+    spec.servers = vec![
+        Server {
+            url: format!(
+                "{}://{}/api/v{API_VERSION}",
+                config.api_scheme, config.domain
+            )
+            .into(),
+            description: "Public".into(),
+            ..Server::default()
+        },
+        Server {
+            url: format!("http://localhost:{port}/api/v{API_VERSION}").into(),
+            description: "Local".into(),
+            ..Server::default()
+        },
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api_version::{negotiate, API_VERSION};
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("/api/search", None), Ok((API_VERSION, None)));
+        assert_eq!(
+            negotiate("/api/v1/search", None),
+            Ok((1, Some("/api/search".into())))
+        );
+        assert_eq!(
+            negotiate("/api/v1/entry/section", Some("v2")),
+            Ok((1, Some("/api/entry/section".into())))
+        );
+        assert_eq!(negotiate("/api/v1", None), Ok((1, Some("/api/".into()))));
+        assert_eq!(negotiate("/api/search", Some("1")), Ok((1, None)));
+        assert_eq!(negotiate("/api/search", Some("v1")), Ok((1, None)));
+        assert!(negotiate("/api/v2/search", None).is_err());
+        assert!(negotiate("/api/search", Some("v2")).is_err());
+        assert!(negotiate("/api/search", Some("latest")).is_err());
+        // only digits after the v make a version
+        assert_eq!(negotiate("/api/verify", None), Ok((API_VERSION, None)));
+    }
+}
@@ -1,45 +1,127 @@
-use anyhow::Error;
+use anyhow::{format_err, Error};
+use futures::{stream::unfold, Stream, StreamExt};
 use handlebars::Handlebars;
-use log::{error, info};
+use log::{debug, error, info};
 use notify::{
     recommended_watcher, Event, EventHandler, EventKind, INotifyWatcher, RecursiveMode,
     Result as NotifyResult, Watcher,
 };
 use rweb::{
-    filters::BoxedFilter,
+    filters::{
+        sse::{self, Event as SseEvent},
+        ws::{Message as WsMessage, WebSocket, Ws},
+        BoxedFilter,
+    },
     http::header::CONTENT_TYPE,
+    hyper::{
+        service::{make_service_fn, service_fn, Service},
+        Body, Request, Server,
+    },
     openapi::{self, Info},
     Filter, Reply,
 };
-use stack_string::format_sstr;
+use stack_string::{format_sstr, StackString};
 use std::{
-    collections::HashSet,
+    collections::HashMap,
+    convert::Infallible,
+    fs::create_dir_all,
     net::SocketAddr,
     ops::Deref,
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use time::{macros::format_description, Date};
+use time::{macros::format_description, Date, OffsetDateTime};
 use tokio::{
-    sync::watch::{channel, Receiver, Sender},
-    time::{interval, sleep},
+    sync::{
+        broadcast::{error::RecvError, Receiver as BroadcastReceiver},
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        watch::Receiver,
+    },
+    time::{interval, timeout_at},
 };
 
-use diary_app_lib::{config::Config, diary_app_interface::DiaryAppInterface, pgpool::PgPool};
+use diary_app_bot::telegram_bot::TelegramWebhook;
+use diary_app_lib::{
+    change_feed::{ChangeFeed, DiaryChange},
+    config::Config,
+    db_migrations::check_schema,
+    diary_app_interface::DiaryAppInterface,
+    error_reporting,
+    guestbook::Guestbook,
+    kiosk::Kiosk,
+    local_interface::parse_local_path,
+    maintenance::MaintenanceJobs,
+    models::{DateRange, API_SOURCE},
+    pgpool::PgPool,
+    sync_lock::{sync_holder, SyncLock, SyncLockError, SyncLockMode},
+    sync_progress::SyncProgress,
+    tenants::{load_tenants, Tenant},
+    unlock::UnlockSessions,
+};
 
 use super::{
-    errors::error_response,
-    logged_user::{fill_from_db, get_secrets},
+    api_version::{route_request, set_servers, with_version},
+    assets::{self, asset, content_type, get_asset},
+    caching::{cache_response, entry_validator, not_modified},
+    compression::compress_response,
+    errors::{error_response, ServiceError},
+    security::SecurityHeaders,
+    tenancy::TenantRouter,
+    logged_user::{fill_from_db, get_secrets, LoggedUser},
     routes::{
-        commit_conflict, diary_frontpage, display, edit, insert, list, list_conflicts,
-        remove_conflict, replace, search, show_conflict, sync, update_conflict, user,
+        activity, add_comment, add_user, append, commit_conflict, create_journal, dashboard,
+        delete_comment, delete_entry, diary_frontpage, diff, disable_user, display, edit,
+        entries_meta, get_metadata, get_section, get_settings, guestbook, habit_stats, insert,
+        insert_batch, kiosk, link_telegram, list, list_comments, list_conflicts, list_encrypted,
+        list_journals, list_maintenance, list_trash, list_users, lock, mobile_sync, patch_entry,
+        peer_pull, peer_push, print, purge_trash, redact, remove_conflict, replace,
+        replace_encrypted, replace_section, restore_trash, schedule, search, set_telegram_user,
+        show_conflict, star, start_maintenance, stats, storage_stats, sync, sync_date, sync_lock,
+        telegram_webhook, toggle_private, unlock, update_comment, update_conflict, update_metadata,
+        update_settings, user, word_stats,
     },
 };
 
 #[derive(Clone)]
 pub struct DiaryAppActor(pub DiaryAppInterface);
 
+impl DiaryAppActor {
+    /// Scope requests to `journal`, requests go to the default journal if
+    /// none is given
+    #[must_use]
+    pub fn with_journal(&self, journal: Option<&str>) -> Self {
+        match journal {
+            Some(journal) => Self(self.0.clone().with_journal(journal)),
+            None => self.clone(),
+        }
+    }
+
+    /// Attribute text cached through this actor to `author`
+    #[must_use]
+    pub fn with_author(&self, author: &str) -> Self {
+        Self(self.0.clone().with_author(author))
+    }
+
+    /// Limit syncs to `date_range`
+    #[must_use]
+    pub fn with_date_range(&self, date_range: DateRange) -> Self {
+        Self(self.0.clone().with_date_range(date_range))
+    }
+
+    /// What syncs do while another process is syncing
+    #[must_use]
+    pub fn with_sync_lock(&self, sync_lock: SyncLockMode) -> Self {
+        Self(self.0.clone().with_sync_lock(sync_lock))
+    }
+
+    /// Leave private entries out of responses
+    #[must_use]
+    pub fn with_private_hidden(&self, hide_private: bool) -> Self {
+        Self(self.0.clone().with_private_hidden(hide_private))
+    }
+}
+
 impl Deref for DiaryAppActor {
     type Target = DiaryAppInterface;
 
@@ -52,23 +134,34 @@ impl Deref for DiaryAppActor {
 pub struct AppState {
     pub db: DiaryAppActor,
     pub hb: Arc<Handlebars<'static>>,
+    pub unlock: UnlockSessions,
+    pub guestbook: Guestbook,
+    pub kiosk: Kiosk,
+    /// Set when `telegram_webhook_in_api` is
+    pub telegram: Option<TelegramWebhook>,
+    pub maintenance: MaintenanceJobs,
+    /// Writes of every process sharing the database
+    pub changes: ChangeFeed,
 }
 
+/// Changed daily files are synced once they've gone this long without
+/// another event, editors often write a file several times when saving
+const FILE_DEBOUNCE: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 struct Notifier {
-    send: Sender<HashSet<PathBuf>>,
-    recv: Receiver<HashSet<PathBuf>>,
+    send: UnboundedSender<PathBuf>,
     watcher: Option<Arc<INotifyWatcher>>,
 }
 
 impl Notifier {
-    fn new() -> Self {
-        let (send, recv) = channel::<HashSet<PathBuf>>(HashSet::new());
-        Self {
+    fn new() -> (Self, UnboundedReceiver<PathBuf>) {
+        let (send, recv) = unbounded_channel();
+        let notifier = Self {
             send,
-            recv,
             watcher: None,
-        }
+        };
+        (notifier, recv)
     }
 
     fn set_watcher(mut self, directory: &Path) -> Result<Self, Error> {
@@ -83,32 +176,28 @@ impl EventHandler for Notifier {
     fn handle_event(&mut self, event: NotifyResult<Event>) {
         match event {
             Ok(event) => match event.kind {
-                EventKind::Any | EventKind::Create(_) | EventKind::Modify(_) => {
+                EventKind::Any
+                | EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Remove(_) => {
                     info!("expected event {event:?}");
-                    let new_paths: HashSet<_> = event
-                        .paths
-                        .iter()
-                        .filter_map(|p| {
-                            if p.file_name()
-                                .map(|f| f.to_string_lossy())
-                                .and_then(|filename| {
-                                    Date::parse(
-                                        &filename,
-                                        format_description!("[year]-[month]-[day].txt"),
-                                    )
-                                    .ok()
-                                })
-                                .is_some()
-                            {
-                                Some(p.clone())
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-                    if !new_paths.is_empty() {
-                        info!("got event kind {:?} paths {:?}", event.kind, event.paths);
-                        self.send.send_replace(new_paths);
+                    // renames report both paths, whether each one is still
+                    // there is checked once the file settles
+                    for path in event.paths {
+                        let is_daily_file = path
+                            .file_name()
+                            .map(|f| f.to_string_lossy())
+                            .and_then(|filename| {
+                                Date::parse(
+                                    &filename,
+                                    format_description!("[year]-[month]-[day].txt"),
+                                )
+                                .ok()
+                            })
+                            .is_some();
+                        if is_daily_file && self.send.send(path).is_err() {
+                            error!("file watcher stopped receiving events");
+                        }
                     }
                 }
                 _ => (),
@@ -121,67 +210,294 @@ impl EventHandler for Notifier {
 /// # Errors
 /// Returns error if starting app fails
 pub async fn start_app() -> Result<(), Error> {
-    async fn update_db(pool: PgPool) {
+    async fn update_db(pools: Vec<PgPool>) {
+        let mut i = interval(Duration::from_secs(60));
+        loop {
+            fill_from_db(&pools).await.unwrap_or(());
+            i.tick().await;
+        }
+    }
+    async fn sweep_trash(diary_app_interface: DiaryAppInterface) {
+        let mut i = interval(Duration::from_secs(3600));
+        loop {
+            i.tick().await;
+            if let Err(e) = diary_app_interface.purge_trash().await {
+                error!("failed to purge trash {e}");
+            }
+        }
+    }
+    /// Writes of the watcher wait for a sync running elsewhere rather than
+    /// interleaving with it
+    async fn lock_sync(
+        diary_app_interface: &DiaryAppInterface,
+        mode: SyncLockMode,
+    ) -> Option<SyncLock> {
+        let holder = sync_holder(diary_app_interface.source.as_deref());
+        match SyncLock::acquire(&diary_app_interface.pool, &holder, mode).await {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                if e.downcast_ref::<SyncLockError>().is_none() {
+                    error!("failed to lock sync {e}");
+                }
+                None
+            }
+        }
+    }
+    async fn unlock_sync(lock: SyncLock) {
+        if let Err(e) = lock.release().await {
+            error!("failed to release sync lock {e}");
+        }
+    }
+    async fn retry_s3_outbox(diary_app_interface: DiaryAppInterface) {
         let mut i = interval(Duration::from_secs(60));
         loop {
-            fill_from_db(&pool).await.unwrap_or(());
             i.tick().await;
+            // a running sync processes the outbox itself
+            let Some(lock) = lock_sync(&diary_app_interface, SyncLockMode::Fail).await else {
+                continue;
+            };
+            match diary_app_interface.process_s3_outbox().await {
+                Ok(output) => output.iter().for_each(|line| info!("{line}")),
+                Err(e) => error!("failed to process s3 outbox {e}"),
+            }
+            unlock_sync(lock).await;
         }
     }
     async fn run_sync(diary_app_interface: &DiaryAppInterface) {
+        let Some(lock) = lock_sync(diary_app_interface, SyncLockMode::Wait).await else {
+            return;
+        };
         match diary_app_interface.local.import_from_local().await {
             Ok(entries) => info!("entries: {entries:?}"),
-            Err(e) => error!("got error {e}"),
+            Err(e) => {
+                error!("got error {e}");
+                diary_app_interface.report_sync_failure("local", None, &e);
+            }
         }
+        unlock_sync(lock).await;
     }
-    async fn check_files(dapp_interface: DiaryAppInterface, mut notifier: Notifier) {
+    async fn sync_files(dapp_interface: &DiaryAppInterface, paths: &[PathBuf]) {
+        let mut changed: HashMap<StackString, Vec<Date>> = HashMap::new();
+        for path in paths {
+            if let Some((journal, date)) = parse_local_path(&dapp_interface.config.diary_path, path)
+            {
+                changed.entry(journal).or_default().push(date);
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+        let Some(lock) = lock_sync(dapp_interface, SyncLockMode::Wait).await else {
+            return;
+        };
+        for (journal, dates) in changed {
+            let dapp_interface = dapp_interface.clone().with_journal(journal);
+            match dapp_interface.local.import_dates(&dates).await {
+                Ok(entries) => info!("entries: {entries:?}"),
+                Err(e) => {
+                    error!("got error {e}");
+                    dapp_interface.report_sync_failure("local", None, &e);
+                }
+            }
+            for date in dates {
+                match dapp_interface.handle_local_removal(date).await {
+                    Ok(output) => output.iter().for_each(|line| info!("{line}")),
+                    Err(e) => error!("failed to remove {date} {e}"),
+                }
+            }
+        }
+        unlock_sync(lock).await;
+    }
+    async fn check_files(dapp_interface: DiaryAppInterface, mut recv: UnboundedReceiver<PathBuf>) {
         run_sync(&dapp_interface).await;
-        while notifier.recv.changed().await.is_ok() {
-            sleep(Duration::from_secs(10)).await;
-            run_sync(&dapp_interface).await;
+        // last event of each file not synced yet
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        loop {
+            let next_ready = pending.values().min().map(|t| *t + FILE_DEBOUNCE);
+            let received = match next_ready {
+                Some(deadline) => timeout_at(deadline.into(), recv.recv()).await.ok(),
+                None => Some(recv.recv().await),
+            };
+            match received {
+                Some(Some(path)) => {
+                    pending.insert(path, Instant::now());
+                }
+                Some(None) => break,
+                None => {
+                    let now = Instant::now();
+                    let ready: Vec<PathBuf> = pending
+                        .iter()
+                        .filter(|(_, t)| now.duration_since(**t) >= FILE_DEBOUNCE)
+                        .map(|(path, _)| path.clone())
+                        .collect();
+                    for path in &ready {
+                        pending.remove(path);
+                    }
+                    sync_files(&dapp_interface, &ready).await;
+                }
+            }
         }
     }
 
+    fn spawn_sync_tasks(dapp_interface: &DiaryAppInterface) -> Result<(), Error> {
+        let (notifier, file_events) = Notifier::new();
+        let notifier = notifier.set_watcher(&dapp_interface.config.diary_path)?;
+        tokio::task::spawn(sweep_trash(dapp_interface.clone()));
+        tokio::task::spawn(retry_s3_outbox(dapp_interface.clone()));
+        tokio::task::spawn({
+            let diary_app_interface = dapp_interface.clone();
+            async move {
+                // the watcher stops when the notifier is dropped
+                let _notifier = notifier;
+                check_files(diary_app_interface, file_events).await;
+            }
+        });
+        Ok(())
+    }
+
     let config = Config::init_config()?;
+    let _reporting = error_reporting::init(&config, "diary-app-api");
     get_secrets(&config.secret_path, &config.jwt_secret_path).await?;
     let pool = PgPool::new(&config.database_url)?;
+    check_schema(&pool, config.auto_migrate).await?;
     let sdk_config = aws_config::load_from_env().await;
-    let dapp = DiaryAppActor(DiaryAppInterface::new(config.clone(), &sdk_config, pool));
-    let notifier = Notifier::new().set_watcher(&config.diary_path)?;
-
-    tokio::task::spawn(update_db(dapp.pool.clone()));
-    tokio::task::spawn({
-        let diary_app_interface = dapp.0.clone();
-        async move {
-            check_files(diary_app_interface, notifier).await;
+    let dapp = DiaryAppActor(
+        DiaryAppInterface::new(config.clone(), &sdk_config, pool).with_source(API_SOURCE),
+    );
+    spawn_sync_tasks(&dapp)?;
+
+    let mut tenants = Vec::new();
+    if let Some(tenants_file) = &config.tenants_file {
+        for tenant in load_tenants(tenants_file)? {
+            let tenant_config = tenant.config(&config);
+            let pool = PgPool::new(&tenant_config.database_url)?;
+            check_schema(&pool, config.auto_migrate)
+                .await
+                .map_err(|e| format_err!("tenant {}: {e}", tenant.name))?;
+            create_dir_all(&tenant_config.diary_path)?;
+            let tenant_dapp = DiaryAppActor(
+                DiaryAppInterface::new(tenant_config, &sdk_config, pool).with_source(API_SOURCE),
+            );
+            spawn_sync_tasks(&tenant_dapp)?;
+            info!("serving tenant {}", tenant.name);
+            tenants.push((tenant, tenant_dapp));
         }
-    });
-    run_app(dapp, config.port).await
+    }
+
+    let pools = std::iter::once(dapp.pool.clone())
+        .chain(tenants.iter().map(|(_, d)| d.pool.clone()))
+        .collect();
+    tokio::task::spawn(update_db(pools));
+    run_app(dapp, tenants, config.port).await
+}
+
+/// Refuse requests declaring a body longer than `max_bytes` with 413 before
+/// the body is read, and bodies of unknown length (chunked without a
+/// `content-length`) with 411
+fn body_limit(max_bytes: u64) -> BoxedFilter<()> {
+    rweb::filters::header::optional::<u64>("content-length")
+        .and(rweb::filters::header::optional::<String>(
+            "transfer-encoding",
+        ))
+        .and_then(
+            move |length: Option<u64>, encoding: Option<String>| async move {
+                match (length, encoding) {
+                    (Some(length), _) if length > max_bytes => {
+                        Err(rweb::reject::custom(ServiceError::PayloadTooLarge(
+                            format!("Request body is {length} bytes, the limit is {max_bytes}"),
+                        )))
+                    }
+                    (None, Some(_)) => Err(rweb::reject::custom(ServiceError::LengthRequired)),
+                    _ => Ok(()),
+                }
+            },
+        )
+        .untuple_one()
+        .boxed()
 }
 
 fn get_api_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
+    let body_limit = body_limit(app.db.config.max_request_bytes);
     let search_path = search(app.clone()).boxed();
-    let insert_path = insert(app.clone()).boxed();
+    let insert_path = body_limit.clone().and(insert(app.clone())).boxed();
+    let insert_batch_path = body_limit.clone().and(insert_batch(app.clone())).boxed();
     let sync_path = sync(app.clone()).boxed();
-    let replace_path = replace(app.clone()).boxed();
+    let replace_path = body_limit.clone().and(replace(app.clone())).boxed();
+    let append_path = body_limit.clone().and(append(app.clone())).boxed();
+    let patch_entry_path = body_limit.clone().and(patch_entry(app.clone())).boxed();
+    let get_section_path = get_section(app.clone()).boxed();
+    let replace_section_path = body_limit.clone().and(replace_section(app.clone())).boxed();
+    let redact_path = body_limit.clone().and(redact(app.clone())).boxed();
     let list_path = list(app.clone()).boxed();
     let edit_path = edit(app.clone()).boxed();
     let display_path = display(app.clone()).boxed();
-    let frontpage_path = diary_frontpage().boxed();
+    let print_path = print(app.clone()).boxed();
+    let frontpage_path = diary_frontpage(app.clone()).boxed();
     let list_conflicts_path = list_conflicts(app.clone()).boxed();
     let show_conflict_path = show_conflict(app.clone()).boxed();
     let remove_conflict_path = remove_conflict(app.clone()).boxed();
     let update_conflict_path = update_conflict(app.clone()).boxed();
     let commit_conflict_path = commit_conflict(app.clone()).boxed();
     let user_path = user().boxed();
+    let mobile_sync_path = body_limit.clone().and(mobile_sync(app.clone())).boxed();
+    let unlock_path = body_limit.clone().and(unlock(app.clone())).boxed();
+    let lock_path = lock(app.clone()).boxed();
+    let list_trash_path = list_trash(app.clone()).boxed();
+    let restore_trash_path = restore_trash(app.clone()).boxed();
+    let purge_trash_path = purge_trash(app.clone()).boxed();
+    let delete_entry_path = delete_entry(app.clone()).boxed();
+    let list_encrypted_path = list_encrypted(app.clone()).boxed();
+    let replace_encrypted_path = body_limit.clone().and(replace_encrypted(app.clone())).boxed();
+    let star_path = star(app.clone()).boxed();
+    let list_journals_path = list_journals(app.clone()).boxed();
+    let create_journal_path = body_limit.clone().and(create_journal(app.clone())).boxed();
+    let get_metadata_path = get_metadata(app.clone()).boxed();
+    let update_metadata_path = body_limit.clone().and(update_metadata(app.clone())).boxed();
+    let stats_path = stats(app.clone()).boxed();
+    let storage_stats_path = storage_stats(app.clone()).boxed();
+    let word_stats_path = word_stats(app.clone()).boxed();
+    let habit_stats_path = habit_stats(app.clone()).boxed();
+    let entries_meta_path = entries_meta(app.clone()).boxed();
+    let sync_date_path = sync_date(app.clone()).boxed();
+    let diff_path = diff(app.clone()).boxed();
+    let schedule_path = body_limit.clone().and(schedule(app.clone())).boxed();
+    let dashboard_path = dashboard(app.clone()).boxed();
+    let get_settings_path = get_settings(app.clone()).boxed();
+    let update_settings_path = body_limit.clone().and(update_settings(app.clone())).boxed();
+    let list_comments_path = list_comments(app.clone()).boxed();
+    let activity_path = activity(app.clone()).boxed();
+    let add_comment_path = body_limit.clone().and(add_comment(app.clone())).boxed();
+    let update_comment_path = body_limit.clone().and(update_comment(app.clone())).boxed();
+    let delete_comment_path = delete_comment(app.clone()).boxed();
+    let list_users_path = list_users(app.clone()).boxed();
+    let add_user_path = body_limit.clone().and(add_user(app.clone())).boxed();
+    let disable_user_path = disable_user(app.clone()).boxed();
+    let set_telegram_user_path = body_limit.clone().and(set_telegram_user(app.clone())).boxed();
+    let start_maintenance_path = body_limit.clone().and(start_maintenance(app.clone())).boxed();
+    let list_maintenance_path = list_maintenance(app.clone()).boxed();
+    let sync_lock_path = sync_lock(app.clone()).boxed();
+    let link_telegram_path = link_telegram(app.clone()).boxed();
+    let toggle_private_path = toggle_private(app.clone()).boxed();
+    let peer_pull_path = body_limit.clone().and(peer_pull(app.clone())).boxed();
+    let peer_push_path = body_limit.clone().and(peer_push(app.clone())).boxed();
+    let guestbook_path = body_limit.clone().and(guestbook(app.clone())).boxed();
+    let telegram_webhook_path = body_limit.clone().and(telegram_webhook(app.clone())).boxed();
+    let kiosk_path = kiosk(app.clone()).boxed();
 
     search_path
         .or(insert_path)
+        .or(insert_batch_path)
         .or(sync_path)
         .or(replace_path)
+        .or(append_path)
+        .or(patch_entry_path)
+        .or(get_section_path)
+        .or(replace_section_path)
+        .or(redact_path)
         .or(list_path)
         .or(edit_path)
         .or(display_path)
+        .or(print_path)
         .or(frontpage_path)
         .or(list_conflicts_path)
         .or(show_conflict_path)
@@ -189,25 +505,119 @@ fn get_api_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
         .or(update_conflict_path)
         .or(commit_conflict_path)
         .or(user_path)
+        .or(mobile_sync_path)
+        .or(unlock_path)
+        .or(lock_path)
+        .or(list_trash_path)
+        .or(restore_trash_path)
+        .or(purge_trash_path)
+        .or(delete_entry_path)
+        .or(list_encrypted_path)
+        .or(replace_encrypted_path)
+        .or(star_path)
+        .or(list_journals_path)
+        .or(create_journal_path)
+        .or(get_metadata_path)
+        .or(update_metadata_path)
+        .or(stats_path)
+        .or(schedule_path)
+        .or(dashboard_path)
+        .or(get_settings_path)
+        .or(update_settings_path)
+        .or(list_comments_path)
+        .or(activity_path)
+        .or(add_comment_path)
+        .or(update_comment_path)
+        .or(delete_comment_path)
+        .or(list_users_path)
+        .or(add_user_path)
+        .or(disable_user_path)
+        .or(set_telegram_user_path)
+        .or(start_maintenance_path)
+        .or(list_maintenance_path)
+        .or(sync_lock_path)
+        .or(link_telegram_path)
+        .or(toggle_private_path)
+        .or(sync_date_path)
+        .or(diff_path)
+        .or(storage_stats_path)
+        .or(word_stats_path)
+        .or(habit_stats_path)
+        .or(entries_meta_path)
+        .or(peer_pull_path)
+        .or(peer_push_path)
+        .or(guestbook_path)
+        .or(telegram_webhook_path)
+        .or(kiosk_path)
         .boxed()
 }
 
-async fn run_app(db: DiaryAppActor, port: u32) -> Result<(), Error> {
-    let mut hb = Handlebars::new();
-    hb.register_template_string("id", include_str!("../../templates/index.html.hbr"))
-        .expect("Failed to parse template");
-    let hb = Arc::new(hb);
+fn progress_events(
+    recv: Receiver<SyncProgress>,
+) -> impl Stream<Item = Result<SseEvent, Infallible>> + Send + 'static {
+    unfold(recv, |mut recv| async move {
+        recv.changed().await.ok()?;
+        let progress = recv.borrow().clone();
+        let data = serde_json::to_string(&progress).ok()?;
+        Some((Ok(SseEvent::default().event("progress").data(data)), recv))
+    })
+}
 
-    let app = AppState { db, hb };
+/// Send the changes made by any process to a frontend until it goes away
+async fn forward_changes(socket: WebSocket, recv: BroadcastReceiver<DiaryChange>) {
+    let (send, _) = socket.split();
+    let changes = unfold(recv, |mut recv| async move {
+        loop {
+            match recv.recv().await {
+                Ok(change) => {
+                    let data = serde_json::to_string(&change).ok()?;
+                    return Some((Ok(WsMessage::text(data)), recv));
+                }
+                // the frontend refreshes on the next change anyway
+                Err(RecvError::Lagged(skipped)) => debug!("skipped {skipped} changes"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    if let Err(e) = changes.forward(send).await {
+        debug!("change socket closed {e}");
+    }
+}
 
-    let (spec, api_path) = openapi::spec()
+async fn app_state(db: DiaryAppActor, hb: Arc<Handlebars<'static>>) -> Result<AppState, Error> {
+    let unlock = UnlockSessions::from_config(&db.config);
+    let guestbook = Guestbook::from_config(&db.config);
+    let kiosk = Kiosk::from_config(&db.config);
+    let telegram = if db.config.telegram_webhook_in_api {
+        Some(TelegramWebhook::start(db.0.clone()).await?)
+    } else {
+        None
+    };
+    let changes = ChangeFeed::new();
+    changes.listen(&db.config.database_url);
+    Ok(AppState {
+        db,
+        hb,
+        unlock,
+        guestbook,
+        kiosk,
+        telegram,
+        maintenance: MaintenanceJobs::new(),
+        changes,
+    })
+}
+
+/// Every route of one diary, the default one or a tenant
+fn get_app_path(app: &AppState, port: u32) -> Result<BoxedFilter<(impl Reply,)>, Error> {
+    let (mut spec, api_path) = openapi::spec()
         .info(Info {
             title: "Frontend for Diary".into(),
             description: "Web Frontend for Diary Service".into(),
             version: env!("CARGO_PKG_VERSION").into(),
             ..Info::default()
         })
-        .build(|| get_api_path(&app));
+        .build(|| get_api_path(app));
+    set_servers(&mut spec, &app.db.config, port);
     let spec = Arc::new(spec);
     let spec_json_path = rweb::path!("api" / "openapi" / "json")
         .and(rweb::path::end())
@@ -224,12 +634,167 @@ async fn run_app(db: DiaryAppActor, port: u32) -> Result<(), Error> {
             rweb::reply::with_header(reply, CONTENT_TYPE, "text/yaml")
         });
 
+    let manifest_path = rweb::path!("api" / "manifest.json")
+        .and(rweb::path::end())
+        .map(|| {
+            let reply = rweb::reply::html(asset("manifest.json"));
+            rweb::reply::with_header(reply, CONTENT_TYPE, "application/manifest+json")
+        });
+    let service_worker_path = rweb::path!("api" / "sw.js").and(rweb::path::end()).map(|| {
+        let reply = rweb::reply::html(asset("sw.js"));
+        rweb::reply::with_header(reply, CONTENT_TYPE, "application/javascript")
+    });
+
+    let assets_path = rweb::path!("assets" / String)
+        .and(rweb::path::end())
+        .and(rweb::filters::method::get())
+        .and_then(|name: String| async move {
+            let text = get_asset(&name).ok_or_else(rweb::reject::not_found)?;
+            let reply = rweb::reply::with_header(text, CONTENT_TYPE, content_type(&name));
+            Ok::<_, rweb::Rejection>(reply)
+        });
+
+    let sync_progress_path = rweb::path!("api" / "sync_progress")
+        .and(rweb::path::end())
+        .and(LoggedUser::filter())
+        .map({
+            let progress = app.db.progress.clone();
+            move |_: LoggedUser| {
+                let events = progress_events(progress.subscribe());
+                sse::reply(sse::keep_alive().stream(events))
+            }
+        });
+
+    let changes_path = rweb::path!("api" / "changes")
+        .and(rweb::path::end())
+        .and(LoggedUser::filter())
+        .and(rweb::filters::ws::ws())
+        .map({
+            let changes = app.changes.clone();
+            move |_: LoggedUser, ws: Ws| {
+                let recv = changes.subscribe();
+                ws.on_upgrade(move |socket| forward_changes(socket, recv))
+            }
+        });
+
     let routes = api_path
         .or(spec_json_path)
         .or(spec_yaml_path)
-        .recover(error_response);
+        .or(manifest_path)
+        .or(service_worker_path)
+        .or(assets_path)
+        .or(sync_progress_path)
+        .or(changes_path)
+        .recover(error_response)
+        .boxed();
+    Ok(routes)
+}
+
+async fn run_app(
+    db: DiaryAppActor,
+    tenants: Vec<(Tenant, DiaryAppActor)>,
+    port: u32,
+) -> Result<(), Error> {
+    assets::init(db.config.template_dir.as_deref())?;
+    let mut hb = Handlebars::new();
+    hb.register_template_string("id", asset("index.html.hbr"))
+        .map_err(|e| format_err!("Failed to parse template index.html.hbr: {e}"))?;
+    let hb = Arc::new(hb);
+
+    let app = app_state(db, hb.clone()).await?;
+    // services are indexed like the tenants of the router, the default
+    // diary first
+    let mut services = vec![rweb::service(get_app_path(&app, port)?)];
+    let mut states = vec![app.clone()];
+    let mut tenant_pools = Vec::with_capacity(tenants.len());
+    for (tenant, db) in tenants {
+        let tenant_app = app_state(db, hb.clone()).await?;
+        services.push(rweb::service(get_app_path(&tenant_app, port)?));
+        tenant_pools.push((tenant, tenant_app.db.pool.clone()));
+        states.push(tenant_app);
+    }
+    let services: Arc<[_]> = services.into();
+    let states: Arc<[AppState]> = states.into();
+    let router = TenantRouter::new(app.db.pool.clone(), tenant_pools);
+
     let addr: SocketAddr = format_sstr!("127.0.0.1:{port}").parse()?;
-    rweb::serve(routes).bind(addr).await;
+    // `/t/{tenant}/...` and then `/api/v1/...` are rewritten to the
+    // `/api/...` routes before they see it
+    let security = SecurityHeaders::from_config(&app.db.config);
+    let display_cache_days = app.db.config.display_cache_days;
+    let make_service = make_service_fn(move |_| {
+        let services = services.clone();
+        let states = states.clone();
+        let router = router.clone();
+        let security = security.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                let services = services.clone();
+                let states = states.clone();
+                let router = router.clone();
+                let security = security.clone();
+                async move {
+                    if let Some(resp) = security.preflight(&req) {
+                        return Ok(resp);
+                    }
+                    let request_headers = req.headers().clone();
+                    let route = match router.route(&mut req) {
+                        Ok(route) => route,
+                        Err(resp) => return Ok(resp),
+                    };
+                    let mut resp = match router.check_user(&route, &req).await {
+                        Err(resp) => resp,
+                        Ok(()) => match route_request(req) {
+                            Ok((version, req)) => {
+                                let method = req.method().clone();
+                                let uri = req.uri().clone();
+                                let today = OffsetDateTime::now_utc().date();
+                                let validator = entry_validator(
+                                    &method,
+                                    &uri,
+                                    &request_headers,
+                                    &states[route.index],
+                                    today,
+                                )
+                                .await;
+                                let unchanged = validator.as_ref().and_then(|v| {
+                                    not_modified(
+                                        &uri,
+                                        &request_headers,
+                                        v,
+                                        today,
+                                        display_cache_days,
+                                    )
+                                });
+                                if let Some(resp) = unchanged {
+                                    with_version(resp, version)
+                                } else {
+                                    let mut service = services[route.index].clone();
+                                    let resp =
+                                        with_version(service.call(req).await?, version);
+                                    cache_response(
+                                        &method,
+                                        &uri,
+                                        &request_headers,
+                                        resp,
+                                        today,
+                                        display_cache_days,
+                                        validator.as_ref(),
+                                    )
+                                    .await
+                                }
+                            }
+                            Err(resp) => resp,
+                        },
+                    };
+                    TenantRouter::apply(&route, &mut resp);
+                    security.apply(&request_headers, &mut resp);
+                    Ok::<_, Infallible>(compress_response(&request_headers, resp).await)
+                }
+            }))
+        }
+    });
+    Server::bind(&addr).serve(make_service).await?;
     Ok(())
 }
 
@@ -272,7 +837,7 @@ mod tests {
 
         tokio::task::spawn(async move {
             env_logger::init();
-            run_app(dapp, test_port).await.unwrap()
+            run_app(dapp, Vec::new(), test_port).await.unwrap()
         });
 
         let auth_port: u32 = 54321;
@@ -0,0 +1,132 @@
+use anyhow::{format_err, Error};
+use log::info;
+use std::{collections::HashMap, fs, path::Path, sync::OnceLock};
+
+/// Files of `templates` compiled into the binary, so an installed binary
+/// doesn't need the source tree
+const EMBEDDED: [(&str, &str); 7] = [
+    (
+        "index.html.hbr",
+        include_str!("../templates/index.html.hbr"),
+    ),
+    ("kiosk.css", include_str!("../templates/kiosk.css")),
+    ("manifest.json", include_str!("../templates/manifest.json")),
+    ("print.css", include_str!("../templates/print.css")),
+    ("scripts.js", include_str!("../templates/scripts.js")),
+    ("style.css", include_str!("../templates/style.css")),
+    ("sw.js", include_str!("../templates/sw.js")),
+];
+
+static ASSETS: OnceLock<Assets> = OnceLock::new();
+
+fn embedded(name: &str) -> Option<&'static str> {
+    EMBEDDED
+        .iter()
+        .find_map(|(n, text)| if *n == name { Some(*text) } else { None })
+}
+
+/// The embedded templates, with any file of the same name in `template_dir`
+/// used in its place
+#[derive(Debug, Default)]
+pub struct Assets {
+    overrides: HashMap<&'static str, String>,
+}
+
+impl Assets {
+    /// Files in `dir` not named like an embedded template are ignored
+    /// # Errors
+    /// Return error if an override can't be read
+    pub fn load(dir: Option<&Path>) -> Result<Self, Error> {
+        let mut overrides = HashMap::new();
+        if let Some(dir) = dir {
+            for (name, _) in EMBEDDED {
+                let path = dir.join(name);
+                if path.exists() {
+                    info!("using {}", path.display());
+                    let text = fs::read_to_string(&path)
+                        .map_err(|e| format_err!("Failed to read {}: {e}", path.display()))?;
+                    overrides.insert(name, text);
+                }
+            }
+        }
+        Ok(Self { overrides })
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.overrides
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| embedded(name))
+    }
+}
+
+/// Load the overrides in `dir`, has to run before the first call to `asset`
+/// to take effect
+/// # Errors
+/// Return error if an override can't be read or the assets were already
+/// loaded
+pub fn init(dir: Option<&Path>) -> Result<(), Error> {
+    ASSETS
+        .set(Assets::load(dir)?)
+        .map_err(|_| format_err!("Assets already loaded"))
+}
+
+/// Text of the template `name`, `None` for unknown names
+#[must_use]
+pub fn get_asset(name: &str) -> Option<&'static str> {
+    match ASSETS.get() {
+        Some(assets) => assets.get(name),
+        None => embedded(name),
+    }
+}
+
+/// Text of one of the templates the app itself uses
+#[must_use]
+pub fn asset(name: &str) -> &'static str {
+    get_asset(name).unwrap_or_default()
+}
+
+#[must_use]
+pub fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript",
+        Some("json") => "application/manifest+json",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assets::{content_type, Assets, EMBEDDED};
+
+    #[test]
+    fn test_assets_load() {
+        let assets = Assets::load(None).unwrap();
+        for (name, text) in EMBEDDED {
+            assert_eq!(assets.get(name), Some(text));
+        }
+        assert_eq!(assets.get("../Cargo.toml"), None);
+
+        let dir = std::env::temp_dir().join(format!("diary_assets_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("style.css"), "body {}").unwrap();
+        fs::write(dir.join("other.css"), "p {}").unwrap();
+        let assets = Assets::load(Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(assets.get("style.css"), Some("body {}"));
+        assert_eq!(assets.get("other.css"), None);
+        assert!(assets.get("scripts.js").unwrap().contains("function"));
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type("style.css"), "text/css; charset=utf-8");
+        assert_eq!(content_type("sw.js"), "application/javascript");
+        assert_eq!(content_type("manifest.json"), "application/manifest+json");
+        assert_eq!(content_type("index.html.hbr"), "text/plain; charset=utf-8");
+    }
+}
@@ -0,0 +1,340 @@
+use log::error;
+use rweb::{
+    http::{
+        header::{HeaderMap, HeaderValue, CACHE_CONTROL, ETAG, IF_NONE_MATCH, LAST_MODIFIED},
+        Method, StatusCode, Uri,
+    },
+    hyper::{body::to_bytes, Body, Response},
+};
+use sha2::{Digest, Sha256};
+use stack_string::{format_sstr, StackString};
+use std::str::FromStr;
+use time::{macros::format_description, Date, OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use diary_app_lib::models::{AuthorizedUsers, DiaryEntries, EntryVersion};
+
+use crate::{app::AppState, logged_user::LoggedUser, tenancy::get_cookie};
+
+/// Revalidate with the `ETag` before every use
+const REVALIDATE: &str = "private, no-cache";
+/// Entries this old are rarely touched, browsers may keep them for a day
+const OLD_ENTRY: &str = "private, max-age=86400";
+const MANIFEST: &str = "public, max-age=86400";
+
+/// `Cache-Control` of the routes whose responses are worth caching, `None`
+/// for everything else, `display_cache_days` is how old an entry has to be
+/// to be kept without revalidating
+#[must_use]
+pub fn cache_policy(uri: &Uri, today: Date, display_cache_days: i64) -> Option<&'static str> {
+    match uri.path() {
+        "/api/index.html" | "/api/sw.js" => Some(REVALIDATE),
+        "/api/manifest.json" => Some(MANIFEST),
+        "/api/list" => Some(REVALIDATE),
+        path if path.starts_with("/assets/") => Some(REVALIDATE),
+        "/api/display" => {
+            let date = query_date(uri)?;
+            if (today - date).whole_days() > display_cache_days {
+                Some(OLD_ENTRY)
+            } else {
+                Some(REVALIDATE)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn query_value<'a>(uri: &'a Uri, key: &str) -> Option<&'a str> {
+    uri.query()?.split('&').find_map(|kv| {
+        let (k, v) = kv.split_once('=')?;
+        if k == key {
+            Some(v)
+        } else {
+            None
+        }
+    })
+}
+
+fn query_date(uri: &Uri) -> Option<Date> {
+    let date = query_value(uri, "date")?;
+    Date::parse(date, format_description!("[year]-[month]-[day]")).ok()
+}
+
+fn quoted_hex(digest: &[u8]) -> StackString {
+    let mut tag = StackString::from("\"");
+    for byte in &digest[..16] {
+        tag.push_str(&format_sstr!("{byte:02x}"));
+    }
+    tag.push('"');
+    tag
+}
+
+#[must_use]
+pub fn etag(body: &[u8]) -> StackString {
+    quoted_hex(&Sha256::digest(body))
+}
+
+/// `Last-Modified` format, `Tue, 05 Mar 2024 08:49:37 GMT`
+#[must_use]
+pub fn http_date(datetime: OffsetDateTime) -> StackString {
+    datetime
+        .to_offset(UtcOffset::UTC)
+        .format(format_description!(
+            "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+        ))
+        .unwrap_or_default()
+        .into()
+}
+
+/// `ETag` and `Last-Modified` of a page computed from the database before
+/// rendering it, a matching `If-None-Match` is answered without rendering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validator {
+    pub etag: StackString,
+    pub last_modified: OffsetDateTime,
+}
+
+impl Validator {
+    /// `context` is everything else the page depends on, the query, the
+    /// user and their language
+    #[must_use]
+    pub fn new(version: &EntryVersion, context: &[&str]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(version.version.as_bytes());
+        for value in context {
+            hasher.update(b"|");
+            hasher.update(value.as_bytes());
+        }
+        Self {
+            etag: quoted_hex(&hasher.finalize()),
+            last_modified: version.last_modified.into(),
+        }
+    }
+
+    fn set_headers(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&http_date(self.last_modified)) {
+            headers.insert(LAST_MODIFIED, value);
+        }
+    }
+}
+
+fn request_user(request_headers: &HeaderMap) -> Option<LoggedUser> {
+    let session: Uuid = get_cookie(request_headers, "session-id")?.parse().ok()?;
+    let user = LoggedUser::from_str(get_cookie(request_headers, "jwt")?).ok()?;
+    user.verify_session_id(session).ok()?;
+    Some(user)
+}
+
+/// Validator of `/api/display` and `/api/list` from the `last_modified` of
+/// the entries, `None` for other requests and when the route has to answer,
+/// e.g. for a locked diary or a user who isn't logged in
+pub async fn entry_validator(
+    method: &Method,
+    uri: &Uri,
+    request_headers: &HeaderMap,
+    state: &AppState,
+    today: Date,
+) -> Option<Validator> {
+    if method != Method::GET {
+        return None;
+    }
+    let path = uri.path();
+    if path != "/api/display" && path != "/api/list" {
+        return None;
+    }
+    let user = request_user(request_headers)?;
+    let dapp = state.db.with_journal(query_value(uri, "journal"));
+    let version = if path == "/api/display" {
+        if !state.unlock.is_unlocked(user.session.into()) {
+            return None;
+        }
+        DiaryEntries::get_version(&dapp.journal, query_date(uri)?, &dapp.pool).await
+    } else {
+        DiaryEntries::get_journal_version(&dapp.journal, &dapp.pool).await
+    };
+    let version = version
+        .map_err(|e| error!("failed to get entry version {e}"))
+        .ok()??;
+    // viewers don't see private entries
+    let role = AuthorizedUsers::get_by_email(&user.email, &dapp.pool)
+        .await
+        .ok()??
+        .role;
+    let locale = dapp.get_locale(&user.email).await.ok()?;
+    let today = today.to_string();
+    let context: [&str; 5] = [
+        uri.query().unwrap_or(""),
+        &user.email,
+        &role,
+        locale.as_str(),
+        &today,
+    ];
+    Some(Validator::new(&version, &context))
+}
+
+fn if_none_match(request_headers: &HeaderMap, etag: &str) -> bool {
+    request_headers
+        .get(IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| etag_matches(h, etag))
+}
+
+/// 304 for a client which already has the current page, the route isn't
+/// called
+#[must_use]
+pub fn not_modified(
+    uri: &Uri,
+    request_headers: &HeaderMap,
+    validator: &Validator,
+    today: Date,
+    display_cache_days: i64,
+) -> Option<Response<Body>> {
+    let policy = cache_policy(uri, today, display_cache_days)?;
+    if !if_none_match(request_headers, &validator.etag) {
+        return None;
+    }
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::NOT_MODIFIED;
+    validator.set_headers(resp.headers_mut());
+    resp.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static(policy));
+    Some(resp)
+}
+
+/// Whether an `If-None-Match` header matches `etag`, weak tags compare equal
+/// to strong ones
+#[must_use]
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Set `Cache-Control` and `ETag` on cacheable responses, answering with 304
+/// when the client already has the body.  The `ETag` is hashed from the body
+/// unless the page has a `validator`.
+pub async fn cache_response(
+    method: &Method,
+    uri: &Uri,
+    request_headers: &HeaderMap,
+    resp: Response<Body>,
+    today: Date,
+    display_cache_days: i64,
+    validator: Option<&Validator>,
+) -> Response<Body> {
+    if method != Method::GET || resp.status() != StatusCode::OK {
+        return resp;
+    }
+    let Some(policy) = cache_policy(uri, today, display_cache_days) else {
+        return resp;
+    };
+    let (mut parts, body) = resp.into_parts();
+    if let Some(validator) = validator {
+        validator.set_headers(&mut parts.headers);
+        parts
+            .headers
+            .entry(CACHE_CONTROL)
+            .or_insert(HeaderValue::from_static(policy));
+        return Response::from_parts(parts, body);
+    }
+    let body = match to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("failed to read response body {e}");
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return resp;
+        }
+    };
+    let tag = etag(&body);
+    if let Ok(value) = HeaderValue::from_str(&tag) {
+        parts.headers.insert(ETAG, value);
+    }
+    parts
+        .headers
+        .entry(CACHE_CONTROL)
+        .or_insert(HeaderValue::from_static(policy));
+    if if_none_match(request_headers, &tag) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use rweb::http::{header::HeaderMap, Uri};
+    use time::macros::{date, datetime};
+
+    use diary_app_lib::models::EntryVersion;
+
+    use crate::caching::{
+        cache_policy, etag, etag_matches, http_date, not_modified, Validator, OLD_ENTRY, REVALIDATE,
+    };
+
+    #[test]
+    fn test_cache_policy() {
+        let today = date!(2024 - 03 - 01);
+        let policy = |uri: &str| cache_policy(&uri.parse::<Uri>().unwrap(), today, 30);
+        assert_eq!(policy("/api/index.html"), Some(REVALIDATE));
+        assert_eq!(policy("/assets/style.css"), Some(REVALIDATE));
+        assert_eq!(policy("/api/display?date=2014-03-01"), Some(OLD_ENTRY));
+        assert_eq!(
+            policy("/api/display?journal=work&date=2024-02-28"),
+            Some(REVALIDATE)
+        );
+        assert_eq!(policy("/api/display"), None);
+        assert_eq!(policy("/api/list?start=10&limit=10"), Some(REVALIDATE));
+        assert_eq!(policy("/api/search?text=walk"), None);
+    }
+
+    #[test]
+    fn test_etag() {
+        let tag = etag(b"went for a walk");
+        assert_eq!(tag.len(), 34);
+        assert_eq!(tag, etag(b"went for a walk"));
+        assert_ne!(tag, etag(b"went for a run"));
+        assert!(etag_matches(&tag, &tag));
+        assert!(etag_matches(&format!("\"other\", W/{tag}"), &tag));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches("\"other\"", &tag));
+    }
+
+    #[test]
+    fn test_validator() {
+        let version = EntryVersion {
+            last_modified: datetime!(2024-03-05 08:49:37 UTC).into(),
+            version: "2024-03-05 08:49:37+00|false|public|{}|0-".into(),
+        };
+        assert_eq!(
+            http_date(version.last_modified.into()),
+            "Tue, 05 Mar 2024 08:49:37 GMT"
+        );
+        let validator = Validator::new(&version, &["date=2024-03-05", "user@test", "en"]);
+        assert_eq!(
+            validator,
+            Validator::new(&version, &["date=2024-03-05", "user@test", "en"])
+        );
+        assert_ne!(
+            validator,
+            Validator::new(&version, &["date=2024-03-05", "user@test", "fr"])
+        );
+
+        let today = date!(2024 - 03 - 06);
+        let uri: Uri = "/api/display?date=2024-03-05".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        assert!(not_modified(&uri, &headers, &validator, today, 30).is_none());
+        headers.insert("if-none-match", validator.etag.parse().unwrap());
+        let resp = not_modified(&uri, &headers, &validator, today, 30).unwrap();
+        assert_eq!(resp.status(), 304);
+        assert_eq!(resp.headers()["etag"], validator.etag.as_str());
+        assert_eq!(
+            resp.headers()["last-modified"],
+            "Tue, 05 Mar 2024 08:49:37 GMT"
+        );
+    }
+}
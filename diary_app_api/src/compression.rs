@@ -0,0 +1,216 @@
+use flate2::{write::GzEncoder, Compression};
+use log::error;
+use rweb::{
+    http::{
+        header::{
+            HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH,
+            CONTENT_TYPE, ETAG, VARY,
+        },
+        StatusCode,
+    },
+    hyper::{body::to_bytes, Body, Response},
+};
+use stack_string::format_sstr;
+use std::io::{Error as IoError, Write};
+
+/// Bodies smaller than this aren't worth the cpu
+pub const MIN_COMPRESS_BYTES: usize = 1024;
+
+/// Brotli quality, 11 is too slow to do on every response
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    #[must_use]
+    pub fn content_encoding(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Encoding with the highest weight in an `Accept-Encoding` header,
+    /// brotli wins ties
+    #[must_use]
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        // weights in thousandths
+        let mut best: Option<(Self, u16)> = None;
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or_default().trim().to_lowercase();
+            let weight = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .map_or(1000, |q| (q.clamp(0.0, 1.0) * 1000.0).round() as u16);
+            let encoding = match name.as_str() {
+                "br" | "*" => Self::Brotli,
+                "gzip" => Self::Gzip,
+                _ => continue,
+            };
+            if weight == 0 {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((current, w)) => {
+                    weight > w || (weight == w && encoding == Self::Brotli && current != encoding)
+                }
+            };
+            if better {
+                best = Some((encoding, weight));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// # Errors
+    /// Return error if the encoder fails
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, IoError> {
+        match self {
+            Self::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(data)?;
+                writer.flush()?;
+                Ok(writer.into_inner())
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Text the app serves, event streams are left alone since buffering them
+/// would hold back every event
+fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.split(';').next().unwrap_or_default().trim();
+    content_type != "text/event-stream"
+        && (content_type.starts_with("text/")
+            || content_type.ends_with("json")
+            || content_type.ends_with("javascript")
+            || content_type.ends_with("yaml"))
+}
+
+/// Compress the body of `resp` with the best encoding the client accepts
+pub async fn compress_response(
+    request_headers: &HeaderMap,
+    resp: Response<Body>,
+) -> Response<Body> {
+    let Some(encoding) = request_headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|h| h.to_str().ok())
+        .and_then(Encoding::negotiate)
+    else {
+        return resp;
+    };
+    let compressible = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(is_compressible);
+    if !compressible
+        || resp.headers().contains_key(CONTENT_ENCODING)
+        || resp.status() == StatusCode::NOT_MODIFIED
+    {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    let body = match to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("failed to read response body {e}");
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return resp;
+        }
+    };
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    if body.len() < MIN_COMPRESS_BYTES {
+        return Response::from_parts(parts, Body::from(body));
+    }
+    match encoding.compress(&body) {
+        Ok(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            // the compressed bytes differ, only a weak match remains valid
+            if let Some(weak) = parts
+                .headers
+                .get(ETAG)
+                .and_then(|tag| tag.to_str().ok())
+                .filter(|tag| !tag.starts_with("W/"))
+                .and_then(|tag| HeaderValue::from_str(&format_sstr!("W/{tag}")).ok())
+            {
+                parts.headers.insert(ETAG, weak);
+            }
+            parts.headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.content_encoding()),
+            );
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        Err(e) => {
+            error!("failed to compress response {e}");
+            Response::from_parts(parts, Body::from(body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    use crate::compression::{is_compressible, Encoding};
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(Encoding::negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(
+            Encoding::negotiate("br;q=0.5, gzip;q=0.8"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("identity"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("application/manifest+json"));
+        assert!(is_compressible("application/javascript"));
+        assert!(!is_compressible("text/event-stream"));
+        assert!(!is_compressible("image/png"));
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let text = "Went for a walk along the river\n".repeat(100);
+        let compressed = Encoding::Gzip.compress(text.as_bytes()).unwrap();
+        assert!(compressed.len() < text.len());
+        let mut decoded = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        let compressed = Encoding::Brotli.compress(text.as_bytes()).unwrap();
+        assert!(compressed.len() < text.len());
+    }
+}
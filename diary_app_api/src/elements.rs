@@ -3,19 +3,47 @@ use dioxus::prelude::{
     VirtualDom,
 };
 use rweb_helper::DateType;
-use stack_string::StackString;
+use stack_string::{format_sstr, StackString};
 use std::collections::{BTreeSet, HashSet};
 use time::{macros::format_description, Date, OffsetDateTime};
 use time_tz::OffsetDateTimeExt;
+use uuid::Uuid;
 
-use diary_app_lib::{date_time_wrapper::DateTimeWrapper, models::DiaryConflict};
+use diary_app_lib::{
+    authorship::entry_authors,
+    date_time_wrapper::DateTimeWrapper,
+    line_diff::{inline_diff, DiffHunk, DiffTag, InlineSegment},
+    i18n::{Locale, Message},
+    models::{DateGroup, DiaryConflict, DiarySubentry, StatsPeriod, DEFAULT_JOURNAL},
+    sections::parse_sections,
+};
 
-use crate::errors::ServiceError as Error;
+use crate::{
+    assets::asset,
+    errors::ServiceError as Error,
+    requests::{Comment, Dashboard, EncryptedEntry},
+};
 
 /// # Errors
 /// Returns error if formatting fails
-pub fn index_body() -> Result<String, Error> {
-    let mut app = VirtualDom::new(IndexElement);
+pub fn index_body(
+    favorites: Vec<DateType>,
+    journals: Vec<StackString>,
+    journal: Option<StackString>,
+    dashboard: Option<Dashboard>,
+    locale: Locale,
+) -> Result<String, Error> {
+    let journal = journal.unwrap_or_else(|| DEFAULT_JOURNAL.into());
+    let mut app = VirtualDom::new_with_props(
+        IndexElement,
+        IndexElementProps {
+            favorites,
+            journals,
+            journal,
+            dashboard,
+            locale,
+        },
+    );
     app.rebuild_in_place();
     let mut renderer = dioxus_ssr::Renderer::default();
     let mut buffer = String::new();
@@ -26,22 +54,94 @@ pub fn index_body() -> Result<String, Error> {
 }
 
 #[component]
-fn IndexElement() -> Element {
+fn IndexElement(
+    favorites: Vec<DateType>,
+    journals: Vec<StackString>,
+    journal: StackString,
+    dashboard: Option<Dashboard>,
+    locale: Locale,
+) -> Element {
+    let dashboard = dashboard.map(|dashboard| {
+        rsx! {
+            DashboardElement {
+                dashboard: dashboard,
+                locale: locale,
+            }
+        }
+    });
+    let favorites_label = locale.text(Message::Favorites);
+    let favorites = if favorites.is_empty() {
+        None
+    } else {
+        Some(rsx! {
+            div {
+                id: "favorites",
+                "{favorites_label}: ",
+                {favorites.iter().enumerate().map(|(idx, t)| {
+                    let d: Date = (*t).into();
+                    rsx! {
+                        input {
+                            key: "favorite-key-{idx}",
+                            "type": "button",
+                            name: "favorite_{d}",
+                            value: "{d}",
+                            "onclick": "switchToDate( '{d}' )",
+                        }
+                    }
+                })},
+            }
+        })
+    };
     rsx! {
         head {
+            meta {
+                name: "viewport",
+                content: "width=device-width, initial-scale=1",
+            }
+            link {
+                rel: "manifest",
+                href: "../api/manifest.json",
+            }
             style {
-                dangerous_inner_html: include_str!("../../templates/style.css")
+                dangerous_inner_html: asset("style.css")
             }
         }
         body {
             form {
                 action: "javascript:searchDiary();",
+                select {
+                    id: "journal",
+                    name: "journal",
+                    "onchange": "switchJournal();",
+                    {journals.iter().enumerate().map(|(idx, j)| {
+                        rsx! {
+                            option {
+                                key: "journal-key-{idx}",
+                                value: "{j}",
+                                selected: *j == journal,
+                                "{j}"
+                            }
+                        }
+                    })},
+                },
+                input {
+                    "type": "button",
+                    name: "new_journal_button",
+                    value: locale.text(Message::NewJournal),
+                    "onclick": "createJournal();",
+                },
                 input {
                     "type": "button",
                     name: "sync_button",
-                    value: "Sync",
+                    value: locale.text(Message::Sync),
                     "onclick": "syncDiary();",
                 },
+                input {
+                    "type": "button",
+                    name: "note_button",
+                    value: locale.text(Message::Note),
+                    "onclick": "insertNote();",
+                },
                 input {
                     "type": "text",
                     name: "search_text",
@@ -50,9 +150,28 @@ fn IndexElement() -> Element {
                 input {
                     "type": "button",
                     name: "search_button",
-                    value: "Search",
+                    value: locale.text(Message::Search),
                     "onclick": "searchDiary();",
                 },
+                input {
+                    "type": "button",
+                    name: "trash_button",
+                    value: locale.text(Message::Trash),
+                    "onclick": "listTrash();",
+                },
+                input {
+                    "type": "button",
+                    name: "lock_button",
+                    value: locale.text(Message::Lock),
+                    "onclick": "lockDiary();",
+                },
+                input {
+                    "type": "button",
+                    name: "key_button",
+                    id: "key_button",
+                    value: locale.text(Message::Key),
+                    "onclick": "loadEncryptionKey();",
+                },
                 button {
                     name: "diary_status",
                     id: "diary_status",
@@ -64,7 +183,7 @@ fn IndexElement() -> Element {
                         input {
                             "type": "button",
                             name: "search_date_button",
-                            value: "Date",
+                            value: locale.text(Message::Date),
                             "onclick": "searchDate();",
                         },
                         input {
@@ -75,6 +194,8 @@ fn IndexElement() -> Element {
                     },
                 },
             },
+            {favorites},
+            {dashboard},
             nav {
                 id: "navigation",
                 "start": "0",
@@ -85,18 +206,61 @@ fn IndexElement() -> Element {
             script {
                 "language": "JavaScript",
                 "type": "text/javascript",
-                dangerous_inner_html: include_str!("../../templates/scripts.js")
+                dangerous_inner_html: asset("scripts.js")
             }
         }
     }
 }
 
+#[component]
+fn DashboardElement(dashboard: Dashboard, locale: Locale) -> Element {
+    let streak = dashboard.streak;
+    let conflicts = dashboard.conflicts;
+    let last_sync = dashboard.last_sync.map_or_else(
+        || StackString::from(locale.text(Message::Never)),
+        |d| StackString::from_display(DateTimeWrapper::from_offsetdatetime(d.into())),
+    );
+    let streak_label = locale.text(Message::Streak);
+    let days = locale.text(Message::Days);
+    let conflicts_label = locale.text(Message::Conflicts);
+    let last_sync_label = locale.text(Message::LastSync);
+    rsx! {
+        div {
+            id: "dashboard",
+            div {
+                "{streak_label}: {streak} {days}, ",
+                "{conflicts_label}: {conflicts}, ",
+                "{last_sync_label}: {last_sync}",
+            },
+            {dashboard.recent.iter().enumerate().map(|(idx, entry)| {
+                let d: Date = entry.date.into();
+                let first_line = &entry.first_line;
+                rsx! {
+                    div {
+                        key: "recent-key-{idx}",
+                        input {
+                            "type": "button",
+                            name: "recent_{d}",
+                            value: "{d}",
+                            "onclick": "switchToDate( '{d}' )",
+                        },
+                        " {first_line}",
+                    }
+                }
+            })},
+        }
+    }
+}
+
 /// # Errors
 /// Returns error if formatting fails
 pub fn list_body(
     conflicts: HashSet<DateType>,
     dates: Vec<DateType>,
     start: Option<usize>,
+    next_cursor: Option<DateType>,
+    compact: bool,
+    locale: Locale,
 ) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         DateListElement,
@@ -104,6 +268,9 @@ pub fn list_body(
             conflicts,
             dates,
             start,
+            next_cursor,
+            compact,
+            locale,
         },
     );
     app.rebuild_in_place();
@@ -120,29 +287,51 @@ fn DateListElement(
     conflicts: HashSet<DateType>,
     dates: Vec<DateType>,
     start: Option<usize>,
+    next_cursor: Option<DateType>,
+    compact: bool,
+    locale: Locale,
 ) -> Element {
-    let buttons = if start.is_some() {
+    let previous = locale.text(Message::Previous);
+    let next = locale.text(Message::Next);
+    let conflict = locale.text(Message::Conflict);
+    let previous_button = start.map(|_| {
         rsx! {
             button {
                 "type": "submit",
                 "onclick": "gotoEntries(-10)",
-                "Previous",
-            },
-            button {
-                "type": "submit",
-                "onclick": "gotoEntries(10)",
-                "Next",
+                "{previous}",
             }
         }
-    } else {
+    });
+    // the next page starts before the last date shown, which stays put when
+    // entries are added or removed
+    let next_button = next_cursor.map(|cursor| {
+        let cursor: Date = cursor.into();
         rsx! {
             button {
                 "type": "submit",
-                "onclick": "gotoEntries(10)",
-                "Next",
+                "onclick": "gotoEntries(10, '{cursor}')",
+                "{next}",
             }
         }
+    });
+    let buttons = rsx! {
+        {previous_button},
+        {next_button},
     };
+    if compact {
+        return rsx! {
+            CompactDateListElement {
+                conflicts: conflicts,
+                dates: dates,
+                locale: locale,
+            },
+            div {
+                class: "compact-list",
+                {buttons},
+            }
+        };
+    }
     rsx! {
         {dates.iter().enumerate().map(|(idx, t)| {
             let d: Date = (*t).into();
@@ -151,7 +340,7 @@ fn DateListElement(
                     input {
                         "type": "submit",
                         name: "conflict_{d}",
-                        value: "Conflict {d}",
+                        value: "{conflict} {d}",
                         "onclick": "listConflicts( '{d}' )",
                     }
                 })
@@ -176,15 +365,150 @@ fn DateListElement(
     }
 }
 
+/// Dates wrapped into rows of short buttons for narrow screens, dates with
+/// conflicts are marked and open the conflict list
+#[component]
+fn CompactDateListElement(
+    conflicts: HashSet<DateType>,
+    dates: Vec<DateType>,
+    locale: Locale,
+) -> Element {
+    rsx! {
+        div {
+            class: "compact-list",
+            {dates.iter().enumerate().map(|(idx, t)| {
+                let d: Date = (*t).into();
+                let label = locale.format_short_date(d);
+                if conflicts.contains(t) {
+                    rsx! {
+                        input {
+                            key: "compact-key-{idx}",
+                            "type": "button",
+                            class: "conflict",
+                            name: "conflict_{d}",
+                            value: "{label}*",
+                            "onclick": "listConflicts( '{d}' )",
+                        }
+                    }
+                } else {
+                    rsx! {
+                        input {
+                            key: "compact-key-{idx}",
+                            "type": "button",
+                            name: "{d}",
+                            value: "{label}",
+                            "onclick": "switchToDate( '{d}' )",
+                        }
+                    }
+                }
+            })},
+        }
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn list_groups_body(
+    conflicts: HashSet<DateType>,
+    groups: Vec<DateGroup>,
+    period: StatsPeriod,
+    newest: Vec<DateType>,
+    compact: bool,
+    locale: Locale,
+) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(
+        DateGroupsElement,
+        DateGroupsElementProps {
+            conflicts,
+            groups,
+            period,
+            newest,
+            compact,
+            locale,
+        },
+    );
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer
+        .render_to(&mut buffer, &app)
+        .map_err(Into::<Error>::into)?;
+    Ok(buffer)
+}
+
+/// Collapsible groups with their number of entries and words, only the
+/// newest group comes with its dates, the others load theirs when opened
+#[component]
+fn DateGroupsElement(
+    conflicts: HashSet<DateType>,
+    groups: Vec<DateGroup>,
+    period: StatsPeriod,
+    newest: Vec<DateType>,
+    compact: bool,
+    locale: Locale,
+) -> Element {
+    let entries = locale.text(Message::Entries);
+    let words = locale.text(Message::Words);
+    let week_of = locale.text(Message::WeekOf);
+    rsx! {
+        {groups.iter().enumerate().map(|(idx, group)| {
+            let first = group.period;
+            let last = period.last_day(first);
+            let label = match period {
+                StatsPeriod::Day => locale.format_date(first),
+                StatsPeriod::Week => {
+                    let short = locale.format_short_date(first);
+                    format_sstr!("{week_of} {short}, {}", first.year())
+                }
+                StatsPeriod::Month => locale.format_month(first),
+                StatsPeriod::Year => StackString::from_display(first.year()),
+            };
+            let (nentries, nwords) = (group.entries, group.words);
+            let dates = if idx == 0 {
+                Some(rsx! {
+                    DateListElement {
+                        conflicts: conflicts.clone(),
+                        dates: newest.clone(),
+                        start: None,
+                        next_cursor: None,
+                        compact: compact,
+                        locale: locale,
+                    }
+                })
+            } else {
+                None
+            };
+            rsx! {
+                details {
+                    key: "group-key-{idx}",
+                    class: "date-group",
+                    open: idx == 0,
+                    "ontoggle": "loadGroup(this, '{first}', '{last}')",
+                    summary { "{label} ({nentries} {entries}, {nwords} {words})" },
+                    div {
+                        class: "date-group-dates",
+                        {dates}
+                    }
+                }
+            }
+        })}
+    }
+}
+
 /// # Errors
 /// Returns error if formatting fails
 pub fn list_conflicts_body(
     date: Option<DateType>,
     conflicts: Vec<DateTimeWrapper>,
+    locale: Locale,
 ) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         ListConflictsElement,
-        ListConflictsElementProps { date, conflicts },
+        ListConflictsElementProps {
+            date,
+            conflicts,
+            locale,
+        },
     );
     app.rebuild_in_place();
     let mut renderer = dioxus_ssr::Renderer::default();
@@ -196,8 +520,15 @@ pub fn list_conflicts_body(
 }
 
 #[component]
-fn ListConflictsElement(date: Option<DateType>, conflicts: Vec<DateTimeWrapper>) -> Element {
+fn ListConflictsElement(
+    date: Option<DateType>,
+    conflicts: Vec<DateTimeWrapper>,
+    locale: Locale,
+) -> Element {
     let local = DateTimeWrapper::local_tz();
+    let clean = locale.text(Message::Clean);
+    let show = locale.text(Message::Show);
+    let list = locale.text(Message::List);
     let clean_conflicts = if let Some(date) = date {
         if conflicts.is_empty() {
             None
@@ -207,7 +538,7 @@ fn ListConflictsElement(date: Option<DateType>, conflicts: Vec<DateTimeWrapper>)
                 button {
                     "type": "submit",
                     "onclick": "cleanConflicts('{date}')",
-                    "Clean"
+                    "{clean}"
                 }
             })
         }
@@ -222,7 +553,7 @@ fn ListConflictsElement(date: Option<DateType>, conflicts: Vec<DateTimeWrapper>)
                     key: "show-key-{idx}",
                     "type": "button",
                     name: "show_{t}",
-                    value: "Show {t}",
+                    value: "{show} {t}",
                     "onclick": "showConflict( '{d}', '{t}' )",
                 }
             }
@@ -232,7 +563,7 @@ fn ListConflictsElement(date: Option<DateType>, conflicts: Vec<DateTimeWrapper>)
             button {
                 "type": "submit",
                 "onclick": "switchToList()",
-                "List",
+                "{list}",
             },
         },
     }
@@ -240,8 +571,17 @@ fn ListConflictsElement(date: Option<DateType>, conflicts: Vec<DateTimeWrapper>)
 
 /// # Errors
 /// Returns error if formatting fails
-pub fn search_body(results: Vec<StackString>) -> Result<String, Error> {
-    let mut app = VirtualDom::new_with_props(SearchElement, SearchElementProps { results });
+pub fn search_body(
+    results: Vec<StackString>,
+    search_text: Option<StackString>,
+) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(
+        SearchElement,
+        SearchElementProps {
+            results,
+            search_text,
+        },
+    );
     app.rebuild_in_place();
     let mut renderer = dioxus_ssr::Renderer::default();
     let mut buffer = String::new();
@@ -251,31 +591,95 @@ pub fn search_body(results: Vec<StackString>) -> Result<String, Error> {
     Ok(buffer)
 }
 
+/// One based number of the first line of `text` containing `needle`
+/// (ignoring case), the first line if none does
+fn matching_line(text: &str, needle: &str) -> usize {
+    let needle = needle.to_lowercase();
+    text.split('\n')
+        .position(|line| line.to_lowercase().contains(&needle))
+        .map_or(1, |idx| idx + 1)
+}
+
+/// Date of a search result headed by an entry date, optionally followed by a
+/// section anchor, cache entries are headed by a timestamp and aren't linked
+fn result_date(header: &str) -> Option<Date> {
+    let date = header.get(..10)?;
+    let rest = &header[10..];
+    if !rest.is_empty() && !rest.starts_with(" #") {
+        return None;
+    }
+    Date::parse(date, format_description!("[year]-[month]-[day]")).ok()
+}
+
 #[component]
-fn SearchElement(results: Vec<StackString>) -> Element {
-    let body = results.join("\n");
+fn SearchElement(results: Vec<StackString>, search_text: Option<StackString>) -> Element {
+    let needle = search_text.unwrap_or_default();
+    let results = results.into_iter().map(|result| {
+        let (header, body) = result.split_once('\n').unwrap_or(("", &result));
+        let header = match result_date(header) {
+            Some(date) => {
+                let line = matching_line(body, &needle);
+                rsx! {
+                    a {
+                        class: "search-link",
+                        href: "../api/display?date={date}#L{line}",
+                        "onclick": "return showLine('{date}', {line});",
+                        "{header}"
+                    }
+                }
+            }
+            None if header.is_empty() => rsx! {},
+            None => rsx! {
+                span { "{header}" }
+            },
+        };
+        rsx! {
+            div {
+                class: "search-result",
+                {header},
+                pre { class: "search-text", "{body}" }
+            }
+        }
+    });
     rsx! {
-        textarea {
-            "autofocus": "true",
-            readonly: "readonly",
-            name: "message",
-            id: "diary_editor_form",
-            "rows": "50",
-            "cols": "100",
-            "{body}",
+        div {
+            id: "search_results",
+            {results},
         }
     }
 }
 
+/// Shown below an entry when it is displayed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EntryFooter {
+    pub comments: Vec<Comment>,
+    /// Cache entries merged into the entry
+    pub subentries: Vec<DiarySubentry>,
+    /// Pathway of the last write
+    pub source: Option<StackString>,
+}
+
 /// # Errors
 /// Returns error if formatting fails
-pub fn edit_body(date: Date, text: Vec<StackString>, edit_button: bool) -> Result<String, Error> {
+pub fn edit_body(
+    date: Date,
+    text: Vec<StackString>,
+    edit_button: bool,
+    encrypted: Option<EncryptedEntry>,
+    hash: Option<StackString>,
+    footer: EntryFooter,
+    locale: Locale,
+) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         EditElement,
         EditElementProps {
             date,
             text,
             edit_button,
+            encrypted,
+            hash,
+            footer,
+            locale,
         },
     );
     app.rebuild_in_place();
@@ -288,15 +692,105 @@ pub fn edit_body(date: Date, text: Vec<StackString>, edit_button: bool) -> Resul
 }
 
 #[component]
-fn EditElement(date: Date, text: Vec<StackString>, edit_button: bool) -> Element {
+fn EditElement(
+    date: Date,
+    text: Vec<StackString>,
+    edit_button: bool,
+    encrypted: Option<EncryptedEntry>,
+    hash: Option<StackString>,
+    footer: EntryFooter,
+    locale: Locale,
+) -> Element {
+    let EntryFooter {
+        comments,
+        subentries,
+        source,
+    } = footer;
     let text = text.join("\n");
+    let hash = hash.unwrap_or_default();
+    // paragraphs of shared journals are stamped with their author
+    let badges: Vec<_> = if edit_button {
+        entry_authors(&text)
+            .into_iter()
+            .map(|author| {
+                rsx! {
+                    span {
+                        class: "author-badge",
+                        "{author}"
+                    }
+                }
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    // links to the `## ` headers of the entry, the slug is the anchor
+    // `/api/entry/section` and search results use
+    let sections: Vec<_> = if edit_button {
+        parse_sections(&text)
+            .into_iter()
+            .filter(|section| !section.slug.is_empty())
+            .map(|section| {
+                let slug = &section.slug;
+                let title = &section.title;
+                let line = section.line + 1;
+                rsx! {
+                    a {
+                        id: "section-{slug}",
+                        href: "#L{line}",
+                        "{title}"
+                    }
+                }
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let encrypted = encrypted.map(|entry| {
+        let ciphertext = &entry.ciphertext;
+        let nonce = &entry.nonce;
+        rsx! {
+            div {
+                id: "encrypted_entry",
+                "data-ciphertext": "{ciphertext}",
+                "data-nonce": "{nonce}",
+            }
+        }
+    });
     let buttons = if edit_button {
         rsx! {
             input {
                 "type": "button",
                 name: "edit",
-                value: "Edit",
+                value: locale.text(Message::Edit),
                 "onclick": "switchToEditor('{date}')",
+            },
+            input {
+                "type": "button",
+                name: "star",
+                id: "star_button",
+                value: locale.text(Message::Star),
+                "onclick": "toggleStar('{date}')",
+            },
+            input {
+                "type": "button",
+                name: "private",
+                id: "private_button",
+                value: locale.text(Message::Lock),
+                title: locale.text(Message::HideFromViewers),
+                "onclick": "togglePrivate('{date}')",
+            },
+            input {
+                "type": "button",
+                name: "delete",
+                value: locale.text(Message::Delete),
+                "onclick": "deleteEntry('{date}')",
+            },
+            input {
+                "type": "button",
+                name: "comment",
+                value: locale.text(Message::Comment),
+                "onclick": "addComment('{date}')",
             }
         }
     } else {
@@ -306,28 +800,45 @@ fn EditElement(date: Date, text: Vec<StackString>, edit_button: bool) -> Element
                 input {
                     "type": "button",
                     name: "update",
-                    value: "Update",
+                    value: locale.text(Message::Update),
+                    title: "Ctrl+S",
                     "onclick": "submitFormData('{date}')",
                 },
                 input {
                     "type": "button",
                     name: "cancel",
-                    value: "Cancel",
-                    "onclick": "switchToDisplay('{date}')",
+                    value: locale.text(Message::Cancel),
+                    title: "Esc",
+                    "onclick": "cancelEdit('{date}')",
                 }
             }
         }
     };
     let textarea = if edit_button {
+        // numbered lines, `#L42` links to line 42
+        let lines = text.split('\n').enumerate().map(|(idx, line)| {
+            let number = idx + 1;
+            rsx! {
+                div {
+                    id: "L{number}",
+                    class: "diary-line",
+                    a {
+                        class: "line-number",
+                        href: "#L{number}",
+                        "{number}"
+                    },
+                    span {
+                        class: "line-text",
+                        "{line}"
+                    }
+                }
+            }
+        });
         rsx! {
-            textarea {
-                name: "message",
-                id: "diary_editor_form",
-                rows: "50",
-                cols: "100",
-                form: "diary_edit_form",
-                readonly: true,
-                "{text}",
+            div {
+                id: "diary_display",
+                class: "diary-text",
+                {lines},
             }
         }
     } else {
@@ -335,17 +846,91 @@ fn EditElement(date: Date, text: Vec<StackString>, edit_button: bool) -> Element
             textarea {
                 name: "message",
                 id: "diary_editor_form",
-                rows: "50",
-                cols: "100",
+                class: "diary-text",
                 form: "diary_edit_form",
+                "data-date": "{date}",
+                "data-hash": "{hash}",
                 "{text}",
             }
         }
     };
+    let comments = comments.into_iter().map(|comment| {
+        let id: Uuid = comment.id.into();
+        let author = &comment.author;
+        let created_at: DateTimeWrapper = OffsetDateTime::from(comment.created_at).into();
+        let text = &comment.text;
+        rsx! {
+            div {
+                class: "diary-comment",
+                "data-id": "{id}",
+                span {
+                    class: "author-badge",
+                    "{author}"
+                },
+                span {
+                    class: "comment-date",
+                    "{created_at}"
+                },
+                p { "{text}" }
+            }
+        }
+    });
+    let written_via = locale.text(Message::LastWrittenVia);
+    let source_text = source.map(|source| {
+        rsx! {
+            div {
+                class: "entry-source",
+                "{written_via} {source}"
+            }
+        }
+    });
+    // cache entries merged into the entry, in the order they were written
+    let local = DateTimeWrapper::local_tz();
+    let timeline = subentries.into_iter().map(|subentry| {
+        let time = subentry
+            .diary_datetime
+            .to_timezone(local)
+            .format(format_description!("[hour]:[minute]"))
+            .unwrap_or_else(|_| String::new());
+        let source = subentry.source.unwrap_or_default();
+        let text = &subentry.diary_text;
+        rsx! {
+            div {
+                class: "diary-subentry",
+                span {
+                    class: "subentry-time",
+                    "{time}"
+                },
+                span {
+                    class: "author-badge",
+                    "{source}"
+                },
+                p { "{text}" }
+            }
+        }
+    });
     rsx! {
+        {encrypted},
+        div {
+            class: "author-badges",
+            {badges.into_iter()},
+        },
+        nav {
+            class: "entry-sections",
+            {sections.into_iter()},
+        },
         {textarea},
         br {
             {buttons}
+        },
+        div {
+            id: "diary_timeline",
+            {timeline},
+        },
+        {source_text},
+        div {
+            id: "diary_comments",
+            {comments},
         }
     }
 }
@@ -356,6 +941,8 @@ pub fn show_conflict_body(
     date: Date,
     conflicts: Vec<DiaryConflict>,
     datetime: DateTimeWrapper,
+    split: bool,
+    locale: Locale,
 ) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         ShowConflictElement,
@@ -363,6 +950,8 @@ pub fn show_conflict_body(
             date,
             conflicts,
             datetime,
+            split,
+            locale,
         },
     );
     app.rebuild_in_place();
@@ -379,8 +968,26 @@ fn ShowConflictElement(
     date: Date,
     conflicts: Vec<DiaryConflict>,
     datetime: DateTimeWrapper,
+    split: bool,
+    locale: Locale,
 ) -> Element {
-    let conflict_text = {
+    let dt = datetime
+        .format(format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]Z"
+        ))
+        .unwrap_or_else(|_| String::new());
+    let split_text = split.then(|| {
+        rsx! {
+            SplitConflictElement {
+                date: date,
+                conflicts: conflicts.clone(),
+                dt: dt.clone(),
+            }
+        }
+    });
+    let conflict_text = if split {
+        Vec::new()
+    } else {
         let diary_dates: BTreeSet<Date> = conflicts.iter().map(|entry| entry.diary_date).collect();
         if diary_dates.len() > 1 {
             Vec::new()
@@ -395,16 +1002,20 @@ fn ShowConflictElement(
                     let nlines = entry.diff_text.split('\n').count() + 1;
                     let id = entry.id;
                     let diff = &entry.diff_text;
-                    let dt = datetime
-                        .format(format_description!(
-                            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]Z"
-                        ))
-                        .unwrap_or_else(|_| String::new());
+                    let author = entry.author.as_ref().map(|author| {
+                        rsx! {
+                            span {
+                                class: "author-badge",
+                                "{author}"
+                            }
+                        }
+                    });
                     match entry.diff_type.as_ref() {
                         "rem" => rsx! {
+                            {author},
                             textarea {
                                 style: "color:Red;",
-                                cols: 100,
+                                class: "conflict-text",
                                 rows: "{nlines}",
                                 "{diff}"
                             },
@@ -418,9 +1029,10 @@ fn ShowConflictElement(
                             }
                         },
                         "add" => rsx! {
+                            {author},
                             textarea {
                                 style: "color:Blue;",
-                                cols: 100,
+                                class: "conflict-text",
                                 rows: "{nlines}",
                                 "{diff}"
                             },
@@ -435,7 +1047,7 @@ fn ShowConflictElement(
                         },
                         _ => rsx! {
                             textarea {
-                                cols: 100,
+                                class: "conflict-text",
                                 rows: "{nlines}",
                                 "{diff}",
                             }
@@ -447,38 +1059,453 @@ fn ShowConflictElement(
         }
     };
 
-    let dt = datetime
-        .format(format_description!(
-            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]Z"
-        ))
-        .unwrap_or_else(|_| String::new());
+    let (view_label, other_view) = if split {
+        (locale.text(Message::Stacked), "stacked")
+    } else {
+        (locale.text(Message::SideBySide), "split")
+    };
     rsx! {
         div {
+            {split_text},
             {conflict_text.into_iter()},
         }
+        input {
+            "type": "button",
+            name: "view",
+            value: "{view_label}",
+            "onclick": "showConflictView('{date}', '{dt}', '{other_view}')",
+        },
         input {
             "type": "button",
             name: "display",
-            value: "Display",
+            value: locale.text(Message::Display),
             "onclick": "switchToDisplay('{date}')",
         },
         input {
             "type": "button",
             name: "commit",
-            value: "Commit",
+            value: locale.text(Message::Commit),
             "onclick": "commitConflict('{date}', '{dt}')",
         },
         input {
             "type": "button",
             name: "remove",
-            value: "Remove",
+            value: locale.text(Message::Remove),
             "onclick": "removeConflict('{date}', '{dt}')",
         },
         input {
             "type": "button",
             name: "edit",
-            value: "Edit",
+            value: locale.text(Message::Edit),
             "onclick": "switchToEditor('{date}')",
         },
     }
 }
+
+/// Line of one side of the split view, `segments` split a replaced line
+/// where it differs from the line it was paired with
+struct SplitCell {
+    line: usize,
+    segments: Vec<InlineSegment>,
+}
+
+impl SplitCell {
+    fn new(line: usize, text: &str) -> Self {
+        Self {
+            line,
+            segments: vec![InlineSegment {
+                changed: false,
+                text: text.into(),
+            }],
+        }
+    }
+}
+
+enum SplitRow {
+    Same(SplitCell, SplitCell),
+    Changed(Option<SplitCell>, Option<SplitCell>),
+    /// Buttons toggling the `rem` and `add` conflict rows of a change
+    Buttons(Vec<DiaryConflict>, Vec<DiaryConflict>),
+}
+
+type SplitHunks = Vec<(DiaryConflict, DiffHunk)>;
+
+/// Rows of the split view, the lines removed and added by each change are
+/// paired up in order
+fn split_rows(conflicts: &[DiaryConflict]) -> Vec<SplitRow> {
+    fn flush(rows: &mut Vec<SplitRow>, rem: &mut SplitHunks, add: &mut SplitHunks) {
+        if rem.is_empty() && add.is_empty() {
+            return;
+        }
+        let lines = |hunks: &[(DiaryConflict, DiffHunk)], new: bool| -> Vec<(usize, StackString)> {
+            hunks
+                .iter()
+                .flat_map(|(_, hunk)| {
+                    let start = if new { hunk.new_line } else { hunk.old_line };
+                    hunk.text
+                        .split('\n')
+                        .enumerate()
+                        .map(move |(idx, line)| (start + idx, line.into()))
+                })
+                .collect()
+        };
+        let old_lines = lines(rem, false);
+        let new_lines = lines(add, true);
+        for idx in 0..old_lines.len().max(new_lines.len()) {
+            let row = match (old_lines.get(idx), new_lines.get(idx)) {
+                (Some((old_line, old)), Some((new_line, new))) => {
+                    let (old_segments, new_segments) = inline_diff(old, new);
+                    SplitRow::Changed(
+                        Some(SplitCell {
+                            line: *old_line,
+                            segments: old_segments,
+                        }),
+                        Some(SplitCell {
+                            line: *new_line,
+                            segments: new_segments,
+                        }),
+                    )
+                }
+                (old, new) => SplitRow::Changed(
+                    old.map(|(line, text)| SplitCell::new(*line, text)),
+                    new.map(|(line, text)| SplitCell::new(*line, text)),
+                ),
+            };
+            rows.push(row);
+        }
+        rows.push(SplitRow::Buttons(
+            rem.drain(..).map(|(c, _)| c).collect(),
+            add.drain(..).map(|(c, _)| c).collect(),
+        ));
+    }
+
+    let mut rows = Vec::new();
+    let mut rem = Vec::new();
+    let mut add = Vec::new();
+    for (conflict, hunk) in conflicts.iter().zip(DiaryConflict::hunks(conflicts)) {
+        match hunk.tag {
+            DiffTag::Same => {
+                flush(&mut rows, &mut rem, &mut add);
+                for (idx, line) in hunk.text.split('\n').enumerate() {
+                    rows.push(SplitRow::Same(
+                        SplitCell::new(hunk.old_line + idx, line),
+                        SplitCell::new(hunk.new_line + idx, line),
+                    ));
+                }
+            }
+            DiffTag::Rem => rem.push((conflict.clone(), hunk)),
+            DiffTag::Add => add.push((conflict.clone(), hunk)),
+        }
+    }
+    flush(&mut rows, &mut rem, &mut add);
+    rows
+}
+
+/// Original text on the left and updated text on the right, with the
+/// changed words of replaced lines highlighted
+#[component]
+fn SplitConflictElement(date: Date, conflicts: Vec<DiaryConflict>, dt: String) -> Element {
+    let side = |cell: Option<&SplitCell>, class: &'static str| match cell {
+        Some(cell) => {
+            let line = cell.line;
+            let segments = cell.segments.iter().enumerate().map(|(idx, segment)| {
+                let text = &segment.text;
+                if segment.changed {
+                    rsx! {
+                        span {
+                            key: "segment-key-{idx}",
+                            class: "{class}-word",
+                            "{text}"
+                        }
+                    }
+                } else {
+                    rsx! {
+                        span {
+                            key: "segment-key-{idx}",
+                            "{text}"
+                        }
+                    }
+                }
+            });
+            rsx! {
+                td {
+                    class: "line-no",
+                    "{line}"
+                },
+                td {
+                    class: "{class}",
+                    {segments}
+                },
+            }
+        }
+        None => rsx! {
+            td { class: "line-no" },
+            td {},
+        },
+    };
+    let buttons = |conflicts: &[DiaryConflict], add: bool| {
+        let buttons = conflicts.iter().enumerate().map(|(idx, conflict)| {
+            let id = conflict.id;
+            let author = conflict.author.as_ref().map(|author| {
+                rsx! {
+                    span {
+                        class: "author-badge",
+                        "{author}"
+                    }
+                }
+            });
+            if add {
+                rsx! {
+                    span {
+                        key: "button-key-{idx}",
+                        {author},
+                        input {
+                            "type": "button",
+                            name: "add",
+                            value: "Add",
+                            "onclick": "updateConflictAdd('{id}', '{date}', '{dt}');",
+                        }
+                    }
+                }
+            } else {
+                rsx! {
+                    span {
+                        key: "button-key-{idx}",
+                        {author},
+                        input {
+                            "type": "button",
+                            name: "rm",
+                            value: "Rm",
+                            "onclick": "updateConflictRem('{id}', '{date}', '{dt}');",
+                        }
+                    }
+                }
+            }
+        });
+        rsx! {
+            td { class: "line-no" },
+            td { {buttons} },
+        }
+    };
+    let rows = split_rows(&conflicts);
+    rsx! {
+        table {
+            class: "conflict-split",
+            {rows.iter().enumerate().map(|(idx, row)| {
+                let cells = match row {
+                    SplitRow::Same(old, new) => rsx! {
+                        {side(Some(old), "diff-same")},
+                        {side(Some(new), "diff-same")},
+                    },
+                    SplitRow::Changed(old, new) => rsx! {
+                        {side(old.as_ref(), "diff-rem")},
+                        {side(new.as_ref(), "diff-add")},
+                    },
+                    SplitRow::Buttons(rem, add) => rsx! {
+                        {buttons(rem, true)},
+                        {buttons(add, false)},
+                    },
+                };
+                rsx! {
+                    tr {
+                        key: "split-key-{idx}",
+                        {cells}
+                    }
+                }
+            })},
+        }
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn trash_body(entries: Vec<(Date, DateTimeWrapper)>, locale: Locale) -> Result<String, Error> {
+    let mut app =
+        VirtualDom::new_with_props(TrashElement, TrashElementProps { entries, locale });
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer
+        .render_to(&mut buffer, &app)
+        .map_err(Into::<Error>::into)?;
+    Ok(buffer)
+}
+
+#[component]
+fn TrashElement(entries: Vec<(Date, DateTimeWrapper)>, locale: Locale) -> Element {
+    let deleted = locale.text(Message::Deleted);
+    let list = locale.text(Message::List);
+    rsx! {
+        {entries.iter().enumerate().map(|(idx, (d, deleted_at))| {
+            rsx! {
+                div {
+                    key: "trash-key-{idx}",
+                    "{d} {deleted} {deleted_at}",
+                    input {
+                        "type": "button",
+                        name: "restore_{d}",
+                        value: locale.text(Message::Restore),
+                        "onclick": "restoreEntry('{d}')",
+                    },
+                    input {
+                        "type": "button",
+                        name: "purge_{d}",
+                        value: locale.text(Message::Purge),
+                        "onclick": "purgeEntry('{d}')",
+                    },
+                }
+            }
+        })},
+        button {
+            "type": "submit",
+            "onclick": "switchToList()",
+            "{list}",
+        },
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn print_body(
+    title: StackString,
+    entries: Vec<(Date, Option<StackString>)>,
+    locale: Locale,
+) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(
+        PrintElement,
+        PrintElementProps {
+            title,
+            entries,
+            locale,
+        },
+    );
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::from("<!DOCTYPE html>");
+    renderer
+        .render_to(&mut buffer, &app)
+        .map_err(Into::<Error>::into)?;
+    Ok(buffer)
+}
+
+/// Standalone document without buttons or scripts, one entry per page,
+/// encrypted entries (`None`) can't be rendered by the server
+#[component]
+fn PrintElement(
+    title: StackString,
+    entries: Vec<(Date, Option<StackString>)>,
+    locale: Locale,
+) -> Element {
+    let encrypted = locale.text(Message::EncryptedEntry);
+    let entries = entries.iter().enumerate().map(|(idx, (date, text))| {
+        let date = locale.format_date(*date);
+        let text = match text {
+            Some(text) => rsx! {
+                div { class: "print-text", "{text}" }
+            },
+            None => rsx! {
+                div { class: "print-encrypted", "{encrypted}" }
+            },
+        };
+        rsx! {
+            article {
+                key: "print-key-{idx}",
+                class: "print-entry",
+                h2 { "{date}" },
+                {text},
+            }
+        }
+    });
+    rsx! {
+        html {
+            head {
+                meta { charset: "utf-8" },
+                title { "{title}" },
+                style {
+                    dangerous_inner_html: asset("print.css")
+                }
+            }
+            body {
+                h1 { "{title}" },
+                {entries},
+            }
+        }
+    }
+}
+
+/// Seconds between reloads of the kiosk view
+const KIOSK_REFRESH_SECS: u32 = 1800;
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn kiosk_body(
+    date: Date,
+    text: Option<StackString>,
+    prompt: StackString,
+    locale: Locale,
+) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(
+        KioskElement,
+        KioskElementProps {
+            date,
+            text,
+            prompt,
+            locale,
+        },
+    );
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::from("<!DOCTYPE html>");
+    renderer
+        .render_to(&mut buffer, &app)
+        .map_err(Into::<Error>::into)?;
+    Ok(buffer)
+}
+
+/// Black on white with no scripts, e-ink screens redraw the whole page
+#[component]
+fn KioskElement(
+    date: Date,
+    text: Option<StackString>,
+    prompt: StackString,
+    locale: Locale,
+) -> Element {
+    let heading = locale.format_date(date);
+    let empty = locale.text(Message::NothingWrittenYet);
+    let text = text.map_or_else(
+        || {
+            rsx! {
+                p { class: "kiosk-empty", "{empty}" }
+            }
+        },
+        |text| {
+            rsx! {
+                div { class: "kiosk-text", "{text}" }
+            }
+        },
+    );
+    rsx! {
+        html {
+            head {
+                meta { charset: "utf-8" },
+                meta {
+                    "http-equiv": "refresh",
+                    content: "{KIOSK_REFRESH_SECS}",
+                },
+                meta {
+                    name: "viewport",
+                    content: "width=device-width, initial-scale=1",
+                },
+                title { "{heading}" },
+                style {
+                    dangerous_inner_html: asset("kiosk.css")
+                }
+            }
+            body {
+                h1 { "{heading}" },
+                {text},
+                p { class: "kiosk-prompt", "{prompt}" },
+            }
+        }
+    }
+}
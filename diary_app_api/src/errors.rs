@@ -18,14 +18,58 @@ use std::{
 };
 use thiserror::Error;
 
+use diary_app_lib::{
+    entry_limits::LimitError,
+    error_reporting::{report_error, report_message},
+    retry::{Backend, BackendError},
+    services::EntryError,
+    sync_lock::SyncLockError,
+};
+
+/// Machine readable reason of an error response, the `error_code` of the
+/// body, clients match on it rather than on the message
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    NotFound,
+    EntryNotFound,
+    Locked,
+    Forbidden,
+    ConflictExists,
+    SyncInProgress,
+    TooManyRequests,
+    PayloadTooLarge,
+    LengthRequired,
+    MethodNotAllowed,
+    S3Unavailable,
+    InternalError,
+}
+
 #[derive(Error, Debug)]
 pub enum ServiceError {
     #[error("Internal Server Error")]
     InternalServerError,
     #[error("BadRequest: {0}")]
     BadRequest(String),
+    #[error("Entry Not Found: {0}")]
+    EntryNotFound(String),
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Locked")]
+    Locked,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Sync In Progress: {0}")]
+    SyncInProgress(String),
+    #[error("Too Many Requests")]
+    TooManyRequests,
+    #[error("Payload Too Large: {0}")]
+    PayloadTooLarge(String),
+    #[error("Length Required")]
+    LengthRequired,
     #[error("Anyhow error {0}")]
     AnyhowError(#[from] AnyhowError),
     #[error("Handlebars RenderError {0}")]
@@ -38,9 +82,90 @@ pub enum ServiceError {
 
 impl Reject for ServiceError {}
 
+impl ServiceError {
+    /// Status, code and message of the response, errors from the library are
+    /// classified by their cause
+    fn response_parts(&self) -> (StatusCode, ErrorCode, Cow<str>) {
+        match self {
+            Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, ErrorCode::BadRequest, msg.into()),
+            Self::EntryNotFound(msg) => {
+                (StatusCode::NOT_FOUND, ErrorCode::EntryNotFound, msg.into())
+            }
+            Self::Locked => (StatusCode::FORBIDDEN, ErrorCode::Locked, "Locked".into()),
+            Self::Forbidden => (
+                StatusCode::FORBIDDEN,
+                ErrorCode::Forbidden,
+                "Forbidden".into(),
+            ),
+            Self::Conflict(msg) => (StatusCode::CONFLICT, ErrorCode::ConflictExists, msg.into()),
+            Self::SyncInProgress(msg) => {
+                (StatusCode::CONFLICT, ErrorCode::SyncInProgress, msg.into())
+            }
+            Self::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorCode::TooManyRequests,
+                "Too Many Requests".into(),
+            ),
+            Self::PayloadTooLarge(msg) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorCode::PayloadTooLarge,
+                msg.into(),
+            ),
+            Self::LengthRequired => (
+                StatusCode::LENGTH_REQUIRED,
+                ErrorCode::LengthRequired,
+                "Length Required".into(),
+            ),
+            Self::AnyhowError(e) => {
+                if let Some(e) = e.downcast_ref::<EntryError>() {
+                    (
+                        StatusCode::NOT_FOUND,
+                        ErrorCode::EntryNotFound,
+                        e.to_string().into(),
+                    )
+                } else if let Some(e) = e.downcast_ref::<SyncLockError>() {
+                    (
+                        StatusCode::CONFLICT,
+                        ErrorCode::SyncInProgress,
+                        e.to_string().into(),
+                    )
+                } else if let Some(e) = e.downcast_ref::<LimitError>() {
+                    (
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        ErrorCode::PayloadTooLarge,
+                        e.to_string().into(),
+                    )
+                } else if e
+                    .chain()
+                    .filter_map(|e| e.downcast_ref::<BackendError>())
+                    .any(|e| e.backend() == Backend::S3)
+                {
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        ErrorCode::S3Unavailable,
+                        "S3 Unavailable, Please try again later".into(),
+                    )
+                } else {
+                    internal_error()
+                }
+            }
+            _ => internal_error(),
+        }
+    }
+}
+
+fn internal_error() -> (StatusCode, ErrorCode, Cow<'static, str>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::InternalError,
+        "Internal Server Error, Please try again later".into(),
+    )
+}
+
 #[derive(Serialize)]
 struct ErrorMessage<'a> {
     code: u16,
+    error_code: ErrorCode,
     message: &'a str,
 }
 
@@ -48,11 +173,13 @@ struct ErrorMessage<'a> {
 /// Function never returns an error
 pub async fn error_response(err: Rejection) -> Result<Box<dyn Reply>, Infallible> {
     let code: StatusCode;
-    let message: &str;
+    let error_code: ErrorCode;
+    let message: Cow<str>;
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
-        message = "NOT FOUND";
+        error_code = ErrorCode::NotFound;
+        message = "NOT FOUND".into();
     } else if err.find::<InvalidHeader>().is_some() {
         return Ok(Box::new(login_html()));
     } else if let Some(missing_cookie) = err.find::<MissingCookie>() {
@@ -60,40 +187,50 @@ pub async fn error_response(err: Rejection) -> Result<Box<dyn Reply>, Infallible
             return Ok(Box::new(login_html()));
         }
         code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Internal Server Error";
+        error_code = ErrorCode::InternalError;
+        message = "Internal Server Error".into();
     } else if let Some(service_err) = err.find::<ServiceError>() {
-        match service_err {
-            ServiceError::BadRequest(msg) => {
-                code = StatusCode::BAD_REQUEST;
-                message = msg.as_str();
-            }
-            ServiceError::Unauthorized => {
-                return Ok(Box::new(login_html()));
-            }
-            _ => {
-                error!("Other error: {:?}", service_err);
-                code = StatusCode::INTERNAL_SERVER_ERROR;
-                message = "Internal Server Error, Please try again later";
-            }
+        if let ServiceError::Unauthorized = service_err {
+            return Ok(Box::new(login_html()));
+        }
+        (code, error_code, message) = service_err.response_parts();
+        if code.is_server_error() {
+            error!("Other error: {:?}", service_err);
+            report_service_error(service_err, code);
         }
+    } else if err.find::<rweb::reject::PayloadTooLarge>().is_some() {
+        code = StatusCode::PAYLOAD_TOO_LARGE;
+        error_code = ErrorCode::PayloadTooLarge;
+        message = "Payload Too Large".into();
     } else if err.find::<rweb::reject::MethodNotAllowed>().is_some() {
         code = StatusCode::METHOD_NOT_ALLOWED;
-        message = "METHOD NOT ALLOWED";
+        error_code = ErrorCode::MethodNotAllowed;
+        message = "METHOD NOT ALLOWED".into();
     } else {
         error!("Unknown error: {:?}", err);
-        code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Internal Server Error, Please try again later";
+        report_message(&format!("Unknown error: {err:?}"), &[("status", "500")]);
+        (code, error_code, message) = internal_error();
     };
 
     let reply = rweb::reply::json(&ErrorMessage {
         code: code.as_u16(),
-        message,
+        error_code,
+        message: &message,
     });
     let reply = rweb::reply::with_status(reply, code);
 
     Ok(Box::new(reply))
 }
 
+/// Errors answered with 5xx are bugs or outages, send them to sentry
+fn report_service_error(err: &ServiceError, code: StatusCode) {
+    let context = [("status", code.as_str())];
+    match err {
+        ServiceError::AnyhowError(e) => report_error(e, &context),
+        e => report_message(&e.to_string(), &context),
+    }
+}
+
 fn login_html() -> impl Reply {
     rweb::reply::html(
         "
@@ -120,9 +257,40 @@ impl ResponseEntity for ServiceError {
     fn describe_responses(_: &mut ComponentDescriptor) -> Responses {
         let mut map = Responses::new();
 
+        // the body is {"code": status, "error_code": ErrorCode, "message": text}
         let error_responses = [
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
-            (StatusCode::BAD_REQUEST, "Bad Request"),
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error, error_code INTERNAL_ERROR",
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                "Bad Request, error_code BAD_REQUEST",
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                "Not Found, error_code ENTRY_NOT_FOUND or NOT_FOUND",
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                "Locked or Forbidden, error_code LOCKED or FORBIDDEN",
+            ),
+            (
+                StatusCode::CONFLICT,
+                "Conflict, error_code CONFLICT_EXISTS or SYNC_IN_PROGRESS",
+            ),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too Many Requests, error_code TOO_MANY_REQUESTS",
+            ),
+            (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Payload Too Large, error_code PAYLOAD_TOO_LARGE",
+            ),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Storage Unavailable, error_code S3_UNAVAILABLE",
+            ),
         ];
 
         for (code, msg) in &error_responses {
@@ -143,8 +311,15 @@ impl ResponseEntity for ServiceError {
 mod test {
     use anyhow::Error;
     use rweb::Reply;
+    use time::{macros::date, OffsetDateTime};
+
+    use diary_app_lib::{
+        retry::{Backend, BackendError},
+        services::EntryError,
+        sync_lock::SyncLockError,
+    };
 
-    use crate::errors::{error_response, ServiceError};
+    use crate::errors::{error_response, ErrorCode, ServiceError};
 
     #[tokio::test]
     async fn test_service_error() -> Result<(), Error> {
@@ -152,9 +327,77 @@ mod test {
         let resp = error_response(err).await?.into_response();
         assert_eq!(resp.status().as_u16(), 400);
 
+        let err = ServiceError::Locked.into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let err = ServiceError::Forbidden.into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let err = ServiceError::Conflict("TEST CONFLICT".into()).into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 409);
+
+        let err = ServiceError::TooManyRequests.into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 429);
+
+        let err = ServiceError::PayloadTooLarge("TEST TOO LONG".into()).into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 413);
+
+        let err = ServiceError::LengthRequired.into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 411);
+
         let err = ServiceError::InternalServerError.into();
         let resp = error_response(err).await?.into_response();
         assert_eq!(resp.status().as_u16(), 500);
+
+        let err = ServiceError::EntryNotFound("TEST DATE".into()).into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_codes() -> Result<(), Error> {
+        let parts = |err: ServiceError| {
+            let (status, code, _) = err.response_parts();
+            (status.as_u16(), code)
+        };
+        let held = SyncLockError::Held {
+            holder: "cli".into(),
+            acquired_at: OffsetDateTime::now_utc().into(),
+        };
+        assert_eq!(
+            parts(ServiceError::AnyhowError(held.into())),
+            (409, ErrorCode::SyncInProgress)
+        );
+        let s3 = BackendError::new(Backend::S3, true, anyhow::format_err!("timeout"));
+        let err = anyhow::Error::from(s3).context("sync failed");
+        assert_eq!(
+            parts(ServiceError::AnyhowError(err)),
+            (503, ErrorCode::S3Unavailable)
+        );
+        let missing = EntryError::NotFound(date!(2011 - 06 - 01));
+        assert_eq!(
+            parts(ServiceError::AnyhowError(missing.into())),
+            (404, ErrorCode::EntryNotFound)
+        );
+        assert_eq!(
+            parts(ServiceError::AnyhowError(anyhow::format_err!("bug"))),
+            (500, ErrorCode::InternalError)
+        );
+        assert_eq!(
+            parts(ServiceError::Conflict("changed".into())),
+            (409, ErrorCode::ConflictExists)
+        );
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::EntryNotFound)?,
+            r#""ENTRY_NOT_FOUND""#
+        );
         Ok(())
     }
 }
@@ -8,24 +8,34 @@
 #![allow(clippy::implicit_hasher)]
 #![allow(clippy::ignored_unit_patterns)]
 
+pub mod api_version;
 pub mod app;
+pub mod assets;
+pub mod caching;
+pub mod compression;
 pub mod elements;
 pub mod errors;
 pub mod logged_user;
 pub mod requests;
 pub mod routes;
+pub mod security;
+pub mod tenancy;
 
 use rweb::Schema;
 use serde::{Deserialize, Serialize};
+use stack_string::StackString;
 
-use rweb_helper::{derive_rweb_schema, DateTimeType, DateType};
+use rweb_helper::{derive_rweb_schema, DateTimeType, DateType, UuidWrapper};
 
 use diary_app_lib::date_time_wrapper::DateTimeWrapper;
 
 #[derive(Serialize, Deserialize)]
 pub struct ConflictData {
     pub date: Option<DateType>,
+    pub id: Option<UuidWrapper>,
     pub datetime: Option<DateTimeWrapper>,
+    pub journal: Option<StackString>,
+    pub view: Option<StackString>,
 }
 
 derive_rweb_schema!(ConflictData, _ConflictData);
@@ -36,13 +46,21 @@ derive_rweb_schema!(ConflictData, _ConflictData);
 struct _ConflictData {
     #[schema(description = "Conflict Date")]
     pub date: Option<DateType>,
-    #[schema(description = "Conflict DateTime")]
+    #[schema(description = "Conflict Group ID, takes precedence over datetime")]
+    pub id: Option<UuidWrapper>,
+    #[schema(description = "Conflict DateTime, matched to the millisecond")]
     pub datetime: Option<DateTimeType>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Conflict View, split shows the versions side by side")]
+    pub view: Option<StackString>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct CommitConflictData {
-    pub datetime: DateTimeWrapper,
+    pub id: Option<UuidWrapper>,
+    pub datetime: Option<DateTimeWrapper>,
+    pub journal: Option<StackString>,
 }
 
 derive_rweb_schema!(CommitConflictData, _CommitConflictData);
@@ -50,7 +68,11 @@ derive_rweb_schema!(CommitConflictData, _CommitConflictData);
 #[allow(dead_code)]
 #[derive(Schema)]
 struct _CommitConflictData {
-    pub datetime: DateTimeType,
+    #[schema(description = "Conflict Group ID, takes precedence over datetime")]
+    pub id: Option<UuidWrapper>,
+    #[schema(description = "Conflict DateTime, matched to the millisecond")]
+    pub datetime: Option<DateTimeType>,
+    pub journal: Option<StackString>,
 }
 
 #[cfg(test)]
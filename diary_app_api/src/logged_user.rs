@@ -5,16 +5,20 @@ pub use authorized_users::{
 use futures::TryStreamExt;
 use log::debug;
 use maplit::hashmap;
-use rweb::{filters::cookie::cookie, Filter, Rejection, Schema};
+use rweb::{
+    filters::{cookie::cookie, header},
+    Filter, Rejection, Schema,
+};
 use rweb_helper::{DateTimeType, UuidWrapper};
 use serde::{Deserialize, Serialize};
-use stack_string::StackString;
+use stack_string::{format_sstr, StackString};
 use std::{
     collections::HashMap,
     convert::{TryFrom, TryInto},
     env::var,
     str::FromStr,
 };
+use subtle::ConstantTimeEq;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
@@ -56,6 +60,35 @@ impl LoggedUser {
     }
 }
 
+/// Another diary_app instance, authenticated by sending
+/// `Authorization: Bearer <peer_token>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerUser {
+    authorization: Option<StackString>,
+}
+
+impl PeerUser {
+    /// # Errors
+    /// Returns error unless `peer_token` is set and matches the bearer token
+    pub fn verify(&self, peer_token: Option<&str>) -> Result<(), Error> {
+        let expected = peer_token.map(|t| format_sstr!("Bearer {t}"));
+        let matches = match (expected, &self.authorization) {
+            (Some(expected), Some(auth)) => expected.as_bytes().ct_eq(auth.as_bytes()).into(),
+            _ => false,
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized)
+        }
+    }
+
+    #[must_use]
+    pub fn filter() -> impl Filter<Extract = (Self,), Error = Rejection> + Copy {
+        header::optional::<StackString>("authorization").map(|authorization| Self { authorization })
+    }
+}
+
 impl From<ExternalUser> for LoggedUser {
     fn from(user: ExternalUser) -> Self {
         Self {
@@ -89,9 +122,11 @@ impl FromStr for LoggedUser {
     }
 }
 
+/// Users of the default diary and of every tenant may log in, which tenant
+/// they can use is checked per request
 /// # Errors
 /// Returns error if `get_authorized_users` fails
-pub async fn fill_from_db(pool: &PgPool) -> Result<(), Error> {
+pub async fn fill_from_db(pools: &[PgPool]) -> Result<(), Error> {
     if let Ok("true") = var("TESTENV").as_ref().map(String::as_str) {
         AUTHORIZED_USERS.update_users(hashmap! {
             "user@test".into() => ExternalUser {
@@ -103,8 +138,11 @@ pub async fn fill_from_db(pool: &PgPool) -> Result<(), Error> {
         });
         return Ok(());
     }
-    let (created_at, deleted_at) = AuthorizedUsers::get_most_recent(pool).await?;
-    let most_recent_user_db = created_at.max(deleted_at);
+    let mut most_recent_user_db = None;
+    for pool in pools {
+        let (created_at, deleted_at) = AuthorizedUsers::get_most_recent(pool).await?;
+        most_recent_user_db = most_recent_user_db.max(created_at.max(deleted_at));
+    }
     let existing_users = AUTHORIZED_USERS.get_users();
     let most_recent_user = existing_users.values().map(|i| i.created_at).max();
     debug!("most_recent_user_db {most_recent_user_db:?} most_recent_user {most_recent_user:?}");
@@ -115,23 +153,47 @@ pub async fn fill_from_db(pool: &PgPool) -> Result<(), Error> {
         return Ok(());
     }
 
-    let result: Result<HashMap<StackString, _>, _> = AuthorizedUsers::get_authorized_users(pool)
-        .await?
-        .map_ok(|u| {
-            (
-                u.email.clone(),
-                ExternalUser {
-                    email: u.email,
-                    session: Uuid::new_v4(),
-                    secret_key: StackString::default(),
-                    created_at: u.created_at,
-                },
-            )
-        })
-        .try_collect()
-        .await;
-    let users = result?;
+    let mut users = HashMap::new();
+    for pool in pools {
+        let result: Result<HashMap<StackString, _>, _> =
+            AuthorizedUsers::get_authorized_users(pool)
+                .await?
+                .map_ok(|u| {
+                    (
+                        u.email.clone(),
+                        ExternalUser {
+                            email: u.email,
+                            session: Uuid::new_v4(),
+                            secret_key: StackString::default(),
+                            created_at: u.created_at,
+                        },
+                    )
+                })
+                .try_collect()
+                .await;
+        users.extend(result?);
+    }
     AUTHORIZED_USERS.update_users(users);
     debug!("AUTHORIZED_USERS {:?}", *AUTHORIZED_USERS);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::logged_user::PeerUser;
+
+    #[test]
+    fn test_peer_user_verify() {
+        let peer = PeerUser {
+            authorization: Some("Bearer secret".into()),
+        };
+        assert!(peer.verify(Some("secret")).is_ok());
+        assert!(peer.verify(Some("secreT")).is_err());
+        assert!(peer.verify(Some("secret2")).is_err());
+        assert!(peer.verify(None).is_err());
+        let peer = PeerUser {
+            authorization: None,
+        };
+        assert!(peer.verify(Some("secret")).is_err());
+    }
+}
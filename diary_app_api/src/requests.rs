@@ -1,30 +1,56 @@
 use anyhow::{format_err, Error};
-use futures::TryStreamExt;
-use itertools::Itertools;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
 use rweb::Schema;
-use rweb_helper::DateType;
+use rweb_helper::{DateTimeType, DateType, UuidWrapper};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use stack_string::{format_sstr, StackString};
-use std::collections::BTreeSet;
-use time::Date;
+use std::convert::TryFrom;
+use time::{Date, OffsetDateTime};
 use uuid::Uuid;
 
 use diary_app_lib::{
+    date_sync::{DateSyncReport, EntryCopy, SyncDirection},
     date_time_wrapper::DateTimeWrapper,
-    models::{DiaryConflict, DiaryEntries},
+    entry_availability::EntryAvailability,
+    entry_patch::EntryPatch,
+    i18n::Locale,
+    maintenance::MaintenanceJob,
+    mobile_sync::{ClientEntryState, ServerEntryState},
+    models::{
+        AuthorizedUsers, CacheItem, ConflictKey, DateGroup, DatePage, DateRange, DiaryAudit,
+        DiaryCache, DiaryComment, DiaryConflict, DiaryEntries, DiarySubentry, DiaryTerm,
+        MetadataStats, StatsPeriod, SyncLease, UserLinkCode, UserSettings,
+    },
+    sections::Section,
+    services::{
+        ConflictList, ConflictSummary, ConflictUpdate, DashboardSummary, DiaryService, EntryUpdate,
+        ListQuery, MetadataStatsQuery, MetadataUpdate, MobileSyncUpdate, NewJournal, PatchUpdate,
+        RedactQuery, SearchQuery, SectionQuery, SectionUpdate, SettingsUpdate,
+    },
+    storage_report::StorageReport,
+    writing_habits::WritingHabits,
 };
 
 use super::app::DiaryAppActor;
 
+/// Number of recent entries shown on the dashboard
+pub const DASHBOARD_ENTRIES: usize = 5;
+
 #[derive(Serialize, Deserialize, Schema)]
 pub struct SearchOptions {
     #[schema(description = "Search Text")]
     pub text: Option<StackString>,
     #[schema(description = "Search Date")]
     pub date: Option<DateType>,
+    #[schema(description = "Only Starred Entries")]
+    pub starred: Option<bool>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
 }
 
-#[derive(Serialize, Deserialize, Default, Copy, Clone, Schema)]
+#[derive(Serialize, Deserialize, Default, Clone, Schema)]
 pub struct ListOptions {
     #[schema(description = "Minimum Date")]
     pub min_date: Option<DateType>,
@@ -34,28 +60,550 @@ pub struct ListOptions {
     pub start: Option<usize>,
     #[schema(description = "Limit")]
     pub limit: Option<usize>,
+    #[schema(description = "Only Dates past the Cursor of the Previous Page")]
+    pub cursor: Option<DateType>,
+    #[schema(description = "Sort Order, asc or desc (default desc)")]
+    pub order: Option<StackString>,
+    #[schema(description = "Only Starred Entries")]
+    pub starred: Option<bool>,
+    #[schema(description = "Only Entries with at least this many Words")]
+    pub min_words: Option<usize>,
+    #[schema(description = "Only Entries with at most this many Words")]
+    pub max_words: Option<usize>,
+    #[schema(description = "Only Entries with (true) or without (false) Conflicts")]
+    pub has_conflict: Option<bool>,
+    #[schema(description = "Group Dates by week, month or year")]
+    pub group: Option<StackString>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Compact Layout for Narrow Screens")]
+    pub compact: Option<bool>,
+}
+
+/// Entry encrypted in the browser, the server only ever stores the
+/// ciphertext
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "EncryptedEntry")]
+pub struct EncryptedEntry {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Base64 Encoded Ciphertext")]
+    pub ciphertext: StackString,
+    #[schema(description = "Base64 Encoded Nonce")]
+    pub nonce: StackString,
+    #[schema(description = "Journal")]
+    #[serde(default)]
+    pub journal: Option<StackString>,
+}
+
+impl TryFrom<&DiaryEntries> for EncryptedEntry {
+    type Error = Error;
+    fn try_from(entry: &DiaryEntries) -> Result<Self, Self::Error> {
+        let date = entry.diary_date;
+        match (&entry.diary_ciphertext, &entry.diary_nonce) {
+            (Some(ciphertext), Some(nonce)) if entry.is_encrypted => Ok(Self {
+                date: date.into(),
+                ciphertext: STANDARD.encode(ciphertext).into(),
+                nonce: STANDARD.encode(nonce).into(),
+                journal: Some(entry.journal.clone()),
+            }),
+            _ => Err(format_err!("Entry {date} is not encrypted")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+pub struct RecentEntry {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "First Line of Entry")]
+    pub first_line: StackString,
+}
+
+impl From<&DiaryEntries> for RecentEntry {
+    fn from(entry: &DiaryEntries) -> Self {
+        let first_line = if entry.is_encrypted {
+            "(encrypted)".into()
+        } else {
+            entry
+                .diary_text
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or("")
+                .into()
+        };
+        Self {
+            date: entry.diary_date.into(),
+            first_line,
+        }
+    }
+}
+
+/// Frontpage summary of the current journal
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "Dashboard")]
+pub struct Dashboard {
+    #[schema(description = "Most Recent Entries")]
+    pub recent: Vec<RecentEntry>,
+    #[schema(description = "Consecutive Days with an Entry")]
+    pub streak: usize,
+    #[schema(description = "Number of Dates with Outstanding Conflicts")]
+    pub conflicts: usize,
+    #[schema(description = "Last Completed Sync")]
+    pub last_sync: Option<DateTimeType>,
+}
+
+impl From<DashboardSummary> for Dashboard {
+    fn from(summary: DashboardSummary) -> Self {
+        Self {
+            recent: summary.recent.iter().map(Into::into).collect(),
+            streak: summary.streak,
+            conflicts: summary.conflict_dates,
+            last_sync: summary.last_sync.map(|d| d.to_offsetdatetime().into()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "Settings")]
+pub struct Settings {
+    #[schema(description = "Email Address")]
+    pub email: StackString,
+    #[schema(description = "Send a Past Entry Every Morning")]
+    pub resurface: bool,
+    #[schema(description = "Last Date an Entry was Resurfaced")]
+    pub last_resurfaced: Option<DateType>,
+    #[schema(description = "Language of the Web UI and Bot Replies")]
+    pub locale: Option<StackString>,
+}
+
+impl From<UserSettings> for Settings {
+    fn from(settings: UserSettings) -> Self {
+        Self {
+            email: settings.email,
+            resurface: settings.resurface,
+            last_resurfaced: settings.last_resurfaced.map(Into::into),
+            locale: settings.locale,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "Comment")]
+pub struct Comment {
+    #[schema(description = "Comment Id")]
+    pub id: UuidWrapper,
+    #[schema(description = "Date of the Entry")]
+    pub date: DateType,
+    #[schema(description = "Email of the Author")]
+    pub author: StackString,
+    #[schema(description = "Comment Text")]
+    pub text: StackString,
+    #[schema(description = "Created At")]
+    pub created_at: DateTimeType,
+    #[schema(description = "Last Edited At")]
+    pub updated_at: DateTimeType,
+}
+
+impl From<DiaryComment> for Comment {
+    fn from(comment: DiaryComment) -> Self {
+        Self {
+            id: comment.id.into(),
+            date: comment.diary_date.into(),
+            author: comment.author,
+            text: comment.comment_text,
+            created_at: comment.created_at.to_offsetdatetime().into(),
+            updated_at: comment.updated_at.to_offsetdatetime().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "ConflictRef")]
+pub struct ConflictRef {
+    #[schema(description = "Conflict Group ID, the id taken by show_conflict")]
+    pub id: UuidWrapper,
+    #[schema(description = "Time of the Write which Recorded the Conflict")]
+    pub datetime: DateTimeType,
+    #[schema(description = "Unresolved Conflicts of the Date")]
+    pub count: usize,
+}
+
+impl From<ConflictSummary> for ConflictRef {
+    fn from(summary: ConflictSummary) -> Self {
+        Self {
+            id: summary.group.into(),
+            datetime: OffsetDateTime::from(summary.datetime).into(),
+            count: summary.count,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "Activity")]
+pub struct Activity {
+    #[schema(description = "Time of the Write")]
+    pub created_at: DateTimeType,
+    #[schema(description = "Email of the User, unset for the bot, cli and background jobs")]
+    pub user: Option<StackString>,
+    #[schema(description = "Kind of Write")]
+    pub action: StackString,
+    #[schema(description = "Date of the Entry")]
+    pub date: Option<DateType>,
+    #[schema(description = "Details")]
+    pub detail: StackString,
+    #[schema(description = "Pathway of the Write (api, cli, telegram, ssh, s3...)")]
+    pub source: Option<StackString>,
+}
+
+impl From<DiaryAudit> for Activity {
+    fn from(audit: DiaryAudit) -> Self {
+        Self {
+            created_at: audit.created_at.to_offsetdatetime().into(),
+            user: audit.email,
+            action: audit.action,
+            date: audit.diary_date.map(Into::into),
+            detail: audit.detail,
+            source: audit.source,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "UserAccount")]
+pub struct UserAccount {
+    #[schema(description = "Email Address")]
+    pub email: StackString,
+    #[schema(description = "Owner or Viewer")]
+    pub role: StackString,
+    #[schema(description = "Telegram User Id")]
+    pub telegram_userid: Option<i64>,
+    #[schema(description = "Matrix User Id")]
+    pub matrix_userid: Option<StackString>,
+    #[schema(description = "Created At")]
+    pub created_at: DateTimeType,
+    #[schema(description = "Disabled At")]
+    pub disabled_at: Option<DateTimeType>,
+}
+
+impl From<AuthorizedUsers> for UserAccount {
+    fn from(user: AuthorizedUsers) -> Self {
+        Self {
+            email: user.email,
+            role: user.role,
+            telegram_userid: user.telegram_userid,
+            matrix_userid: user.matrix_userid,
+            created_at: user.created_at.into(),
+            disabled_at: user.deleted_at.map(Into::into),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "LinkCode")]
+pub struct LinkCode {
+    #[schema(description = "One-time Code")]
+    pub code: StackString,
+    #[schema(description = "Expires At")]
+    pub expires_at: DateTimeType,
+    #[schema(description = "Message to send to the Bot")]
+    pub command: StackString,
+}
+
+impl From<UserLinkCode> for LinkCode {
+    fn from(link_code: UserLinkCode) -> Self {
+        Self {
+            command: format_sstr!("/link {}", link_code.code),
+            code: link_code.code,
+            expires_at: link_code.expires_at.to_offsetdatetime().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "MaintenanceJob")]
+pub struct MaintenanceJobInfo {
+    #[schema(description = "Job Id")]
+    pub id: UuidWrapper,
+    #[schema(description = "Task")]
+    pub task: StackString,
+    #[schema(description = "Journal")]
+    pub journal: StackString,
+    #[schema(description = "Running, Finished or Failed")]
+    pub status: StackString,
+    #[schema(description = "Current Stage")]
+    pub stage: StackString,
+    #[schema(description = "Items Processed")]
+    pub processed: usize,
+    #[schema(description = "Total Items")]
+    pub total: usize,
+    #[schema(description = "Summary of a Finished Job")]
+    pub output: Option<StackString>,
+    #[schema(description = "Error of a Failed Job")]
+    pub error: Option<StackString>,
+    #[schema(description = "Started At")]
+    pub started_at: DateTimeType,
+    #[schema(description = "Finished At")]
+    pub finished_at: Option<DateTimeType>,
+}
+
+impl From<MaintenanceJob> for MaintenanceJobInfo {
+    fn from(job: MaintenanceJob) -> Self {
+        let latest = job.progress.latest().cloned().unwrap_or_default();
+        Self {
+            id: job.id.into(),
+            task: job.task.as_str().into(),
+            journal: job.journal,
+            status: job.status.as_str().into(),
+            stage: latest.stage,
+            processed: latest.processed,
+            total: latest.total,
+            output: job.output,
+            error: job.error,
+            started_at: job.started_at.to_offsetdatetime().into(),
+            finished_at: job.finished_at.map(|t| t.to_offsetdatetime().into()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Schema)]
+#[schema(component = "SyncLock")]
+pub struct SyncLockInfo {
+    #[schema(description = "Process Running the Sync")]
+    pub holder: StackString,
+    #[schema(description = "Acquired At")]
+    pub acquired_at: DateTimeType,
+    #[schema(description = "Released Unless Renewed By")]
+    pub expires_at: DateTimeType,
+}
+
+impl From<SyncLease> for SyncLockInfo {
+    fn from(lease: SyncLease) -> Self {
+        Self {
+            holder: lease.holder,
+            acquired_at: lease.acquired_at.to_offsetdatetime().into(),
+            expires_at: lease.expires_at.to_offsetdatetime().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct ActivityOptions {
+    #[schema(description = "Only Writes by this User")]
+    pub user: Option<StackString>,
+    #[schema(description = "Minimum Date")]
+    pub min_date: Option<DateType>,
+    #[schema(description = "Maximum Date")]
+    pub max_date: Option<DateType>,
+    #[schema(description = "Maximum Number of Writes (default 100)")]
+    pub limit: Option<usize>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+impl TryFrom<EncryptedEntry> for DiaryEntries {
+    type Error = Error;
+    fn try_from(entry: EncryptedEntry) -> Result<Self, Self::Error> {
+        let ciphertext = STANDARD.decode(&entry.ciphertext)?;
+        let nonce = STANDARD.decode(&entry.nonce)?;
+        Ok(Self::new_encrypted(entry.date.into(), ciphertext, nonce))
+    }
 }
 
 pub enum DiaryAppRequests {
     Search(SearchOptions),
-    Insert(StackString),
+    Insert(CacheItem),
+    InsertBatch(Vec<CacheItem>),
     Sync,
-    Replace { date: Date, text: StackString },
+    Replace {
+        date: Date,
+        text: StackString,
+    },
+    Append {
+        date: Date,
+        text: StackString,
+    },
+    Patch {
+        date: Date,
+        patch: EntryPatch,
+        base_hash: Option<StackString>,
+    },
+    Section {
+        date: Date,
+        slug: StackString,
+    },
+    ReplaceSection {
+        date: Date,
+        slug: StackString,
+        text: StackString,
+    },
+    Redact {
+        regex: Regex,
+        placeholder: Option<StackString>,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+        permanent: bool,
+    },
     List(ListOptions),
+    ListGroups(StatsPeriod),
     Display(Date),
+    Print {
+        min_date: Date,
+        max_date: Date,
+    },
     ListConflicts(Option<DateType>),
-    ShowConflict(DateTimeWrapper),
-    RemoveConflict(DateTimeWrapper),
+    ShowConflict(ConflictKey),
+    RemoveConflict(ConflictKey),
     CleanConflicts(Date),
-    UpdateConflict { id: Uuid, diff_text: StackString },
-    CommitConflict(DateTimeWrapper),
+    UpdateConflict {
+        id: Uuid,
+        diff_text: StackString,
+    },
+    CommitConflict(ConflictKey),
+    MobileSync {
+        client_id: StackString,
+        entries: Vec<ClientEntryState>,
+    },
+    ReplaceEncrypted(EncryptedEntry),
+    ListEncrypted(ListOptions),
+    Delete(Date),
+    ListTrash,
+    RestoreTrash(Date),
+    PurgeTrash(Date),
+    ToggleStar(Date),
+    TogglePrivate(Date),
+    ListJournals,
+    CreateJournal {
+        journal_name: StackString,
+        shared: bool,
+    },
+    GetMetadata(Date),
+    UpdateMetadata {
+        date: Date,
+        key: StackString,
+        value: Option<StackString>,
+    },
+    MetadataStats {
+        key: StackString,
+        period: StatsPeriod,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+    },
+    Schedule {
+        date: Date,
+        text: StackString,
+    },
+    Dashboard,
+    GetSettings(StackString),
+    UpdateSettings {
+        email: StackString,
+        resurface: bool,
+        locale: Option<Locale>,
+    },
+    ListComments(Date),
+    Subentries(Date),
+    EntrySource(Date),
+    AddComment {
+        date: Date,
+        author: StackString,
+        text: StackString,
+    },
+    UpdateComment {
+        id: Uuid,
+        author: StackString,
+        text: StackString,
+    },
+    DeleteComment {
+        id: Uuid,
+        author: StackString,
+    },
+    Activity(ActivityOptions),
+    ListUsers,
+    AddUser {
+        email: StackString,
+        role: StackString,
+        telegram_userid: Option<i64>,
+    },
+    DisableUser(StackString),
+    SetTelegramUser {
+        email: StackString,
+        telegram_userid: Option<i64>,
+    },
+    LinkCode(StackString),
+    SyncDate {
+        date: Date,
+        direction: SyncDirection,
+    },
+    Diff {
+        date: Date,
+        from: EntryCopy,
+        to: EntryCopy,
+    },
+    StorageReport,
+    TopTerms {
+        range: DateRange,
+        limit: i64,
+    },
+    Habits(DateRange),
+    Availability(DateRange),
 }
 
 pub enum DiaryAppOutput {
     Lines(Vec<StackString>),
+    /// Date and text of the entry, and the conflict the write recorded
+    Replaced(StackString, Option<ConflictRef>),
     Timestamps(Vec<DateTimeWrapper>),
     Dates(Vec<Date>),
+    DatePage(DatePage),
+    Groups(Vec<DateGroup>),
     Conflicts(Vec<DiaryConflict>),
+    MobileSync(Vec<ServerEntryState>),
+    Encrypted(Vec<EncryptedEntry>),
+    Entries(Vec<DiaryEntries>),
+    Starred(bool),
+    Private(bool),
+    Metadata(Value),
+    Stats(Vec<MetadataStats>),
+    Dashboard(Dashboard),
+    CacheBatch(Vec<Result<DiaryCache, StackString>>),
+    Settings(Settings),
+    Comments(Vec<Comment>),
+    Activity(Vec<Activity>),
+    Users(Vec<UserAccount>),
+    LinkCode(LinkCode),
+    SyncDate(DateSyncReport),
+    Diff(StackString),
+    StorageReport(StorageReport),
+    Terms(Vec<DiaryTerm>),
+    Habits(WritingHabits),
+    Availability(EntryAvailability),
+    Subentries(Vec<DiarySubentry>),
+    Source(Option<StackString>),
+    Section(Option<Section>),
+}
+
+impl TryFrom<ListOptions> for ListQuery {
+    type Error = Error;
+    fn try_from(opts: ListOptions) -> Result<Self, Self::Error> {
+        Ok(Self {
+            min_date: opts.min_date.map(Into::into),
+            max_date: opts.max_date.map(Into::into),
+            start: opts.start,
+            limit: opts.limit,
+            starred: opts.starred.unwrap_or(false),
+            order: opts
+                .order
+                .as_deref()
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            cursor: opts.cursor.map(Into::into),
+            min_words: opts.min_words,
+            max_words: opts.max_words,
+            has_conflict: opts.has_conflict,
+        })
+    }
 }
 
 impl From<Vec<StackString>> for DiaryAppOutput {
@@ -82,142 +630,366 @@ impl From<Vec<DiaryConflict>> for DiaryAppOutput {
     }
 }
 
+impl From<Vec<ServerEntryState>> for DiaryAppOutput {
+    fn from(value: Vec<ServerEntryState>) -> Self {
+        Self::MobileSync(value)
+    }
+}
+
+impl From<Vec<EncryptedEntry>> for DiaryAppOutput {
+    fn from(value: Vec<EncryptedEntry>) -> Self {
+        Self::Encrypted(value)
+    }
+}
+
+impl From<Vec<DiaryEntries>> for DiaryAppOutput {
+    fn from(value: Vec<DiaryEntries>) -> Self {
+        Self::Entries(value)
+    }
+}
+
+/// Date and text of an entry after a write, shown in the editor
+fn entry_lines(entry: &DiaryEntries) -> DiaryAppOutput {
+    vec![entry_body(entry)].into()
+}
+
+fn entry_body(entry: &DiaryEntries) -> StackString {
+    format_sstr!("{}\n{}", entry.diary_date, entry.diary_text)
+}
+
 impl DiaryAppRequests {
+    /// Run the request through the typed [`DiaryService`], wrapping the
+    /// result for the routes
     /// # Errors
     /// Return error if any operation fails
     pub async fn process(self, dapp: &DiaryAppActor) -> Result<DiaryAppOutput, Error> {
+        let service = DiaryService::new(dapp);
         match self {
             DiaryAppRequests::Search(opts) => {
-                let body = if let Some(text) = opts.text {
-                    let results: Vec<_> = dapp.search_text(&text).await?;
-                    results
-                } else if let Some(date) = opts.date.map(Into::into) {
-                    let entry = DiaryEntries::get_by_date(date, &dapp.pool)
-                        .await?
-                        .ok_or_else(|| format_err!("Date should exist {}", date))?;
-                    vec![entry.diary_text]
-                } else {
-                    vec!["".into()]
+                let query = SearchQuery {
+                    text: opts.text,
+                    date: opts.date.map(Into::into),
+                    starred: opts.starred == Some(true),
                 };
-                Ok(body.into())
+                Ok(service.search(query).await?.entries.into())
             }
-            DiaryAppRequests::Insert(text) => {
-                let cache = dapp.cache_text(&text).await?;
+            DiaryAppRequests::Insert(item) => {
+                let cache = service.insert(item).await?;
                 Ok(vec![cache.diary_datetime].into())
             }
-            DiaryAppRequests::Sync => {
-                let output = dapp.sync_everything().await?;
-                Ok(output.into())
+            DiaryAppRequests::InsertBatch(items) => {
+                let results = service.insert_batch(items).await?;
+                Ok(DiaryAppOutput::CacheBatch(results))
             }
+            DiaryAppRequests::Sync => Ok(service.sync().await?.into()),
             DiaryAppRequests::Replace { date, text } => {
-                let (entry, _) = dapp.replace_text(date, &text).await?;
-                let body: StackString = format_sstr!("{}\n{}", entry.diary_date, entry.diary_text);
-                Ok(vec![body].into())
+                let (entry, conflict) = service.replace(EntryUpdate { date, text }).await?;
+                Ok(DiaryAppOutput::Replaced(
+                    entry_body(&entry),
+                    conflict.map(Into::into),
+                ))
             }
-            DiaryAppRequests::List(opts) => {
-                let dates = dapp
-                    .get_list_of_dates(
-                        opts.min_date.map(Into::into),
-                        opts.max_date.map(Into::into),
-                        opts.start,
-                        opts.limit,
-                    )
-                    .await?;
+            DiaryAppRequests::Append { date, text } => {
+                let entry = service.append(EntryUpdate { date, text }).await?;
+                Ok(entry_lines(&entry))
+            }
+            DiaryAppRequests::Patch {
+                date,
+                patch,
+                base_hash,
+            } => {
+                let update = PatchUpdate {
+                    date,
+                    patch,
+                    base_hash,
+                };
+                let entry = service.patch(update).await?;
+                Ok(entry_lines(&entry))
+            }
+            DiaryAppRequests::Section { date, slug } => {
+                let section = service.section(SectionQuery { date, slug }).await?;
+                Ok(DiaryAppOutput::Section(section))
+            }
+            DiaryAppRequests::ReplaceSection { date, slug, text } => {
+                let update = SectionUpdate { date, slug, text };
+                let entry = service.replace_section(update).await?;
+                Ok(entry_lines(&entry))
+            }
+            DiaryAppRequests::Redact {
+                regex,
+                placeholder,
+                min_date,
+                max_date,
+                permanent,
+            } => {
+                let query = RedactQuery {
+                    regex,
+                    placeholder,
+                    min_date,
+                    max_date,
+                    permanent,
+                };
+                let dates = service.redact(query).await?;
                 Ok(dates.into())
             }
+            DiaryAppRequests::List(opts) => Ok(DiaryAppOutput::DatePage(
+                service.list(opts.try_into()?).await?,
+            )),
+            DiaryAppRequests::ListGroups(period) => {
+                Ok(DiaryAppOutput::Groups(service.list_groups(period).await?))
+            }
             DiaryAppRequests::Display(date) => {
-                let entry = DiaryEntries::get_by_date(date, &dapp.pool)
-                    .await?
-                    .ok_or_else(|| format_err!("Date should exist {}", date))?;
+                let entry = service.entry(date).await?;
+                if entry.is_encrypted {
+                    let entry = EncryptedEntry::try_from(&entry)?;
+                    return Ok(vec![entry].into());
+                }
                 Ok(vec![entry.diary_text].into())
             }
-            DiaryAppRequests::ListConflicts(None) => {
-                let mut conflicts: Vec<_> = DiaryConflict::get_all_dates(&dapp.pool)
-                    .await?
-                    .try_collect()
-                    .await?;
-                conflicts.sort();
-                conflicts.dedup();
-                Ok(conflicts.into())
-            }
-            DiaryAppRequests::ListConflicts(Some(date)) => {
-                let mut conflicts: Vec<_> = DiaryConflict::get_by_date(date.into(), &dapp.pool)
-                    .await?
-                    .try_collect()
-                    .await?;
-                conflicts.sort();
-                conflicts.dedup();
-                Ok(conflicts.into())
+            DiaryAppRequests::Print { min_date, max_date } => {
+                let range = DateRange {
+                    since: Some(min_date),
+                    until: Some(max_date),
+                };
+                let entries = service.print(range).await?;
+                Ok(DiaryAppOutput::Entries(entries))
             }
-            DiaryAppRequests::ShowConflict(datetime) => {
-                let conflicts: Vec<_> = DiaryConflict::get_by_datetime(datetime, &dapp.pool)
-                    .await?
-                    .try_collect()
-                    .await?;
-                Ok(conflicts.into())
+            DiaryAppRequests::ListConflicts(date) => {
+                match service.list_conflicts(date.map(Into::into)).await? {
+                    ConflictList::Dates(dates) => Ok(dates.into()),
+                    ConflictList::Syncs(syncs) => Ok(syncs.into()),
+                }
             }
-            DiaryAppRequests::RemoveConflict(datetime) => {
-                DiaryConflict::remove_by_datetime(datetime, &dapp.pool).await?;
-                let body: StackString = format_sstr!("remove {datetime}");
+            DiaryAppRequests::ShowConflict(key) => Ok(service.show_conflict(key).await?.into()),
+            DiaryAppRequests::RemoveConflict(key) => {
+                service.remove_conflict(key).await?;
+                let body: StackString = format_sstr!("remove {key}");
                 Ok(vec![body].into())
             }
             DiaryAppRequests::CleanConflicts(date) => {
-                let results: Result<Vec<StackString>, Error> =
-                    DiaryConflict::get_by_date(date, &dapp.pool)
-                        .await?
-                        .map_err(Into::into)
-                        .and_then(|datetime| {
-                            let pool = dapp.pool.clone();
-                            async move {
-                                DiaryConflict::remove_by_datetime(datetime, &pool).await?;
-                                Ok(format_sstr!("remove {datetime}"))
-                            }
-                        })
-                        .try_collect()
-                        .await;
-                results.map(Into::into)
+                let lines: Vec<StackString> = service
+                    .clean_conflicts(date)
+                    .await?
+                    .into_iter()
+                    .map(|datetime| format_sstr!("remove {datetime}"))
+                    .collect();
+                Ok(lines.into())
             }
             DiaryAppRequests::UpdateConflict { id, diff_text } => {
-                let new_diff_type = match diff_text.as_str() {
-                    "rem" => "rem",
-                    "add" => "add",
-                    _ => return Err(format_err!("Bad diff type {}", diff_text)),
+                let update = ConflictUpdate {
+                    id,
+                    diff_type: diff_text,
                 };
-                DiaryConflict::update_by_id(id, new_diff_type, &dapp.pool).await?;
+                service.update_conflict(update).await?;
                 let body: StackString = "updated".into();
                 Ok(vec![body].into())
             }
-            DiaryAppRequests::CommitConflict(datetime) => {
-                let conflicts: Vec<_> = DiaryConflict::get_by_datetime(datetime, &dapp.pool)
-                    .await?
-                    .try_collect()
+            DiaryAppRequests::CommitConflict(key) => {
+                let (entry, conflict) = service.commit_conflict(key).await?;
+                Ok(DiaryAppOutput::Replaced(
+                    entry_body(&entry),
+                    conflict.map(Into::into),
+                ))
+            }
+            DiaryAppRequests::MobileSync { client_id, entries } => {
+                let update = MobileSyncUpdate { client_id, entries };
+                let output = service.mobile_sync(update).await?;
+                Ok(output.into())
+            }
+            DiaryAppRequests::ReplaceEncrypted(entry) => {
+                let entry = service
+                    .replace_encrypted(DiaryEntries::try_from(entry)?)
                     .await?;
-                let diary_dates: BTreeSet<Date> =
-                    conflicts.iter().map(|entry| entry.diary_date).collect();
-                if diary_dates.len() > 1 {
-                    return Err(format_err!(
-                        "Something has gone horribly wrong {:?}",
-                        conflicts
-                    ));
-                }
-                let date = diary_dates.into_iter().next().ok_or_else(|| {
-                    format_err!("Something has gone horribly wrong {:?}", conflicts)
-                })?;
-
-                let additions = conflicts
-                    .into_iter()
-                    .filter_map(|entry| {
-                        if &entry.diff_type == "add" || &entry.diff_type == "same" {
-                            Some(entry.diff_text)
-                        } else {
-                            None
-                        }
+                let body = format_sstr!("{}", entry.diary_date);
+                Ok(vec![body].into())
+            }
+            DiaryAppRequests::ListEncrypted(opts) => {
+                let entries: Result<Vec<_>, Error> = service
+                    .list_encrypted(opts.try_into()?)
+                    .await?
+                    .iter()
+                    .map(EncryptedEntry::try_from)
+                    .collect();
+                entries.map(Into::into)
+            }
+            DiaryAppRequests::Delete(date) => {
+                let output = service.delete(date).await?;
+                Ok(output.into())
+            }
+            DiaryAppRequests::ToggleStar(date) => {
+                Ok(DiaryAppOutput::Starred(service.toggle_star(date).await?))
+            }
+            DiaryAppRequests::TogglePrivate(date) => {
+                Ok(DiaryAppOutput::Private(service.toggle_private(date).await?))
+            }
+            DiaryAppRequests::ListTrash => Ok(service.list_trash().await?.into()),
+            DiaryAppRequests::RestoreTrash(date) => {
+                service.restore_trash(date).await?;
+                let body = format_sstr!("restored {date}");
+                Ok(vec![body].into())
+            }
+            DiaryAppRequests::PurgeTrash(date) => {
+                service.purge_trash(date).await?;
+                let body = format_sstr!("purged {date}");
+                Ok(vec![body].into())
+            }
+            DiaryAppRequests::ListJournals => {
+                let journals = service.list_journals().await?;
+                Ok(journals.into())
+            }
+            DiaryAppRequests::CreateJournal {
+                journal_name,
+                shared,
+            } => {
+                let journal = service
+                    .create_journal(NewJournal {
+                        journal_name,
+                        shared,
                     })
-                    .join("\n");
-                let (entry, _) = dapp.replace_text(date, &additions).await?;
-                let body = format_sstr!("{}\n{}", entry.diary_date, entry.diary_text);
+                    .await?;
+                Ok(vec![journal.journal_name].into())
+            }
+            DiaryAppRequests::GetMetadata(date) => {
+                let entry = service.entry(date).await?;
+                Ok(DiaryAppOutput::Metadata(entry.metadata))
+            }
+            DiaryAppRequests::UpdateMetadata { date, key, value } => {
+                let update = MetadataUpdate { date, key, value };
+                let metadata = service.update_metadata(update).await?;
+                Ok(DiaryAppOutput::Metadata(metadata))
+            }
+            DiaryAppRequests::MetadataStats {
+                key,
+                period,
+                min_date,
+                max_date,
+            } => {
+                let query = MetadataStatsQuery {
+                    key,
+                    period,
+                    min_date,
+                    max_date,
+                };
+                Ok(DiaryAppOutput::Stats(service.metadata_stats(query).await?))
+            }
+            DiaryAppRequests::Schedule { date, text } => {
+                let entry = service.schedule(EntryUpdate { date, text }).await?;
+                let body = format_sstr!("scheduled {}", entry.diary_date);
                 Ok(vec![body].into())
             }
+            DiaryAppRequests::Dashboard => {
+                let summary = service.dashboard(DASHBOARD_ENTRIES).await?;
+                Ok(DiaryAppOutput::Dashboard(summary.into()))
+            }
+            DiaryAppRequests::GetSettings(email) => {
+                let settings = service.settings(&email).await?;
+                Ok(DiaryAppOutput::Settings(settings.into()))
+            }
+            DiaryAppRequests::UpdateSettings {
+                email,
+                resurface,
+                locale,
+            } => {
+                let update = SettingsUpdate {
+                    email,
+                    resurface,
+                    locale,
+                };
+                let settings = service.update_settings(update).await?;
+                Ok(DiaryAppOutput::Settings(settings.into()))
+            }
+            DiaryAppRequests::ListComments(date) => {
+                let comments = dapp.get_comments(date).await?;
+                Ok(DiaryAppOutput::Comments(
+                    comments.into_iter().map(Into::into).collect(),
+                ))
+            }
+            DiaryAppRequests::Subentries(date) => {
+                let subentries = dapp.get_subentries(date).await?;
+                Ok(DiaryAppOutput::Subentries(subentries))
+            }
+            DiaryAppRequests::EntrySource(date) => {
+                let source = dapp.get_entry_source(date).await?;
+                Ok(DiaryAppOutput::Source(source))
+            }
+            DiaryAppRequests::AddComment { date, author, text } => {
+                let comment = dapp.add_comment(date, &author, &text).await?;
+                Ok(DiaryAppOutput::Comments(vec![comment.into()]))
+            }
+            DiaryAppRequests::UpdateComment { id, author, text } => {
+                let comment = dapp.update_comment(id, &author, &text).await?;
+                Ok(DiaryAppOutput::Comments(vec![comment.into()]))
+            }
+            DiaryAppRequests::DeleteComment { id, author } => {
+                let comment = dapp.delete_comment(id, &author).await?;
+                Ok(DiaryAppOutput::Comments(vec![comment.into()]))
+            }
+            DiaryAppRequests::Activity(opts) => {
+                let activity = dapp
+                    .get_activity(
+                        opts.user.as_deref(),
+                        opts.min_date.map(Into::into),
+                        opts.max_date.map(Into::into),
+                        opts.limit.unwrap_or(100),
+                    )
+                    .await?;
+                Ok(DiaryAppOutput::Activity(
+                    activity.into_iter().map(Into::into).collect(),
+                ))
+            }
+            DiaryAppRequests::ListUsers => {
+                let users = dapp.list_users().await?;
+                Ok(DiaryAppOutput::Users(
+                    users.into_iter().map(Into::into).collect(),
+                ))
+            }
+            DiaryAppRequests::AddUser {
+                email,
+                role,
+                telegram_userid,
+            } => {
+                let user = dapp.add_user(&email, &role, telegram_userid).await?;
+                Ok(DiaryAppOutput::Users(vec![user.into()]))
+            }
+            DiaryAppRequests::DisableUser(email) => {
+                let user = dapp.disable_user(&email).await?;
+                Ok(DiaryAppOutput::Users(vec![user.into()]))
+            }
+            DiaryAppRequests::SetTelegramUser {
+                email,
+                telegram_userid,
+            } => {
+                let user = dapp.set_telegram_userid(&email, telegram_userid).await?;
+                Ok(DiaryAppOutput::Users(vec![user.into()]))
+            }
+            DiaryAppRequests::LinkCode(email) => {
+                let link_code = dapp.create_link_code(&email).await?;
+                Ok(DiaryAppOutput::LinkCode(link_code.into()))
+            }
+            DiaryAppRequests::SyncDate { date, direction } => {
+                let report = dapp.sync_date(date, direction).await?;
+                Ok(DiaryAppOutput::SyncDate(report))
+            }
+            DiaryAppRequests::Diff { date, from, to } => {
+                let diff = dapp.diff_copies(date, &from, &to).await?;
+                Ok(DiaryAppOutput::Diff(diff))
+            }
+            DiaryAppRequests::StorageReport => {
+                let report = dapp.get_storage_report().await?;
+                Ok(DiaryAppOutput::StorageReport(report))
+            }
+            DiaryAppRequests::TopTerms { range, limit } => {
+                let terms = dapp.get_top_terms(range, limit).await?;
+                Ok(DiaryAppOutput::Terms(terms))
+            }
+            DiaryAppRequests::Habits(range) => {
+                let habits = dapp.get_writing_habits(range).await?;
+                Ok(DiaryAppOutput::Habits(habits))
+            }
+            DiaryAppRequests::Availability(range) => {
+                let availability = dapp.get_entry_availability(range).await?;
+                Ok(DiaryAppOutput::Availability(availability))
+            }
         }
     }
 }
@@ -1,30 +1,133 @@
-use rweb::{delete, get, patch, post, Json, Query, Rejection, Schema};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use rweb::{
+    delete,
+    filters::{addr, header},
+    get,
+    http::header::{CACHE_CONTROL, CONTENT_TYPE, REFERRER_POLICY},
+    openapi::{
+        ComponentDescriptor, ComponentOrInlineSchema, Entity, Response, ResponseEntity, Responses,
+    },
+    patch, post, Filter, Json, Query, Rejection, Reply, Schema,
+};
 use rweb_helper::{
-    html_response::HtmlResponse as HtmlBase, json_response::JsonResponse as JsonBase, DateType,
-    RwebResponse, UuidWrapper,
+    html_response::HtmlResponse as HtmlBase, json_response::JsonResponse as JsonBase, DateTimeType,
+    DateType, RwebResponse, UuidWrapper,
 };
 use serde::{Deserialize, Serialize};
-use stack_string::StackString;
-use std::collections::HashSet;
+use serde_json::{Map, Value};
+use stack_string::{format_sstr, StackString};
+use std::{borrow::Cow, collections::HashSet, net::IpAddr, time::Instant};
+use teloxide::types::Update;
 use time::{Date, OffsetDateTime};
 use time_tz::OffsetDateTimeExt;
 
-use diary_app_lib::date_time_wrapper::DateTimeWrapper;
+use diary_app_lib::{
+    comments::CommentError,
+    date_sync::{EntryCopy, SyncDirection},
+    date_time_wrapper::DateTimeWrapper,
+    entry_availability::EntryAvailability,
+    entry_limits::LimitError,
+    entry_patch::{EntryPatch, LineRange, PatchError},
+    guestbook::{client_addr, GuestbookError},
+    i18n::{Locale, Message},
+    kiosk::{render_text, KioskFormat},
+    maintenance::{MaintenanceError, MaintenanceTask},
+    mobile_sync::{ClientEntryState, ServerEntryState},
+    models::{
+        AuditAction, AuthorizedUsers, CacheItem, ConflictKey, DateRange, DiaryCache,
+        DiaryEntries, DiaryTombstone, MetadataStats, SortOrder, StatsPeriod, SyncLease,
+        DEFAULT_JOURNAL, OWNER_ROLE, PEER_SOURCE, PUBLIC_VISIBILITY,
+    },
+    peer_sync::{handle_pull, handle_push, EntryHash, PeerPullRequest, PeerPushRequest},
+    redaction::redaction_regex,
+    sections::Section,
+    services::EntryError,
+    storage_report::StorageReport,
+    sync_lock::{SyncLockError, SyncLockMode, SYNC_LEASE},
+    unlock::UnlockError,
+    users::UserError,
+    word_stats::{parse_term_range, TOP_TERMS},
+    writing_habits::WritingHabits,
+};
 
 use super::{
-    app::AppState,
+    app::{AppState, DiaryAppActor},
     elements::{
-        edit_body, index_body, list_body, list_conflicts_body, search_body, show_conflict_body,
+        edit_body, index_body, kiosk_body, list_body, list_conflicts_body, list_groups_body,
+        print_body, search_body, show_conflict_body, trash_body, EntryFooter,
     },
     errors::ServiceError as Error,
-    logged_user::LoggedUser,
-    requests::{DiaryAppOutput, DiaryAppRequests, ListOptions, SearchOptions},
+    logged_user::{LoggedUser, PeerUser},
+    requests::{
+        Activity, ActivityOptions, Comment, ConflictRef, Dashboard, DiaryAppOutput,
+        DiaryAppRequests, EncryptedEntry, LinkCode, ListOptions, MaintenanceJobInfo, SearchOptions,
+        Settings, SyncLockInfo, UserAccount,
+    },
     CommitConflictData, ConflictData,
 };
 
 pub type WarpResult<T> = Result<T, Rejection>;
 pub type HttpResult<T> = Result<T, Error>;
 
+fn check_unlocked(user: &LoggedUser, state: &AppState) -> HttpResult<()> {
+    if state.unlock.is_unlocked(user.session.into()) {
+        Ok(())
+    } else {
+        Err(Error::Locked)
+    }
+}
+
+async fn is_viewer(user: &LoggedUser, state: &AppState) -> HttpResult<bool> {
+    let is_viewer = AuthorizedUsers::get_by_email(&user.email, &state.db.pool)
+        .await?
+        .is_some_and(|u| u.is_viewer());
+    Ok(is_viewer)
+}
+
+/// Viewers can read entries and comment on them but not change them
+async fn check_writer(user: &LoggedUser, state: &AppState) -> HttpResult<()> {
+    if is_viewer(user, state).await? {
+        Err(Error::Forbidden)
+    } else {
+        Ok(())
+    }
+}
+
+/// Text longer than `max_entry_length` is answered with 413
+fn too_large(e: anyhow::Error) -> Error {
+    match e.downcast::<LimitError>() {
+        Ok(e) => Error::PayloadTooLarge(e.to_string()),
+        Err(e) => e.into(),
+    }
+}
+
+/// Private entries are left out of what viewers read
+async fn reader_state(user: &LoggedUser, state: AppState) -> HttpResult<AppState> {
+    let hide_private = is_viewer(user, &state).await?;
+    Ok(AppState {
+        db: state.db.with_private_hidden(hide_private),
+        ..state
+    })
+}
+
+/// Language the pages of `user` are rendered in
+async fn user_locale(user: &LoggedUser, state: &AppState) -> HttpResult<Locale> {
+    Ok(state.db.get_locale(&user.email).await?)
+}
+
+/// Owners manage the other users
+async fn check_owner(user: &LoggedUser, state: &AppState) -> HttpResult<()> {
+    let is_owner = AuthorizedUsers::get_by_email(&user.email, &state.db.pool)
+        .await?
+        .is_some_and(|u| u.is_owner());
+    if is_owner {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
 #[derive(RwebResponse)]
 #[response(description = "Search Output", content = "html")]
 struct SearchResponse(HtmlBase<StackString, Error>);
@@ -33,17 +136,21 @@ struct SearchResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "Search Output Page")]
 pub async fn search(
     query: Query<SearchOptions>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<SearchResponse> {
+    check_unlocked(&user, &state)?;
     let query = query.into_inner();
+    let state = reader_state(&user, state).await?;
+    let search_text = query.text.clone();
     let results = search_results(query, state).await?;
-    let body = search_body(results)?.into();
+    let body = search_body(results, search_text)?.into();
     Ok(HtmlBase::new(body).into())
 }
 
 async fn search_results(query: SearchOptions, state: AppState) -> HttpResult<Vec<StackString>> {
-    if let DiaryAppOutput::Lines(body) = DiaryAppRequests::Search(query).process(&state.db).await? {
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::Lines(body) = DiaryAppRequests::Search(query).process(&dapp).await? {
         Ok(body)
     } else {
         Err(Error::BadRequest("Bad Output".into()))
@@ -55,11 +162,29 @@ async fn search_results(query: SearchOptions, state: AppState) -> HttpResult<Vec
 pub struct InsertData {
     #[schema(description = "Text to Insert")]
     pub text: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Client Generated Key, retries with the same key aren't duplicated")]
+    pub idempotency_key: Option<UuidWrapper>,
+    #[schema(description = "Time the Text was Written, defaults to now")]
+    pub datetime: Option<DateTimeType>,
+}
+
+impl From<InsertData> for CacheItem {
+    fn from(data: InsertData) -> Self {
+        Self {
+            diary_text: data.text,
+            diary_datetime: data.datetime.map(|d| OffsetDateTime::from(d).into()),
+            idempotency_key: data.idempotency_key.map(Into::into),
+        }
+    }
 }
 
 #[derive(Schema, Serialize)]
 struct InsertDataOutput {
     datetime: String,
+    #[schema(description = "Passages which Look Like Credentials")]
+    warnings: Vec<StackString>,
 }
 
 #[derive(RwebResponse)]
@@ -70,26 +195,129 @@ struct InsertDataResponse(JsonBase<InsertDataOutput, Error>);
 #[openapi(description = "Insert Text into Cache")]
 pub async fn insert(
     data: Json<InsertData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<InsertDataResponse> {
+    check_writer(&user, &state).await?;
     let data = data.into_inner();
-    let body = insert_body(data, state).await?;
+    let warnings = state.db.scan_secrets(&data.text);
+    let body = insert_body(data, &user.email, state).await?;
     let datetime = body.join("\n");
-    Ok(JsonBase::new(InsertDataOutput { datetime }).into())
+    Ok(JsonBase::new(InsertDataOutput { datetime, warnings }).into())
 }
 
-async fn insert_body(data: InsertData, state: AppState) -> HttpResult<Vec<StackString>> {
-    if let DiaryAppOutput::Lines(body) = DiaryAppRequests::Insert(data.text)
-        .process(&state.db)
-        .await?
-    {
+async fn insert_body(
+    data: InsertData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<StackString>> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::Insert(data.into());
+    if let DiaryAppOutput::Lines(body) = req.process(&dapp).await.map_err(too_large)? {
         Ok(body)
     } else {
         Err(Error::BadRequest("Wrong output".into()))
     }
 }
 
+#[derive(Serialize, Deserialize, Schema)]
+pub struct InsertBatchItem {
+    #[schema(description = "Text to Insert")]
+    pub text: StackString,
+    #[schema(description = "Time the Text was Written, defaults to now")]
+    pub datetime: Option<DateTimeType>,
+    #[schema(description = "Client Generated Key, retries with the same key aren't duplicated")]
+    pub idempotency_key: Option<UuidWrapper>,
+}
+
+impl From<InsertBatchItem> for CacheItem {
+    fn from(item: InsertBatchItem) -> Self {
+        Self {
+            diary_text: item.text,
+            diary_datetime: item.datetime.map(|d| OffsetDateTime::from(d).into()),
+            idempotency_key: item.idempotency_key.map(Into::into),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "InsertBatchData")]
+pub struct InsertBatchData {
+    #[schema(description = "Items to Insert")]
+    pub items: Vec<InsertBatchItem>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct InsertBatchOutput {
+    #[schema(description = "Cache Entry Datetime, unset if the insert failed")]
+    datetime: Option<String>,
+    #[schema(description = "Error Inserting Item")]
+    error: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Insert Batch Result", status = "CREATED")]
+struct InsertBatchResponse(JsonBase<Vec<InsertBatchOutput>, Error>);
+
+#[post("/api/insert_batch")]
+#[openapi(description = "Insert Several Texts into Cache in One Transaction")]
+pub async fn insert_batch(
+    data: Json<InsertBatchData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<InsertBatchResponse> {
+    check_writer(&user, &state).await?;
+    let data = data.into_inner();
+    let results = insert_batch_body(data, &user.email, state).await?;
+    Ok(JsonBase::new(results).into())
+}
+
+async fn insert_batch_body(
+    data: InsertBatchData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<InsertBatchOutput>> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::InsertBatch(data.items.into_iter().map(Into::into).collect());
+    if let DiaryAppOutput::CacheBatch(results) = req.process(&dapp).await.map_err(too_large)? {
+        Ok(results
+            .into_iter()
+            .map(|result| match result {
+                Ok(cache) => InsertBatchOutput {
+                    datetime: Some(cache.diary_datetime.to_string()),
+                    error: None,
+                },
+                Err(error) => InsertBatchOutput {
+                    datetime: None,
+                    error: Some(error),
+                },
+            })
+            .collect())
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Schema)]
+pub struct SyncOptions {
+    #[schema(description = "Only Sync Dates From")]
+    pub since: Option<DateType>,
+    #[schema(description = "Only Sync Dates Until")]
+    pub until: Option<DateType>,
+    #[schema(description = "Wait for a Sync Running Elsewhere to Finish")]
+    pub wait: Option<bool>,
+    #[schema(description = "Take Over the Sync Lock from a Sync Running Elsewhere")]
+    pub force: Option<bool>,
+}
+
 #[derive(RwebResponse)]
 #[response(description = "Sync Output", content = "html")]
 struct SyncResponse(HtmlBase<StackString, Error>);
@@ -97,16 +325,42 @@ struct SyncResponse(HtmlBase<StackString, Error>);
 #[post("/api/sync")]
 #[openapi(description = "Sync Diary")]
 pub async fn sync(
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    query: Query<SyncOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<SyncResponse> {
-    let results = sync_body(state).await?;
-    let body = search_body(results)?.into();
+    check_writer(&user, &state).await?;
+    let results = sync_body(query.into_inner(), &user.email, state).await?;
+    let body = search_body(results, None)?.into();
     Ok(HtmlBase::new(body).into())
 }
 
-async fn sync_body(state: AppState) -> HttpResult<Vec<StackString>> {
-    if let DiaryAppOutput::Lines(body) = DiaryAppRequests::Sync.process(&state.db).await? {
+async fn sync_body(
+    query: SyncOptions,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<StackString>> {
+    let date_range = DateRange::new(query.since.map(Into::into), query.until.map(Into::into));
+    let sync_lock = if query.force == Some(true) {
+        SyncLockMode::Force
+    } else if query.wait == Some(true) {
+        SyncLockMode::Wait
+    } else {
+        SyncLockMode::Fail
+    };
+    let dapp = state
+        .db
+        .with_author(author)
+        .with_date_range(date_range)
+        .with_sync_lock(sync_lock);
+    let output = DiaryAppRequests::Sync
+        .process(&dapp)
+        .await
+        .map_err(|e| match e.downcast::<SyncLockError>() {
+            Ok(e) => Error::SyncInProgress(e.to_string()),
+            Err(e) => e.into(),
+        })?;
+    if let DiaryAppOutput::Lines(body) = output {
         Ok(body)
     } else {
         Err(Error::BadRequest("Bad output".into()))
@@ -120,11 +374,17 @@ pub struct ReplaceData {
     pub date: DateType,
     #[schema(description = "Replacement Text")]
     pub text: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
 }
 
 #[derive(Schema, Serialize)]
 struct ReplaceOutput {
     entry: String,
+    #[schema(description = "Passages which Look Like Credentials")]
+    warnings: Vec<StackString>,
+    #[schema(description = "Conflict the Write Recorded, unset if no lines were dropped")]
+    conflict: Option<ConflictRef>,
 }
 
 #[derive(RwebResponse)]
@@ -135,21 +395,401 @@ struct ReplaceResponse(JsonBase<ReplaceOutput, Error>);
 #[openapi(description = "Insert Text at Specific Date, replace existing text")]
 pub async fn replace(
     data: Json<ReplaceData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<ReplaceResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
     let data = data.into_inner();
-    let body = replace_body(data, state).await?;
-    let entry = body.join("\n");
-    Ok(JsonBase::new(ReplaceOutput { entry }).into())
+    let warnings = state.db.scan_secrets(&data.text);
+    let (entry, conflict) = replace_body(data, &user.email, state).await?;
+    Ok(JsonBase::new(ReplaceOutput {
+        entry: entry.to_string(),
+        warnings,
+        conflict,
+    })
+    .into())
 }
 
-async fn replace_body(data: ReplaceData, state: AppState) -> HttpResult<Vec<StackString>> {
+async fn replace_body(
+    data: ReplaceData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<(StackString, Option<ConflictRef>)> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
     let req = DiaryAppRequests::Replace {
         date: data.date.into(),
         text: data.text,
     };
-    if let DiaryAppOutput::Lines(body) = req.process(&state.db).await? {
+    if let DiaryAppOutput::Replaced(entry, conflict) =
+        req.process(&dapp).await.map_err(too_large)?
+    {
+        Ok((entry, conflict))
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "AppendData")]
+pub struct AppendData {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Text to Append")]
+    pub text: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Append Response", status = "CREATED")]
+struct AppendResponse(JsonBase<ReplaceOutput, Error>);
+
+#[post("/api/append")]
+#[openapi(description = "Append Text to Entry at Specific Date, creating it if missing")]
+pub async fn append(
+    data: Json<AppendData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<AppendResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let warnings = state.db.scan_secrets(&data.text);
+    let body = append_body(data, &user.email, state).await?;
+    let entry = body.join("\n");
+    Ok(JsonBase::new(ReplaceOutput {
+        entry,
+        warnings,
+        conflict: None,
+    })
+    .into())
+}
+
+async fn append_body(
+    data: AppendData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<StackString>> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::Append {
+        date: data.date.into(),
+        text: data.text,
+    };
+    if let DiaryAppOutput::Lines(body) = req.process(&dapp).await.map_err(too_large)? {
+        Ok(body)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct LineRangeData {
+    #[schema(description = "First Line to Replace (zero based)")]
+    pub start: usize,
+    #[schema(description = "Line After the Last Line to Replace")]
+    pub end: usize,
+    #[schema(description = "Replacement Text, empty to remove the lines")]
+    pub text: StackString,
+}
+
+impl From<LineRangeData> for LineRange {
+    fn from(range: LineRangeData) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+            text: range.text,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "PatchData")]
+pub struct PatchData {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Sha256 of the Text the Patch was Made Against")]
+    pub hash: Option<StackString>,
+    #[schema(description = "Line Ranges to Replace")]
+    pub ranges: Option<Vec<LineRangeData>>,
+    #[schema(description = "Unified Diff")]
+    pub diff: Option<StackString>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Patch Response")]
+struct PatchResponse(JsonBase<ReplaceOutput, Error>);
+
+#[patch("/api/entry")]
+#[openapi(description = "Apply Line Ranges or a Unified Diff to an Entry")]
+pub async fn patch_entry(
+    data: Json<PatchData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<PatchResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let body = patch_entry_body(data, &user.email, &state).await?;
+    let entry = body.join("\n");
+    let warnings = state.db.scan_secrets(&entry);
+    Ok(JsonBase::new(ReplaceOutput {
+        entry,
+        warnings,
+        conflict: None,
+    })
+    .into())
+}
+
+async fn patch_entry_body(
+    data: PatchData,
+    author: &str,
+    state: &AppState,
+) -> HttpResult<Vec<StackString>> {
+    let patch = match (data.ranges, data.diff) {
+        (Some(ranges), None) => EntryPatch::Ranges(ranges.into_iter().map(Into::into).collect()),
+        (None, Some(diff)) => EntryPatch::UnifiedDiff(diff),
+        _ => {
+            return Err(Error::BadRequest(
+                "Give exactly one of ranges or diff".into(),
+            ))
+        }
+    };
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::Patch {
+        date: data.date.into(),
+        patch,
+        base_hash: data.hash,
+    };
+    let output = req.process(&dapp).await.map_err(|e| match e.downcast() {
+        Ok(PatchError::Conflict(msg)) => Error::Conflict(msg.to_string()),
+        Ok(PatchError::Invalid(msg)) => Error::BadRequest(msg.to_string()),
+        Err(e) => too_large(e),
+    })?;
+    if let DiaryAppOutput::Lines(body) = output {
+        Ok(body)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct SectionQuery {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Section Slug, e.g. morning for ## Morning")]
+    pub section: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct SectionOutput {
+    #[schema(description = "Header Text, empty for text above the first header")]
+    title: StackString,
+    #[schema(description = "Section Slug")]
+    slug: StackString,
+    #[schema(description = "Zero based Line of the Header")]
+    line: usize,
+    #[schema(description = "Section Text")]
+    text: StackString,
+}
+
+impl From<Section> for SectionOutput {
+    fn from(section: Section) -> Self {
+        Self {
+            title: section.title,
+            slug: section.slug,
+            line: section.line,
+            text: section.text,
+        }
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Entry Section")]
+struct SectionResponse(JsonBase<SectionOutput, Error>);
+
+#[get("/api/entry/section")]
+#[openapi(description = "Get one Section of an Entry")]
+pub async fn get_section(
+    query: Query<SectionQuery>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<SectionResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let req = DiaryAppRequests::Section {
+        date: query.date.into(),
+        slug: query.section,
+    };
+    if let DiaryAppOutput::Section(Some(section)) = req.process(&dapp).await? {
+        Ok(JsonBase::new(section.into()).into())
+    } else {
+        Err(Error::BadRequest("No such section".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "SectionData")]
+pub struct SectionData {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Section Slug")]
+    pub section: StackString,
+    #[schema(description = "Replacement Text for the Section, the header is kept")]
+    pub text: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Replace Section Response")]
+struct ReplaceSectionResponse(JsonBase<ReplaceOutput, Error>);
+
+#[post("/api/entry/section")]
+#[openapi(description = "Replace one Section of an Entry")]
+pub async fn replace_section(
+    data: Json<SectionData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<ReplaceSectionResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let warnings = state.db.scan_secrets(&data.text);
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(&user.email);
+    let req = DiaryAppRequests::ReplaceSection {
+        date: data.date.into(),
+        slug: data.section,
+        text: data.text,
+    };
+    if let DiaryAppOutput::Lines(body) = req.process(&dapp).await.map_err(too_large)? {
+        let entry = body.join("\n");
+        Ok(JsonBase::new(ReplaceOutput {
+            entry,
+            warnings,
+            conflict: None,
+        })
+        .into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "RedactData")]
+pub struct RedactData {
+    #[schema(description = "Text to Redact")]
+    pub pattern: StackString,
+    #[schema(description = "Treat Pattern as a Regular Expression")]
+    pub regex: Option<bool>,
+    #[schema(description = "Replacement Text")]
+    pub placeholder: Option<StackString>,
+    #[schema(description = "Minimum Date")]
+    pub min_date: Option<DateType>,
+    #[schema(description = "Maximum Date")]
+    pub max_date: Option<DateType>,
+    #[schema(description = "Discard the Original Text instead of Storing it Encrypted")]
+    pub permanent: Option<bool>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Redacted Dates")]
+struct RedactResponse(JsonBase<Vec<DateType>, Error>);
+
+#[post("/api/redact")]
+#[openapi(description = "Replace a Text Span or Regex across a Date Range with a Placeholder")]
+pub async fn redact(
+    data: Json<RedactData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<RedactResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let dates = redact_body(data, &user.email, state).await?;
+    Ok(JsonBase::new(dates.into_iter().map(Into::into).collect()).into())
+}
+
+async fn redact_body(data: RedactData, author: &str, state: AppState) -> HttpResult<Vec<Date>> {
+    let regex = redaction_regex(&data.pattern, data.regex.unwrap_or(false))
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::Redact {
+        regex,
+        placeholder: data.placeholder,
+        min_date: data.min_date.map(Into::into),
+        max_date: data.max_date.map(Into::into),
+        permanent: data.permanent.unwrap_or(false),
+    };
+    if let DiaryAppOutput::Dates(dates) = req.process(&dapp).await? {
+        Ok(dates)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Schedule Response", status = "CREATED")]
+struct ScheduleResponse(JsonBase<ReplaceOutput, Error>);
+
+#[post("/api/schedule")]
+#[openapi(description = "Insert Entry at Future Date, hidden until the date arrives")]
+pub async fn schedule(
+    data: Json<ReplaceData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<ScheduleResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let warnings = state.db.scan_secrets(&data.text);
+    let body = schedule_body(data, &user.email, state).await?;
+    let entry = body.join("\n");
+    Ok(JsonBase::new(ReplaceOutput {
+        entry,
+        warnings,
+        conflict: None,
+    })
+    .into())
+}
+
+async fn schedule_body(
+    data: ReplaceData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<StackString>> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::Schedule {
+        date: data.date.into(),
+        text: data.text,
+    };
+    if let DiaryAppOutput::Lines(body) = req.process(&dapp).await? {
         Ok(body)
     } else {
         Err(Error::BadRequest("Bad output".into()))
@@ -164,31 +804,102 @@ struct ListResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "List of Date Buttons")]
 pub async fn list(
     query: Query<ListOptions>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<ListResponse> {
     let query = query.into_inner();
-    let body = get_body(query, &state).await?;
+    check_list_options(&query)?;
+    let state = reader_state(&user, state).await?;
+    let locale = user_locale(&user, &state).await?;
+    let body = get_body(query, &state, locale).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn get_body(query: ListOptions, state: &AppState) -> HttpResult<StackString> {
-    let dates = list_api_body(query, state).await?;
-    let conflicts = if let DiaryAppOutput::Dates(d) = DiaryAppRequests::ListConflicts(None)
-        .process(&state.db)
-        .await?
+async fn get_body(
+    query: ListOptions,
+    state: &AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
+    // pages reached by cursor get a previous button too
+    let start = query.start.or(query.cursor.map(|_| 0));
+    let compact = query.compact.unwrap_or(false);
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let conflicts = if let DiaryAppOutput::Dates(d) =
+        DiaryAppRequests::ListConflicts(None).process(&dapp).await?
     {
         d.into_iter().map(Into::into).collect()
     } else {
         HashSet::new()
     };
-    let body = list_body(conflicts, dates, query.start)?.into();
+    if let Some(period) = list_group(&query)? {
+        return groups_body(query, period, conflicts, state, locale).await;
+    }
+    let (dates, next_cursor) = list_api_body(query, state).await?;
+    let body = list_body(conflicts, dates, start, next_cursor, compact, locale)?.into();
     Ok(body)
 }
 
-async fn list_api_body(query: ListOptions, state: &AppState) -> HttpResult<Vec<DateType>> {
-    if let DiaryAppOutput::Dates(dates) = DiaryAppRequests::List(query).process(&state.db).await? {
-        Ok(dates.into_iter().map(Into::into).collect())
+/// Collapsible groups of dates, the dates of the newest group are shown
+/// right away and those of the others are loaded when they are opened
+async fn groups_body(
+    query: ListOptions,
+    period: StatsPeriod,
+    conflicts: HashSet<DateType>,
+    state: &AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
+    let compact = query.compact.unwrap_or(false);
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let groups = if let DiaryAppOutput::Groups(groups) =
+        DiaryAppRequests::ListGroups(period).process(&dapp).await?
+    {
+        groups
+    } else {
+        return Err(Error::BadRequest("Bad results".into()));
+    };
+    let newest = match groups.first() {
+        Some(group) => {
+            let query = ListOptions {
+                min_date: Some(group.period.into()),
+                max_date: Some(period.last_day(group.period).into()),
+                journal: query.journal,
+                ..ListOptions::default()
+            };
+            list_api_body(query, state).await?.0
+        }
+        None => Vec::new(),
+    };
+    let body = list_groups_body(conflicts, groups, period, newest, compact, locale)?;
+    Ok(body.into())
+}
+
+fn list_group(query: &ListOptions) -> HttpResult<Option<StatsPeriod>> {
+    query
+        .group
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(|e| Error::BadRequest(format_sstr!("{e}")))
+}
+
+fn check_list_options(query: &ListOptions) -> HttpResult<()> {
+    if let Some(order) = &query.order {
+        order
+            .parse::<SortOrder>()
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+    }
+    Ok(())
+}
+
+/// Dates of the page and the cursor of the next one
+async fn list_api_body(
+    query: ListOptions,
+    state: &AppState,
+) -> HttpResult<(Vec<DateType>, Option<DateType>)> {
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::DatePage(page) = DiaryAppRequests::List(query).process(&dapp).await? {
+        let dates = page.dates.into_iter().map(Into::into).collect();
+        Ok((dates, page.next_cursor.map(Into::into)))
     } else {
         Err(Error::BadRequest("Bad results".into()))
     }
@@ -197,6 +908,8 @@ async fn list_api_body(query: ListOptions, state: &AppState) -> HttpResult<Vec<D
 #[derive(Serialize, Deserialize, Schema)]
 pub struct EditData {
     pub date: DateType,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
 }
 
 #[derive(RwebResponse)]
@@ -207,26 +920,50 @@ struct EditResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "Diary Edit Form")]
 pub async fn edit(
     query: Query<EditData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<EditResponse> {
+    check_unlocked(&user, &state)?;
     let query = query.into_inner();
-    let body = get_edit_body(query, state).await?;
+    let state = reader_state(&user, state).await?;
+    let locale = user_locale(&user, &state).await?;
+    let body = get_edit_body(query, state, locale).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn get_edit_body(query: EditData, state: AppState) -> HttpResult<StackString> {
+/// Text or encrypted entry of `date`, `None` if there is no entry yet
+async fn display_entry(
+    diary_date: Date,
+    dapp: &DiaryAppActor,
+) -> HttpResult<Option<(Vec<StackString>, Option<EncryptedEntry>)>> {
+    match DiaryAppRequests::Display(diary_date).process(dapp).await {
+        Ok(DiaryAppOutput::Lines(lines)) => Ok(Some((lines, None))),
+        Ok(DiaryAppOutput::Encrypted(entries)) => {
+            Ok(Some((Vec::new(), entries.into_iter().next())))
+        }
+        Ok(_) => Ok(Some((Vec::new(), None))),
+        Err(e) if e.downcast_ref::<EntryError>().is_some() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_edit_body(
+    query: EditData,
+    state: AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
     let diary_date = query.date.into();
-    let text = if let DiaryAppOutput::Lines(lines) = DiaryAppRequests::Display(diary_date)
-        .process(&state.db)
-        .await?
-    {
-        lines
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let (text, encrypted) = display_entry(diary_date, &dapp).await?.unwrap_or_default();
+    // encrypted entries are hashed in the browser once decrypted
+    let hash = if encrypted.is_none() {
+        Some(DiaryEntries::new(diary_date, text.join("\n")).get_hash())
     } else {
-        Vec::new()
+        None
     };
-    let body = edit_body(diary_date, text, false)?.into();
-    Ok(body)
+    let footer = EntryFooter::default();
+    let body = edit_body(diary_date, text, false, encrypted, hash, footer, locale)?;
+    Ok(body.into())
 }
 
 #[derive(RwebResponse)]
@@ -237,26 +974,108 @@ struct DisplayResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "Display Diary Entry")]
 pub async fn display(
     query: Query<EditData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<DisplayResponse> {
+    check_unlocked(&user, &state)?;
     let query = query.into_inner();
-    let body = display_body(query, state).await?;
+    let state = reader_state(&user, state).await?;
+    let locale = user_locale(&user, &state).await?;
+    let body = display_body(query, state, locale).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn display_body(query: EditData, state: AppState) -> HttpResult<StackString> {
+async fn display_body(
+    query: EditData,
+    state: AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
     let diary_date = query.date.into();
-    let text = if let DiaryAppOutput::Lines(lines) = DiaryAppRequests::Display(diary_date)
-        .process(&state.db)
-        .await?
-    {
-        lines
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let Some((text, encrypted)) = display_entry(diary_date, &dapp).await? else {
+        // offer to write the missing entry instead of an error page
+        let hash = DiaryEntries::new(diary_date, "").get_hash();
+        let footer = EntryFooter::default();
+        let body = edit_body(diary_date, Vec::new(), false, None, Some(hash), footer, locale)?;
+        return Ok(body.into());
+    };
+    let comments = comments_body(DiaryAppRequests::ListComments(diary_date), &dapp).await?;
+    let subentries = match DiaryAppRequests::Subentries(diary_date).process(&dapp).await? {
+        DiaryAppOutput::Subentries(subentries) => subentries,
+        _ => Vec::new(),
+    };
+    let source = match DiaryAppRequests::EntrySource(diary_date).process(&dapp).await? {
+        DiaryAppOutput::Source(source) => source,
+        _ => None,
+    };
+    let footer = EntryFooter {
+        comments,
+        subentries,
+        source,
+    };
+    let body = edit_body(diary_date, text, true, encrypted, None, footer, locale)?;
+    Ok(body.into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct PrintOptions {
+    #[schema(description = "First Date to Print")]
+    pub min_date: DateType,
+    #[schema(description = "Last Date to Print")]
+    pub max_date: DateType,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Print View", content = "html")]
+struct PrintResponse(HtmlBase<StackString, Error>);
+
+#[get("/api/print")]
+#[openapi(description = "Printable Document of the Entries in a Date Range")]
+pub async fn print(
+    query: Query<PrintOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<PrintResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let state = reader_state(&user, state).await?;
+    let locale = user_locale(&user, &state).await?;
+    let body = print_view_body(query, state, locale).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn print_view_body(
+    query: PrintOptions,
+    state: AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
+    let min_date: Date = query.min_date.into();
+    let max_date: Date = query.max_date.into();
+    if min_date > max_date {
+        return Err(Error::BadRequest("min_date is after max_date".into()));
+    }
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let req = DiaryAppRequests::Print { min_date, max_date };
+    let entries = if let DiaryAppOutput::Entries(entries) = req.process(&dapp).await? {
+        entries
+            .into_iter()
+            .map(|entry| {
+                let text = if entry.is_encrypted {
+                    None
+                } else {
+                    Some(entry.diary_text)
+                };
+                (entry.diary_date, text)
+            })
+            .collect()
     } else {
         Vec::new()
     };
-    let body = edit_body(diary_date, text, true)?.into();
-    Ok(body)
+    let title = format_sstr!("{} {min_date} to {max_date}", dapp.journal);
+    let body = print_body(title, entries, locale)?;
+    Ok(body.into())
 }
 
 #[derive(RwebResponse)]
@@ -266,12 +1085,263 @@ struct FrontpageResponse(HtmlBase<StackString, Error>);
 #[get("/api/index.html")]
 #[openapi(description = "Diary Main Page")]
 pub async fn diary_frontpage(
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    query: Query<JournalData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
 ) -> WarpResult<FrontpageResponse> {
-    let body = index_body()?.into();
+    let journal = query.into_inner().journal;
+    let state = reader_state(&user, state).await?;
+    let query = ListOptions {
+        limit: Some(10),
+        starred: Some(true),
+        journal: journal.clone(),
+        ..ListOptions::default()
+    };
+    let (favorites, _) = list_api_body(query, &state).await?;
+    let journals = journals_body(&state).await?;
+    let dashboard = if check_unlocked(&user, &state).is_ok() {
+        let dapp = state.db.with_journal(journal.as_deref());
+        Some(dashboard_body(&dapp).await?)
+    } else {
+        None
+    };
+    let locale = user_locale(&user, &state).await?;
+    let body = index_body(favorites, journals, journal, dashboard, locale)?.into();
     Ok(HtmlBase::new(body).into())
 }
 
+#[derive(RwebResponse)]
+#[response(description = "Dashboard")]
+struct DashboardResponse(JsonBase<Dashboard, Error>);
+
+#[get("/api/dashboard")]
+#[openapi(description = "Recent Entries, Streak, Conflicts and Last Sync")]
+pub async fn dashboard(
+    query: Query<JournalData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<DashboardResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let dashboard = dashboard_body(&dapp).await?;
+    Ok(JsonBase::new(dashboard).into())
+}
+
+async fn dashboard_body(dapp: &DiaryAppActor) -> HttpResult<Dashboard> {
+    if let DiaryAppOutput::Dashboard(dashboard) = DiaryAppRequests::Dashboard.process(dapp).await? {
+        Ok(dashboard)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Settings")]
+struct SettingsResponse(JsonBase<Settings, Error>);
+
+#[get("/api/settings")]
+#[openapi(description = "Get Settings of Current User")]
+pub async fn get_settings(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<SettingsResponse> {
+    let settings = settings_body(DiaryAppRequests::GetSettings(user.email), &state).await?;
+    Ok(JsonBase::new(settings).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct SettingsData {
+    #[schema(description = "Send a Past Entry Every Morning")]
+    pub resurface: bool,
+    #[schema(description = "Language of the Web UI and Bot Replies (en, de, fr or es)")]
+    pub locale: Option<StackString>,
+}
+
+#[post("/api/settings")]
+#[openapi(description = "Update Settings of Current User")]
+pub async fn update_settings(
+    data: Json<SettingsData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<SettingsResponse> {
+    let data = data.into_inner();
+    let locale = data
+        .locale
+        .map(|locale| locale.parse::<Locale>())
+        .transpose()
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    let req = DiaryAppRequests::UpdateSettings {
+        email: user.email,
+        resurface: data.resurface,
+        locale,
+    };
+    let settings = settings_body(req, &state).await?;
+    Ok(JsonBase::new(settings).into())
+}
+
+async fn settings_body(req: DiaryAppRequests, state: &AppState) -> HttpResult<Settings> {
+    if let DiaryAppOutput::Settings(settings) = req.process(&state.db).await? {
+        Ok(settings)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Comments")]
+struct CommentsResponse(JsonBase<Vec<Comment>, Error>);
+
+#[get("/api/comments")]
+#[openapi(description = "List Comments on Entry")]
+pub async fn list_comments(
+    query: Query<EditData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<CommentsResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let comments = comments_body(DiaryAppRequests::ListComments(query.date.into()), &dapp).await?;
+    Ok(JsonBase::new(comments).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AddCommentData {
+    #[schema(description = "Date of the Entry")]
+    pub date: DateType,
+    #[schema(description = "Comment Text")]
+    pub text: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Added Comment", status = "CREATED")]
+struct AddCommentResponse(JsonBase<Comment, Error>);
+
+#[post("/api/comments")]
+#[openapi(description = "Comment on Entry, Viewers can Comment too")]
+pub async fn add_comment(
+    data: Json<AddCommentData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<AddCommentResponse> {
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(data.journal.as_deref());
+    let req = DiaryAppRequests::AddComment {
+        date: data.date.into(),
+        author: user.email,
+        text: data.text,
+    };
+    let comment = single_comment_body(req, &dapp).await?;
+    Ok(JsonBase::new(comment).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct UpdateCommentData {
+    #[schema(description = "Comment Id")]
+    pub id: UuidWrapper,
+    #[schema(description = "Comment Text")]
+    pub text: StackString,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Comment")]
+struct CommentResponse(JsonBase<Comment, Error>);
+
+#[patch("/api/comments")]
+#[openapi(description = "Edit Own Comment")]
+pub async fn update_comment(
+    data: Json<UpdateCommentData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<CommentResponse> {
+    let data = data.into_inner();
+    let req = DiaryAppRequests::UpdateComment {
+        id: data.id.into(),
+        author: user.email,
+        text: data.text,
+    };
+    let comment = single_comment_body(req, &state.db).await?;
+    Ok(JsonBase::new(comment).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct CommentIdData {
+    #[schema(description = "Comment Id")]
+    pub id: UuidWrapper,
+}
+
+#[delete("/api/comments")]
+#[openapi(description = "Delete Own Comment")]
+pub async fn delete_comment(
+    query: Query<CommentIdData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<CommentResponse> {
+    let query = query.into_inner();
+    let req = DiaryAppRequests::DeleteComment {
+        id: query.id.into(),
+        author: user.email,
+    };
+    let comment = single_comment_body(req, &state.db).await?;
+    Ok(JsonBase::new(comment).into())
+}
+
+async fn comments_body(req: DiaryAppRequests, dapp: &DiaryAppActor) -> HttpResult<Vec<Comment>> {
+    let output = req.process(dapp).await.map_err(|e| match e.downcast() {
+        Ok(CommentError::NotAuthor) => Error::Forbidden,
+        Ok(e @ CommentError::NoEntry(_)) => Error::EntryNotFound(e.to_string()),
+        Ok(e) => Error::BadRequest(e.to_string()),
+        Err(e) => e.into(),
+    })?;
+    if let DiaryAppOutput::Comments(comments) = output {
+        Ok(comments)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+async fn single_comment_body(req: DiaryAppRequests, dapp: &DiaryAppActor) -> HttpResult<Comment> {
+    comments_body(req, dapp)
+        .await?
+        .pop()
+        .ok_or_else(|| Error::BadRequest("Bad output".into()))
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Activity Log")]
+struct ActivityResponse(JsonBase<Vec<Activity>, Error>);
+
+#[get("/api/activity")]
+#[openapi(description = "Inserts, Replaces, Syncs and Conflict Resolutions, Most Recent First")]
+pub async fn activity(
+    query: Query<ActivityOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<ActivityResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let body = activity_body(query, state).await?;
+    Ok(JsonBase::new(body).into())
+}
+
+async fn activity_body(query: ActivityOptions, state: AppState) -> HttpResult<Vec<Activity>> {
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::Activity(activity) =
+        DiaryAppRequests::Activity(query).process(&dapp).await?
+    {
+        Ok(activity)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
 #[derive(RwebResponse)]
 #[response(description = "List Conflicts", content = "html")]
 struct ListConflictsResponse(HtmlBase<StackString, Error>);
@@ -280,25 +1350,32 @@ struct ListConflictsResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "List Conflicts")]
 pub async fn list_conflicts(
     query: Query<ConflictData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<ListConflictsResponse> {
     let query = query.into_inner();
-    let body = get_conflicts_body(query, state).await?;
+    let locale = user_locale(&user, &state).await?;
+    let state = reader_state(&user, state).await?;
+    let body = get_conflicts_body(query, state, locale).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn get_conflicts_body(query: ConflictData, state: AppState) -> HttpResult<StackString> {
+async fn get_conflicts_body(
+    query: ConflictData,
+    state: AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
+    let dapp = state.db.with_journal(query.journal.as_deref());
     let conflicts = if let DiaryAppOutput::Timestamps(dates) =
         DiaryAppRequests::ListConflicts(query.date)
-            .process(&state.db)
+            .process(&dapp)
             .await?
     {
         dates
     } else {
         Vec::new()
     };
-    let body = list_conflicts_body(query.date, conflicts)?.into();
+    let body = list_conflicts_body(query.date, conflicts, locale)?.into();
     Ok(body)
 }
 
@@ -310,33 +1387,58 @@ struct ShowConflictResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "Show Conflict")]
 pub async fn show_conflict(
     query: Query<ConflictData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<ShowConflictResponse> {
+    check_unlocked(&user, &state)?;
     let query = query.into_inner();
-    let body = get_show_conflict(query, state).await?;
+    let locale = user_locale(&user, &state).await?;
+    let state = reader_state(&user, state).await?;
+    let body = get_show_conflict(query, state, locale).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn get_show_conflict(query: ConflictData, state: AppState) -> HttpResult<StackString> {
+/// The group id takes precedence over the datetime of the sync
+fn conflict_key(
+    id: Option<UuidWrapper>,
+    datetime: Option<DateTimeWrapper>,
+) -> Option<ConflictKey> {
+    match (id, datetime) {
+        (Some(id), _) => Some(ConflictKey::Group(id.into())),
+        (None, Some(datetime)) => Some(datetime.into()),
+        (None, None) => None,
+    }
+}
+
+async fn get_show_conflict(
+    query: ConflictData,
+    state: AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
     let local = DateTimeWrapper::local_tz();
-    let datetime = query
-        .datetime
+    let key = conflict_key(query.id, query.datetime)
         .unwrap_or_else(|| OffsetDateTime::now_utc().into());
     let diary_date: Date = query
         .date
         .unwrap_or_else(|| OffsetDateTime::now_utc().to_timezone(local).date().into())
         .into();
+    let dapp = state.db.with_journal(query.journal.as_deref());
     let conflicts = if let DiaryAppOutput::Conflicts(conflicts) =
-        DiaryAppRequests::ShowConflict(datetime)
-            .process(&state.db)
+        DiaryAppRequests::ShowConflict(key)
+            .process(&dapp)
             .await?
     {
         conflicts
     } else {
         Vec::new()
     };
-    let body = show_conflict_body(diary_date, conflicts, datetime)?.into();
+    let datetime = match (conflicts.first(), key) {
+        (Some(conflict), _) => conflict.sync_datetime,
+        (None, ConflictKey::Datetime(datetime)) => datetime,
+        (None, ConflictKey::Group(_)) => OffsetDateTime::now_utc().into(),
+    };
+    let split = query.view.as_deref() == Some("split");
+    let body = show_conflict_body(diary_date, conflicts, datetime, split, locale)?.into();
     Ok(body)
 }
 
@@ -348,18 +1450,27 @@ struct RemoveConflictResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "Delete Conflict")]
 pub async fn remove_conflict(
     query: Query<ConflictData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<RemoveConflictResponse> {
+    check_writer(&user, &state).await?;
     let query = query.into_inner();
-    let body = remove_conflict_body(query, state).await?;
+    let body = remove_conflict_body(query, &user.email, state).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn remove_conflict_body(query: ConflictData, state: AppState) -> HttpResult<StackString> {
-    let body = if let Some(datetime) = query.datetime {
-        if let DiaryAppOutput::Lines(lines) = DiaryAppRequests::RemoveConflict(datetime)
-            .process(&state.db)
+async fn remove_conflict_body(
+    query: ConflictData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<StackString> {
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(author);
+    let body = if let Some(key) = conflict_key(query.id, query.datetime) {
+        if let DiaryAppOutput::Lines(lines) = DiaryAppRequests::RemoveConflict(key)
+            .process(&dapp)
             .await?
         {
             lines.join("\n")
@@ -368,7 +1479,7 @@ async fn remove_conflict_body(query: ConflictData, state: AppState) -> HttpResul
         }
     } else if let Some(date) = query.date {
         if let DiaryAppOutput::Lines(lines) = DiaryAppRequests::CleanConflicts(date.into())
-            .process(&state.db)
+            .process(&dapp)
             .await?
         {
             lines.join("\n")
@@ -397,20 +1508,25 @@ struct UpdateConflictResponse(HtmlBase<&'static str, Error>);
 #[openapi(description = "Update Conflict")]
 pub async fn update_conflict(
     query: Query<ConflictUpdateData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<UpdateConflictResponse> {
+    check_writer(&user, &state).await?;
     let query = query.into_inner();
-    update_conflict_body(query, state).await?;
+    update_conflict_body(query, &user.email, state).await?;
     Ok(HtmlBase::new("finished").into())
 }
 
-async fn update_conflict_body(query: ConflictUpdateData, state: AppState) -> HttpResult<()> {
+async fn update_conflict_body(
+    query: ConflictUpdateData,
+    author: &str,
+    state: AppState,
+) -> HttpResult<()> {
     DiaryAppRequests::UpdateConflict {
         id: query.id.into(),
         diff_text: query.diff_type,
     }
-    .process(&state.db)
+    .process(&state.db.with_author(author))
     .await?;
     Ok(())
 }
@@ -423,26 +1539,38 @@ struct ConflictResponse(JsonBase<ReplaceOutput, Error>);
 #[openapi(description = "Commit Conflict")]
 pub async fn commit_conflict(
     query: Query<CommitConflictData>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
     #[data] state: AppState,
 ) -> WarpResult<ConflictResponse> {
+    check_writer(&user, &state).await?;
     let query = query.into_inner();
-    let body = commit_conflict_body(query, state).await?;
-    let entry = body.join("\n");
-    Ok(JsonBase::new(ReplaceOutput { entry }).into())
+    let (entry, conflict) = commit_conflict_body(query, &user.email, state).await?;
+    Ok(JsonBase::new(ReplaceOutput {
+        entry: entry.to_string(),
+        warnings: Vec::new(),
+        conflict,
+    })
+    .into())
 }
 
 async fn commit_conflict_body(
     query: CommitConflictData,
+    author: &str,
     state: AppState,
-) -> HttpResult<Vec<StackString>> {
-    if let DiaryAppOutput::Lines(lines) = DiaryAppRequests::CommitConflict(query.datetime)
-        .process(&state.db)
+) -> HttpResult<(StackString, Option<ConflictRef>)> {
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(author);
+    let key = conflict_key(query.id, query.datetime)
+        .ok_or_else(|| Error::BadRequest("id or datetime is required".into()))?;
+    if let DiaryAppOutput::Replaced(entry, conflict) = DiaryAppRequests::CommitConflict(key)
+        .process(&dapp)
         .await?
     {
-        Ok(lines)
+        Ok((entry, conflict))
     } else {
-        Ok(Vec::new())
+        Err(Error::BadRequest("Bad output".into()))
     }
 }
 
@@ -455,3 +1583,1767 @@ struct UserResponse(JsonBase<LoggedUser, Error>);
 pub async fn user(#[filter = "LoggedUser::filter"] user: LoggedUser) -> WarpResult<UserResponse> {
     Ok(JsonBase::new(user).into())
 }
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct ClientEntry {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Sha256 of Client Text")]
+    pub hash: StackString,
+    #[schema(description = "Server Modified Time at Last Sync")]
+    pub server_modified: Option<DateTimeType>,
+    #[schema(description = "Client Version Counter")]
+    pub client_version: i64,
+    #[schema(description = "Modified Client Text")]
+    pub text: Option<StackString>,
+}
+
+impl From<ClientEntry> for ClientEntryState {
+    fn from(entry: ClientEntry) -> Self {
+        Self {
+            diary_date: entry.date.into(),
+            hash: entry.hash,
+            server_modified: entry
+                .server_modified
+                .map(|d| OffsetDateTime::from(d).into()),
+            client_version: entry.client_version,
+            diary_text: entry.text,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "MobileSyncRequest")]
+pub struct MobileSyncRequest {
+    #[schema(description = "Client ID")]
+    pub client_id: StackString,
+    #[schema(description = "Entries Known by Client")]
+    pub entries: Vec<ClientEntry>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct ServerEntry {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Server Text, Empty for Encrypted Entries")]
+    pub text: StackString,
+    #[schema(description = "Entry is Encrypted Client Side")]
+    pub is_encrypted: bool,
+    #[schema(description = "Base64 Encoded Ciphertext")]
+    pub ciphertext: Option<StackString>,
+    #[schema(description = "Base64 Encoded Nonce")]
+    pub nonce: Option<StackString>,
+    #[schema(description = "Server Modified Time")]
+    pub server_modified: DateTimeType,
+    #[schema(description = "Client Text Recorded as Conflict")]
+    pub conflict: bool,
+    #[schema(description = "Entry was Deleted, the Client should Remove its Copy")]
+    pub deleted: bool,
+}
+
+impl From<ServerEntryState> for ServerEntry {
+    fn from(entry: ServerEntryState) -> Self {
+        Self {
+            date: entry.diary_date.into(),
+            text: entry.diary_text,
+            is_encrypted: entry.is_encrypted,
+            ciphertext: entry.diary_ciphertext.map(|c| STANDARD.encode(c).into()),
+            nonce: entry.diary_nonce.map(|n| STANDARD.encode(n).into()),
+            server_modified: entry.server_modified.to_offsetdatetime().into(),
+            conflict: entry.conflict,
+            deleted: entry.deleted,
+        }
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Mobile Sync")]
+struct MobileSyncResponse(JsonBase<Vec<ServerEntry>, Error>);
+
+#[post("/api/mobile_sync")]
+#[openapi(description = "Sync Entries with Offline Client")]
+pub async fn mobile_sync(
+    data: Json<MobileSyncRequest>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<MobileSyncResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let entries = mobile_sync_body(data, &user.email, state).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+async fn mobile_sync_body(
+    data: MobileSyncRequest,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<ServerEntry>> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    let req = DiaryAppRequests::MobileSync {
+        client_id: data.client_id,
+        entries: data.entries.into_iter().map(Into::into).collect(),
+    };
+    if let DiaryAppOutput::MobileSync(entries) = req.process(&dapp).await? {
+        Ok(entries.into_iter().map(Into::into).collect())
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Encrypted Entries")]
+struct EncryptedEntriesResponse(JsonBase<Vec<EncryptedEntry>, Error>);
+
+#[get("/api/encrypted")]
+#[openapi(description = "List Encrypted Entries")]
+pub async fn list_encrypted(
+    query: Query<ListOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<EncryptedEntriesResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    check_list_options(&query)?;
+    let entries = list_encrypted_body(query, state).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+async fn list_encrypted_body(
+    query: ListOptions,
+    state: AppState,
+) -> HttpResult<Vec<EncryptedEntry>> {
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::Encrypted(entries) = DiaryAppRequests::ListEncrypted(query)
+        .process(&dapp)
+        .await?
+    {
+        Ok(entries)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Replace Encrypted Response", status = "CREATED")]
+struct ReplaceEncryptedResponse(JsonBase<ReplaceOutput, Error>);
+
+#[post("/api/encrypted")]
+#[openapi(description = "Insert Encrypted Entry at Specific Date, replace existing entry")]
+pub async fn replace_encrypted(
+    data: Json<EncryptedEntry>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<ReplaceEncryptedResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let body = replace_encrypted_body(data, &user.email, state).await?;
+    let entry = body.join("\n");
+    Ok(JsonBase::new(ReplaceOutput {
+        entry,
+        warnings: Vec::new(),
+        conflict: None,
+    })
+    .into())
+}
+
+async fn replace_encrypted_body(
+    data: EncryptedEntry,
+    author: &str,
+    state: AppState,
+) -> HttpResult<Vec<StackString>> {
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(author);
+    if let DiaryAppOutput::Lines(body) = DiaryAppRequests::ReplaceEncrypted(data)
+        .process(&dapp)
+        .await?
+    {
+        Ok(body)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "UnlockData")]
+pub struct UnlockData {
+    #[schema(description = "PIN or TOTP Code")]
+    pub code: StackString,
+}
+
+#[derive(Schema, Serialize)]
+struct UnlockOutput {
+    #[schema(description = "Unlocked Until")]
+    expires_at: DateTimeType,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Unlock Response")]
+struct UnlockResponse(JsonBase<UnlockOutput, Error>);
+
+#[post("/api/unlock")]
+#[openapi(description = "Unlock Entries for the Current Session")]
+pub async fn unlock(
+    data: Json<UnlockData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<UnlockResponse> {
+    let data = data.into_inner();
+    let expires_at = state
+        .unlock
+        .unlock(user.session.into(), &data.code, OffsetDateTime::now_utc())
+        .map_err(|e| match e {
+            UnlockError::InvalidCode => Error::Locked,
+            UnlockError::TooManyAttempts(_) => Error::TooManyRequests,
+        })?;
+    Ok(JsonBase::new(UnlockOutput {
+        expires_at: expires_at.into(),
+    })
+    .into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Lock Response", content = "html")]
+struct LockResponse(HtmlBase<&'static str, Error>);
+
+#[post("/api/lock")]
+#[openapi(description = "Lock Entries for the Current Session")]
+pub async fn lock(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<LockResponse> {
+    state.unlock.lock(user.session.into());
+    Ok(HtmlBase::new("locked").into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Trash", content = "html")]
+struct TrashResponse(HtmlBase<StackString, Error>);
+
+#[get("/api/trash")]
+#[openapi(description = "List Deleted Entries")]
+pub async fn list_trash(
+    query: Query<JournalData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<TrashResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let locale = user_locale(&user, &state).await?;
+    let body = list_trash_body(query, state, locale).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn list_trash_body(
+    query: JournalData,
+    state: AppState,
+    locale: Locale,
+) -> HttpResult<StackString> {
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let entries = if let DiaryAppOutput::Entries(entries) =
+        DiaryAppRequests::ListTrash.process(&dapp).await?
+    {
+        entries
+            .into_iter()
+            .filter_map(|entry| Some((entry.diary_date, entry.deleted_at?)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let body = trash_body(entries, locale)?.into();
+    Ok(body)
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Restore Entry", content = "html")]
+struct RestoreTrashResponse(HtmlBase<StackString, Error>);
+
+#[post("/api/trash/restore")]
+#[openapi(description = "Restore Deleted Entry")]
+pub async fn restore_trash(
+    query: Query<EditData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<RestoreTrashResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(&user.email);
+    let body = trash_request_body(DiaryAppRequests::RestoreTrash(query.date.into()), &dapp).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Purge Entry", content = "html")]
+struct PurgeTrashResponse(HtmlBase<StackString, Error>);
+
+#[delete("/api/trash")]
+#[openapi(description = "Permanently Remove Deleted Entry")]
+pub async fn purge_trash(
+    query: Query<EditData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<PurgeTrashResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(&user.email);
+    let body = trash_request_body(DiaryAppRequests::PurgeTrash(query.date.into()), &dapp).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn trash_request_body(
+    req: DiaryAppRequests,
+    dapp: &DiaryAppActor,
+) -> HttpResult<StackString> {
+    if let DiaryAppOutput::Lines(lines) = req.process(dapp).await? {
+        Ok(lines.join("\n").into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct DeleteEntryData {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Confirm Deletion")]
+    pub confirm: Option<bool>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Delete Entry", content = "html")]
+struct DeleteEntryResponse(HtmlBase<StackString, Error>);
+
+#[delete("/api/entry")]
+#[openapi(description = "Move Entry to Trash and Remove Local and S3 Copies")]
+pub async fn delete_entry(
+    query: Query<DeleteEntryData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<DeleteEntryResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    if query.confirm != Some(true) {
+        return Err(Error::BadRequest("Deleting an entry requires confirm=true".into()).into());
+    }
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(&user.email);
+    let body = trash_request_body(DiaryAppRequests::Delete(query.date.into()), &dapp).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(Schema, Serialize)]
+struct StarOutput {
+    date: DateType,
+    starred: bool,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Star Response")]
+struct StarResponse(JsonBase<StarOutput, Error>);
+
+#[post("/api/star")]
+#[openapi(description = "Toggle Starred Flag on Entry")]
+pub async fn star(
+    query: Query<EditData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<StarResponse> {
+    check_writer(&user, &state).await?;
+    let query = query.into_inner();
+    let date = query.date;
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(&user.email);
+    let starred = star_body(date.into(), &dapp).await?;
+    Ok(JsonBase::new(StarOutput { date, starred }).into())
+}
+
+async fn star_body(date: Date, dapp: &DiaryAppActor) -> HttpResult<bool> {
+    if let DiaryAppOutput::Starred(starred) =
+        DiaryAppRequests::ToggleStar(date).process(dapp).await?
+    {
+        Ok(starred)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Schema, Serialize)]
+struct PrivateOutput {
+    date: DateType,
+    private: bool,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Private Response")]
+struct PrivateResponse(JsonBase<PrivateOutput, Error>);
+
+#[post("/api/private")]
+#[openapi(description = "Toggle Private Flag on Entry, Private Entries are Hidden from Viewers")]
+pub async fn toggle_private(
+    query: Query<EditData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<PrivateResponse> {
+    check_writer(&user, &state).await?;
+    let query = query.into_inner();
+    let date = query.date;
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(&user.email);
+    let req = DiaryAppRequests::TogglePrivate(date.into());
+    if let DiaryAppOutput::Private(private) = req.process(&dapp).await? {
+        Ok(JsonBase::new(PrivateOutput { date, private }).into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct SyncDateData {
+    pub date: DateType,
+    #[schema(description = "pull (local file or s3 to database) or push (database to both)")]
+    pub direction: StackString,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct SyncDateOutput {
+    date: DateType,
+    direction: StackString,
+    #[schema(description = "Copies which Differed from the Database")]
+    differed: Vec<StackString>,
+    output: Vec<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Date Response")]
+struct SyncDateResponse(JsonBase<SyncDateOutput, Error>);
+
+#[post("/api/sync_date")]
+#[openapi(description = "Force Re-sync of a Single Date between Local File, Database and S3")]
+pub async fn sync_date(
+    query: Query<SyncDateData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<SyncDateResponse> {
+    check_writer(&user, &state).await?;
+    let query = query.into_inner();
+    let date = query.date;
+    let direction: SyncDirection = query
+        .direction
+        .parse()
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    let dapp = state
+        .db
+        .with_journal(query.journal.as_deref())
+        .with_author(&user.email);
+    let req = DiaryAppRequests::SyncDate {
+        date: date.into(),
+        direction,
+    };
+    if let DiaryAppOutput::SyncDate(report) = req.process(&dapp).await? {
+        Ok(JsonBase::new(SyncDateOutput {
+            date,
+            direction: direction.to_str().into(),
+            differed: report.differed,
+            output: report.output,
+        })
+        .into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct DiffData {
+    pub date: DateType,
+    #[schema(description = "db, local, s3 or an s3 Version ID")]
+    pub from: StackString,
+    #[schema(description = "db, local, s3 or an s3 Version ID (default db)")]
+    pub to: Option<StackString>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct DiffOutput {
+    date: DateType,
+    from: StackString,
+    to: StackString,
+    #[schema(description = "Unified Diff, Empty if the Copies Match")]
+    diff: StackString,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Diff Response")]
+struct DiffResponse(JsonBase<DiffOutput, Error>);
+
+#[get("/api/diff")]
+#[openapi(description = "Unified Diff between two Copies of an Entry")]
+pub async fn diff(
+    query: Query<DiffData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<DiffResponse> {
+    check_unlocked(&user, &state)?;
+    check_writer(&user, &state).await?;
+    let query = query.into_inner();
+    let date = query.date;
+    let parse = |copy: &str| {
+        copy.parse::<EntryCopy>().map_err(|e| Error::BadRequest(e.to_string()))
+    };
+    let from = parse(&query.from)?;
+    let to = parse(query.to.as_deref().unwrap_or("db"))?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let req = DiaryAppRequests::Diff {
+        date: date.into(),
+        from: from.clone(),
+        to: to.clone(),
+    };
+    if let DiaryAppOutput::Diff(diff) = req.process(&dapp).await? {
+        Ok(JsonBase::new(DiffOutput {
+            date,
+            from: from.to_string().into(),
+            to: to.to_string().into(),
+            diff,
+        })
+        .into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct JournalData {
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Journals")]
+struct JournalsResponse(JsonBase<Vec<StackString>, Error>);
+
+#[get("/api/journals")]
+#[openapi(description = "List Journals")]
+pub async fn list_journals(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<JournalsResponse> {
+    let journals = journals_body(&state).await?;
+    Ok(JsonBase::new(journals).into())
+}
+
+async fn journals_body(state: &AppState) -> HttpResult<Vec<StackString>> {
+    if let DiaryAppOutput::Lines(journals) =
+        DiaryAppRequests::ListJournals.process(&state.db).await?
+    {
+        Ok(journals)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "CreateJournalData")]
+pub struct CreateJournalData {
+    #[schema(description = "Journal Name")]
+    pub journal: StackString,
+    #[schema(description = "Every User Writes into the Same Entries, defaults to false")]
+    pub shared: Option<bool>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Create Journal", status = "CREATED")]
+struct CreateJournalResponse(JsonBase<Vec<StackString>, Error>);
+
+#[post("/api/journals")]
+#[openapi(description = "Create Journal")]
+pub async fn create_journal(
+    data: Json<CreateJournalData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<CreateJournalResponse> {
+    check_writer(&user, &state).await?;
+    let data = data.into_inner();
+    let journals = create_journal_body(data, state).await?;
+    Ok(JsonBase::new(journals).into())
+}
+
+async fn create_journal_body(
+    data: CreateJournalData,
+    state: AppState,
+) -> HttpResult<Vec<StackString>> {
+    let req = DiaryAppRequests::CreateJournal {
+        journal_name: data.journal,
+        shared: data.shared.unwrap_or(false),
+    };
+    if let DiaryAppOutput::Lines(journals) = req.process(&state.db).await? {
+        Ok(journals)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct MetadataItem {
+    #[schema(description = "Metadata Key")]
+    pub key: StackString,
+    #[schema(description = "Metadata Value")]
+    pub value: StackString,
+}
+
+fn metadata_items(metadata: &Value) -> Vec<MetadataItem> {
+    metadata
+        .as_object()
+        .map(|m| {
+            m.iter()
+                .map(|(key, value)| MetadataItem {
+                    key: key.as_str().into(),
+                    value: match value {
+                        Value::String(s) => s.as_str().into(),
+                        v => StackString::from_display(v),
+                    },
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Entry Metadata")]
+struct MetadataResponse(JsonBase<Vec<MetadataItem>, Error>);
+
+#[get("/api/metadata")]
+#[openapi(description = "Get Entry Metadata")]
+pub async fn get_metadata(
+    query: Query<EditData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<MetadataResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let metadata = metadata_body(DiaryAppRequests::GetMetadata(query.date.into()), &dapp).await?;
+    Ok(JsonBase::new(metadata).into())
+}
+
+async fn metadata_body(
+    req: DiaryAppRequests,
+    dapp: &DiaryAppActor,
+) -> HttpResult<Vec<MetadataItem>> {
+    if let DiaryAppOutput::Metadata(metadata) = req.process(dapp).await? {
+        Ok(metadata_items(&metadata))
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "MetadataData")]
+pub struct MetadataData {
+    #[schema(description = "Entry Date")]
+    pub date: DateType,
+    #[schema(description = "Metadata Key")]
+    pub key: StackString,
+    #[schema(description = "Metadata Value, the key is removed if unset")]
+    pub value: Option<StackString>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Update Metadata Response")]
+struct UpdateMetadataResponse(JsonBase<Vec<MetadataItem>, Error>);
+
+#[post("/api/metadata")]
+#[openapi(description = "Set or Remove an Entry Metadata Key")]
+pub async fn update_metadata(
+    data: Json<MetadataData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<UpdateMetadataResponse> {
+    check_writer(&user, &state).await?;
+    check_unlocked(&user, &state)?;
+    let data = data.into_inner();
+    let dapp = state
+        .db
+        .with_journal(data.journal.as_deref())
+        .with_author(&user.email);
+    let req = DiaryAppRequests::UpdateMetadata {
+        date: data.date.into(),
+        key: data.key,
+        value: data.value,
+    };
+    let metadata = metadata_body(req, &dapp).await?;
+    Ok(JsonBase::new(metadata).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct StatsOptions {
+    #[schema(description = "Numeric Metadata Key")]
+    pub key: StackString,
+    #[schema(description = "Aggregation Period (day, week, month or year)")]
+    pub period: Option<StackString>,
+    #[schema(description = "Minimum Date")]
+    pub min_date: Option<DateType>,
+    #[schema(description = "Maximum Date")]
+    pub max_date: Option<DateType>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct StatsOutput {
+    #[schema(description = "First Day of Period")]
+    period: DateType,
+    count: i64,
+    total: f64,
+    average: f64,
+    minimum: f64,
+    maximum: f64,
+}
+
+impl From<MetadataStats> for StatsOutput {
+    fn from(stats: MetadataStats) -> Self {
+        Self {
+            period: stats.period.into(),
+            count: stats.count,
+            total: stats.total,
+            average: stats.average,
+            minimum: stats.minimum,
+            maximum: stats.maximum,
+        }
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Metadata Stats")]
+struct StatsResponse(JsonBase<Vec<StatsOutput>, Error>);
+
+#[get("/api/stats")]
+#[openapi(description = "Aggregate Numeric Metadata Over Time")]
+pub async fn stats(
+    query: Query<StatsOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<StatsResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let stats = stats_body(query, state).await?;
+    Ok(JsonBase::new(stats).into())
+}
+
+async fn stats_body(query: StatsOptions, state: AppState) -> HttpResult<Vec<StatsOutput>> {
+    let period = match &query.period {
+        Some(period) => period
+            .parse()
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?,
+        None => StatsPeriod::default(),
+    };
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let req = DiaryAppRequests::MetadataStats {
+        key: query.key,
+        period,
+        min_date: query.min_date.map(Into::into),
+        max_date: query.max_date.map(Into::into),
+    };
+    if let DiaryAppOutput::Stats(stats) = req.process(&dapp).await? {
+        Ok(stats.into_iter().map(Into::into).collect())
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+#[derive(Schema, Serialize)]
+struct YearSizeOutput {
+    year: i32,
+    entries: i64,
+    characters: i64,
+    #[schema(description = "Characters Compared to the Previous Year")]
+    growth: Option<i64>,
+}
+
+#[derive(Schema, Serialize)]
+struct EntrySizeOutput {
+    date: DateType,
+    characters: i64,
+}
+
+#[derive(Schema, Serialize)]
+struct StorageOutput {
+    entries: i64,
+    characters: i64,
+    #[schema(description = "Entries and Characters per Year")]
+    years: Vec<YearSizeOutput>,
+    #[schema(description = "Longest Entries")]
+    biggest: Vec<EntrySizeOutput>,
+    #[schema(description = "Number of S3 Objects")]
+    s3_objects: usize,
+    #[schema(description = "Bytes of S3 Snapshots")]
+    s3_snapshot_bytes: i64,
+    #[schema(description = "Bytes of S3 Deltas")]
+    s3_delta_bytes: i64,
+}
+
+impl From<StorageReport> for StorageOutput {
+    fn from(report: StorageReport) -> Self {
+        let years = report
+            .years
+            .iter()
+            .zip(report.growth())
+            .map(|(year, growth)| YearSizeOutput {
+                year: year.year,
+                entries: year.entries,
+                characters: year.characters,
+                growth,
+            })
+            .collect();
+        let biggest = report
+            .biggest
+            .iter()
+            .map(|entry| EntrySizeOutput {
+                date: entry.diary_date.into(),
+                characters: entry.characters,
+            })
+            .collect();
+        Self {
+            entries: report.entries(),
+            characters: report.characters(),
+            years,
+            biggest,
+            s3_objects: report.s3.objects,
+            s3_snapshot_bytes: report.s3.snapshot_bytes,
+            s3_delta_bytes: report.s3.delta_bytes,
+        }
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Storage Stats")]
+struct StorageResponse(JsonBase<StorageOutput, Error>);
+
+#[get("/api/stats/storage")]
+#[openapi(description = "Entry Sizes per Year, Biggest Entries and S3 Storage")]
+pub async fn storage_stats(
+    query: Query<JournalData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<StorageResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::StorageReport(report) =
+        DiaryAppRequests::StorageReport.process(&dapp).await?
+    {
+        Ok(JsonBase::new(report.into()).into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct HabitsOptions {
+    #[schema(description = "Only Entries From")]
+    pub since: Option<DateType>,
+    #[schema(description = "Only Entries Until")]
+    pub until: Option<DateType>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct HabitsOutput {
+    #[schema(description = "Writes in each Hour of the Day, Local Time")]
+    hours: Vec<i64>,
+    writes: i64,
+    peak_hour: Option<usize>,
+    #[schema(description = "Average Hours from the Start of the Entry Date to the Write")]
+    average_lag_hours: Option<f64>,
+}
+
+impl From<WritingHabits> for HabitsOutput {
+    fn from(habits: WritingHabits) -> Self {
+        let peak_hour = habits.peak_hour();
+        Self {
+            hours: habits.hours,
+            writes: habits.writes,
+            peak_hour,
+            average_lag_hours: habits.average_lag_hours,
+        }
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Writing Habits")]
+struct HabitsResponse(JsonBase<HabitsOutput, Error>);
+
+#[get("/api/stats/habits")]
+#[openapi(description = "When Entries are Written, from Cache Entry Timestamps")]
+pub async fn habit_stats(
+    query: Query<HabitsOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<HabitsResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let range = DateRange::new(query.since.map(Into::into), query.until.map(Into::into));
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::Habits(habits) = DiaryAppRequests::Habits(range).process(&dapp).await? {
+        Ok(JsonBase::new(habits.into()).into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct EntriesMetaOptions {
+    #[schema(description = "First Date of the Availability Bitmap")]
+    pub since: Option<DateType>,
+    #[schema(description = "Last Date of the Availability Bitmap")]
+    pub until: Option<DateType>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct EntriesMetaOutput {
+    #[schema(description = "First Date with an Entry")]
+    min_date: Option<DateType>,
+    #[schema(description = "Last Date with an Entry")]
+    max_date: Option<DateType>,
+    #[schema(description = "Number of Entries")]
+    count: usize,
+    #[schema(description = "First Day of the Bitmap")]
+    since: Option<DateType>,
+    #[schema(description = "Last Day of the Bitmap")]
+    until: Option<DateType>,
+    #[schema(description = "One Character per Day from since to until, 1 if it has an Entry")]
+    days: StackString,
+}
+
+impl From<EntryAvailability> for EntriesMetaOutput {
+    fn from(availability: EntryAvailability) -> Self {
+        Self {
+            min_date: availability.min_date.map(Into::into),
+            max_date: availability.max_date.map(Into::into),
+            count: availability.count,
+            since: availability.since.map(Into::into),
+            until: availability.until.map(Into::into),
+            days: availability.days,
+        }
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Entry Availability")]
+struct EntriesMetaResponse(JsonBase<EntriesMetaOutput, Error>);
+
+#[get("/api/entries/meta")]
+#[openapi(description = "Range of Entry Dates and which Days in a Range have Entries")]
+pub async fn entries_meta(
+    query: Query<EntriesMetaOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<EntriesMetaResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let range = DateRange::new(query.since.map(Into::into), query.until.map(Into::into));
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    if let DiaryAppOutput::Availability(availability) =
+        DiaryAppRequests::Availability(range).process(&dapp).await?
+    {
+        Ok(JsonBase::new(availability.into()).into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct WordsOptions {
+    #[schema(description = "Year (2024) or Month (2024-03), all Entries if Missing")]
+    pub range: Option<StackString>,
+    #[schema(description = "Number of Terms (default 50)")]
+    pub limit: Option<i64>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(Schema, Serialize)]
+struct WordOutput {
+    term: StackString,
+    count: i64,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Top Words")]
+struct WordsResponse(JsonBase<Vec<WordOutput>, Error>);
+
+#[get("/api/stats/words")]
+#[openapi(description = "Most Frequent Terms Leaving Out Stopwords, for Word Clouds")]
+pub async fn word_stats(
+    query: Query<WordsOptions>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<WordsResponse> {
+    check_unlocked(&user, &state)?;
+    let query = query.into_inner();
+    let range = match &query.range {
+        Some(range) => {
+            parse_term_range(range).map_err(|e| Error::BadRequest(format_sstr!("{e}")))?
+        }
+        None => DateRange::default(),
+    };
+    let state = reader_state(&user, state).await?;
+    let dapp = state.db.with_journal(query.journal.as_deref());
+    let req = DiaryAppRequests::TopTerms {
+        range,
+        limit: query.limit.unwrap_or(TOP_TERMS),
+    };
+    if let DiaryAppOutput::Terms(terms) = req.process(&dapp).await? {
+        let terms = terms
+            .into_iter()
+            .map(|t| WordOutput {
+                term: t.term,
+                count: t.count,
+            })
+            .collect();
+        Ok(JsonBase::new(terms).into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Users")]
+struct UsersResponse(JsonBase<Vec<UserAccount>, Error>);
+
+#[get("/api/admin/users")]
+#[openapi(description = "List Users Including Disabled Ones, Owners Only")]
+pub async fn list_users(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<UsersResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let users = users_body(DiaryAppRequests::ListUsers, &state).await?;
+    Ok(JsonBase::new(users).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AddUserData {
+    #[schema(description = "Email Address")]
+    pub email: StackString,
+    #[schema(description = "Owner or Viewer (default owner)")]
+    pub role: Option<StackString>,
+    #[schema(description = "Telegram User Id")]
+    pub telegram_userid: Option<i64>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "User")]
+struct UserAccountResponse(JsonBase<UserAccount, Error>);
+
+#[post("/api/admin/users")]
+#[openapi(description = "Add or Re-enable User, Owners Only")]
+pub async fn add_user(
+    data: Json<AddUserData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<UserAccountResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let data = data.into_inner();
+    let req = DiaryAppRequests::AddUser {
+        email: data.email,
+        role: data.role.unwrap_or_else(|| OWNER_ROLE.into()),
+        telegram_userid: data.telegram_userid,
+    };
+    let user = single_user_body(req, &state).await?;
+    Ok(JsonBase::new(user).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct UserEmailData {
+    #[schema(description = "Email Address")]
+    pub email: StackString,
+}
+
+#[delete("/api/admin/users")]
+#[openapi(description = "Disable User, Owners Only")]
+pub async fn disable_user(
+    query: Query<UserEmailData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<UserAccountResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let query = query.into_inner();
+    if query.email == user.email {
+        return Err(Error::BadRequest("Can't disable yourself".into()).into());
+    }
+    let user = single_user_body(DiaryAppRequests::DisableUser(query.email), &state).await?;
+    Ok(JsonBase::new(user).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct TelegramUserData {
+    #[schema(description = "Email Address")]
+    pub email: StackString,
+    #[schema(description = "Telegram User Id, Unset to Remove")]
+    pub telegram_userid: Option<i64>,
+}
+
+#[patch("/api/admin/users")]
+#[openapi(description = "Set Telegram User Id of User, Owners Only")]
+pub async fn set_telegram_user(
+    data: Json<TelegramUserData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<UserAccountResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let data = data.into_inner();
+    let req = DiaryAppRequests::SetTelegramUser {
+        email: data.email,
+        telegram_userid: data.telegram_userid,
+    };
+    let user = single_user_body(req, &state).await?;
+    Ok(JsonBase::new(user).into())
+}
+
+async fn users_body(req: DiaryAppRequests, state: &AppState) -> HttpResult<Vec<UserAccount>> {
+    let output = req
+        .process(&state.db)
+        .await
+        .map_err(|e| match e.downcast::<UserError>() {
+            Ok(e) => Error::BadRequest(e.to_string()),
+            Err(e) => e.into(),
+        })?;
+    if let DiaryAppOutput::Users(users) = output {
+        Ok(users)
+    } else {
+        Err(Error::BadRequest("Bad output".into()))
+    }
+}
+
+async fn single_user_body(req: DiaryAppRequests, state: &AppState) -> HttpResult<UserAccount> {
+    users_body(req, state)
+        .await?
+        .pop()
+        .ok_or_else(|| Error::BadRequest("Bad output".into()))
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct MaintenanceData {
+    #[schema(description = "purge_conflicts, rebuild_terms, recompute_hashes or warm_s3_cache")]
+    pub task: StackString,
+    #[schema(description = "Purge Conflicts Older than this Many Days (default 90)")]
+    pub older_than_days: Option<i64>,
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Started Maintenance Job", status = "CREATED")]
+struct MaintenanceJobResponse(JsonBase<MaintenanceJobInfo, Error>);
+
+#[post("/api/admin/maintenance")]
+#[openapi(description = "Start a Maintenance Job in the Background, Owners Only")]
+pub async fn start_maintenance(
+    data: Json<MaintenanceData>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<MaintenanceJobResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let data = data.into_inner();
+    let task: MaintenanceTask = data
+        .task
+        .parse()
+        .map_err(|e: MaintenanceError| Error::BadRequest(e.to_string()))?;
+    if data.older_than_days.is_some_and(|days| days < 0) {
+        return Err(Error::BadRequest("older_than_days can't be negative".into()).into());
+    }
+    let dapp = state.db.with_journal(data.journal.as_deref());
+    let job = state
+        .maintenance
+        .start(&dapp, task, data.older_than_days)
+        .map_err(|e| Error::Conflict(e.to_string()))?;
+    Ok(JsonBase::new(job.into()).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct MaintenanceJobQuery {
+    #[schema(description = "Job Id, all recent jobs if unset")]
+    pub id: Option<UuidWrapper>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Maintenance Jobs")]
+struct MaintenanceJobsResponse(JsonBase<Vec<MaintenanceJobInfo>, Error>);
+
+#[get("/api/admin/maintenance")]
+#[openapi(description = "Status and Progress of Maintenance Jobs, Owners Only")]
+pub async fn list_maintenance(
+    query: Query<MaintenanceJobQuery>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<MaintenanceJobsResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let jobs = match query.into_inner().id {
+        Some(id) => {
+            let job = state
+                .maintenance
+                .get(id.into())
+                .ok_or_else(|| Error::BadRequest("Unknown maintenance job".into()))?;
+            vec![job]
+        }
+        None => state.maintenance.list(),
+    };
+    let jobs = jobs.into_iter().map(Into::into).collect();
+    Ok(JsonBase::new(jobs).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Lock")]
+struct SyncLockResponse(JsonBase<Option<SyncLockInfo>, Error>);
+
+#[get("/api/admin/sync_lock")]
+#[openapi(description = "Process Running a Sync, if any, Owners Only")]
+pub async fn sync_lock(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<SyncLockResponse> {
+    check_unlocked(&user, &state)?;
+    check_owner(&user, &state).await?;
+    let lease = SyncLease::get_by_name(SYNC_LEASE, &state.db.pool)
+        .await?
+        .map(Into::into);
+    Ok(JsonBase::new(lease).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Link Code", status = "CREATED")]
+struct LinkCodeResponse(JsonBase<LinkCode, Error>);
+
+#[post("/api/user/link_telegram")]
+#[openapi(description = "Create a One-time Code which Links the Telegram Account it is Sent From")]
+pub async fn link_telegram(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] state: AppState,
+) -> WarpResult<LinkCodeResponse> {
+    check_unlocked(&user, &state)?;
+    let req = DiaryAppRequests::LinkCode(user.email);
+    if let DiaryAppOutput::LinkCode(link_code) = req.process(&state.db).await? {
+        Ok(JsonBase::new(link_code).into())
+    } else {
+        Err(Error::BadRequest("Bad output".into()).into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct PeerEntryHash {
+    #[schema(description = "Entry Date")]
+    pub diary_date: DateType,
+    #[schema(description = "Sha256 of Entry Text")]
+    pub hash: StackString,
+    #[schema(description = "Last Modified")]
+    pub last_modified: DateTimeType,
+}
+
+impl From<EntryHash> for PeerEntryHash {
+    fn from(hash: EntryHash) -> Self {
+        Self {
+            diary_date: hash.diary_date.into(),
+            hash: hash.hash,
+            last_modified: hash.last_modified.to_offsetdatetime().into(),
+        }
+    }
+}
+
+impl From<PeerEntryHash> for EntryHash {
+    fn from(hash: PeerEntryHash) -> Self {
+        Self {
+            diary_date: hash.diary_date.into(),
+            hash: hash.hash,
+            last_modified: OffsetDateTime::from(hash.last_modified).into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct PeerEntry {
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Entry Date")]
+    pub diary_date: DateType,
+    #[schema(description = "Entry Text, Empty for Encrypted Entries")]
+    pub diary_text: StackString,
+    #[schema(description = "Last Modified")]
+    pub last_modified: DateTimeType,
+    #[schema(description = "Entry is Encrypted Client Side")]
+    #[serde(default)]
+    pub is_encrypted: bool,
+    #[schema(description = "Ciphertext")]
+    #[serde(default)]
+    pub diary_ciphertext: Option<Vec<u8>>,
+    #[schema(description = "Nonce")]
+    #[serde(default)]
+    pub diary_nonce: Option<Vec<u8>>,
+    #[schema(description = "Moved to the Trash At")]
+    #[serde(default)]
+    pub deleted_at: Option<DateTimeType>,
+    #[schema(description = "Starred")]
+    #[serde(default)]
+    pub starred: bool,
+    #[schema(description = "Metadata")]
+    #[serde(default)]
+    pub metadata: Option<Value>,
+    #[schema(description = "Hidden until the Entry Date")]
+    #[serde(default)]
+    pub scheduled: bool,
+    #[schema(description = "Visibility")]
+    #[serde(default)]
+    pub visibility: Option<StackString>,
+    #[schema(description = "Source of the Last Write")]
+    #[serde(default)]
+    pub source: Option<StackString>,
+}
+
+impl From<DiaryEntries> for PeerEntry {
+    fn from(entry: DiaryEntries) -> Self {
+        Self {
+            journal: Some(entry.journal),
+            diary_date: entry.diary_date.into(),
+            diary_text: entry.diary_text,
+            last_modified: entry.last_modified.to_offsetdatetime().into(),
+            is_encrypted: entry.is_encrypted,
+            diary_ciphertext: entry.diary_ciphertext,
+            diary_nonce: entry.diary_nonce,
+            deleted_at: entry.deleted_at.map(|d| d.to_offsetdatetime().into()),
+            starred: entry.starred,
+            metadata: Some(entry.metadata),
+            scheduled: entry.scheduled,
+            visibility: Some(entry.visibility),
+            source: entry.source,
+        }
+    }
+}
+
+/// Older peers leave out the journal and the later columns of an entry
+impl From<PeerEntry> for DiaryEntries {
+    fn from(entry: PeerEntry) -> Self {
+        Self {
+            journal: entry.journal.unwrap_or_else(|| DEFAULT_JOURNAL.into()),
+            diary_date: entry.diary_date.into(),
+            diary_text: entry.diary_text,
+            last_modified: OffsetDateTime::from(entry.last_modified).into(),
+            is_encrypted: entry.is_encrypted,
+            diary_ciphertext: entry.diary_ciphertext,
+            diary_nonce: entry.diary_nonce,
+            deleted_at: entry.deleted_at.map(|d| OffsetDateTime::from(d).into()),
+            starred: entry.starred,
+            metadata: entry.metadata.unwrap_or_else(|| Value::Object(Map::new())),
+            scheduled: entry.scheduled,
+            visibility: entry.visibility.unwrap_or_else(|| PUBLIC_VISIBILITY.into()),
+            source: entry.source,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct PeerTombstone {
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Deleted Date")]
+    pub diary_date: DateType,
+    #[schema(description = "Deleted At")]
+    pub deleted_at: DateTimeType,
+    #[schema(description = "Kept Until")]
+    pub expires_at: DateTimeType,
+}
+
+impl From<DiaryTombstone> for PeerTombstone {
+    fn from(tombstone: DiaryTombstone) -> Self {
+        Self {
+            journal: Some(tombstone.journal),
+            diary_date: tombstone.diary_date.into(),
+            deleted_at: tombstone.deleted_at.to_offsetdatetime().into(),
+            expires_at: tombstone.expires_at.to_offsetdatetime().into(),
+        }
+    }
+}
+
+impl From<PeerTombstone> for DiaryTombstone {
+    fn from(tombstone: PeerTombstone) -> Self {
+        Self {
+            journal: tombstone.journal.unwrap_or_else(|| DEFAULT_JOURNAL.into()),
+            diary_date: tombstone.diary_date.into(),
+            deleted_at: OffsetDateTime::from(tombstone.deleted_at).into(),
+            expires_at: OffsetDateTime::from(tombstone.expires_at).into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct PeerCache {
+    #[schema(description = "Cache Timestamp")]
+    pub diary_datetime: DateTimeType,
+    #[schema(description = "Cached Text")]
+    pub diary_text: StackString,
+    #[schema(description = "Journal")]
+    pub journal: StackString,
+    #[schema(description = "Client Generated Idempotency Key")]
+    pub idempotency_key: Option<UuidWrapper>,
+    #[schema(description = "Author Email")]
+    pub author: Option<StackString>,
+    #[schema(description = "Source of the Text")]
+    pub source: Option<StackString>,
+}
+
+impl From<DiaryCache> for PeerCache {
+    fn from(cache: DiaryCache) -> Self {
+        Self {
+            diary_datetime: cache.diary_datetime.to_offsetdatetime().into(),
+            diary_text: cache.diary_text,
+            journal: cache.journal,
+            idempotency_key: cache.idempotency_key.map(Into::into),
+            author: cache.author,
+            source: cache.source,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "PeerPullData")]
+pub struct PeerPullData {
+    #[schema(description = "Journal, Peers without Journals only know diary")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Hashes of every Entry Held by the Requesting Peer")]
+    pub hashes: Vec<PeerEntryHash>,
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "PeerPullOutput")]
+pub struct PeerPullOutput {
+    #[schema(description = "Cache Entries")]
+    pub cache: Vec<PeerCache>,
+    #[schema(description = "Entries the Requesting Peer is Missing or Holds an Older Version of")]
+    pub entries: Vec<PeerEntry>,
+    #[schema(description = "Hashes of every Entry Held by this Server")]
+    pub hashes: Vec<PeerEntryHash>,
+    #[schema(description = "Deletions which must not be Undone by Older Copies")]
+    #[serde(default)]
+    pub tombstones: Vec<PeerTombstone>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Peer Pull")]
+struct PeerPullResponse(JsonBase<PeerPullOutput, Error>);
+
+#[post("/api/peer/pull")]
+#[openapi(description = "Entries and Cache a Peer Server is Missing, Peer Token Required")]
+pub async fn peer_pull(
+    data: Json<PeerPullData>,
+    #[filter = "PeerUser::filter"] peer: PeerUser,
+    #[data] state: AppState,
+) -> WarpResult<PeerPullResponse> {
+    peer.verify(state.db.config.peer_token.as_deref())?;
+    let data = data.into_inner();
+    let req = PeerPullRequest {
+        journal: data.journal.unwrap_or_else(|| DEFAULT_JOURNAL.into()),
+        hashes: data.hashes.into_iter().map(Into::into).collect(),
+    };
+    let resp = handle_pull(&state.db.pool, req)
+        .await
+        .map_err(Error::from)?;
+    let output = PeerPullOutput {
+        cache: resp.cache.into_iter().map(Into::into).collect(),
+        entries: resp.entries.into_iter().map(Into::into).collect(),
+        hashes: resp.hashes.into_iter().map(Into::into).collect(),
+        tombstones: resp.tombstones.into_iter().map(Into::into).collect(),
+    };
+    Ok(JsonBase::new(output).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "PeerPushData")]
+pub struct PeerPushData {
+    #[schema(description = "Journal")]
+    pub journal: Option<StackString>,
+    #[schema(description = "Entries Changed on the Requesting Peer")]
+    pub entries: Vec<PeerEntry>,
+    #[schema(description = "Cache Entries Received in the Preceding Pull")]
+    #[serde(default)]
+    pub pulled_cache: Vec<DateTimeType>,
+    #[schema(description = "Deletions on the Requesting Peer")]
+    #[serde(default)]
+    pub tombstones: Vec<PeerTombstone>,
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "PeerPushOutput")]
+pub struct PeerPushOutput {
+    #[schema(description = "Updated Dates")]
+    pub updated: Vec<DateType>,
+    #[schema(description = "Number of Cache Entries Removed")]
+    pub cache_cleared: usize,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Peer Push")]
+struct PeerPushResponse(JsonBase<PeerPushOutput, Error>);
+
+#[post("/api/peer/push")]
+#[openapi(description = "Apply Entries Changed on a Peer Server, Peer Token Required")]
+pub async fn peer_push(
+    data: Json<PeerPushData>,
+    #[filter = "PeerUser::filter"] peer: PeerUser,
+    #[data] state: AppState,
+) -> WarpResult<PeerPushResponse> {
+    peer.verify(state.db.config.peer_token.as_deref())?;
+    let data = data.into_inner();
+    let req = PeerPushRequest {
+        journal: data.journal.unwrap_or_else(|| DEFAULT_JOURNAL.into()),
+        entries: data.entries.into_iter().map(Into::into).collect(),
+        pulled_cache: data
+            .pulled_cache
+            .into_iter()
+            .map(|d| OffsetDateTime::from(d).into())
+            .collect(),
+        tombstones: data.tombstones.into_iter().map(Into::into).collect(),
+    };
+    let dapp = state
+        .db
+        .0
+        .clone()
+        .with_journal(req.journal.clone())
+        .with_source(PEER_SOURCE);
+    let resp = handle_push(&state.db.pool, req)
+        .await
+        .map_err(Error::from)?;
+    dapp.record_activity(
+        AuditAction::PeerPush,
+        None,
+        format_sstr!(
+            "updated {} entries, cleared {} cached",
+            resp.updated.len(),
+            resp.cache_cleared
+        ),
+    )
+    .await;
+    let output = PeerPushOutput {
+        updated: resp.updated.into_iter().map(Into::into).collect(),
+        cache_cleared: resp.cache_cleared,
+    };
+    Ok(JsonBase::new(output).into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "GuestbookData")]
+pub struct GuestbookData {
+    #[schema(description = "Guestbook Token")]
+    pub token: StackString,
+    #[schema(description = "Note")]
+    pub text: StackString,
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct GuestbookOutput {
+    #[schema(description = "Cache Timestamp of the Note")]
+    pub datetime: DateTimeType,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Guestbook Note")]
+struct GuestbookResponse(JsonBase<GuestbookOutput, Error>);
+
+/// Address of the client, see [`client_addr`]
+fn remote_addr() -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Copy {
+    addr::remote()
+        .and(header::optional::<String>("x-forwarded-for"))
+        .map(|remote, forwarded_for: Option<String>| client_addr(remote, forwarded_for.as_deref()))
+}
+
+#[post("/api/guestbook")]
+#[openapi(description = "Leave a Note in the Cache with a Guestbook Token, no Login Required")]
+pub async fn guestbook(
+    data: Json<GuestbookData>,
+    #[filter = "remote_addr"] client: Option<IpAddr>,
+    #[data] state: AppState,
+) -> WarpResult<GuestbookResponse> {
+    let data = data.into_inner();
+    let client = client.ok_or_else(|| Error::BadRequest("Unknown client".into()))?;
+    let text = state
+        .guestbook
+        .submit(&data.token, client, &data.text, Instant::now())
+        .map_err(|e| match e {
+            GuestbookError::Disabled => rweb::reject::not_found(),
+            GuestbookError::RateLimited => rweb::reject::custom(Error::TooManyRequests),
+            e => rweb::reject::custom(Error::BadRequest(e.to_string())),
+        })?;
+    let cache = state.db.cache_text(text).await.map_err(Error::from)?;
+    let output = GuestbookOutput {
+        datetime: cache.diary_datetime.to_offsetdatetime().into(),
+    };
+    Ok(JsonBase::new(output).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Telegram Update Accepted", content = "html")]
+struct TelegramWebhookResponse(HtmlBase<&'static str, Error>);
+
+/// Secret Telegram sends with every webhook request
+fn telegram_secret() -> impl Filter<Extract = (Option<StackString>,), Error = Rejection> + Copy {
+    header::optional::<StackString>("x-telegram-bot-api-secret-token")
+}
+
+#[post("/api/telegram/webhook")]
+#[openapi(description = "Updates Sent by Telegram, Requires the Webhook Secret")]
+pub async fn telegram_webhook(
+    update: Json<Value>,
+    #[filter = "telegram_secret"] secret: Option<StackString>,
+    #[data] state: AppState,
+) -> WarpResult<TelegramWebhookResponse> {
+    let telegram = state.telegram.ok_or_else(rweb::reject::not_found)?;
+    if !telegram.verify(secret.as_deref()) {
+        return Err(Error::Forbidden.into());
+    }
+    let update: Update = serde_json::from_value(update.into_inner())
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    telegram.dispatch(update).await.map_err(Error::from)?;
+    Ok(HtmlBase::new("").into())
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+#[schema(component = "KioskOptions")]
+pub struct KioskOptions {
+    #[schema(description = "Kiosk Token, for Devices which can't Set an Authorization Header")]
+    pub token: Option<StackString>,
+    #[schema(description = "html (default) or text")]
+    pub format: Option<StackString>,
+}
+
+/// Today's entry for a kiosk device, the token may be in the url so the
+/// reply is kept out of caches and referers
+pub struct KioskResponse {
+    body: String,
+    content_type: &'static str,
+}
+
+impl Reply for KioskResponse {
+    fn into_response(self) -> rweb::reply::Response {
+        let reply = rweb::reply::with_header(self.body, CONTENT_TYPE, self.content_type);
+        let reply = rweb::reply::with_header(reply, CACHE_CONTROL, "no-store");
+        rweb::reply::with_header(reply, REFERRER_POLICY, "no-referrer").into_response()
+    }
+}
+
+impl Entity for KioskResponse {
+    fn type_name() -> Cow<'static, str> {
+        String::type_name()
+    }
+    fn describe(comp_d: &mut ComponentDescriptor) -> ComponentOrInlineSchema {
+        String::describe(comp_d)
+    }
+}
+
+impl ResponseEntity for KioskResponse {
+    fn describe_responses(comp_d: &mut ComponentDescriptor) -> Responses {
+        let mut map = Error::describe_responses(comp_d);
+        map.insert(
+            Cow::Borrowed("200"),
+            Response {
+                description: Cow::Borrowed("Today's Entry as text/plain or text/html"),
+                ..Response::default()
+            },
+        );
+        map
+    }
+}
+
+fn authorization() -> impl Filter<Extract = (Option<StackString>,), Error = Rejection> + Copy {
+    header::optional::<StackString>("authorization")
+}
+
+#[get("/api/today/plain")]
+#[openapi(description = "Today's Entry for E-ink Dashboards, Kiosk Token Required")]
+pub async fn kiosk(
+    query: Query<KioskOptions>,
+    #[filter = "authorization"] auth: Option<StackString>,
+    #[data] state: AppState,
+) -> WarpResult<KioskResponse> {
+    let kiosk = &state.kiosk;
+    if !kiosk.is_enabled() {
+        return Err(rweb::reject::not_found());
+    }
+    let query = query.into_inner();
+    let format: KioskFormat = match &query.format {
+        Some(format) => format
+            .parse()
+            .map_err(|e: anyhow::Error| Error::BadRequest(e.to_string()))?,
+        None => KioskFormat::default(),
+    };
+    let token = auth
+        .as_deref()
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .or(query.token.as_deref())
+        .unwrap_or_default();
+    let device = kiosk.device(token).ok_or(Error::Forbidden)?;
+    debug!("kiosk view for {device}");
+    let dapp = state
+        .db
+        .with_journal(Some(kiosk.journal()))
+        .with_private_hidden(true);
+    let (today, entry) = dapp.get_today().await.map_err(Error::from)?;
+    let locale = state.db.config.default_locale;
+    let text = entry.map(|entry| {
+        if entry.is_encrypted {
+            locale.text(Message::EncryptedEntry).into()
+        } else {
+            entry.diary_text
+        }
+    });
+    let prompt = kiosk.prompt(today);
+    let (body, content_type) = match format {
+        KioskFormat::Text => (
+            render_text(today, text.as_deref(), prompt, locale).into(),
+            "text/plain; charset=utf-8",
+        ),
+        KioskFormat::Html => (
+            kiosk_body(today, text, prompt.into(), locale)?,
+            "text/html; charset=utf-8",
+        ),
+    };
+    Ok(KioskResponse { body, content_type })
+}
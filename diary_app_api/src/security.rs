@@ -0,0 +1,230 @@
+use rweb::{
+    http::{
+        header::{
+            HeaderMap, HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD,
+            CONTENT_SECURITY_POLICY, ORIGIN, REFERRER_POLICY, VARY, X_CONTENT_TYPE_OPTIONS,
+            X_FRAME_OPTIONS,
+        },
+        Method, StatusCode,
+    },
+    hyper::{Body, Request, Response},
+};
+use stack_string::StackString;
+use std::collections::HashSet;
+
+use diary_app_lib::config::ConfigInner;
+
+/// The web UI uses inline scripts, styles and event handlers, everything
+/// else has to come from the app itself
+pub const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src \
+                               'self' 'unsafe-inline'; img-src 'self' data:; frame-ancestors \
+                               'none'; base-uri 'self'; form-action 'self'";
+
+const ALLOWED_METHODS: &str = "GET, POST, PATCH, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str = "content-type, authorization, api-version";
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+/// CORS for the origins in `cors_origins`, with credentials so the auth
+/// cookie is sent, plus security headers on every response
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    origins: HashSet<StackString>,
+    csp: HeaderValue,
+}
+
+impl SecurityHeaders {
+    #[must_use]
+    pub fn from_config(config: &ConfigInner) -> Self {
+        let origins = config
+            .cors_origins
+            .as_deref()
+            .map(|origins| {
+                origins
+                    .split(',')
+                    .map(|o| o.trim().trim_end_matches('/'))
+                    .filter(|o| !o.is_empty())
+                    .map(Into::into)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let csp = config
+            .content_security_policy
+            .as_deref()
+            .and_then(|csp| HeaderValue::from_str(csp).ok())
+            .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_CSP));
+        Self { origins, csp }
+    }
+
+    fn allowed_origin<'a>(&self, headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+        headers
+            .get(ORIGIN)
+            .filter(|o| o.to_str().is_ok_and(|o| self.origins.contains(o)))
+    }
+
+    /// Answer for a CORS preflight, `None` if `req` isn't one
+    #[must_use]
+    pub fn preflight(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        if req.method() != Method::OPTIONS
+            || !req.headers().contains_key(ORIGIN)
+            || !req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return None;
+        }
+        let mut resp = Response::new(Body::empty());
+        if let Some(origin) = self.allowed_origin(req.headers()) {
+            *resp.status_mut() = StatusCode::NO_CONTENT;
+            let headers = resp.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static(ALLOWED_METHODS),
+            );
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_static(ALLOWED_HEADERS),
+            );
+            headers.insert(
+                ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from_static(PREFLIGHT_MAX_AGE),
+            );
+            headers.insert(VARY, HeaderValue::from_static("origin"));
+        } else {
+            *resp.status_mut() = StatusCode::FORBIDDEN;
+        }
+        self.apply_security(resp.headers_mut());
+        Some(resp)
+    }
+
+    /// Add the headers to the response to a request with `request_headers`,
+    /// headers set by the route (e.g. the referrer policy of the kiosk
+    /// view) are kept
+    pub fn apply(&self, request_headers: &HeaderMap, resp: &mut Response<Body>) {
+        let headers = resp.headers_mut();
+        if let Some(origin) = self.allowed_origin(request_headers) {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if !self.origins.is_empty() {
+            headers.append(VARY, HeaderValue::from_static("origin"));
+        }
+        self.apply_security(headers);
+    }
+
+    fn apply_security(&self, headers: &mut HeaderMap) {
+        let defaults: [(HeaderName, HeaderValue); 4] = [
+            (CONTENT_SECURITY_POLICY, self.csp.clone()),
+            (X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+            (REFERRER_POLICY, HeaderValue::from_static("same-origin")),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ];
+        for (name, value) in defaults {
+            headers.entry(name).or_insert(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rweb::{
+        http::{
+            header::{
+                HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD,
+                CONTENT_SECURITY_POLICY, ORIGIN, REFERRER_POLICY, X_FRAME_OPTIONS,
+            },
+            Method, StatusCode,
+        },
+        hyper::{Body, Request, Response},
+    };
+
+    use diary_app_lib::config::ConfigInner;
+
+    use crate::security::{SecurityHeaders, DEFAULT_CSP};
+
+    fn security() -> SecurityHeaders {
+        let config = ConfigInner {
+            cors_origins: Some("https://app.example.com/, https://other.example.com".into()),
+            ..ConfigInner::default()
+        };
+        SecurityHeaders::from_config(&config)
+    }
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/search")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_preflight() {
+        let security = security();
+        let resp = security
+            .preflight(&preflight("https://app.example.com"))
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            resp.headers()
+                .get(ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .unwrap(),
+            "true"
+        );
+
+        let resp = security
+            .preflight(&preflight("https://evil.example.com"))
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let req = Request::builder()
+            .uri("/api/search")
+            .body(Body::empty())
+            .unwrap();
+        assert!(security.preflight(&req).is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let security = security();
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ORIGIN, HeaderValue::from_static("https://evil.example.com"));
+        let mut resp = Response::new(Body::empty());
+        resp.headers_mut()
+            .insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+        security.apply(&request_headers, &mut resp);
+        assert!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert_eq!(resp.headers().get(X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert_eq!(
+            resp.headers().get(CONTENT_SECURITY_POLICY).unwrap(),
+            DEFAULT_CSP
+        );
+        assert_eq!(resp.headers().get(REFERRER_POLICY).unwrap(), "no-referrer");
+
+        request_headers.insert(
+            ORIGIN,
+            HeaderValue::from_static("https://other.example.com"),
+        );
+        let mut resp = Response::new(Body::empty());
+        security.apply(&request_headers, &mut resp);
+        assert_eq!(
+            resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://other.example.com"
+        );
+    }
+}
@@ -0,0 +1,712 @@
+!function() {
+    gotoEntries( 0 );
+    if ('serviceWorker' in navigator) {
+        navigator.serviceWorker.register('../api/sw.js');
+    }
+    flushQueue();
+    watchChanges();
+}();
+window.addEventListener('online', flushQueue);
+var autosave_timeout = null;
+var encryption_key = null;
+// whether a plaintext entry is encrypted on save, asked once per edit
+var encrypt_choice = null;
+var original_hash = null;
+var unsaved_changes = false;
+var conflict_view = 'stacked';
+var displayed_date = null;
+var navigation_timeout = null;
+window.addEventListener('beforeunload', function(e) {
+    if (unsaved_changes) {
+        e.preventDefault();
+        e.returnValue = '';
+    }
+});
+document.addEventListener('keydown', function(e) {
+    let editor = document.getElementById('diary_editor_form');
+    if (!editor || !editor.dataset.date) {
+        return;
+    }
+    if ((e.ctrlKey || e.metaKey) && e.key === 's') {
+        e.preventDefault();
+        submitFormData(editor.dataset.date);
+    } else if (e.key === 'Escape') {
+        e.preventDefault();
+        cancelEdit(editor.dataset.date);
+    }
+});
+function updateMainArticle( url , status_message="done", method="GET", nav_update=null ) {
+    displayed_date = null;
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.onload = function f() {
+        if (xmlhttp.status == 403) {
+            unlockDiary(() => updateMainArticle(url, status_message, method, nav_update));
+            return;
+        }
+        document.getElementById("diary_status").innerHTML = status_message;
+        document.getElementById("main_article").innerHTML = xmlhttp.responseText;
+        addTabEventHandler();
+        if (nav_update) {
+            nav_update()
+        } else {
+            gotoEntries(0);
+        }
+        decryptEntry().then(trackEditorChanges);
+    }
+    xmlhttp.open(method, journalUrl(url), true);
+    xmlhttp.send(null);
+}
+function currentJournal() {
+    let journal = document.getElementById('journal');
+    return journal ? journal.value : null;
+}
+function journalUrl( url ) {
+    let journal = currentJournal();
+    if (!journal) {
+        return url;
+    }
+    let separator = url.includes('?') ? '&' : '?';
+    return url + separator + 'journal=' + encodeURIComponent(journal);
+}
+function switchJournal() {
+    location.replace(journalUrl('../api/index.html'));
+}
+function createJournal() {
+    let name = prompt("New journal name");
+    if (!name) {
+        return;
+    }
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', '../api/journals', true);
+    xmlhttp.onload = function see_result() {
+        if (xmlhttp.status == 201) {
+            location.replace('../api/index.html?journal=' + encodeURIComponent(name));
+        } else {
+            document.getElementById("diary_status").innerHTML = "invalid journal name";
+        }
+    }
+    xmlhttp.setRequestHeader('Content-Type', 'application/json');
+    xmlhttp.send(JSON.stringify({'journal': name}));
+}
+function addComment( date ) {
+    let text = prompt("Comment on " + date);
+    if (!text) {
+        return;
+    }
+    let data = {'date': date, 'text': text};
+    let journal = currentJournal();
+    if (journal) {
+        data['journal'] = journal;
+    }
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', '../api/comments', true);
+    xmlhttp.onload = function see_result() {
+        if (xmlhttp.status == 201) {
+            switchToDate( date );
+        } else {
+            document.getElementById("diary_status").innerHTML = "failed to add comment";
+        }
+    }
+    xmlhttp.setRequestHeader('Content-Type', 'application/json');
+    xmlhttp.send(JSON.stringify(data));
+}
+function openQueue() {
+    return new Promise(function(resolve, reject) {
+        let request = indexedDB.open('diary_queue', 1);
+        request.onupgradeneeded = () => request.result.createObjectStore('inserts', {'keyPath': 'idempotency_key'});
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    });
+}
+async function queueInsert( text ) {
+    let db = await openQueue();
+    let item = {
+        'idempotency_key': crypto.randomUUID(),
+        'text': text,
+        'datetime': new Date().toISOString(),
+        'journal': currentJournal(),
+    };
+    await new Promise(function(resolve, reject) {
+        let tx = db.transaction('inserts', 'readwrite');
+        tx.objectStore('inserts').put(item);
+        tx.oncomplete = resolve;
+        tx.onerror = () => reject(tx.error);
+    });
+    document.getElementById("diary_status").innerHTML = "queued offline";
+    flushQueue();
+}
+async function flushQueue() {
+    if (!navigator.onLine) {
+        return;
+    }
+    let db = await openQueue();
+    let items = await new Promise(function(resolve, reject) {
+        let request = db.transaction('inserts').objectStore('inserts').getAll();
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    });
+    let journals = new Map();
+    for (let item of items) {
+        if (!journals.has(item.journal)) {
+            journals.set(item.journal, []);
+        }
+        journals.get(item.journal).push(item);
+    }
+    for (let [journal, queued] of journals) {
+        let response;
+        try {
+            response = await fetch('../api/insert_batch', {
+                'method': 'POST',
+                'headers': {'Content-Type': 'application/json'},
+                'body': JSON.stringify({'journal': journal, 'items': queued}),
+            });
+        } catch (e) {
+            return;
+        }
+        if (response.status != 201) {
+            return;
+        }
+        let results = await response.json();
+        let store = db.transaction('inserts', 'readwrite').objectStore('inserts');
+        results.forEach(function(result, idx) {
+            if (result.datetime) {
+                store.delete(queued[idx].idempotency_key);
+            }
+        });
+        document.getElementById("diary_status").innerHTML = `sent ${results.length} queued`;
+    }
+}
+function insertNote() {
+    let text = prompt("Note");
+    if (!text) {
+        return;
+    }
+    queueInsert(text);
+}
+function isCompact() {
+    return window.matchMedia('(max-width: 600px)').matches;
+}
+async function sha256Hex( text ) {
+    let digest = await crypto.subtle.digest('SHA-256', new TextEncoder().encode(text));
+    return Array.from(new Uint8Array(digest)).map(b => b.toString(16).padStart(2, '0')).join('');
+}
+async function trackEditorChanges() {
+    unsaved_changes = false;
+    original_hash = null;
+    encrypt_choice = null;
+    let editor = document.getElementById('diary_editor_form');
+    if (!editor || !editor.dataset.date) {
+        return;
+    }
+    // the server only knows the hash of plaintext entries
+    original_hash = editor.dataset.hash ? editor.dataset.hash : await sha256Hex(editor.value);
+    editor.addEventListener('input', async function() {
+        unsaved_changes = (await sha256Hex(editor.value)) != original_hash;
+    });
+}
+function discardChanges() {
+    if (unsaved_changes && !confirm("Discard unsaved changes?")) {
+        return false;
+    }
+    unsaved_changes = false;
+    return true;
+}
+function cancelEdit( date ) {
+    if (!discardChanges()) {
+        return;
+    }
+    switchToDisplay( date );
+}
+function switchToDate( date ) {
+    if (!discardChanges()) {
+        return;
+    }
+    if (autosave_timeout) {
+        clearInterval(autosave_timeout);
+    }
+    updateMainArticle(`../api/display?date=${date}`, status_message=date)
+    displayed_date = date;
+}
+function listConflicts( date ) {
+    let url = '../api/list_conflicts?date=' + date;
+    updateNavigation(url);
+}
+function showConflict( date, datetime ) {
+    let url = '../api/show_conflict?date=' + date + '&datetime=' + datetime + '&view=' + conflict_view;
+    updateMainArticle(url, status_message=date, method="GET", nav_update=() => listConflicts(date), )
+}
+function showConflictView( date, datetime, view ) {
+    conflict_view = view;
+    showConflict( date, datetime );
+}
+function cleanConflicts(date) {
+    let url = journalUrl('../api/remove_conflict?date=' + date);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('DELETE', url, true);
+    xmlhttp.onload = function see_result() {
+        switchToDate( date )
+    }
+    xmlhttp.send(null);
+}
+function removeConflict( date, datetime ) {
+    let url = journalUrl('../api/remove_conflict?datetime=' + datetime);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('DELETE', url, true);
+    xmlhttp.onload = function see_result() {
+        switchToDate( date )
+    }
+    xmlhttp.send(null);
+}
+function commitConflict( date, datetime ) {
+    let url = journalUrl('../api/commit_conflict?datetime=' + datetime);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', url, true);
+    xmlhttp.onload = function see_result() {
+        removeConflict( date, datetime );
+    }
+    xmlhttp.send(null);
+}
+function unlockDiary( on_unlock ) {
+    let code = prompt("Diary is locked, enter PIN or TOTP code");
+    if (!code) {
+        document.getElementById("diary_status").innerHTML = "locked";
+        return;
+    }
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', '../api/unlock', true);
+    xmlhttp.onload = function see_result() {
+        if (xmlhttp.status == 200) {
+            on_unlock();
+        } else {
+            document.getElementById("diary_status").innerHTML = "invalid code";
+        }
+    }
+    xmlhttp.setRequestHeader('Content-Type', 'application/json');
+    xmlhttp.send(JSON.stringify({'code': code}));
+}
+function lockDiary() {
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', '../api/lock', true);
+    xmlhttp.onload = function see_result() {
+        document.getElementById("diary_status").innerHTML = "locked";
+        document.getElementById("main_article").innerHTML = "";
+    }
+    xmlhttp.send(null);
+}
+function deleteEntry( date ) {
+    if (!confirm(`Move entry for ${date} to the trash?`)) {
+        return;
+    }
+    let url = journalUrl('../api/entry?confirm=true&date=' + date);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('DELETE', url, true);
+    xmlhttp.onload = function see_result() {
+        document.getElementById("diary_status").innerHTML = `deleted ${date}`;
+        document.getElementById("main_article").innerHTML = "";
+        gotoEntries(0);
+    }
+    xmlhttp.send(null);
+}
+function toggleStar( date ) {
+    let url = journalUrl('../api/star?date=' + date);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', url, true);
+    xmlhttp.onload = function see_result() {
+        let starred = JSON.parse(xmlhttp.responseText).starred;
+        document.getElementById("star_button").value = starred ? "Unstar" : "Star";
+        document.getElementById("diary_status").innerHTML = starred ? `starred ${date}` : `unstarred ${date}`;
+    }
+    xmlhttp.send(null);
+}
+function togglePrivate( date ) {
+    let url = journalUrl('../api/private?date=' + date);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', url, true);
+    xmlhttp.onload = function see_result() {
+        let isPrivate = JSON.parse(xmlhttp.responseText).private;
+        document.getElementById("private_button").value = isPrivate ? "Unlock" : "Lock";
+        document.getElementById("diary_status").innerHTML = isPrivate ? `locked ${date}` : `unlocked ${date}`;
+    }
+    xmlhttp.send(null);
+}
+function listTrash() {
+    updateMainArticle('../api/trash', status_message="trash");
+}
+function restoreEntry( date ) {
+    let url = journalUrl('../api/trash/restore?date=' + date);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', url, true);
+    xmlhttp.onload = function see_result() {
+        switchToDate( date );
+    }
+    xmlhttp.send(null);
+}
+function purgeEntry( date ) {
+    if (!confirm(`Permanently remove entry for ${date}?`)) {
+        return;
+    }
+    let url = journalUrl('../api/trash?date=' + date);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('DELETE', url, true);
+    xmlhttp.onload = function see_result() {
+        listTrash();
+    }
+    xmlhttp.send(null);
+}
+function searchDiary() {
+    let text_form = document.getElementById( 'search_text' );
+    let url = encodeURI('../api/search?text=' + text_form.value);
+    updateMainArticle(url, status_message=text_form.value, method="GET", nav_update=() => {
+        gotoEntries(0);
+        searchEncrypted(text_form.value);
+    });
+}
+async function searchEncrypted( text ) {
+    if (!encryption_key) {
+        return;
+    }
+    let response = await fetch(journalUrl('../api/encrypted'));
+    if (!response.ok) {
+        return;
+    }
+    let entries = await response.json();
+    let container = document.getElementById('search_results');
+    if (!container) {
+        return;
+    }
+    for (let entry of entries) {
+        let plaintext;
+        try {
+            plaintext = await decryptText(entry.ciphertext, entry.nonce);
+        } catch (e) {
+            continue;
+        }
+        if (!plaintext.includes(text)) {
+            continue;
+        }
+        let line = plaintext.split('\n').findIndex(l => l.includes(text)) + 1;
+        let result = document.createElement('div');
+        result.className = 'search-result';
+        let link = document.createElement('a');
+        link.className = 'search-link';
+        link.href = `../api/display?date=${entry.date}#L${line}`;
+        link.onclick = () => showLine(entry.date, line);
+        link.textContent = entry.date;
+        let body = document.createElement('pre');
+        body.className = 'search-text';
+        body.textContent = plaintext;
+        result.append(link, body);
+        container.append(result);
+    }
+}
+function searchDate() {
+    let text_form = document.getElementById( 'search_date' );
+    switchToDate( text_form.value );
+}
+function syncDiary() {
+    let progress = new EventSource('../api/sync_progress');
+    progress.addEventListener('progress', function(e) {
+        let stages = JSON.parse(e.data).stages.map(s => `${s.stage} ${s.processed}/${s.total}`);
+        document.getElementById("main_article").innerHTML = `syncing... ${stages.join(', ')}`;
+    });
+    updateMainArticle('../api/sync', status_message="done", method="POST", nav_update=() => {
+        progress.close();
+        gotoEntries(0);
+    });
+    document.getElementById("main_article").innerHTML = "syncing..."
+}
+// the most recent listed entries are fetched ahead, the browser keeps them
+// and opening one only costs a 304 while it's unchanged
+function prefetchEntries() {
+    let buttons = document.querySelectorAll('#navigation [onclick^="switchToDate"]');
+    for (let button of Array.from(buttons).slice(0, 3)) {
+        let date = button.getAttribute('onclick').match(/\d{4}-\d{2}-\d{2}/);
+        if (date) {
+            fetch(journalUrl(`../api/display?date=${date[0]}`), {priority: 'low'});
+        }
+    }
+}
+// entries written by the cli, the bots or another server show up without
+// reloading the page
+function watchChanges() {
+    let url = new URL('../api/changes', location.href);
+    url.protocol = url.protocol == 'https:' ? 'wss:' : 'ws:';
+    let socket = new WebSocket(url);
+    socket.onmessage = function(e) {
+        let change = JSON.parse(e.data);
+        if (change.journal != (currentJournal() || 'diary')) {
+            return;
+        }
+        if (change.diary_date == displayed_date) {
+            if (document.getElementById('diary_editor_form')) {
+                document.getElementById("diary_status").innerHTML = "changed elsewhere";
+                return;
+            }
+            // old entries may be kept by the browser for a day, refresh its copy first
+            let date = displayed_date;
+            fetch(journalUrl(`../api/display?date=${date}`), {cache: 'reload'})
+                .finally(() => switchToDate(date));
+            return;
+        }
+        if (navigation_timeout) {
+            clearTimeout(navigation_timeout);
+        }
+        navigation_timeout = setTimeout(() => gotoEntries(0), 1000);
+    };
+    socket.onclose = () => setTimeout(watchChanges, 5000);
+}
+function updateNavigation( url ) {
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.onload = function f() {
+        document.getElementById("navigation").innerHTML = xmlhttp.responseText;
+        prefetchEntries();
+    }
+    xmlhttp.open("GET", journalUrl(url), true);
+    xmlhttp.send(null);
+}
+function gotoEntries( increment, cursor=null ) {
+    increment = Number(increment);
+    let start = Number(document.getElementById('navigation').getAttribute('start'));
+    start = start + increment;
+    let url = '../api/list';
+    if (cursor) {
+        url = url + '?cursor=' + cursor + '&limit=10';
+        document.getElementById('navigation').setAttribute('start', start);
+    } else if (start > 0) {
+        url = url + '?start=' + start + '&limit=10';
+        document.getElementById('navigation').setAttribute('start', start);
+    } else {
+        url = url + '?group=month';
+        document.getElementById('navigation').setAttribute('start', 0);
+    }
+    if (isCompact()) {
+        url = url + '&compact=true';
+    }
+    updateNavigation(url);
+}
+// the dates of a group are only fetched once it is opened
+function loadGroup( group, min_date, max_date ) {
+    let dates = group.querySelector('.date-group-dates');
+    if (!group.open || dates.childElementCount > 0) {
+        return;
+    }
+    let url = `../api/list?min_date=${min_date}&max_date=${max_date}`;
+    if (isCompact()) {
+        url = url + '&compact=true';
+    }
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.onload = function f() {
+        dates.innerHTML = xmlhttp.responseText;
+    }
+    xmlhttp.open("GET", journalUrl(url), true);
+    xmlhttp.send(null);
+}
+function switchToList() {
+    location.replace(journalUrl('../api/index.html'));
+}
+async function saveEntry( date, onload=null, ask=true ) {
+    let url = '../api/replace';
+    let text = document.getElementById( 'diary_editor_form' );
+    let is_encrypted = document.getElementById('encrypted_entry') != null;
+    if (is_encrypted && (!encryption_key || text.readOnly)) {
+        document.getElementById("diary_status").innerHTML = "load key to save";
+        return;
+    }
+    // plaintext entries are only encrypted when asked to
+    if (ask && !is_encrypted && encryption_key && encrypt_choice === null) {
+        encrypt_choice = confirm("Encrypt this entry?");
+    }
+    let data = {'date': date, 'text': text.value};
+    if (is_encrypted || (encryption_key && encrypt_choice)) {
+        url = '../api/encrypted';
+        data = await encryptText(text.value);
+        data['date'] = date;
+    }
+    data['journal'] = currentJournal();
+    let saved_hash = await sha256Hex(text.value);
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('POST', url, true);
+    xmlhttp.onload = function see_result() {
+        if (xmlhttp.status == 201) {
+            original_hash = saved_hash;
+            unsaved_changes = false;
+            let warnings = JSON.parse(xmlhttp.responseText).warnings;
+            if (warnings && warnings.length > 0) {
+                document.getElementById("diary_status").innerHTML =
+                    `saved ${date}, ${warnings.join(', ')}`;
+            } else {
+                document.getElementById("diary_status").innerHTML = `saved ${date}`;
+            }
+        } else {
+            document.getElementById("diary_status").innerHTML = `failed to save ${date}`;
+        }
+        if (onload) {
+            onload();
+        }
+    }
+    xmlhttp.setRequestHeader('Content-Type', 'application/json');
+    xmlhttp.send(JSON.stringify(data));
+}
+function submitFormData( date ) {
+    saveEntry(date, onload=() => switchToDate( date ));
+}
+function switchToDisplay( date ) {
+    switchToDate( date );
+}
+function autoSave( date ) {
+    // saving an untouched editor could only overwrite the entry
+    if (unsaved_changes) {
+        saveEntry(date, onload=null, ask=false);
+    }
+}
+function toBase64( bytes ) {
+    return btoa(String.fromCharCode(...new Uint8Array(bytes)));
+}
+function fromBase64( text ) {
+    return Uint8Array.from(atob(text), c => c.charCodeAt(0));
+}
+async function loadEncryptionKey() {
+    let passphrase = prompt("Encryption passphrase");
+    if (!passphrase) {
+        encryption_key = null;
+        document.getElementById("key_button").value = "Key";
+        return;
+    }
+    let encoder = new TextEncoder();
+    let material = await crypto.subtle.importKey(
+        'raw', encoder.encode(passphrase), 'PBKDF2', false, ['deriveKey']
+    );
+    encryption_key = await crypto.subtle.deriveKey(
+        {'name': 'PBKDF2', 'salt': encoder.encode('diary_app_rust'), 'iterations': 250000, 'hash': 'SHA-256'},
+        material,
+        {'name': 'AES-GCM', 'length': 256},
+        false,
+        ['encrypt', 'decrypt'],
+    );
+    document.getElementById("key_button").value = "Key (loaded)";
+    decryptEntry().then(trackEditorChanges);
+}
+async function encryptText( text ) {
+    let nonce = crypto.getRandomValues(new Uint8Array(12));
+    let ciphertext = await crypto.subtle.encrypt(
+        {'name': 'AES-GCM', 'iv': nonce}, encryption_key, new TextEncoder().encode(text)
+    );
+    return {'ciphertext': toBase64(ciphertext), 'nonce': toBase64(nonce)};
+}
+async function decryptText( ciphertext, nonce ) {
+    let plaintext = await crypto.subtle.decrypt(
+        {'name': 'AES-GCM', 'iv': fromBase64(nonce)}, encryption_key, fromBase64(ciphertext)
+    );
+    return new TextDecoder().decode(plaintext);
+}
+function renderLines( container, text ) {
+    container.replaceChildren(...text.split('\n').map((line, idx) => {
+        let row = document.createElement('div');
+        row.id = `L${idx + 1}`;
+        row.className = 'diary-line';
+        let number = document.createElement('a');
+        number.className = 'line-number';
+        number.href = `#L${idx + 1}`;
+        number.textContent = idx + 1;
+        let content = document.createElement('span');
+        content.className = 'line-text';
+        content.textContent = line;
+        row.append(number, content);
+        return row;
+    }));
+}
+async function decryptEntry() {
+    let entry = document.getElementById('encrypted_entry');
+    let textarea = document.getElementById('diary_editor_form');
+    let display = document.getElementById('diary_display');
+    if (!entry || !(textarea || display)) {
+        return;
+    }
+    let text = "Encrypted entry, load key to view";
+    let decrypted = false;
+    if (encryption_key) {
+        try {
+            text = await decryptText(entry.dataset.ciphertext, entry.dataset.nonce);
+            decrypted = true;
+        } catch (e) {
+            // a wrong key must not be used to save over the entry
+            encryption_key = null;
+            document.getElementById("key_button").value = "Key";
+            text = "Failed to decrypt entry";
+        }
+    }
+    if (textarea) {
+        textarea.value = text;
+        textarea.readOnly = !decrypted;
+    } else {
+        renderLines(display, text);
+    }
+}
+function switchToEditor( date ) {
+    let url = `../api/edit?date=${date}`;
+    updateMainArticle(url, status_message=date);
+    autosave_timeout = setInterval(function() {
+        autoSave(date)
+    }, 60000);
+}
+function showLine( date, line ) {
+    if (!discardChanges()) {
+        return false;
+    }
+    if (autosave_timeout) {
+        clearInterval(autosave_timeout);
+    }
+    updateMainArticle(`../api/display?date=${date}`, status_message=date, method="GET", nav_update=() => {
+        gotoEntries(0);
+        location.hash = `L${line}`;
+    });
+    return false;
+}
+function updateConflictAdd( id, date, datetime ) {
+    let url = '../api/update_conflict?id=' + id + '&diff_type=add';
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('PATCH', url, true);
+    xmlhttp.onload = function see_result() {
+        showConflict( date, datetime );
+    }
+    xmlhttp.send(null);
+}
+function updateConflictRem( id, date, datetime ) {
+    let url = '../api/update_conflict?id=' + id + '&diff_type=rem';
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.open('PATCH', url, true);
+    xmlhttp.onload = function see_result() {
+        showConflict( date, datetime );
+    }
+    xmlhttp.send(null);
+}
+function addTabEventHandler() {
+    let editor_form = document.getElementById( 'diary_editor_form' );
+    if (editor_form) {
+        editor_form.addEventListener(
+            'keydown',
+            function(e) {
+                if(e.keyCode === 9) { // tab was pressed
+                    // get caret position/selection
+                    let start = this.selectionStart;
+                    let end = this.selectionEnd;
+
+                    let target = e.target;
+                    let value = target.value;
+
+                    // set textarea value to: text before caret + tab + text after caret
+                    target.value = value.substring(0, start)
+                                + "    "
+                                + value.substring(end);
+
+                    // put caret at right position again (add one for the tab)
+                    this.selectionStart = this.selectionEnd = start + 4;
+
+                    // prevent the focus lose
+                    e.preventDefault();
+                }
+            },
+            false
+        );
+    }
+}
@@ -0,0 +1,29 @@
+const SHELL_CACHE = 'diary-shell-v1';
+const SHELL_URL = '/api/index.html';
+
+self.addEventListener('install', function(e) {
+    e.waitUntil(caches.open(SHELL_CACHE).then(cache => cache.add(SHELL_URL)));
+    self.skipWaiting();
+});
+self.addEventListener('activate', function(e) {
+    e.waitUntil(caches.keys().then(keys => Promise.all(
+        keys.filter(key => key != SHELL_CACHE).map(key => caches.delete(key))
+    )));
+    self.clients.claim();
+});
+// network first for the page itself so edits on other devices show up,
+// the cached shell is only used while offline
+self.addEventListener('fetch', function(e) {
+    if (e.request.mode != 'navigate') {
+        return;
+    }
+    e.respondWith(
+        fetch(e.request).then(function(response) {
+            if (response.ok) {
+                let copy = response.clone();
+                caches.open(SHELL_CACHE).then(cache => cache.put(SHELL_URL, copy));
+            }
+            return response;
+        }).catch(() => caches.match(SHELL_URL))
+    );
+});
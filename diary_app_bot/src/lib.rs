@@ -5,4 +5,5 @@
 #![allow(clippy::cast_possible_truncation)]
 
 pub mod failure_count;
+pub mod telegram_api;
 pub mod telegram_bot;
@@ -0,0 +1,155 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use log::error;
+use teloxide::{
+    payloads::{GetUpdatesSetters, SendMessageSetters},
+    requests::Requester,
+    types::{
+        BotCommand as TelegramCommand, ChatId, InlineKeyboardButton, InlineKeyboardMarkup,
+        MessageId, ReplyParameters, Update,
+    },
+    Bot,
+};
+use url::Url;
+
+use diary_app_lib::{
+    bot_core::{BotReply, SLASH_COMMANDS},
+    entry_limits::split_text,
+};
+
+/// Seconds a `getUpdates` request waits for new updates
+const POLL_TIMEOUT: u32 = 30;
+/// Characters telegram accepts in a message
+const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// Where a reply goes, the message it answers if any
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TelegramChat {
+    pub chat_id: ChatId,
+    pub reply_to: Option<MessageId>,
+}
+
+impl TelegramChat {
+    /// Private chat with a user, their chat id is their user id
+    #[must_use]
+    pub fn user(user_id: i64) -> Self {
+        Self {
+            chat_id: ChatId(user_id),
+            reply_to: None,
+        }
+    }
+}
+
+/// Calls the bot makes to the telegram api, implemented by [`Bot`] and by a
+/// mock in tests
+#[async_trait]
+pub trait TelegramApi: Send + Sync {
+    /// Long poll for updates after `offset`
+    async fn get_updates(&self, offset: i32) -> Result<Vec<Update>, Error>;
+
+    async fn send_reply(&self, chat: &TelegramChat, reply: &BotReply) -> Result<(), Error>;
+
+    /// Stop the client showing a spinner on a pressed button
+    async fn answer_callback(&self, callback_id: &str) -> Result<(), Error>;
+
+    /// Commands offered as completions by telegram clients
+    async fn set_commands(&self) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl TelegramApi for Bot {
+    async fn get_updates(&self, offset: i32) -> Result<Vec<Update>, Error> {
+        let updates = Requester::get_updates(self)
+            .offset(offset)
+            .timeout(POLL_TIMEOUT)
+            .await?;
+        Ok(updates)
+    }
+
+    async fn send_reply(&self, chat: &TelegramChat, reply: &BotReply) -> Result<(), Error> {
+        // long replies go out as several messages, the first answers the
+        // message and the last carries the buttons
+        let pieces = split_text(&reply.text, MAX_MESSAGE_LENGTH);
+        let last = pieces.len() - 1;
+        for (idx, piece) in pieces.into_iter().enumerate() {
+            let mut request = self.send_message(chat.chat_id, piece);
+            if let Some(reply_to) = chat.reply_to.filter(|_| idx == 0) {
+                request = request.reply_parameters(ReplyParameters::new(reply_to));
+            }
+            if idx == last {
+                if let Some(keyboard) = inline_keyboard(reply) {
+                    request = request.reply_markup(keyboard);
+                }
+            }
+            request.await?;
+        }
+        Ok(())
+    }
+
+    async fn answer_callback(&self, callback_id: &str) -> Result<(), Error> {
+        self.answer_callback_query(callback_id).await?;
+        Ok(())
+    }
+
+    async fn set_commands(&self) -> Result<(), Error> {
+        let commands = SLASH_COMMANDS
+            .iter()
+            .map(|(command, description)| TelegramCommand::new(*command, *description));
+        self.set_my_commands(commands).await?;
+        Ok(())
+    }
+}
+
+/// One row per button, `None` if the reply has no buttons
+#[must_use]
+pub fn inline_keyboard(reply: &BotReply) -> Option<InlineKeyboardMarkup> {
+    if reply.buttons.is_empty() && reply.links.is_empty() {
+        return None;
+    }
+    let buttons = reply
+        .buttons
+        .iter()
+        .map(|(label, command)| InlineKeyboardButton::callback(label.as_str(), command.as_str()));
+    let links = reply
+        .links
+        .iter()
+        .filter_map(|(label, url)| match Url::parse(url) {
+            Ok(url) => Some(InlineKeyboardButton::url(label.as_str(), url)),
+            Err(e) => {
+                error!("invalid link {url} {e}");
+                None
+            }
+        });
+    let rows: Vec<_> = buttons.chain(links).map(|button| vec![button]).collect();
+    Some(InlineKeyboardMarkup::new(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    use diary_app_lib::bot_core::BotReply;
+
+    use crate::telegram_api::inline_keyboard;
+
+    #[test]
+    fn test_inline_keyboard() {
+        assert!(inline_keyboard(&"plain".into()).is_none());
+        let reply = BotReply::from("conflict")
+            .with_button("Keep mine", "/keep_mine 2024-03-01T08:00:00.0Z")
+            .with_link("Open in web", "https://example.com/api/show_conflict")
+            .with_link("Broken", "not a url");
+        let keyboard = inline_keyboard(&reply).unwrap();
+        assert_eq!(keyboard.inline_keyboard.len(), 2);
+        let button = &keyboard.inline_keyboard[0][0];
+        assert_eq!(button.text, "Keep mine");
+        assert_eq!(
+            button.kind,
+            InlineKeyboardButtonKind::CallbackData("/keep_mine 2024-03-01T08:00:00.0Z".into())
+        );
+        assert!(matches!(
+            keyboard.inline_keyboard[1][0].kind,
+            InlineKeyboardButtonKind::Url(_)
+        ));
+    }
+}
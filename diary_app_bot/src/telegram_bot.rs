@@ -1,180 +1,209 @@
-use anyhow::Error;
-use futures::{future::join, StreamExt, TryStreamExt};
-use itertools::Itertools;
-use log::debug;
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use futures::{future::join5, pin_mut, StreamExt};
+use log::{debug, error};
 use once_cell::sync::Lazy;
 use stack_string::{format_sstr, StackString};
-use std::collections::HashSet;
-use telegram_bot::{types::refs::UserId, Api, CanReplySendMessage, MessageKind, UpdateKind};
+use std::{collections::VecDeque, net::SocketAddr, sync::Arc};
+use subtle::ConstantTimeEq;
+use teloxide::{
+    payloads::SetWebhookSetters,
+    requests::Requester,
+    types::{Update, UpdateKind},
+    update_listeners::{
+        webhooks::{self, Options},
+        AsUpdateStream,
+    },
+    Bot,
+};
 use tokio::{
     sync::{
-        mpsc::{channel, Receiver},
-        RwLock,
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
     },
     task::spawn,
     time::{sleep, timeout, Duration},
 };
 
 use diary_app_lib::{
-    config::Config, diary_app_interface::DiaryAppInterface, models::AuthorizedUsers, pgpool::PgPool,
+    bot_core::{BotCore, BotReply, IncomingMessage, Messenger},
+    config::Config,
+    date_time_wrapper::DateTimeWrapper,
+    diary_app_interface::DiaryAppInterface,
+    error_reporting,
+    models::AuthorizedUsers,
+    pgpool::PgPool,
 };
 
-use crate::failure_count::FailureCount;
-
-type UserIds = RwLock<HashSet<UserId>>;
-type OBuffer = RwLock<Vec<StackString>>;
+use crate::{
+    failure_count::FailureCount,
+    telegram_api::{TelegramApi, TelegramChat},
+};
 
-static TELEGRAM_USERIDS: Lazy<UserIds> = Lazy::new(|| RwLock::new(HashSet::new()));
-static OUTPUT_BUFFER: Lazy<OBuffer> = Lazy::new(|| RwLock::new(Vec::new()));
 static FAILURE_COUNT: Lazy<FailureCount> = Lazy::new(|| FailureCount::new(5));
 
-async fn diary_sync(
-    dapp_interface: DiaryAppInterface,
-    mut recv: Receiver<()>,
-) -> Result<(), Error> {
-    while recv.recv().await.is_some() {
-        let output = dapp_interface
-            .sync_merge_cache_to_entries()
-            .await?
-            .into_iter()
-            .chain(dapp_interface.local.import_from_local().await?.into_iter())
-            .map(|d| format_sstr!("update {}", d.diary_date))
-            .sorted()
-            .join("\n")
-            .into();
-        let mut buf = OUTPUT_BUFFER.write().await;
-        buf.clear();
-        buf.push(output);
-    }
-    Ok(())
+/// Where updates come from
+#[derive(Clone)]
+pub enum UpdateSource {
+    /// `getUpdates` long polling
+    Polling,
+    /// Updates telegram posts to the webhook, shared so the listener
+    /// outlives each messenger
+    Webhook(Arc<Mutex<Receiver<Update>>>),
 }
 
-async fn bot_handler(dapp_interface: DiaryAppInterface) -> Result<(), Error> {
-    let (send, recv) = channel(1);
-    let sync_task = {
-        let d = dapp_interface.clone();
-        spawn(diary_sync(d, recv))
-    };
-    let api = Api::new(&dapp_interface.config.telegram_bot_token);
-    let mut stream = api.stream();
-    while let Some(update) = stream.next().await {
-        FAILURE_COUNT.check()?;
-        // If the received update contains a new message...
-        if let UpdateKind::Message(message) = update?.kind {
-            FAILURE_COUNT.check()?;
-            if let MessageKind::Text { ref data, .. } = message.kind {
-                FAILURE_COUNT.check()?;
-                // Print received text message to stdout.
-                debug!("{:?}", message);
-                if TELEGRAM_USERIDS.read().await.contains(&message.from.id) {
-                    FAILURE_COUNT.check()?;
-                    let first_word = data.split_whitespace().next();
-                    match first_word.map(str::to_lowercase).as_deref() {
-                        Some(":search" | ":s") => {
-                            let search_text = data.trim_start_matches(first_word.unwrap()).trim();
-                            OUTPUT_BUFFER.write().await.clear();
-                            if let Ok(mut search_results) =
-                                dapp_interface.search_text(search_text).await
-                            {
-                                search_results.reverse();
-                                OUTPUT_BUFFER
-                                    .write()
-                                    .await
-                                    .extend_from_slice(&search_results);
-                            }
-                            FAILURE_COUNT.check()?;
-                            if let Some(entry) = OUTPUT_BUFFER.write().await.pop() {
-                                api.send(message.text_reply(entry.as_str())).await?;
-                            } else {
-                                api.send(message.text_reply("...")).await?;
-                            }
-                            FAILURE_COUNT.check()?;
-                        }
-                        Some(":help" | ":h") => {
-                            let help_text = format_sstr!(
-                                "{}\n{}\n{}\n{}",
-                                ":s, :search => search for text, get text for given date, or for \
-                                 `today`",
-                                ":n, :next => get the next page of search results",
-                                ":sync => sync with local and s3",
-                                ":i, :insert => insert text (also the action if no other command \
-                                 is specified"
-                            );
-                            api.send(message.text_reply(help_text.as_str())).await?;
-                        }
-                        Some(":sync") => {
-                            send.send(()).await?;
-                            api.send(
-                                message.text_reply("started sync, reply with :n to see result"),
-                            )
-                            .await?;
-                        }
-                        Some(":next" | ":n") => {
-                            if let Some(entry) = OUTPUT_BUFFER.write().await.pop() {
-                                api.send(message.text_reply(entry.as_str())).await?;
-                            } else {
-                                api.send(message.text_reply("...")).await?;
-                            }
-                        }
-                        Some(":insert" | ":i") => {
-                            let insert_text = data.trim_start_matches(first_word.unwrap()).trim();
-                            if let Ok(cache_entry) = dapp_interface.cache_text(insert_text).await {
-                                let reply = format_sstr!("cached entry {cache_entry:?}");
-                                api.send(message.text_reply(reply.as_str())).await?;
-                            } else {
-                                api.send(message.text_reply("failed to cache entry"))
-                                    .await?;
-                            }
-                            FAILURE_COUNT.check()?;
-                        }
-                        _ => {
-                            if let Ok(cache_entry) = dapp_interface.cache_text(data).await {
-                                let reply = format_sstr!("cached entry {cache_entry:?}");
-                                api.send(message.text_reply(reply.as_str())).await?;
-                            } else {
-                                api.send(message.text_reply("failed to cache entry"))
-                                    .await?;
-                            }
-                            FAILURE_COUNT.check()?;
-                        }
+pub struct TelegramMessenger<A> {
+    api: Arc<A>,
+    source: UpdateSource,
+    /// `update_id` after the last update seen when polling
+    offset: i32,
+    pending: VecDeque<Update>,
+}
+
+impl<A: TelegramApi> TelegramMessenger<A> {
+    #[must_use]
+    pub fn new(api: Arc<A>, source: UpdateSource) -> Self {
+        Self {
+            api,
+            source,
+            offset: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if polling fails
+    async fn next_update(&mut self) -> Result<Option<Update>, Error> {
+        loop {
+            if let Some(update) = self.pending.pop_front() {
+                return Ok(Some(update));
+            }
+            match &self.source {
+                UpdateSource::Polling => {
+                    let updates = self.api.get_updates(self.offset).await?;
+                    if let Some(last) = updates.last() {
+                        self.offset = last.id.as_offset();
                     }
-                } else {
-                    // Answer message with "Hi".
-                    let reply = format_sstr!(
-                        "Hi, {n}, user_id {i}! You just wrote '{data}'",
-                        n = message.from.first_name,
-                        i = message.from.id,
-                    );
-                    api.send(message.text_reply(reply.as_str())).await?;
+                    self.pending.extend(updates);
+                }
+                UpdateSource::Webhook(recv) => return Ok(recv.lock().await.recv().await),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<A: TelegramApi> Messenger for TelegramMessenger<A> {
+    type Chat = TelegramChat;
+
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn user_id(user: &AuthorizedUsers) -> Option<StackString> {
+        user.telegram_userid.map(|userid| format_sstr!("{userid}"))
+    }
+
+    async fn receive(&mut self) -> Result<Option<IncomingMessage<TelegramChat>>, Error> {
+        while let Some(update) = self.next_update().await? {
+            FAILURE_COUNT.check()?;
+            match update.kind {
+                // If the received update contains a new message...
+                UpdateKind::Message(message) => {
+                    let (Some(text), Some(from)) = (message.text(), &message.from) else {
+                        continue;
+                    };
+                    // Print received text message to stdout.
+                    debug!("{:?}", message);
+                    return Ok(Some(IncomingMessage {
+                        sender: format_sstr!("{}", from.id.0),
+                        sender_name: from.first_name.as_str().into(),
+                        text: text.into(),
+                        chat: TelegramChat {
+                            chat_id: message.chat.id,
+                            reply_to: Some(message.id),
+                        },
+                    }));
                 }
+                // a button of an inline keyboard was pressed, its data is a command
+                UpdateKind::CallbackQuery(query) => {
+                    self.api.answer_callback(&query.id).await?;
+                    let (Some(message), Some(data)) = (query.regular_message(), &query.data) else {
+                        continue;
+                    };
+                    debug!("callback {data}");
+                    return Ok(Some(IncomingMessage {
+                        sender: format_sstr!("{}", query.from.id.0),
+                        sender_name: query.from.first_name.as_str().into(),
+                        text: data.as_str().into(),
+                        chat: TelegramChat {
+                            chat_id: message.chat.id,
+                            reply_to: None,
+                        },
+                    }));
+                }
+                _ => {}
             }
         }
+        Ok(None)
+    }
+
+    async fn send(&self, chat: &TelegramChat, reply: &BotReply) -> Result<(), Error> {
+        self.api.send_reply(chat, reply).await?;
+        FAILURE_COUNT.check()
+    }
+
+    async fn link_user(dapp: &DiaryAppInterface, email: &str, sender: &str) -> Result<(), Error> {
+        let userid = sender.parse()?;
+        dapp.set_telegram_userid(email, Some(userid)).await?;
+        Ok(())
+    }
+}
+
+/// Start the webhook listener, telegram is told to post updates to
+/// `telegram_webhook_url` which is expected to proxy to
+/// `telegram_webhook_port`
+async fn webhook_source(bot: Bot, config: &Config, url: &str) -> Result<UpdateSource, Error> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.telegram_webhook_port));
+    let mut options = Options::new(addr, url.parse()?);
+    if let Some(secret) = &config.telegram_webhook_secret {
+        options = options.secret_token(secret.as_str().into());
     }
-    sync_task.await?
+    let mut listener = webhooks::axum(bot, options).await?;
+    let (send, recv) = channel(16);
+    spawn(async move {
+        let stream = listener.as_stream();
+        pin_mut!(stream);
+        while let Some(update) = stream.next().await {
+            match update {
+                Ok(update) => {
+                    if send.send(update).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => error!("webhook failed {e}"),
+            }
+        }
+    });
+    Ok(UpdateSource::Webhook(Arc::new(Mutex::new(recv))))
 }
 
-async fn telegram_worker(dapp: DiaryAppInterface) -> Result<(), Error> {
+async fn telegram_worker(core: BotCore, bot: Arc<Bot>, source: UpdateSource) -> Result<(), Error> {
     loop {
         FAILURE_COUNT.check()?;
-        let d = dapp.clone();
+        let messenger = TelegramMessenger::new(bot.clone(), source.clone());
 
-        match timeout(Duration::from_secs(3600), bot_handler(d)).await {
+        match timeout(Duration::from_secs(3600), core.run(messenger)).await {
             Err(_) | Ok(Ok(())) => FAILURE_COUNT.reset()?,
             Ok(Err(_)) => FAILURE_COUNT.increment()?,
         }
     }
 }
 
-async fn fill_telegram_user_ids(pool: PgPool) -> Result<(), Error> {
+async fn fill_telegram_user_ids(core: BotCore) -> Result<(), Error> {
     loop {
         FAILURE_COUNT.check()?;
-        let p = pool.clone();
-        if let Ok(authorized_users) = AuthorizedUsers::get_authorized_users(&p).await {
-            let telegram_userid_set: HashSet<_> = authorized_users
-                .try_filter_map(|user| async move { Ok(user.telegram_userid.map(UserId::new)) })
-                .try_collect()
-                .await?;
-            *TELEGRAM_USERIDS.write().await = telegram_userid_set;
+        if core.refresh_users::<TelegramMessenger<Bot>>().await.is_ok() {
             FAILURE_COUNT.reset()?;
         } else {
             FAILURE_COUNT.increment()?;
@@ -183,19 +212,363 @@ async fn fill_telegram_user_ids(pool: PgPool) -> Result<(), Error> {
     }
 }
 
+/// Send entries scheduled for the future to every telegram user once their
+/// date arrives, checked hourly so they surface shortly after midnight
+async fn release_scheduled_entries(core: BotCore, api: Arc<Bot>) -> Result<(), Error> {
+    let dapp = &core.dapp;
+    loop {
+        // wait for fill_telegram_user_ids so released entries aren't missed
+        sleep(Duration::from_secs(3600)).await;
+        match dapp.release_scheduled().await {
+            Ok(entries) => {
+                for entry in entries {
+                    let text = format_sstr!(
+                        "letter from the past for {}\n{}",
+                        entry.diary_date,
+                        entry.diary_text
+                    );
+                    let reply = BotReply::from(text);
+                    let user_ids = core.user_ids().await;
+                    for chat in user_ids
+                        .iter()
+                        .filter_map(|id| id.parse().ok().map(TelegramChat::user))
+                    {
+                        if let Err(e) = api.send_reply(&chat, &reply).await {
+                            error!("failed to send scheduled entry {e}");
+                        }
+                    }
+                }
+            }
+            Err(e) => error!("failed to release scheduled entries {e}"),
+        }
+    }
+}
+
+/// Tell users who can change entries about conflicts recorded by syncs,
+/// conflicts from before the bot started are left to `/conflicts`
+async fn notify_conflicts(core: BotCore, api: Arc<Bot>) -> Result<(), Error> {
+    let mut since = DateTimeWrapper::now();
+    loop {
+        sleep(Duration::from_secs(60)).await;
+        let notifications = match core.conflict_notifications(&mut since).await {
+            Ok(notifications) => notifications,
+            Err(e) => {
+                error!("failed to check for conflicts {e}");
+                continue;
+            }
+        };
+        if notifications.is_empty() {
+            continue;
+        }
+        let user_ids = core.writer_ids().await;
+        for reply in &notifications {
+            for chat in user_ids
+                .iter()
+                .filter_map(|id| id.parse().ok().map(TelegramChat::user))
+            {
+                if let Err(e) = api.send_reply(&chat, reply).await {
+                    error!("failed to send conflict notification {e}");
+                }
+            }
+        }
+    }
+}
+
+/// Send each opted in user one past entry a day once `resurface_hour` has
+/// passed, users without a telegram id are skipped
+async fn resurface_entries(dapp: DiaryAppInterface, api: Arc<Bot>) -> Result<(), Error> {
+    loop {
+        sleep(Duration::from_secs(3600)).await;
+        if !dapp.is_resurface_due() {
+            continue;
+        }
+        let messages = match dapp.get_resurface_messages().await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("failed to resurface entries {e}");
+                continue;
+            }
+        };
+        for (recipient, text) in messages {
+            let Some(chat) = recipient.telegram_userid.map(TelegramChat::user) else {
+                continue;
+            };
+            if let Err(e) = api.send_reply(&chat, &text.into()).await {
+                error!("failed to send resurfaced entry {e}");
+            } else if let Err(e) = dapp.mark_resurfaced(&recipient.email).await {
+                error!("failed to mark resurfaced entry {e}");
+            }
+        }
+    }
+}
+
+/// Answer updates from `source` and send scheduled, resurfaced and conflict
+/// messages
+async fn run_tasks(dapp: DiaryAppInterface, bot: Bot, source: UpdateSource) -> Result<(), Error> {
+    let core = BotCore::new(dapp.clone());
+    let bot = Arc::new(bot);
+
+    let userid_handle = fill_telegram_user_ids(core.clone());
+    let release_handle = release_scheduled_entries(core.clone(), bot.clone());
+    let resurface_handle = resurface_entries(dapp, bot.clone());
+    let conflict_handle = notify_conflicts(core.clone(), bot.clone());
+    let telegram_handle = telegram_worker(core, bot, source);
+
+    let (r0, r1, r2, r3, r4) = join5(
+        userid_handle,
+        release_handle,
+        resurface_handle,
+        conflict_handle,
+        telegram_handle,
+    )
+    .await;
+    r0.and(r1).and(r2).and(r3).and(r4)
+}
+
 /// # Errors
 /// Returns error if config fails or bot fails
 pub async fn run_bot() -> Result<(), Error> {
     let config = Config::init_config()?;
-    let pool = PgPool::new(&config.database_url)?;
+    let _reporting = error_reporting::init(&config, "diary-app-bot");
+    let pool = PgPool::new(&config.database_url)?.with_retry_policy(config.retry_policy());
     let sdk_config = aws_config::load_from_env().await;
+    if config.telegram_webhook_in_api {
+        return Err(format_err!("telegram updates are handled by the api"));
+    }
     let dapp = DiaryAppInterface::new(config, &sdk_config, pool);
+    let bot = Bot::new(dapp.config.telegram_bot_token.as_str());
+    if let Err(e) = bot.set_commands().await {
+        error!("failed to register commands {e}");
+    }
+    let source = match &dapp.config.telegram_webhook_url {
+        Some(url) => webhook_source(bot.clone(), &dapp.config, url).await?,
+        None => UpdateSource::Polling,
+    };
+    run_tasks(dapp, bot, source).await
+}
 
-    let pool_ = dapp.pool.clone();
+/// Bot fed by updates telegram posts to the api instead of a separate
+/// diary-app-bot process
+#[derive(Clone)]
+pub struct TelegramWebhook {
+    secret: StackString,
+    send: Sender<Update>,
+}
+
+impl TelegramWebhook {
+    fn new(secret: impl Into<StackString>, send: Sender<Update>) -> Self {
+        Self {
+            secret: secret.into(),
+            send,
+        }
+    }
+
+    /// Point telegram at `telegram_webhook_url` and start the bot
+    /// # Errors
+    /// Return error if the webhook settings are missing or telegram rejects
+    /// them
+    pub async fn start(dapp: DiaryAppInterface) -> Result<Self, Error> {
+        let config = &dapp.config;
+        let (Some(url), Some(secret)) = (
+            &config.telegram_webhook_url,
+            &config.telegram_webhook_secret,
+        ) else {
+            return Err(format_err!(
+                "telegram_webhook_url and telegram_webhook_secret must be set"
+            ));
+        };
+        let bot = Bot::new(config.telegram_bot_token.as_str());
+        if let Err(e) = bot.set_commands().await {
+            error!("failed to register commands {e}");
+        }
+        bot.set_webhook(url.parse()?)
+            .secret_token(secret.as_str())
+            .await?;
+        let (send, recv) = channel(16);
+        let webhook = Self::new(secret.clone(), send);
+        let source = UpdateSource::Webhook(Arc::new(Mutex::new(recv)));
+        spawn(async move {
+            if let Err(e) = run_tasks(dapp, bot, source).await {
+                error!("telegram bot stopped {e}");
+            }
+        });
+        Ok(webhook)
+    }
 
-    let userid_handle = fill_telegram_user_ids(pool_);
-    let telegram_handle = telegram_worker(dapp);
+    /// Telegram sends the secret given to `setWebhook` in the
+    /// `X-Telegram-Bot-Api-Secret-Token` header, compared in constant time
+    #[must_use]
+    pub fn verify(&self, token: Option<&str>) -> bool {
+        token.is_some_and(|token| self.secret.as_bytes().ct_eq(token.as_bytes()).into())
+    }
+
+    /// # Errors
+    /// Return error if the bot has stopped
+    pub async fn dispatch(&self, update: Update) -> Result<(), Error> {
+        self.send
+            .send(update)
+            .await
+            .map_err(|_| format_err!("telegram bot stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{format_err, Error};
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use std::{collections::VecDeque, sync::Arc};
+    use teloxide::types::{ChatId, MessageId, Update};
+    use tokio::sync::{mpsc::channel, Mutex as AsyncMutex};
+
+    use diary_app_lib::bot_core::{BotReply, Messenger};
+
+    use crate::{
+        telegram_api::{TelegramApi, TelegramChat},
+        telegram_bot::{TelegramMessenger, TelegramWebhook, UpdateSource},
+    };
 
-    let (r0, r1) = join(userid_handle, telegram_handle).await;
-    r0.and(r1)
+    #[derive(Default)]
+    struct MockApi {
+        updates: Mutex<VecDeque<Vec<Update>>>,
+        offsets: Mutex<Vec<i32>>,
+        sent: Mutex<Vec<(TelegramChat, BotReply)>>,
+        answered: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl TelegramApi for MockApi {
+        async fn get_updates(&self, offset: i32) -> Result<Vec<Update>, Error> {
+            self.offsets.lock().push(offset);
+            self.updates
+                .lock()
+                .pop_front()
+                .ok_or_else(|| format_err!("no more updates"))
+        }
+
+        async fn send_reply(&self, chat: &TelegramChat, reply: &BotReply) -> Result<(), Error> {
+            self.sent.lock().push((*chat, reply.clone()));
+            Ok(())
+        }
+
+        async fn answer_callback(&self, callback_id: &str) -> Result<(), Error> {
+            self.answered.lock().push(callback_id.into());
+            Ok(())
+        }
+
+        async fn set_commands(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn message_json(message_id: i32, text: &str) -> serde_json::Value {
+        json!({
+            "message_id": message_id,
+            "date": 1_700_000_000,
+            "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+            "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+            "text": text,
+        })
+    }
+
+    fn text_update(update_id: u32, message_id: i32, text: &str) -> Update {
+        serde_json::from_value(json!({
+            "update_id": update_id,
+            "message": message_json(message_id, text),
+        }))
+        .unwrap()
+    }
+
+    fn callback_update(update_id: u32, data: &str) -> Update {
+        serde_json::from_value(json!({
+            "update_id": update_id,
+            "callback_query": {
+                "id": "cb1",
+                "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+                "chat_instance": "1",
+                "data": data,
+                "message": message_json(6, "started sync"),
+            },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_receive_polling() -> Result<(), Error> {
+        let edited: Update = serde_json::from_value(json!({
+            "update_id": 11,
+            "edited_message": message_json(5, "fixed typo"),
+        }))?;
+        let api = Arc::new(MockApi::default());
+        api.updates.lock().push_back(vec![
+            text_update(10, 5, "/today"),
+            edited,
+            callback_update(12, "/next"),
+        ]);
+        let mut messenger = TelegramMessenger::new(api.clone(), UpdateSource::Polling);
+
+        let message = messenger.receive().await?.unwrap();
+        assert_eq!(message.sender, "42");
+        assert_eq!(message.sender_name, "Alice");
+        assert_eq!(message.text, "/today");
+        assert_eq!(
+            message.chat,
+            TelegramChat {
+                chat_id: ChatId(42),
+                reply_to: Some(MessageId(5)),
+            }
+        );
+
+        let message = messenger.receive().await?.unwrap();
+        assert_eq!(message.text, "/next");
+        assert_eq!(message.chat, TelegramChat::user(42));
+        assert_eq!(*api.answered.lock(), vec!["cb1".to_string()]);
+
+        assert!(messenger.receive().await.is_err());
+        assert_eq!(*api.offsets.lock(), vec![0, 13]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_receive_webhook() -> Result<(), Error> {
+        let api = Arc::new(MockApi::default());
+        let (send, recv) = channel(4);
+        let source = UpdateSource::Webhook(Arc::new(AsyncMutex::new(recv)));
+        let mut messenger = TelegramMessenger::new(api.clone(), source);
+
+        send.send(text_update(1, 5, "went hiking")).await?;
+        let message = messenger.receive().await?.unwrap();
+        assert_eq!(message.text, "went hiking");
+
+        drop(send);
+        assert!(messenger.receive().await?.is_none());
+        assert!(api.offsets.lock().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_webhook_dispatch() -> Result<(), Error> {
+        let (send, mut recv) = channel(4);
+        let webhook = TelegramWebhook::new("s3cret", send);
+        assert!(webhook.verify(Some("s3cret")));
+        assert!(!webhook.verify(Some("guess")));
+        assert!(!webhook.verify(None));
+
+        webhook.dispatch(text_update(1, 5, "/today")).await?;
+        assert_eq!(recv.recv().await.unwrap().id.0, 1);
+        drop(recv);
+        assert!(webhook.dispatch(text_update(2, 6, "/next")).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Error> {
+        let api = Arc::new(MockApi::default());
+        let messenger = TelegramMessenger::new(api.clone(), UpdateSource::Polling);
+        let reply = BotReply::from("entry").with_button("Next", "/next");
+        messenger.send(&TelegramChat::user(42), &reply).await?;
+        assert_eq!(*api.sent.lock(), vec![(TelegramChat::user(42), reply)]);
+        Ok(())
+    }
 }
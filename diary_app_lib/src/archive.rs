@@ -0,0 +1,166 @@
+use anyhow::Error;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// Compression applied to year exports older than `archive_after_years`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Extension appended to the name of a compressed file
+    #[must_use]
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gz"),
+            Self::Zstd => Some("zst"),
+        }
+    }
+
+    /// `Content-Encoding` of s3 objects compressed this way
+    #[must_use]
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gzip"),
+            Self::Zstd => Some("zstd"),
+        }
+    }
+
+    #[must_use]
+    pub fn from_content_encoding(encoding: &str) -> Option<Self> {
+        match encoding.trim().to_lowercase().as_str() {
+            "" | "identity" => Some(Self::None),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Split a compression extension off of `filename`
+    #[must_use]
+    pub fn from_filename(filename: &str) -> (&str, Self) {
+        [Self::Gzip, Self::Zstd]
+            .iter()
+            .find_map(|c| {
+                let ext = c.extension()?;
+                let base = filename.strip_suffix(ext)?.strip_suffix('.')?;
+                Some((base, *c))
+            })
+            .unwrap_or((filename, Self::None))
+    }
+
+    /// # Errors
+    /// Return error if compression fails
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish().map_err(Into::into)
+            }
+            Self::Zstd => zstd::encode_all(data, 0).map_err(Into::into),
+        }
+    }
+
+    /// # Errors
+    /// Return error if `data` isn't valid for this compression
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut output = Vec::new();
+                GzDecoder::new(data).read_to_end(&mut output)?;
+                Ok(output)
+            }
+            Self::Zstd => zstd::decode_all(data).map_err(Into::into),
+        }
+    }
+}
+
+/// Read a text file, decompressing it if its name ends in `.gz` or `.zst`
+/// # Errors
+/// Return error if reading or decompressing the file fails
+pub fn read_to_string(path: &Path) -> Result<String, Error> {
+    let filename = path
+        .file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or_default();
+    let (_, compression) = Compression::from_filename(&filename);
+    let data = compression.decompress(&fs::read(path)?)?;
+    String::from_utf8(data).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::fs;
+    use tempdir::TempDir;
+
+    use crate::archive::{read_to_string, Compression};
+
+    #[test]
+    fn test_from_filename() {
+        assert_eq!(
+            Compression::from_filename("diary_2020.txt.gz"),
+            ("diary_2020.txt", Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_filename("2020-01-01.txt.zst"),
+            ("2020-01-01.txt", Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::from_filename("2020-01-01.txt"),
+            ("2020-01-01.txt", Compression::None)
+        );
+        assert_eq!(
+            Compression::from_filename("2020-01-01.txtgz"),
+            ("2020-01-01.txtgz", Compression::None)
+        );
+    }
+
+    #[test]
+    fn test_content_encoding() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let encoding = compression.content_encoding().unwrap_or("");
+            assert_eq!(
+                Compression::from_content_encoding(encoding),
+                Some(compression)
+            );
+        }
+        assert_eq!(
+            Compression::from_content_encoding("X-GZIP"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::from_content_encoding("br"), None);
+    }
+
+    #[test]
+    fn test_read_to_string() -> Result<(), Error> {
+        let t = TempDir::new("test_archive")?;
+        let text = "2020-01-01\n\nsome text\n\n";
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let mut filename = "diary_2020.txt".to_string();
+            if let Some(ext) = compression.extension() {
+                filename.push('.');
+                filename.push_str(ext);
+            }
+            let path = t.path().join(filename);
+            fs::write(&path, compression.compress(text.as_bytes())?)?;
+            assert_eq!(read_to_string(&path)?, text);
+        }
+        Ok(())
+    }
+}
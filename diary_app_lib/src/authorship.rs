@@ -0,0 +1,123 @@
+use stack_string::{format_sstr, StackString};
+
+use crate::line_diff::{DiffHunk, DiffTag};
+
+/// Opens the author stamp at the end of the header line of a paragraph in a
+/// shared journal, `2024-03-01 08:00:00 -05:00 [@alice@example.com]`
+const STAMP_OPEN: &str = " [@";
+
+/// Header line of a paragraph written by `author`
+#[must_use]
+pub fn stamp_header(header: &str, author: &str) -> StackString {
+    format_sstr!("{header}{STAMP_OPEN}{author}]")
+}
+
+/// Author of the paragraph if `line` is a stamped header line
+#[must_use]
+pub fn parse_stamp(line: &str) -> Option<&str> {
+    let (_, author) = line.trim_end().strip_suffix(']')?.rsplit_once(STAMP_OPEN)?;
+    if author.is_empty() || author.contains(char::is_whitespace) {
+        None
+    } else {
+        Some(author)
+    }
+}
+
+/// Distinct authors of the paragraphs of `text` in order of appearance
+#[must_use]
+pub fn entry_authors(text: &str) -> Vec<&str> {
+    let mut authors: Vec<&str> = Vec::new();
+    for author in text.lines().filter_map(parse_stamp) {
+        if !authors.contains(&author) {
+            authors.push(author);
+        }
+    }
+    authors
+}
+
+/// Author of each hunk of a line diff between two versions of an entry,
+/// lines belong to the last stamped paragraph above them in their version
+#[must_use]
+pub fn diff_authors(hunks: &[DiffHunk]) -> Vec<Option<StackString>> {
+    let mut original: Option<&str> = None;
+    let mut updated: Option<&str> = None;
+    hunks
+        .iter()
+        .map(|hunk| {
+            let text = &hunk.text;
+            let current = match hunk.tag {
+                DiffTag::Same | DiffTag::Rem => original,
+                DiffTag::Add => updated,
+            };
+            let first = first_stamp(text);
+            let last = text.lines().rev().find_map(parse_stamp);
+            if let Some(last) = last {
+                match hunk.tag {
+                    DiffTag::Same => {
+                        original = Some(last);
+                        updated = Some(last);
+                    }
+                    DiffTag::Rem => original = Some(last),
+                    DiffTag::Add => updated = Some(last),
+                }
+            }
+            first.or(current).map(Into::into)
+        })
+        .collect()
+}
+
+/// Stamp on the first non blank line of `text`
+fn first_stamp(text: &str) -> Option<&str> {
+    text.lines()
+        .find(|line| !line.trim().is_empty())
+        .and_then(parse_stamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+
+    use crate::{
+        authorship::{diff_authors, entry_authors, parse_stamp, stamp_header},
+        line_diff::LineDiff,
+    };
+
+    #[test]
+    fn test_stamp() {
+        let header = stamp_header("2024-03-01 08:00:00 -05:00", "alice@example.com");
+        assert_eq!(header, "2024-03-01 08:00:00 -05:00 [@alice@example.com]");
+        assert_eq!(parse_stamp(&header), Some("alice@example.com"));
+        assert_eq!(parse_stamp("2024-03-01 08:00:00 -05:00"), None);
+        assert_eq!(parse_stamp("meet [@ noon]"), None);
+        assert_eq!(parse_stamp("empty [@]"), None);
+    }
+
+    #[test]
+    fn test_entry_authors() {
+        let text = "t0 [@alice]\nwent for a walk\n\nt1 [@bob]\nmade dinner\n\nt2 [@alice]\nslept";
+        assert_eq!(entry_authors(text), vec!["alice", "bob"]);
+        assert!(entry_authors("plain entry\nno stamps").is_empty());
+    }
+
+    #[test]
+    fn test_diff_authors() {
+        let original = "t0 [@alice]\nwent for a walk\n\nt1 [@bob]\nmade dinner";
+        let updated = "t0 [@alice]\nwent for a run\n\nt1 [@bob]\nmade dinner\nt2 [@carol]\nhi";
+        let diff = LineDiff::new(original, updated);
+        let authors: Vec<(StackString, StackString)> = diff_authors(&diff.hunks)
+            .into_iter()
+            .zip(diff.hunks.iter())
+            .map(|(author, hunk)| (hunk.text.clone(), author.unwrap_or_default()))
+            .collect();
+        assert_eq!(
+            authors,
+            vec![
+                ("t0 [@alice]".into(), "alice".into()),
+                ("went for a walk".into(), "alice".into()),
+                ("went for a run".into(), "alice".into()),
+                ("\nt1 [@bob]\nmade dinner".into(), "bob".into()),
+                ("t2 [@carol]\nhi".into(), "carol".into()),
+            ]
+        );
+    }
+}
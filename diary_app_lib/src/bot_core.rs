@@ -0,0 +1,749 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, sync::Arc};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        RwLock,
+    },
+    task::spawn,
+};
+use uuid::Uuid;
+
+use crate::{
+    commands::{CommandOutput, DiaryCommand},
+    date_time_wrapper::DateTimeWrapper,
+    diary_app_interface::DiaryAppInterface,
+    entry_limits::LimitError,
+    i18n::{Locale, Message},
+    models::{AuthorizedUsers, ConflictKey, DiaryConflict},
+    services::ConflictList,
+    sync_lock::SyncLockError,
+    users::UserError,
+};
+
+/// Commands offered as completions by messengers which support it, text
+/// which doesn't start with a command is inserted
+pub const SLASH_COMMANDS: [(&str, &str); 7] = [
+    ("search", "Search for text or get the entry for a date"),
+    ("today", "Show today's entry"),
+    ("insert", "Add text to the diary"),
+    ("sync", "Sync with local and s3"),
+    ("next", "Next page of results"),
+    ("conflicts", "List conflicts waiting to be resolved"),
+    ("help", "Show the commands"),
+];
+
+/// Command sent to a chat bot, the older `:s`, `:n`, `:i` and `:sync`
+/// commands are still understood
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BotCommand<'a> {
+    Search(&'a str),
+    Today,
+    Next,
+    Sync,
+    Insert(&'a str),
+    Help,
+    Conflicts,
+    /// Ask to confirm committing the conflict recorded at a datetime
+    Resolve(&'a str),
+    Commit(&'a str),
+    /// Restore the text from before the sync which recorded a conflict
+    KeepMine(&'a str),
+    Cancel,
+    /// Link the sender to the user who created a one-time code
+    Link(&'a str),
+}
+
+impl<'a> BotCommand<'a> {
+    #[must_use]
+    pub fn parse(text: &'a str) -> Self {
+        let text = text.trim_start();
+        let Some(first_word) = text.split_whitespace().next() else {
+            return Self::Insert(text);
+        };
+        let rest = text[first_word.len()..].trim();
+        // telegram appends the bot name to commands in group chats
+        let command = first_word.split('@').next().unwrap_or(first_word);
+        match command.to_lowercase().as_str() {
+            "/search" | ":search" | ":s" => Self::Search(rest),
+            "/today" => Self::Today,
+            "/next" | ":next" | ":n" => Self::Next,
+            "/sync" | ":sync" => Self::Sync,
+            "/insert" | ":insert" | ":i" => Self::Insert(rest),
+            "/help" | "/start" | ":help" | ":h" => Self::Help,
+            "/conflicts" => Self::Conflicts,
+            "/resolve" => Self::Resolve(rest),
+            "/commit" | "/keep_theirs" => Self::Commit(rest),
+            "/keep_mine" => Self::KeepMine(rest),
+            "/cancel" => Self::Cancel,
+            "/link" | ":link" => Self::Link(rest),
+            _ => Self::Insert(text),
+        }
+    }
+
+    /// Viewers can search but not change entries
+    #[must_use]
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::Sync | Self::Insert(_) | Self::Resolve(_) | Self::Commit(_) | Self::KeepMine(_)
+        )
+    }
+}
+
+#[must_use]
+pub fn help_text() -> StackString {
+    let mut help = StackString::new();
+    for (command, description) in SLASH_COMMANDS {
+        help.push_str(&format_sstr!("/{command} => {description}\n"));
+    }
+    help.push_str("text without a command is inserted");
+    help
+}
+
+/// Reply to a bot message, messengers without buttons only show the text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BotReply {
+    pub text: StackString,
+    /// `(label, command)`, pressing a button sends its command
+    pub buttons: Vec<(StackString, StackString)>,
+    /// `(label, url)` opened in a browser
+    pub links: Vec<(StackString, StackString)>,
+}
+
+impl BotReply {
+    #[must_use]
+    pub fn with_button(
+        mut self,
+        label: impl Into<StackString>,
+        command: impl Into<StackString>,
+    ) -> Self {
+        self.buttons.push((label.into(), command.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn with_link(mut self, label: impl Into<StackString>, url: impl Into<StackString>) -> Self {
+        self.links.push((label.into(), url.into()));
+        self
+    }
+}
+
+impl From<StackString> for BotReply {
+    fn from(text: StackString) -> Self {
+        Self {
+            text,
+            buttons: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+}
+
+impl From<&str> for BotReply {
+    fn from(text: &str) -> Self {
+        StackString::from(text).into()
+    }
+}
+
+/// Message received by a [`Messenger`]
+#[derive(Clone, Debug)]
+pub struct IncomingMessage<C> {
+    /// Id of the sender on the messenger, compared with [`Messenger::user_id`]
+    pub sender: StackString,
+    /// Name unknown senders are greeted with
+    pub sender_name: StackString,
+    pub text: StackString,
+    /// Where replies go
+    pub chat: C,
+}
+
+/// Chat protocol a bot frontend talks over (telegram, matrix, signal-cli,
+/// xmpp...), the commands themselves are handled by [`BotCore`]
+#[async_trait]
+pub trait Messenger: Send {
+    /// Conversation a message came from and replies are sent to
+    type Chat: Send + Sync;
+
+    /// Name of the messenger in the activity log
+    fn name(&self) -> &'static str;
+
+    /// Id of `user` on this messenger, users without one can't use the bot
+    fn user_id(user: &AuthorizedUsers) -> Option<StackString>;
+
+    /// Wait for the next message, `None` once the connection has closed
+    async fn receive(&mut self) -> Result<Option<IncomingMessage<Self::Chat>>, Error>;
+
+    async fn send(&self, chat: &Self::Chat, reply: &BotReply) -> Result<(), Error>;
+
+    /// Store `sender` as the id on this messenger of the user with `email`
+    async fn link_user(dapp: &DiaryAppInterface, email: &str, sender: &str) -> Result<(), Error>;
+}
+
+/// Bot commands independent of the messenger, search results are paged
+/// through an output buffer and syncs run in a background task
+#[derive(Clone)]
+pub struct BotCore {
+    pub dapp: DiaryAppInterface,
+    /// messenger user id -> authorized user
+    users: Arc<RwLock<HashMap<StackString, AuthorizedUsers>>>,
+    output_buffer: Arc<RwLock<Vec<StackString>>>,
+}
+
+impl BotCore {
+    #[must_use]
+    pub fn new(dapp: DiaryAppInterface) -> Self {
+        Self {
+            dapp,
+            users: Arc::new(RwLock::new(HashMap::new())),
+            output_buffer: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Reload the users allowed to talk to the bot over `M`, users disabled
+    /// since the last refresh are dropped
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn refresh_users<M: Messenger>(&self) -> Result<(), Error> {
+        let users: HashMap<_, _> = AuthorizedUsers::get_authorized_users(&self.dapp.pool)
+            .await?
+            .try_filter_map(|user| async move { Ok(M::user_id(&user).map(|id| (id, user))) })
+            .try_collect()
+            .await?;
+        *self.users.write().await = users;
+        Ok(())
+    }
+
+    pub async fn get_user(&self, user_id: &str) -> Option<AuthorizedUsers> {
+        self.users.read().await.get(user_id).cloned()
+    }
+
+    /// Messenger ids of every authorized user
+    pub async fn user_ids(&self) -> Vec<StackString> {
+        self.users.read().await.keys().cloned().collect()
+    }
+
+    /// Messenger ids of the users allowed to change entries
+    pub async fn writer_ids(&self) -> Vec<StackString> {
+        self.users
+            .read()
+            .await
+            .iter()
+            .filter(|(_, user)| !user.is_viewer())
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// A message with resolve buttons for each conflict recorded after
+    /// `since`, which is moved past them
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn conflict_notifications(
+        &self,
+        since: &mut DateTimeWrapper,
+    ) -> Result<Vec<BotReply>, Error> {
+        let dapp = &self.dapp;
+        // sent to every user, so in the configured language
+        let locale = dapp.config.default_locale;
+        let conflicts = DiaryConflict::get_since(&dapp.journal, *since, &dapp.pool).await?;
+        let mut replies = Vec::new();
+        for group in conflicts.chunk_by(|a, b| a.sync_datetime == b.sync_datetime) {
+            let conflict = &group[0];
+            let datetime = conflict.sync_datetime;
+            let query = format_sstr!(
+                "date={}&datetime={datetime}&journal={}",
+                conflict.diary_date,
+                dapp.journal
+            );
+            let url = format_sstr!("https://{}/api/show_conflict?{query}", dapp.config.domain);
+            let text = format_sstr!(
+                "sync changed {date}, keep mine restores the text from before the sync\n{preview}",
+                date = conflict.diary_date,
+                preview = conflict_preview(group),
+            );
+            replies.push(
+                BotReply::from(text)
+                    .with_button(
+                        locale.text(Message::KeepMine),
+                        format_sstr!("/keep_mine {datetime}"),
+                    )
+                    .with_button(
+                        locale.text(Message::KeepTheirs),
+                        format_sstr!("/keep_theirs {datetime}"),
+                    )
+                    .with_link(locale.text(Message::OpenInWeb), url),
+            );
+            *since = datetime;
+        }
+        Ok(replies)
+    }
+
+    /// Answer messages from `messenger` until its connection closes
+    /// # Errors
+    /// Return error if receiving or sending fails
+    pub async fn run<M: Messenger>(&self, mut messenger: M) -> Result<(), Error> {
+        let (sync_send, recv) = channel(1);
+        let sync_task = spawn({
+            let dapp = self.dapp.clone().with_source(messenger.name());
+            let output_buffer = self.output_buffer.clone();
+            diary_sync(dapp, recv, output_buffer)
+        });
+        let result = loop {
+            match messenger.receive().await {
+                Ok(Some(message)) => {
+                    if let Err(e) = self.handle(&messenger, message, &sync_send).await {
+                        break Err(e);
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        sync_task.abort();
+        result
+    }
+
+    async fn handle<M: Messenger>(
+        &self,
+        messenger: &M,
+        message: IncomingMessage<M::Chat>,
+        sync_send: &Sender<()>,
+    ) -> Result<(), Error> {
+        // unknown senders can link themselves, so check before looking them up
+        if let BotCommand::Link(code) = BotCommand::parse(&message.text) {
+            let reply = self.link_user::<M>(code, &message.sender).await?;
+            return messenger.send(&message.chat, &reply).await;
+        }
+        let replies = match self.get_user(&message.sender).await {
+            Some(user) => {
+                self.dispatch(&user, &message.text, messenger.name(), sync_send)
+                    .await?
+            }
+            None => vec![format_sstr!(
+                "Hi, {n}, user_id {i}! You just wrote '{t}'",
+                n = message.sender_name,
+                i = message.sender,
+                t = message.text,
+            )
+            .into()],
+        };
+        for reply in replies {
+            messenger.send(&message.chat, &reply).await?;
+        }
+        Ok(())
+    }
+
+    /// Redeem a code from `/api/user/link_telegram` for `sender`
+    /// # Errors
+    /// Return error if db query fails
+    async fn link_user<M: Messenger>(&self, code: &str, sender: &str) -> Result<BotReply, Error> {
+        let email = match self.dapp.redeem_link_code(code).await {
+            Ok(email) => email,
+            Err(e) => match e.downcast::<UserError>() {
+                Ok(e) => return Ok(format_sstr!("{e}").into()),
+                Err(e) => return Err(e),
+            },
+        };
+        M::link_user(&self.dapp, &email, sender).await?;
+        self.refresh_users::<M>().await?;
+        Ok(format_sstr!("linked to {email}").into())
+    }
+
+    /// Run the command in `text` for `user`, returns the replies to send
+    /// # Errors
+    /// Return error if the sync task has stopped or db query fails
+    async fn dispatch(
+        &self,
+        user: &AuthorizedUsers,
+        text: &str,
+        source: &str,
+        sync_send: &Sender<()>,
+    ) -> Result<Vec<BotReply>, Error> {
+        let dapp = self
+            .dapp
+            .clone()
+            .with_author(&user.email)
+            .with_source(source)
+            .with_private_hidden(user.is_viewer());
+        let locale = dapp.get_locale(&user.email).await?;
+        let command = BotCommand::parse(text);
+        if command.is_write() && user.is_viewer() {
+            return Ok(vec![locale.text(Message::ViewersCanOnlySearch).into()]);
+        }
+        let replies = match command {
+            BotCommand::Search(search_text) => {
+                vec![self.search(&dapp, search_text, locale).await]
+            }
+            BotCommand::Today => vec![self.search(&dapp, "today", locale).await],
+            BotCommand::Help => vec![help_text().into()],
+            BotCommand::Sync => {
+                sync_send.send(()).await?;
+                vec![BotReply::from(locale.text(Message::StartedSync))
+                    .with_button(locale.text(Message::Result), "/next")]
+            }
+            BotCommand::Next => vec![self.next_page(&dapp, locale).await],
+            BotCommand::Insert(insert_text) => {
+                match DiaryCommand::Insert(insert_text.into()).run(&dapp).await {
+                    Ok(output) => {
+                        let mut replies: Vec<BotReply> =
+                            output.lines().into_iter().map(Into::into).collect();
+                        replies.extend(secret_warning(&dapp, insert_text).map(Into::into));
+                        replies
+                    }
+                    Err(e) => match e.downcast_ref::<LimitError>() {
+                        Some(e) => vec![StackString::from_display(e).into()],
+                        None => vec![locale.text(Message::FailedToCacheEntry).into()],
+                    },
+                }
+            }
+            BotCommand::Conflicts => vec![list_conflicts(&dapp, locale).await?],
+            BotCommand::Resolve(datetime) => {
+                vec![confirm_conflict(&dapp, datetime, locale).await?]
+            }
+            BotCommand::Commit(datetime) => vec![commit_conflict(&dapp, datetime, locale).await?],
+            BotCommand::KeepMine(datetime) => {
+                vec![revert_conflict(&dapp, datetime, locale).await?]
+            }
+            BotCommand::Cancel => vec![locale.text(Message::Cancelled).into()],
+            BotCommand::Link(_) => vec![locale.text(Message::AlreadyLinked).into()],
+        };
+        Ok(replies)
+    }
+
+    async fn search(
+        &self,
+        dapp: &DiaryAppInterface,
+        search_text: &str,
+        locale: Locale,
+    ) -> BotReply {
+        {
+            let mut buf = self.output_buffer.write().await;
+            buf.clear();
+            if let Ok(output) = DiaryCommand::Search(search_text.into()).run(dapp).await {
+                buf.extend(output.lines().into_iter().rev());
+            }
+        }
+        self.next_page(dapp, locale).await
+    }
+
+    /// Next page of search or sync output, with a button for the page after
+    async fn next_page(&self, dapp: &DiaryAppInterface, locale: Locale) -> BotReply {
+        let mut buf = self.output_buffer.write().await;
+        if let Some(entry) = buf.pop() {
+            let reply = BotReply::from(entry);
+            if buf.is_empty() {
+                reply
+            } else {
+                reply.with_button(locale.text(Message::Next), "/next")
+            }
+        } else {
+            let progress = dapp.progress.current();
+            if progress.is_finished() {
+                "...".into()
+            } else {
+                BotReply::from(format_sstr!("sync in progress: {progress}"))
+                    .with_button(locale.text(Message::Refresh), "/next")
+            }
+        }
+    }
+}
+
+/// Conflicts shown by `/conflicts`, the rest are left for the web UI
+const MAX_CONFLICT_BUTTONS: usize = 5;
+
+async fn list_conflicts(dapp: &DiaryAppInterface, locale: Locale) -> Result<BotReply, Error> {
+    let dates = match DiaryCommand::ListConflicts(None).run(dapp).await? {
+        CommandOutput::Conflicts(ConflictList::Dates(dates)) => dates,
+        _ => Vec::new(),
+    };
+    if dates.is_empty() {
+        return Ok(locale.text(Message::NoConflicts).into());
+    }
+    let mut reply = BotReply::from(format_sstr!("conflicts on {} dates", dates.len()));
+    for date in dates {
+        let datetimes = match DiaryCommand::ListConflicts(Some(date)).run(dapp).await? {
+            CommandOutput::Conflicts(ConflictList::Syncs(datetimes)) => datetimes,
+            _ => Vec::new(),
+        };
+        for datetime in datetimes {
+            if reply.buttons.len() == MAX_CONFLICT_BUTTONS {
+                return Ok(reply);
+            }
+            reply = reply.with_button(
+                format_sstr!("{} {date}", locale.text(Message::Resolve)),
+                format_sstr!("/resolve {datetime}"),
+            );
+        }
+    }
+    Ok(reply)
+}
+
+/// Conflict group id, or the datetime of the sync which recorded it
+fn parse_conflict_key(text: &str) -> Option<ConflictKey> {
+    if let Ok(group) = text.parse::<Uuid>() {
+        return Some(group.into());
+    }
+    OffsetDateTime::parse(text, &Rfc3339).ok().map(Into::into)
+}
+
+async fn confirm_conflict(
+    dapp: &DiaryAppInterface,
+    datetime: &str,
+    locale: Locale,
+) -> Result<BotReply, Error> {
+    let Some(key) = parse_conflict_key(datetime) else {
+        return Ok(locale.text(Message::InvalidConflictTime).into());
+    };
+    let conflicts = match DiaryCommand::ShowConflict(key).run(dapp).await? {
+        CommandOutput::Conflict(conflicts) => conflicts,
+        _ => Vec::new(),
+    };
+    let Some(first) = conflicts.first() else {
+        return Ok(format_sstr!("no conflict at {key}").into());
+    };
+    let count = |diff_type: &str| {
+        conflicts
+            .iter()
+            .filter(|c| c.diff_type == diff_type)
+            .map(|c| c.diff_text.lines().count())
+            .sum::<usize>()
+    };
+    let text = format_sstr!(
+        "commit {} added and {} removed lines to {}?",
+        count("add"),
+        count("rem"),
+        first.diary_date
+    );
+    Ok(BotReply::from(text)
+        .with_button(
+            locale.text(Message::Confirm),
+            format_sstr!("/commit {}", first.conflict_group),
+        )
+        .with_button(locale.text(Message::Cancel), "/cancel"))
+}
+
+async fn commit_conflict(
+    dapp: &DiaryAppInterface,
+    datetime: &str,
+    locale: Locale,
+) -> Result<BotReply, Error> {
+    let Some(key) = parse_conflict_key(datetime) else {
+        return Ok(locale.text(Message::InvalidConflictTime).into());
+    };
+    match DiaryCommand::CommitConflict(key).run(dapp).await {
+        Ok(output) => Ok(output.lines().join("\n").into()),
+        Err(e) => Ok(format_sstr!("failed to commit conflict {e}").into()),
+    }
+}
+
+async fn revert_conflict(
+    dapp: &DiaryAppInterface,
+    datetime: &str,
+    locale: Locale,
+) -> Result<BotReply, Error> {
+    let Some(key) = parse_conflict_key(datetime) else {
+        return Ok(locale.text(Message::InvalidConflictTime).into());
+    };
+    match DiaryCommand::RevertConflict(key).run(dapp).await {
+        Ok(output) => Ok(output.lines().join("\n").into()),
+        Err(e) => Ok(format_sstr!("failed to revert conflict {e}").into()),
+    }
+}
+
+/// Lines of a conflict included in a notification
+const PREVIEW_LINES: usize = 6;
+const PREVIEW_LINE_LENGTH: usize = 80;
+
+/// Removed and added lines of a conflict prefixed with `-` and `+`
+#[must_use]
+pub fn conflict_preview(conflicts: &[DiaryConflict]) -> StackString {
+    let mut changed = conflicts
+        .iter()
+        .filter_map(|conflict| {
+            let prefix = match conflict.diff_type.as_str() {
+                "rem" => "-",
+                "add" => "+",
+                _ => return None,
+            };
+            Some(conflict.diff_text.lines().map(move |line| (prefix, line)))
+        })
+        .flatten();
+    let mut preview = StackString::new();
+    for (prefix, line) in changed.by_ref().take(PREVIEW_LINES) {
+        let line: String = line.chars().take(PREVIEW_LINE_LENGTH).collect();
+        preview.push_str(&format_sstr!("{prefix} {line}\n"));
+    }
+    if changed.next().is_some() {
+        preview.push_str("...\n");
+    }
+    preview.trim_end().into()
+}
+
+/// Syncs run one at a time in the background, the output is read with
+/// `/next`
+async fn diary_sync(
+    dapp: DiaryAppInterface,
+    mut recv: Receiver<()>,
+    output_buffer: Arc<RwLock<Vec<StackString>>>,
+) {
+    while recv.recv().await.is_some() {
+        let output = match DiaryCommand::Sync.run(&dapp).await {
+            Ok(output) => {
+                let mut lines = output.lines();
+                lines.sort();
+                lines.join("\n").into()
+            }
+            Err(e) => {
+                if e.downcast_ref::<SyncLockError>().is_none() {
+                    dapp.report_sync_failure("local", None, &e);
+                }
+                format_sstr!("sync failed: {e}")
+            }
+        };
+        let mut buf = output_buffer.write().await;
+        buf.clear();
+        buf.push(output);
+    }
+}
+
+/// Chat messages end up in plaintext backups, warn if one looks like it
+/// contains credentials
+fn secret_warning(dapp: &DiaryAppInterface, text: &str) -> Option<StackString> {
+    let warnings = dapp.scan_secrets(text);
+    if warnings.is_empty() {
+        None
+    } else {
+        Some(format_sstr!("warning: {}", warnings.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{
+        macros::{date, datetime},
+        OffsetDateTime,
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        bot_core::{conflict_preview, help_text, parse_conflict_key, BotCommand, SLASH_COMMANDS},
+        models::{ConflictKey, DiaryConflict},
+    };
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            BotCommand::parse(":s  went hiking"),
+            BotCommand::Search("went hiking")
+        );
+        assert_eq!(
+            BotCommand::parse(":SEARCH today"),
+            BotCommand::Search("today")
+        );
+        assert_eq!(BotCommand::parse(":n"), BotCommand::Next);
+        assert_eq!(BotCommand::parse(":sync"), BotCommand::Sync);
+        assert_eq!(BotCommand::parse(":help"), BotCommand::Help);
+        assert_eq!(
+            BotCommand::parse(":i :i twice"),
+            BotCommand::Insert(":i twice")
+        );
+        assert_eq!(
+            BotCommand::parse("  made dinner"),
+            BotCommand::Insert("made dinner")
+        );
+        assert!(BotCommand::parse(":i note").is_write());
+        assert!(!BotCommand::parse(":s note").is_write());
+    }
+
+    #[test]
+    fn test_parse_slash_command() {
+        assert_eq!(
+            BotCommand::parse("/search went hiking"),
+            BotCommand::Search("went hiking")
+        );
+        assert_eq!(
+            BotCommand::parse("/insert@diary_bot made dinner"),
+            BotCommand::Insert("made dinner")
+        );
+        assert_eq!(BotCommand::parse("/today"), BotCommand::Today);
+        assert_eq!(BotCommand::parse("/start"), BotCommand::Help);
+        assert_eq!(
+            BotCommand::parse("/commit 2024-03-01T08:00:00.0Z"),
+            BotCommand::Commit("2024-03-01T08:00:00.0Z")
+        );
+        assert_eq!(
+            BotCommand::parse("/keep_theirs 2024-03-01T08:00:00.0Z"),
+            BotCommand::Commit("2024-03-01T08:00:00.0Z")
+        );
+        assert!(BotCommand::parse("/keep_mine 2024-03-01T08:00:00.0Z").is_write());
+        assert!(BotCommand::parse("/resolve 2024-03-01T08:00:00.0Z").is_write());
+        assert!(!BotCommand::parse("/conflicts").is_write());
+        assert_eq!(
+            BotCommand::parse(":link AB23CD45"),
+            BotCommand::Link("AB23CD45")
+        );
+        assert_eq!(
+            BotCommand::parse("/link@diary_bot x"),
+            BotCommand::Link("x")
+        );
+        assert!(!BotCommand::parse("/link x").is_write());
+        assert_eq!(
+            BotCommand::parse("/unknown text"),
+            BotCommand::Insert("/unknown text")
+        );
+    }
+
+    #[test]
+    fn test_conflict_preview() {
+        let datetime = OffsetDateTime::now_utc();
+        let conflict = |diff_type, text, sequence| {
+            DiaryConflict::new(
+                "diary",
+                datetime,
+                date!(2024 - 03 - 01),
+                diff_type,
+                text,
+                sequence,
+            )
+        };
+        let conflicts = vec![
+            conflict("same", "went for a walk", 0),
+            conflict("rem", "made dinner", 1),
+            conflict("add", "ordered pizza\nwatched a movie", 2),
+        ];
+        assert_eq!(
+            conflict_preview(&conflicts),
+            "- made dinner\n+ ordered pizza\n+ watched a movie"
+        );
+        let long: Vec<_> = (0..10).map(|i| conflict("add", "line", i)).collect();
+        let preview = conflict_preview(&long);
+        assert_eq!(preview.lines().count(), 7);
+        assert!(preview.ends_with("..."));
+        assert!(conflict_preview(&conflicts[..1]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_conflict_key() {
+        let group = Uuid::new_v4();
+        assert_eq!(
+            parse_conflict_key(&group.to_string()),
+            Some(ConflictKey::Group(group))
+        );
+        assert_eq!(
+            parse_conflict_key("2024-03-01T08:00:00.123Z"),
+            Some(datetime!(2024-03-01 08:00:00.123 UTC).into())
+        );
+        assert_eq!(parse_conflict_key("yesterday"), None);
+    }
+
+    #[test]
+    fn test_help_text() {
+        let help = help_text();
+        for (command, _) in SLASH_COMMANDS {
+            assert!(help.contains(&format!("/{command} =>")));
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use stack_string::StackString;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+use time::{Date, OffsetDateTime};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+use crate::models::{DiaryCache, DiarySubentry};
+
+/// Cache entries older than this many days are reported as stale by
+/// `recover-cache`, which also checks the merges of as many days
+pub const RECOVER_CACHE_DAYS: i64 = 7;
+
+/// Inconsistency left behind by an interrupted merge of the cache
+#[derive(Clone, Debug)]
+pub enum CacheIssue {
+    /// Cache entry older than the cutoff, merged on repair
+    Stale(DiaryCache),
+    /// Cache entry with the text of an earlier one for the same date,
+    /// removed on repair
+    Duplicate(DiaryCache),
+    /// Cache entry whose text is already in the entry of its date, kept as a
+    /// subentry and removed on repair
+    Merged(DiaryCache),
+    /// Subentry whose text is in its entry more than once, only reported as
+    /// the copy to drop needs a look
+    DuplicatedText(DiarySubentry),
+}
+
+impl fmt::Display for CacheIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Stale(c) => write!(f, "stale {} {}", c.journal, c.diary_datetime),
+            Self::Duplicate(c) => write!(f, "duplicate {} {}", c.journal, c.diary_datetime),
+            Self::Merged(c) => write!(f, "merged {} {}", c.journal, c.diary_datetime),
+            Self::DuplicatedText(s) => write!(
+                f,
+                "duplicated text {} {} {}",
+                s.journal, s.diary_date, s.diary_datetime
+            ),
+        }
+    }
+}
+
+/// Find the issues among `cache` and `subentries`, `entries` holds the text
+/// of the entries of their journals and dates (local dates in `tz` for the
+/// cache), cache entries written before `cutoff` are stale
+#[must_use]
+pub fn find_cache_issues(
+    cache: &[DiaryCache],
+    subentries: &[DiarySubentry],
+    entries: &HashMap<(StackString, Date), StackString>,
+    cutoff: OffsetDateTime,
+    tz: &Tz,
+) -> Vec<CacheIssue> {
+    let mut cache: Vec<_> = cache.iter().collect();
+    cache.sort_by_key(|c| c.diary_datetime);
+    let mut seen = HashSet::new();
+    let mut issues = Vec::new();
+    for item in cache {
+        let date = item.diary_datetime.to_timezone(tz).date();
+        let text = item.diary_text.trim();
+        if !seen.insert((&item.journal, date, text)) {
+            issues.push(CacheIssue::Duplicate(item.clone()));
+        } else if !text.is_empty()
+            && entries
+                .get(&(item.journal.clone(), date))
+                .is_some_and(|entry| entry.contains(text))
+        {
+            issues.push(CacheIssue::Merged(item.clone()));
+        } else if *item.diary_datetime < cutoff {
+            issues.push(CacheIssue::Stale(item.clone()));
+        }
+    }
+    for subentry in subentries {
+        let text = subentry.diary_text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if entries
+            .get(&(subentry.journal.clone(), subentry.diary_date))
+            .is_some_and(|entry| entry.matches(text).count() > 1)
+        {
+            issues.push(CacheIssue::DuplicatedText(subentry.clone()));
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+    use std::collections::HashMap;
+    use time::macros::{date, datetime};
+    use time_tz::timezones::db::UTC;
+
+    use crate::{
+        cache_recovery::find_cache_issues,
+        models::{DiaryCache, DiarySubentry},
+    };
+
+    #[test]
+    fn test_find_cache_issues() {
+        let cache = |text: &str, datetime| DiaryCache {
+            diary_datetime: datetime.into(),
+            diary_text: text.into(),
+            journal: "diary".into(),
+            idempotency_key: None,
+            author: None,
+            source: None,
+        };
+        let cache = [
+            cache("went hiking", datetime!(2024-03-01 10:00 UTC)),
+            cache("went hiking", datetime!(2024-03-01 11:00 UTC)),
+            cache("already merged", datetime!(2024-03-01 12:00 UTC)),
+            cache("left behind", datetime!(2024-02-01 12:00 UTC)),
+            cache("just written", datetime!(2024-03-08 12:00 UTC)),
+        ];
+        let subentry = DiarySubentry {
+            journal: "diary".into(),
+            diary_date: date!(2024 - 03 - 02),
+            diary_datetime: datetime!(2024-03-02 09:00 UTC).into(),
+            diary_text: "twice".into(),
+            source: None,
+            author: None,
+        };
+        let entries: HashMap<(StackString, _), StackString> = [
+            (
+                ("diary".into(), date!(2024 - 03 - 01)),
+                "morning\n\nalready merged".into(),
+            ),
+            (
+                ("diary".into(), date!(2024 - 03 - 02)),
+                "twice\n\ntwice".into(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let issues: Vec<_> = find_cache_issues(
+            &cache,
+            &[subentry],
+            &entries,
+            datetime!(2024-03-07 00:00 UTC),
+            UTC,
+        )
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        assert_eq!(
+            issues,
+            vec![
+                "stale diary 2024-02-01T12:00:00.0Z",
+                "stale diary 2024-03-01T10:00:00.0Z",
+                "duplicate diary 2024-03-01T11:00:00.0Z",
+                "merged diary 2024-03-01T12:00:00.0Z",
+                "duplicated text diary 2024-03-02 2024-03-02T09:00:00.0Z",
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Error;
+use futures::{stream::poll_fn, StreamExt};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::time::Duration;
+use time::Date;
+use tokio::{
+    sync::{
+        broadcast::{channel, Receiver, Sender},
+        mpsc::unbounded_channel,
+    },
+    task::spawn,
+    time::sleep,
+};
+use tokio_postgres::{connect, AsyncMessage, NoTls};
+
+/// Channel the triggers on `diary_entries` and `diary_conflict` notify
+pub const CHANGE_CHANNEL: &str = "diary_changes";
+
+/// Changes kept for subscribers that fall behind, older ones are dropped
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Wait before listening again after the connection is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Row written by any process sharing the database, the cli, the bots or
+/// another api server
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DiaryChange {
+    /// `diary_entries` or `diary_conflict`
+    pub table: StackString,
+    /// `insert`, `update` or `delete`
+    pub operation: StackString,
+    pub journal: StackString,
+    pub diary_date: Date,
+}
+
+impl DiaryChange {
+    /// # Errors
+    /// Return error if the payload isn't a change
+    pub fn from_payload(payload: &str) -> Result<Self, Error> {
+        serde_json::from_str(payload).map_err(Into::into)
+    }
+}
+
+/// Changes to the database published to the frontends of this process
+#[derive(Clone, Debug)]
+pub struct ChangeFeed(Sender<DiaryChange>);
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeFeed {
+    #[must_use]
+    pub fn new() -> Self {
+        let (send, _) = channel(CHANNEL_CAPACITY);
+        Self(send)
+    }
+
+    #[must_use]
+    pub fn subscribe(&self) -> Receiver<DiaryChange> {
+        self.0.subscribe()
+    }
+
+    pub fn publish(&self, change: DiaryChange) {
+        // nobody is listening until a frontend connects
+        self.0.send(change).ok();
+    }
+
+    /// Listen for changes to the database at `database_url` in the
+    /// background, listening again whenever the connection is lost
+    pub fn listen(&self, database_url: &str) {
+        let feed = self.clone();
+        let database_url: StackString = database_url.into();
+        spawn(async move {
+            loop {
+                if let Err(e) = feed.listen_once(&database_url).await {
+                    error!("listening for {CHANGE_CHANNEL} failed {e}");
+                }
+                sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+
+    async fn listen_once(&self, database_url: &str) -> Result<(), Error> {
+        let (client, mut connection) = connect(database_url, NoTls).await?;
+        let (send, mut recv) = unbounded_channel();
+        // the connection has to be polled for the notifications to arrive
+        let driver = spawn(async move {
+            let mut messages = poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(message) = messages.next().await {
+                if let AsyncMessage::Notification(notification) = message? {
+                    if send.send(notification).is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok::<_, tokio_postgres::Error>(())
+        });
+        client
+            .batch_execute(&format_sstr!("LISTEN {CHANGE_CHANNEL}"))
+            .await?;
+        info!("listening for {CHANGE_CHANNEL}");
+        while let Some(notification) = recv.recv().await {
+            match DiaryChange::from_payload(notification.payload()) {
+                Ok(change) => {
+                    debug!("change {change:?}");
+                    self.publish(change);
+                }
+                Err(e) => error!("bad {CHANGE_CHANNEL} payload {e}"),
+            }
+        }
+        driver.await??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::date;
+
+    use crate::change_feed::{ChangeFeed, DiaryChange};
+
+    #[test]
+    fn test_diary_change_from_payload() -> Result<(), Error> {
+        let payload = r#"{
+            "table" : "diary_entries",
+            "operation" : "update",
+            "journal" : "diary",
+            "diary_date" : "2024-03-05"
+        }"#;
+        let change = DiaryChange::from_payload(payload)?;
+        assert_eq!(change.table, "diary_entries");
+        assert_eq!(change.operation, "update");
+        assert_eq!(change.journal, "diary");
+        assert_eq!(change.diary_date, date!(2024 - 03 - 05));
+        assert!(DiaryChange::from_payload("{}").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_feed() -> Result<(), Error> {
+        let feed = ChangeFeed::new();
+        let change = DiaryChange {
+            table: "diary_conflict".into(),
+            operation: "insert".into(),
+            journal: "dreams".into(),
+            diary_date: date!(2024 - 03 - 05),
+        };
+        // publishing without subscribers is fine
+        feed.publish(change.clone());
+        let mut recv = feed.subscribe();
+        feed.publish(change.clone());
+        assert_eq!(recv.recv().await?, change);
+        Ok(())
+    }
+}
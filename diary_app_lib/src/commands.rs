@@ -0,0 +1,245 @@
+use anyhow::Error;
+use stack_string::{format_sstr, StackString};
+use time::Date;
+use uuid::Uuid;
+
+use crate::{
+    diary_app_interface::DiaryAppInterface,
+    models::{ConflictKey, DiaryCache, DiaryConflict, DiaryEntries},
+    services::{ConflictList, ConflictUpdate, DiaryService, SearchQuery},
+};
+
+/// Commands the cli and the bots have in common, each frontend parses its
+/// own syntax into these and renders the [`CommandOutput`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiaryCommand {
+    Search(StackString),
+    Insert(StackString),
+    Sync,
+    /// Dates with conflicts, or the syncs which recorded those of a date
+    ListConflicts(Option<Date>),
+    ShowConflict(ConflictKey),
+    RemoveConflict(ConflictKey),
+    /// Keep the text the sync brought in and drop the conflict
+    CommitConflict(ConflictKey),
+    /// Keep the text from before the sync and drop the conflict
+    RevertConflict(ConflictKey),
+    CleanConflicts(Date),
+    /// Mark a line of a conflict as `add` or `rem`
+    UpdateConflict {
+        id: Uuid,
+        diff_type: StackString,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub enum CommandOutput {
+    Lines(Vec<StackString>),
+    Cached(DiaryCache),
+    Conflicts(ConflictList),
+    Conflict(Vec<DiaryConflict>),
+    Committed(DiaryEntries),
+    Reverted(DiaryEntries),
+    Removed(Vec<ConflictKey>),
+    Updated(Uuid),
+}
+
+impl DiaryCommand {
+    /// Viewers can search and look at conflicts but not change entries
+    #[must_use]
+    pub fn is_write(&self) -> bool {
+        !matches!(
+            self,
+            Self::Search(_) | Self::ListConflicts(_) | Self::ShowConflict(_)
+        )
+    }
+
+    /// # Errors
+    /// Return error if the command fails
+    pub async fn run(self, dapp: &DiaryAppInterface) -> Result<CommandOutput, Error> {
+        let service = DiaryService::new(dapp);
+        match self {
+            Self::Search(text) => {
+                let query = SearchQuery {
+                    text: Some(text),
+                    ..SearchQuery::default()
+                };
+                Ok(CommandOutput::Lines(service.search(query).await?.entries))
+            }
+            Self::Insert(text) => Ok(CommandOutput::Cached(service.insert_text(&text).await?)),
+            Self::Sync => Ok(CommandOutput::Lines(service.sync().await?)),
+            Self::ListConflicts(date) => Ok(CommandOutput::Conflicts(
+                service.list_conflicts(date).await?,
+            )),
+            Self::ShowConflict(key) => {
+                Ok(CommandOutput::Conflict(service.show_conflict(key).await?))
+            }
+            Self::RemoveConflict(key) => {
+                service.remove_conflict(key).await?;
+                Ok(CommandOutput::Removed(vec![key]))
+            }
+            Self::CommitConflict(key) => {
+                let (entry, _) = service.commit_conflict(key).await?;
+                DiaryConflict::remove_by_key(key, &dapp.pool).await?;
+                Ok(CommandOutput::Committed(entry))
+            }
+            Self::RevertConflict(key) => {
+                let entry = service.revert_conflict(key).await?;
+                DiaryConflict::remove_by_key(key, &dapp.pool).await?;
+                Ok(CommandOutput::Reverted(entry))
+            }
+            Self::CleanConflicts(date) => {
+                let removed = service.clean_conflicts(date).await?;
+                Ok(CommandOutput::Removed(
+                    removed.into_iter().map(Into::into).collect(),
+                ))
+            }
+            Self::UpdateConflict { id, diff_type } => {
+                service
+                    .update_conflict(ConflictUpdate { id, diff_type })
+                    .await?;
+                Ok(CommandOutput::Updated(id))
+            }
+        }
+    }
+}
+
+impl CommandOutput {
+    /// Plain text of the output, conflict lines are prefixed with `-` and `+`
+    #[must_use]
+    pub fn lines(&self) -> Vec<StackString> {
+        match self {
+            Self::Lines(lines) => lines.clone(),
+            Self::Cached(cache) => vec![format_sstr!("cached entry {}", cache.diary_datetime)],
+            Self::Conflicts(ConflictList::Dates(dates)) => {
+                dates.iter().map(StackString::from_display).collect()
+            }
+            Self::Conflicts(ConflictList::Syncs(syncs)) => {
+                syncs.iter().map(StackString::from_display).collect()
+            }
+            Self::Conflict(conflicts) => conflicts
+                .iter()
+                .map(|conflict| {
+                    let prefix = match conflict.diff_type.as_str() {
+                        "rem" => "-",
+                        "add" => "+",
+                        _ => " ",
+                    };
+                    format_sstr!("{prefix} {}", conflict.diff_text)
+                })
+                .collect(),
+            Self::Committed(entry) => {
+                vec![format_sstr!("committed conflict for {}", entry.diary_date)]
+            }
+            Self::Reverted(entry) => vec![format_sstr!(
+                "restored {} from before the sync",
+                entry.diary_date
+            )],
+            Self::Removed(syncs) => syncs
+                .iter()
+                .map(|key| format_sstr!("remove {key}"))
+                .collect(),
+            Self::Updated(id) => vec![format_sstr!("updated {id}")],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::format_sstr;
+    use time::macros::date;
+    use uuid::Uuid;
+
+    use crate::{
+        commands::{CommandOutput, DiaryCommand},
+        config::Config,
+        diary_app_interface::DiaryAppInterface,
+        models::DiaryEntries,
+        services::ConflictList,
+        test_db::{ConflictFixture, TestDb},
+    };
+
+    #[test]
+    fn test_is_write() {
+        assert!(!DiaryCommand::Search("text".into()).is_write());
+        assert!(!DiaryCommand::ListConflicts(None).is_write());
+        assert!(DiaryCommand::Sync.is_write());
+        assert!(DiaryCommand::CleanConflicts(date!(2011 - 05 - 23)).is_write());
+        let command = DiaryCommand::UpdateConflict {
+            id: Uuid::new_v4(),
+            diff_type: "add".into(),
+        };
+        assert!(command.is_write());
+    }
+
+    #[test]
+    fn test_command_output_lines() {
+        let output = CommandOutput::Conflicts(ConflictList::Dates(vec![
+            date!(2011 - 05 - 23),
+            date!(2011 - 05 - 24),
+        ]));
+        assert_eq!(output.lines(), ["2011-05-23", "2011-05-24"]);
+        let entry = DiaryEntries::new(date!(2011 - 05 - 23), "text");
+        let output = CommandOutput::Committed(entry);
+        assert_eq!(output.lines(), ["committed conflict for 2011-05-23"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_commit_conflict_command() -> Result<(), Error> {
+        let config = Config::init_config()?;
+        let sdk_config = aws_config::load_from_env().await;
+        let db = TestDb::new().await?;
+        let dap = DiaryAppInterface::new(config, &sdk_config, db.pool.clone());
+        let datetime = ConflictFixture::new(date!(2011 - 05 - 23), "a\nb", "a\nc")
+            .insert(&dap.pool)
+            .await?;
+
+        let output = DiaryCommand::CommitConflict(datetime.into())
+            .run(&dap)
+            .await?;
+        let CommandOutput::Committed(entry) = output else {
+            panic!("expected a committed entry");
+        };
+        assert_eq!(entry.diary_text, "a\nc");
+        let output = DiaryCommand::ListConflicts(None).run(&dap).await?;
+        assert!(output.lines().is_empty());
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_update_conflict_command() -> Result<(), Error> {
+        let config = Config::init_config()?;
+        let sdk_config = aws_config::load_from_env().await;
+        let db = TestDb::new().await?;
+        let dap = DiaryAppInterface::new(config, &sdk_config, db.pool.clone());
+        let datetime = ConflictFixture::new(date!(2011 - 05 - 23), "a\nb", "a\nc")
+            .insert(&dap.pool)
+            .await?;
+
+        let CommandOutput::Conflict(conflicts) = DiaryCommand::ShowConflict(datetime.into())
+            .run(&dap)
+            .await?
+        else {
+            panic!("expected conflict lines");
+        };
+        let removed = conflicts.iter().find(|c| c.diff_type == "rem").unwrap();
+        let command = DiaryCommand::UpdateConflict {
+            id: removed.id,
+            diff_type: "add".into(),
+        };
+        let output = command.run(&dap).await?;
+        assert_eq!(output.lines(), [format_sstr!("updated {}", removed.id)]);
+        let output = DiaryCommand::ShowConflict(datetime.into())
+            .run(&dap)
+            .await?;
+        assert!(!output.lines().iter().any(|line| line.starts_with('-')));
+
+        let command = DiaryCommand::UpdateConflict {
+            id: removed.id,
+            diff_type: "same".into(),
+        };
+        assert!(command.run(&dap).await.is_err());
+        db.cleanup().await
+    }
+}
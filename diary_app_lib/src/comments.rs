@@ -0,0 +1,54 @@
+use stack_string::{format_sstr, StackString};
+use thiserror::Error as ThisError;
+use time::Date;
+use uuid::Uuid;
+
+use crate::models::DiaryComment;
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum CommentError {
+    #[error("No entry for {0}")]
+    NoEntry(Date),
+    #[error("Comment {0} not found")]
+    NotFound(Uuid),
+    #[error("Only the author can change a comment")]
+    NotAuthor,
+    #[error("Comment must not be empty")]
+    Empty,
+}
+
+/// Plain text listing of `comments` written next to the year export
+#[must_use]
+pub fn format_comments(comments: &[DiaryComment]) -> StackString {
+    let mut output = StackString::new();
+    for comment in comments {
+        output.push_str(&format_sstr!(
+            "{date} {author} {created_at}\n{text}\n\n",
+            date = comment.diary_date,
+            author = comment.author,
+            created_at = comment.created_at,
+            text = comment.comment_text.trim(),
+        ));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use crate::{comments::format_comments, models::DiaryComment};
+
+    #[test]
+    fn test_format_comments() {
+        let first = DiaryComment::new("diary", date!(2024 - 03 - 01), "bob@example.com", "nice!\n");
+        let second = DiaryComment::new("diary", date!(2024 - 03 - 02), "amy@example.com", "same");
+        let output = format_comments(&[first.clone(), second.clone()]);
+        let expected = format!(
+            "2024-03-01 bob@example.com {}\nnice!\n\n2024-03-02 amy@example.com {}\nsame\n\n",
+            first.created_at, second.created_at
+        );
+        assert_eq!(output, expected);
+        assert!(format_comments(&[]).is_empty());
+    }
+}
@@ -4,30 +4,201 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use stack_string::StackString;
+use stack_string::{format_sstr, StackString};
 
-#[derive(Default, Debug, Deserialize)]
+use crate::{
+    archive::Compression,
+    i18n::Locale,
+    models::DEFAULT_JOURNAL,
+    retry::{JitterStrategy, RetryPolicy},
+};
+
+#[derive(Default, Debug, Clone, Deserialize)]
 pub struct ConfigInner {
     pub database_url: StackString,
     #[serde(default = "default_diary_bucket")]
     pub diary_bucket: StackString,
     #[serde(default = "default_diary_path")]
     pub diary_path: PathBuf,
+    /// Prefix of every key in `diary_bucket`, e.g. `.tenants/smith/`, set
+    /// for tenants sharing the bucket
+    pub s3_prefix: Option<StackString>,
     #[serde(default = "default_aws_region_name")]
     pub aws_region_name: StackString,
     #[serde(default)]
     pub telegram_bot_token: StackString,
+    /// Public https url telegram posts updates to, the bot long polls when
+    /// unset
+    pub telegram_webhook_url: Option<StackString>,
+    /// Compared with the secret token header of webhook requests
+    pub telegram_webhook_secret: Option<StackString>,
+    #[serde(default = "default_telegram_webhook_port")]
+    pub telegram_webhook_port: u16,
+    /// Serve the webhook from the api at `/api/telegram/webhook` instead of
+    /// running diary-app-bot
+    #[serde(default)]
+    pub telegram_webhook_in_api: bool,
+    /// Homeserver and login of the matrix bot account
+    pub matrix_homeserver: Option<StackString>,
+    pub matrix_username: Option<StackString>,
+    pub matrix_password: Option<StackString>,
     pub ssh_url: Option<StackString>,
+    pub ssh_identity_file: Option<PathBuf>,
+    pub ssh_known_hosts_file: Option<PathBuf>,
+    pub ssh_strict_host_key_checking: Option<StackString>,
+    #[serde(default = "default_ssh_connect_timeout")]
+    pub ssh_connect_timeout: u64,
+    pub peer_url: Option<StackString>,
+    pub peer_token: Option<StackString>,
+    /// PIN required to unlock entries in the web UI
+    pub unlock_pin: Option<StackString>,
+    /// Base32 TOTP secret accepted as an alternative to `unlock_pin`
+    pub unlock_totp_secret: Option<StackString>,
+    #[serde(default = "default_unlock_timeout")]
+    pub unlock_timeout: u64,
+    /// Days an entry stays in the trash before being purged
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: i64,
+    /// Days a deleted date is protected from being re-imported
+    #[serde(default = "default_tombstone_retention_days")]
+    pub tombstone_retention_days: i64,
+    /// Local hour after which the daily resurfaced entry is sent
+    #[serde(default = "default_resurface_hour")]
+    pub resurface_hour: u8,
+    /// Message sent with a resurfaced entry, `{date}`, `{years}` and `{text}`
+    /// are replaced
+    #[serde(default = "default_resurface_template")]
+    pub resurface_template: StackString,
+    #[serde(default = "default_resurface_anniversary_weight")]
+    pub resurface_anniversary_weight: f64,
+    #[serde(default = "default_resurface_nearby_weight")]
+    pub resurface_nearby_weight: f64,
+    #[serde(default = "default_resurface_other_weight")]
+    pub resurface_other_weight: f64,
+    #[serde(default = "default_resurface_min_age_days")]
+    pub resurface_min_age_days: i64,
+    /// Compression of year exports older than `archive_after_years`
+    #[serde(default)]
+    pub archive_compression: Compression,
+    #[serde(default = "default_archive_after_years")]
+    pub archive_after_years: i32,
+    /// Key encrypting the original text of redacted entries, created on
+    /// first use
+    #[serde(default = "default_redaction_key_path")]
+    pub redaction_key_path: PathBuf,
+    #[serde(default = "default_redaction_placeholder")]
+    pub redaction_placeholder: StackString,
+    /// Warn when saved text looks like it contains credentials
+    #[serde(default = "default_secret_scan")]
+    pub secret_scan: bool,
+    /// Additional regex flagged by the secret scanner
+    pub secret_scan_pattern: Option<StackString>,
+    /// Accept notes from people holding a `guestbook_tokens` entry without
+    /// logging in
+    #[serde(default)]
+    pub guestbook_enabled: bool,
+    /// Comma separated `name:token` pairs
+    pub guestbook_tokens: Option<StackString>,
+    #[serde(default = "default_guestbook_max_length")]
+    pub guestbook_max_length: usize,
+    /// Submissions allowed per client address every
+    /// `guestbook_rate_window_secs`
+    #[serde(default = "default_guestbook_rate_limit")]
+    pub guestbook_rate_limit: usize,
+    #[serde(default = "default_guestbook_rate_window_secs")]
+    pub guestbook_rate_window_secs: u64,
+    /// Comma separated `name:token` pairs of devices allowed to read
+    /// today's entry from `/api/today/plain`
+    pub kiosk_tokens: Option<StackString>,
+    /// Journal shown by `/api/today/plain`, the default journal when unset
+    pub kiosk_journal: Option<StackString>,
+    /// `|` separated prompts shown below today's entry
+    pub kiosk_prompts: Option<StackString>,
+    /// Write the comments of each year next to its export
+    #[serde(default)]
+    pub export_comments: bool,
+    /// Days before replaced versions of objects in `diary_bucket` move to
+    /// glacier, applied by the `s3-lifecycle` command
+    #[serde(default = "default_s3_glacier_transition_days")]
+    pub s3_glacier_transition_days: i32,
+    /// Days before replaced versions are deleted, kept forever when unset
+    pub s3_noncurrent_expiration_days: Option<i32>,
+    /// Second copy of `diary_bucket`, ideally in another region, checked
+    /// and repaired by the `validate-replica` command
+    pub replica_bucket: Option<StackString>,
+    /// Region of `replica_bucket`, the default region when unset
+    pub replica_region: Option<StackString>,
+    /// Compression of entries uploaded to s3, existing objects are
+    /// recompressed by the `s3-recompress` command
+    #[serde(default)]
+    pub s3_compression: Compression,
+    /// Entries at least this many bytes long are uploaded to s3 as diffs
+    /// against the previous upload, always uploaded whole when unset
+    pub s3_delta_min_size: Option<usize>,
+    /// Diffs uploaded before the next full snapshot of an entry
+    #[serde(default = "default_s3_snapshot_every")]
+    pub s3_snapshot_every: u32,
+    /// Characters allowed in an entry or cached text, longer text is
+    /// rejected before it reaches the database
+    #[serde(default = "default_max_entry_length")]
+    pub max_entry_length: usize,
+    /// Bytes allowed in the body of requests writing text to the api
+    #[serde(default = "default_max_request_bytes")]
+    pub max_request_bytes: u64,
     #[serde(default = "default_host")]
     pub host: StackString,
     #[serde(default = "default_port")]
     pub port: u32,
     #[serde(default = "default_domain")]
     pub domain: StackString,
+    /// Errors, panics and failed syncs are reported to this sentry project
+    pub sentry_dsn: Option<StackString>,
+    pub sentry_environment: Option<StackString>,
+    /// Comma separated origins, e.g. `https://diary.example.com`, allowed to
+    /// make requests to the api with the auth cookie from a browser
+    pub cors_origins: Option<StackString>,
+    /// Replaces the default `Content-Security-Policy` header
+    pub content_security_policy: Option<StackString>,
+    /// Days after which a displayed entry is cached by browsers for a day
+    /// instead of being revalidated on every view
+    #[serde(default = "default_display_cache_days")]
+    pub display_cache_days: i64,
+    /// Directory of replacements for the embedded `scripts.js`, `style.css`
+    /// and other web templates, read once at startup
+    pub template_dir: Option<PathBuf>,
+    /// Language of the web UI and bot replies for users who haven't picked
+    /// one in their settings, and of the kiosk view
+    #[serde(default)]
+    pub default_locale: Locale,
+    /// Json list of tenants the api serves next to the default diary, each
+    /// with its own database, files and s3 keys
+    pub tenants_file: Option<PathBuf>,
+    /// Apply pending migrations when the api starts, without it the api
+    /// refuses to start until `run-migrations` has been run
+    #[serde(default)]
+    pub auto_migrate: bool,
+    /// Scheme of the public url of the api, listed in the openapi servers
+    #[serde(default = "default_api_scheme")]
+    pub api_scheme: StackString,
     #[serde(default = "default_n_db_workers")]
     pub n_db_workers: usize,
+    #[serde(default = "default_sync_concurrency")]
+    pub sync_concurrency: usize,
+    #[serde(default = "default_retry_max_attempts")]
+    pub retry_max_attempts: usize,
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub retry_max_delay_ms: u64,
+    #[serde(default)]
+    pub retry_jitter: JitterStrategy,
+    #[serde(default = "default_circuit_breaker_threshold")]
+    pub circuit_breaker_threshold: usize,
+    #[serde(default = "default_circuit_breaker_reset_secs")]
+    pub circuit_breaker_reset_secs: u64,
     #[serde(default = "default_home_dir")]
     pub home_dir: PathBuf,
     #[serde(default = "default_secret_path")]
@@ -55,12 +226,105 @@ fn default_host() -> StackString {
 fn default_port() -> u32 {
     3042
 }
+fn default_telegram_webhook_port() -> u16 {
+    3043
+}
 fn default_domain() -> StackString {
     "localhost".into()
 }
+fn default_api_scheme() -> StackString {
+    "https".into()
+}
+fn default_display_cache_days() -> i64 {
+    30
+}
+fn default_ssh_connect_timeout() -> u64 {
+    10
+}
+fn default_unlock_timeout() -> u64 {
+    300
+}
+fn default_trash_retention_days() -> i64 {
+    30
+}
+fn default_tombstone_retention_days() -> i64 {
+    365
+}
+fn default_resurface_hour() -> u8 {
+    8
+}
+fn default_resurface_template() -> StackString {
+    "From {date}, {years} years ago:\n{text}".into()
+}
+fn default_resurface_anniversary_weight() -> f64 {
+    20.0
+}
+fn default_resurface_nearby_weight() -> f64 {
+    5.0
+}
+fn default_resurface_other_weight() -> f64 {
+    1.0
+}
+fn default_resurface_min_age_days() -> i64 {
+    30
+}
+fn default_archive_after_years() -> i32 {
+    2
+}
+fn default_redaction_key_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap()
+        .join("diary_app_rust")
+        .join("redaction.key")
+}
+fn default_redaction_placeholder() -> StackString {
+    "[REDACTED]".into()
+}
+fn default_secret_scan() -> bool {
+    true
+}
+fn default_guestbook_max_length() -> usize {
+    1000
+}
+fn default_guestbook_rate_limit() -> usize {
+    5
+}
+fn default_guestbook_rate_window_secs() -> u64 {
+    3600
+}
+fn default_s3_glacier_transition_days() -> i32 {
+    30
+}
+fn default_s3_snapshot_every() -> u32 {
+    10
+}
+fn default_max_entry_length() -> usize {
+    200_000
+}
+fn default_max_request_bytes() -> u64 {
+    2 * 1024 * 1024
+}
 fn default_n_db_workers() -> usize {
     2
 }
+fn default_sync_concurrency() -> usize {
+    10
+}
+fn default_retry_max_attempts() -> usize {
+    5
+}
+fn default_retry_base_delay_ms() -> u64 {
+    1000
+}
+fn default_retry_max_delay_ms() -> u64 {
+    64000
+}
+fn default_circuit_breaker_threshold() -> usize {
+    1
+}
+fn default_circuit_breaker_reset_secs() -> u64 {
+    300
+}
 fn default_aws_region_name() -> StackString {
     "us-east-1".into()
 }
@@ -91,6 +355,45 @@ impl ConfigInner {
 
         envy::from_env().map_err(Into::into)
     }
+
+    /// Entries of the default journal are kept directly in `diary_path`,
+    /// other journals in a subdirectory named after the journal
+    #[must_use]
+    pub fn journal_path(&self, journal: &str) -> PathBuf {
+        if journal == DEFAULT_JOURNAL {
+            self.diary_path.clone()
+        } else {
+            self.diary_path.join(journal)
+        }
+    }
+
+    /// Key prefix of `journal` in `diary_bucket`, only `s3_prefix` for the
+    /// default journal
+    #[must_use]
+    pub fn journal_prefix(&self, journal: &str) -> StackString {
+        let prefix = self.s3_key_prefix();
+        if journal == DEFAULT_JOURNAL {
+            prefix.into()
+        } else {
+            format_sstr!("{prefix}{journal}/")
+        }
+    }
+
+    /// `s3_prefix`, empty when unset
+    #[must_use]
+    pub fn s3_key_prefix(&self) -> &str {
+        self.s3_prefix.as_deref().unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_max_attempts,
+            base_delay: Duration::from_millis(self.retry_base_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+            jitter: self.retry_jitter,
+        }
+    }
 }
 
 impl Config {
@@ -117,6 +420,12 @@ impl Config {
     }
 }
 
+impl From<ConfigInner> for Config {
+    fn from(config: ConfigInner) -> Self {
+        Self(Arc::new(config))
+    }
+}
+
 impl Deref for Config {
     type Target = ConfigInner;
 
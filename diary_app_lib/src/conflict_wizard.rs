@@ -0,0 +1,309 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{
+    env, fs,
+    io::{BufRead, Write},
+    process::Command,
+};
+use uuid::Uuid;
+
+use crate::{line_diff::DiffTag, models::DiaryConflict};
+
+/// Part of a conflict, either lines both versions agree on or a change the
+/// wizard asks about
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConflictPart {
+    Same(StackString),
+    /// Consecutive removed and added hunks between unchanged ones
+    Change {
+        removed: Option<StackString>,
+        added: Option<StackString>,
+    },
+}
+
+/// Answer to a change, like `git add -p`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HunkChoice {
+    /// Keep the lines the sync brought in
+    Accept,
+    /// Keep the lines from before the sync
+    Reject,
+    /// Replace the change with edited lines, `None` drops it
+    Edit(Option<StackString>),
+}
+
+/// Hunks of a conflict grouped into the changes to answer
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConflictWizard {
+    pub parts: Vec<ConflictPart>,
+}
+
+impl ConflictWizard {
+    /// `conflicts` are the rows of one conflict ordered by `sequence`
+    #[must_use]
+    pub fn new(conflicts: &[DiaryConflict]) -> Self {
+        let mut parts = Vec::new();
+        for hunk in DiaryConflict::hunks(conflicts) {
+            let text = hunk.text;
+            match (hunk.tag, parts.last_mut()) {
+                (DiffTag::Same, _) => parts.push(ConflictPart::Same(text)),
+                (DiffTag::Rem, Some(ConflictPart::Change { removed, .. })) => {
+                    *removed = Some(join_lines(removed.take(), text));
+                }
+                (DiffTag::Add, Some(ConflictPart::Change { added, .. })) => {
+                    *added = Some(join_lines(added.take(), text));
+                }
+                (DiffTag::Rem, _) => parts.push(ConflictPart::Change {
+                    removed: Some(text),
+                    added: None,
+                }),
+                (DiffTag::Add, _) => parts.push(ConflictPart::Change {
+                    removed: None,
+                    added: Some(text),
+                }),
+            }
+        }
+        Self { parts }
+    }
+
+    #[must_use]
+    pub fn nchanges(&self) -> usize {
+        self.parts
+            .iter()
+            .filter(|part| matches!(part, ConflictPart::Change { .. }))
+            .count()
+    }
+
+    /// Text of the entry with one choice per change, in order
+    /// # Errors
+    /// Return error if there isn't a choice for every change
+    pub fn merge(&self, choices: &[HunkChoice]) -> Result<StackString, Error> {
+        if choices.len() != self.nchanges() {
+            return Err(format_err!(
+                "{} choices for {} changes",
+                choices.len(),
+                self.nchanges()
+            ));
+        }
+        let mut choices = choices.iter();
+        let lines: Vec<&str> = self
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                ConflictPart::Same(text) => Some(text.as_str()),
+                ConflictPart::Change { removed, added } => match choices.next()? {
+                    HunkChoice::Accept => added.as_deref(),
+                    HunkChoice::Reject => removed.as_deref(),
+                    HunkChoice::Edit(text) => text.as_deref(),
+                },
+            })
+            .collect();
+        Ok(lines.join("\n").into())
+    }
+
+    /// Ask about every change on `input`, `None` if the user quits, edited
+    /// changes are opened in `$EDITOR`
+    /// # Errors
+    /// Return error if reading the answers or running the editor fails
+    pub fn prompt(
+        &self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<Option<Vec<HunkChoice>>, Error> {
+        self.prompt_with(input, output, edit_in_editor)
+    }
+
+    fn prompt_with(
+        &self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+        edit: impl Fn(&str) -> Result<StackString, Error>,
+    ) -> Result<Option<Vec<HunkChoice>>, Error> {
+        let nchanges = self.nchanges();
+        let mut choices = Vec::with_capacity(nchanges);
+        let mut context: Option<&str> = None;
+        for part in &self.parts {
+            let (removed, added) = match part {
+                ConflictPart::Same(text) => {
+                    context = text.rsplit('\n').next();
+                    continue;
+                }
+                ConflictPart::Change { removed, added } => (removed, added),
+            };
+            writeln!(output, "({}/{nchanges})", choices.len() + 1)?;
+            if let Some(line) = context {
+                writeln!(output, "  {line}")?;
+            }
+            for line in removed.iter().flat_map(|text| text.split('\n')) {
+                writeln!(output, "\x1b[91m- {line}\x1b[0m")?;
+            }
+            for line in added.iter().flat_map(|text| text.split('\n')) {
+                writeln!(output, "\x1b[92m+ {line}\x1b[0m")?;
+            }
+            let choice = loop {
+                write!(output, "Apply this change [y,n,e,q,?]? ")?;
+                output.flush()?;
+                let mut answer = String::new();
+                if input.read_line(&mut answer)? == 0 {
+                    return Ok(None);
+                }
+                match answer.trim() {
+                    "y" => break HunkChoice::Accept,
+                    "n" => break HunkChoice::Reject,
+                    "e" => {
+                        let template = edit_template(removed.as_deref(), added.as_deref());
+                        break HunkChoice::Edit(parse_edited(&edit(&template)?));
+                    }
+                    "q" => return Ok(None),
+                    _ => writeln!(
+                        output,
+                        "y - keep the lines the sync brought in\n\
+                         n - keep the lines from before the sync\n\
+                         e - edit the change\n\
+                         q - quit without changing the entry"
+                    )?,
+                }
+            };
+            choices.push(choice);
+        }
+        Ok(Some(choices))
+    }
+}
+
+fn join_lines(text: Option<StackString>, lines: StackString) -> StackString {
+    match text {
+        Some(text) => format_sstr!("{text}\n{lines}"),
+        None => lines,
+    }
+}
+
+/// The change in diff form, the lines left after editing are kept
+fn edit_template(removed: Option<&str>, added: Option<&str>) -> StackString {
+    let mut template = StackString::from(
+        "# Lines starting with - are dropped, + and space are kept.\n\
+         # Lines starting with # are ignored.\n",
+    );
+    for line in removed.iter().flat_map(|text| text.split('\n')) {
+        template.push_str(&format_sstr!("-{line}\n"));
+    }
+    for line in added.iter().flat_map(|text| text.split('\n')) {
+        template.push_str(&format_sstr!("+{line}\n"));
+    }
+    template
+}
+
+/// Lines kept from an edited template, `None` if none are left
+fn parse_edited(text: &str) -> Option<StackString> {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix('+')
+                .or_else(|| line.strip_prefix(' '))
+                .or_else(|| line.is_empty().then_some(""))
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n").into())
+    }
+}
+
+fn edit_in_editor(template: &str) -> Result<StackString, Error> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    let path = env::temp_dir().join(format_sstr!("diary_conflict_{}.diff", Uuid::new_v4()));
+    fs::write(&path, template)?;
+    let status = Command::new(editor.as_str()).arg(&path).status();
+    let text = fs::read_to_string(&path);
+    fs::remove_file(&path)?;
+    if !status?.success() {
+        return Err(format_err!("{editor} exited with an error"));
+    }
+    Ok(text?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::io::Cursor;
+    use time::{macros::date, OffsetDateTime};
+
+    use crate::{
+        conflict_wizard::{parse_edited, ConflictPart, ConflictWizard, HunkChoice},
+        line_diff::LineDiff,
+        models::DiaryConflict,
+    };
+
+    fn wizard(original: &str, updated: &str) -> ConflictWizard {
+        let conflicts: Vec<_> = LineDiff::new(original, updated)
+            .hunks
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, hunk)| {
+                DiaryConflict::new(
+                    "diary",
+                    OffsetDateTime::now_utc(),
+                    date!(2011 - 05 - 23),
+                    hunk.tag.to_str(),
+                    hunk.text,
+                    sequence as i32,
+                )
+            })
+            .collect();
+        ConflictWizard::new(&conflicts)
+    }
+
+    #[test]
+    fn test_conflict_wizard_merge() -> Result<(), Error> {
+        let wizard = wizard("a\nb\nc\nd", "a\nB\nc\nd\ne");
+        assert_eq!(wizard.nchanges(), 2);
+        assert_eq!(
+            wizard.parts[1],
+            ConflictPart::Change {
+                removed: Some("b".into()),
+                added: Some("B".into()),
+            }
+        );
+        let choices = [HunkChoice::Accept, HunkChoice::Reject];
+        assert_eq!(wizard.merge(&choices)?, "a\nB\nc\nd");
+        let choices = [HunkChoice::Edit(Some("b\nB".into())), HunkChoice::Accept];
+        assert_eq!(wizard.merge(&choices)?, "a\nb\nB\nc\nd\ne");
+        let choices = [HunkChoice::Edit(None), HunkChoice::Accept];
+        assert_eq!(wizard.merge(&choices)?, "a\nc\nd\ne");
+        assert!(wizard.merge(&[HunkChoice::Accept]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict_wizard_prompt() -> Result<(), Error> {
+        let wizard = wizard("a\nb\nc\nd", "a\nB\nc\nd\ne");
+        let mut output = Vec::new();
+        let mut input = Cursor::new("x\ne\nn\n");
+        let choices = wizard
+            .prompt_with(&mut input, &mut output, |template| {
+                Ok(template.replace("-b", " b").into())
+            })?
+            .unwrap();
+        assert_eq!(
+            choices,
+            [HunkChoice::Edit(Some("b\nB".into())), HunkChoice::Reject]
+        );
+        let output = String::from_utf8(output)?;
+        assert!(output.contains("(1/2)\n  a\n"));
+        assert!(output.contains("q - quit"));
+
+        let mut input = Cursor::new("y\nq\n");
+        let choices = wizard.prompt_with(&mut input, &mut Vec::new(), |_| unreachable!())?;
+        assert!(choices.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_edited() {
+        let text = "# comment\n-b\n+B\n c\n";
+        assert_eq!(parse_edited(text), Some("B\nc".into()));
+        assert_eq!(parse_edited("# comment\n-b\n"), None);
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{fmt, str::FromStr};
+
+use crate::{s3_delta::text_hash, text_normalize::normalize_text};
+
+/// Whether a forced re-sync of one date overwrites the database from the
+/// local file and s3, or the local file and s3 from the database
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDirection {
+    Pull,
+    Push,
+}
+
+impl SyncDirection {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Pull => "pull",
+            Self::Push => "push",
+        }
+    }
+}
+
+impl fmt::Display for SyncDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for SyncDirection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pull" => Ok(Self::Pull),
+            "push" => Ok(Self::Push),
+            _ => Err(format_err!("direction must be pull or push, not {s}")),
+        }
+    }
+}
+
+/// One copy of the entry for a date, anything besides `db`, `local` and `s3`
+/// is taken as the version id of a previous s3 object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryCopy {
+    Db,
+    Local,
+    S3,
+    Version(StackString),
+}
+
+impl fmt::Display for EntryCopy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Db => f.write_str("db"),
+            Self::Local => f.write_str("local"),
+            Self::S3 => f.write_str("s3"),
+            Self::Version(version_id) => write!(f, "s3@{version_id}"),
+        }
+    }
+}
+
+impl FromStr for EntryCopy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err(format_err!(
+                "copy must be db, local, s3 or an s3 version id"
+            )),
+            "db" => Ok(Self::Db),
+            "local" => Ok(Self::Local),
+            "s3" => Ok(Self::S3),
+            version_id => Ok(Self::Version(version_id.into())),
+        }
+    }
+}
+
+/// Text of each copy of a date, `None` where there is no copy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DateCopies {
+    pub db: Option<StackString>,
+    pub local: Option<StackString>,
+    pub s3: Option<StackString>,
+}
+
+impl DateCopies {
+    /// Names of the copies which don't match the database, differences
+    /// `normalize_text` evens out are ignored as writes normalize the text
+    #[must_use]
+    pub fn differing(&self) -> Vec<StackString> {
+        let normalized = |text: &str| normalize_text(text.trim());
+        let db = self.db.as_deref().map(normalized);
+        [("local", &self.local), ("s3", &self.s3)]
+            .into_iter()
+            .filter(|(_, copy)| copy.as_deref().map(normalized) != db)
+            .map(|(name, _)| name.into())
+            .collect()
+    }
+}
+
+/// Outcome of a forced re-sync of one date
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DateSyncReport {
+    /// Copies which differed from the database before the sync
+    pub differed: Vec<StackString>,
+    pub output: Vec<StackString>,
+}
+
+/// Hash of an entry text as compared against sync watermarks, surrounding
+/// whitespace is ignored as imports trim it, as are the differences
+/// `normalize_text` evens out
+#[must_use]
+pub fn sync_hash(text: &str) -> StackString {
+    text_hash(normalize_text(text.trim()).as_str())
+}
+
+/// What importing a backend copy of a date does to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportAction {
+    /// Nothing to import, either the copies match or only the database
+    /// changed since the last sync
+    Skip,
+    /// The database has no entry for the date
+    Insert,
+    /// Only the backend changed since the last sync
+    Replace,
+    /// Both changed since the last sync, or there never was one, whichever
+    /// text isn't kept is recorded as a conflict
+    Conflict,
+}
+
+/// Decide how to import a backend copy from the hashes of the text at the
+/// last sync (the watermark), of the database entry and of the backend copy
+#[must_use]
+pub fn import_action(watermark: Option<&str>, db: Option<&str>, backend: &str) -> ImportAction {
+    match (watermark, db) {
+        (_, None) => ImportAction::Insert,
+        (_, Some(db)) if db == backend => ImportAction::Skip,
+        (Some(watermark), Some(_)) if watermark == backend => ImportAction::Skip,
+        (Some(watermark), Some(db)) if watermark == db => ImportAction::Replace,
+        _ => ImportAction::Conflict,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{option, prelude::*};
+    use stack_string::StackString;
+
+    use crate::date_sync::{import_action, DateCopies, EntryCopy, ImportAction, SyncDirection};
+
+    #[test]
+    fn test_sync_direction() {
+        assert_eq!(
+            "pull".parse::<SyncDirection>().unwrap(),
+            SyncDirection::Pull
+        );
+        assert_eq!(SyncDirection::Push.to_string(), "push");
+        assert!("both".parse::<SyncDirection>().is_err());
+    }
+
+    #[test]
+    fn test_entry_copy() {
+        assert_eq!("db".parse::<EntryCopy>().unwrap(), EntryCopy::Db);
+        assert_eq!("s3".parse::<EntryCopy>().unwrap(), EntryCopy::S3);
+        let version: EntryCopy = "3HL4kqtJlcpXroDTDmJ".parse().unwrap();
+        assert_eq!(version.to_string(), "s3@3HL4kqtJlcpXroDTDmJ");
+        assert!("".parse::<EntryCopy>().is_err());
+    }
+
+    #[test]
+    fn test_differing() {
+        let copies = DateCopies {
+            db: Some("went hiking".into()),
+            local: Some("went hiking\n".into()),
+            s3: Some("went hikin".into()),
+        };
+        assert_eq!(copies.differing(), vec![StackString::from("s3")]);
+        let copies = DateCopies {
+            db: Some("went hiking\nfar".into()),
+            local: Some("went hiking  \r\nfar".into()),
+            s3: Some("went hiking\nfar".into()),
+        };
+        assert!(copies.differing().is_empty());
+        let copies = DateCopies {
+            db: None,
+            local: None,
+            s3: Some("went hiking".into()),
+        };
+        assert_eq!(copies.differing(), vec![StackString::from("s3")]);
+        assert!(DateCopies::default().differing().is_empty());
+    }
+
+    #[test]
+    fn test_import_action() {
+        assert_eq!(import_action(None, None, "b"), ImportAction::Insert);
+        assert_eq!(import_action(Some("a"), None, "b"), ImportAction::Insert);
+        assert_eq!(import_action(None, Some("b"), "b"), ImportAction::Skip);
+        assert_eq!(import_action(None, Some("a"), "b"), ImportAction::Conflict);
+        // only the database changed, export pushes it
+        assert_eq!(import_action(Some("a"), Some("c"), "a"), ImportAction::Skip);
+        assert_eq!(
+            import_action(Some("a"), Some("a"), "b"),
+            ImportAction::Replace
+        );
+        assert_eq!(
+            import_action(Some("a"), Some("c"), "b"),
+            ImportAction::Conflict
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_import_action(
+            watermark in option::of("[ab]"),
+            db in option::of("[ab]"),
+            backend in "[ab]",
+        ) {
+            let action = import_action(watermark.as_deref(), db.as_deref(), &backend);
+            if db.is_none() {
+                prop_assert_eq!(action, ImportAction::Insert);
+            }
+            if db.as_deref() == Some(backend.as_str()) {
+                prop_assert_eq!(action, ImportAction::Skip);
+            }
+            // the database is only overwritten without a conflict when it
+            // didn't change since the last sync
+            if action == ImportAction::Replace {
+                prop_assert!(watermark.is_some());
+                prop_assert_eq!(&watermark, &db);
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Error;
+use log::{info, warn};
+use refinery::embed_migrations;
+use stack_string::{format_sstr, StackString};
+use thiserror::Error as ThisError;
+
+use crate::{models::table_exists, pgpool::PgPool};
+
+embed_migrations!("../migrations");
+
+/// Table refinery records the applied migrations in
+const MIGRATION_TABLE: &str = "refinery_schema_history";
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum SchemaError {
+    #[error(
+        "Database schema is at version {applied}, migrations {pending} are pending, run \
+         `diary-app-rust run-migrations` or set AUTO_MIGRATE=true"
+    )]
+    Pending { applied: i64, pending: StackString },
+}
+
+/// Version of the database schema compared to the migrations built into
+/// this binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaStatus {
+    /// Last applied migration, 0 for a fresh database
+    pub applied: i64,
+    /// Last migration of this build
+    pub latest: i64,
+    /// `V{version}__{name}` of the migrations not applied yet
+    pub pending: Vec<StackString>,
+}
+
+impl SchemaStatus {
+    fn new<'a>(applied: i64, migrations: impl IntoIterator<Item = (i64, &'a str)>) -> Self {
+        let mut latest = 0;
+        let mut pending = Vec::new();
+        for (version, name) in migrations {
+            latest = latest.max(version);
+            if version > applied {
+                pending.push((version, format_sstr!("V{version}__{name}")));
+            }
+        }
+        pending.sort();
+        Self {
+            applied,
+            latest,
+            pending: pending.into_iter().map(|(_, name)| name).collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_current(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn schema_status(pool: &PgPool) -> Result<SchemaStatus, Error> {
+    let runner = migrations::runner();
+    let applied = if table_exists(MIGRATION_TABLE, pool).await? {
+        let mut client = pool.get().await?;
+        runner
+            .get_last_applied_migration_async(&mut **client)
+            .await?
+            .map_or(0, |m| i64::from(m.version()))
+    } else {
+        0
+    };
+    let migrations = runner
+        .get_migrations()
+        .iter()
+        .map(|m| (i64::from(m.version()), m.name()));
+    Ok(SchemaStatus::new(applied, migrations))
+}
+
+/// Apply the pending migrations, returns the names of those applied
+/// # Errors
+/// Return error if a migration fails
+pub async fn run_migrations(pool: &PgPool) -> Result<Vec<StackString>, Error> {
+    let mut client = pool.get().await?;
+    let report = migrations::runner().run_async(&mut **client).await?;
+    Ok(report
+        .applied_migrations()
+        .iter()
+        .map(|m| format_sstr!("V{}__{}", m.version(), m.name()))
+        .collect())
+}
+
+/// Make sure the database schema matches this build before serving, pending
+/// migrations are applied when `auto_migrate` is set, otherwise starting is
+/// refused rather than failing on the first query touching a new column
+/// # Errors
+/// Return error if migrations are pending and `auto_migrate` isn't set, or
+/// applying them fails
+pub async fn check_schema(pool: &PgPool, auto_migrate: bool) -> Result<(), Error> {
+    let status = schema_status(pool).await?;
+    if status.applied > status.latest {
+        warn!(
+            "database schema version {} is newer than version {} of this build",
+            status.applied, status.latest
+        );
+    }
+    if status.is_current() {
+        return Ok(());
+    }
+    if !auto_migrate {
+        return Err(SchemaError::Pending {
+            applied: status.applied,
+            pending: status.pending.join(", ").into(),
+        }
+        .into());
+    }
+    for name in run_migrations(pool).await? {
+        info!("applied migration {name}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_migrations::{migrations, SchemaError, SchemaStatus};
+
+    #[test]
+    fn test_schema_status() {
+        let migrations = [(2, "second"), (1, "first"), (3, "third")];
+        let status = SchemaStatus::new(1, migrations);
+        assert_eq!(status.latest, 3);
+        assert_eq!(&status.pending, &["V2__second", "V3__third"]);
+        assert!(!status.is_current());
+
+        let status = SchemaStatus::new(3, migrations);
+        assert!(status.is_current());
+        let status = SchemaStatus::new(4, migrations);
+        assert!(status.is_current());
+        assert!(status.applied > status.latest);
+
+        let err = SchemaError::Pending {
+            applied: 1,
+            pending: SchemaStatus::new(1, migrations).pending.join(", ").into(),
+        };
+        assert!(err
+            .to_string()
+            .starts_with("Database schema is at version 1, migrations V2__second, V3__third"));
+    }
+
+    #[test]
+    fn test_embedded_migrations() {
+        let runner = migrations::runner();
+        let migrations = runner
+            .get_migrations()
+            .iter()
+            .map(|m| (i64::from(m.version()), m.name()));
+        let status = SchemaStatus::new(0, migrations);
+        assert_eq!(status.pending.len() as i64, status.latest);
+        assert_eq!(status.pending[0].as_str(), "V1__diary_entries");
+    }
+}
@@ -1,35 +1,100 @@
 use anyhow::{format_err, Error};
 use aws_config::SdkConfig;
-use futures::{future::try_join_all, stream::FuturesUnordered, TryStreamExt};
+use futures::{future::try_join_all, stream, StreamExt, TryStreamExt};
 use jwalk::WalkDir;
-use log::{debug, info};
+use log::{debug, error, info};
+use parking_lot::Mutex;
+use rand::thread_rng;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use regex::Regex;
 use stack_string::{format_sstr, StackString};
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
+    time::Duration,
 };
 use stdout_channel::StdoutChannel;
 use time::{macros::format_description, Date, OffsetDateTime};
 use time_tz::OffsetDateTimeExt;
 use tokio::{
-    fs::{remove_file, OpenOptions},
+    fs::{read_to_string, remove_file, OpenOptions},
     io::AsyncWriteExt,
     task::{spawn, spawn_blocking},
 };
 use url::Url;
+use uuid::Uuid;
 
 use crate::{
+    archive::{self, Compression},
+    authorship::stamp_header,
+    cache_recovery::{find_cache_issues, CacheIssue},
+    comments::CommentError,
     config::Config,
+    date_sync::{sync_hash, DateCopies, DateSyncReport, EntryCopy, SyncDirection},
     date_time_wrapper::DateTimeWrapper,
-    local_interface::LocalInterface,
-    models::{DiaryCache, DiaryEntries},
+    entry_availability::EntryAvailability,
+    entry_limits::{check_length, LimitError},
+    entry_patch::{EntryPatch, PatchError},
+    error_reporting::report_error,
+    i18n::Locale,
+    line_diff::unified_diff,
+    local_interface::{LocalInterface, LOCAL_KEEP_DAYS},
+    models::{
+        AuditAction, AuthorizedUsers, CacheItem, CacheMerge, ConflictKey, DateGroup, DateListQuery,
+        DatePage, DateRange, DiaryAudit, DiaryCache, DiaryComment, DiaryConflict, DiaryEntries,
+        DiaryPendingAppend, DiaryRedaction, DiarySubentry, DiaryTerm, DiaryTombstone, EntrySize,
+        Journal, ResurfaceRecipient, S3Outbox, StatsPeriod, SyncBackend, SyncWatermark,
+        UserLinkCode, UserSettings, WrittenAt, YearSize, DEFAULT_JOURNAL, SSH_SOURCE, VIEWER_ROLE,
+    },
+    peer_sync::{sync_with_peer, PeerClient},
     pgpool::PgPool,
-    s3_interface::S3Interface,
-    ssh_instance::SSHInstance,
+    redaction::{redact_text, RedactionKey},
+    resurface::{choose_date, render_template, ResurfaceWeights},
+    retry::{Backend, CircuitBreaker},
+    s3_interface::{S3Interface, OUTBOX_RETRY_POLICY},
+    secret_scan::{scan_secrets, secret_warnings},
+    sections::{self, find_section, section_containing, Section},
+    ssh_instance::{SSHInstance, SSHOptions},
+    storage_report::{StorageReport, BIGGEST_ENTRIES},
+    sync_lock::{sync_holder, SyncLock, SyncLockMode},
+    sync_progress::ProgressReporter,
+    text_normalize::normalize_text,
+    users::{generate_link_code, validate_email, validate_role, UserError},
+    writing_habits::WritingHabits,
 };
 
+/// How long a code to link a messenger account can be redeemed
+const LINK_CODE_TTL: time::Duration = time::Duration::minutes(10);
+
+fn local_today() -> Date {
+    OffsetDateTime::now_utc()
+        .to_timezone(DateTimeWrapper::local_tz())
+        .date()
+}
+
+/// Number of consecutive days with an entry ending today, or yesterday if
+/// today's entry hasn't been written yet
+#[must_use]
+pub fn current_streak(dates: &HashSet<Date>, today: Date) -> usize {
+    let mut date = if dates.contains(&today) {
+        today
+    } else {
+        match today.previous_day() {
+            Some(d) => d,
+            None => return 0,
+        }
+    };
+    let mut streak = 0;
+    while dates.contains(&date) {
+        streak += 1;
+        match date.previous_day() {
+            Some(d) => date = d,
+            None => break,
+        }
+    }
+    streak
+}
+
 #[derive(Clone)]
 pub struct DiaryAppInterface {
     pub config: Config,
@@ -37,70 +102,1145 @@ pub struct DiaryAppInterface {
     pub local: LocalInterface,
     pub s3: S3Interface,
     pub stdout: StdoutChannel<StackString>,
+    pub progress: ProgressReporter,
+    pub s3_breaker: Arc<CircuitBreaker>,
+    pub ssh_breaker: Arc<CircuitBreaker>,
+    pub peer_breaker: Arc<CircuitBreaker>,
+    /// Journal used by searches, edits and the local and s3 import/export
+    pub journal: StackString,
+    /// When `sync_everything` last completed in this process
+    pub last_sync: Arc<Mutex<Option<DateTimeWrapper>>>,
+    /// User cached text is attributed to in shared journals and writes are
+    /// attributed to in the activity log
+    pub author: Option<StackString>,
+    /// Leave private entries out of lists, searches and displays, set for
+    /// viewers
+    pub hide_private: bool,
+    /// Pathway cached text comes in through, kept with the subentries of the
+    /// day after the merge
+    pub source: Option<StackString>,
+    /// What `sync_everything` does while another process is syncing
+    pub sync_lock: SyncLockMode,
 }
 
 impl DiaryAppInterface {
     #[must_use]
     pub fn new(config: Config, sdk_config: &SdkConfig, pool: PgPool) -> Self {
+        let progress = ProgressReporter::new();
+        let reset_timeout = Duration::from_secs(config.circuit_breaker_reset_secs);
+        let s3_breaker =
+            CircuitBreaker::new(Backend::S3, config.circuit_breaker_threshold, reset_timeout);
+        let ssh_breaker = CircuitBreaker::new(
+            Backend::Ssh,
+            config.circuit_breaker_threshold,
+            reset_timeout,
+        );
+        let peer_breaker = CircuitBreaker::new(
+            Backend::Peer,
+            config.circuit_breaker_threshold,
+            reset_timeout,
+        );
         Self {
-            local: LocalInterface::new(config.clone(), pool.clone()),
-            s3: S3Interface::new(config.clone(), sdk_config, pool.clone()),
+            local: LocalInterface::new(config.clone(), pool.clone())
+                .with_progress(progress.clone()),
+            s3: S3Interface::new(config.clone(), sdk_config, pool.clone())
+                .with_progress(progress.clone()),
             pool,
             config,
             stdout: StdoutChannel::new(),
+            progress,
+            s3_breaker: Arc::new(s3_breaker),
+            ssh_breaker: Arc::new(ssh_breaker),
+            peer_breaker: Arc::new(peer_breaker),
+            journal: DEFAULT_JOURNAL.into(),
+            last_sync: Arc::new(Mutex::new(None)),
+            author: None,
+            hide_private: false,
+            source: None,
+            sync_lock: SyncLockMode::default(),
         }
     }
 
+    #[must_use]
+    pub fn with_journal(mut self, journal: impl Into<StackString>) -> Self {
+        let journal = journal.into();
+        self.local = self.local.with_journal(&journal);
+        self.s3 = self.s3.with_journal(&journal);
+        self.journal = journal;
+        self
+    }
+
+    /// Publish the progress of syncs and maintenance jobs to `progress`
+    /// instead of the shared reporter
+    #[must_use]
+    pub fn with_progress(mut self, progress: ProgressReporter) -> Self {
+        self.local = self.local.with_progress(progress.clone());
+        self.s3 = self.s3.with_progress(progress.clone());
+        self.progress = progress;
+        self
+    }
+
+    #[must_use]
+    pub fn with_author(mut self, author: impl Into<StackString>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_source(mut self, source: impl Into<StackString>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_sync_lock(mut self, sync_lock: SyncLockMode) -> Self {
+        self.sync_lock = sync_lock;
+        self
+    }
+
+    /// Limit the local and s3 import/export of syncs to `date_range`
+    #[must_use]
+    pub fn with_date_range(mut self, date_range: DateRange) -> Self {
+        self.local = self.local.with_date_range(date_range);
+        self.s3 = self.s3.with_date_range(date_range);
+        self
+    }
+
+    #[must_use]
+    pub fn with_private_hidden(mut self, hide_private: bool) -> Self {
+        self.hide_private = hide_private;
+        self
+    }
+
+    /// Private entries are unreadable when `hide_private` is set
+    #[must_use]
+    pub fn can_read(&self, entry: &DiaryEntries) -> bool {
+        !self.hide_private || !entry.is_private()
+    }
+
+    /// Entry for `date` unless it is hidden from this interface
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+        let entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?;
+        Ok(entry.filter(|entry| self.can_read(entry)))
+    }
+
+    /// Whether anything about `date`, its conflicts and comments included,
+    /// may be shown, `false` if its entry is hidden from this interface
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn can_read_date(&self, date: Date) -> Result<bool, Error> {
+        let entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?;
+        Ok(entry.is_none_or(|entry| self.can_read(&entry)))
+    }
+
+    /// Dates of the entries hidden from this interface, none unless
+    /// `hide_private` is set
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_unreadable_dates(&self) -> Result<HashSet<Date>, Error> {
+        if self.hide_private {
+            DiaryEntries::get_private_dates(&self.journal, &self.pool).await
+        } else {
+            Ok(HashSet::new())
+        }
+    }
+
+    /// Today's date in the local timezone and its entry unless it is hidden
+    /// from this interface
     /// # Errors
     /// Return error if db query fails
+    pub async fn get_today(&self) -> Result<(Date, Option<DiaryEntries>), Error> {
+        let today = local_today();
+        let entry = self.get_entry(today).await?;
+        Ok((today, entry))
+    }
+
+    /// Scheduled entries whose date hasn't arrived, plus private entries if
+    /// `hide_private` is set
+    async fn get_hidden_dates(&self, today: Date) -> Result<HashSet<Date>, Error> {
+        let mut hidden = DiaryEntries::get_hidden_dates(&self.journal, today, &self.pool).await?;
+        if self.hide_private {
+            hidden.extend(DiaryEntries::get_private_dates(&self.journal, &self.pool).await?);
+        }
+        Ok(hidden)
+    }
+
+    /// Create the journal if it doesn't exist yet, every authorized user
+    /// writes into the same entries of a `shared` journal
+    /// # Errors
+    /// Return error if the name is invalid or db query fails
+    pub async fn create_journal(&self, journal_name: &str, shared: bool) -> Result<Journal, Error> {
+        if !Journal::is_valid_name(journal_name) {
+            return Err(format_err!("Invalid journal name {journal_name}"));
+        }
+        let journal = Journal::new(journal_name).with_shared(shared);
+        journal.insert(&self.pool).await?;
+        Ok(journal)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_journals(&self) -> Result<Vec<StackString>, Error> {
+        Journal::get_all(&self.pool)
+            .await?
+            .map_ok(|j| j.journal_name)
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    pub(crate) fn check_length(&self, text: &str) -> Result<(), LimitError> {
+        check_length(text, self.config.max_entry_length)
+    }
+
+    /// # Errors
+    /// Return `LimitError` if the text is longer than `max_entry_length`, or
+    /// error if db query fails
     pub async fn cache_text(
         &self,
         diary_text: impl Into<StackString>,
     ) -> Result<DiaryCache, Error> {
+        let diary_text = diary_text.into();
+        self.check_length(&diary_text)?;
         let dc = DiaryCache {
             diary_datetime: OffsetDateTime::now_utc().into(),
-            diary_text: diary_text.into(),
+            diary_text,
+            journal: self.journal.clone(),
+            idempotency_key: None,
+            author: self.author.clone(),
+            source: self.source.clone(),
         };
         dc.insert_entry(&self.pool).await?;
         Ok(dc)
     }
 
+    /// Insert `items` in one transaction, items without a datetime are stamped
+    /// a microsecond apart so they keep their order and don't collide
     /// # Errors
-    /// Return error if db query fails
+    /// Return `LimitError` if an item is longer than `max_entry_length`, or
+    /// error if the transaction fails, errors of individual items are
+    /// returned in the output
+    pub async fn cache_text_batch(
+        &self,
+        items: Vec<CacheItem>,
+    ) -> Result<Vec<Result<DiaryCache, Error>>, Error> {
+        for item in &items {
+            self.check_length(&item.diary_text)?;
+        }
+        let now = OffsetDateTime::now_utc();
+        let entries = items
+            .into_iter()
+            .zip(0..)
+            .map(|(item, idx)| DiaryCache {
+                diary_datetime: item
+                    .diary_datetime
+                    .unwrap_or_else(|| (now + Duration::from_micros(idx)).into()),
+                diary_text: item.diary_text,
+                journal: self.journal.clone(),
+                idempotency_key: item.idempotency_key,
+                author: self.author.clone(),
+                source: self.source.clone(),
+            })
+            .collect();
+        DiaryCache::insert_batch(entries, &self.pool).await
+    }
+
+    /// Same as `cache_text`, but the text is stamped with the time the client
+    /// wrote it if given, and an insert repeating the idempotency key returns
+    /// the row created by the first insert
+    /// # Errors
+    /// Return `LimitError` if the text is longer than `max_entry_length`, or
+    /// error if db query fails
+    pub async fn cache_item(&self, item: CacheItem) -> Result<DiaryCache, Error> {
+        self.check_length(&item.diary_text)?;
+        let dc = DiaryCache {
+            diary_datetime: item
+                .diary_datetime
+                .unwrap_or_else(|| OffsetDateTime::now_utc().into()),
+            diary_text: item.diary_text,
+            journal: self.journal.clone(),
+            idempotency_key: item.idempotency_key,
+            author: self.author.clone(),
+            source: self.source.clone(),
+        };
+        let Some(idempotency_key) = dc.idempotency_key else {
+            dc.insert_entry(&self.pool).await?;
+            return Ok(dc);
+        };
+        if let Some(dc) = DiaryCache::get_by_idempotency_key(idempotency_key, &self.pool).await? {
+            return Ok(dc);
+        }
+        if let Err(e) = dc.insert_entry(&self.pool).await {
+            // a concurrent retry may have won the race on the unique key
+            return DiaryCache::get_by_idempotency_key(idempotency_key, &self.pool)
+                .await?
+                .ok_or(e);
+        }
+        Ok(dc)
+    }
+
+    /// Replace the text of the entry for `diary_date` with `diary_text`
+    /// normalized, see `normalize_text`
+    /// # Errors
+    /// Return `LimitError` if the text is longer than `max_entry_length`, or
+    /// error if db query fails
     pub async fn replace_text(
         &self,
         diary_date: Date,
         diary_text: impl Into<StackString>,
     ) -> Result<(DiaryEntries, Option<OffsetDateTime>), Error> {
-        let de = DiaryEntries::new(diary_date, diary_text);
+        let diary_text = normalize_text(&diary_text.into());
+        self.check_length(&diary_text)?;
+        let de = DiaryEntries::new(diary_date, diary_text)
+            .with_journal(&self.journal)
+            .with_source(self.source.clone());
         let output = de.upsert_entry(&self.pool, true).await?;
         Ok((de, output))
     }
 
+    /// Append `diary_text` to the entry for `date` on a new line, creating the
+    /// entry if needed
+    /// # Errors
+    /// Return `LimitError` if the entry would be longer than
+    /// `max_entry_length`, or error if db query fails
+    pub async fn append_text(
+        &self,
+        diary_date: Date,
+        diary_text: &str,
+    ) -> Result<DiaryEntries, Error> {
+        self.check_length(diary_text)?;
+        let diary_text = match &self.author {
+            Some(author) if self.is_shared_journal().await? => {
+                let now = OffsetDateTime::now_utc().to_timezone(DateTimeWrapper::local_tz());
+                let header = stamp_header(&format_sstr!("{now}"), author);
+                format_sstr!("{header}\n{diary_text}")
+            }
+            _ => diary_text.into(),
+        };
+        DiaryEntries::append_text(
+            &self.journal,
+            diary_date,
+            &diary_text,
+            "\n",
+            self.source.as_deref(),
+            self.config.max_entry_length,
+            &self.pool,
+        )
+        .await
+    }
+
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_list_of_dates(
+    pub async fn is_shared_journal(&self) -> Result<bool, Error> {
+        let journal = Journal::get_by_name(&self.journal, &self.pool).await?;
+        Ok(journal.is_some_and(|j| j.shared))
+    }
+
+    /// # Errors
+    /// Return `CommentError::NoEntry` if there is no entry for `diary_date` or
+    /// it is hidden from this interface, or error if db query fails
+    pub async fn get_comments(&self, diary_date: Date) -> Result<Vec<DiaryComment>, Error> {
+        if self.get_entry(diary_date).await?.is_none() {
+            return Err(CommentError::NoEntry(diary_date).into());
+        }
+        DiaryComment::get_by_date_range(&self.journal, diary_date, diary_date, &self.pool).await
+    }
+
+    /// # Errors
+    /// Return `CommentError` if there is no entry for `diary_date`, it is
+    /// hidden from this interface or the comment is empty, or error if db
+    /// query fails
+    pub async fn add_comment(
+        &self,
+        diary_date: Date,
+        author: &str,
+        comment_text: &str,
+    ) -> Result<DiaryComment, Error> {
+        if comment_text.trim().is_empty() {
+            return Err(CommentError::Empty.into());
+        }
+        if self.get_entry(diary_date).await?.is_none() {
+            return Err(CommentError::NoEntry(diary_date).into());
+        }
+        let comment = DiaryComment::new(&self.journal, diary_date, author, comment_text);
+        comment.insert(&self.pool).await?;
+        Ok(comment)
+    }
+
+    async fn get_own_comment(&self, id: Uuid, author: &str) -> Result<DiaryComment, Error> {
+        let comment = DiaryComment::get_by_id(id, &self.pool)
+            .await?
+            .ok_or(CommentError::NotFound(id))?;
+        if comment.author != author {
+            return Err(CommentError::NotAuthor.into());
+        }
+        Ok(comment)
+    }
+
+    /// # Errors
+    /// Return `CommentError` if the comment doesn't exist, was written by
+    /// someone else or `comment_text` is empty, or error if db query fails
+    pub async fn update_comment(
+        &self,
+        id: Uuid,
+        author: &str,
+        comment_text: &str,
+    ) -> Result<DiaryComment, Error> {
+        if comment_text.trim().is_empty() {
+            return Err(CommentError::Empty.into());
+        }
+        let mut comment = self.get_own_comment(id, author).await?;
+        comment.update_text(comment_text, &self.pool).await?;
+        Ok(comment)
+    }
+
+    /// # Errors
+    /// Return `CommentError` if the comment doesn't exist or was written by
+    /// someone else, or error if db query fails
+    pub async fn delete_comment(&self, id: Uuid, author: &str) -> Result<DiaryComment, Error> {
+        let comment = self.get_own_comment(id, author).await?;
+        comment.delete(&self.pool).await?;
+        Ok(comment)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list_users(&self) -> Result<Vec<AuthorizedUsers>, Error> {
+        AuthorizedUsers::get_all(&self.pool).await
+    }
+
+    /// Add a user or re-enable a disabled one
+    /// # Errors
+    /// Return `UserError` if `email` or `role` are invalid, or error if db
+    /// query fails
+    pub async fn add_user(
+        &self,
+        email: &str,
+        role: &str,
+        telegram_userid: Option<i64>,
+    ) -> Result<AuthorizedUsers, Error> {
+        let email = validate_email(email)?;
+        let role = validate_role(role)?;
+        AuthorizedUsers::upsert(email, role, telegram_userid, &self.pool).await?;
+        self.get_user(email).await
+    }
+
+    /// The bots drop disabled users the next time they refresh their users
+    /// # Errors
+    /// Return `UserError::NotFound` if there is no enabled user with `email`,
+    /// or error if db query fails
+    pub async fn disable_user(&self, email: &str) -> Result<AuthorizedUsers, Error> {
+        if !AuthorizedUsers::disable(email, &self.pool).await? {
+            return Err(UserError::NotFound(email.into()).into());
+        }
+        self.get_user(email).await
+    }
+
+    /// # Errors
+    /// Return `UserError::NotFound` if there is no user with `email`, or
+    /// error if db query fails
+    pub async fn set_telegram_userid(
+        &self,
+        email: &str,
+        telegram_userid: Option<i64>,
+    ) -> Result<AuthorizedUsers, Error> {
+        if !AuthorizedUsers::set_telegram_userid(email, telegram_userid, &self.pool).await? {
+            return Err(UserError::NotFound(email.into()).into());
+        }
+        self.get_user(email).await
+    }
+
+    /// One-time code `email` sends to a bot to link their messenger account,
+    /// replaces any earlier code
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn create_link_code(&self, email: &str) -> Result<UserLinkCode, Error> {
+        let code = generate_link_code(&mut thread_rng());
+        let link_code = UserLinkCode::new(code, email, LINK_CODE_TTL);
+        link_code.insert(&self.pool).await?;
+        Ok(link_code)
+    }
+
+    /// Email of the user who created `code`, which can't be used again
+    /// # Errors
+    /// Return `UserError::InvalidLinkCode` if `code` is unknown or expired,
+    /// or error if db query fails
+    pub async fn redeem_link_code(&self, code: &str) -> Result<StackString, Error> {
+        let code = code.trim().to_uppercase();
+        UserLinkCode::redeem(&code, &self.pool)
+            .await?
+            .map(|link_code| link_code.email)
+            .ok_or_else(|| UserError::InvalidLinkCode.into())
+    }
+
+    /// User with `email` whether or not they are disabled
+    async fn get_user(&self, email: &str) -> Result<AuthorizedUsers, Error> {
+        self.list_users()
+            .await?
+            .into_iter()
+            .find(|user| user.email == email)
+            .ok_or_else(|| UserError::NotFound(email.into()).into())
+    }
+
+    /// Replace the entry with the added and unchanged lines of the conflict
+    /// `key`, also returns the datetime of the conflict the replacement
+    /// recorded if it dropped lines
+    /// # Errors
+    /// Return error if there is no such conflict or db query fails
+    pub async fn commit_conflict(
+        &self,
+        key: impl Into<ConflictKey>,
+    ) -> Result<(DiaryEntries, Option<OffsetDateTime>), Error> {
+        self.replace_with_conflict(key.into(), &["add", "same"])
+            .await
+    }
+
+    /// Replace the entry with the text it had before the sync which recorded
+    /// the conflict `key`, the removed and unchanged lines
+    /// # Errors
+    /// Return error if there is no such conflict or db query fails
+    pub async fn revert_conflict(
+        &self,
+        key: impl Into<ConflictKey>,
+    ) -> Result<(DiaryEntries, Option<OffsetDateTime>), Error> {
+        self.replace_with_conflict(key.into(), &["rem", "same"])
+            .await
+    }
+
+    async fn replace_with_conflict(
+        &self,
+        key: ConflictKey,
+        diff_types: &[&str],
+    ) -> Result<(DiaryEntries, Option<OffsetDateTime>), Error> {
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&self.journal, key, &self.pool)
+            .await?
+            .try_collect()
+            .await?;
+        let diary_dates: HashSet<Date> = conflicts.iter().map(|entry| entry.diary_date).collect();
+        if diary_dates.len() > 1 {
+            return Err(format_err!(
+                "Something has gone horribly wrong {:?}",
+                conflicts
+            ));
+        }
+        let date = diary_dates
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("No conflict {key}"))?;
+
+        let lines: Vec<_> = conflicts
+            .into_iter()
+            .filter_map(|entry| {
+                if diff_types.contains(&entry.diff_type.as_str()) {
+                    Some(entry.diff_text)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        self.replace_text(date, lines.join("\n")).await
+    }
+
+    /// Add a write to the activity log, the write has already happened so a
+    /// failure to record it is only logged
+    pub async fn record_activity(
+        &self,
+        action: AuditAction,
+        diary_date: Option<Date>,
+        detail: impl Into<StackString>,
+    ) {
+        let mut audit = DiaryAudit::new(
+            self.author.as_deref(),
+            action,
+            &self.journal,
+            diary_date,
+            detail,
+        );
+        audit.source = self.source.clone();
+        if let Err(e) = audit.insert(&self.pool).await {
+            error!("Failed to record {action} in activity log {e}");
+        }
+    }
+
+    /// Writes to the journal, most recent first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_activity(
         &self,
+        email: Option<&str>,
         min_date: Option<Date>,
         max_date: Option<Date>,
-        start: Option<usize>,
-        limit: Option<usize>,
+        limit: usize,
+    ) -> Result<Vec<DiaryAudit>, Error> {
+        DiaryAudit::get_filtered(&self.journal, email, min_date, max_date, limit, &self.pool).await
+    }
+
+    /// Replace matches of `regex` in entries between `min_date` and
+    /// `max_date`, the original text is stored encrypted with the redaction
+    /// key unless `permanent` is set, returns the redacted dates
+    /// # Errors
+    /// Return error if the redaction key can't be loaded or db query fails
+    pub async fn redact(
+        &self,
+        regex: &Regex,
+        placeholder: Option<&str>,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+        permanent: bool,
     ) -> Result<Vec<Date>, Error> {
-        let mut dates: Vec<_> = DiaryEntries::get_modified_map(&self.pool, min_date, max_date)
-            .await?
-            .into_keys()
-            .collect();
+        let placeholder = placeholder.unwrap_or(self.config.redaction_placeholder.as_str());
+        let key = if permanent {
+            None
+        } else {
+            Some(RedactionKey::from_path(&self.config.redaction_key_path)?)
+        };
+        let mut dates: Vec<_> =
+            DiaryEntries::get_modified_map(&self.journal, &self.pool, min_date, max_date)
+                .await?
+                .into_keys()
+                .collect();
         dates.sort();
-        dates.reverse();
-        if let Some(start) = start {
-            if start <= dates.len() {
-                dates = dates.split_off(start);
+        let mut redacted = Vec::new();
+        for date in dates {
+            let Some(entry) = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?
+            else {
+                continue;
+            };
+            if entry.is_encrypted {
+                continue;
             }
+            let Some(diary_text) = redact_text(&entry.diary_text, regex, placeholder) else {
+                continue;
+            };
+            let redaction = match &key {
+                Some(key) => {
+                    let (ciphertext, nonce) = key.encrypt(&entry.diary_text)?;
+                    Some(DiaryRedaction::new(&self.journal, date, ciphertext, nonce))
+                }
+                None => None,
+            };
+            let entry = DiaryEntries {
+                diary_text: diary_text.into(),
+                source: self.source.clone(),
+                ..entry
+            };
+            entry
+                .replace_redacted(redaction.as_ref(), regex, placeholder, &self.pool)
+                .await?;
+            redacted.push(date);
         }
-        if let Some(limit) = limit {
-            dates.truncate(limit);
+        if !redacted.is_empty() {
+            self.record_activity(
+                AuditAction::Redact,
+                None,
+                format_sstr!("redacted {} entries", redacted.len()),
+            )
+            .await;
         }
-        Ok(dates)
+        Ok(redacted)
+    }
+
+    /// Warnings for passages of `text` which look like credentials, empty if
+    /// `secret_scan` is disabled
+    #[must_use]
+    pub fn scan_secrets(&self, text: &str) -> Vec<StackString> {
+        if !self.config.secret_scan {
+            return Vec::new();
+        }
+        let extra = self
+            .config
+            .secret_scan_pattern
+            .as_ref()
+            .and_then(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    error!("invalid secret_scan_pattern {e}");
+                    None
+                }
+            });
+        secret_warnings(&scan_secrets(text, extra.as_ref()))
+    }
+
+    /// Apply `patch` to the current text of the entry for `date`, if
+    /// `base_hash` is given the patch is rejected when the entry changed since
+    /// the client loaded it
+    /// # Errors
+    /// Return `PatchError` if the patch doesn't apply, or error if the entry
+    /// doesn't exist, is encrypted or db query fails
+    pub async fn patch_entry(
+        &self,
+        diary_date: Date,
+        patch: &EntryPatch,
+        base_hash: Option<&str>,
+    ) -> Result<(DiaryEntries, Option<OffsetDateTime>), Error> {
+        let entry = DiaryEntries::get_by_date(&self.journal, diary_date, &self.pool)
+            .await?
+            .ok_or_else(|| format_err!("No entry for {diary_date}"))?;
+        if entry.is_encrypted {
+            return Err(format_err!("Entry {diary_date} is encrypted"));
+        }
+        if let Some(base_hash) = base_hash {
+            if entry.get_hash() != base_hash {
+                return Err(PatchError::Conflict("hash mismatch".into()).into());
+            }
+        }
+        let text = patch.apply(&entry.diary_text)?;
+        self.replace_text(diary_date, text).await
+    }
+
+    /// Section `slug` of the entry for `date`, `None` if the entry is hidden
+    /// or has no such section
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_section(&self, date: Date, slug: &str) -> Result<Option<Section>, Error> {
+        let entry = self.get_entry(date).await?;
+        Ok(entry.and_then(|entry| find_section(&entry.diary_text, slug)))
+    }
+
+    /// Replace the text of section `slug` of the entry for `date`, the header
+    /// and the other sections are kept
+    /// # Errors
+    /// Return error if the entry or section doesn't exist, the entry is
+    /// encrypted or db query fails
+    pub async fn replace_section(
+        &self,
+        date: Date,
+        slug: &str,
+        text: &str,
+    ) -> Result<(DiaryEntries, Option<OffsetDateTime>), Error> {
+        let entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool)
+            .await?
+            .ok_or_else(|| format_err!("No entry for {date}"))?;
+        if entry.is_encrypted {
+            return Err(format_err!("Entry {date} is encrypted"));
+        }
+        let text = sections::replace_section(&entry.diary_text, slug, text)
+            .ok_or_else(|| format_err!("No section {slug} in {date}"))?;
+        self.replace_text(date, text).await
+    }
+
+    /// Move the entry for `date` to the trash and remove its copies from the
+    /// local directory and s3, a tombstone keeps importers from re-creating
+    /// it from older copies
+    /// # Errors
+    /// Return error if the entry doesn't exist or a backend fails
+    pub async fn delete_entry(&self, date: Date) -> Result<Vec<StackString>, Error> {
+        let entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool)
+            .await?
+            .ok_or_else(|| format_err!("No entry for {date}"))?;
+        entry.delete_entry(&self.pool).await?;
+        let retention = time::Duration::days(self.config.tombstone_retention_days);
+        DiaryTombstone::new(&self.journal, date, retention)
+            .upsert(&self.pool)
+            .await?;
+        let mut output = vec![format_sstr!("trash {date}")];
+        if self.local.delete_entry(date).await? {
+            output.push(format_sstr!("local delete {date}"));
+        }
+        self.s3.delete_entry(date).await?;
+        output.push(format_sstr!("s3 delete {date}"));
+        self.record_activity(AuditAction::Delete, Some(date), "moved to trash")
+            .await;
+        Ok(output)
+    }
+
+    /// Move the entry of a daily file removed from the local directory to
+    /// the trash, files older than `LOCAL_KEEP_DAYS` are removed by
+    /// `cleanup_local` and leave their entry alone
+    /// # Errors
+    /// Return error if a backend fails
+    pub async fn handle_local_removal(&self, date: Date) -> Result<Vec<StackString>, Error> {
+        let filepath = self.local.diary_path().join(format_sstr!("{date}.txt"));
+        if filepath.exists() || date <= local_today() - time::Duration::days(LOCAL_KEEP_DAYS) {
+            return Ok(Vec::new());
+        }
+        if DiaryEntries::get_by_date(&self.journal, date, &self.pool)
+            .await?
+            .is_none()
+        {
+            return Ok(Vec::new());
+        }
+        info!("local file of {date} was removed");
+        self.delete_entry(date).await
+    }
+
+    /// Force the copies of one date into agreement, `Pull` imports the most
+    /// recently modified of the local file and s3 copy into the database
+    /// (keeping the replaced text as a conflict), `Push` overwrites the local
+    /// file and s3 with the database entry
+    /// # Errors
+    /// Return error if the copy to sync from doesn't exist or a backend fails
+    pub async fn sync_date(
+        &self,
+        date: Date,
+        direction: SyncDirection,
+    ) -> Result<DateSyncReport, Error> {
+        let db = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?;
+        let local = self.local.read_entry(date).await.map_err(|e| {
+            self.report_sync_failure("local", Some(date), &e);
+            e
+        })?;
+        let s3 = self
+            .s3_breaker
+            .call(self.s3.download_entry(date))
+            .await
+            .map_err(|e| {
+                self.report_sync_failure("s3", Some(date), &e);
+                e
+            })?;
+        let copies = DateCopies {
+            db: db.as_ref().map(|e| e.diary_text.clone()),
+            local: local.as_ref().map(|e| e.diary_text.clone()),
+            s3: s3.as_ref().map(|e| e.diary_text.clone()),
+        };
+        let differed = copies.differing();
+        let mut output = Vec::new();
+        match direction {
+            SyncDirection::Pull => {
+                let (source, entry) = [("local", local), ("s3", s3)]
+                    .into_iter()
+                    .filter_map(|(source, entry)| Some((source, entry?)))
+                    .max_by_key(|(_, entry)| OffsetDateTime::from(entry.last_modified))
+                    .ok_or_else(|| format_err!("No local or s3 copy of {date}"))?;
+                output.push(format_sstr!("pull {date} from {source}"));
+                if let Some(datetime) = entry.upsert_entry(&self.pool, true).await? {
+                    output.push(format_sstr!("replaced text kept as conflict {datetime}"));
+                }
+            }
+            SyncDirection::Push => {
+                let entry = db.ok_or_else(|| format_err!("No entry for {date}"))?;
+                if entry.is_encrypted {
+                    return Err(format_err!("Entry {date} is encrypted"));
+                }
+                self.local.write_entry(&entry).await?;
+                output.push(format_sstr!("local write {date}"));
+                self.s3_breaker
+                    .call(self.s3.force_upload_entry(date))
+                    .await
+                    .map_err(|e| {
+                        self.report_sync_failure("s3", Some(date), &e);
+                        e
+                    })?;
+                output.push(format_sstr!("s3 upload {date}"));
+            }
+        }
+        self.record_activity(
+            AuditAction::Sync,
+            Some(date),
+            format_sstr!("{direction} {date}, differed: {}", differed.join(", ")),
+        )
+        .await;
+        Ok(DateSyncReport { differed, output })
+    }
+
+    /// Text of one copy of the entry for `date`, `None` if there is no such
+    /// copy
+    /// # Errors
+    /// Return error if the database entry is encrypted or a backend fails
+    pub async fn get_copy(
+        &self,
+        date: Date,
+        copy: &EntryCopy,
+    ) -> Result<Option<StackString>, Error> {
+        let entry = match copy {
+            EntryCopy::Db => {
+                let entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?;
+                if entry.as_ref().is_some_and(|e| e.is_encrypted) {
+                    return Err(format_err!("Entry {date} is encrypted"));
+                }
+                entry
+            }
+            EntryCopy::Local => self.local.read_entry(date).await?,
+            EntryCopy::S3 => self.s3_breaker.call(self.s3.download_entry(date)).await?,
+            EntryCopy::Version(version_id) => {
+                self.s3_breaker
+                    .call(self.s3.download_version(date, Some(version_id.as_str())))
+                    .await?
+            }
+        };
+        Ok(entry.map(|e| e.diary_text))
+    }
+
+    /// Unified diff from the `from` copy of the entry for `date` to the `to`
+    /// copy, a missing copy is compared as empty text
+    /// # Errors
+    /// Return error if a copy can't be read
+    pub async fn diff_copies(
+        &self,
+        date: Date,
+        from: &EntryCopy,
+        to: &EntryCopy,
+    ) -> Result<StackString, Error> {
+        let original = self.get_copy(date, from).await?.unwrap_or_default();
+        let updated = self.get_copy(date, to).await?.unwrap_or_default();
+        Ok(unified_diff(
+            &original,
+            &updated,
+            &format_sstr!("{date} ({from})"),
+            &format_sstr!("{date} ({to})"),
+        ))
+    }
+
+    /// Replace the entry for `date` with a previous version of its s3
+    /// object, the replaced text is recorded as a conflict so that it can be
+    /// kept instead. Returns the datetime of the conflict, `None` if the
+    /// entry didn't change
+    /// # Errors
+    /// Return error if the version is empty, s3 api or db query fails
+    pub async fn restore_s3_version(
+        &self,
+        date: Date,
+        version_id: &str,
+    ) -> Result<Option<DateTimeWrapper>, Error> {
+        let entry = self
+            .s3
+            .download_version(date, Some(version_id))
+            .await?
+            .ok_or_else(|| format_err!("Version {version_id} of {date} is empty"))?;
+        let conflict = entry.upsert_entry(&self.pool, true).await?;
+        self.record_activity(
+            AuditAction::Replace,
+            Some(date),
+            format_sstr!("restored s3 version {version_id}"),
+        )
+        .await;
+        Ok(conflict.map(Into::into))
+    }
+
+    /// Write a letter to the future self, the entry stays hidden from list
+    /// and search until `date` arrives
+    /// # Errors
+    /// Return `LimitError` if the text is longer than `max_entry_length`, or
+    /// error if `date` isn't in the future, an entry already exists or db
+    /// query fails
+    pub async fn schedule_entry(&self, date: Date, text: &str) -> Result<DiaryEntries, Error> {
+        self.check_length(text)?;
+        let today = local_today();
+        if date <= today {
+            return Err(format_err!("Scheduled entries must be dated after {today}"));
+        }
+        if DiaryEntries::get_by_date(&self.journal, date, &self.pool)
+            .await?
+            .is_some()
+        {
+            return Err(format_err!("Entry for {date} already exists"));
+        }
+        let mut entry = DiaryEntries::new(date, text)
+            .with_journal(self.journal.clone())
+            .with_source(self.source.clone());
+        entry.scheduled = true;
+        entry.insert_entry(&self.pool).await?;
+        self.record_activity(
+            AuditAction::Schedule,
+            Some(date),
+            format_sstr!("{} bytes", entry.diary_text.len()),
+        )
+        .await;
+        Ok(entry)
+    }
+
+    /// Release the scheduled entries of every journal whose date has arrived
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn release_scheduled(&self) -> Result<Vec<DiaryEntries>, Error> {
+        DiaryEntries::release_scheduled(local_today(), &self.pool).await
+    }
+
+    /// Whether today's resurfaced entries should be sent, true from
+    /// `resurface_hour` local time until midnight
+    #[must_use]
+    pub fn is_resurface_due(&self) -> bool {
+        let now = OffsetDateTime::now_utc().to_timezone(DateTimeWrapper::local_tz());
+        now.hour() >= self.config.resurface_hour
+    }
+
+    /// Pick a past entry at random, weighted towards anniversaries of
+    /// `today`, encrypted, empty and (if `hide_private` is set) private
+    /// entries are skipped
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn choose_resurfaced_entry(
+        &self,
+        today: Date,
+    ) -> Result<Option<DiaryEntries>, Error> {
+        let weights = ResurfaceWeights::from(&*self.config);
+        let mut dates: Vec<_> =
+            DiaryEntries::get_modified_map(&self.journal, &self.pool, None, Some(today))
+                .await?
+                .into_keys()
+                .collect();
+        loop {
+            let Some(date) = choose_date(&dates, today, &weights, &mut thread_rng()) else {
+                return Ok(None);
+            };
+            if let Some(entry) = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await? {
+                if !entry.is_encrypted
+                    && !entry.diary_text.trim().is_empty()
+                    && self.can_read(&entry)
+                {
+                    return Ok(Some(entry));
+                }
+            }
+            dates.retain(|d| *d != date);
+        }
+    }
+
+    /// Render a resurfaced entry for every opted in user who hasn't received
+    /// one today, users are marked once the message has been delivered with
+    /// `UserSettings::mark_resurfaced`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_resurface_messages(
+        &self,
+    ) -> Result<Vec<(ResurfaceRecipient, StackString)>, Error> {
+        let today = local_today();
+        let mut messages = Vec::new();
+        for recipient in UserSettings::get_resurface_recipients(today, &self.pool).await? {
+            // viewers aren't sent private entries
+            let dapp = self
+                .clone()
+                .with_private_hidden(recipient.role == VIEWER_ROLE);
+            if let Some(entry) = dapp.choose_resurfaced_entry(today).await? {
+                let text = render_template(
+                    &self.config.resurface_template,
+                    entry.diary_date,
+                    today,
+                    &entry.diary_text,
+                );
+                messages.push((recipient, text));
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Locale picked in the settings of `email`, `default_locale` if none
+    /// was picked or the stored one is no longer supported
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_locale(&self, email: &str) -> Result<Locale, Error> {
+        let locale = UserSettings::get_by_email(email, &self.pool)
+            .await?
+            .and_then(|settings| settings.locale)
+            .and_then(|locale| locale.parse().ok())
+            .unwrap_or(self.config.default_locale);
+        Ok(locale)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn mark_resurfaced(&self, email: &str) -> Result<(), Error> {
+        UserSettings::mark_resurfaced(email, local_today(), &self.pool).await
+    }
+
+    /// Purge entries which have been in the trash for longer than
+    /// `trash_retention_days` along with expired tombstones
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn purge_trash(&self) -> Result<u64, Error> {
+        let cutoff =
+            OffsetDateTime::now_utc() - time::Duration::days(self.config.trash_retention_days);
+        let purged = DiaryEntries::purge_deleted(cutoff, &self.pool).await?;
+        if purged > 0 {
+            info!("purged {purged} entries from trash");
+        }
+        let expired = DiaryTombstone::purge_expired(&self.pool).await?;
+        if expired > 0 {
+            info!("removed {expired} expired tombstones");
+        }
+        Ok(purged)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_list_of_dates(
+        &self,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+        start: Option<usize>,
+        limit: Option<usize>,
+        starred: bool,
+    ) -> Result<Vec<Date>, Error> {
+        let query = DateListQuery {
+            range: DateRange::new(min_date, max_date),
+            offset: start,
+            limit,
+            starred,
+            ..DateListQuery::default()
+        };
+        DiaryEntries::get_visible_dates(
+            &self.journal,
+            query,
+            local_today(),
+            self.hide_private,
+            &self.pool,
+        )
+        .await
+    }
+
+    /// Page of the visible dates, the next page starts past its
+    /// `next_cursor`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_date_page(&self, query: DateListQuery) -> Result<DatePage, Error> {
+        // one more date tells whether there is a next page
+        let lookahead = DateListQuery {
+            limit: query.limit.map(|limit| limit + 1),
+            ..query
+        };
+        let mut dates = DiaryEntries::get_visible_dates(
+            &self.journal,
+            lookahead,
+            local_today(),
+            self.hide_private,
+            &self.pool,
+        )
+        .await?;
+        let next_cursor = match query.limit {
+            Some(limit) if dates.len() > limit => {
+                dates.truncate(limit);
+                dates.last().copied()
+            }
+            _ => None,
+        };
+        Ok(DatePage { dates, next_cursor })
+    }
+
+    /// Number of visible entries and their words per `period`, newest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_date_groups(&self, period: StatsPeriod) -> Result<Vec<DateGroup>, Error> {
+        DateGroup::get_by_period(
+            &self.journal,
+            period,
+            local_today(),
+            self.hide_private,
+            &self.pool,
+        )
+        .await
+    }
+
+    /// Which dates have entries, hidden entries are left out as in
+    /// [`Self::get_list_of_dates`]
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_entry_availability(
+        &self,
+        range: DateRange,
+    ) -> Result<EntryAvailability, Error> {
+        let today = local_today();
+        let summary =
+            DiaryEntries::get_date_summary(&self.journal, today, self.hide_private, &self.pool)
+                .await?;
+        let hidden = self.get_hidden_dates(today).await?;
+        let dates: Vec<_> =
+            DiaryEntries::get_modified_map(&self.journal, &self.pool, range.since, range.until)
+                .await?
+                .into_keys()
+                .filter(|d| !hidden.contains(d))
+                .collect();
+        Ok(EntryAvailability::from_summary(summary, &dates, range))
     }
 
     fn get_matching_dates(
@@ -158,11 +1298,217 @@ impl DiaryAppInterface {
         Ok(dates)
     }
 
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_streak(&self) -> Result<usize, Error> {
+        let dates: HashSet<_> =
+            DiaryEntries::get_modified_map(&self.journal, &self.pool, None, None)
+                .await?
+                .into_keys()
+                .collect();
+        Ok(current_streak(&dates, local_today()))
+    }
+
+    /// Most frequent terms of the entries in `range`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_top_terms(
+        &self,
+        range: DateRange,
+        limit: i64,
+    ) -> Result<Vec<DiaryTerm>, Error> {
+        DiaryTerm::get_top(&self.journal, range, self.hide_private, limit, &self.pool).await
+    }
+
+    /// Recount the terms of every entry, term counts are otherwise only
+    /// updated when an entry is written
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn rebuild_terms(&self) -> Result<usize, Error> {
+        let dates = DiaryEntries::get_modified_map(&self.journal, &self.pool, None, None).await?;
+        let stage = self.progress.start("rebuild terms", dates.len());
+        for date in dates.keys() {
+            if let Some(entry) = DiaryEntries::get_by_date(&self.journal, *date, &self.pool).await?
+            {
+                DiaryTerm::replace(&self.journal, *date, &entry.diary_text, &self.pool).await?;
+            }
+            stage.increment();
+        }
+        Ok(dates.len())
+    }
+
+    /// Remove the conflicts of every journal recorded more than
+    /// `older_than_days` days ago, returns the number of rows removed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn purge_old_conflicts(&self, older_than_days: i64) -> Result<u64, Error> {
+        let stage = self.progress.start("purge conflicts", 1);
+        let cutoff = OffsetDateTime::now_utc() - time::Duration::days(older_than_days);
+        let removed = DiaryConflict::delete_before(cutoff, &self.pool).await?;
+        stage.increment();
+        Ok(removed)
+    }
+
+    /// Bring the local watermarks in line with the daily files, see
+    /// [`LocalInterface::recompute_watermarks`]
+    /// # Errors
+    /// Return error if reading a file or db query fails
+    pub async fn recompute_hashes(&self) -> Result<usize, Error> {
+        self.local.recompute_watermarks().await
+    }
+
+    /// Refresh the cached s3 listing used by storage reports and syncs,
+    /// returns the number of keys in the bucket
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn warm_s3_cache(&self) -> Result<usize, Error> {
+        let stage = self.progress.start("warm s3 cache", 1);
+        let keys = self.s3.warm_cache().await?;
+        stage.increment();
+        Ok(keys)
+    }
+
+    /// Pathway of the last write to the entry for `date`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_entry_source(&self, date: Date) -> Result<Option<StackString>, Error> {
+        let entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?;
+        Ok(entry.and_then(|entry| entry.source))
+    }
+
+    /// Cache entries merged into the entry for `date`, oldest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_subentries(&self, date: Date) -> Result<Vec<DiarySubentry>, Error> {
+        DiarySubentry::get_by_date(&self.journal, date, self.hide_private, &self.pool).await
+    }
+
+    /// When the entries in `range` were written, from the timestamps the
+    /// merge step keeps in their metadata and those of cache entries which
+    /// are not merged yet
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_writing_habits(&self, range: DateRange) -> Result<WritingHabits, Error> {
+        let local = DateTimeWrapper::local_tz();
+        let mut times: Vec<_> =
+            WrittenAt::get_by_range(&self.journal, range, self.hide_private, &self.pool)
+                .await?
+                .into_iter()
+                .map(|w| (w.diary_date, w.written_at.to_offsetdatetime()))
+                .collect();
+        let cached: Vec<DiaryCache> = DiaryCache::get_cache_entries(&self.pool)
+            .await?
+            .try_filter(|entry| {
+                let keep = entry.journal == self.journal;
+                async move { keep }
+            })
+            .try_collect()
+            .await?;
+        times.extend(cached.into_iter().filter_map(|entry| {
+            let written = entry.diary_datetime.to_offsetdatetime();
+            let date = written.to_timezone(local).date();
+            range.contains(date).then_some((date, written))
+        }));
+        Ok(WritingHabits::new(times, local))
+    }
+
+    /// # Errors
+    /// Return error if db query or s3 api fails
+    pub async fn get_storage_report(&self) -> Result<StorageReport, Error> {
+        let years = YearSize::get_by_year(&self.journal, &self.pool).await?;
+        let biggest = EntrySize::get_biggest(&self.journal, BIGGEST_ENTRIES, &self.pool).await?;
+        let s3 = self.s3_breaker.call(self.s3.get_storage()).await?;
+        Ok(StorageReport { years, biggest, s3 })
+    }
+
+    /// Most recent `limit` visible entries, newest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_recent_entries(&self, limit: usize) -> Result<Vec<DiaryEntries>, Error> {
+        let dates = self
+            .get_list_of_dates(None, None, None, Some(limit), false)
+            .await?;
+        let mut entries = Vec::with_capacity(dates.len());
+        for date in dates {
+            if let Some(entry) = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Visible entries from `min_date` to `max_date`, oldest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_entries_in_range(
+        &self,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+    ) -> Result<Vec<DiaryEntries>, Error> {
+        let mut dates = self
+            .get_list_of_dates(min_date, max_date, None, None, false)
+            .await?;
+        dates.reverse();
+        let mut entries = Vec::with_capacity(dates.len());
+        for date in dates {
+            if let Some(entry) = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Number of dates with outstanding conflicts
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn count_conflict_dates(&self) -> Result<usize, Error> {
+        let dates: Vec<_> = DiaryConflict::get_all_dates(&self.journal, &self.pool)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(dates.len())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_starred_dates(&self) -> Result<HashSet<Date>, Error> {
+        DiaryEntries::get_starred(&self.journal, &self.pool)
+            .await?
+            .map_ok(|entry| entry.diary_date)
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn search_text(&self, search_text: &str) -> Result<Vec<StackString>, Error> {
+        self.search_entries(search_text, false).await
+    }
+
+    /// Same as `search_text` restricted to starred entries, cache entries are
+    /// not included
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn search_starred(&self, search_text: &str) -> Result<Vec<StackString>, Error> {
+        self.search_entries(search_text, true).await
+    }
+
+    async fn search_entries(
+        &self,
+        search_text: &str,
+        starred: bool,
+    ) -> Result<Vec<StackString>, Error> {
         let local = DateTimeWrapper::local_tz();
-        let mod_map = DiaryEntries::get_modified_map(&self.pool, None, None).await?;
+        let today = local_today();
+        let hidden = self.get_hidden_dates(today).await?;
+        let mut mod_map =
+            DiaryEntries::get_modified_map(&self.journal, &self.pool, None, None).await?;
+        mod_map.retain(|d, _| !hidden.contains(d));
+        if starred {
+            let starred_dates = self.get_starred_dates().await?;
+            mod_map.retain(|d, _| starred_dates.contains(d));
+        }
 
         let mut dates = Self::get_dates_from_search_text(&mod_map, search_text)?;
 
@@ -170,69 +1516,118 @@ impl DiaryAppInterface {
         debug!("search dates {}", dates.len());
 
         if dates.is_empty() {
-            let mut diary_entries: Vec<_> = DiaryEntries::get_by_text(search_text, &self.pool)
-                .await?
-                .map_ok(|entry| format_sstr!("{}\n{}", entry.diary_date, entry.diary_text))
-                .try_collect()
-                .await?;
-            let diary_cache_entries: Vec<_> = DiaryCache::get_by_text(search_text, &self.pool)
-                .await?
-                .map_ok(|entry| {
-                    format_sstr!(
-                        "{}\n{}",
-                        entry
-                            .diary_datetime
-                            .format(format_description!(
-                                "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
-                            ))
-                            .unwrap_or_else(|_| String::new()),
-                        entry.diary_text
-                    )
-                })
-                .try_collect()
-                .await?;
+            let mut diary_entries: Vec<_> =
+                DiaryEntries::get_by_text(&self.journal, search_text, &self.pool)
+                    .await?
+                    .try_filter(|entry| {
+                        let keep = (!starred || entry.starred)
+                            && entry.is_visible(today)
+                            && self.can_read(&entry);
+                        async move { keep }
+                    })
+                    .map_ok(|entry| {
+                        // point at the section with the match
+                        let anchor = section_containing(&entry.diary_text, search_text)
+                            .filter(|s| !s.slug.is_empty())
+                            .map_or_else(StackString::new, |s| format_sstr!(" #{}", s.slug));
+                        format_sstr!("{}{anchor}\n{}", entry.diary_date, entry.diary_text)
+                    })
+                    .try_collect()
+                    .await?;
+            if starred {
+                return Ok(diary_entries);
+            }
+            let diary_cache_entries: Vec<_> =
+                DiaryCache::get_by_text(&self.journal, search_text, &self.pool)
+                    .await?
+                    .map_ok(|entry| {
+                        format_sstr!(
+                            "{}\n{}",
+                            entry
+                                .diary_datetime
+                                .format(format_description!(
+                                    "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+                                ))
+                                .unwrap_or_else(|_| String::new()),
+                            entry.diary_text
+                        )
+                    })
+                    .try_collect()
+                    .await?;
             diary_entries.extend_from_slice(&diary_cache_entries);
             Ok(diary_entries)
         } else {
+            // one query for the entries and one for the cache however many
+            // dates match, e.g. searching a whole year
+            let entries: HashMap<Date, DiaryEntries> =
+                DiaryEntries::get_by_dates(&self.journal, &dates, &self.pool)
+                    .await?
+                    .into_iter()
+                    .map(|entry| (entry.diary_date, entry))
+                    .collect();
+            let mut cache_by_date: HashMap<Date, Vec<StackString>> = HashMap::new();
+            if !starred {
+                for entry in DiaryCache::get_by_journal(&self.journal, &self.pool).await? {
+                    let date = entry.diary_datetime.to_timezone(local).date();
+                    cache_by_date.entry(date).or_default().push(format_sstr!(
+                        "{}\n{}",
+                        entry.diary_datetime,
+                        entry.diary_text
+                    ));
+                }
+            }
             let mut diary_entries = Vec::new();
             for date in dates {
                 debug!("search date {}", date);
-                let entry = DiaryEntries::get_by_date(date, &self.pool)
-                    .await?
+                let entry = entries
+                    .get(&date)
                     .ok_or_else(|| format_err!("Date SHOULD exist {date}"))?;
                 let entry = format_sstr!("{}\n{}", entry.diary_date, entry.diary_text);
                 diary_entries.push(entry);
-                let diary_cache_entries: Vec<_> = DiaryCache::get_cache_entries(&self.pool)
-                    .await?
-                    .try_filter_map(|entry| async move {
-                        if entry.diary_datetime.to_timezone(local).date() == date {
-                            Ok(Some(format_sstr!(
-                                "{}\n{}",
-                                entry.diary_datetime,
-                                entry.diary_text
-                            )))
-                        } else {
-                            Ok(None)
-                        }
-                    })
-                    .try_collect()
-                    .await?;
-                diary_entries.extend_from_slice(&diary_cache_entries);
+                if let Some(diary_cache_entries) = cache_by_date.get(&date) {
+                    diary_entries.extend_from_slice(diary_cache_entries);
+                }
             }
             Ok(diary_entries)
         }
     }
 
+    /// Remote backends (ssh, peer and s3) sit behind circuit breakers, a backend
+    /// that fails is reported in the output (along with whether the failure
+    /// was transient or permanent) and skipped until its reset timeout
+    /// elapses rather than aborting the whole sync.  Only one process syncs
+    /// at a time, `self.sync_lock` decides what happens while another is
     /// # Errors
-    /// Return error if db query fails
+    /// Return `SyncLockError::Held` if another process is syncing, or error
+    /// if db query fails
     pub async fn sync_everything(&self) -> Result<Vec<StackString>, Error> {
+        let holder = sync_holder(self.source.as_deref());
+        let lock = SyncLock::acquire(&self.pool, &holder, self.sync_lock).await?;
+        let result = self.sync_everything_locked().await;
+        lock.release().await?;
+        result
+    }
+
+    async fn sync_everything_locked(&self) -> Result<Vec<StackString>, Error> {
         let mut output = Vec::new();
-        output.extend(
-            self.sync_ssh()
-                .await?
-                .into_iter()
-                .map(|c| format_sstr!("ssh cache {}", c.diary_datetime)),
-        );
+        match self.ssh_breaker.call(self.sync_ssh()).await {
+            Ok(entries) => output.extend(
+                entries
+                    .into_iter()
+                    .map(|c| format_sstr!("ssh cache {}", c.diary_datetime)),
+            ),
+            Err(e) => {
+                self.report_sync_failure("ssh", None, &e);
+                output.push(format_sstr!("ssh sync failed: {e}"));
+            }
+        }
+        match self.peer_breaker.call(self.sync_peer()).await {
+            Ok(lines) => output.extend(lines),
+            Err(e) => {
+                self.report_sync_failure("peer", None, &e);
+                output.push(format_sstr!("peer sync failed: {e}"));
+            }
+        }
 
         output.extend(
             self.sync_merge_cache_to_entries()
@@ -241,6 +1636,40 @@ impl DiaryAppInterface {
                 .map(|c| format_sstr!("update {}", c.diary_date)),
         );
 
+        for journal in self.get_journals().await? {
+            let lines = self
+                .clone()
+                .with_journal(journal.clone())
+                .sync_journal()
+                .await?;
+            if journal == DEFAULT_JOURNAL {
+                output.extend(lines);
+            } else {
+                output.extend(lines.into_iter().map(|l| format_sstr!("{journal}: {l}")));
+            }
+        }
+        output.extend(self.process_s3_outbox().await?);
+
+        self.cleanup_backup().await?;
+        // a sync of a few dates leaves the others as they were
+        if self.local.date_range.is_unbounded() {
+            self.last_sync.lock().replace(DateTimeWrapper::now());
+        }
+
+        Ok(output)
+    }
+
+    #[must_use]
+    pub fn get_last_sync(&self) -> Option<DateTimeWrapper> {
+        *self.last_sync.lock()
+    }
+
+    /// Import and export the entries of `self.journal` to the local
+    /// directory and s3
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn sync_journal(&self) -> Result<Vec<StackString>, Error> {
+        let mut output = Vec::new();
         let local = spawn({
             let local = self.local.clone();
             async move { local.import_from_local().await }
@@ -248,7 +1677,8 @@ impl DiaryAppInterface {
 
         let s3 = spawn({
             let s3 = self.s3.clone();
-            async move { s3.import_from_s3().await }
+            let breaker = self.s3_breaker.clone();
+            async move { breaker.call(s3.import_from_s3()).await }
         });
         output.extend(
             local
@@ -256,11 +1686,17 @@ impl DiaryAppInterface {
                 .into_iter()
                 .map(|c| format_sstr!("local import {}", c.diary_date)),
         );
-        output.extend(
-            s3.await??
-                .into_iter()
-                .map(|c| format_sstr!("s3 import {}", c.diary_date)),
-        );
+        match s3.await? {
+            Ok(entries) => output.extend(
+                entries
+                    .into_iter()
+                    .map(|c| format_sstr!("s3 import {}", c.diary_date)),
+            ),
+            Err(e) => {
+                self.report_sync_failure("s3", None, &e);
+                output.push(format_sstr!("s3 import failed: {e}"));
+            }
+        }
         output.extend(
             self.local
                 .cleanup_local()
@@ -270,92 +1706,293 @@ impl DiaryAppInterface {
         );
         let s3 = spawn({
             let s3 = self.s3.clone();
-            async move { s3.export_to_s3().await }
+            let breaker = self.s3_breaker.clone();
+            async move { breaker.call(s3.export_to_s3()).await }
         });
         let local = spawn({
             let local = self.local.clone();
             async move { local.export_year_to_local().await }
         });
         output.extend_from_slice(&local.await??);
-        output.extend(
-            s3.await??
-                .into_iter()
-                .map(|c| format_sstr!("s3 export {}", c.diary_date)),
-        );
+        match s3.await? {
+            Ok(entries) => output.extend(
+                entries
+                    .into_iter()
+                    .map(|c| format_sstr!("s3 export {}", c.diary_date)),
+            ),
+            Err(e) => {
+                self.report_sync_failure("s3", None, &e);
+                output.push(format_sstr!("s3 export failed: {e}"));
+                // the bucket catches up through the outbox once it's reachable
+                let dates = self.s3.unexported_dates().await?;
+                let queued = S3Outbox::enqueue(&self.journal, &dates, &self.pool).await?;
+                output.push(format_sstr!("s3 outbox queued {queued} dates"));
+            }
+        }
 
-        self.cleanup_backup().await?;
+        Ok(output)
+    }
+
+    /// Retry the s3 uploads of the outbox whose backoff has elapsed, the
+    /// remaining ones wait for the next round after the first failure
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn process_s3_outbox(&self) -> Result<Vec<StackString>, Error> {
+        let mut output = Vec::new();
+        for item in S3Outbox::get_due(&self.pool).await? {
+            let dapp = self.clone().with_journal(item.journal.clone());
+            match self
+                .s3_breaker
+                .call(dapp.s3.upload_entry(item.diary_date))
+                .await
+            {
+                Ok(_) => {
+                    item.delete(&self.pool).await?;
+                    output.push(format_sstr!(
+                        "s3 outbox upload {} {}",
+                        item.journal,
+                        item.diary_date
+                    ));
+                }
+                Err(e) => {
+                    let delay = OUTBOX_RETRY_POLICY.delay(item.attempts as usize);
+                    let next_attempt_at = OffsetDateTime::now_utc() + delay;
+                    item.record_failure(&e.to_string(), next_attempt_at, &self.pool)
+                        .await?;
+                    output.push(format_sstr!(
+                        "s3 outbox {} {} failed: {e}",
+                        item.journal,
+                        item.diary_date
+                    ));
+                    break;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Report a failed sync with `backend` to sentry, `date` when only one
+    /// date was synced
+    pub fn report_sync_failure(&self, backend: &str, date: Option<Date>, error: &Error) {
+        let date = date.map(StackString::from_display);
+        let mut context = vec![("journal", self.journal.as_str()), ("backend", backend)];
+        if let Some(date) = &date {
+            context.push(("date", date.as_str()));
+        }
+        report_error(error, &context);
+    }
 
+    /// Exchange cache entries and entries of every journal with the instance
+    /// at `peer_url`, does nothing unless `peer_url` and `peer_token` are
+    /// configured
+    /// # Errors
+    /// Return error if db query or a peer request fails
+    pub async fn sync_peer(&self) -> Result<Vec<StackString>, Error> {
+        let Some(peer) = PeerClient::from_config(&self.config) else {
+            return Ok(Vec::new());
+        };
+        let mut output = Vec::new();
+        for journal in self.get_journals().await? {
+            let lines = sync_with_peer(&self.pool, &peer, &journal).await?;
+            if journal == DEFAULT_JOURNAL {
+                output.extend(lines);
+            } else {
+                output.extend(lines.into_iter().map(|l| format_sstr!("{journal}: {l}")));
+            }
+        }
         Ok(output)
     }
 
     /// # Errors
     /// Return error if db query fails
     pub async fn sync_merge_cache_to_entries(&self) -> Result<Vec<DiaryEntries>, Error> {
+        // finish the file appends of an interrupted merge first
+        for pending in DiaryPendingAppend::get_all(&self.pool).await? {
+            self.apply_pending_append(&pending).await?;
+        }
         let local = DateTimeWrapper::local_tz();
+        let shared_journals: HashSet<StackString> = Journal::get_all(&self.pool)
+            .await?
+            .try_filter_map(
+                |j| async move { Ok(if j.shared { Some(j.journal_name) } else { None }) },
+            )
+            .try_collect()
+            .await?;
         let date_entry_map = DiaryCache::get_cache_entries(&self.pool)
             .await?
             .try_fold(
                 HashMap::new(),
-                |mut acc: HashMap<Date, Vec<DiaryCache>>, entry| async move {
+                |mut acc: HashMap<(StackString, Date), Vec<DiaryCache>>, entry| async move {
                     let entry_date = entry.diary_datetime.to_timezone(local).date();
-                    acc.entry(entry_date).or_default().push(entry);
+                    acc.entry((entry.journal.clone(), entry_date))
+                        .or_default()
+                        .push(entry);
                     Ok(acc)
                 },
             )
             .await?;
 
-        let futures: FuturesUnordered<_> = date_entry_map
+        let stage = self.progress.start("merge cache", date_entry_map.len());
+
+        let futures: Vec<_> = date_entry_map
             .into_iter()
-            .map(|(entry_date, entry_list)| {
+            .map(|((journal, entry_date), entry_list)| {
+                let shared = shared_journals.contains(&journal);
                 let entry_string: Vec<_> = entry_list
                     .iter()
                     .map(|entry| {
-                        let entry_datetime = entry.diary_datetime.to_timezone(local);
-                        format_sstr!("{}\n{}", entry_datetime, entry.diary_text)
+                        let entry_datetime =
+                            format_sstr!("{}", entry.diary_datetime.to_timezone(local));
+                        let header = match &entry.author {
+                            Some(author) if shared => stamp_header(&entry_datetime, author),
+                            _ => entry_datetime,
+                        };
+                        format_sstr!("{header}\n{}", entry.diary_text)
                     })
                     .collect();
                 let entry_string = entry_string.join("\n\n");
+                // the entry is attributed to the pathway of the latest text
+                let source = entry_list
+                    .iter()
+                    .max_by_key(|entry| entry.diary_datetime)
+                    .and_then(|entry| entry.source.clone());
 
                 let diary_file = self
                     .config
-                    .diary_path
+                    .journal_path(&journal)
                     .join(format_sstr!("{entry_date}.txt"));
 
                 async move {
-                    let result = if diary_file.exists() {
-                        let mut f = OpenOptions::new().append(true).open(&diary_file).await?;
-                        let entry_text = format_sstr!("\n\n{}\n\n", entry_string);
-                        f.write_all(entry_text.as_bytes()).await?;
-                        None
-                    } else if let Some(mut current_entry) =
-                        DiaryEntries::get_by_date(entry_date, &self.pool).await?
+                    // the file is appended to only after the transaction
+                    // removing the cache entries commits
+                    let pending = diary_file
+                        .exists()
+                        .then(|| DiaryPendingAppend::new(&journal, entry_date, &entry_string));
+                    let result = match DiaryCache::merge_date(
+                        &journal,
+                        entry_date,
+                        &entry_list,
+                        &entry_string,
+                        source,
+                        pending.as_ref(),
+                        &self.pool,
+                    )
+                    .await?
                     {
-                        current_entry.diary_text =
-                            format_sstr!("{t}\n\n{entry_string}", t = current_entry.diary_text);
-                        self.stdout
-                            .send(format_sstr!("update {}", diary_file.to_string_lossy()));
-                        current_entry.update_entry(&self.pool, true).await?;
-                        Some(current_entry)
+                        CacheMerge::Merged(result) => result,
+                        CacheMerge::Kept => {
+                            self.stdout.send(format_sstr!(
+                                "keep cache for {entry_date}, entry is encrypted or trashed"
+                            ));
+                            return Ok(None);
+                        }
+                    };
+                    if let Some(pending) = pending {
+                        self.apply_pending_append(&pending).await?;
                     } else {
-                        let new_entry = DiaryEntries::new(entry_date, &entry_string);
                         self.stdout
-                            .send(format_sstr!("upsert {}", diary_file.to_string_lossy()));
-                        new_entry.upsert_entry(&self.pool, true).await?;
-                        Some(new_entry)
-                    };
-                    for entry in entry_list {
-                        entry.delete_entry(&self.pool).await?;
+                            .send(format_sstr!("update {}", diary_file.to_string_lossy()));
                     }
                     Ok(result)
                 }
             })
             .collect();
-        futures
+        stream::iter(futures)
+            .buffer_unordered(self.config.sync_concurrency.max(1))
+            .inspect(|_| stage.increment())
             .try_filter_map(|x| async move { Ok(x) })
             .try_collect()
             .await
     }
 
+    /// Look for cache entries older than `days` days, duplicated or already
+    /// merged and for text merged into an entry more than once in the last
+    /// `days` days.  With `repair` stale cache entries are merged and the
+    /// duplicated or already merged ones removed.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn recover_cache(&self, days: i64, repair: bool) -> Result<Vec<CacheIssue>, Error> {
+        let local = DateTimeWrapper::local_tz();
+        let cutoff = OffsetDateTime::now_utc() - time::Duration::days(days);
+        let cache: Vec<_> = DiaryCache::get_cache_entries(&self.pool)
+            .await?
+            .try_collect()
+            .await?;
+        let subentries = DiarySubentry::get_since(cutoff, &self.pool).await?;
+        let dates: HashSet<(StackString, Date)> = cache
+            .iter()
+            .map(|c| {
+                (
+                    c.journal.clone(),
+                    c.diary_datetime.to_timezone(local).date(),
+                )
+            })
+            .chain(subentries.iter().map(|s| (s.journal.clone(), s.diary_date)))
+            .collect();
+        let mut entries = HashMap::new();
+        for (journal, date) in dates {
+            if let Some(entry) = DiaryEntries::get_by_date(&journal, date, &self.pool).await? {
+                entries.insert((journal, date), entry.diary_text);
+            }
+        }
+        let issues = find_cache_issues(&cache, &subentries, &entries, cutoff, local);
+        if repair {
+            for issue in &issues {
+                match issue {
+                    CacheIssue::Duplicate(item) => item.delete_entry(&self.pool).await?,
+                    CacheIssue::Merged(item) => {
+                        let date = item.diary_datetime.to_timezone(local).date();
+                        DiarySubentry::from_cache(item, date)
+                            .insert(&self.pool)
+                            .await?;
+                        item.delete_entry(&self.pool).await?;
+                    }
+                    CacheIssue::Stale(_) | CacheIssue::DuplicatedText(_) => {}
+                }
+            }
+            if issues.iter().any(|i| matches!(i, CacheIssue::Stale(_))) {
+                self.sync_merge_cache_to_entries().await?;
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Append the text of a merge to its daily file and drop the pending row,
+    /// a text already at the end of the file or entry isn't appended again
+    async fn apply_pending_append(&self, pending: &DiaryPendingAppend) -> Result<(), Error> {
+        let diary_file = self
+            .config
+            .journal_path(&pending.journal)
+            .join(format_sstr!("{}.txt", pending.diary_date));
+        let text = pending.diary_text.trim_end();
+        if diary_file.exists() {
+            let current = read_to_string(&diary_file).await?;
+            if !current.trim_end().ends_with(text) {
+                let mut f = OpenOptions::new().append(true).open(&diary_file).await?;
+                let entry_text = format_sstr!("\n\n{}\n\n", pending.diary_text);
+                f.write_all(entry_text.as_bytes()).await?;
+            }
+        } else {
+            // the file was removed since the merge, the text goes to the entry
+            let entry =
+                DiaryEntries::get_by_date(&pending.journal, pending.diary_date, &self.pool).await?;
+            let entry = match entry {
+                Some(entry) if entry.diary_text.trim_end().ends_with(text) => None,
+                Some(mut entry) => {
+                    entry.diary_text = format_sstr!("{}\n\n{text}", entry.diary_text);
+                    Some(entry)
+                }
+                None => {
+                    Some(DiaryEntries::new(pending.diary_date, text).with_journal(&pending.journal))
+                }
+            };
+            if let Some(entry) = entry {
+                entry.upsert_entry(&self.pool, true).await?;
+            }
+        }
+        pending.delete(&self.pool).await
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn serialize_cache(&self) -> Result<Vec<StackString>, Error> {
@@ -371,13 +2008,38 @@ impl DiaryAppInterface {
             .await
     }
 
+    /// Returns `None` unless `ssh_url` is set to an `ssh://` url
+    pub async fn get_ssh_instance(&self) -> Option<SSHInstance> {
+        let ssh_url: Url = self.config.ssh_url.as_ref()?.parse().ok()?;
+        if ssh_url.scheme() != "ssh" {
+            return None;
+        }
+        SSHInstance::from_url(&ssh_url).await.map(|inst| {
+            inst.with_retry_policy(self.config.retry_policy())
+                .with_options(SSHOptions::from_config(&self.config))
+        })
+    }
+
+    /// # Errors
+    /// Return error if `ssh_url` isn't configured or the connection fails
+    pub async fn check_ssh(&self) -> Result<StackString, Error> {
+        let ssh_inst = self
+            .get_ssh_instance()
+            .await
+            .ok_or_else(|| format_err!("No valid ssh_url configured"))?;
+        ssh_inst.check_connection().await?;
+        Ok(format_sstr!(
+            "ssh connection to {}@{}:{} ok",
+            ssh_inst.user,
+            ssh_inst.host,
+            ssh_inst.port
+        ))
+    }
+
     async fn process_ssh(
-        ssh_url: &Url,
+        ssh_inst: &SSHInstance,
         cache_set: &HashSet<OffsetDateTime>,
     ) -> Result<Vec<DiaryCache>, Error> {
-        let ssh_inst = SSHInstance::from_url(ssh_url)
-            .await
-            .ok_or_else(|| format_err!("Failed to parse url"))?;
         let mut entries = Vec::new();
         for line in ssh_inst
             .run_command_stream_stdout("/usr/bin/diary-app-rust ser")
@@ -395,19 +2057,10 @@ impl DiaryAppInterface {
     /// # Errors
     /// Return error if db query fails
     pub async fn sync_ssh(&self) -> Result<Vec<DiaryCache>, Error> {
-        let ssh_url = match self
-            .config
-            .ssh_url
-            .as_ref()
-            .and_then(|s| s.parse::<Url>().ok())
-        {
-            Some(ssh_url) => Arc::new(ssh_url),
+        let ssh_inst = match self.get_ssh_instance().await {
+            Some(ssh_inst) => ssh_inst,
             None => return Ok(Vec::new()),
         };
-
-        if ssh_url.scheme() != "ssh" {
-            return Ok(Vec::new());
-        }
         let cache_set: HashSet<_> = DiaryCache::get_cache_entries(&self.pool)
             .await?
             .map_ok(|entry| {
@@ -416,7 +2069,28 @@ impl DiaryAppInterface {
             })
             .try_collect()
             .await?;
-        let entries = Self::process_ssh(&ssh_url, &cache_set).await?;
+        let local = DateTimeWrapper::local_tz();
+        let mut watermarks: HashMap<StackString, HashMap<Date, SyncWatermark>> = HashMap::new();
+        let mut entries = Vec::new();
+        for mut item in Self::process_ssh(&ssh_inst, &cache_set).await? {
+            item.source = Some(SSH_SOURCE.into());
+            if !watermarks.contains_key(&item.journal) {
+                let journal_watermarks =
+                    SyncWatermark::get_map(&item.journal, SyncBackend::Ssh, &self.pool).await?;
+                watermarks.insert(item.journal.clone(), journal_watermarks);
+            }
+            // merged cache entries are gone from the cache table, skip those
+            // pulled before the last sync of their date
+            let date = item.diary_datetime.to_timezone(local).date();
+            let diary_datetime: OffsetDateTime = item.diary_datetime.into();
+            if watermarks[&item.journal]
+                .get(&date)
+                .is_some_and(|w| diary_datetime <= OffsetDateTime::from(w.modified))
+            {
+                continue;
+            }
+            entries.push(item);
+        }
         let futures = entries.into_iter().map(|item| {
             let pool = self.pool.clone();
             async move {
@@ -426,12 +2100,31 @@ impl DiaryAppInterface {
         });
         let inserted_entries: Result<Vec<_>, Error> = try_join_all(futures).await;
         let inserted_entries = inserted_entries?;
-        if !inserted_entries.is_empty() {
-            if let Some(inst) = SSHInstance::from_url(&ssh_url).await {
-                inst.run_command_ssh("/usr/bin/diary-app-rust clear")
-                    .await?;
+        let mut latest: HashMap<(StackString, Date), &DiaryCache> = HashMap::new();
+        for item in &inserted_entries {
+            let date = item.diary_datetime.to_timezone(local).date();
+            let current = latest.entry((item.journal.clone(), date)).or_insert(item);
+            if item.diary_datetime > current.diary_datetime {
+                *current = item;
             }
         }
+        for ((journal, date), item) in latest {
+            let hash = sync_hash(&item.diary_text);
+            SyncWatermark::new(
+                journal,
+                date,
+                SyncBackend::Ssh,
+                hash,
+                item.diary_datetime.into(),
+            )
+            .upsert(&self.pool)
+            .await?;
+        }
+        if !inserted_entries.is_empty() {
+            ssh_inst
+                .run_command_ssh("/usr/bin/diary-app-rust clear")
+                .await?;
+        }
         Ok(inserted_entries)
     }
 
@@ -450,22 +2143,26 @@ impl DiaryAppInterface {
             .into_iter()
             .map(|entry| {
                 let entry = entry?;
-                let metadata = entry.metadata()?;
-                Ok((entry.file_name, metadata.len() as usize))
+                Ok((entry.file_name.to_string_lossy().into_owned(), entry.path()))
             })
             .collect();
-        let results: HashMap<_, _> = files?
+        files?
             .into_par_iter()
-            .filter_map(|(filename, backup_size)| {
-                Date::parse(
-                    &filename.to_string_lossy(),
-                    format_description!("[year]-[month]-[day].txt"),
-                )
-                .ok()
-                .map(|date| (date, backup_size))
+            .filter_map(|(filename, path)| {
+                let (base, compression) = Compression::from_filename(&filename);
+                let date =
+                    Date::parse(base, format_description!("[year]-[month]-[day].txt")).ok()?;
+                // compare against the length of the text rather than of the archive
+                let backup_size = match compression {
+                    Compression::None => path
+                        .metadata()
+                        .map(|m| m.len() as usize)
+                        .map_err(Into::into),
+                    _ => archive::read_to_string(&path).map(|text| text.len()),
+                };
+                Some(backup_size.map(|backup_size| (date, backup_size)))
             })
-            .collect();
-        Ok(results)
+            .collect()
     }
 
     /// # Errors
@@ -478,12 +2175,13 @@ impl DiaryAppInterface {
         let file_date_len_map = Arc::new(file_date_len_map?);
         info!("len file_date_len_map {}", file_date_len_map.len());
 
-        let futures: FuturesUnordered<_> = file_date_len_map
+        let futures: Vec<_> = file_date_len_map
             .iter()
             .map(|(date, backup_len)| {
                 let pool = self.pool.clone();
+                let journal = self.journal.clone();
                 async move {
-                    let entry = DiaryEntries::get_by_date(*date, &pool)
+                    let entry = DiaryEntries::get_by_date(&journal, *date, &pool)
                         .await?
                         .ok_or_else(|| format_err!("Date should exist {date}"))?;
                     let diary_len = entry.diary_text.len();
@@ -495,7 +2193,8 @@ impl DiaryAppInterface {
                 }
             })
             .collect();
-        futures
+        stream::iter(futures)
+            .buffer_unordered(self.config.sync_concurrency.max(1))
             .try_filter_map(|x| async move { Ok(x) })
             .try_collect()
             .await
@@ -516,7 +2215,7 @@ impl DiaryAppInterface {
         }
         let results = self.validate_backup().await?;
 
-        let futures: FuturesUnordered<_> = results
+        let futures: Vec<_> = results
             .into_iter()
             .map(|(date, backup_len, diary_len)| {
                 let backup_directory = &backup_directory;
@@ -543,7 +2242,8 @@ impl DiaryAppInterface {
                 }
             })
             .collect();
-        futures
+        stream::iter(futures)
+            .buffer_unordered(self.config.sync_concurrency.max(1))
             .try_filter_map(|x| async move { Ok(x) })
             .try_collect()
             .await
@@ -555,54 +2255,154 @@ mod tests {
     use anyhow::Error;
     use futures::TryStreamExt;
     use log::debug;
-    use time::macros::{date, datetime, format_description};
+    use stack_string::format_sstr;
+    use std::collections::HashSet;
+    use time::{
+        macros::{date, datetime, format_description},
+        Duration, OffsetDateTime,
+    };
+    use time_tz::OffsetDateTimeExt;
 
     use crate::{
         config::Config,
-        diary_app_interface::DiaryAppInterface,
-        models::{DiaryCache, DiaryConflict, DiaryEntries},
+        date_time_wrapper::DateTimeWrapper,
+        diary_app_interface::{current_streak, DiaryAppInterface},
+        models::{
+            CacheItem, DateListQuery, DateRange, DiaryCache, DiaryConflict, DiaryEntries,
+            DiaryPendingAppend, S3Outbox, StatsPeriod, API_SOURCE,
+        },
         pgpool::PgPool,
+        test_db::{ConflictFixture, EntryFixture, TestDb},
     };
 
-    async fn get_dap() -> Result<DiaryAppInterface, Error> {
+    async fn get_dap() -> Result<(TestDb, DiaryAppInterface), Error> {
         let config = Config::init_config()?;
         let sdk_config = aws_config::load_from_env().await;
-        let pool = PgPool::new(&config.database_url)?;
-        Ok(DiaryAppInterface::new(config, &sdk_config, pool))
+        let db = TestDb::new().await?;
+        let dap = DiaryAppInterface::new(config, &sdk_config, db.pool.clone());
+        Ok((db, dap))
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_search_text() -> Result<(), Error> {
-        let dap = get_dap().await?;
-        let test_date = date!(2011 - 05 - 23);
-        let original_text = DiaryEntries::get_by_date(test_date, &dap.pool).await?;
-        if original_text.is_none() {
-            let test_entry = DiaryEntries::new(test_date, "test_text");
-            test_entry.insert_entry(&dap.pool).await?;
-        }
+        let (db, dap) = get_dap().await?;
+        EntryFixture::new(date!(2011 - 05 - 23), "test_text")
+            .insert(&dap.pool)
+            .await?;
 
         let results = dap.search_text("2011-05-23").await?;
         assert_eq!(results.len(), 1);
         assert!(results[0].starts_with("2011-05-23"));
-        let results = results.join("\n");
-        match &original_text {
-            Some(t) => assert!(results.contains(t.diary_text.as_str())),
-            None => assert!(results.contains("test_text")),
-        }
+        assert!(results.join("\n").contains("test_text"));
 
         let results = dap.search_text("1952-01-01").await?;
         assert_eq!(results.len(), 0);
 
-        if original_text.is_none() {
-            let test_entry = DiaryEntries::new(test_date, "test_text");
-            test_entry.delete_entry(&dap.pool).await?;
+        EntryFixture::new(date!(2011 - 05 - 24), "caf\u{e9} \u{1f389} 100% fun")
+            .insert(&dap.pool)
+            .await?;
+        for search_text in ["\u{1f389}", "cafe\u{301}", "100%", "\u{e9} \u{1f389}"] {
+            let results = dap.search_text(search_text).await?;
+            assert_eq!(results.len(), 1, "{search_text}");
+            assert!(results[0].starts_with("2011-05-24"));
         }
-        Ok(())
+        // wildcards are taken literally
+        assert!(dap.search_text("10_%").await?.is_empty());
+        assert!(dap.search_text("caf%fun").await?.is_empty());
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_hostile_input() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        EntryFixture::new(date!(2011 - 05 - 23), "it's -- fine")
+            .insert(&dap.pool)
+            .await?;
+        let hostile = [
+            "' OR '1'='1",
+            "'; DROP TABLE diary_entries; --",
+            "%' OR journal <> '",
+            "\\'",
+        ];
+        for text in hostile {
+            let entries: Vec<_> = DiaryEntries::get_by_text(&dap.journal, text, &dap.pool)
+                .await?
+                .try_collect()
+                .await?;
+            assert!(entries.is_empty(), "{text}");
+            let cache: Vec<_> = DiaryCache::get_by_text(&dap.journal, text, &dap.pool)
+                .await?
+                .try_collect()
+                .await?;
+            assert!(cache.is_empty(), "{text}");
+            let journal = format_sstr!("{}{text}", dap.journal);
+            let mod_map = DiaryEntries::get_modified_map(&journal, &dap.pool, None, None).await?;
+            assert!(mod_map.is_empty(), "{text}");
+            let encrypted: Vec<_> = DiaryEntries::get_encrypted(&journal, &dap.pool, None, None)
+                .await?
+                .try_collect()
+                .await?;
+            assert!(encrypted.is_empty(), "{text}");
+        }
+        // quotes and comment markers are matched as text
+        let entries: Vec<_> = DiaryEntries::get_by_text(&dap.journal, "it's --", &dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(entries.len(), 1);
+        let mod_map = DiaryEntries::get_modified_map(
+            &dap.journal,
+            &dap.pool,
+            Some(date!(2011 - 05 - 23)),
+            Some(date!(2011 - 05 - 23)),
+        )
+        .await?;
+        assert_eq!(mod_map.len(), 1);
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_s3_outbox() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        EntryFixture::insert_days(date!(2011 - 05 - 23), 3, &dap.pool).await?;
+
+        let dates = dap.s3.unexported_dates().await?;
+        assert_eq!(
+            dates,
+            [
+                date!(2011 - 05 - 23),
+                date!(2011 - 05 - 24),
+                date!(2011 - 05 - 25)
+            ]
+        );
+        assert_eq!(S3Outbox::enqueue("diary", &dates, &dap.pool).await?, 3);
+        assert_eq!(S3Outbox::enqueue("diary", &dates[..1], &dap.pool).await?, 0);
+
+        let due = S3Outbox::get_due(&dap.pool).await?;
+        assert_eq!(due.len(), 3);
+        let later = OffsetDateTime::now_utc() + Duration::hours(1);
+        due[0]
+            .record_failure("unreachable", later, &dap.pool)
+            .await?;
+        due[1].delete(&dap.pool).await?;
+
+        let due = S3Outbox::get_due(&dap.pool).await?;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].diary_date, date!(2011 - 05 - 25));
+        let all = S3Outbox::get_all(&dap.pool).await?;
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].attempts, 1);
+        assert_eq!(all[0].last_error.as_deref(), Some("unreachable"));
+        db.cleanup().await
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_list_of_dates() -> Result<(), Error> {
-        let dap = get_dap().await?;
+        let (db, dap) = get_dap().await?;
+        EntryFixture::insert_days(date!(2011 - 05 - 23), 167, &dap.pool).await?;
+        EntryFixture::new(date!(2012 - 01 - 02), "outside the range")
+            .insert(&dap.pool)
+            .await?;
 
         let results = dap
             .get_list_of_dates(
@@ -610,6 +2410,7 @@ mod tests {
                 Some(date!(2012 - 01 - 01)),
                 None,
                 None,
+                false,
             )
             .await?;
         assert_eq!(results.len(), 167);
@@ -620,28 +2421,83 @@ mod tests {
                 Some(date!(2012 - 01 - 01)),
                 None,
                 Some(10),
+                false,
             )
             .await?;
         assert_eq!(results.len(), 10);
-        Ok(())
+
+        let mut query = DateListQuery {
+            range: DateRange::new(Some(date!(2011 - 05 - 23)), Some(date!(2012 - 01 - 01))),
+            limit: Some(100),
+            ..DateListQuery::default()
+        };
+        let page = dap.get_date_page(query).await?;
+        assert_eq!(page.dates.len(), 100);
+        assert_eq!(page.next_cursor, page.dates.last().copied());
+        query.cursor = page.next_cursor;
+        let next_page = dap.get_date_page(query).await?;
+        assert_eq!(next_page.dates.len(), 67);
+        assert_eq!(next_page.next_cursor, None);
+        assert!(next_page.dates[0] < page.dates[99]);
+        assert_eq!(next_page.dates[66], date!(2011 - 05 - 23));
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_date_groups() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        EntryFixture::insert_days(date!(2011 - 05 - 30), 4, &dap.pool).await?;
+
+        let groups = dap.get_date_groups(StatsPeriod::Month).await?;
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].period, date!(2011 - 06 - 01));
+        assert_eq!(groups[0].entries, 2);
+        // three words each, "entry for 2011-06-01"
+        assert_eq!(groups[0].words, 6);
+        assert_eq!(groups[1].period, date!(2011 - 05 - 01));
+
+        let groups = dap.get_date_groups(StatsPeriod::Year).await?;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].entries, 4);
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_entry_availability() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        EntryFixture::insert_days(date!(2011 - 05 - 23), 3, &dap.pool).await?;
+        EntryFixture::new(date!(2011 - 06 - 01), "later")
+            .insert(&dap.pool)
+            .await?;
+
+        let range = DateRange::new(Some(date!(2011 - 05 - 24)), Some(date!(2011 - 05 - 31)));
+        let availability = dap.get_entry_availability(range).await?;
+        assert_eq!(availability.min_date, Some(date!(2011 - 05 - 23)));
+        assert_eq!(availability.max_date, Some(date!(2011 - 06 - 01)));
+        assert_eq!(availability.count, 4);
+        assert_eq!(availability.days, "11000000");
+        db.cleanup().await
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_matching_dates() -> Result<(), Error> {
-        let dap = get_dap().await?;
-        let mod_map = DiaryEntries::get_modified_map(&dap.pool, None, None).await?;
+        let (db, dap) = get_dap().await?;
+        EntryFixture::insert_days(date!(2011 - 01 - 01), 41, &dap.pool).await?;
+        EntryFixture::insert_days(date!(2011 - 06 - 01), 23, &dap.pool).await?;
+        EntryFixture::insert_days(date!(2012 - 06 - 01), 5, &dap.pool).await?;
+        let mod_map = DiaryEntries::get_modified_map(&dap.journal, &dap.pool, None, None).await?;
 
         let results = DiaryAppInterface::get_matching_dates(&mod_map, Some(2011), None, None);
-        assert_eq!(results.len(), 288);
+        assert_eq!(results.len(), 64);
 
         let results = DiaryAppInterface::get_matching_dates(&mod_map, Some(2011), Some(6), None);
         assert_eq!(results.len(), 23);
-        Ok(())
+        db.cleanup().await
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_cache_text() -> Result<(), Error> {
-        let dap = get_dap().await?;
+        let (db, dap) = get_dap().await?;
 
         let test_text = "Test text";
         let result = dap.cache_text(test_text).await?;
@@ -656,12 +2512,138 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0], result);
         assert!(results2[0].contains("Test text"));
-        Ok(())
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_merge_cache_keeps_subentries() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let dap = dap.with_source(API_SOURCE);
+        let first = datetime!(1950-01-01 12:00 UTC);
+        let second = datetime!(1950-01-01 12:30 UTC);
+        for (text, datetime) in [("morning", first), ("lunch", second)] {
+            let item = CacheItem {
+                diary_text: text.into(),
+                diary_datetime: Some(datetime.into()),
+                idempotency_key: None,
+            };
+            dap.cache_item(item).await?;
+        }
+        let date = first.to_timezone(DateTimeWrapper::local_tz()).date();
+
+        let merged = dap.sync_merge_cache_to_entries().await?;
+        assert_eq!(merged.len(), 1);
+
+        let subentries = dap.get_subentries(date).await?;
+        let texts: Vec<_> = subentries.iter().map(|s| s.diary_text.as_str()).collect();
+        assert_eq!(texts, vec!["morning", "lunch"]);
+        assert!(subentries
+            .iter()
+            .all(|s| s.source.as_deref() == Some(API_SOURCE)));
+        let entry = DiaryEntries::get_by_date(&dap.journal, date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(
+            entry.metadata["written_at"].as_array().map(Vec::len),
+            Some(2)
+        );
+        assert_eq!(entry.source.as_deref(), Some(API_SOURCE));
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_merge_cache_into_encrypted_entry() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let datetime = datetime!(1950-01-03 12:00 UTC);
+        let date = datetime.to_timezone(DateTimeWrapper::local_tz()).date();
+        DiaryEntries::new_encrypted(date, vec![1, 2, 3], vec![0; 12])
+            .with_journal(&dap.journal)
+            .insert_entry(&dap.pool)
+            .await?;
+        let item = CacheItem {
+            diary_text: "plain text".into(),
+            diary_datetime: Some(datetime.into()),
+            idempotency_key: None,
+        };
+        dap.cache_item(item).await?;
+
+        let merged = dap.sync_merge_cache_to_entries().await?;
+        assert!(merged.is_empty());
+        let entry = DiaryEntries::get_by_date(&dap.journal, date, &dap.pool)
+            .await?
+            .unwrap();
+        assert!(entry.is_encrypted);
+        assert_eq!(entry.diary_ciphertext, Some(vec![1, 2, 3]));
+        let cache: Vec<_> = DiaryCache::get_cache_entries(&dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache[0].diary_text, "plain text");
+        assert!(dap.get_subentries(date).await?.is_empty());
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_merge_cache_into_trashed_entry() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let datetime = datetime!(1950-01-04 12:00 UTC);
+        let date = datetime.to_timezone(DateTimeWrapper::local_tz()).date();
+        let entry = DiaryEntries::new(date, "first").with_journal(&dap.journal);
+        entry.insert_entry(&dap.pool).await?;
+        entry.delete_entry(&dap.pool).await?;
+        let item = CacheItem {
+            diary_text: "late text".into(),
+            diary_datetime: Some(datetime.into()),
+            idempotency_key: None,
+        };
+        dap.cache_item(item).await?;
+
+        assert!(dap.sync_merge_cache_to_entries().await?.is_empty());
+        let cache: Vec<_> = DiaryCache::get_cache_entries(&dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(cache.len(), 1);
+
+        // once restored the cache goes to the entry
+        DiaryEntries::restore_entry(&dap.journal, date, &dap.pool).await?;
+        let merged = dap.sync_merge_cache_to_entries().await?;
+        assert_eq!(merged.len(), 1);
+        assert!(merged[0].diary_text.starts_with("first\n\n"));
+        assert!(merged[0].diary_text.ends_with("late text"));
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_merge_cache_replays_pending_append() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let date = date!(1950 - 01 - 02);
+        EntryFixture::new(date, "first").insert(&dap.pool).await?;
+        // an interrupted merge whose daily file is gone by now
+        let pending = DiaryPendingAppend::new(&dap.journal, date, "late text");
+        pending.insert(&dap.pool).await?;
+
+        dap.sync_merge_cache_to_entries().await?;
+        assert!(DiaryPendingAppend::get_all(&dap.pool).await?.is_empty());
+        let entry = DiaryEntries::get_by_date(&dap.journal, date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "first\n\nlate text");
+
+        // replaying the same append again leaves the entry alone
+        pending.insert(&dap.pool).await?;
+        dap.apply_pending_append(&pending).await?;
+        let entry = DiaryEntries::get_by_date(&dap.journal, date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "first\n\nlate text");
+        db.cleanup().await
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_replace_text() -> Result<(), Error> {
-        let dap = get_dap().await?;
+        let (db, dap) = get_dap().await?;
         let test_date = date!(1950 - 01 - 01);
         let test_text = "Test text";
 
@@ -670,7 +2652,7 @@ mod tests {
         let test_text2 = "Test text2";
         let (result2, conflict2) = dap.replace_text(test_date, test_text2).await?;
 
-        result.delete_entry(&dap.pool).await?;
+        result.purge_entry(&dap.pool).await?;
 
         assert_eq!(result.diary_date, test_date);
         assert!(conflict.is_none());
@@ -678,19 +2660,132 @@ mod tests {
         assert_eq!(result2.diary_text.as_str(), test_text2);
         assert!(conflict2.is_some());
         let conflict2 = conflict2.unwrap();
-        let result3: Vec<_> = DiaryConflict::get_by_datetime(conflict2.into(), &dap.pool)
+        let result3: Vec<_> = DiaryConflict::get_by_key(&dap.journal, conflict2, &dap.pool)
             .await?
             .try_collect()
             .await?;
         assert_eq!(result3.len(), 2);
-        DiaryConflict::remove_by_datetime(conflict2.into(), &dap.pool).await?;
-        Ok(())
+        DiaryConflict::remove_by_key(conflict2, &dap.pool).await?;
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replace_text_normalizes() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let test_date = date!(1950 - 01 - 02);
+        dap.replace_text(test_date, "cafe\u{301}  \r\nline\n")
+            .await?;
+        let entry = DiaryEntries::get_by_date(&dap.journal, test_date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "caf\u{e9}\nline");
+
+        // the same text from another platform isn't a conflict
+        let entry =
+            DiaryEntries::new(test_date, "cafe\u{301}\r\nline  ").with_journal(&dap.journal);
+        assert!(entry.upsert_entry(&dap.pool, true).await?.is_none());
+        let entry = DiaryEntries::get_by_date(&dap.journal, test_date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "caf\u{e9}\nline");
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_conflict_fixture() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let test_date = date!(1950 - 01 - 02);
+        let datetime = ConflictFixture::new(test_date, "a\nb\nc", "a\nB\nc")
+            .insert(&dap.pool)
+            .await?;
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&dap.journal, datetime, &dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        let diff_types: Vec<_> = conflicts.iter().map(|c| c.diff_type.as_str()).collect();
+        assert_eq!(diff_types, ["same", "rem", "add", "same"]);
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_commit_conflict_journal() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let test_date = date!(1950 - 01 - 04);
+        let work = dap.clone().with_journal("work");
+        dap.replace_text(test_date, "diary text").await?;
+        work.replace_text(test_date, "a\nb").await?;
+        let (_, conflict) = work.replace_text(test_date, "a\nc").await?;
+        let conflict = conflict.unwrap();
+
+        // the conflict of "work" isn't visible from the default journal
+        assert!(dap.commit_conflict(conflict).await.is_err());
+        let entry = DiaryEntries::get_by_date(&dap.journal, test_date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "diary text");
+
+        let (entry, _) = work.revert_conflict(conflict).await?;
+        assert_eq!(entry.journal, "work");
+        assert_eq!(entry.diary_text, "a\nb");
+        let entry = DiaryEntries::get_by_date(&dap.journal, test_date, &dap.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "diary text");
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_conflicts_close_together() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let test_date = date!(1950 - 01 - 03);
+        let sync_datetime = OffsetDateTime::now_utc();
+        let first = ConflictFixture::new(test_date, "a\nb", "a\nc")
+            .sync_datetime(sync_datetime)
+            .insert(&dap.pool)
+            .await?;
+        let second = ConflictFixture::new(test_date, "a\nc", "a\nd")
+            .sync_datetime(sync_datetime + Duration::microseconds(300))
+            .insert(&dap.pool)
+            .await?;
+
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&dap.journal, first, &dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        let texts: Vec<_> = conflicts.iter().map(|c| c.diff_text.as_str()).collect();
+        assert_eq!(texts, ["a", "b", "c"]);
+        let group = conflicts[0].conflict_group;
+
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&dap.journal, second, &dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        let texts: Vec<_> = conflicts.iter().map(|c| c.diff_text.as_str()).collect();
+        assert_eq!(texts, ["a", "c", "d"]);
+        assert_ne!(conflicts[0].conflict_group, group);
+
+        DiaryConflict::remove_by_key(group, &dap.pool).await?;
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&dap.journal, group, &dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        assert!(conflicts.is_empty());
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&dap.journal, second, &dap.pool)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(conflicts.len(), 3);
+        db.cleanup().await
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[ignore]
     async fn test_validate_backup() -> Result<(), Error> {
-        let dap = get_dap().await?;
+        // checks the backups of the real entries
+        let config = Config::init_config()?;
+        let sdk_config = aws_config::load_from_env().await;
+        let pool = PgPool::new(&config.database_url)?;
+        let dap = DiaryAppInterface::new(config, &sdk_config, pool);
         let results = dap.validate_backup().await?;
         for (date, backup_len, diary_len) in results.iter() {
             println!(
@@ -712,4 +2807,22 @@ mod tests {
         assert_eq!(&f, "2022-01-01T01:02:03.12341Z");
         Ok(())
     }
+
+    #[test]
+    fn test_current_streak() {
+        let today = date!(2024 - 03 - 10);
+        let dates: HashSet<_> = [
+            date!(2024 - 03 - 10),
+            date!(2024 - 03 - 09),
+            date!(2024 - 03 - 08),
+            date!(2024 - 03 - 06),
+        ]
+        .iter()
+        .copied()
+        .collect();
+        assert_eq!(current_streak(&dates, today), 3);
+        assert_eq!(current_streak(&dates, date!(2024 - 03 - 11)), 3);
+        assert_eq!(current_streak(&dates, date!(2024 - 03 - 12)), 0);
+        assert_eq!(current_streak(&HashSet::new(), today), 0);
+    }
 }
@@ -1,23 +1,32 @@
 use anyhow::{format_err, Error};
 use clap::Parser;
 use futures::TryStreamExt;
-use refinery::embed_migrations;
-use stack_string::StackString;
-use std::{collections::BTreeSet, str::FromStr};
+use stack_string::{format_sstr, StackString};
+use std::{
+    io::{stdin, stdout},
+    str::FromStr,
+};
 use time::{
     format_description::well_known::Rfc3339, macros::format_description, Date, OffsetDateTime,
 };
 use time_tz::{timezones::db::UTC, OffsetDateTimeExt};
+use tokio::task::{spawn, spawn_blocking};
+use uuid::Uuid;
 
 use crate::{
+    cache_recovery::RECOVER_CACHE_DAYS,
+    commands::{CommandOutput, DiaryCommand},
     config::Config,
+    conflict_wizard::ConflictWizard,
+    db_migrations::run_migrations,
     diary_app_interface::DiaryAppInterface,
-    models::{DiaryCache, DiaryConflict},
+    models::{ConflictKey, DateRange, DiaryCache, DiaryConflict, Journal, CLI_SOURCE},
     pgpool::PgPool,
+    s3_replica::S3Replica,
+    services::{ConflictList, DiaryService},
+    sync_lock::{SyncLockError, SyncLockMode},
 };
 
-embed_migrations!("../migrations");
-
 #[derive(Debug, Clone, Copy)]
 pub enum DiaryAppCommands {
     Search,
@@ -28,7 +37,27 @@ pub enum DiaryAppCommands {
     ListConflicts,
     ShowConflict,
     RemoveConflict,
+    CommitConflict,
+    CleanConflicts,
+    UpdateConflict,
+    Resolve,
     RunMigrations,
+    SshCheck,
+    Delete,
+    Journals,
+    CreateJournal,
+    Schedule,
+    S3Versioning,
+    S3Lifecycle,
+    S3Versions,
+    Restore,
+    ValidateReplica,
+    S3Compact,
+    S3Recompress,
+    Stats,
+    RebuildTerms,
+    RecoverCache,
+    LintFiles,
 }
 
 impl FromStr for DiaryAppCommands {
@@ -44,7 +73,27 @@ impl FromStr for DiaryAppCommands {
             "list" | "list_conflicts" => Ok(Self::ListConflicts),
             "show" | "show_conflict" => Ok(Self::ShowConflict),
             "remove" | "remove_conflict" => Ok(Self::RemoveConflict),
+            "commit" | "commit_conflict" => Ok(Self::CommitConflict),
+            "clean_conflicts" => Ok(Self::CleanConflicts),
+            "update_conflict" => Ok(Self::UpdateConflict),
+            "resolve" => Ok(Self::Resolve),
             "run-migrations" => Ok(Self::RunMigrations),
+            "ssh-check" => Ok(Self::SshCheck),
+            "delete" => Ok(Self::Delete),
+            "journals" => Ok(Self::Journals),
+            "create-journal" => Ok(Self::CreateJournal),
+            "schedule" => Ok(Self::Schedule),
+            "s3-versioning" => Ok(Self::S3Versioning),
+            "s3-lifecycle" => Ok(Self::S3Lifecycle),
+            "s3-versions" => Ok(Self::S3Versions),
+            "restore" => Ok(Self::Restore),
+            "validate-replica" => Ok(Self::ValidateReplica),
+            "s3-compact" => Ok(Self::S3Compact),
+            "s3-recompress" => Ok(Self::S3Recompress),
+            "stats" => Ok(Self::Stats),
+            "rebuild-terms" => Ok(Self::RebuildTerms),
+            "recover-cache" => Ok(Self::RecoverCache),
+            "lint-files" => Ok(Self::LintFiles),
             _ => Err(format_err!("Parse failure")),
         }
     }
@@ -54,20 +103,126 @@ fn parse_commands_from_str(s: &str) -> Result<DiaryAppCommands, String> {
     s.parse().map_err(|e| format!("{e}"))
 }
 
+fn parse_date_from_str(s: &str) -> Result<Date, String> {
+    Date::parse(s, format_description!("[year]-[month]-[day]")).map_err(|e| format!("{e}"))
+}
+
+/// Conflict with the group or recorded at the datetime given as text, or the
+/// first one
+async fn conflict_key(
+    text: &[StackString],
+    dap: &DiaryAppInterface,
+) -> Result<Option<ConflictKey>, Error> {
+    let text = text.join("");
+    if let Ok(group) = text.parse::<Uuid>() {
+        return Ok(Some(group.into()));
+    }
+    if let Ok(datetime) = OffsetDateTime::parse(&text.replace('Z', "+00:00"), &Rfc3339) {
+        return Ok(Some(datetime.to_timezone(UTC).into()));
+    }
+    let first = DiaryConflict::get_first_conflict(&dap.journal, &dap.pool).await?;
+    Ok(first.map(Into::into))
+}
+
+/// Ask about each change of the conflict `key` and replace the entry with
+/// the merged text, returns false if the user quits
+async fn resolve_conflict(dap: &DiaryAppInterface, key: ConflictKey) -> Result<bool, Error> {
+    let service = DiaryService::new(dap);
+    let conflicts = service.show_conflict(key).await?;
+    let Some(date) = conflicts.first().map(|entry| entry.diary_date) else {
+        return Ok(false);
+    };
+    let wizard = ConflictWizard::new(&conflicts);
+    // the prompts go straight to the terminal, answers are read between them
+    let choices = spawn_blocking({
+        let wizard = wizard.clone();
+        move || {
+            println!("conflict {key} for {date}");
+            wizard.prompt(&mut stdin().lock(), &mut stdout().lock())
+        }
+    })
+    .await??;
+    let Some(choices) = choices else {
+        return Ok(false);
+    };
+    let text = wizard.merge(&choices)?;
+    dap.replace_text(date, text).await?;
+    service.remove_conflict(key).await?;
+    println!("resolved conflict for {date}");
+    Ok(true)
+}
+
 #[derive(Parser, Debug, Clone)]
 pub struct DiaryAppOpts {
     #[clap(value_parser = parse_commands_from_str)]
-    /// Available commands are "(s)earch", "(i)nsert", "sync", "serialize,
-    /// "clear", "clear_cache", "list", "list_conflicts", "show",
-    /// "show_conflict", "remove", "remove_conflict"
+    /// Available commands are "(s)earch", "(i)nsert", "sync" (gives up
+    /// while another process syncs unless given --wait or --force),
+    /// "serialize, "clear", "clear_cache", "list", "list_conflicts", "show",
+    /// "show_conflict", "remove", "remove_conflict", "commit",
+    /// "commit_conflict" (the conflict with the group id or at the datetime
+    /// given as text, or the first one), "clean_conflicts" (every conflict of the date given as
+    /// text), "update_conflict" (the id of a conflict line listed by "show"
+    /// and "add" or "rem" given as text), "resolve" (asks about each change
+    /// of the conflict given as text, or of every conflict,
+    /// answer e to edit the change in $EDITOR), "ssh-check", "delete",
+    /// "journals", "create-journal", "schedule" (the first text argument is
+    /// the date to reveal the entry on), "s3-versioning", "s3-lifecycle",
+    /// "s3-versions" (versions of the date given as text) and "restore"
+    /// (the version of the date given with --version-id),
+    /// "validate-replica" (copies missing keys to the replica with --yes),
+    /// "s3-compact" (replaces the deltas in s3 with full snapshots),
+    /// "s3-recompress" (applies s3_compression to existing entries),
+    /// "stats" (entry sizes by year, biggest entries and s3 bytes),
+    /// "rebuild-terms" (recounts the terms behind /api/stats/words),
+    /// "recover-cache" (reports cache entries an interrupted merge left
+    /// behind, repaired with --yes), "lint-files" (reports daily files sync
+    /// would skip or garble, renamed or rewritten with backups with --yes)
     pub command: DiaryAppCommands,
     #[clap(
         short = 't',
         long = "text",
         required_if_eq("command", "search"),
-        required_if_eq("command", "insert")
+        required_if_eq("command", "insert"),
+        required_if_eq("command", "delete"),
+        required_if_eq("command", "clean_conflicts"),
+        required_if_eq("command", "update_conflict"),
+        required_if_eq("command", "create-journal"),
+        required_if_eq("command", "schedule"),
+        required_if_eq("command", "s3-versions"),
+        required_if_eq("command", "restore")
     )]
     pub text: Vec<StackString>,
+    /// Journal to operate on, defaults to "diary"
+    #[clap(short = 'j', long = "journal")]
+    pub journal: Option<StackString>,
+    /// Confirm deleting an entry or repairing the replica bucket, the cache
+    /// or the daily files
+    #[clap(long = "yes")]
+    pub yes: bool,
+    /// Create a journal several users write into
+    #[clap(long = "shared")]
+    pub shared: bool,
+    /// Email inserted text is attributed to in shared journals
+    #[clap(long = "author")]
+    pub author: Option<StackString>,
+    /// Only import and export dates from this one on when syncing
+    #[clap(long = "since", value_parser = parse_date_from_str)]
+    pub since: Option<Date>,
+    /// Only import and export dates up to this one when syncing
+    #[clap(long = "until", value_parser = parse_date_from_str)]
+    pub until: Option<Date>,
+    /// S3 object version to restore, listed by "s3-versions"
+    #[clap(long = "version-id", required_if_eq("command", "restore"))]
+    pub version_id: Option<StackString>,
+    /// Age in days of cache entries "recover-cache" reports as stale
+    #[clap(long = "days")]
+    pub days: Option<i64>,
+    /// Wait for a sync running elsewhere to finish instead of giving up
+    #[clap(long = "wait", conflicts_with = "force")]
+    pub wait: bool,
+    /// Take over the sync lock from a sync running elsewhere
+    #[clap(long = "force")]
+    pub force: bool,
 }
 
 impl DiaryAppOpts {
@@ -77,20 +232,61 @@ impl DiaryAppOpts {
         let opts = Self::parse();
 
         let config = Config::init_config()?;
-        let pool = PgPool::new(&config.database_url)?;
+        let pool = PgPool::new(&config.database_url)?.with_retry_policy(config.retry_policy());
         let sdk_config = aws_config::load_from_env().await;
-        let dap = DiaryAppInterface::new(config, &sdk_config, pool);
+        let mut dap = DiaryAppInterface::new(config.clone(), &sdk_config, pool);
+        if let Some(journal) = opts.journal {
+            if Journal::get_by_name(&journal, &dap.pool).await?.is_none() {
+                return Err(format_err!("No journal named {journal}"));
+            }
+            dap = dap.with_journal(journal);
+        }
+        if let Some(author) = opts.author {
+            dap = dap.with_author(author);
+        }
+        dap = dap.with_source(CLI_SOURCE);
+        dap = dap.with_date_range(DateRange::new(opts.since, opts.until));
+        if opts.wait {
+            dap = dap.with_sync_lock(SyncLockMode::Wait);
+        } else if opts.force {
+            dap = dap.with_sync_lock(SyncLockMode::Force);
+        }
 
         match opts.command {
             DiaryAppCommands::Search => {
-                let result = dap.search_text(&opts.text.join(" ")).await?;
-                dap.stdout.send(result.join("\n"));
+                let output = DiaryCommand::Search(opts.text.join(" ").into())
+                    .run(&dap)
+                    .await?;
+                dap.stdout.send(output.lines().join("\n"));
             }
             DiaryAppCommands::Insert => {
-                dap.cache_text(&opts.text.join(" ")).await?;
+                let output = DiaryCommand::Insert(opts.text.join(" ").into())
+                    .run(&dap)
+                    .await?;
+                dap.stdout.send(output.lines().join("\n"));
             }
             DiaryAppCommands::Sync => {
-                dap.sync_everything().await?;
+                let progress_task = spawn({
+                    let mut recv = dap.progress.subscribe();
+                    let stdout = dap.stdout.clone();
+                    async move {
+                        while recv.changed().await.is_ok() {
+                            let progress = recv.borrow().clone();
+                            stdout.send(format_sstr!("{progress}"));
+                        }
+                    }
+                });
+                let result = DiaryCommand::Sync.run(&dap).await;
+                progress_task.abort();
+                match result {
+                    Ok(output) => dap.stdout.send(output.lines().join("\n")),
+                    Err(e) => {
+                        return match e.downcast::<SyncLockError>() {
+                            Ok(e) => Err(format_err!("{e}, run with --wait or --force")),
+                            Err(e) => Err(e),
+                        };
+                    }
+                }
             }
             DiaryAppCommands::Serialize => {
                 for entry in dap.serialize_cache().await? {
@@ -108,92 +304,230 @@ impl DiaryAppOpts {
                 }
             }
             DiaryAppCommands::ListConflicts => {
-                async fn get_all_conflicts(
-                    dap: &DiaryAppInterface,
-                    date: Date,
-                ) -> Result<(), Error> {
-                    let conflicts: BTreeSet<_> = DiaryConflict::get_by_date(date, &dap.pool)
-                        .await?
-                        .try_collect()
-                        .await?;
-                    for entry in conflicts {
-                        let timestamp: StackString = entry
-                            .format(format_description!(
-                                "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]Z"
-                            ))
-                            .unwrap_or_else(|_| String::new())
-                            .into();
-                        dap.stdout.send(timestamp);
-                    }
-                    Ok(())
-                }
-
-                if let Ok(date) = Date::parse(
-                    &opts.text.join(""),
-                    format_description!("[year]-[month]-[day]"),
-                ) {
-                    get_all_conflicts(&dap, date).await?;
-                } else {
-                    let conflicts: Vec<_> = DiaryConflict::get_all_dates(&dap.pool)
-                        .await?
-                        .try_collect()
-                        .await?;
-                    if conflicts.len() > 1 {
-                        for date in conflicts {
-                            let date = StackString::from_display(date);
-                            dap.stdout.send(date);
-                        }
-                    } else {
-                        for date in conflicts {
-                            get_all_conflicts(&dap, date).await?;
-                        }
+                let date = parse_date_from_str(&opts.text.join("")).ok();
+                let mut output = DiaryCommand::ListConflicts(date).run(&dap).await?;
+                // the syncs of the only date with conflicts are more useful
+                if let CommandOutput::Conflicts(ConflictList::Dates(dates)) = &output {
+                    if let [date] = dates[..] {
+                        output = DiaryCommand::ListConflicts(Some(date)).run(&dap).await?;
                     }
                 }
+                dap.stdout.send(output.lines().join("\n"));
             }
             DiaryAppCommands::ShowConflict => {
-                async fn show_conflict(
-                    dap: &DiaryAppInterface,
-                    datetime: OffsetDateTime,
-                ) -> Result<(), Error> {
-                    dap.stdout.send(format!("datetime {datetime}"));
-                    let conflicts: Vec<_> =
-                        DiaryConflict::get_by_datetime(datetime.into(), &dap.pool)
-                            .await?
-                            .map_ok(|entry| match entry.diff_type.as_str() {
-                                "rem" => format!("\x1b[91m{}\x1b[0m", entry.diff_text).into(),
-                                "add" => format!("\x1b[92m{}\x1b[0m", entry.diff_text).into(),
+                if let Some(key) = conflict_key(&opts.text, &dap).await? {
+                    if let CommandOutput::Conflict(conflicts) =
+                        DiaryCommand::ShowConflict(key).run(&dap).await?
+                    {
+                        if let Some(first) = conflicts.first() {
+                            dap.stdout.send(format_sstr!(
+                                "conflict {} datetime {}",
+                                first.conflict_group,
+                                first.sync_datetime
+                            ));
+                        }
+                        for entry in conflicts {
+                            // the id is what update_conflict takes
+                            let line: StackString = match entry.diff_type.as_str() {
+                                "rem" => format_sstr!("\x1b[91m{}\x1b[0m", entry.diff_text),
+                                "add" => format_sstr!("\x1b[92m{}\x1b[0m", entry.diff_text),
                                 _ => entry.diff_text,
-                            })
-                            .try_collect()
-                            .await?;
-                    for timestamp in conflicts {
-                        dap.stdout.send(timestamp);
+                            };
+                            dap.stdout.send(format_sstr!("{} {line}", entry.id));
+                        }
                     }
-                    Ok(())
-                }
-
-                if let Ok(datetime) =
-                    OffsetDateTime::parse(&opts.text.join("").replace('Z', "+00:00"), &Rfc3339)
-                        .map(|x| x.to_timezone(UTC))
-                {
-                    show_conflict(&dap, datetime).await?;
-                } else if let Some(datetime) = DiaryConflict::get_first_conflict(&dap.pool).await? {
-                    show_conflict(&dap, datetime).await?;
                 }
             }
             DiaryAppCommands::RemoveConflict => {
-                if let Ok(datetime) =
-                    OffsetDateTime::parse(&opts.text.join("").replace('Z', "+00:00"), &Rfc3339)
-                        .map(|x| x.to_timezone(UTC))
-                {
-                    DiaryConflict::remove_by_datetime(datetime.into(), &dap.pool).await?;
-                } else if let Some(datetime) = DiaryConflict::get_first_conflict(&dap.pool).await? {
-                    DiaryConflict::remove_by_datetime(datetime.into(), &dap.pool).await?;
+                if let Some(key) = conflict_key(&opts.text, &dap).await? {
+                    let output = DiaryCommand::RemoveConflict(key).run(&dap).await?;
+                    dap.stdout.send(output.lines().join("\n"));
                 }
             }
+            DiaryAppCommands::CommitConflict => {
+                if let Some(key) = conflict_key(&opts.text, &dap).await? {
+                    let output = DiaryCommand::CommitConflict(key).run(&dap).await?;
+                    dap.stdout.send(output.lines().join("\n"));
+                }
+            }
+            DiaryAppCommands::CleanConflicts => {
+                let date = parse_date_from_str(&opts.text.join("")).map_err(|e| format_err!(e))?;
+                let output = DiaryCommand::CleanConflicts(date).run(&dap).await?;
+                dap.stdout.send(output.lines().join("\n"));
+            }
+            DiaryAppCommands::UpdateConflict => {
+                let (Some(id), Some(diff_type)) = (opts.text.first(), opts.text.get(1)) else {
+                    return Err(format_err!("update_conflict needs an id and add or rem"));
+                };
+                let command = DiaryCommand::UpdateConflict {
+                    id: id.parse()?,
+                    diff_type: diff_type.clone(),
+                };
+                let output = command.run(&dap).await?;
+                dap.stdout.send(output.lines().join("\n"));
+            }
+            DiaryAppCommands::Resolve => loop {
+                let Some(key) = conflict_key(&opts.text, &dap).await? else {
+                    dap.stdout.send(StackString::from("no conflicts"));
+                    break;
+                };
+                if !resolve_conflict(&dap, key).await? || !opts.text.is_empty() {
+                    break;
+                }
+            },
             DiaryAppCommands::RunMigrations => {
-                let mut client = dap.pool.get().await?;
-                migrations::runner().run_async(&mut **client).await?;
+                for name in run_migrations(&dap.pool).await? {
+                    dap.stdout.send(format_sstr!("applied migration {name}"));
+                }
+            }
+            DiaryAppCommands::SshCheck => {
+                dap.stdout.send(dap.check_ssh().await?);
+            }
+            DiaryAppCommands::Delete => {
+                let date = Date::parse(
+                    &opts.text.join(""),
+                    format_description!("[year]-[month]-[day]"),
+                )?;
+                if !opts.yes {
+                    return Err(format_err!("Refusing to delete {date} without --yes"));
+                }
+                for line in dap.delete_entry(date).await? {
+                    dap.stdout.send(line);
+                }
+            }
+            DiaryAppCommands::Journals => {
+                for journal in dap.get_journals().await? {
+                    dap.stdout.send(journal);
+                }
+            }
+            DiaryAppCommands::CreateJournal => {
+                let journal = dap.create_journal(&opts.text.join(""), opts.shared).await?;
+                dap.stdout
+                    .send(format_sstr!("created journal {}", journal.journal_name));
+            }
+            DiaryAppCommands::Schedule => {
+                let (date, text) = opts
+                    .text
+                    .split_first()
+                    .ok_or_else(|| format_err!("No date given"))?;
+                let date = Date::parse(date, format_description!("[year]-[month]-[day]"))?;
+                let entry = dap.schedule_entry(date, &text.join(" ")).await?;
+                dap.stdout
+                    .send(format_sstr!("scheduled {}", entry.diary_date));
+            }
+            DiaryAppCommands::S3Versioning => {
+                dap.s3.enable_versioning().await?;
+                dap.stdout.send(format_sstr!(
+                    "enabled versioning of {}",
+                    dap.config.diary_bucket
+                ));
+            }
+            DiaryAppCommands::S3Lifecycle => {
+                dap.s3.set_version_lifecycle().await?;
+                dap.stdout.send(format_sstr!(
+                    "previous versions move to glacier after {} days",
+                    dap.config.s3_glacier_transition_days
+                ));
+            }
+            DiaryAppCommands::S3Versions => {
+                let date = Date::parse(
+                    &opts.text.join(""),
+                    format_description!("[year]-[month]-[day]"),
+                )?;
+                for version in dap.s3.get_versions(date).await? {
+                    let last_modified = version
+                        .last_modified
+                        .and_then(|t| OffsetDateTime::from_unix_timestamp(t.secs()).ok())
+                        .map(|t| StackString::from_display(t.to_timezone(UTC)))
+                        .unwrap_or_default();
+                    let latest = if version.is_latest == Some(true) {
+                        " latest"
+                    } else {
+                        ""
+                    };
+                    dap.stdout.send(format_sstr!(
+                        "{} {last_modified} {}{latest}",
+                        version.version_id.as_deref().unwrap_or("null"),
+                        version.size.unwrap_or(0),
+                    ));
+                }
+            }
+            DiaryAppCommands::Restore => {
+                let date = Date::parse(
+                    &opts.text.join(""),
+                    format_description!("[year]-[month]-[day]"),
+                )?;
+                let version_id = opts
+                    .version_id
+                    .ok_or_else(|| format_err!("No version id given"))?;
+                match dap.restore_s3_version(date, &version_id).await? {
+                    Some(datetime) => dap.stdout.send(format_sstr!(
+                        "restored {date}, the replaced text is kept as conflict {datetime}"
+                    )),
+                    None => dap.stdout.send(format_sstr!("{date} is unchanged")),
+                }
+            }
+            DiaryAppCommands::ValidateReplica => {
+                let replica = S3Replica::new(config, &sdk_config)?;
+                let mismatches = replica.validate(opts.yes).await?;
+                for mismatch in &mismatches {
+                    dap.stdout.send(StackString::from_display(mismatch));
+                }
+                let verb = if opts.yes { "repaired" } else { "found" };
+                dap.stdout
+                    .send(format_sstr!("{verb} {} mismatches", mismatches.len()));
+            }
+            DiaryAppCommands::S3Compact => {
+                for date in dap.s3.compact_s3().await? {
+                    dap.stdout.send(format_sstr!("compacted {date}"));
+                }
+            }
+            DiaryAppCommands::S3Recompress => {
+                let dates = dap.s3.recompress_s3().await?;
+                for date in &dates {
+                    dap.stdout.send(format_sstr!("recompressed {date}"));
+                }
+                dap.stdout.send(format_sstr!(
+                    "recompressed {} entries as {:?}",
+                    dates.len(),
+                    dap.config.s3_compression
+                ));
+            }
+            DiaryAppCommands::Stats => {
+                for line in dap.get_storage_report().await?.lines() {
+                    dap.stdout.send(line);
+                }
+            }
+            DiaryAppCommands::RebuildTerms => {
+                let count = dap.rebuild_terms().await?;
+                dap.stdout
+                    .send(format_sstr!("recounted terms of {count} entries"));
+            }
+            DiaryAppCommands::RecoverCache => {
+                let days = opts.days.unwrap_or(RECOVER_CACHE_DAYS);
+                let issues = dap.recover_cache(days, opts.yes).await?;
+                for issue in &issues {
+                    dap.stdout.send(StackString::from_display(issue));
+                }
+                let verb = if opts.yes { "repaired" } else { "found" };
+                dap.stdout
+                    .send(format_sstr!("{verb} {} issues", issues.len()));
+            }
+            DiaryAppCommands::LintFiles => {
+                let issues = dap.local.lint_files().await?;
+                for issue in &issues {
+                    dap.stdout.send(StackString::from_display(issue));
+                }
+                if opts.yes {
+                    for action in dap.local.repair_files(issues).await? {
+                        dap.stdout.send(action);
+                    }
+                } else if !issues.is_empty() {
+                    dap.stdout.send(format_sstr!(
+                        "found {} issues, run with --yes to repair them",
+                        issues.len()
+                    ));
+                }
             }
         }
         dap.stdout.close().await.map_err(Into::into)
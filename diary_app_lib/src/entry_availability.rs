@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use time::Date;
+
+use crate::models::{DateRange, DateSummary};
+
+/// Which dates of a journal have entries, so calendars and lists can show
+/// them without fetching every entry
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryAvailability {
+    /// First date with an entry, `None` for an empty journal
+    pub min_date: Option<Date>,
+    /// Last date with an entry
+    pub max_date: Option<Date>,
+    pub count: usize,
+    /// First day of `days`
+    pub since: Option<Date>,
+    /// Last day of `days`
+    pub until: Option<Date>,
+    /// One character per day from `since` to `until`, `1` if the day has an
+    /// entry and `0` otherwise
+    pub days: StackString,
+}
+
+impl EntryAvailability {
+    /// Summarize the `dates` of all the entries, `days` covers the part of
+    /// `range` between the first and last entries so open or far off ends
+    /// don't blow up its size
+    #[must_use]
+    pub fn new(dates: &[Date], range: DateRange) -> Self {
+        let summary = DateSummary {
+            min_date: dates.iter().min().copied(),
+            max_date: dates.iter().max().copied(),
+            count: dates.len() as i64,
+        };
+        Self::from_summary(summary, dates, range)
+    }
+
+    /// Like [`Self::new`] for a journal summarized by `summary`, `dates` only
+    /// needs the dates within `range`
+    #[must_use]
+    pub fn from_summary(summary: DateSummary, dates: &[Date], range: DateRange) -> Self {
+        let DateSummary {
+            min_date,
+            max_date,
+            count,
+        } = summary;
+        let since = match (range.since, min_date) {
+            (Some(since), Some(min_date)) => Some(since.max(min_date)),
+            (_, min_date) => min_date,
+        };
+        let until = match (range.until, max_date) {
+            (Some(until), Some(max_date)) => Some(until.min(max_date)),
+            (_, max_date) => max_date,
+        };
+        let (since, until, days) = match (since, until) {
+            (Some(since), Some(until)) if since <= until => {
+                let ndays = (until - since).whole_days() as usize + 1;
+                let mut days = vec!['0'; ndays];
+                for date in dates {
+                    if (since..=until).contains(date) {
+                        days[(*date - since).whole_days() as usize] = '1';
+                    }
+                }
+                let days: String = days.into_iter().collect();
+                (Some(since), Some(until), days.into())
+            }
+            _ => (None, None, StackString::new()),
+        };
+        Self {
+            min_date,
+            max_date,
+            count: count as usize,
+            since,
+            until,
+            days,
+        }
+    }
+
+    #[must_use]
+    pub fn has_entry(&self, date: Date) -> bool {
+        match self.since {
+            Some(since) if date >= since => {
+                let offset = (date - since).whole_days() as usize;
+                self.days.as_bytes().get(offset) == Some(&b'1')
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use crate::{
+        entry_availability::EntryAvailability,
+        models::{DateRange, DateSummary},
+    };
+
+    #[test]
+    fn test_entry_availability() {
+        let dates = [
+            date!(2024 - 03 - 05),
+            date!(2024 - 03 - 01),
+            date!(2024 - 03 - 03),
+            date!(2024 - 02 - 10),
+        ];
+        let range = DateRange::new(Some(date!(2024 - 03 - 01)), Some(date!(2024 - 03 - 31)));
+        let availability = EntryAvailability::new(&dates, range);
+        assert_eq!(availability.min_date, Some(date!(2024 - 02 - 10)));
+        assert_eq!(availability.max_date, Some(date!(2024 - 03 - 05)));
+        assert_eq!(availability.count, 4);
+        assert_eq!(availability.since, Some(date!(2024 - 03 - 01)));
+        assert_eq!(availability.until, Some(date!(2024 - 03 - 05)));
+        assert_eq!(availability.days, "10101");
+        assert!(availability.has_entry(date!(2024 - 03 - 03)));
+        assert!(!availability.has_entry(date!(2024 - 03 - 04)));
+        assert!(!availability.has_entry(date!(2024 - 02 - 10)));
+
+        let availability = EntryAvailability::new(&dates, DateRange::default());
+        assert_eq!(availability.since, Some(date!(2024 - 02 - 10)));
+        assert_eq!(availability.days.len(), 25);
+        assert!(availability.has_entry(date!(2024 - 02 - 10)));
+
+        let range = DateRange::new(Some(date!(2025 - 01 - 01)), None);
+        let availability = EntryAvailability::new(&dates, range);
+        assert_eq!(availability.count, 4);
+        assert_eq!(availability.since, None);
+        assert!(availability.days.is_empty());
+
+        let availability = EntryAvailability::new(&[], DateRange::default());
+        assert_eq!(availability, EntryAvailability::default());
+    }
+    #[test]
+    fn test_entry_availability_from_summary() {
+        let summary = DateSummary {
+            min_date: Some(date!(2023 - 01 - 01)),
+            max_date: Some(date!(2024 - 12 - 31)),
+            count: 100,
+        };
+        let dates = [date!(2024 - 03 - 02), date!(2024 - 03 - 04)];
+        let range = DateRange::new(Some(date!(2024 - 03 - 01)), Some(date!(2024 - 03 - 05)));
+        let availability = EntryAvailability::from_summary(summary, &dates, range);
+        assert_eq!(availability.min_date, Some(date!(2023 - 01 - 01)));
+        assert_eq!(availability.max_date, Some(date!(2024 - 12 - 31)));
+        assert_eq!(availability.count, 100);
+        assert_eq!(availability.since, Some(date!(2024 - 03 - 01)));
+        assert_eq!(availability.until, Some(date!(2024 - 03 - 05)));
+        assert_eq!(availability.days, "01010");
+    }
+}
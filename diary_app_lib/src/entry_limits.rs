@@ -0,0 +1,61 @@
+use thiserror::Error as ThisError;
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum LimitError {
+    #[error("Text is {length} characters long, the limit is {max}")]
+    TooLong { length: usize, max: usize },
+}
+
+/// # Errors
+/// Return `LimitError::TooLong` if `text` has more than `max` characters
+pub fn check_length(text: &str, max: usize) -> Result<(), LimitError> {
+    let length = text.chars().count();
+    if length > max {
+        return Err(LimitError::TooLong { length, max });
+    }
+    Ok(())
+}
+
+/// Split `text` into pieces of at most `max` characters, at the last line
+/// break of each piece where there is one
+#[must_use]
+pub fn split_text(text: &str, max: usize) -> Vec<&str> {
+    let max = max.max(1);
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.chars().count() > max {
+        let end = rest.char_indices().nth(max).map_or(rest.len(), |(i, _)| i);
+        let split = rest[..end].rfind('\n').filter(|i| *i > 0).unwrap_or(end);
+        pieces.push(&rest[..split]);
+        rest = rest[split..].trim_start_matches('\n');
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entry_limits::{check_length, split_text, LimitError};
+
+    #[test]
+    fn test_check_length() {
+        assert!(check_length("héllo", 5).is_ok());
+        assert_eq!(
+            check_length("héllo!", 5),
+            Err(LimitError::TooLong { length: 6, max: 5 })
+        );
+    }
+
+    #[test]
+    fn test_split_text() {
+        assert_eq!(split_text("short", 10), vec!["short"]);
+        assert_eq!(split_text("", 10), vec![""]);
+        assert_eq!(
+            split_text("first line\nsecond line", 15),
+            vec!["first line", "second line"]
+        );
+        assert_eq!(split_text("ééééé", 2), vec!["éé", "éé", "é"]);
+    }
+}
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use thiserror::Error as ThisError;
+
+/// Replace lines `start..end` (zero based, `end` exclusive) with `text`, an
+/// empty `text` removes the lines and `start == end` inserts before `start`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+    pub text: StackString,
+}
+
+/// Partial update of an entry, applied against the current text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryPatch {
+    Ranges(Vec<LineRange>),
+    UnifiedDiff(StackString),
+}
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum PatchError {
+    #[error("Entry changed since it was loaded: {0}")]
+    Conflict(StackString),
+    #[error("Invalid patch: {0}")]
+    Invalid(StackString),
+}
+
+impl EntryPatch {
+    /// # Errors
+    /// Return `PatchError::Invalid` if the patch is malformed or out of range
+    /// and `PatchError::Conflict` if diff context doesn't match `text`
+    pub fn apply(&self, text: &str) -> Result<String, PatchError> {
+        match self {
+            Self::Ranges(ranges) => apply_line_ranges(text, ranges),
+            Self::UnifiedDiff(diff) => apply_unified_diff(text, diff),
+        }
+    }
+}
+
+fn apply_line_ranges(text: &str, ranges: &[LineRange]) -> Result<String, PatchError> {
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    let mut ranges: Vec<_> = ranges.iter().collect();
+    ranges.sort_by_key(|r| (r.start, r.end));
+    for pair in ranges.windows(2) {
+        if pair[0].end > pair[1].start {
+            return Err(PatchError::Invalid(format_sstr!(
+                "ranges {}..{} and {}..{} overlap",
+                pair[0].start,
+                pair[0].end,
+                pair[1].start,
+                pair[1].end
+            )));
+        }
+    }
+    // apply from the end so earlier ranges keep their line numbers
+    for range in ranges.into_iter().rev() {
+        if range.start > range.end || range.end > lines.len() {
+            return Err(PatchError::Invalid(format_sstr!(
+                "range {}..{} outside of {} lines",
+                range.start,
+                range.end,
+                lines.len()
+            )));
+        }
+        let replacement: Vec<&str> = if range.text.is_empty() {
+            Vec::new()
+        } else {
+            range.text.split('\n').collect()
+        };
+        lines.splice(range.start..range.end, replacement);
+    }
+    Ok(lines.join("\n"))
+}
+
+fn parse_hunk_header(header: &str) -> Result<(usize, usize), PatchError> {
+    let invalid = || PatchError::Invalid(format_sstr!("bad hunk header {header}"));
+    let old = header
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.strip_prefix('-'))
+        .ok_or_else(invalid)?;
+    let mut parts = old.splitn(2, ',');
+    let start = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(invalid)?;
+    let len = match parts.next() {
+        Some(s) => s.parse().map_err(|_| invalid())?,
+        None => 1,
+    };
+    Ok((start, len))
+}
+
+fn apply_unified_diff(text: &str, diff: &str) -> Result<String, PatchError> {
+    let old: Vec<&str> = text.split('\n').collect();
+    let mut output: Vec<&str> = Vec::with_capacity(old.len());
+    let mut pos = 0;
+    let mut hunks = 0;
+    let mut lines = diff.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(header) = line.strip_prefix("@@ ") else {
+            if hunks == 0 && (line.starts_with("---") || line.starts_with("+++")) {
+                continue;
+            }
+            return Err(PatchError::Invalid(format_sstr!("unexpected line {line}")));
+        };
+        hunks += 1;
+        let (old_start, old_len) = parse_hunk_header(header)?;
+        // a hunk which only adds lines gives the line it follows
+        let start = if old_len == 0 {
+            old_start
+        } else {
+            old_start
+                .checked_sub(1)
+                .ok_or_else(|| PatchError::Invalid(format_sstr!("bad hunk start {old_start}")))?
+        };
+        if start < pos || start > old.len() {
+            return Err(PatchError::Invalid(format_sstr!(
+                "hunk at line {old_start} out of order or range"
+            )));
+        }
+        output.extend_from_slice(&old[pos..start]);
+        pos = start;
+        while let Some(line) = lines.next_if(|l| !l.starts_with("@@ ")) {
+            // some editors strip the trailing space of empty context lines
+            let mut chars = line.chars();
+            let marker = chars.next().unwrap_or(' ');
+            let content = chars.as_str();
+            match marker {
+                ' ' | '-' => {
+                    if old.get(pos) != Some(&content) {
+                        return Err(PatchError::Conflict(format_sstr!(
+                            "line {} doesn't match",
+                            pos + 1
+                        )));
+                    }
+                    if marker == ' ' {
+                        output.push(content);
+                    }
+                    pos += 1;
+                }
+                '+' => output.push(content),
+                '\\' => (),
+                _ => return Err(PatchError::Invalid(format_sstr!("unexpected line {line}"))),
+            }
+        }
+    }
+    if hunks == 0 {
+        return Err(PatchError::Invalid("diff has no hunks".into()));
+    }
+    output.extend_from_slice(&old[pos..]);
+    Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entry_patch::{EntryPatch, LineRange, PatchError};
+
+    const TEXT: &str = "one\ntwo\nthree\nfour";
+
+    #[test]
+    fn test_apply_line_ranges() {
+        let patch = EntryPatch::Ranges(vec![
+            LineRange {
+                start: 3,
+                end: 4,
+                text: "FOUR".into(),
+            },
+            LineRange {
+                start: 0,
+                end: 1,
+                text: "zero\none".into(),
+            },
+            LineRange {
+                start: 2,
+                end: 3,
+                text: "".into(),
+            },
+        ]);
+        assert_eq!(patch.apply(TEXT).unwrap(), "zero\none\ntwo\nFOUR");
+
+        let patch = EntryPatch::Ranges(vec![LineRange {
+            start: 2,
+            end: 9,
+            text: "x".into(),
+        }]);
+        assert!(matches!(patch.apply(TEXT), Err(PatchError::Invalid(_))));
+
+        let patch = EntryPatch::Ranges(vec![
+            LineRange {
+                start: 0,
+                end: 2,
+                text: "x".into(),
+            },
+            LineRange {
+                start: 1,
+                end: 3,
+                text: "y".into(),
+            },
+        ]);
+        assert!(matches!(patch.apply(TEXT), Err(PatchError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_apply_unified_diff() {
+        let diff =
+            "--- a\n+++ b\n@@ -1,3 +1,3 @@\n one\n-two\n+TWO\n three\n@@ -4,0 +5,1 @@\n+five";
+        let patch = EntryPatch::UnifiedDiff(diff.into());
+        assert_eq!(patch.apply(TEXT).unwrap(), "one\nTWO\nthree\nfour\nfive");
+
+        let diff = "@@ -2,1 +2,1 @@\n-deux\n+TWO";
+        let patch = EntryPatch::UnifiedDiff(diff.into());
+        assert!(matches!(patch.apply(TEXT), Err(PatchError::Conflict(_))));
+
+        let patch = EntryPatch::UnifiedDiff("not a diff".into());
+        assert!(matches!(patch.apply(TEXT), Err(PatchError::Invalid(_))));
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::Error;
+use log::info;
+use sentry::{integrations::anyhow::capture_anyhow, ClientInitGuard, ClientOptions, Level};
+
+use crate::config::ConfigInner;
+
+/// Send errors and panics to the sentry project at `sentry_dsn`, events are
+/// only sent while the returned guard is alive, so keep it until the process
+/// exits. Without a dsn nothing is reported and the `report_*` functions do
+/// nothing.
+#[must_use]
+pub fn init(config: &ConfigInner, service: &'static str) -> Option<ClientInitGuard> {
+    let dsn = config.sentry_dsn.as_deref()?;
+    let guard = sentry::init((
+        dsn,
+        ClientOptions {
+            release: sentry::release_name!(),
+            environment: config
+                .sentry_environment
+                .as_ref()
+                .map(|e| e.to_string().into()),
+            ..ClientOptions::default()
+        },
+    ));
+    if !guard.is_enabled() {
+        return None;
+    }
+    sentry::configure_scope(|scope| scope.set_tag("service", service));
+    info!("reporting errors of {service} to sentry");
+    Some(guard)
+}
+
+/// Report `error`, `context` is attached as tags, e.g. the date and
+/// backend of a failed sync
+pub fn report_error(error: &Error, context: &[(&str, &str)]) {
+    sentry::with_scope(
+        |scope| {
+            for (key, value) in context {
+                scope.set_tag(key, value);
+            }
+        },
+        || capture_anyhow(error),
+    );
+}
+
+/// Report a failure which isn't an `anyhow::Error`
+pub fn report_message(message: &str, context: &[(&str, &str)]) {
+    sentry::with_scope(
+        |scope| {
+            for (key, value) in context {
+                scope.set_tag(key, value);
+            }
+        },
+        || sentry::capture_message(message, Level::Error),
+    );
+}
@@ -0,0 +1,356 @@
+use anyhow::Error;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use time::{macros::format_description, Date, Month, OffsetDateTime};
+
+use crate::archive::Compression;
+
+/// Directory in the diary directory the originals of repaired files are
+/// copied to, hidden so it isn't taken for a journal
+pub const BACKUP_DIR: &str = ".lint-backup";
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+
+/// Daily file `import_from_local` would skip or garble
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileIssue {
+    /// Name that looks like a date but isn't `YYYY-MM-DD.txt`, renamed on
+    /// repair unless the right name is taken
+    BadName { path: PathBuf, date: Date },
+    /// Text starting with a UTF-16 byte order mark, rewritten as UTF-8
+    Utf16(PathBuf),
+    /// Text that isn't UTF-8, rewritten taking it for Latin-1
+    InvalidUtf8(PathBuf),
+    /// UTF-8 byte order mark, stripped on repair
+    ByteOrderMark(PathBuf),
+    /// `\r\n` or `\r` line endings, rewritten with `\n`
+    CarriageReturn(PathBuf),
+    /// Zero bytes or only whitespace, removed on repair
+    Empty(PathBuf),
+    /// Compressed file which fails to decompress, only reported
+    Unreadable { path: PathBuf, error: StackString },
+}
+
+impl FileIssue {
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::BadName { path, .. } | Self::Unreadable { path, .. } => path,
+            Self::Utf16(path)
+            | Self::InvalidUtf8(path)
+            | Self::ByteOrderMark(path)
+            | Self::CarriageReturn(path)
+            | Self::Empty(path) => path,
+        }
+    }
+
+    fn is_rewritten(&self) -> bool {
+        matches!(
+            self,
+            Self::Utf16(_)
+                | Self::InvalidUtf8(_)
+                | Self::ByteOrderMark(_)
+                | Self::CarriageReturn(_)
+        )
+    }
+}
+
+impl fmt::Display for FileIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadName { path, date } => {
+                write!(f, "bad name {} should be {date}.txt", path.display())
+            }
+            Self::Utf16(path) => write!(f, "utf-16 {}", path.display()),
+            Self::InvalidUtf8(path) => write!(f, "invalid utf-8 {}", path.display()),
+            Self::ByteOrderMark(path) => write!(f, "byte order mark {}", path.display()),
+            Self::CarriageReturn(path) => write!(f, "crlf line endings {}", path.display()),
+            Self::Empty(path) => write!(f, "empty {}", path.display()),
+            Self::Unreadable { path, error } => {
+                write!(f, "unreadable {} {error}", path.display())
+            }
+        }
+    }
+}
+
+/// Date a misnamed daily file is most likely for, eight digits separated by
+/// `-`, `_`, `.` or spaces with an optional `.txt` extension in any case
+fn misnamed_date(filename: &str) -> Option<Date> {
+    let mut base = filename.to_lowercase();
+    while let Some(stripped) = base.strip_suffix(".txt") {
+        base = stripped.into();
+    }
+    if !base
+        .chars()
+        .all(|c| c.is_ascii_digit() || "-_. ".contains(c))
+    {
+        return None;
+    }
+    let digits: String = base.chars().filter(char::is_ascii_digit).collect();
+    if digits.len() != 8 {
+        return None;
+    }
+    let year = digits[..4].parse().ok()?;
+    let month = Month::try_from(digits[4..6].parse::<u8>().ok()?).ok()?;
+    Date::from_calendar_date(year, month, digits[6..].parse().ok()?).ok()
+}
+
+fn content_issues(path: &Path, compression: Compression) -> Vec<FileIssue> {
+    let data = match fs::read(path)
+        .map_err(Into::into)
+        .and_then(|data| compression.decompress(&data))
+    {
+        Ok(data) => data,
+        Err(e) => {
+            return vec![FileIssue::Unreadable {
+                path: path.into(),
+                error: format_sstr!("{e}"),
+            }]
+        }
+    };
+    let path: PathBuf = path.into();
+    if data.starts_with(UTF16LE_BOM) || data.starts_with(UTF16BE_BOM) {
+        return vec![FileIssue::Utf16(path)];
+    }
+    let mut issues = Vec::new();
+    let data = match data.strip_prefix(UTF8_BOM) {
+        Some(data) => {
+            issues.push(FileIssue::ByteOrderMark(path.clone()));
+            data
+        }
+        None => &data,
+    };
+    match std::str::from_utf8(data) {
+        Ok(text) if text.trim().is_empty() => return vec![FileIssue::Empty(path)],
+        Ok(text) if text.contains('\r') => issues.push(FileIssue::CarriageReturn(path)),
+        Ok(_) => (),
+        Err(_) => issues.push(FileIssue::InvalidUtf8(path)),
+    }
+    issues
+}
+
+/// Issues of the files directly in `diary_path`, the yearly exports and other
+/// files not named after a date are left alone
+/// # Errors
+/// Return error if listing the directory fails
+pub fn find_file_issues(diary_path: &Path) -> Result<Vec<FileIssue>, Error> {
+    let mut issues = Vec::new();
+    if !diary_path.exists() {
+        return Ok(issues);
+    }
+    let mut entries = fs::read_dir(diary_path)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(fs::DirEntry::file_name);
+    for entry in entries {
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        if filename.starts_with('.') || !entry.file_type()?.is_file() {
+            continue;
+        }
+        let (base, compression) = Compression::from_filename(&filename);
+        let path = entry.path();
+        if Date::parse(base, format_description!("[year]-[month]-[day].txt")).is_err() {
+            let Some(date) = misnamed_date(base) else {
+                continue;
+            };
+            issues.push(FileIssue::BadName {
+                path: path.clone(),
+                date,
+            });
+        }
+        issues.extend(content_issues(&path, compression));
+    }
+    Ok(issues)
+}
+
+/// Text of a daily file as UTF-8 with `\n` line endings
+#[must_use]
+pub fn normalized_text(data: &[u8]) -> String {
+    let text = if let Some(data) = data.strip_prefix(UTF16LE_BOM) {
+        decode_utf16(data, u16::from_le_bytes)
+    } else if let Some(data) = data.strip_prefix(UTF16BE_BOM) {
+        decode_utf16(data, u16::from_be_bytes)
+    } else {
+        let data = data.strip_prefix(UTF8_BOM).unwrap_or(data);
+        match std::str::from_utf8(data) {
+            Ok(text) => text.into(),
+            Err(_) => data.iter().copied().map(char::from).collect(),
+        }
+    };
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn decode_utf16(data: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Copy `path` to `BACKUP_DIR` under a name with the current timestamp
+fn backup(diary_path: &Path, path: &Path) -> Result<PathBuf, Error> {
+    let backup_dir = diary_path.join(BACKUP_DIR);
+    fs::create_dir_all(&backup_dir)?;
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    let backup_path = backup_dir.join(format_sstr!("{filename}.{timestamp}"));
+    fs::copy(path, &backup_path)?;
+    Ok(backup_path)
+}
+
+/// Fix `issues` of the files in `diary_path`, the originals of rewritten and
+/// removed files are backed up first, returns what was done
+/// # Errors
+/// Return error if reading, writing or renaming a file fails
+pub fn repair_file_issues(
+    diary_path: &Path,
+    issues: &[FileIssue],
+) -> Result<Vec<StackString>, Error> {
+    let mut actions = Vec::new();
+    let mut rewritten = HashSet::new();
+    for issue in issues.iter().filter(|issue| issue.is_rewritten()) {
+        let path = issue.path();
+        if !rewritten.insert(path) {
+            continue;
+        }
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        let (_, compression) = Compression::from_filename(&filename);
+        let text = normalized_text(&compression.decompress(&fs::read(path)?)?);
+        let backup_path = backup(diary_path, path)?;
+        fs::write(path, compression.compress(text.as_bytes())?)?;
+        actions.push(format_sstr!(
+            "rewrote {} backup {}",
+            path.display(),
+            backup_path.display()
+        ));
+    }
+    for issue in issues {
+        match issue {
+            FileIssue::Empty(path) => {
+                let backup_path = backup(diary_path, path)?;
+                fs::remove_file(path)?;
+                actions.push(format_sstr!(
+                    "removed {} backup {}",
+                    path.display(),
+                    backup_path.display()
+                ));
+            }
+            FileIssue::BadName { path, date } if path.exists() => {
+                let filename = path.file_name().unwrap_or_default().to_string_lossy();
+                let new_path = match Compression::from_filename(&filename).1.extension() {
+                    Some(ext) => diary_path.join(format_sstr!("{date}.txt.{ext}")),
+                    None => diary_path.join(format_sstr!("{date}.txt")),
+                };
+                if new_path.exists() {
+                    actions.push(format_sstr!(
+                        "kept {}, {} exists",
+                        path.display(),
+                        new_path.display()
+                    ));
+                } else {
+                    fs::rename(path, &new_path)?;
+                    actions.push(format_sstr!(
+                        "renamed {} to {}",
+                        path.display(),
+                        new_path.display()
+                    ));
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::fs;
+    use tempdir::TempDir;
+    use time::macros::date;
+
+    use crate::{
+        archive::Compression,
+        file_lint::{
+            find_file_issues, misnamed_date, normalized_text, repair_file_issues, FileIssue,
+            BACKUP_DIR,
+        },
+    };
+
+    #[test]
+    fn test_misnamed_date() {
+        assert_eq!(misnamed_date("2024_03_01.txt"), Some(date!(2024 - 03 - 01)));
+        assert_eq!(misnamed_date("20240301.TXT"), Some(date!(2024 - 03 - 01)));
+        assert_eq!(misnamed_date("2024-03-01"), Some(date!(2024 - 03 - 01)));
+        assert_eq!(
+            misnamed_date("2024-03-01.txt.txt"),
+            Some(date!(2024 - 03 - 01))
+        );
+        assert_eq!(misnamed_date("2024-13-01.txt"), None);
+        assert_eq!(misnamed_date("diary_2024.txt"), None);
+        assert_eq!(misnamed_date("comments_2024.txt"), None);
+    }
+
+    #[test]
+    fn test_normalized_text() {
+        assert_eq!(normalized_text(b"\xef\xbb\xbfa\r\nb\rc"), "a\nb\nc");
+        assert_eq!(normalized_text(b"caf\xe9"), "caf\u{e9}");
+        assert_eq!(normalized_text(b"\xff\xfea\x00\r\x00\n\x00"), "a\n");
+        assert_eq!(normalized_text(b"\xfe\xff\x00a"), "a");
+    }
+
+    #[test]
+    fn test_find_and_repair_file_issues() -> Result<(), Error> {
+        let tempdir = TempDir::new("test_file_lint")?;
+        let diary_path = tempdir.path();
+        fs::write(diary_path.join("2024-03-01.txt"), "fine")?;
+        fs::write(diary_path.join("2024-03-02.txt"), "a\r\nb")?;
+        fs::write(diary_path.join("2024-03-03.txt"), b"caf\xe9")?;
+        fs::write(diary_path.join("2024-03-04.txt"), "")?;
+        fs::write(diary_path.join("2024_03_05.txt"), "misnamed")?;
+        fs::write(diary_path.join("2024-03-06.txt.gz"), b"not gzip")?;
+        fs::write(
+            diary_path.join("2024-03-07.txt.zst"),
+            Compression::Zstd.compress(b"\xef\xbb\xbfzipped")?,
+        )?;
+        fs::write(diary_path.join("diary_2024.txt"), "")?;
+
+        let path = |name: &str| diary_path.join(name);
+        let issues = find_file_issues(diary_path)?;
+        assert_eq!(issues.len(), 6);
+        assert_eq!(issues[0], FileIssue::CarriageReturn(path("2024-03-02.txt")));
+        assert_eq!(issues[1], FileIssue::InvalidUtf8(path("2024-03-03.txt")));
+        assert_eq!(issues[2], FileIssue::Empty(path("2024-03-04.txt")));
+        assert!(matches!(issues[3], FileIssue::Unreadable { .. }));
+        assert_eq!(
+            issues[4],
+            FileIssue::ByteOrderMark(path("2024-03-07.txt.zst"))
+        );
+        assert_eq!(
+            issues[5],
+            FileIssue::BadName {
+                path: path("2024_03_05.txt"),
+                date: date!(2024 - 03 - 05),
+            }
+        );
+
+        let actions = repair_file_issues(diary_path, &issues)?;
+        assert_eq!(actions.len(), 5);
+        assert_eq!(fs::read_to_string(path("2024-03-02.txt"))?, "a\nb");
+        assert_eq!(fs::read_to_string(path("2024-03-03.txt"))?, "caf\u{e9}");
+        assert!(!path("2024-03-04.txt").exists());
+        assert_eq!(fs::read_to_string(path("2024-03-05.txt"))?, "misnamed");
+        let zipped = Compression::Zstd.decompress(&fs::read(path("2024-03-07.txt.zst"))?)?;
+        assert_eq!(zipped, b"zipped");
+        assert_eq!(fs::read_dir(diary_path.join(BACKUP_DIR))?.count(), 4);
+
+        let issues = find_file_issues(diary_path)?;
+        assert_eq!(issues.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use parking_lot::Mutex;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+
+use crate::config::ConfigInner;
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum GuestbookError {
+    #[error("Guestbook is disabled")]
+    Disabled,
+    #[error("Invalid guestbook token")]
+    InvalidToken,
+    #[error("Too many guestbook submissions")]
+    RateLimited,
+    #[error("Note must be between 1 and {0} characters")]
+    InvalidLength(usize),
+}
+
+/// Address of the client, the first `X-Forwarded-For` entry is only trusted
+/// when the request comes from a reverse proxy on the same host
+#[must_use]
+pub fn client_addr(remote: Option<SocketAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+    let remote = remote?.ip();
+    if remote.is_loopback() {
+        if let Some(addr) = forwarded_for
+            .and_then(|f| f.split(',').next())
+            .and_then(|f| f.trim().parse().ok())
+        {
+            return Some(addr);
+        }
+    }
+    Some(remote)
+}
+
+/// Sliding window limit on the number of submissions per client address
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    hits: Arc<Mutex<HashMap<IpAddr, VecDeque<Instant>>>>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Record a request from `addr`, returns `false` if it exceeds the limit
+    #[must_use]
+    pub fn check(&self, addr: IpAddr, now: Instant) -> bool {
+        let mut hits = self.hits.lock();
+        // forget addresses which have been quiet for a whole window
+        hits.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|t| now.duration_since(*t) < self.window)
+        });
+        let times = hits.entry(addr).or_default();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.window)
+        {
+            times.pop_front();
+        }
+        if times.len() >= self.limit {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+/// Unauthenticated note submission for people given a token, notes are
+/// cached tagged with the name the token was issued to
+#[derive(Clone, Debug)]
+pub struct Guestbook {
+    enabled: bool,
+    /// token -> name
+    tokens: HashMap<StackString, StackString>,
+    max_length: usize,
+    limiter: RateLimiter,
+}
+
+/// Parse `name:token` pairs separated by commas
+pub(crate) fn parse_tokens(tokens: &str) -> HashMap<StackString, StackString> {
+    tokens
+        .split(',')
+        .filter_map(|pair| {
+            let (name, token) = pair.trim().split_once(':')?;
+            let (name, token) = (name.trim(), token.trim());
+            if name.is_empty() || token.is_empty() {
+                None
+            } else {
+                Some((token.into(), name.into()))
+            }
+        })
+        .collect()
+}
+
+impl Guestbook {
+    #[must_use]
+    pub fn from_config(config: &ConfigInner) -> Self {
+        Self {
+            enabled: config.guestbook_enabled,
+            tokens: config
+                .guestbook_tokens
+                .as_deref()
+                .map(parse_tokens)
+                .unwrap_or_default(),
+            max_length: config.guestbook_max_length,
+            limiter: RateLimiter::new(
+                config.guestbook_rate_limit,
+                Duration::from_secs(config.guestbook_rate_window_secs),
+            ),
+        }
+    }
+
+    /// Validate a submission, returning the note to cache
+    /// # Errors
+    /// Return `GuestbookError` if the guestbook is disabled, the token is
+    /// unknown, `addr` is over the rate limit or the note is empty or too long
+    pub fn submit(
+        &self,
+        token: &str,
+        addr: IpAddr,
+        text: &str,
+        now: Instant,
+    ) -> Result<StackString, GuestbookError> {
+        if !self.enabled {
+            return Err(GuestbookError::Disabled);
+        }
+        if !self.limiter.check(addr, now) {
+            return Err(GuestbookError::RateLimited);
+        }
+        let name = self.tokens.get(token).ok_or(GuestbookError::InvalidToken)?;
+        let text = text.trim();
+        if text.is_empty() || text.chars().count() > self.max_length {
+            return Err(GuestbookError::InvalidLength(self.max_length));
+        }
+        Ok(format_sstr!("[guestbook {name}] {text}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        config::ConfigInner,
+        guestbook::{client_addr, parse_tokens, Guestbook, GuestbookError, RateLimiter},
+    };
+
+    const ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn test_parse_tokens() {
+        let tokens = parse_tokens("mom:abc123, dad : def456,bad,:nope");
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens.get("abc123").map(StackString::as_str), Some("mom"));
+        assert_eq!(tokens.get("def456").map(StackString::as_str), Some("dad"));
+    }
+
+    #[test]
+    fn test_client_addr() {
+        let proxy: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let remote: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        assert_eq!(
+            client_addr(Some(proxy), Some("10.0.0.1, 10.0.0.3")),
+            Some(ADDR)
+        );
+        assert_eq!(client_addr(Some(proxy), Some("garbage")), Some(proxy.ip()));
+        assert_eq!(client_addr(Some(remote), Some("10.0.0.1")), Some(OTHER));
+        assert_eq!(client_addr(None, Some("10.0.0.1")), None);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.check(ADDR, now));
+        assert!(limiter.check(ADDR, now));
+        assert!(!limiter.check(ADDR, now));
+        assert!(limiter.check(OTHER, now));
+        assert!(limiter.check(ADDR, now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_guestbook_submit() {
+        let config = ConfigInner {
+            guestbook_enabled: true,
+            guestbook_tokens: Some("mom:abc123".into()),
+            guestbook_max_length: 10,
+            guestbook_rate_limit: 5,
+            guestbook_rate_window_secs: 60,
+            ..ConfigInner::default()
+        };
+        let guestbook = Guestbook::from_config(&config);
+        let now = Instant::now();
+        assert_eq!(
+            guestbook.submit("abc123", ADDR, " hi there ", now).unwrap(),
+            "[guestbook mom] hi there"
+        );
+        assert_eq!(
+            guestbook.submit("wrong", ADDR, "hi", now),
+            Err(GuestbookError::InvalidToken)
+        );
+        assert_eq!(
+            guestbook.submit("abc123", ADDR, "far too long a note", now),
+            Err(GuestbookError::InvalidLength(10))
+        );
+
+        let config = ConfigInner {
+            guestbook_enabled: false,
+            ..config
+        };
+        let guestbook = Guestbook::from_config(&config);
+        assert_eq!(
+            guestbook.submit("abc123", ADDR, "hi", now),
+            Err(GuestbookError::Disabled)
+        );
+    }
+}
@@ -0,0 +1,451 @@
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::{fmt, str::FromStr};
+use thiserror::Error as ThisError;
+use time::{Date, Month, Weekday};
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+#[error("Unsupported locale {0}")]
+pub struct LocaleError(pub StackString);
+
+/// Languages of the web UI and bot replies
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl Locale {
+    pub const ALL: [Self; 4] = [Self::En, Self::De, Self::Fr, Self::Es];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+            Self::Fr => "fr",
+            Self::Es => "es",
+        }
+    }
+
+    #[must_use]
+    pub fn text(self, message: Message) -> &'static str {
+        let [en, de, fr, es] = message.texts();
+        match self {
+            Self::En => en,
+            Self::De => de,
+            Self::Fr => fr,
+            Self::Es => es,
+        }
+    }
+
+    fn month(self, month: Month) -> &'static str {
+        let idx = month as usize - 1;
+        match self {
+            Self::En => [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ][idx],
+            Self::De => [
+                "Januar",
+                "Februar",
+                "März",
+                "April",
+                "Mai",
+                "Juni",
+                "Juli",
+                "August",
+                "September",
+                "Oktober",
+                "November",
+                "Dezember",
+            ][idx],
+            Self::Fr => [
+                "janvier",
+                "février",
+                "mars",
+                "avril",
+                "mai",
+                "juin",
+                "juillet",
+                "août",
+                "septembre",
+                "octobre",
+                "novembre",
+                "décembre",
+            ][idx],
+            Self::Es => [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ][idx],
+        }
+    }
+
+    fn weekday(self, weekday: Weekday) -> &'static str {
+        let idx = weekday.number_days_from_monday() as usize;
+        match self {
+            Self::En => [
+                "Monday",
+                "Tuesday",
+                "Wednesday",
+                "Thursday",
+                "Friday",
+                "Saturday",
+                "Sunday",
+            ][idx],
+            Self::De => [
+                "Montag",
+                "Dienstag",
+                "Mittwoch",
+                "Donnerstag",
+                "Freitag",
+                "Samstag",
+                "Sonntag",
+            ][idx],
+            Self::Fr => [
+                "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+            ][idx],
+            Self::Es => [
+                "lunes",
+                "martes",
+                "miércoles",
+                "jueves",
+                "viernes",
+                "sábado",
+                "domingo",
+            ][idx],
+        }
+    }
+
+    /// Date with the weekday written out, e.g. `Friday, March 1, 2024`
+    #[must_use]
+    pub fn format_date(self, date: Date) -> StackString {
+        let weekday = self.weekday(date.weekday());
+        let month = self.month(date.month());
+        let (day, year) = (date.day(), date.year());
+        match self {
+            Self::En => format_sstr!("{weekday}, {month} {day}, {year}"),
+            Self::De => format_sstr!("{weekday}, {day}. {month} {year}"),
+            Self::Fr => format_sstr!("{weekday} {day} {month} {year}"),
+            Self::Es => format_sstr!("{weekday}, {day} de {month} de {year}"),
+        }
+    }
+
+    /// Month and day for labels where space is short, e.g. `Mar 1`
+    #[must_use]
+    pub fn format_short_date(self, date: Date) -> StackString {
+        let month: StackString = self.month(date.month()).chars().take(3).collect();
+        let day = date.day();
+        match self {
+            Self::En => format_sstr!("{month} {day}"),
+            Self::De => format_sstr!("{day}. {month}"),
+            Self::Fr | Self::Es => format_sstr!("{day} {month}"),
+        }
+    }
+
+    /// Month and year of `date`, e.g. `March 2024`
+    #[must_use]
+    pub fn format_month(self, date: Date) -> StackString {
+        let month = self.month(date.month());
+        let year = date.year();
+        match self {
+            Self::Es => format_sstr!("{month} de {year}"),
+            Self::En | Self::De | Self::Fr => format_sstr!("{month} {year}"),
+        }
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Locale {
+    type Err = LocaleError;
+
+    /// Accepts language tags like `de-AT` or `fr_CA`, only the language is
+    /// used
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let language = s
+            .trim()
+            .split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|locale| locale.as_str() == language)
+            .ok_or_else(|| LocaleError(s.into()))
+    }
+}
+
+/// Strings of the web UI and bot replies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    NewJournal,
+    Sync,
+    Note,
+    Search,
+    Trash,
+    Lock,
+    Key,
+    Date,
+    Favorites,
+    Streak,
+    Days,
+    Conflicts,
+    LastSync,
+    Never,
+    Previous,
+    Next,
+    Conflict,
+    Clean,
+    Show,
+    List,
+    Edit,
+    Star,
+    HideFromViewers,
+    Delete,
+    Comment,
+    Update,
+    Cancel,
+    LastWrittenVia,
+    Stacked,
+    SideBySide,
+    Display,
+    Commit,
+    Remove,
+    Restore,
+    Purge,
+    Deleted,
+    EncryptedEntry,
+    NothingWrittenYet,
+    KeepMine,
+    KeepTheirs,
+    OpenInWeb,
+    Resolve,
+    Confirm,
+    Result,
+    Refresh,
+    NoConflicts,
+    Cancelled,
+    StartedSync,
+    ViewersCanOnlySearch,
+    AlreadyLinked,
+    InvalidConflictTime,
+    FailedToCacheEntry,
+    WeekOf,
+    Entries,
+    Words,
+}
+
+impl Message {
+    /// en, de, fr and es text
+    fn texts(self) -> [&'static str; 4] {
+        match self {
+            Self::NewJournal => [
+                "New Journal",
+                "Neues Journal",
+                "Nouveau journal",
+                "Nuevo diario",
+            ],
+            Self::Sync => ["Sync", "Synchronisieren", "Synchroniser", "Sincronizar"],
+            Self::Note => ["Note", "Notiz", "Note", "Nota"],
+            Self::Search => ["Search", "Suchen", "Rechercher", "Buscar"],
+            Self::Trash => ["Trash", "Papierkorb", "Corbeille", "Papelera"],
+            Self::Lock => ["Lock", "Sperren", "Verrouiller", "Bloquear"],
+            Self::Key => ["Key", "Schlüssel", "Clé", "Clave"],
+            Self::Date => ["Date", "Datum", "Date", "Fecha"],
+            Self::Favorites => ["Favorites", "Favoriten", "Favoris", "Favoritos"],
+            Self::Streak => ["Streak", "Serie", "Série", "Racha"],
+            Self::Days => ["days", "Tage", "jours", "días"],
+            Self::Conflicts => ["Conflicts", "Konflikte", "Conflits", "Conflictos"],
+            Self::LastSync => [
+                "Last Sync",
+                "Letzte Synchronisierung",
+                "Dernière synchronisation",
+                "Última sincronización",
+            ],
+            Self::Never => ["never", "nie", "jamais", "nunca"],
+            Self::Previous => ["Previous", "Zurück", "Précédent", "Anterior"],
+            Self::Next => ["Next", "Weiter", "Suivant", "Siguiente"],
+            Self::Conflict => ["Conflict", "Konflikt", "Conflit", "Conflicto"],
+            Self::Clean => ["Clean", "Aufräumen", "Nettoyer", "Limpiar"],
+            Self::Show => ["Show", "Zeigen", "Afficher", "Mostrar"],
+            Self::List => ["List", "Liste", "Liste", "Lista"],
+            Self::Edit => ["Edit", "Bearbeiten", "Modifier", "Editar"],
+            Self::Star => ["Star", "Markieren", "Favori", "Destacar"],
+            Self::HideFromViewers => [
+                "Hide from viewers",
+                "Vor Lesern verbergen",
+                "Masquer aux lecteurs",
+                "Ocultar a los lectores",
+            ],
+            Self::Delete => ["Delete", "Löschen", "Supprimer", "Eliminar"],
+            Self::Comment => ["Comment", "Kommentieren", "Commenter", "Comentar"],
+            Self::Update => ["Update", "Speichern", "Enregistrer", "Guardar"],
+            Self::Cancel => ["Cancel", "Abbrechen", "Annuler", "Cancelar"],
+            Self::LastWrittenVia => [
+                "Last written via",
+                "Zuletzt geschrieben über",
+                "Dernière écriture via",
+                "Escrito por última vez desde",
+            ],
+            Self::Stacked => ["Stacked", "Untereinander", "Empilé", "Apilado"],
+            Self::SideBySide => [
+                "Side by Side",
+                "Nebeneinander",
+                "Côte à côte",
+                "Lado a lado",
+            ],
+            Self::Display => ["Display", "Anzeigen", "Afficher", "Ver"],
+            Self::Commit => ["Commit", "Übernehmen", "Valider", "Confirmar"],
+            Self::Remove => ["Remove", "Entfernen", "Retirer", "Quitar"],
+            Self::Restore => ["Restore", "Wiederherstellen", "Restaurer", "Restaurar"],
+            Self::Purge => [
+                "Purge",
+                "Endgültig löschen",
+                "Purger",
+                "Eliminar definitivamente",
+            ],
+            Self::Deleted => ["deleted", "gelöscht", "supprimé", "eliminado"],
+            Self::EncryptedEntry => [
+                "Encrypted entry",
+                "Verschlüsselter Eintrag",
+                "Entrée chiffrée",
+                "Entrada cifrada",
+            ],
+            Self::NothingWrittenYet => [
+                "Nothing written yet",
+                "Noch nichts geschrieben",
+                "Rien d'écrit pour l'instant",
+                "Aún no hay nada escrito",
+            ],
+            Self::KeepMine => [
+                "Keep mine",
+                "Meine behalten",
+                "Garder la mienne",
+                "Conservar la mía",
+            ],
+            Self::KeepTheirs => [
+                "Keep theirs",
+                "Andere behalten",
+                "Garder l'autre",
+                "Conservar la otra",
+            ],
+            Self::OpenInWeb => [
+                "Open in web",
+                "Im Web öffnen",
+                "Ouvrir sur le web",
+                "Abrir en la web",
+            ],
+            Self::Resolve => ["Resolve", "Lösen", "Résoudre", "Resolver"],
+            Self::Confirm => ["Confirm", "Bestätigen", "Confirmer", "Confirmar"],
+            Self::Result => ["Result", "Ergebnis", "Résultat", "Resultado"],
+            Self::Refresh => ["Refresh", "Aktualisieren", "Actualiser", "Actualizar"],
+            Self::NoConflicts => [
+                "no conflicts",
+                "keine Konflikte",
+                "aucun conflit",
+                "no hay conflictos",
+            ],
+            Self::Cancelled => ["cancelled", "abgebrochen", "annulé", "cancelado"],
+            Self::StartedSync => [
+                "started sync",
+                "Synchronisierung gestartet",
+                "synchronisation lancée",
+                "sincronización iniciada",
+            ],
+            Self::ViewersCanOnlySearch => [
+                "viewers can only search",
+                "Leser können nur suchen",
+                "les lecteurs peuvent seulement rechercher",
+                "los lectores solo pueden buscar",
+            ],
+            Self::AlreadyLinked => [
+                "already linked",
+                "bereits verknüpft",
+                "déjà lié",
+                "ya vinculado",
+            ],
+            Self::InvalidConflictTime => [
+                "invalid conflict time",
+                "ungültige Konfliktzeit",
+                "heure de conflit invalide",
+                "hora de conflicto no válida",
+            ],
+            Self::FailedToCacheEntry => [
+                "failed to cache entry",
+                "Eintrag konnte nicht gespeichert werden",
+                "échec de l'enregistrement de l'entrée",
+                "no se pudo guardar la entrada",
+            ],
+            Self::WeekOf => ["Week of", "Woche vom", "Semaine du", "Semana del"],
+            Self::Entries => ["entries", "Einträge", "entrées", "entradas"],
+            Self::Words => ["words", "Wörter", "mots", "palabras"],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use crate::i18n::{Locale, LocaleError, Message};
+
+    #[test]
+    fn test_locale_from_str() {
+        assert_eq!("de".parse::<Locale>(), Ok(Locale::De));
+        assert_eq!("fr-CA".parse::<Locale>(), Ok(Locale::Fr));
+        assert_eq!("ES_mx".parse::<Locale>(), Ok(Locale::Es));
+        assert_eq!("pt-BR".parse::<Locale>(), Err(LocaleError("pt-BR".into())));
+        for locale in Locale::ALL {
+            assert_eq!(locale.as_str().parse::<Locale>(), Ok(locale));
+        }
+    }
+
+    #[test]
+    fn test_format_date() {
+        let day = date!(2024 - 03 - 01);
+        assert_eq!(Locale::En.format_date(day), "Friday, March 1, 2024");
+        assert_eq!(Locale::De.format_date(day), "Freitag, 1. März 2024");
+        assert_eq!(Locale::Fr.format_date(day), "vendredi 1 mars 2024");
+        assert_eq!(Locale::Es.format_date(day), "viernes, 1 de marzo de 2024");
+        assert_eq!(Locale::En.format_short_date(day), "Mar 1");
+        assert_eq!(Locale::De.format_short_date(day), "1. Mär");
+        assert_eq!(Locale::En.format_month(day), "March 2024");
+        assert_eq!(Locale::Es.format_month(day), "marzo de 2024");
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(Locale::En.text(Message::Conflict), "Conflict");
+        assert_eq!(Locale::De.text(Message::Conflict), "Konflikt");
+        assert_eq!(Locale::default().text(Message::Next), "Next");
+    }
+}
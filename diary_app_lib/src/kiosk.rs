@@ -0,0 +1,177 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, str::FromStr};
+use time::Date;
+
+use crate::{
+    config::ConfigInner,
+    guestbook::parse_tokens,
+    i18n::{Locale, Message},
+    models::DEFAULT_JOURNAL,
+};
+
+/// Prompts shown below today's entry when `kiosk_prompts` isn't set
+pub const DEFAULT_PROMPTS: [&str; 7] = [
+    "What are you looking forward to today?",
+    "What is one thing you want to remember about today?",
+    "Who did you talk to today?",
+    "What surprised you today?",
+    "What would make today a good day?",
+    "What did you learn today?",
+    "What are you grateful for?",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KioskFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+impl FromStr for KioskFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Self::Html),
+            "text" => Ok(Self::Text),
+            _ => Err(format_err!("format must be html or text, not {s}")),
+        }
+    }
+}
+
+/// Read only view of today's entry for e-ink dashboards, a device holding a
+/// `kiosk_tokens` entry can read today's entry of `kiosk_journal` and
+/// nothing else, private entries are never shown
+#[derive(Clone, Debug)]
+pub struct Kiosk {
+    /// token -> device name
+    tokens: HashMap<StackString, StackString>,
+    journal: StackString,
+    prompts: Vec<StackString>,
+}
+
+impl Kiosk {
+    #[must_use]
+    pub fn from_config(config: &ConfigInner) -> Self {
+        let prompts: Vec<StackString> = config
+            .kiosk_prompts
+            .as_deref()
+            .map(|prompts| {
+                prompts
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(Into::into)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            tokens: config
+                .kiosk_tokens
+                .as_deref()
+                .map(parse_tokens)
+                .unwrap_or_default(),
+            journal: config
+                .kiosk_journal
+                .clone()
+                .unwrap_or_else(|| DEFAULT_JOURNAL.into()),
+            prompts: if prompts.is_empty() {
+                DEFAULT_PROMPTS.iter().map(|p| (*p).into()).collect()
+            } else {
+                prompts
+            },
+        }
+    }
+
+    /// The view is disabled unless `kiosk_tokens` is set
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Name of the device `token` was issued to
+    #[must_use]
+    pub fn device(&self, token: &str) -> Option<&str> {
+        self.tokens.get(token).map(StackString::as_str)
+    }
+
+    #[must_use]
+    pub fn journal(&self) -> &str {
+        &self.journal
+    }
+
+    /// Same prompt all day, the next one tomorrow
+    #[must_use]
+    pub fn prompt(&self, date: Date) -> &str {
+        let idx = date.to_julian_day().rem_euclid(self.prompts.len() as i32) as usize;
+        &self.prompts[idx]
+    }
+}
+
+/// Plain text rendering, `None` if nothing has been written today
+#[must_use]
+pub fn render_text(date: Date, text: Option<&str>, prompt: &str, locale: Locale) -> StackString {
+    let date = locale.format_date(date);
+    let text = text.map_or(locale.text(Message::NothingWrittenYet), str::trim);
+    format_sstr!("{date}\n\n{text}\n\n{prompt}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use crate::{
+        config::ConfigInner,
+        i18n::Locale,
+        kiosk::{render_text, Kiosk, KioskFormat, DEFAULT_PROMPTS},
+    };
+
+    #[test]
+    fn test_kiosk_from_config() {
+        let kiosk = Kiosk::from_config(&ConfigInner::default());
+        assert!(!kiosk.is_enabled());
+        assert_eq!(kiosk.journal(), "diary");
+        let day = date!(2024 - 03 - 01);
+        assert!(DEFAULT_PROMPTS.contains(&kiosk.prompt(day)));
+        assert_eq!(kiosk.prompt(day), kiosk.prompt(day));
+
+        let config = ConfigInner {
+            kiosk_tokens: Some("kitchen:abc123".into()),
+            kiosk_prompts: Some("first | second".into()),
+            ..ConfigInner::default()
+        };
+        let kiosk = Kiosk::from_config(&config);
+        assert!(kiosk.is_enabled());
+        assert_eq!(kiosk.device("abc123"), Some("kitchen"));
+        assert_eq!(kiosk.device("kitchen"), None);
+        let next = day.next_day().unwrap();
+        assert_ne!(kiosk.prompt(day), kiosk.prompt(next));
+        assert!(["first", "second"].contains(&kiosk.prompt(next)));
+    }
+
+    #[test]
+    fn test_kiosk_format() {
+        assert_eq!("text".parse::<KioskFormat>().ok(), Some(KioskFormat::Text));
+        assert_eq!("html".parse::<KioskFormat>().ok(), Some(KioskFormat::Html));
+        assert!("pdf".parse::<KioskFormat>().is_err());
+    }
+
+    #[test]
+    fn test_render_text() {
+        let day = date!(2024 - 03 - 01);
+        assert_eq!(
+            render_text(
+                day,
+                Some("went for a walk\n"),
+                "What did you learn today?",
+                Locale::En
+            ),
+            "Friday, March 1, 2024\n\nwent for a walk\n\nWhat did you learn today?\n"
+        );
+        assert_eq!(
+            render_text(day, None, "prompt", Locale::De),
+            "Freitag, 1. März 2024\n\nNoch nichts geschrieben\n\nprompt\n"
+        );
+    }
+}
@@ -6,25 +6,65 @@
 #![allow(clippy::cast_possible_wrap)]
 #![allow(clippy::doc_markdown)]
 
+pub mod archive;
+pub mod authorship;
+pub mod bot_core;
+pub mod cache_recovery;
+pub mod change_feed;
+pub mod commands;
+pub mod comments;
 pub mod config;
+pub mod conflict_wizard;
+pub mod date_sync;
 pub mod date_time_wrapper;
+pub mod db_migrations;
 pub mod diary_app_interface;
 pub mod diary_app_opts;
+pub mod entry_availability;
+pub mod entry_limits;
+pub mod entry_patch;
+pub mod error_reporting;
+pub mod file_lint;
+pub mod guestbook;
+pub mod i18n;
+pub mod kiosk;
+pub mod line_diff;
 pub mod local_interface;
+pub mod maintenance;
+pub mod mobile_sync;
 pub mod models;
+pub mod peer_sync;
 pub mod pgpool;
+pub mod redaction;
+pub mod resurface;
+pub mod retry;
+pub mod s3_delta;
 pub mod s3_instance;
 pub mod s3_interface;
+pub mod s3_replica;
+pub mod secret_scan;
+pub mod sections;
+pub mod services;
 pub mod ssh_instance;
+pub mod storage_report;
+pub mod sync_engine;
+pub mod sync_lock;
+pub mod sync_progress;
+pub mod tenants;
+#[cfg(test)]
+pub mod test_db;
+pub mod text_normalize;
+pub mod unlock;
+pub mod users;
+pub mod word_stats;
+pub mod writing_habits;
 
 use anyhow::Error;
-use rand::{
-    distributions::{Distribution, Uniform},
-    thread_rng,
-};
 use std::future::Future;
-use tokio::time::{sleep, Duration};
 
+use crate::retry::RetryPolicy;
+
+/// Retry using the default [`RetryPolicy`]
 /// # Errors
 /// Return error if closure fails
 pub async fn exponential_retry<T, U, F>(f: T) -> Result<U, Error>
@@ -32,18 +72,5 @@ where
     T: Fn() -> F,
     F: Future<Output = Result<U, Error>>,
 {
-    let mut timeout: f64 = 1.0;
-    let range = Uniform::from(0..1000);
-    loop {
-        match f().await {
-            Ok(resp) => return Ok(resp),
-            Err(err) => {
-                sleep(Duration::from_millis((timeout * 1000.0) as u64)).await;
-                timeout *= 4.0 * f64::from(range.sample(&mut thread_rng())) / 1000.0;
-                if timeout >= 64.0 {
-                    return Err(err);
-                }
-            }
-        }
-    }
+    RetryPolicy::default().retry(f).await
 }
@@ -0,0 +1,262 @@
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, ChangeTag, DiffOp, TextDiff};
+use stack_string::{format_sstr, StackString};
+use std::{fmt, str::FromStr};
+
+/// Whether the lines of a hunk are in both versions of a text, only in the
+/// original or only in the updated one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffTag {
+    Same,
+    Rem,
+    Add,
+}
+
+impl DiffTag {
+    /// Name stored as `diff_type` of conflict rows
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Same => "same",
+            Self::Rem => "rem",
+            Self::Add => "add",
+        }
+    }
+}
+
+impl fmt::Display for DiffTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for DiffTag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "same" => Ok(Self::Same),
+            "rem" => Ok(Self::Rem),
+            "add" => Ok(Self::Add),
+            _ => Err(format_err!("Bad diff type {s}")),
+        }
+    }
+}
+
+/// Consecutive lines with the same tag, `old_line` and `new_line` are the
+/// 1-based numbers of the first line in the original and updated text, a
+/// hunk missing from one text starts where its lines would be inserted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiffHunk {
+    pub tag: DiffTag,
+    pub text: StackString,
+    pub old_line: usize,
+    pub new_line: usize,
+}
+
+impl DiffHunk {
+    #[must_use]
+    pub fn nlines(&self) -> usize {
+        self.text.split('\n').count()
+    }
+}
+
+/// Line diff between two versions of an entry, joining the `same` and `rem`
+/// hunks with newlines gives the original text and the `same` and `add`
+/// hunks the updated one
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineDiff {
+    pub hunks: Vec<DiffHunk>,
+}
+
+impl LineDiff {
+    #[must_use]
+    pub fn new(original: &str, updated: &str) -> Self {
+        let old: Vec<&str> = original.split('\n').collect();
+        let new: Vec<&str> = updated.split('\n').collect();
+        let mut diff = Self::default();
+        for op in capture_diff_slices(Algorithm::Myers, &old, &new) {
+            let (_, old_range, new_range) = op.as_tag_tuple();
+            match op {
+                DiffOp::Equal { .. } => diff.push(DiffTag::Same, &old[old_range]),
+                DiffOp::Delete { .. } => diff.push(DiffTag::Rem, &old[old_range]),
+                DiffOp::Insert { .. } => diff.push(DiffTag::Add, &new[new_range]),
+                DiffOp::Replace { .. } => {
+                    diff.push(DiffTag::Rem, &old[old_range]);
+                    diff.push(DiffTag::Add, &new[new_range]);
+                }
+            }
+        }
+        diff
+    }
+
+    /// Append `lines`, merging them into the last hunk if it has the same tag
+    fn push(&mut self, tag: DiffTag, lines: &[&str]) {
+        if lines.is_empty() {
+            return;
+        }
+        let (old_line, new_line) = match self.hunks.last() {
+            Some(last) => {
+                let n = last.nlines();
+                match last.tag {
+                    DiffTag::Same => (last.old_line + n, last.new_line + n),
+                    DiffTag::Rem => (last.old_line + n, last.new_line),
+                    DiffTag::Add => (last.old_line, last.new_line + n),
+                }
+            }
+            None => (1, 1),
+        };
+        let text = lines.join("\n");
+        match self.hunks.last_mut() {
+            Some(last) if last.tag == tag => {
+                last.text = format_sstr!("{}\n{text}", last.text);
+            }
+            _ => self.hunks.push(DiffHunk {
+                tag,
+                text: text.into(),
+                old_line,
+                new_line,
+            }),
+        }
+    }
+
+    /// Whether the two versions differ
+    #[must_use]
+    pub fn is_changed(&self) -> bool {
+        self.hunks.iter().any(|hunk| hunk.tag != DiffTag::Same)
+    }
+}
+
+/// Unified diff of two versions of a text with three lines of context, the
+/// headers name the versions
+#[must_use]
+pub fn unified_diff(
+    original: &str,
+    updated: &str,
+    original_name: &str,
+    updated_name: &str,
+) -> StackString {
+    TextDiff::from_lines(original, updated)
+        .unified_diff()
+        .context_radius(3)
+        .header(original_name, updated_name)
+        .to_string()
+        .into()
+}
+
+/// Run of words of a line, `changed` if it isn't in the other version
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InlineSegment {
+    pub changed: bool,
+    pub text: StackString,
+}
+
+/// Words of a replaced line and of its replacement, split where they differ
+/// to highlight the changes within the line
+#[must_use]
+pub fn inline_diff(old: &str, new: &str) -> (Vec<InlineSegment>, Vec<InlineSegment>) {
+    fn push(segments: &mut Vec<InlineSegment>, changed: bool, text: &str) {
+        match segments.last_mut() {
+            Some(last) if last.changed == changed => {
+                last.text = format_sstr!("{}{text}", last.text);
+            }
+            _ => segments.push(InlineSegment {
+                changed,
+                text: text.into(),
+            }),
+        }
+    }
+    let (mut old_segments, mut new_segments) = (Vec::new(), Vec::new());
+    for change in TextDiff::from_words(old, new).iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => {
+                push(&mut old_segments, false, change.value());
+                push(&mut new_segments, false, change.value());
+            }
+            ChangeTag::Delete => push(&mut old_segments, true, change.value()),
+            ChangeTag::Insert => push(&mut new_segments, true, change.value()),
+        }
+    }
+    (old_segments, new_segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::line_diff::{inline_diff, unified_diff, DiffTag, InlineSegment, LineDiff};
+
+    #[test]
+    fn test_line_diff() {
+        let diff = LineDiff::new("a\nb\nc\nd", "a\nB\nc\nd\ne");
+        let hunks: Vec<_> = diff
+            .hunks
+            .iter()
+            .map(|h| (h.tag, h.text.as_str(), h.old_line, h.new_line))
+            .collect();
+        assert_eq!(
+            hunks,
+            vec![
+                (DiffTag::Same, "a", 1, 1),
+                (DiffTag::Rem, "b", 2, 2),
+                (DiffTag::Add, "B", 3, 2),
+                (DiffTag::Same, "c\nd", 3, 3),
+                (DiffTag::Add, "e", 5, 5),
+            ]
+        );
+        assert!(diff.is_changed());
+        assert!(!LineDiff::new("same\ntext", "same\ntext").is_changed());
+    }
+
+    #[test]
+    fn test_inline_diff() {
+        fn segments(segments: &[InlineSegment]) -> Vec<(bool, &str)> {
+            segments
+                .iter()
+                .map(|s| (s.changed, s.text.as_str()))
+                .collect()
+        }
+        let (old, new) = inline_diff("went for a walk today", "went for a run today");
+        assert_eq!(
+            segments(&old),
+            vec![(false, "went for a "), (true, "walk"), (false, " today")]
+        );
+        assert_eq!(
+            segments(&new),
+            vec![(false, "went for a "), (true, "run"), (false, " today")]
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("a\nb\nc\n", "a\nB\nc\n", "db", "s3");
+        assert_eq!(diff, "--- db\n+++ s3\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+        assert_eq!(unified_diff("same\n", "same\n", "db", "s3"), "");
+    }
+
+    proptest! {
+        #[test]
+        fn prop_line_diff_rebuilds_both_texts(
+            original in vec("[a-c]{1,2}", 1..8),
+            updated in vec("[a-c]{1,2}", 1..8),
+        ) {
+            let (original, updated) = (original.join("\n"), updated.join("\n"));
+            let diff = LineDiff::new(&original, &updated);
+            let rebuild = |skipped: DiffTag| {
+                diff.hunks
+                    .iter()
+                    .filter(|h| h.tag != skipped)
+                    .map(|h| h.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            prop_assert_eq!(rebuild(DiffTag::Add), original);
+            prop_assert_eq!(rebuild(DiffTag::Rem), updated);
+            for pair in diff.hunks.windows(2) {
+                prop_assert_ne!(pair[0].tag, pair[1].tag);
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::{format_err, Error};
+use async_trait::async_trait;
 use futures::{future::try_join_all, stream::FuturesUnordered, TryStreamExt};
 use jwalk::WalkDir;
 use log::debug;
@@ -6,8 +7,8 @@ use stack_string::{format_sstr, StackString};
 use std::{
     collections::{BTreeMap, HashMap},
     fs::metadata,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::SystemTime,
 };
 use time::{
     macros::{datetime, format_description},
@@ -15,30 +16,103 @@ use time::{
 };
 use time_tz::OffsetDateTimeExt;
 use tokio::{
-    fs::{read_to_string, remove_file, File},
+    fs::{create_dir_all, remove_file, File},
     io::AsyncWriteExt,
+    task::spawn_blocking,
 };
 
 use crate::{
-    config::Config, date_time_wrapper::DateTimeWrapper, models::DiaryEntries, pgpool::PgPool,
+    archive::{self, Compression},
+    comments::format_comments,
+    config::Config,
+    date_sync::sync_hash,
+    date_time_wrapper::DateTimeWrapper,
+    file_lint::{find_file_issues, repair_file_issues, FileIssue},
+    models::{
+        default_metadata, default_visibility, DateRange, DiaryComment, DiaryEntries, Journal,
+        SyncBackend, SyncWatermark, DEFAULT_JOURNAL,
+    },
+    pgpool::PgPool,
+    sync_engine::{PgEntryStore, SyncEngine, SyncStore},
+    sync_progress::ProgressReporter,
 };
 
+/// Daily files of the last `LOCAL_KEEP_DAYS` days are kept in the diary
+/// directory, older ones are removed by `cleanup_local`
+pub const LOCAL_KEEP_DAYS: i64 = 4;
+
+/// Journal and date of a daily file under `diary_path`, files of other
+/// journals are in a subdirectory named after the journal
+#[must_use]
+pub fn parse_local_path(diary_path: &Path, path: &Path) -> Option<(StackString, Date)> {
+    let filename = path.file_name()?.to_string_lossy();
+    let date = Date::parse(&filename, format_description!("[year]-[month]-[day].txt")).ok()?;
+    let parent = path.parent()?;
+    if parent == diary_path {
+        return Some((DEFAULT_JOURNAL.into(), date));
+    }
+    let journal = parent.file_name()?.to_string_lossy();
+    if parent.parent()? == diary_path && Journal::is_valid_name(&journal) {
+        Some((journal.as_ref().into(), date))
+    } else {
+        None
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LocalInterface {
     pub config: Config,
     pub pool: PgPool,
+    pub progress: ProgressReporter,
+    pub journal: StackString,
+    /// Dates `import_from_local` is limited to
+    pub date_range: DateRange,
 }
 
 impl LocalInterface {
     #[must_use]
     pub fn new(config: Config, pool: PgPool) -> Self {
-        Self { config, pool }
+        Self {
+            config,
+            pool,
+            progress: ProgressReporter::new(),
+            journal: DEFAULT_JOURNAL.into(),
+            date_range: DateRange::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    #[must_use]
+    pub fn with_journal(mut self, journal: impl Into<StackString>) -> Self {
+        self.journal = journal.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_date_range(mut self, date_range: DateRange) -> Self {
+        self.date_range = date_range;
+        self
+    }
+
+    /// Directory holding the files of `journal`
+    #[must_use]
+    pub fn diary_path(&self) -> PathBuf {
+        self.config.journal_path(&self.journal)
     }
 
     /// # Errors
     /// Return error if db query fails
     pub async fn export_year_to_local(&self) -> Result<Vec<StackString>, Error> {
-        let mod_map = DiaryEntries::get_modified_map(&self.pool, None, None).await?;
+        let mod_map = DiaryEntries::get_modified_map(&self.journal, &self.pool, None, None).await?;
+        if mod_map.is_empty() {
+            return Ok(Vec::new());
+        }
+        create_dir_all(self.diary_path()).await?;
         let year_mod_map: BTreeMap<i32, OffsetDateTime> =
             mod_map.iter().fold(BTreeMap::new(), |mut acc, (k, v)| {
                 let year = k.year();
@@ -60,13 +134,24 @@ impl LocalInterface {
                 acc
             });
 
+        let current_year = OffsetDateTime::now_utc()
+            .to_timezone(DateTimeWrapper::local_tz())
+            .year();
         let futures = year_map.into_iter().map(|(year, date_list)| {
             let year_mod_map = year_mod_map.clone();
             async move {
-                let filepath = self
-                    .config
-                    .diary_path
-                    .join(format_sstr!("diary_{year}.txt"));
+                let compression = if year < current_year - self.config.archive_after_years {
+                    self.config.archive_compression
+                } else {
+                    Compression::None
+                };
+                let plain_path = self.diary_path().join(format_sstr!("diary_{year}.txt"));
+                let filepath = match compression.extension() {
+                    Some(ext) => self
+                        .diary_path()
+                        .join(format_sstr!("diary_{year}.txt.{ext}")),
+                    None => plain_path.clone(),
+                };
                 if filepath.exists() {
                     if let Ok(metadata) = filepath.metadata() {
                         if let Ok(modified) = metadata.modified() {
@@ -80,13 +165,25 @@ impl LocalInterface {
                     }
                 }
 
-                let mut f = File::create(filepath).await?;
+                let mut buf = Vec::new();
                 for date in &date_list {
-                    let entry = DiaryEntries::get_by_date(*date, &self.pool)
+                    let entry = DiaryEntries::get_by_date(&self.journal, *date, &self.pool)
                         .await?
                         .ok_or_else(|| format_err!("Date should exist {date}"))?;
+                    if entry.is_encrypted {
+                        continue;
+                    }
                     let entry_text = format_sstr!("{date}\n\n{t}\n\n", t = entry.diary_text);
-                    f.write_all(entry_text.as_bytes()).await?;
+                    buf.extend_from_slice(entry_text.as_bytes());
+                }
+                let buf = spawn_blocking(move || compression.compress(&buf)).await??;
+                let mut f = File::create(&filepath).await?;
+                f.write_all(&buf).await?;
+                if filepath != plain_path && plain_path.exists() {
+                    remove_file(&plain_path).await?;
+                }
+                if self.config.export_comments {
+                    self.export_comments(year, &date_list, compression).await?;
                 }
                 Ok(format_sstr!("{year} {l}", l = date_list.len()))
             }
@@ -97,17 +194,58 @@ impl LocalInterface {
         Ok(output)
     }
 
+    /// Write the comments on `date_list` to `comments_{year}.txt`, rewritten
+    /// along with the year export
+    async fn export_comments(
+        &self,
+        year: i32,
+        date_list: &[Date],
+        compression: Compression,
+    ) -> Result<(), Error> {
+        let (Some(min_date), Some(max_date)) = (date_list.first(), date_list.last()) else {
+            return Ok(());
+        };
+        let comments =
+            DiaryComment::get_by_date_range(&self.journal, *min_date, *max_date, &self.pool)
+                .await?;
+        if comments.is_empty() {
+            return Ok(());
+        }
+        let buf = format_comments(&comments);
+        let buf = spawn_blocking(move || compression.compress(buf.as_bytes())).await??;
+        let plain_path = self.diary_path().join(format_sstr!("comments_{year}.txt"));
+        let filepath = match compression.extension() {
+            Some(ext) => self
+                .diary_path()
+                .join(format_sstr!("comments_{year}.txt.{ext}")),
+            None => plain_path.clone(),
+        };
+        let mut f = File::create(&filepath).await?;
+        f.write_all(&buf).await?;
+        if filepath != plain_path && plain_path.exists() {
+            remove_file(&plain_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove old daily files and bring the recent ones in line with the
+    /// database, when both changed since the last sync the file is kept and
+    /// the text of the entry it replaces is stored as a conflict
     /// # Errors
     /// Return error if db query fails
     pub async fn cleanup_local(&self) -> Result<Vec<DiaryEntries>, Error> {
         let local = DateTimeWrapper::local_tz();
-        let existing_map = DiaryEntries::get_modified_map(&self.pool, None, None).await?;
-        let previous_date = (OffsetDateTime::now_utc() - Duration::days(4))
+        let watermarks =
+            SyncWatermark::get_map(&self.journal, SyncBackend::Local, &self.pool).await?;
+        let diary_path = self.diary_path();
+        create_dir_all(&diary_path).await?;
+        let previous_date = (OffsetDateTime::now_utc() - Duration::days(LOCAL_KEEP_DAYS))
             .to_timezone(local)
             .date();
 
-        let futures: FuturesUnordered<_> = WalkDir::new(&self.config.diary_path)
+        let futures: FuturesUnordered<_> = WalkDir::new(&diary_path)
             .sort(true)
+            .max_depth(1)
             .into_iter()
             .map(|entry| async move {
                 let entry = entry?;
@@ -115,19 +253,14 @@ impl LocalInterface {
                 if let Ok(date) =
                     Date::parse(&filename, format_description!("[year]-[month]-[day].txt"))
                 {
-                    let filepath = self.config.diary_path.join(filename.as_ref());
+                    let filepath = self.diary_path().join(filename.as_ref());
                     if date <= previous_date {
                         debug!("{:?}\n", filepath);
                         remove_file(&filepath).await?;
                     } else {
-                        let metadata = metadata(&filepath)?;
-                        let size = metadata.len() as usize;
-                        let modified_secs = metadata
-                            .modified()?
-                            .duration_since(SystemTime::UNIX_EPOCH)?
-                            .as_secs() as i64;
-                        let modified = OffsetDateTime::from_unix_timestamp(modified_secs)?;
-                        return Ok(Some((date, (modified, size))));
+                        let text =
+                            spawn_blocking(move || archive::read_to_string(&filepath)).await??;
+                        return Ok(Some((date, sync_hash(&text))));
                     }
                 }
                 Ok(None)
@@ -138,55 +271,72 @@ impl LocalInterface {
             .try_collect()
             .await;
         let dates = dates?;
+        let empty_hash = sync_hash("");
 
         let current_date = OffsetDateTime::now_utc().to_timezone(local).date();
 
         let mut entries = Vec::new();
-        for current_date in (0..4).map(|i| (current_date - Duration::days(i))) {
-            if let Some((file_mod, file_size)) = dates.get(&current_date) {
-                if let Some(db_mod) = existing_map.get(&current_date) {
-                    if file_mod < db_mod {
-                        if let Some(existing_entry) =
-                            DiaryEntries::get_by_date(current_date, &self.pool).await?
-                        {
-                            let existing_size = existing_entry.diary_text.len();
-                            if existing_size > *file_size {
-                                debug!("file db diff {} {}", file_mod, db_mod);
-                                debug!("file db size {} {}", file_size, db_mod);
-                                let current_date_str = StackString::from_display(current_date);
-                                let filepath = self
-                                    .config
-                                    .diary_path
-                                    .join(current_date_str)
-                                    .with_extension("txt");
-                                let mut f = File::create(&filepath).await?;
-                                f.write_all(existing_entry.diary_text.as_bytes()).await?;
-                            }
-                            entries.push(existing_entry);
+        for current_date in (0..LOCAL_KEEP_DAYS).map(|i| (current_date - Duration::days(i))) {
+            let existing_entry =
+                DiaryEntries::get_by_date(&self.journal, current_date, &self.pool).await?;
+            match (dates.get(&current_date), existing_entry) {
+                (Some(file_hash), Some(existing_entry)) => {
+                    let db_hash = sync_hash(&existing_entry.diary_text);
+                    if existing_entry.is_encrypted || db_hash == *file_hash {
+                        continue;
+                    }
+                    let watermark = watermarks.get(&current_date).map(|w| &w.hash);
+                    if *file_hash == empty_hash || watermark == Some(file_hash) {
+                        // only the entry changed since the last sync, but an
+                        // empty entry never truncates the file
+                        if db_hash == empty_hash {
+                            continue;
+                        }
+                        debug!("file db diff {current_date}");
+                        self.write_entry(&existing_entry).await?;
+                        entries.push(existing_entry);
+                    } else if watermark != Some(&db_hash) {
+                        // both sides changed, the file wins as in the import
+                        // and the replaced text of the entry is kept as a
+                        // conflict
+                        if let Some(entry) = self.read_entry(current_date).await? {
+                            debug!("file db conflict {current_date}");
+                            entry.upsert_entry(&self.pool, true).await?;
+                            SyncWatermark::new(
+                                &self.journal,
+                                current_date,
+                                SyncBackend::Local,
+                                file_hash.clone(),
+                                entry.last_modified.to_offsetdatetime(),
+                            )
+                            .upsert(&self.pool)
+                            .await?;
+                            entries.push(entry);
                         }
                     }
-                } else {
-                    let d = DiaryEntries::new(current_date, "");
+                    // files edited since the last sync are left for the import
+                }
+                (Some(_), None) => {
+                    // files not imported yet become the entry instead of an
+                    // empty one
+                    let d = match self.read_entry(current_date).await? {
+                        Some(entry) => entry,
+                        None => DiaryEntries::new(current_date, "")
+                            .with_journal(&self.journal)
+                            .with_source(Some(SyncBackend::Local.to_str())),
+                    };
                     d.upsert_entry(&self.pool, true).await?;
                     entries.push(d);
                 }
-            } else {
-                let current_date_str = StackString::from_display(current_date);
-                let filepath = self
-                    .config
-                    .diary_path
-                    .join(current_date_str)
-                    .with_extension("txt");
-                let mut f = File::create(&filepath).await?;
-
-                if let Some(existing_entry) =
-                    DiaryEntries::get_by_date(current_date, &self.pool).await?
-                {
-                    f.write_all(existing_entry.diary_text.as_bytes()).await?;
+                (None, Some(existing_entry)) => {
+                    self.write_entry(&existing_entry).await?;
                     entries.push(existing_entry);
-                } else {
-                    f.write_all(b"").await?;
-                    let new_entry = DiaryEntries::new(current_date, "");
+                }
+                (None, None) => {
+                    let new_entry = DiaryEntries::new(current_date, "")
+                        .with_journal(&self.journal)
+                        .with_source(Some(SyncBackend::Local.to_str()));
+                    self.write_entry(&new_entry).await?;
                     new_entry.upsert_entry(&self.pool, true).await?;
                     entries.push(new_entry);
                 }
@@ -195,89 +345,296 @@ impl LocalInterface {
         Ok(entries)
     }
 
+    /// Remove the daily file for `date`, returns `false` if there was none
+    /// # Errors
+    /// Return error if removing the file fails
+    pub async fn delete_entry(&self, date: Date) -> Result<bool, Error> {
+        let filepath = self.diary_path().join(format_sstr!("{date}.txt"));
+        if !filepath.exists() {
+            return Ok(false);
+        }
+        remove_file(&filepath).await?;
+        SyncWatermark::delete(&self.journal, date, SyncBackend::Local, &self.pool).await?;
+        Ok(true)
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn import_from_local(&self) -> Result<Vec<DiaryEntries>, Error> {
-        let file_dates: HashMap<Date, _> = WalkDir::new(&self.config.diary_path)
-            .sort(true)
+        let db = PgEntryStore::new(&self.journal, self.pool.clone());
+        self.sync_engine(&db).import().await
+    }
+
+    fn sync_engine<'a>(&'a self, db: &'a PgEntryStore) -> SyncEngine<'a> {
+        SyncEngine::new(db, self)
+            .with_date_range(self.date_range)
+            .with_progress(self.progress.clone())
+    }
+
+    /// Daily file of `date`, which may have been compressed
+    fn daily_file(&self, date: Date) -> Option<PathBuf> {
+        let filename = format_sstr!("{date}.txt");
+        [Compression::None, Compression::Gzip, Compression::Zstd]
             .into_iter()
-            .filter_map(|entry| {
-                entry.ok().and_then(|entry| {
-                    let filename = entry.file_name.to_string_lossy();
-                    Date::parse(&filename, format_description!("[year]-[month]-[day].txt"))
-                        .ok()
-                        .and_then(|d| {
-                            let metadata = entry.metadata().ok()?;
-                            let modified: OffsetDateTime = metadata.modified().ok()?.into();
-                            let size = metadata.len();
-                            if size == 0 {
-                                None
-                            } else {
-                                Some((d, modified))
-                            }
-                        })
-                })
+            .map(|c| match c.extension() {
+                Some(ext) => self.diary_path().join(format_sstr!("{filename}.{ext}")),
+                None => self.diary_path().join(&filename),
             })
-            .collect();
-        let min_date = file_dates.keys().min().copied();
-        let existing_map = DiaryEntries::get_modified_map(&self.pool, min_date, None).await?;
-        let mut entries = Vec::new();
-        for (date, modified) in file_dates {
-            let filename = format_sstr!("{date}.txt");
-            let filepath = self.config.diary_path.join(&filename);
-            let should_modify = match existing_map.get(&date) {
-                Some(current_modified) => (*current_modified - modified).whole_seconds() < -1,
-                None => true,
-            };
-            if !should_modify {
+            .find(|path| path.exists())
+    }
+
+    fn file_entry(
+        &self,
+        date: Date,
+        diary_text: StackString,
+        modified: OffsetDateTime,
+    ) -> DiaryEntries {
+        DiaryEntries {
+            journal: self.journal.clone(),
+            diary_date: date,
+            diary_text,
+            last_modified: modified.into(),
+            is_encrypted: false,
+            diary_ciphertext: None,
+            diary_nonce: None,
+            deleted_at: None,
+            starred: false,
+            metadata: default_metadata(),
+            scheduled: false,
+            visibility: default_visibility(),
+            source: Some(SyncBackend::Local.to_str().into()),
+        }
+    }
+
+    /// Entry in the daily file of `date`, `None` if there is no file or it is
+    /// empty
+    /// # Errors
+    /// Return error if reading the file fails
+    pub async fn read_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+        let Some(filepath) = self.daily_file(date) else {
+            return Ok(None);
+        };
+        let Ok(metadata) = metadata(&filepath) else {
+            return Ok(None);
+        };
+        let modified: OffsetDateTime = metadata.modified()?.into();
+        let diary_text = spawn_blocking(move || archive::read_to_string(&filepath)).await??;
+        let diary_text: StackString = diary_text.trim().into();
+        if diary_text.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.file_entry(date, diary_text, modified)))
+    }
+
+    /// Overwrite the daily file of the entry's date with its text
+    /// # Errors
+    /// Return error if writing the file fails
+    pub async fn write_entry(&self, entry: &DiaryEntries) -> Result<(), Error> {
+        let modified = self.write_file(entry.diary_date, &entry.diary_text).await?;
+        let hash = sync_hash(&entry.diary_text);
+        SyncWatermark::new(
+            &self.journal,
+            entry.diary_date,
+            SyncBackend::Local,
+            hash,
+            modified,
+        )
+        .upsert(&self.pool)
+        .await
+    }
+
+    /// Point the watermarks of daily files whose text matches the entry at
+    /// the hash of that text, so the next sync doesn't treat them as changed
+    /// on both sides, returns the number of watermarks updated
+    /// # Errors
+    /// Return error if reading a file or db query fails
+    pub async fn recompute_watermarks(&self) -> Result<usize, Error> {
+        let watermarks =
+            SyncWatermark::get_map(&self.journal, SyncBackend::Local, &self.pool).await?;
+        let files = self.list().await?;
+        let stage = self.progress.start("recompute hashes", files.len());
+        let mut updated = 0;
+        for (date, modified) in files {
+            let file_entry = self.read_entry(date).await?;
+            let db_entry = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await?;
+            stage.increment();
+            let (Some(file_entry), Some(db_entry)) = (file_entry, db_entry) else {
                 continue;
-            }
-            let diary_text: StackString = read_to_string(&filepath).await?.trim().into();
-            if diary_text.is_empty() {
+            };
+            let hash = sync_hash(&file_entry.diary_text);
+            if db_entry.is_encrypted
+                || sync_hash(&db_entry.diary_text) != hash
+                || watermarks.get(&date).map(|w| &w.hash) == Some(&hash)
+            {
                 continue;
             }
-            let entry = DiaryEntries {
-                diary_date: date,
-                diary_text,
-                last_modified: modified.into(),
-            };
-            debug!(
-                "import local date {} lines {}\n",
-                entry.diary_date,
-                entry.diary_text.matches('\n').count()
-            );
-            entry.upsert_entry(&self.pool, true).await?;
-            entries.push(entry);
+            SyncWatermark::new(&self.journal, date, SyncBackend::Local, hash, modified)
+                .upsert(&self.pool)
+                .await?;
+            updated += 1;
         }
-        Ok(entries)
+        Ok(updated)
+    }
+
+    /// Daily files `import_from_local` would skip or garble
+    /// # Errors
+    /// Return error if listing the directory fails
+    pub async fn lint_files(&self) -> Result<Vec<FileIssue>, Error> {
+        let diary_path = self.diary_path();
+        spawn_blocking(move || find_file_issues(&diary_path)).await?
+    }
+
+    /// Rename, rewrite or remove the files of `issues`, keeping backups of
+    /// the originals, returns what was done
+    /// # Errors
+    /// Return error if a file can't be repaired
+    pub async fn repair_files(&self, issues: Vec<FileIssue>) -> Result<Vec<StackString>, Error> {
+        let diary_path = self.diary_path();
+        spawn_blocking(move || repair_file_issues(&diary_path, &issues)).await?
+    }
+
+    /// Import the daily files of `dates` modified since the last sync,
+    /// missing and empty files are skipped
+    /// # Errors
+    /// Return error if reading a file or db query fails
+    pub async fn import_dates(&self, dates: &[Date]) -> Result<Vec<DiaryEntries>, Error> {
+        let listing = dates
+            .iter()
+            .filter_map(|date| {
+                let metadata = metadata(self.daily_file(*date)?).ok()?;
+                if metadata.len() == 0 {
+                    return None;
+                }
+                let modified: OffsetDateTime = metadata.modified().ok()?.into();
+                Some((*date, modified))
+            })
+            .collect();
+        let db = PgEntryStore::new(&self.journal, self.pool.clone());
+        self.sync_engine(&db).import_listing(listing).await
+    }
+
+    /// Write `text` to the daily file of `date`, returns when the file was
+    /// modified
+    async fn write_file(&self, date: Date, text: &str) -> Result<OffsetDateTime, Error> {
+        let diary_path = self.diary_path();
+        create_dir_all(&diary_path).await?;
+        let filepath = diary_path.join(format_sstr!("{date}.txt"));
+        let mut f = File::create(&filepath).await?;
+        f.write_all(text.as_bytes()).await?;
+        f.sync_all().await?;
+        metadata(&filepath)?
+            .modified()
+            .map(Into::into)
+            .map_err(Into::into)
+    }
+}
+
+#[async_trait]
+impl SyncStore for LocalInterface {
+    fn backend(&self) -> SyncBackend {
+        SyncBackend::Local
+    }
+
+    /// Daily files are what gets edited, they win
+    fn keeps_own_copy(&self) -> bool {
+        true
+    }
+
+    async fn list(&self) -> Result<HashMap<Date, OffsetDateTime>, Error> {
+        let diary_path = self.diary_path();
+        if !diary_path.exists() {
+            return Ok(HashMap::new());
+        }
+        Ok(WalkDir::new(&diary_path)
+            .sort(true)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let filename = entry.file_name.to_string_lossy();
+                let (base, _) = Compression::from_filename(&filename);
+                let date =
+                    Date::parse(base, format_description!("[year]-[month]-[day].txt")).ok()?;
+                let metadata = entry.metadata().ok()?;
+                if metadata.len() == 0 {
+                    return None;
+                }
+                let modified: OffsetDateTime = metadata.modified().ok()?.into();
+                Some((date, modified))
+            })
+            .collect())
+    }
+
+    async fn read(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+        self.read_entry(date).await
+    }
+
+    async fn write(&self, entry: &DiaryEntries) -> Result<OffsetDateTime, Error> {
+        self.write_file(entry.diary_date, &entry.diary_text).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
+    use futures::TryStreamExt;
     use jwalk::WalkDir;
     use log::debug;
+    use std::path::Path;
     use tempdir::TempDir;
+    use time::{macros::date, Duration, OffsetDateTime};
+    use time_tz::OffsetDateTimeExt;
 
-    use crate::{config::Config, local_interface::LocalInterface, pgpool::PgPool};
+    use crate::{
+        config::Config,
+        date_sync::sync_hash,
+        date_time_wrapper::DateTimeWrapper,
+        local_interface::{parse_local_path, LocalInterface},
+        models::{DiaryConflict, DiaryEntries, SyncBackend, SyncWatermark},
+        test_db::{EntryFixture, TestDb},
+    };
 
     fn get_tempdir() -> Result<TempDir, Error> {
         TempDir::new("test_diary").map_err(Into::into)
     }
 
-    fn get_li(tempdir: &TempDir) -> Result<LocalInterface, Error> {
+    async fn get_li(tempdir: &TempDir) -> Result<(TestDb, LocalInterface), Error> {
         let config = Config::get_local_config(tempdir.path())?;
-        let pool = PgPool::new(&config.database_url)?;
-        Ok(LocalInterface::new(config, pool))
+        let db = TestDb::new().await?;
+        let li = LocalInterface::new(config, db.pool.clone());
+        Ok((db, li))
+    }
+
+    #[test]
+    fn test_parse_local_path() {
+        let diary_path = Path::new("/home/user/diary");
+        assert_eq!(
+            parse_local_path(diary_path, &diary_path.join("2024-03-01.txt")),
+            Some(("diary".into(), date!(2024 - 03 - 01)))
+        );
+        assert_eq!(
+            parse_local_path(diary_path, &diary_path.join("dreams/2024-03-02.txt")),
+            Some(("dreams".into(), date!(2024 - 03 - 02)))
+        );
+        assert_eq!(
+            parse_local_path(diary_path, &diary_path.join("a/b/2024-03-02.txt")),
+            None
+        );
+        assert_eq!(
+            parse_local_path(diary_path, &diary_path.join("diary_2024.txt")),
+            None
+        );
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_export_year_to_local() -> Result<(), Error> {
         let t = get_tempdir()?;
-        let li = get_li(&t)?;
+        let (db, li) = get_li(&t).await?;
+        EntryFixture::insert_days(date!(2013 - 01 - 01), 296, &li.pool).await?;
+        EntryFixture::insert_days(date!(2014 - 03 - 01), 10, &li.pool).await?;
         let results = li.export_year_to_local().await?;
         assert!(results.contains(&"2013 296".into()));
+        assert!(results.contains(&"2014 10".into()));
         let nentries = results.len();
         debug!("{:?}", results);
         debug!("{:?}", t.path());
@@ -297,15 +654,15 @@ mod tests {
             .filter_map(|x| x.transpose())
             .collect();
         let results = results?;
-        assert!(results.len() >= 9);
+        assert_eq!(results.len(), 2);
         assert_eq!(results.len(), nentries);
-        Ok(())
+        db.cleanup().await
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_cleanup_local() -> Result<(), Error> {
         let t = get_tempdir()?;
-        let li = get_li(&t)?;
+        let (db, li) = get_li(&t).await?;
         let results = li.cleanup_local().await?;
         let number_results = results.len();
         debug!("{:?}", results);
@@ -327,6 +684,46 @@ mod tests {
         let results = results?;
         debug!("{:?}", results);
         assert_eq!(results.len(), number_results);
-        Ok(())
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cleanup_local_keeps_diverged_file() -> Result<(), Error> {
+        let t = get_tempdir()?;
+        let (db, li) = get_li(&t).await?;
+        let today = OffsetDateTime::now_utc()
+            .to_timezone(DateTimeWrapper::local_tz())
+            .date();
+        let yesterday = today - Duration::days(1);
+
+        // both sides changed without a watermark, the file wins
+        EntryFixture::new(today, "db line").insert(&li.pool).await?;
+        li.write_file(today, "file line").await?;
+        // the file is unchanged since the last sync but the entry is empty
+        EntryFixture::new(yesterday, "").insert(&li.pool).await?;
+        let modified = li.write_file(yesterday, "kept line").await?;
+        SyncWatermark::new(
+            &li.journal,
+            yesterday,
+            SyncBackend::Local,
+            sync_hash("kept line"),
+            modified,
+        )
+        .upsert(&li.pool)
+        .await?;
+
+        li.cleanup_local().await?;
+        let entry = DiaryEntries::get_by_date(&li.journal, today, &li.pool)
+            .await?
+            .unwrap();
+        assert_eq!(entry.diary_text, "file line");
+        let conflicts: Vec<_> = DiaryConflict::get_by_date(&li.journal, today, &li.pool)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(conflicts.len(), 1);
+        let entry = li.read_entry(yesterday).await?.unwrap();
+        assert_eq!(entry.diary_text, "kept line");
+        db.cleanup().await
     }
 }
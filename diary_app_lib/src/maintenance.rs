@@ -0,0 +1,353 @@
+use anyhow::Error;
+use log::{error, info};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::{collections::VecDeque, fmt, str::FromStr, sync::Arc};
+use thiserror::Error as ThisError;
+use uuid::Uuid;
+
+use crate::{
+    date_time_wrapper::DateTimeWrapper,
+    diary_app_interface::DiaryAppInterface,
+    error_reporting::report_error,
+    sync_progress::{ProgressReporter, SyncProgress},
+};
+
+/// Conflicts older than this are removed by `purge_conflicts` unless the
+/// request gives another age
+pub const CONFLICT_RETENTION_DAYS: i64 = 90;
+
+/// Finished jobs beyond this many are forgotten, oldest first
+const MAX_JOBS: usize = 20;
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum MaintenanceError {
+    #[error("Unknown maintenance task {0}")]
+    UnknownTask(StackString),
+    #[error("{0} is already running for journal {1}")]
+    AlreadyRunning(MaintenanceTask, StackString),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceTask {
+    /// Remove old conflicts of every journal
+    PurgeConflicts,
+    /// Recount the search terms of every entry of the journal
+    RebuildTerms,
+    /// Fix the local watermarks of daily files matching their entry
+    RecomputeHashes,
+    /// Refresh the cached listing of the s3 bucket
+    WarmS3Cache,
+}
+
+impl MaintenanceTask {
+    pub const ALL: [Self; 4] = [
+        Self::PurgeConflicts,
+        Self::RebuildTerms,
+        Self::RecomputeHashes,
+        Self::WarmS3Cache,
+    ];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PurgeConflicts => "purge_conflicts",
+            Self::RebuildTerms => "rebuild_terms",
+            Self::RecomputeHashes => "recompute_hashes",
+            Self::WarmS3Cache => "warm_s3_cache",
+        }
+    }
+
+    async fn run(
+        self,
+        dapp: &DiaryAppInterface,
+        older_than_days: Option<i64>,
+    ) -> Result<StackString, Error> {
+        match self {
+            Self::PurgeConflicts => {
+                let days = older_than_days.unwrap_or(CONFLICT_RETENTION_DAYS);
+                let removed = dapp.purge_old_conflicts(days).await?;
+                Ok(format_sstr!(
+                    "removed {removed} conflicts older than {days} days"
+                ))
+            }
+            Self::RebuildTerms => {
+                let count = dapp.rebuild_terms().await?;
+                Ok(format_sstr!("recounted terms of {count} entries"))
+            }
+            Self::RecomputeHashes => {
+                let count = dapp.recompute_hashes().await?;
+                Ok(format_sstr!("updated {count} local watermarks"))
+            }
+            Self::WarmS3Cache => {
+                let count = dapp.warm_s3_cache().await?;
+                Ok(format_sstr!("cached {count} s3 keys"))
+            }
+        }
+    }
+}
+
+impl fmt::Display for MaintenanceTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MaintenanceTask {
+    type Err = MaintenanceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|task| task.as_str() == s)
+            .ok_or_else(|| MaintenanceError::UnknownTask(s.into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceStatus {
+    Running,
+    Finished,
+    Failed,
+}
+
+impl MaintenanceStatus {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Finished => "finished",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceJob {
+    pub id: Uuid,
+    pub task: MaintenanceTask,
+    pub journal: StackString,
+    pub status: MaintenanceStatus,
+    pub progress: SyncProgress,
+    /// Summary of what a finished job did
+    pub output: Option<StackString>,
+    pub error: Option<StackString>,
+    pub started_at: DateTimeWrapper,
+    pub finished_at: Option<DateTimeWrapper>,
+}
+
+#[derive(Debug)]
+struct JobEntry {
+    job: MaintenanceJob,
+    progress: ProgressReporter,
+}
+
+impl JobEntry {
+    fn snapshot(&self) -> MaintenanceJob {
+        let mut job = self.job.clone();
+        job.progress = self.progress.current();
+        job
+    }
+}
+
+/// Maintenance jobs started through the api, each runs in the background
+/// with its own progress reporter
+#[derive(Clone, Debug, Default)]
+pub struct MaintenanceJobs {
+    jobs: Arc<Mutex<VecDeque<JobEntry>>>,
+}
+
+impl MaintenanceJobs {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `task` against the journal of `dapp` in the background
+    /// # Errors
+    /// Return error if the same task is still running for the journal
+    pub fn start(
+        &self,
+        dapp: &DiaryAppInterface,
+        task: MaintenanceTask,
+        older_than_days: Option<i64>,
+    ) -> Result<MaintenanceJob, MaintenanceError> {
+        let progress = ProgressReporter::new();
+        let job = self.register(task, &dapp.journal, progress.clone())?;
+        let dapp = dapp.clone().with_progress(progress);
+        let jobs = self.clone();
+        let id = job.id;
+        tokio::spawn(async move {
+            info!("starting maintenance job {task} {id}");
+            let result = task.run(&dapp, older_than_days).await;
+            if let Err(e) = &result {
+                error!("maintenance job {task} {id} failed {e}");
+                report_error(e, &[("task", task.as_str()), ("journal", &dapp.journal)]);
+            }
+            jobs.finish(id, result);
+        });
+        Ok(job)
+    }
+
+    fn register(
+        &self,
+        task: MaintenanceTask,
+        journal: &str,
+        progress: ProgressReporter,
+    ) -> Result<MaintenanceJob, MaintenanceError> {
+        let mut jobs = self.jobs.lock();
+        let running = jobs.iter().any(|e| {
+            e.job.task == task
+                && e.job.journal == journal
+                && e.job.status == MaintenanceStatus::Running
+        });
+        if running {
+            return Err(MaintenanceError::AlreadyRunning(task, journal.into()));
+        }
+        let job = MaintenanceJob {
+            id: Uuid::new_v4(),
+            task,
+            journal: journal.into(),
+            status: MaintenanceStatus::Running,
+            progress: SyncProgress::default(),
+            output: None,
+            error: None,
+            started_at: DateTimeWrapper::now(),
+            finished_at: None,
+        };
+        jobs.push_back(JobEntry {
+            job: job.clone(),
+            progress,
+        });
+        while jobs.len() > MAX_JOBS {
+            let Some(index) = jobs
+                .iter()
+                .position(|e| e.job.status != MaintenanceStatus::Running)
+            else {
+                break;
+            };
+            jobs.remove(index);
+        }
+        Ok(job)
+    }
+
+    fn finish(&self, id: Uuid, result: Result<StackString, Error>) {
+        let mut jobs = self.jobs.lock();
+        if let Some(entry) = jobs.iter_mut().find(|e| e.job.id == id) {
+            match result {
+                Ok(output) => {
+                    entry.job.status = MaintenanceStatus::Finished;
+                    entry.job.output = Some(output);
+                }
+                Err(e) => {
+                    entry.job.status = MaintenanceStatus::Failed;
+                    entry.job.error = Some(format_sstr!("{e}"));
+                }
+            }
+            entry.job.finished_at = Some(DateTimeWrapper::now());
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, id: Uuid) -> Option<MaintenanceJob> {
+        self.jobs
+            .lock()
+            .iter()
+            .find(|e| e.job.id == id)
+            .map(JobEntry::snapshot)
+    }
+
+    /// Known jobs, most recently started first
+    #[must_use]
+    pub fn list(&self) -> Vec<MaintenanceJob> {
+        self.jobs
+            .lock()
+            .iter()
+            .rev()
+            .map(JobEntry::snapshot)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::format_err;
+
+    use crate::{
+        maintenance::{
+            MaintenanceError, MaintenanceJobs, MaintenanceStatus, MaintenanceTask, MAX_JOBS,
+        },
+        sync_progress::ProgressReporter,
+    };
+
+    #[test]
+    fn test_maintenance_task_from_str() {
+        for task in MaintenanceTask::ALL {
+            assert_eq!(task.as_str().parse::<MaintenanceTask>(), Ok(task));
+            let json = serde_json::to_string(&task).unwrap();
+            assert_eq!(json, format!("\"{task}\""));
+        }
+        assert_eq!(
+            "vacuum".parse::<MaintenanceTask>(),
+            Err(MaintenanceError::UnknownTask("vacuum".into()))
+        );
+    }
+
+    #[test]
+    fn test_maintenance_jobs() {
+        let jobs = MaintenanceJobs::new();
+        let progress = ProgressReporter::new();
+        let job = jobs
+            .register(MaintenanceTask::RebuildTerms, "diary", progress.clone())
+            .unwrap();
+        assert_eq!(
+            jobs.register(
+                MaintenanceTask::RebuildTerms,
+                "diary",
+                ProgressReporter::new()
+            ),
+            Err(MaintenanceError::AlreadyRunning(
+                MaintenanceTask::RebuildTerms,
+                "diary".into()
+            ))
+        );
+        let other = jobs
+            .register(
+                MaintenanceTask::RebuildTerms,
+                "work",
+                ProgressReporter::new(),
+            )
+            .unwrap();
+
+        progress.start("rebuild terms", 3).increment();
+        let current = jobs.get(job.id).unwrap();
+        assert_eq!(current.status, MaintenanceStatus::Running);
+        assert_eq!(current.progress.latest().map(|s| s.processed), Some(1));
+
+        jobs.finish(job.id, Ok("recounted terms of 3 entries".into()));
+        jobs.finish(other.id, Err(format_err!("db is gone")));
+        let listed = jobs.list();
+        assert_eq!(listed[0].id, other.id);
+        assert_eq!(listed[0].status, MaintenanceStatus::Failed);
+        assert_eq!(listed[0].error.as_deref(), Some("db is gone"));
+        assert_eq!(listed[1].status, MaintenanceStatus::Finished);
+        assert!(listed[1].finished_at.is_some());
+
+        // finished jobs can be started again and old ones are dropped
+        for _ in 0..MAX_JOBS {
+            let job = jobs
+                .register(
+                    MaintenanceTask::WarmS3Cache,
+                    "diary",
+                    ProgressReporter::new(),
+                )
+                .unwrap();
+            jobs.finish(job.id, Ok("cached 0 s3 keys".into()));
+        }
+        assert_eq!(jobs.list().len(), MAX_JOBS);
+        assert!(jobs.get(job.id).is_none());
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{format_err, Error};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::collections::{HashMap, HashSet};
+use time::Date;
+
+use crate::{
+    date_time_wrapper::DateTimeWrapper,
+    diary_app_interface::DiaryAppInterface,
+    models::{AuditAction, DiaryEntries, DiaryTombstone, SyncState, MOBILE_SOURCE},
+};
+
+/// State of an entry as known by an intermittently connected client
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ClientEntryState {
+    pub diary_date: Date,
+    /// Sha256 of the client's text, see [`DiaryEntries::get_hash`]
+    pub hash: StackString,
+    /// `last_modified` of the server entry the client last synced
+    pub server_modified: Option<DateTimeWrapper>,
+    /// Counter the client increments whenever it edits the entry
+    pub client_version: i64,
+    /// Only sent when the client has local modifications
+    pub diary_text: Option<StackString>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServerEntryState {
+    pub diary_date: Date,
+    /// Empty for encrypted entries
+    pub diary_text: StackString,
+    /// Set for entries encrypted client side, the client decrypts
+    /// `diary_ciphertext` itself
+    pub is_encrypted: bool,
+    pub diary_ciphertext: Option<Vec<u8>>,
+    pub diary_nonce: Option<Vec<u8>>,
+    pub server_modified: DateTimeWrapper,
+    /// Set when both sides changed the entry, the client text was recorded
+    /// as a `DiaryConflict` and the server text is returned.  The text of an
+    /// encrypted entry can't be recorded, the client has to keep its text.
+    pub conflict: bool,
+    /// Set when the entry was deleted on the server, the client should
+    /// remove its copy, the other fields are empty
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+impl ServerEntryState {
+    fn new(entry: DiaryEntries, conflict: bool) -> Self {
+        Self {
+            diary_date: entry.diary_date,
+            diary_text: entry.diary_text,
+            is_encrypted: entry.is_encrypted,
+            diary_ciphertext: entry.diary_ciphertext,
+            diary_nonce: entry.diary_nonce,
+            server_modified: entry.last_modified,
+            conflict,
+            deleted: false,
+        }
+    }
+
+    fn deleted(diary_date: Date) -> Self {
+        Self {
+            diary_date,
+            diary_text: StackString::new(),
+            is_encrypted: false,
+            diary_ciphertext: None,
+            diary_nonce: None,
+            server_modified: DateTimeWrapper::now(),
+            conflict: false,
+            deleted: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    InSync,
+    AcceptClient,
+    SendServer,
+    Conflict,
+    Ignore,
+    Delete,
+}
+
+/// Compare the client's vector clock against the server entry and the
+/// state recorded at the last sync, client text never replaces an encrypted
+/// entry.  A date without an entry which the client synced before, or which
+/// has a tombstone, was deleted on the server, client text only re-creates it
+/// once the client has been told of the deletion.
+#[must_use]
+pub fn resolve(
+    client: &ClientEntryState,
+    server: Option<&DiaryEntries>,
+    recorded: Option<&SyncState>,
+    tombstoned: bool,
+) -> Resolution {
+    let client_changed = client.diary_text.is_some()
+        && recorded.is_none_or(|r| client.client_version > r.client_version);
+    match server {
+        None => match recorded {
+            Some(recorded) if recorded.deleted && client_changed => Resolution::AcceptClient,
+            Some(_) => Resolution::Delete,
+            None if tombstoned => Resolution::Delete,
+            None if client_changed => Resolution::AcceptClient,
+            None => Resolution::Ignore,
+        },
+        Some(server) => {
+            if server.get_hash() == client.hash {
+                return Resolution::InSync;
+            }
+            let server_changed = client
+                .server_modified
+                .is_none_or(|m| server.last_modified > m);
+            match (client_changed, server_changed) {
+                (true, false) if !server.is_encrypted => Resolution::AcceptClient,
+                (true, _) => Resolution::Conflict,
+                (false, _) => Resolution::SendServer,
+            }
+        }
+    }
+}
+
+/// Neither the client nor the server changed the entry since the state
+/// recorded at the last sync
+#[must_use]
+pub fn is_unchanged(
+    client: &ClientEntryState,
+    server_modified: Option<DateTimeWrapper>,
+    recorded: Option<&SyncState>,
+) -> bool {
+    match (server_modified, recorded) {
+        (Some(server_modified), Some(recorded)) => {
+            client.diary_text.is_none()
+                && recorded.matches(client.client_version, server_modified, &client.hash)
+        }
+        _ => false,
+    }
+}
+
+/// Reconcile the entries of `journal` known by `client_id` with the server,
+/// returning the entries the client needs to update and recording the new
+/// state.  Dates the client doesn't list are only returned when they are new
+/// or changed since its last sync, a client which lost its entries has to
+/// sync with a new `client_id`.
+/// # Errors
+/// Return `LimitError` if client text longer than `max_entry_length` would be
+/// written, or error if db query fails
+pub async fn sync_client(
+    dapp: &DiaryAppInterface,
+    client_id: &str,
+    client_entries: Vec<ClientEntryState>,
+) -> Result<Vec<ServerEntryState>, Error> {
+    let pool = &dapp.pool;
+    let journal = dapp.journal.as_str();
+    let recorded: HashMap<Date, SyncState> = SyncState::get_by_client(client_id, journal, pool)
+        .await?
+        .map_ok(|s| (s.diary_date, s))
+        .try_collect()
+        .await?;
+    let modified: HashMap<Date, DateTimeWrapper> =
+        DiaryEntries::get_modified_map(journal, pool, None, None)
+            .await?
+            .into_iter()
+            .map(|(date, m)| (date, m.into()))
+            .collect();
+    let tombstones = DiaryTombstone::get_dates(journal, pool).await?;
+    let client_dates: HashSet<Date> = client_entries.iter().map(|c| c.diary_date).collect();
+    let client_entries: Vec<_> = client_entries
+        .into_iter()
+        .filter(|c| {
+            let date = c.diary_date;
+            !is_unchanged(c, modified.get(&date).copied(), recorded.get(&date))
+        })
+        .collect();
+    let new_dates: Vec<Date> = modified
+        .iter()
+        .filter(|(date, m)| {
+            !client_dates.contains(date)
+                && recorded.get(date).is_none_or(|r| r.server_modified < **m)
+        })
+        .map(|(date, _)| *date)
+        .collect();
+    let dates: Vec<Date> = client_entries
+        .iter()
+        .map(|c| c.diary_date)
+        .chain(new_dates.iter().copied())
+        .collect();
+    let mut server_entries: HashMap<Date, DiaryEntries> =
+        DiaryEntries::get_by_dates(journal, &dates, pool)
+            .await?
+            .into_iter()
+            .map(|e| (e.diary_date, e))
+            .collect();
+
+    let mut output = Vec::new();
+    for client in client_entries {
+        let date = client.diary_date;
+        let server = server_entries.get(&date);
+        let recorded = recorded.get(&date);
+        let tombstoned = tombstones.contains(&date);
+        let (entry, conflict) = match resolve(&client, server, recorded, tombstoned) {
+            Resolution::Ignore => continue,
+            Resolution::Delete => {
+                let version = client.client_version;
+                if !recorded.is_some_and(|r| r.deleted && r.client_version == version) {
+                    SyncState::new(
+                        client_id,
+                        journal,
+                        date,
+                        version,
+                        DateTimeWrapper::now(),
+                        "",
+                    )
+                    .with_deleted(true)
+                    .upsert_state(pool)
+                    .await?;
+                }
+                output.push(ServerEntryState::deleted(date));
+                continue;
+            }
+            Resolution::InSync => {
+                if let Some(server) = server {
+                    let state = SyncState::new(
+                        client_id,
+                        journal,
+                        date,
+                        client.client_version,
+                        server.last_modified,
+                        &client.hash,
+                    );
+                    if !recorded.is_some_and(|r| {
+                        r.matches(state.client_version, state.server_modified, &state.hash)
+                    }) {
+                        state.upsert_state(pool).await?;
+                    }
+                }
+                continue;
+            }
+            Resolution::AcceptClient => {
+                let text = client
+                    .diary_text
+                    .as_ref()
+                    .ok_or_else(|| format_err!("No text for {date}"))?;
+                dapp.check_length(text)?;
+                DiaryEntries::new(date, text)
+                    .with_journal(journal)
+                    .with_source(Some(MOBILE_SOURCE))
+                    .upsert_entry(pool, true)
+                    .await?;
+                dapp.record_activity(
+                    AuditAction::MobileSync,
+                    Some(date),
+                    format_sstr!("accepted {} bytes from {client_id}", text.len()),
+                )
+                .await;
+                let Some(entry) = DiaryEntries::get_by_date(journal, date, pool).await? else {
+                    continue;
+                };
+                (entry, false)
+            }
+            Resolution::SendServer => match server {
+                Some(server) => (server.clone(), false),
+                None => continue,
+            },
+            Resolution::Conflict => {
+                let Some(server) = server else {
+                    continue;
+                };
+                if !server.is_encrypted {
+                    let text = client
+                        .diary_text
+                        .as_ref()
+                        .ok_or_else(|| format_err!("No text for {date}"))?;
+                    dapp.check_length(text)?;
+                    DiaryEntries::new(date, text)
+                        .with_journal(journal)
+                        .with_source(Some(MOBILE_SOURCE))
+                        .update_entry(pool, false)
+                        .await?;
+                    dapp.record_activity(
+                        AuditAction::MobileSync,
+                        Some(date),
+                        format_sstr!("conflicting {} bytes from {client_id}", text.len()),
+                    )
+                    .await;
+                }
+                (server.clone(), true)
+            }
+        };
+        SyncState::new(
+            client_id,
+            journal,
+            date,
+            client.client_version,
+            entry.last_modified,
+            entry.get_hash(),
+        )
+        .upsert_state(pool)
+        .await?;
+        output.push(ServerEntryState::new(entry, conflict));
+    }
+
+    for date in new_dates {
+        let Some(entry) = server_entries.remove(&date) else {
+            continue;
+        };
+        SyncState::new(
+            client_id,
+            journal,
+            date,
+            0,
+            entry.last_modified,
+            entry.get_hash(),
+        )
+        .upsert_state(pool)
+        .await?;
+        output.push(ServerEntryState::new(entry, false));
+    }
+    output.sort_by_key(|e| e.diary_date);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, datetime};
+
+    use crate::{
+        date_time_wrapper::DateTimeWrapper,
+        mobile_sync::{is_unchanged, resolve, ClientEntryState, Resolution},
+        models::{DiaryEntries, SyncState},
+    };
+
+    #[test]
+    fn test_resolve() {
+        let mut server = DiaryEntries::new(date!(2024 - 01 - 01), "server text");
+        server.last_modified = datetime!(2024-01-02 00:00 UTC).into();
+        let synced: DateTimeWrapper = datetime!(2024-01-01 00:00 UTC).into();
+        let recorded = SyncState::new("phone", "diary", server.diary_date, 1, synced, "");
+
+        let mut client = ClientEntryState {
+            diary_date: server.diary_date,
+            hash: server.get_hash(),
+            server_modified: Some(synced),
+            client_version: 1,
+            diary_text: None,
+        };
+        assert_eq!(
+            resolve(&client, Some(&server), Some(&recorded), false),
+            Resolution::InSync
+        );
+
+        let client_entry = DiaryEntries::new(server.diary_date, "client text");
+        client.hash = client_entry.get_hash();
+        assert_eq!(
+            resolve(&client, Some(&server), Some(&recorded), false),
+            Resolution::SendServer
+        );
+
+        client.diary_text = Some("client text".into());
+        client.client_version = 2;
+        assert_eq!(
+            resolve(&client, Some(&server), Some(&recorded), false),
+            Resolution::Conflict
+        );
+
+        client.server_modified = Some(server.last_modified);
+        assert_eq!(
+            resolve(&client, Some(&server), Some(&recorded), false),
+            Resolution::AcceptClient
+        );
+
+        client.client_version = 1;
+        assert_eq!(
+            resolve(&client, Some(&server), Some(&recorded), false),
+            Resolution::SendServer
+        );
+
+        let mut encrypted = DiaryEntries::new_encrypted(server.diary_date, vec![1], vec![0; 12]);
+        encrypted.last_modified = server.last_modified;
+        client.client_version = 2;
+        assert_eq!(
+            resolve(&client, Some(&encrypted), Some(&recorded), false),
+            Resolution::Conflict
+        );
+        client.client_version = 1;
+
+        assert_eq!(
+            resolve(&client, None, None, false),
+            Resolution::AcceptClient
+        );
+        client.diary_text = None;
+        assert_eq!(resolve(&client, None, None, false), Resolution::Ignore);
+    }
+
+    #[test]
+    fn test_resolve_deleted() {
+        let date = date!(2024 - 01 - 01);
+        let synced: DateTimeWrapper = datetime!(2024-01-01 00:00 UTC).into();
+        let recorded = SyncState::new("phone", "diary", date, 1, synced, "hash");
+        let mut client = ClientEntryState {
+            diary_date: date,
+            hash: "hash".into(),
+            server_modified: Some(synced),
+            client_version: 1,
+            diary_text: None,
+        };
+        // the entry the client synced is gone, edits don't bring it back
+        assert_eq!(
+            resolve(&client, None, Some(&recorded), false),
+            Resolution::Delete
+        );
+        client.client_version = 2;
+        client.diary_text = Some("client text".into());
+        assert_eq!(
+            resolve(&client, None, Some(&recorded), false),
+            Resolution::Delete
+        );
+        // nor do older copies of a deleted entry
+        assert_eq!(resolve(&client, None, None, true), Resolution::Delete);
+
+        // once told, the client keeps being told until it writes the date anew
+        let recorded = recorded.with_deleted(true);
+        client.client_version = 1;
+        assert_eq!(
+            resolve(&client, None, Some(&recorded), true),
+            Resolution::Delete
+        );
+        client.client_version = 2;
+        assert_eq!(
+            resolve(&client, None, Some(&recorded), true),
+            Resolution::AcceptClient
+        );
+    }
+    #[test]
+    fn test_is_unchanged() {
+        let date = date!(2024 - 01 - 01);
+        let synced: DateTimeWrapper = datetime!(2024-01-01 00:00 UTC).into();
+        let recorded = SyncState::new("phone", "diary", date, 1, synced, "hash");
+        let mut client = ClientEntryState {
+            diary_date: date,
+            hash: "hash".into(),
+            server_modified: Some(synced),
+            client_version: 1,
+            diary_text: None,
+        };
+        assert!(is_unchanged(&client, Some(synced), Some(&recorded)));
+        assert!(!is_unchanged(&client, Some(synced), None));
+        assert!(!is_unchanged(&client, None, Some(&recorded)));
+
+        let modified: DateTimeWrapper = datetime!(2024-01-02 00:00 UTC).into();
+        assert!(!is_unchanged(&client, Some(modified), Some(&recorded)));
+
+        client.client_version = 2;
+        client.diary_text = Some("client text".into());
+        assert!(!is_unchanged(&client, Some(synced), Some(&recorded)));
+    }
+}
@@ -1,31 +1,271 @@
 use anyhow::{format_err, Error};
 use derive_more::Into;
-use difference::{Changeset, Difference};
 use futures::{Stream, TryStreamExt};
 use log::debug;
-use postgres_query::{client::GenericClient, query, query_dyn, Error as PqError, FromSqlRow};
+use postgres_query::{
+    client::GenericClient, query, query_dyn, Error as PqError, FromSqlRow, Parameter,
+};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+use sha2::{Digest, Sha256};
 use stack_string::{format_sstr, StackString};
-use std::collections::HashMap;
-use time::{Date, OffsetDateTime};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+use time::{util::days_in_year_month, Date, Duration, Month, OffsetDateTime};
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
 use crate::{
+    authorship::diff_authors,
     date_time_wrapper::DateTimeWrapper,
+    entry_limits::check_length,
+    line_diff::{DiffHunk, DiffTag, LineDiff},
     pgpool::{PgPool, PgTransaction},
+    redaction::redact_text,
+    text_normalize::{is_normalized, normalize_text},
+    word_stats::term_counts,
+    writing_habits::WRITTEN_AT_KEY,
 };
 
-#[derive(FromSqlRow, Clone, Debug)]
+/// Journal holding entries created before journals existed, its entries are
+/// kept at the top level of `diary_path` and `diary_bucket`
+pub const DEFAULT_JOURNAL: &str = "diary";
+
+pub(crate) fn default_journal() -> StackString {
+    DEFAULT_JOURNAL.into()
+}
+
+pub(crate) fn default_metadata() -> Value {
+    Value::Object(Map::new())
+}
+
+pub(crate) fn default_visibility() -> StackString {
+    PUBLIC_VISIBILITY.into()
+}
+
+/// Entries every user can read
+pub const PUBLIC_VISIBILITY: &str = "public";
+/// Entries hidden from viewers, only owners can read them
+pub const PRIVATE_VISIBILITY: &str = "private";
+
+/// Interpret a metadata value given as text, numbers and booleans are stored
+/// as such so that they can be aggregated
+#[must_use]
+pub fn parse_metadata_value(value: &str) -> Value {
+    let value = value.trim();
+    if let Ok(b) = value.parse::<bool>() {
+        Value::Bool(b)
+    } else if let Ok(i) = value.parse::<i64>() {
+        Value::Number(i.into())
+    } else if let Some(f) = value.parse::<f64>().ok().and_then(Number::from_f64) {
+        Value::Number(f)
+    } else {
+        Value::String(value.into())
+    }
+}
+
+/// Whether `table` exists in the schema search path, e.g. to tell a fresh
+/// database from one the migrations have run on
+/// # Errors
+/// Return error if db query fails
+pub async fn table_exists(table: &str, pool: &PgPool) -> Result<bool, Error> {
+    #[derive(FromSqlRow, Into)]
+    struct Wrap(bool);
+
+    let query = query!("SELECT to_regclass($table) IS NOT NULL", table = table);
+    let conn = pool.get().await?;
+    let result: Wrap = query.fetch_one(&conn).await?;
+    Ok(result.into())
+}
+
+/// Dates a sync is limited to, both ends are inclusive and unbounded when
+/// `None`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub since: Option<Date>,
+    pub until: Option<Date>,
+}
+
+impl DateRange {
+    #[must_use]
+    pub fn new(since: Option<Date>, until: Option<Date>) -> Self {
+        Self { since, until }
+    }
+
+    #[must_use]
+    pub fn contains(&self, date: Date) -> bool {
+        self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date <= until)
+    }
+
+    #[must_use]
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+}
+
+/// Order of listed dates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(Self::Asc),
+            "desc" => Ok(Self::Desc),
+            _ => Err(format_err!("Invalid sort order {s}")),
+        }
+    }
+}
+
+/// Dates of visible entries to list, pages start `offset` dates in or right
+/// past `cursor`, the last date of the previous page
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateListQuery {
+    pub range: DateRange,
+    pub order: SortOrder,
+    pub cursor: Option<Date>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub starred: bool,
+    /// Word count bounds, encrypted entries have no words to count and are
+    /// left out when either is set
+    pub min_words: Option<usize>,
+    pub max_words: Option<usize>,
+    /// Only entries with, or without, conflicts
+    pub has_conflict: Option<bool>,
+}
+
+/// Dates of a page, `next_cursor` is `None` on the last page
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DatePage {
+    pub dates: Vec<Date>,
+    pub next_cursor: Option<Date>,
+}
+
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Journal {
+    pub journal_name: StackString,
+    pub created_at: DateTimeWrapper,
+    /// Several users write into shared journals, cached paragraphs are
+    /// stamped with their author on merge
+    #[serde(default)]
+    pub shared: bool,
+}
+
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize)]
 pub struct DiaryEntries {
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
     pub diary_date: Date,
     pub diary_text: StackString,
     pub last_modified: DateTimeWrapper,
+    /// Encrypted entries are only readable client side, `diary_text` is left
+    /// empty and the text is stored in `diary_ciphertext`
+    #[serde(default)]
+    pub is_encrypted: bool,
+    #[serde(default)]
+    pub diary_ciphertext: Option<Vec<u8>>,
+    #[serde(default)]
+    pub diary_nonce: Option<Vec<u8>>,
+    /// Entries in the trash, excluded from searches and sync until restored
+    /// or purged
+    #[serde(default)]
+    pub deleted_at: Option<DateTimeWrapper>,
+    /// Favorite entries, listed on the frontpage
+    #[serde(default)]
+    pub starred: bool,
+    /// Arbitrary key-value pairs (sleep hours, workout, medication), numeric
+    /// values can be aggregated with [`MetadataStats`]
+    #[serde(default = "default_metadata")]
+    pub metadata: Value,
+    /// Letters to the future self, hidden from list and search until
+    /// `diary_date` arrives
+    #[serde(default)]
+    pub scheduled: bool,
+    /// [`PUBLIC_VISIBILITY`] or [`PRIVATE_VISIBILITY`]
+    #[serde(default = "default_visibility")]
+    pub visibility: StackString,
+    /// Pathway of the last write to the text, one of the cache sources,
+    /// [`PEER_SOURCE`], [`MOBILE_SOURCE`] or the [`SyncBackend`] it was
+    /// imported from, unset if unknown
+    #[serde(default)]
+    pub source: Option<StackString>,
 }
 
 #[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize)]
 pub struct DiaryCache {
     pub diary_datetime: DateTimeWrapper,
     pub diary_text: StackString,
+    /// Journal the text is merged into on sync
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    /// Client generated key, retried inserts with the same key return the
+    /// existing row
+    #[serde(default)]
+    pub idempotency_key: Option<Uuid>,
+    /// Email of the user who wrote the text
+    #[serde(default)]
+    pub author: Option<StackString>,
+    /// Pathway the text came in through, [`API_SOURCE`], [`CLI_SOURCE`],
+    /// [`SSH_SOURCE`] or the name of a bot messenger
+    #[serde(default)]
+    pub source: Option<StackString>,
+}
+
+/// Text cached through the web api
+pub const API_SOURCE: &str = "api";
+/// Text cached with the `insert` command
+pub const CLI_SOURCE: &str = "cli";
+/// Text pulled from the cache of the ssh host
+pub const SSH_SOURCE: &str = "ssh";
+/// Entries pulled from a peer server
+pub const PEER_SOURCE: &str = "peer";
+/// Entries uploaded by the mobile sync
+pub const MOBILE_SOURCE: &str = "mobile";
+
+/// Outcome of [`DiaryCache::merge_date`]
+#[derive(Clone, Debug)]
+pub enum CacheMerge {
+    /// The text went to the entry, which is returned, or to the daily file
+    Merged(Option<DiaryEntries>),
+    /// The entry is encrypted or in the trash, the server can't append to
+    /// it so the cache entries are left in place
+    Kept,
+}
+
+/// Text submitted for the cache, `diary_datetime` is set by clients that
+/// wrote the text while offline
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheItem {
+    pub diary_text: StackString,
+    pub diary_datetime: Option<DateTimeWrapper>,
+    pub idempotency_key: Option<Uuid>,
 }
 
 impl PartialEq for DiaryCache {
@@ -37,11 +277,39 @@ impl PartialEq for DiaryCache {
     }
 }
 
+/// What a rendered entry or list of entries depends on, `version` changes
+/// whenever the rendering would, without reading the text
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct EntryVersion {
+    pub last_modified: DateTimeWrapper,
+    pub version: StackString,
+}
+
+/// First and last date and number of the entries of a journal
+#[derive(FromSqlRow, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateSummary {
+    pub min_date: Option<Date>,
+    pub max_date: Option<Date>,
+    pub count: i64,
+}
+
+/// Role of users who can read entries and comment on them but not change
+/// them
+pub const VIEWER_ROLE: &str = "viewer";
+/// Role of users who can change entries and manage the other users
+pub const OWNER_ROLE: &str = "owner";
+
 #[derive(FromSqlRow, Clone, Debug)]
 pub struct AuthorizedUsers {
     pub email: StackString,
     pub telegram_userid: Option<i64>,
+    /// Matrix user id, `@alice:example.com`
+    pub matrix_userid: Option<StackString>,
     pub created_at: OffsetDateTime,
+    /// `owner` or `viewer`
+    pub role: StackString,
+    /// Disabled users can't log in or use the bots
+    pub deleted_at: Option<OffsetDateTime>,
 }
 
 #[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -52,572 +320,3799 @@ pub struct DiaryConflict {
     pub diff_type: StackString,
     pub diff_text: StackString,
     pub sequence: i32,
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    /// Author of the paragraph the lines belong to in shared journals
+    #[serde(default)]
+    pub author: Option<StackString>,
+    /// 1-based number of the first line in the original text, `None` for
+    /// rows recorded before hunks had line numbers
+    #[serde(default)]
+    pub old_line: Option<i32>,
+    /// 1-based number of the first line in the updated text
+    #[serde(default)]
+    pub new_line: Option<i32>,
+    /// Shared by the rows recorded by the same update of an entry
+    #[serde(default)]
+    pub conflict_group: Uuid,
 }
 
-impl AuthorizedUsers {
+/// Last state of an entry acknowledged by a sync client, the
+/// (`server_modified`, `client_version`) pair acts as a two element vector
+/// clock
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncState {
+    pub client_id: StackString,
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub client_version: i64,
+    pub server_modified: DateTimeWrapper,
+    pub hash: StackString,
+    pub last_sync: DateTimeWrapper,
+    /// Set once the client has been told the entry was deleted, its next
+    /// edit of the date is a new entry
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// Marks a deleted date so importers don't re-create the entry from an
+/// older copy, kept until `expires_at`
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiaryTombstone {
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub deleted_at: DateTimeWrapper,
+    pub expires_at: DateTimeWrapper,
+}
+
+/// Per user preferences, `resurface` opts in to the daily resurfaced entry
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserSettings {
+    pub email: StackString,
+    pub resurface: bool,
+    pub last_resurfaced: Option<Date>,
+    pub updated_at: DateTimeWrapper,
+    /// `Locale` code, the configured default when unset
+    pub locale: Option<StackString>,
+}
+
+/// Opted in user who hasn't received today's resurfaced entry yet
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct ResurfaceRecipient {
+    pub email: StackString,
+    pub telegram_userid: Option<i64>,
+    pub role: StackString,
+}
+
+/// Note left on an entry by another user, kept apart from the entry text
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiaryComment {
+    pub id: Uuid,
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub author: StackString,
+    pub comment_text: StackString,
+    pub created_at: DateTimeWrapper,
+    pub updated_at: DateTimeWrapper,
+}
+
+/// Write to the diary recorded in the activity log
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiaryAudit {
+    pub id: Uuid,
+    pub created_at: DateTimeWrapper,
+    /// Unset for writes made by the cli or background jobs
+    pub email: Option<StackString>,
+    pub action: StackString,
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    pub diary_date: Option<Date>,
+    pub detail: StackString,
+    /// Pathway the write came in through, see [`DiaryEntries::source`]
+    #[serde(default)]
+    pub source: Option<StackString>,
+}
+
+/// One-time code a logged in user sends to a bot to link their account on
+/// the messenger
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserLinkCode {
+    pub code: StackString,
+    pub email: StackString,
+    pub expires_at: DateTimeWrapper,
+}
+
+/// Text of an entry as last uploaded to s3, the base of the next delta
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct S3EntryState {
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub uploaded_text: StackString,
+    pub delta_count: i32,
+    pub uploaded_at: DateTimeWrapper,
+}
+
+/// Date of an entry waiting to be uploaded to s3 while the bucket can't be
+/// reached, retried with backoff until the upload succeeds
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct S3Outbox {
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub attempts: i32,
+    pub last_error: Option<StackString>,
+    pub next_attempt_at: DateTimeWrapper,
+    pub created_at: DateTimeWrapper,
+}
+
+/// Lease on a sync held by one process at a time, kept alive by the holder
+/// and free for the taking once `expires_at` passes
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncLease {
+    pub name: StackString,
+    pub holder: StackString,
+    pub acquired_at: DateTimeWrapper,
+    pub expires_at: DateTimeWrapper,
+}
+
+/// Hash of the text a backend and the database last agreed on for a date,
+/// along with when the backend copy was modified at that point
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncWatermark {
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub backend: StackString,
+    pub hash: StackString,
+    pub modified: DateTimeWrapper,
+    pub synced_at: DateTimeWrapper,
+}
+
+/// Encrypted copy of an entry taken before a passage was redacted from it
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiaryRedaction {
+    pub id: Uuid,
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub ciphertext: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub created_at: DateTimeWrapper,
+}
+
+impl Journal {
+    #[must_use]
+    pub fn new(journal_name: impl Into<StackString>) -> Self {
+        Self {
+            journal_name: journal_name.into(),
+            created_at: DateTimeWrapper::now(),
+            shared: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_shared(mut self, shared: bool) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    /// Journal names double as a directory and an s3 key prefix
+    #[must_use]
+    pub fn is_valid_name(journal_name: &str) -> bool {
+        !journal_name.is_empty()
+            && journal_name.len() <= 64
+            && journal_name
+                .chars()
+                .all(|c| char::is_alphanumeric(c) || c == '-' || c == '_')
+    }
+
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_authorized_users(
+    pub async fn get_all(
         pool: &PgPool,
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
-        let query = query!("SELECT * FROM authorized_users WHERE deleted_at IS NULL");
+        let query = query!("SELECT * FROM journals ORDER BY journal_name");
         let conn = pool.get().await?;
         query.fetch_streaming(&conn).await.map_err(Into::into)
     }
 
     /// # Errors
-    /// Returns error if db query fails
-    pub async fn get_most_recent(
-        pool: &PgPool,
-    ) -> Result<(Option<OffsetDateTime>, Option<OffsetDateTime>), Error> {
-        #[derive(FromSqlRow)]
-        struct CreatedDeleted {
-            created_at: Option<OffsetDateTime>,
-            deleted_at: Option<OffsetDateTime>,
-        }
+    /// Return error if db query fails
+    pub async fn get_by_name(journal_name: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM journals WHERE journal_name = $journal_name",
+            journal_name = journal_name
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
 
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
-            "SELECT max(created_at) as created_at, max(deleted_at) as deleted_at FROM \
-             authorized_users"
+            r#"
+                INSERT INTO journals (journal_name, created_at, shared)
+                VALUES ($journal_name, $created_at, $shared)
+                ON CONFLICT (journal_name) DO NOTHING
+            "#,
+            journal_name = self.journal_name,
+            created_at = self.created_at,
+            shared = self.shared,
         );
         let conn = pool.get().await?;
-        let result: Option<CreatedDeleted> = query.fetch_opt(&conn).await?;
-        match result {
-            Some(result) => Ok((result.created_at, result.deleted_at)),
-            None => Ok((None, None)),
-        }
+        query.execute(&conn).await?;
+        Ok(())
     }
 }
 
-impl DiaryConflict {
-    pub fn new(
-        sync_datetime: OffsetDateTime,
-        diary_date: Date,
-        diff_type: impl Into<StackString>,
-        diff_text: impl Into<StackString>,
-        sequence: i32,
-    ) -> Self {
-        Self {
-            id: Uuid::new_v4(),
-            sync_datetime: sync_datetime.into(),
-            diary_date,
-            diff_type: diff_type.into(),
-            diff_text: diff_text.into(),
-            sequence,
-        }
+impl AuthorizedUsers {
+    #[must_use]
+    pub fn is_viewer(&self) -> bool {
+        self.role == VIEWER_ROLE
+    }
+
+    #[must_use]
+    pub fn is_owner(&self) -> bool {
+        self.role == OWNER_ROLE
     }
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_all_dates(
+    pub async fn get_by_email(email: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM authorized_users WHERE email = $email AND deleted_at IS NULL",
+            email = email,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_authorized_users(
         pool: &PgPool,
-    ) -> Result<impl Stream<Item = Result<Date, PqError>>, Error> {
-        let query = query!("SELECT distinct diary_date FROM diary_conflict ORDER BY diary_date");
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!("SELECT * FROM authorized_users WHERE deleted_at IS NULL");
         let conn = pool.get().await?;
-        query
-            .query_streaming(&conn)
-            .await
-            .map(|stream| {
-                stream.and_then(|row| async move {
-                    let date: Date = row.try_get(0).map_err(PqError::BeginTransaction)?;
-                    Ok(date)
-                })
-            })
-            .map_err(Into::into)
+        query.fetch_streaming(&conn).await.map_err(Into::into)
     }
 
+    /// Every user including disabled ones
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_first_date(pool: &PgPool) -> Result<Option<Date>, Error> {
-        let query =
-            query!("SELECT distinct diary_date FROM diary_conflict ORDER BY diary_date LIMIT 1");
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM authorized_users ORDER BY email");
         let conn = pool.get().await?;
-        query
-            .query_opt(&conn)
-            .await
-            .map_err(Into::into)
-            .and_then(|opt| {
-                if let Some(row) = opt {
-                    let date: Date = row.try_get(0)?;
-                    Ok(Some(date))
-                } else {
-                    Ok(None)
-                }
-            })
+        query.fetch(&conn).await.map_err(Into::into)
     }
 
+    /// Add a user or re-enable a disabled one, `created_at` is reset so
+    /// logins notice the change
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_by_date(
-        date: Date,
+    pub async fn upsert(
+        email: &str,
+        role: &str,
+        telegram_userid: Option<i64>,
         pool: &PgPool,
-    ) -> Result<impl Stream<Item = Result<DateTimeWrapper, PqError>>, Error> {
+    ) -> Result<(), Error> {
         let query = query!(
             r#"
-                SELECT distinct sync_datetime
-                FROM diary_conflict
-                WHERE diary_date = $date
-                ORDER BY sync_datetime
+                INSERT INTO authorized_users (email, telegram_userid, role, created_at)
+                VALUES ($email, $telegram_userid, $role, now())
+                ON CONFLICT (email) DO UPDATE
+                SET telegram_userid=$telegram_userid, role=$role, created_at=now(),
+                    deleted_at=NULL
             "#,
-            date = date,
+            email = email,
+            role = role,
+            telegram_userid = telegram_userid,
         );
         let conn = pool.get().await?;
-        query
-            .query_streaming(&conn)
-            .await
-            .map_err(Into::into)
-            .map(|stream| {
-                stream.and_then(|row| async move {
-                    let datetime: DateTimeWrapper =
-                        row.try_get(0).map_err(PqError::BeginTransaction)?;
-                    Ok(datetime)
-                })
-            })
+        query.execute(&conn).await?;
+        Ok(())
     }
 
+    /// Returns false if there is no enabled user with `email`
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_first_by_date(
-        date: Date,
-        pool: &PgPool,
-    ) -> Result<Option<OffsetDateTime>, Error> {
-        #[derive(FromSqlRow, Into)]
-        struct Wrap(OffsetDateTime);
-
+    pub async fn disable(email: &str, pool: &PgPool) -> Result<bool, Error> {
         let query = query!(
             r#"
-                SELECT distinct sync_datetime
-                FROM diary_conflict
-                WHERE diary_date = $date
-                ORDER BY sync_datetime
-                LIMIT 1
+                UPDATE authorized_users SET deleted_at=now()
+                WHERE email = $email AND deleted_at IS NULL
             "#,
-            date = date,
+            email = email,
         );
         let conn = pool.get().await?;
-        let result: Option<Wrap> = query.fetch_opt(&conn).await?;
-        Ok(result.map(Into::into))
+        let rows = query.execute(&conn).await?;
+        Ok(rows > 0)
     }
 
+    /// Returns false if there is no user with `email`
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_by_datetime(
-        datetime: DateTimeWrapper,
+    pub async fn set_matrix_userid(
+        email: &str,
+        matrix_userid: Option<&str>,
         pool: &PgPool,
-    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+    ) -> Result<bool, Error> {
         let query = query!(
-            r#"
-                SELECT * FROM diary_conflict
-                WHERE age(sync_datetime, $datetime)
-                    BETWEEN '-1 second' AND interval '1 second'
-                ORDER BY sync_datetime, sequence
-            "#,
-            datetime = datetime,
+            "UPDATE authorized_users SET matrix_userid=$matrix_userid WHERE email = $email",
+            email = email,
+            matrix_userid = matrix_userid,
         );
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        let rows = query.execute(&conn).await?;
+        Ok(rows > 0)
     }
 
+    /// Returns false if there is no user with `email`
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_first_conflict(pool: &PgPool) -> Result<Option<OffsetDateTime>, Error> {
-        if let Some(first_date) = Self::get_first_date(pool).await? {
-            if let Some(first_conflict) = Self::get_first_by_date(first_date, pool).await? {
-                return Ok(Some(first_conflict));
-            }
-        }
-        Ok(None)
+    pub async fn set_telegram_userid(
+        email: &str,
+        telegram_userid: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<bool, Error> {
+        let query = query!(
+            "UPDATE authorized_users SET telegram_userid=$telegram_userid WHERE email = $email",
+            email = email,
+            telegram_userid = telegram_userid,
+        );
+        let conn = pool.get().await?;
+        let rows = query.execute(&conn).await?;
+        Ok(rows > 0)
     }
 
     /// # Errors
-    /// Return error if db query fails
-    pub async fn update_by_id(
-        id: Uuid,
-        new_diff_type: impl AsRef<str>,
+    /// Returns error if db query fails
+    pub async fn get_most_recent(
         pool: &PgPool,
-    ) -> Result<(), Error> {
+    ) -> Result<(Option<OffsetDateTime>, Option<OffsetDateTime>), Error> {
+        #[derive(FromSqlRow)]
+        struct CreatedDeleted {
+            created_at: Option<OffsetDateTime>,
+            deleted_at: Option<OffsetDateTime>,
+        }
+
+        let query = query!(
+            "SELECT max(created_at) as created_at, max(deleted_at) as deleted_at FROM \
+             authorized_users"
+        );
+        let conn = pool.get().await?;
+        let result: Option<CreatedDeleted> = query.fetch_opt(&conn).await?;
+        match result {
+            Some(result) => Ok((result.created_at, result.deleted_at)),
+            None => Ok((None, None)),
+        }
+    }
+}
+
+impl UserSettings {
+    #[must_use]
+    pub fn new(email: impl Into<StackString>) -> Self {
+        Self {
+            email: email.into(),
+            resurface: false,
+            last_resurfaced: None,
+            updated_at: DateTimeWrapper::now(),
+            locale: None,
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_email(email: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM user_settings WHERE email = $email",
+            email = email
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO user_settings (email, resurface, locale, updated_at)
+                VALUES ($email, $resurface, $locale, now())
+                ON CONFLICT (email) DO UPDATE
+                SET resurface=$resurface, locale=$locale, updated_at=now()
+            "#,
+            email = self.email,
+            resurface = self.resurface,
+            locale = self.locale,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_resurface_recipients(
+        today: Date,
+        pool: &PgPool,
+    ) -> Result<Vec<ResurfaceRecipient>, Error> {
+        let query = query!(
+            r#"
+                SELECT s.email, a.telegram_userid, a.role
+                FROM user_settings s
+                JOIN authorized_users a ON a.email = s.email
+                WHERE s.resurface AND a.deleted_at IS NULL
+                    AND (s.last_resurfaced IS NULL OR s.last_resurfaced < $today)
+            "#,
+            today = today,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn mark_resurfaced(email: &str, today: Date, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "UPDATE user_settings SET last_resurfaced = $today WHERE email = $email",
+            email = email,
+            today = today,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+/// How a conflict is looked up, by the group of its rows or by the time of
+/// the sync which recorded it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKey {
+    Group(Uuid),
+    /// Matches the conflict recorded closest to the time, within a
+    /// millisecond
+    Datetime(DateTimeWrapper),
+}
+
+impl From<Uuid> for ConflictKey {
+    fn from(group: Uuid) -> Self {
+        Self::Group(group)
+    }
+}
+
+impl From<DateTimeWrapper> for ConflictKey {
+    fn from(datetime: DateTimeWrapper) -> Self {
+        Self::Datetime(datetime)
+    }
+}
+
+impl From<OffsetDateTime> for ConflictKey {
+    fn from(datetime: OffsetDateTime) -> Self {
+        Self::Datetime(datetime.into())
+    }
+}
+
+impl fmt::Display for ConflictKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Group(group) => write!(f, "{group}"),
+            Self::Datetime(datetime) => write!(f, "{datetime}"),
+        }
+    }
+}
+
+impl DiaryConflict {
+    pub fn new(
+        journal: impl Into<StackString>,
+        sync_datetime: OffsetDateTime,
+        diary_date: Date,
+        diff_type: impl Into<StackString>,
+        diff_text: impl Into<StackString>,
+        sequence: i32,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            sync_datetime: sync_datetime.into(),
+            diary_date,
+            diff_type: diff_type.into(),
+            diff_text: diff_text.into(),
+            sequence,
+            journal: journal.into(),
+            author: None,
+            old_line: None,
+            new_line: None,
+            conflict_group: Uuid::new_v4(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_group(mut self, conflict_group: Uuid) -> Self {
+        self.conflict_group = conflict_group;
+        self
+    }
+
+    #[must_use]
+    pub fn with_author(mut self, author: Option<StackString>) -> Self {
+        self.author = author;
+        self
+    }
+
+    #[must_use]
+    pub fn with_lines(mut self, old_line: i32, new_line: i32) -> Self {
+        self.old_line = Some(old_line);
+        self.new_line = Some(new_line);
+        self
+    }
+
+    /// Hunks of the rows of one conflict ordered by `sequence`, line numbers
+    /// missing from older rows are counted from the rows before them
+    #[must_use]
+    pub fn hunks(conflicts: &[Self]) -> Vec<DiffHunk> {
+        let (mut old_line, mut new_line) = (1, 1);
+        conflicts
+            .iter()
+            .map(|conflict| {
+                let tag = conflict.diff_type.parse().unwrap_or(DiffTag::Same);
+                if let Some(line) = conflict.old_line {
+                    old_line = line as usize;
+                }
+                if let Some(line) = conflict.new_line {
+                    new_line = line as usize;
+                }
+                let hunk = DiffHunk {
+                    tag,
+                    text: conflict.diff_text.clone(),
+                    old_line,
+                    new_line,
+                };
+                let n = hunk.nlines();
+                match tag {
+                    DiffTag::Same => {
+                        old_line += n;
+                        new_line += n;
+                    }
+                    DiffTag::Rem => old_line += n,
+                    DiffTag::Add => new_line += n,
+                }
+                hunk
+            })
+            .collect()
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all_dates(
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Date, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT distinct diary_date FROM diary_conflict
+                WHERE journal = $journal
+                ORDER BY diary_date
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await
+            .map(|stream| {
+                stream.and_then(|row| async move {
+                    let date: Date = row.try_get(0).map_err(PqError::BeginTransaction)?;
+                    Ok(date)
+                })
+            })
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_first_date(journal: &str, pool: &PgPool) -> Result<Option<Date>, Error> {
+        let query = query!(
+            r#"
+                SELECT distinct diary_date FROM diary_conflict
+                WHERE journal = $journal
+                ORDER BY diary_date
+                LIMIT 1
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_opt(&conn)
+            .await
+            .map_err(Into::into)
+            .and_then(|opt| {
+                if let Some(row) = opt {
+                    let date: Date = row.try_get(0)?;
+                    Ok(Some(date))
+                } else {
+                    Ok(None)
+                }
+            })
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_date(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<DateTimeWrapper, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT distinct sync_datetime
+                FROM diary_conflict
+                WHERE journal = $journal AND diary_date = $date
+                ORDER BY sync_datetime
+            "#,
+            journal = journal,
+            date = date,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await
+            .map_err(Into::into)
+            .map(|stream| {
+                stream.and_then(|row| async move {
+                    let datetime: DateTimeWrapper =
+                        row.try_get(0).map_err(PqError::BeginTransaction)?;
+                    Ok(datetime)
+                })
+            })
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_first_by_date(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<OffsetDateTime>, Error> {
+        #[derive(FromSqlRow, Into)]
+        struct Wrap(OffsetDateTime);
+
+        let query = query!(
+            r#"
+                SELECT distinct sync_datetime
+                FROM diary_conflict
+                WHERE journal = $journal AND diary_date = $date
+                ORDER BY sync_datetime
+                LIMIT 1
+            "#,
+            journal = journal,
+            date = date,
+        );
+        let conn = pool.get().await?;
+        let result: Option<Wrap> = query.fetch_opt(&conn).await?;
+        Ok(result.map(Into::into))
+    }
+
+    /// Rows of one conflict of `journal` ordered by `sequence`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_key(
+        journal: &str,
+        key: impl Into<ConflictKey>,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = match key.into() {
+            ConflictKey::Group(group) => query!(
+                r#"
+                    SELECT * FROM diary_conflict
+                    WHERE conflict_group = $group AND journal = $journal
+                    ORDER BY sequence
+                "#,
+                group = group,
+                journal = journal,
+            ),
+            ConflictKey::Datetime(datetime) => query!(
+                r#"
+                    SELECT * FROM diary_conflict
+                    WHERE journal = $journal AND conflict_group = (
+                        SELECT conflict_group FROM diary_conflict
+                        WHERE journal = $journal
+                            AND sync_datetime
+                                BETWEEN $datetime::timestamptz - interval '1 millisecond'
+                                AND $datetime::timestamptz + interval '1 millisecond'
+                        ORDER BY abs(extract(epoch FROM sync_datetime - $datetime)),
+                            sync_datetime
+                        LIMIT 1
+                    )
+                    ORDER BY sequence
+                "#,
+                datetime = datetime,
+                journal = journal,
+            ),
+        };
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// Conflicts recorded after `since`, ordered so the lines of each
+    /// conflict are consecutive
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_since(
+        journal: &str,
+        since: DateTimeWrapper,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_conflict
+                WHERE journal = $journal AND sync_datetime > $since
+                ORDER BY sync_datetime, sequence
+            "#,
+            journal = journal,
+            since = since,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_first_conflict(
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<Option<OffsetDateTime>, Error> {
+        if let Some(first_date) = Self::get_first_date(journal, pool).await? {
+            if let Some(first_conflict) = Self::get_first_by_date(journal, first_date, pool).await?
+            {
+                return Ok(Some(first_conflict));
+            }
+        }
+        Ok(None)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update_by_id(
+        id: Uuid,
+        new_diff_type: impl AsRef<str>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        Self::update_by_id_conn(id, new_diff_type.as_ref(), &conn).await?;
+        Ok(())
+    }
+
+    async fn update_by_id_conn<C>(id: Uuid, new_diff_type: &str, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            r#"
+                UPDATE diary_conflict
+                SET diff_type = $new_diff_type
+                WHERE id = $id
+            "#,
+            id = id,
+            new_diff_type = new_diff_type,
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn remove_by_key(key: impl Into<ConflictKey>, pool: &PgPool) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        Self::remove_by_key_conn(key.into(), &conn).await?;
+        Ok(())
+    }
+
+    async fn remove_by_key_conn<C>(key: ConflictKey, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = match key {
+            ConflictKey::Group(group) => query!(
+                "DELETE FROM diary_conflict WHERE conflict_group = $group",
+                group = group,
+            ),
+            ConflictKey::Datetime(datetime) => query!(
+                r#"
+                    DELETE FROM diary_conflict
+                    WHERE conflict_group = (
+                        SELECT conflict_group FROM diary_conflict
+                        WHERE sync_datetime
+                            BETWEEN $datetime::timestamptz - interval '1 millisecond'
+                            AND $datetime::timestamptz + interval '1 millisecond'
+                        ORDER BY abs(extract(epoch FROM sync_datetime - $datetime)),
+                            sync_datetime
+                        LIMIT 1
+                    )
+                "#,
+                datetime = datetime,
+            ),
+        };
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// Remove the conflicts of every journal recorded before `cutoff`,
+    /// returns the number of rows removed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete_before(cutoff: OffsetDateTime, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            "DELETE FROM diary_conflict WHERE sync_datetime < $cutoff",
+            cutoff = cutoff
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert_conflict(&self, pool: &PgPool) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        self.insert_conflict_conn(&conn).await
+    }
+
+    async fn insert_conflict_conn<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            r#"
+                INSERT INTO diary_conflict (
+                    id, sync_datetime, diary_date, diff_type, diff_text, sequence, journal,
+                    author, old_line, new_line, conflict_group
+                ) VALUES (
+                    $id, $sync_datetime, $diary_date, $diff_type, $diff_text, $sequence,
+                    $journal, $author, $old_line, $new_line, $conflict_group
+                )
+            "#,
+            id = self.id,
+            conflict_group = self.conflict_group,
+            journal = self.journal,
+            author = self.author,
+            sync_datetime = self.sync_datetime,
+            diary_date = self.diary_date,
+            diff_type = self.diff_type,
+            diff_text = self.diff_text,
+            sequence = self.sequence,
+            old_line = self.old_line,
+            new_line = self.new_line,
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// One conflict row per hunk of `diff` sharing a new `conflict_group`,
+    /// the `same` and `rem` rows make up the original text and the `same`
+    /// and `add` rows the new one
+    #[must_use]
+    pub fn from_diff(
+        journal: &str,
+        diary_date: Date,
+        sync_datetime: OffsetDateTime,
+        diff: LineDiff,
+    ) -> Vec<Self> {
+        let authors = diff_authors(&diff.hunks);
+        let conflict_group = Uuid::new_v4();
+        diff.hunks
+            .into_iter()
+            .zip(authors)
+            .enumerate()
+            .map(|(sequence, (hunk, author))| {
+                DiaryConflict::new(
+                    journal,
+                    sync_datetime,
+                    diary_date,
+                    hunk.tag.to_str(),
+                    hunk.text,
+                    sequence as i32,
+                )
+                .with_group(conflict_group)
+                .with_author(author)
+                .with_lines(hunk.old_line as i32, hunk.new_line as i32)
+            })
+            .collect()
+    }
+
+    async fn insert_from_diff<C>(
+        journal: &str,
+        diary_date: Date,
+        diff: LineDiff,
+        conn: &C,
+    ) -> Result<Option<OffsetDateTime>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let sync_datetime = OffsetDateTime::now_utc();
+        let removed_lines = Self::from_diff(journal, diary_date, sync_datetime, diff);
+
+        let n_removed_lines: usize = removed_lines
+            .iter()
+            .filter(|x| &x.diff_type == "rem")
+            .count();
+
+        if n_removed_lines > 0 {
+            debug!("update_entry {:?}", removed_lines);
+            debug!("difference {}", n_removed_lines);
+            for conflict in &removed_lines {
+                conflict.insert_conflict_conn(conn).await?;
+            }
+            Ok(Some(sync_datetime))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl DiaryEntries {
+    pub fn new(diary_date: Date, diary_text: impl Into<StackString>) -> Self {
+        Self {
+            journal: default_journal(),
+            diary_date,
+            diary_text: diary_text.into(),
+            last_modified: DateTimeWrapper::now(),
+            is_encrypted: false,
+            diary_ciphertext: None,
+            diary_nonce: None,
+            deleted_at: None,
+            starred: false,
+            metadata: default_metadata(),
+            scheduled: false,
+            visibility: default_visibility(),
+            source: None,
+        }
+    }
+
+    /// Entry encrypted client side, the server never sees the plaintext
+    #[must_use]
+    pub fn new_encrypted(diary_date: Date, ciphertext: Vec<u8>, nonce: Vec<u8>) -> Self {
+        Self {
+            journal: default_journal(),
+            diary_date,
+            diary_text: StackString::new(),
+            last_modified: DateTimeWrapper::now(),
+            is_encrypted: true,
+            diary_ciphertext: Some(ciphertext),
+            diary_nonce: Some(nonce),
+            deleted_at: None,
+            starred: false,
+            metadata: default_metadata(),
+            scheduled: false,
+            visibility: default_visibility(),
+            source: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_journal(mut self, journal: impl Into<StackString>) -> Self {
+        self.journal = journal.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_source(mut self, source: Option<impl Into<StackString>>) -> Self {
+        self.source = source.map(Into::into);
+        self
+    }
+
+    /// Scheduled entries only become visible once `today` reaches their date
+    #[must_use]
+    pub fn is_visible(&self, today: Date) -> bool {
+        !self.scheduled || self.diary_date <= today
+    }
+
+    #[must_use]
+    pub fn is_private(&self) -> bool {
+        self.visibility == PRIVATE_VISIBILITY
+    }
+
+    /// Sha256 of the entry text (or ciphertext for encrypted entries), used
+    /// to compare entries between peers
+    #[must_use]
+    pub fn get_hash(&self) -> StackString {
+        let mut hasher = Sha256::new();
+        if self.is_encrypted {
+            if let Some(nonce) = &self.diary_nonce {
+                hasher.update(nonce);
+            }
+            if let Some(ciphertext) = &self.diary_ciphertext {
+                hasher.update(ciphertext);
+            }
+        } else {
+            hasher.update(self.diary_text.as_bytes());
+        }
+        format_sstr!("{:x}", hasher.finalize())
+    }
+
+    /// The entry with its text as it is stored, see `normalize_text`,
+    /// encrypted entries are left alone
+    #[must_use]
+    pub fn normalized(&self) -> Cow<'_, Self> {
+        if self.is_encrypted || is_normalized(&self.diary_text) {
+            Cow::Borrowed(self)
+        } else {
+            let mut entry = self.clone();
+            entry.diary_text = normalize_text(&self.diary_text);
+            Cow::Owned(entry)
+        }
+    }
+
+    async fn insert_entry_impl<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let entry = self.normalized();
+        let query = query!(
+            r#"
+                INSERT INTO diary_entries (
+                    journal, diary_date, diary_text, last_modified, is_encrypted,
+                    diary_ciphertext, diary_nonce, starred, metadata, scheduled, visibility,
+                    source
+                )
+                VALUES (
+                    $journal, $diary_date, $diary_text, now(), $is_encrypted,
+                    $diary_ciphertext, $diary_nonce, $starred, $metadata, $scheduled, $visibility,
+                    $source
+                )
+            "#,
+            journal = entry.journal,
+            diary_date = entry.diary_date,
+            diary_text = entry.diary_text,
+            is_encrypted = entry.is_encrypted,
+            diary_ciphertext = entry.diary_ciphertext,
+            diary_nonce = entry.diary_nonce,
+            starred = entry.starred,
+            metadata = entry.metadata,
+            scheduled = entry.scheduled,
+            visibility = entry.visibility,
+            source = entry.source,
+        );
+        query.execute(conn).await?;
+        DiaryTerm::replace_impl(&entry.journal, entry.diary_date, &entry.diary_text, conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert_entry(&self, pool: &PgPool) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        self.insert_entry_impl(&conn).await?;
+        Ok(())
+    }
+
+    async fn update_entry_impl<C>(
+        &self,
+        conn: &C,
+        insert_new: bool,
+    ) -> Result<Option<OffsetDateTime>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let original = Self::_get_by_date(&self.journal, self.diary_date, conn)
+            .await?
+            .ok_or_else(|| format_err!("Not found"))?;
+        if self.is_encrypted && original.deleted_at.is_none() {
+            if insert_new {
+                self.update_encrypted_impl(conn).await?;
+            }
+            return Ok(None);
+        }
+        if original.deleted_at.is_some() {
+            debug!("not replacing deleted entry {}", self.diary_date);
+            return Ok(None);
+        }
+        if original.is_encrypted {
+            debug!("not replacing encrypted entry {}", self.diary_date);
+            return Ok(None);
+        }
+        let entry = self.normalized();
+        let diff = entry.get_diff(&original, insert_new);
+
+        let conflict_opt = if diff.is_changed() {
+            DiaryConflict::insert_from_diff(&entry.journal, entry.diary_date, diff, conn).await?
+        } else {
+            None
+        };
+
+        if insert_new {
+            let query = query!(
+                r#"
+                    UPDATE diary_entries
+                    SET diary_text=$diary_text,last_modified=now(),source=$source
+                    WHERE journal = $journal AND diary_date = $diary_date
+                "#,
+                journal = entry.journal,
+                diary_date = entry.diary_date,
+                diary_text = entry.diary_text,
+                source = entry.source,
+            );
+            query.execute(conn).await?;
+            DiaryTerm::replace_impl(&entry.journal, entry.diary_date, &entry.diary_text, conn)
+                .await?;
+            Ok(conflict_opt)
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn update_encrypted_impl<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET diary_text='',
+                    is_encrypted=true,
+                    diary_ciphertext=$diary_ciphertext,
+                    diary_nonce=$diary_nonce,
+                    last_modified=now(),
+                    source=$source
+                WHERE journal = $journal AND diary_date = $diary_date
+            "#,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            diary_ciphertext = self.diary_ciphertext,
+            diary_nonce = self.diary_nonce,
+            source = self.source,
+        );
+        query.execute(conn).await?;
+        DiaryTerm::replace_impl(&self.journal, self.diary_date, "", conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update_entry(
+        &self,
+        pool: &PgPool,
+        insert_new: bool,
+    ) -> Result<Option<OffsetDateTime>, Error> {
+        let conn = pool.get().await?;
+        self.update_entry_impl(&conn, insert_new)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert_entry(
+        &self,
+        pool: &PgPool,
+        insert_new: bool,
+    ) -> Result<Option<OffsetDateTime>, Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        let output = self.upsert_entry_impl(conn, insert_new).await?;
+        tran.commit().await?;
+        Ok(output)
+    }
+
+    async fn upsert_entry_impl<C>(
+        &self,
+        conn: &C,
+        insert_new: bool,
+    ) -> Result<Option<OffsetDateTime>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let existing = Self::_get_by_date(&self.journal, self.diary_date, conn).await?;
+        if existing.is_some() {
+            self.update_entry_impl(conn, insert_new).await
+        } else if DiaryTombstone::_get_by_date(&self.journal, self.diary_date, conn)
+            .await?
+            .is_some_and(|t| self.last_modified <= t.deleted_at)
+        {
+            debug!("not re-creating deleted entry {}", self.diary_date);
+            Ok(None)
+        } else {
+            self.insert_entry_impl(conn).await?;
+            DiaryTombstone::_remove(&self.journal, self.diary_date, conn).await?;
+            Ok(None)
+        }
+    }
+
+    /// Store the redacted text of the entry, bypassing conflict detection so
+    /// the original text isn't kept as a diff, matches of `regex` in the
+    /// conflicts of the date are replaced as well
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn replace_redacted(
+        &self,
+        redaction: Option<&DiaryRedaction>,
+        regex: &Regex,
+        placeholder: &str,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        if let Some(redaction) = redaction {
+            redaction.insert_impl(conn).await?;
+        }
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET diary_text=$diary_text,last_modified=now(),source=$source
+                WHERE journal = $journal AND diary_date = $diary_date AND NOT is_encrypted
+            "#,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            diary_text = self.diary_text,
+            source = self.source,
+        );
+        if query.execute(conn).await? > 0 {
+            DiaryTerm::replace_impl(&self.journal, self.diary_date, &self.diary_text, conn).await?;
+        }
+        let query = query!(
+            "SELECT * FROM diary_conflict WHERE journal = $journal AND diary_date = $diary_date",
+            journal = self.journal,
+            diary_date = self.diary_date,
+        );
+        let conflicts: Vec<DiaryConflict> = query.fetch(conn).await?;
+        for conflict in conflicts {
+            if let Some(diff_text) = redact_text(&conflict.diff_text, regex, placeholder) {
+                let query = query!(
+                    "UPDATE diary_conflict SET diff_text = $diff_text WHERE id = $id",
+                    id = conflict.id,
+                    diff_text = diff_text,
+                );
+                query.execute(conn).await?;
+            }
+        }
+        let subentries =
+            DiarySubentry::get_by_date_impl(&self.journal, self.diary_date, conn).await?;
+        for subentry in subentries {
+            if let Some(diary_text) = redact_text(&subentry.diary_text, regex, placeholder) {
+                let query = query!(
+                    r#"
+                        UPDATE diary_subentries SET diary_text = $diary_text
+                        WHERE journal = $journal AND diary_datetime = $diary_datetime
+                    "#,
+                    journal = subentry.journal,
+                    diary_datetime = subentry.diary_datetime,
+                    diary_text = diary_text,
+                );
+                query.execute(conn).await?;
+            }
+        }
+        tran.commit().await?;
+        Ok(())
+    }
+
+    /// Append `text` to the entry for `date` in a single statement, the entry
+    /// is created if it doesn't exist yet
+    /// # Errors
+    /// Return error if the entry is encrypted or in the trash, or db query
+    /// fails
+    pub async fn append_text(
+        journal: &str,
+        date: Date,
+        text: &str,
+        separator: &str,
+        source: Option<&str>,
+        max_length: usize,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO diary_entries (journal, diary_date, diary_text, last_modified, source)
+                VALUES ($journal, $date, $text, now(), $source)
+                ON CONFLICT (journal, diary_date) DO UPDATE
+                SET diary_text = CASE
+                        WHEN diary_entries.diary_text = '' THEN EXCLUDED.diary_text
+                        ELSE diary_entries.diary_text || $separator || EXCLUDED.diary_text
+                    END,
+                    last_modified = now(),
+                    source = EXCLUDED.source
+                WHERE NOT diary_entries.is_encrypted AND diary_entries.deleted_at IS NULL
+                RETURNING *
+            "#,
+            journal = journal,
+            date = date,
+            text = text,
+            separator = separator,
+            source = source,
+        );
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        let entry: Self = query.fetch_opt(conn).await?.ok_or_else(|| {
+            format_err!("Cannot append to {date}, entry is encrypted or in the trash")
+        })?;
+        // dropping the transaction rolls the append back
+        check_length(&entry.diary_text, max_length)?;
+        DiaryTerm::replace_impl(journal, date, &entry.diary_text, conn).await?;
+        tran.commit().await?;
+        Ok(entry)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_modified_map(
+        journal: &str,
+        pool: &PgPool,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+    ) -> Result<HashMap<Date, OffsetDateTime>, Error> {
+        let mut constraints = vec!["deleted_at IS NULL", "journal = $journal"];
+        let mut bindings: Vec<(&str, Parameter)> = vec![("journal", &journal)];
+        date_range_bindings(&min_date, &max_date, &mut constraints, &mut bindings);
+        let query = format_sstr!(
+            "SELECT diary_date, last_modified FROM diary_entries WHERE {}",
+            constraints.join(" AND ")
+        );
+        let query = query_dyn!(&query, ..bindings)?;
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await?
+            .map_err(Into::into)
+            .and_then(|row| async move {
+                let diary_date: Date = row.try_get("diary_date")?;
+                let last_modified: OffsetDateTime = row.try_get("last_modified")?;
+                Ok((diary_date, last_modified))
+            })
+            .try_collect()
+            .await
+    }
+
+    async fn _get_by_date<C>(journal: &str, date: Date, conn: &C) -> Result<Option<Self>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            "SELECT * FROM diary_entries WHERE journal = $journal AND diary_date = $date",
+            journal = journal,
+            date = date
+        );
+        query.fetch_opt(conn).await.map_err(Into::into)
+    }
+
+    /// Entries in the trash are not returned
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_date(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let conn = pool.get().await?;
+        Self::_get_by_date(journal, date, &conn)
+            .await
+            .map(|entry| entry.filter(|e| e.deleted_at.is_none()))
+    }
+
+    /// Version of the entry at `date` and its comments, `None` when there's
+    /// no entry or it's in the trash
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_version(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<EntryVersion>, Error> {
+        let query = query!(
+            r#"
+                SELECT e.last_modified,
+                    concat_ws(
+                        '|', e.last_modified, e.starred, e.visibility, e.metadata,
+                        (
+                            SELECT count(*) || '-' || coalesce(max(c.updated_at)::text, '')
+                            FROM diary_comments c
+                            WHERE c.journal = e.journal AND c.diary_date = e.diary_date
+                        )
+                    ) AS version
+                FROM diary_entries e
+                WHERE e.journal = $journal AND e.diary_date = $date AND e.deleted_at IS NULL
+            "#,
+            journal = journal,
+            date = date,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// Version of the dates, stars, visibility and conflicts of the journal,
+    /// `None` for an empty journal
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_journal_version(
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<Option<EntryVersion>, Error> {
+        let query = query!(
+            r#"
+                SELECT max(e.last_modified) AS last_modified,
+                    concat_ws(
+                        '|',
+                        md5(string_agg(
+                            e.diary_date || e.starred::text || e.visibility || e.scheduled::text,
+                            ',' ORDER BY e.diary_date
+                        )),
+                        max(e.last_modified),
+                        (
+                            SELECT count(*) || '-' || coalesce(max(c.sync_datetime)::text, '')
+                            FROM diary_conflict c
+                            WHERE c.journal = $journal
+                        )
+                    ) AS version
+                FROM diary_entries e
+                WHERE e.journal = $journal AND e.deleted_at IS NULL
+                HAVING count(*) > 0
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// Entries of every date in `dates` in a single query, ordered by date,
+    /// entries in the trash are not returned
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_dates(
+        journal: &str,
+        dates: &[Date],
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_entries
+                WHERE journal = $journal
+                    AND diary_date = ANY($dates)
+                    AND deleted_at IS NULL
+                ORDER BY diary_date
+            "#,
+            journal = journal,
+            dates = dates,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_entries
+                WHERE journal = $journal AND deleted_at IS NULL
+                ORDER BY diary_date
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// Most recent starred entries first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_starred(
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_entries
+                WHERE journal = $journal AND starred AND deleted_at IS NULL
+                ORDER BY diary_date DESC
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// Flip the starred flag, returns the new value or `None` if there is no
+    /// entry for `date`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn toggle_starred(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<bool>, Error> {
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET starred = NOT starred
+                WHERE journal = $journal AND diary_date = $date AND deleted_at IS NULL
+                RETURNING starred
+            "#,
+            journal = journal,
+            date = date
+        );
+        let conn = pool.get().await?;
+        let row = query.query_opt(&conn).await?;
+        row.map(|row| row.try_get("starred"))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Flip the entry between public and private, returns whether it is now
+    /// private or `None` if there is no entry for `date`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn toggle_private(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<bool>, Error> {
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET visibility = CASE WHEN visibility = $private THEN $public ELSE $private END
+                WHERE journal = $journal AND diary_date = $date AND deleted_at IS NULL
+                RETURNING visibility
+            "#,
+            journal = journal,
+            date = date,
+            private = PRIVATE_VISIBILITY,
+            public = PUBLIC_VISIBILITY,
+        );
+        let conn = pool.get().await?;
+        let row = query.query_opt(&conn).await?;
+        row.map(|row| {
+            row.try_get::<_, &str>("visibility")
+                .map(|visibility| visibility == PRIVATE_VISIBILITY)
+        })
+        .transpose()
+        .map_err(Into::into)
+    }
+
+    /// Dates of entries hidden from viewers
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_private_dates(journal: &str, pool: &PgPool) -> Result<HashSet<Date>, Error> {
+        let query = query!(
+            r#"
+                SELECT diary_date FROM diary_entries
+                WHERE journal = $journal AND visibility = $private AND deleted_at IS NULL
+            "#,
+            journal = journal,
+            private = PRIVATE_VISIBILITY,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await?
+            .and_then(|row| async move {
+                let date: Date = row.try_get(0).map_err(PqError::BeginTransaction)?;
+                Ok(date)
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Dates of the entries shown on `today`, leaving out scheduled entries
+    /// which are still hidden and private entries if `hide_private` is set
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_date_summary(
+        journal: &str,
+        today: Date,
+        hide_private: bool,
+        pool: &PgPool,
+    ) -> Result<DateSummary, Error> {
+        let query = query!(
+            r#"
+                SELECT min(diary_date) AS min_date, max(diary_date) AS max_date,
+                    count(*) AS count
+                FROM diary_entries
+                WHERE journal = $journal AND deleted_at IS NULL
+                    AND NOT (scheduled AND diary_date > $today)
+                    AND NOT ($hide_private AND visibility = $private)
+            "#,
+            journal = journal,
+            today = today,
+            hide_private = hide_private,
+            private = PRIVATE_VISIBILITY,
+        );
+        let conn = pool.get().await?;
+        query.fetch_one(&conn).await.map_err(Into::into)
+    }
+
+    /// Dates of scheduled entries which are still hidden on `today`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_hidden_dates(
+        journal: &str,
+        today: Date,
+        pool: &PgPool,
+    ) -> Result<HashSet<Date>, Error> {
+        let query = query!(
+            r#"
+                SELECT diary_date FROM diary_entries
+                WHERE journal = $journal AND scheduled AND diary_date > $today
+                    AND deleted_at IS NULL
+            "#,
+            journal = journal,
+            today = today,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await?
+            .and_then(|row| async move {
+                let date: Date = row.try_get(0).map_err(PqError::BeginTransaction)?;
+                Ok(date)
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Dates of the entries matching `query`, leaving out scheduled entries
+    /// until `today` reaches them and private entries if `hide_private` is
+    /// set
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_visible_dates(
+        journal: &str,
+        query: DateListQuery,
+        today: Date,
+        hide_private: bool,
+        pool: &PgPool,
+    ) -> Result<Vec<Date>, Error> {
+        let offset = query.offset.map(|offset| offset as i64);
+        let limit = query.limit.map(|limit| limit as i64);
+        let min_words = query.min_words.map(|words| words as i64);
+        let max_words = query.max_words.map(|words| words as i64);
+        // words are only counted when a bound asks for them
+        let query = query!(
+            r#"
+                SELECT e.diary_date FROM diary_entries e
+                WHERE e.journal = $journal
+                    AND e.deleted_at IS NULL
+                    AND NOT (e.scheduled AND e.diary_date > $today)
+                    AND NOT ($hide_private AND e.visibility = $private)
+                    AND (e.starred OR NOT $starred)
+                    AND e.diary_date >= COALESCE($since::date, e.diary_date)
+                    AND e.diary_date <= COALESCE($until::date, e.diary_date)
+                    AND CASE WHEN $ascending
+                        THEN e.diary_date > COALESCE($cursor::date, e.diary_date - 1)
+                        ELSE e.diary_date < COALESCE($cursor::date, e.diary_date + 1)
+                    END
+                    AND CASE WHEN $min_words::bigint IS NULL AND $max_words::bigint IS NULL
+                        THEN true
+                        ELSE NOT e.is_encrypted AND (
+                            SELECT count(*) BETWEEN COALESCE($min_words::bigint, 0)
+                                AND COALESCE($max_words::bigint, count(*))
+                            FROM regexp_matches(e.diary_text, '\S+', 'g')
+                        )
+                    END
+                    AND COALESCE(
+                        $has_conflict::bool = EXISTS(
+                            SELECT 1 FROM diary_conflict c
+                            WHERE c.journal = e.journal AND c.diary_date = e.diary_date
+                        ),
+                        true
+                    )
+                ORDER BY CASE WHEN $ascending THEN e.diary_date END, e.diary_date DESC
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            journal = journal,
+            today = today,
+            hide_private = hide_private,
+            private = PRIVATE_VISIBILITY,
+            starred = query.starred,
+            since = query.range.since,
+            until = query.range.until,
+            ascending = query.order == SortOrder::Asc,
+            cursor = query.cursor,
+            min_words = min_words,
+            max_words = max_words,
+            has_conflict = query.has_conflict,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await?
+            .and_then(|row| async move {
+                let date: Date = row.try_get(0).map_err(PqError::BeginTransaction)?;
+                Ok(date)
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Clear the scheduled flag of every entry whose date has arrived,
+    /// returning the released entries
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn release_scheduled(today: Date, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET scheduled = false
+                WHERE scheduled AND diary_date <= $today AND deleted_at IS NULL
+                RETURNING *
+            "#,
+            today = today,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// Merge the keys of `patch` into the metadata of the entry, keys set to
+    /// null are removed, returns the new metadata or `None` if there is no
+    /// entry for `date`
+    /// # Errors
+    /// Return error if `patch` is not an object or db query fails
+    pub async fn update_metadata(
+        journal: &str,
+        date: Date,
+        patch: &Value,
+        pool: &PgPool,
+    ) -> Result<Option<Value>, Error> {
+        if !patch.is_object() {
+            return Err(format_err!("Metadata must be an object"));
+        }
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET metadata = jsonb_strip_nulls(metadata || $patch)
+                WHERE journal = $journal AND diary_date = $date AND deleted_at IS NULL
+                RETURNING metadata
+            "#,
+            journal = journal,
+            date = date,
+            patch = patch,
+        );
+        let conn = pool.get().await?;
+        let row = query.query_opt(&conn).await?;
+        row.map(|row| row.try_get("metadata"))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Append the timestamps of merged cache entries to the `written_at`
+    /// metadata of the entry, does nothing if there is no entry for `date`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn append_written_at(
+        journal: &str,
+        date: Date,
+        times: &[DateTimeWrapper],
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        Self::append_written_at_impl(journal, date, times, &conn).await
+    }
+
+    async fn append_written_at_impl<C>(
+        journal: &str,
+        date: Date,
+        times: &[DateTimeWrapper],
+        conn: &C,
+    ) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let times = serde_json::to_value(times)?;
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET metadata = jsonb_set(
+                    metadata,
+                    ARRAY[$key::text],
+                    COALESCE(metadata->$key, '[]'::jsonb) || $times
+                )
+                WHERE journal = $journal AND diary_date = $date AND deleted_at IS NULL
+            "#,
+            key = WRITTEN_AT_KEY,
+            times = times,
+            journal = journal,
+            date = date,
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_encrypted(
+        journal: &str,
+        pool: &PgPool,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let mut constraints = vec!["is_encrypted", "deleted_at IS NULL", "journal = $journal"];
+        let mut bindings: Vec<(&str, Parameter)> = vec![("journal", &journal)];
+        date_range_bindings(&min_date, &max_date, &mut constraints, &mut bindings);
+        let query = format_sstr!(
+            "SELECT * FROM diary_entries WHERE {} ORDER BY diary_date",
+            constraints.join(" AND ")
+        );
+        let query = query_dyn!(&query, ..bindings)?;
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_text(
+        journal: &str,
+        search_text: impl AsRef<str>,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let pattern = like_pattern(search_text.as_ref());
+        let query = query!(
+            r#"
+                SELECT * FROM diary_entries
+                WHERE journal = $journal AND diary_text LIKE $pattern
+                    AND deleted_at IS NULL
+                ORDER BY diary_date
+            "#,
+            journal = journal,
+            pattern = pattern,
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// Entries stored before their text was normalized only differ from it
+    /// if the normalized texts do
+    fn get_diff(&self, original: &Self, insert_new: bool) -> LineDiff {
+        let original = normalize_text(&original.diary_text);
+        let text = normalize_text(&self.diary_text);
+        if insert_new {
+            LineDiff::new(&original, &text)
+        } else {
+            LineDiff::new(&text, &original)
+        }
+    }
+
+    async fn get_difference_impl<C>(
+        &self,
+        conn: &C,
+        insert_new: bool,
+    ) -> Result<Option<LineDiff>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        Self::_get_by_date(&self.journal, self.diary_date, conn)
+            .await
+            .map(|opt| opt.map(|original| self.get_diff(&original, insert_new)))
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_difference(&self, pool: &PgPool) -> Result<Option<LineDiff>, Error> {
+        let conn = pool.get().await?;
+        self.get_difference_impl(&conn, true)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Move the entry to the trash, use `purge_entry` to remove it
+    /// permanently
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete_entry(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET deleted_at=now()
+                WHERE journal = $journal AND diary_date = $diary_date AND deleted_at IS NULL
+            "#,
+            journal = self.journal,
+            diary_date = self.diary_date
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn restore_entry(journal: &str, date: Date, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                UPDATE diary_entries
+                SET deleted_at=null,last_modified=now()
+                WHERE journal = $journal AND diary_date = $date AND deleted_at IS NOT NULL
+            "#,
+            journal = journal,
+            date = date
+        );
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        let restored = query.execute(conn).await?;
+        if restored > 0 {
+            DiaryTombstone::_remove(journal, date, conn).await?;
+        }
+        tran.commit().await?;
+        Ok(restored)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn purge_entry(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "DELETE FROM diary_entries WHERE journal = $journal AND diary_date = $diary_date",
+            journal = self.journal,
+            diary_date = self.diary_date
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_deleted(
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_entries
+                WHERE journal = $journal AND deleted_at IS NOT NULL
+                ORDER BY diary_date
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// Permanently remove entries which have been in the trash since before
+    /// `cutoff`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn purge_deleted(cutoff: OffsetDateTime, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            "DELETE FROM diary_entries WHERE deleted_at < $cutoff",
+            cutoff = cutoff
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+}
+
+impl DiaryCache {
+    async fn insert_entry_impl<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            r#"
+                INSERT INTO diary_cache (
+                    diary_datetime, diary_text, journal, idempotency_key, author, source
+                )
+                VALUES (
+                    $diary_datetime, $diary_text, $journal, $idempotency_key, $author, $source
+                )
+            "#,
+            diary_datetime = self.diary_datetime,
+            diary_text = self.diary_text,
+            journal = self.journal,
+            idempotency_key = self.idempotency_key,
+            author = self.author,
+            source = self.source,
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert_entry(&self, pool: &PgPool) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        self.insert_entry_impl(&conn).await
+    }
+
+    /// Insert all `entries` in one transaction, each entry is inserted under
+    /// its own savepoint so that a failing entry doesn't abort the others.
+    /// Entries repeating an idempotency key return the existing row
+    /// # Errors
+    /// Return error if the transaction fails
+    pub async fn insert_batch(
+        entries: Vec<Self>,
+        pool: &PgPool,
+    ) -> Result<Vec<Result<Self, Error>>, Error> {
+        let mut conn = pool.get().await?;
+        let mut tran = conn.transaction().await?;
+        let mut output = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let savepoint = tran.transaction().await?;
+            let result = entry.insert_or_get_impl(&savepoint).await;
+            if result.is_ok() {
+                savepoint.commit().await?;
+            } else {
+                savepoint.rollback().await?;
+            }
+            output.push(result);
+        }
+        tran.commit().await?;
+        Ok(output)
+    }
+
+    async fn insert_or_get_impl<C>(self, conn: &C) -> Result<Self, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        if let Some(key) = self.idempotency_key {
+            if let Some(existing) = Self::_get_by_idempotency_key(key, conn).await? {
+                return Ok(existing);
+            }
+        }
+        self.insert_entry_impl(conn).await?;
+        Ok(self)
+    }
+
+    async fn _get_by_idempotency_key<C>(
+        idempotency_key: Uuid,
+        conn: &C,
+    ) -> Result<Option<Self>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            "SELECT * FROM diary_cache WHERE idempotency_key = $idempotency_key",
+            idempotency_key = idempotency_key,
+        );
+        query.fetch_opt(conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_idempotency_key(
+        idempotency_key: Uuid,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let conn = pool.get().await?;
+        Self::_get_by_idempotency_key(idempotency_key, &conn).await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_cache_entries(
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!("SELECT * FROM diary_cache");
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_journal(journal: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM diary_cache WHERE journal = $journal ORDER BY diary_datetime",
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_text(
+        journal: &str,
+        search_text: impl AsRef<str>,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let pattern = like_pattern(search_text.as_ref());
+        let query = query!(
+            r#"
+                SELECT * FROM diary_cache
+                WHERE journal = $journal AND diary_text LIKE $pattern
+            "#,
+            journal = journal,
+            pattern = pattern,
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete_entry(&self, pool: &PgPool) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        self.delete_entry_impl(&conn).await
+    }
+
+    async fn delete_entry_impl<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            "DELETE FROM diary_cache WHERE diary_datetime = $diary_datetime",
+            diary_datetime = self.diary_datetime
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// Merge `cache`, the cache entries of `date` joined into `text`, in a
+    /// single transaction: the text is appended to the entry of `date`, or
+    /// recorded as `pending` when it goes to the daily file instead, and the
+    /// cache entries are kept as subentries and removed.  Nothing changes
+    /// when the entry is encrypted or in the trash, the cache is merged once
+    /// it is restored.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn merge_date(
+        journal: &str,
+        date: Date,
+        cache: &[Self],
+        text: &str,
+        source: Option<StackString>,
+        pending: Option<&DiaryPendingAppend>,
+        pool: &PgPool,
+    ) -> Result<CacheMerge, Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        let existing = DiaryEntries::_get_by_date(journal, date, conn).await?;
+        if existing
+            .as_ref()
+            .is_some_and(|e| e.is_encrypted || e.deleted_at.is_some())
+        {
+            return Ok(CacheMerge::Kept);
+        }
+        let entry = if let Some(pending) = pending {
+            pending.insert_impl(conn).await?;
+            None
+        } else {
+            let mut entry = match existing {
+                Some(mut entry) => {
+                    entry.diary_text = format_sstr!("{t}\n\n{text}", t = entry.diary_text);
+                    entry
+                }
+                None => DiaryEntries::new(date, text).with_journal(journal),
+            };
+            entry.source = source;
+            entry.upsert_entry_impl(conn, true).await?;
+            Some(entry)
+        };
+        let times: Vec<_> = cache.iter().map(|e| e.diary_datetime).collect();
+        DiaryEntries::append_written_at_impl(journal, date, &times, conn).await?;
+        for item in cache {
+            DiarySubentry::from_cache(item, date)
+                .insert_impl(conn)
+                .await?;
+            item.delete_entry_impl(conn).await?;
+        }
+        tran.commit().await?;
+        Ok(CacheMerge::Merged(entry))
+    }
+}
+
+impl SyncState {
+    pub fn new(
+        client_id: impl Into<StackString>,
+        journal: impl Into<StackString>,
+        diary_date: Date,
+        client_version: i64,
+        server_modified: DateTimeWrapper,
+        hash: impl Into<StackString>,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            journal: journal.into(),
+            diary_date,
+            client_version,
+            server_modified,
+            hash: hash.into(),
+            last_sync: DateTimeWrapper::now(),
+            deleted: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_deleted(mut self, deleted: bool) -> Self {
+        self.deleted = deleted;
+        self
+    }
+
+    /// The client and the server are still where they were at this sync
+    #[must_use]
+    pub fn matches(
+        &self,
+        client_version: i64,
+        server_modified: DateTimeWrapper,
+        hash: &str,
+    ) -> bool {
+        self.client_version == client_version
+            && self.server_modified == server_modified
+            && self.hash == hash
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_client(
+        client_id: &str,
+        journal: &str,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            "SELECT * FROM sync_state WHERE client_id = $client_id AND journal = $journal",
+            client_id = client_id,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert_state(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO sync_state (
+                    client_id, journal, diary_date, client_version, server_modified, hash,
+                    last_sync, deleted
+                )
+                VALUES (
+                    $client_id, $journal, $diary_date, $client_version, $server_modified,
+                    $hash, now(), $deleted
+                )
+                ON CONFLICT (client_id, journal, diary_date) DO UPDATE
+                SET client_version=$client_version,
+                    server_modified=$server_modified,
+                    hash=$hash,
+                    last_sync=now(),
+                    deleted=$deleted
+            "#,
+            client_id = self.client_id,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            client_version = self.client_version,
+            server_modified = self.server_modified,
+            hash = self.hash,
+            deleted = self.deleted,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+impl DiaryTombstone {
+    #[must_use]
+    pub fn new(journal: impl Into<StackString>, diary_date: Date, retention: Duration) -> Self {
+        let deleted_at = OffsetDateTime::now_utc();
+        Self {
+            journal: journal.into(),
+            diary_date,
+            deleted_at: deleted_at.into(),
+            expires_at: (deleted_at + retention).into(),
+        }
+    }
+
+    async fn _get_by_date<C>(journal: &str, date: Date, conn: &C) -> Result<Option<Self>, Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_tombstones
+                WHERE journal = $journal AND diary_date = $date AND expires_at > now()
+            "#,
+            journal = journal,
+            date = date
+        );
+        query.fetch_opt(conn).await.map_err(Into::into)
+    }
+
+    async fn _remove<C>(journal: &str, date: Date, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            "DELETE FROM diary_tombstones WHERE journal = $journal AND diary_date = $date",
+            journal = journal,
+            date = date
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_date(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let conn = pool.get().await?;
+        Self::_get_by_date(journal, date, &conn).await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            "SELECT * FROM diary_tombstones WHERE expires_at > now() ORDER BY journal, diary_date"
+        );
+        let conn = pool.get().await?;
+        query.fetch_streaming(&conn).await.map_err(Into::into)
+    }
+
+    /// Dates of `journal` deleted within the retention period
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_dates(journal: &str, pool: &PgPool) -> Result<HashSet<Date>, Error> {
+        let query = query!(
+            r#"
+                SELECT diary_date FROM diary_tombstones
+                WHERE journal = $journal AND expires_at > now()
+            "#,
+            journal = journal,
+        );
+        let conn = pool.get().await?;
+        query
+            .query_streaming(&conn)
+            .await?
+            .and_then(|row| async move {
+                let date: Date = row.try_get(0).map_err(PqError::BeginTransaction)?;
+                Ok(date)
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Keeps the most recent deletion if the date already has a tombstone
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO diary_tombstones (journal, diary_date, deleted_at, expires_at)
+                VALUES ($journal, $diary_date, $deleted_at, $expires_at)
+                ON CONFLICT (journal, diary_date) DO UPDATE
+                SET deleted_at=GREATEST(diary_tombstones.deleted_at, $deleted_at),
+                    expires_at=GREATEST(diary_tombstones.expires_at, $expires_at)
+            "#,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            deleted_at = self.deleted_at,
+            expires_at = self.expires_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn purge_expired(pool: &PgPool) -> Result<u64, Error> {
+        let query = query!("DELETE FROM diary_tombstones WHERE expires_at <= now()");
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+}
+
+impl DiaryRedaction {
+    #[must_use]
+    pub fn new(
+        journal: impl Into<StackString>,
+        diary_date: Date,
+        ciphertext: Vec<u8>,
+        nonce: Vec<u8>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            journal: journal.into(),
+            diary_date,
+            ciphertext,
+            nonce,
+            created_at: DateTimeWrapper::now(),
+        }
+    }
+
+    async fn insert_impl<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
+            r#"
+                INSERT INTO diary_redactions (
+                    id, journal, diary_date, ciphertext, nonce, created_at
+                ) VALUES (
+                    $id, $journal, $diary_date, $ciphertext, $nonce, $created_at
+                )
+            "#,
+            id = self.id,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            ciphertext = self.ciphertext,
+            nonce = self.nonce,
+            created_at = self.created_at,
+        );
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_date(journal: &str, date: Date, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_redactions
+                WHERE journal = $journal AND diary_date = $date
+                ORDER BY created_at
+            "#,
+            journal = journal,
+            date = date,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+}
+
+impl DiaryComment {
+    #[must_use]
+    pub fn new(
+        journal: impl Into<StackString>,
+        diary_date: Date,
+        author: impl Into<StackString>,
+        comment_text: impl Into<StackString>,
+    ) -> Self {
+        let now = DateTimeWrapper::now();
+        Self {
+            id: Uuid::new_v4(),
+            journal: journal.into(),
+            diary_date,
+            author: author.into(),
+            comment_text: comment_text.into(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO diary_comments (
+                    id, journal, diary_date, author, comment_text, created_at, updated_at
+                ) VALUES (
+                    $id, $journal, $diary_date, $author, $comment_text, $created_at,
+                    $updated_at
+                )
+            "#,
+            id = self.id,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            author = self.author,
+            comment_text = self.comment_text,
+            created_at = self.created_at,
+            updated_at = self.updated_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_id(id: Uuid, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!("SELECT * FROM diary_comments WHERE id = $id", id = id);
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// Comments on entries between `min_date` and `max_date` (inclusive),
+    /// oldest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_date_range(
+        journal: &str,
+        min_date: Date,
+        max_date: Date,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_comments
+                WHERE journal = $journal
+                  AND diary_date >= $min_date
+                  AND diary_date <= $max_date
+                ORDER BY diary_date, created_at
+            "#,
+            journal = journal,
+            min_date = min_date,
+            max_date = max_date,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update_text(&mut self, comment_text: &str, pool: &PgPool) -> Result<(), Error> {
+        self.comment_text = comment_text.into();
+        self.updated_at = DateTimeWrapper::now();
+        let query = query!(
+            r#"
+                UPDATE diary_comments
+                SET comment_text = $comment_text, updated_at = $updated_at
+                WHERE id = $id
+            "#,
+            id = self.id,
+            comment_text = self.comment_text,
+            updated_at = self.updated_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!("DELETE FROM diary_comments WHERE id = $id", id = self.id);
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+impl UserLinkCode {
+    #[must_use]
+    pub fn new(code: impl Into<StackString>, email: impl Into<StackString>, ttl: Duration) -> Self {
+        Self {
+            code: code.into(),
+            email: email.into(),
+            expires_at: (OffsetDateTime::now_utc() + ttl).into(),
+        }
+    }
+
+    /// Replaces earlier codes of the same user
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        let query = query!(
+            "DELETE FROM user_link_codes WHERE email = $email OR expires_at < now()",
+            email = self.email,
+        );
+        query.execute(conn).await?;
+        let query = query!(
+            r#"
+                INSERT INTO user_link_codes (code, email, expires_at)
+                VALUES ($code, $email, $expires_at)
+            "#,
+            code = self.code,
+            email = self.email,
+            expires_at = self.expires_at,
+        );
+        query.execute(conn).await?;
+        tran.commit().await?;
+        Ok(())
+    }
+
+    /// Remove and return the code if it hasn't expired, so it can only be
+    /// used once
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn redeem(code: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                DELETE FROM user_link_codes
+                WHERE code = $code AND expires_at > now()
+                RETURNING *
+            "#,
+            code = code,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+}
+
+impl S3Outbox {
+    /// Queue the upload of `dates`, dates already queued keep their backoff,
+    /// returns the number of dates added
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn enqueue(journal: &str, dates: &[Date], pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO s3_outbox (journal, diary_date)
+                SELECT $journal, unnest($dates::date[])
+                ON CONFLICT (journal, diary_date) DO NOTHING
+            "#,
+            journal = journal,
+            dates = dates,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+
+    /// Uploads whose backoff has elapsed, oldest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_due(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM s3_outbox
+                WHERE next_attempt_at <= now()
+                ORDER BY next_attempt_at, journal, diary_date
+            "#
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM s3_outbox ORDER BY journal, diary_date");
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn record_failure(
+        &self,
+        error: &str,
+        next_attempt_at: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE s3_outbox
+                SET attempts = attempts + 1,
+                    last_error = $error,
+                    next_attempt_at = $next_attempt_at
+                WHERE journal = $journal AND diary_date = $diary_date
+            "#,
+            error = error,
+            next_attempt_at = next_attempt_at,
+            journal = self.journal,
+            diary_date = self.diary_date,
+        );
         let conn = pool.get().await?;
-        Self::update_by_id_conn(id, new_diff_type.as_ref(), &conn).await?;
+        query.execute(&conn).await?;
         Ok(())
     }
 
-    async fn update_by_id_conn<C>(id: Uuid, new_diff_type: &str, conn: &C) -> Result<(), Error>
-    where
-        C: GenericClient + Sync,
-    {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "DELETE FROM s3_outbox WHERE journal = $journal AND diary_date = $diary_date",
+            journal = self.journal,
+            diary_date = self.diary_date,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+impl SyncLease {
+    /// Take the lease `name` for `ttl_secs` if it is free or expired, or
+    /// regardless of the current holder with `force`, returns `None` when
+    /// another holder keeps it
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn try_acquire(
+        name: &str,
+        holder: &str,
+        ttl_secs: f64,
+        force: bool,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
         let query = query!(
             r#"
-                UPDATE diary_conflict
-                SET diff_type = $new_diff_type
-                WHERE id = $id
+                INSERT INTO sync_lease (name, holder, acquired_at, expires_at)
+                VALUES ($name, $holder, now(), now() + make_interval(secs => $ttl_secs))
+                ON CONFLICT (name) DO UPDATE
+                SET holder = EXCLUDED.holder,
+                    acquired_at = EXCLUDED.acquired_at,
+                    expires_at = EXCLUDED.expires_at
+                WHERE sync_lease.expires_at < now() OR $force
+                RETURNING *
             "#,
-            id = id,
-            new_diff_type = new_diff_type,
+            name = name,
+            holder = holder,
+            ttl_secs = ttl_secs,
+            force = force,
         );
-        query.execute(conn).await?;
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// Current holder of the lease `name`, expired leases aren't held
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_name(name: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM sync_lease WHERE name = $name AND expires_at >= now()",
+            name = name,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// Push back the expiry by `ttl_secs`, returns false if the lease was
+    /// taken over since
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn renew(&self, ttl_secs: f64, pool: &PgPool) -> Result<bool, Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_lease
+                SET expires_at = now() + make_interval(secs => $ttl_secs)
+                WHERE name = $name AND holder = $holder
+            "#,
+            ttl_secs = ttl_secs,
+            name = self.name,
+            holder = self.holder,
+        );
+        let conn = pool.get().await?;
+        Ok(query.execute(&conn).await? > 0)
+    }
+
+    /// Give up the lease, leaving it alone if it was taken over
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn release(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "DELETE FROM sync_lease WHERE name = $name AND holder = $holder",
+            name = self.name,
+            holder = self.holder,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
         Ok(())
     }
+}
+
+impl S3EntryState {
+    #[must_use]
+    pub fn new(
+        journal: impl Into<StackString>,
+        diary_date: Date,
+        uploaded_text: impl Into<StackString>,
+        delta_count: i32,
+    ) -> Self {
+        Self {
+            journal: journal.into(),
+            diary_date,
+            uploaded_text: uploaded_text.into(),
+            delta_count,
+            uploaded_at: DateTimeWrapper::now(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_date(
+        journal: &str,
+        date: Date,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM s3_entry_state WHERE journal = $journal AND diary_date = $date",
+            journal = journal,
+            date = date,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn remove_by_datetime(datetime: DateTimeWrapper, pool: &PgPool) -> Result<(), Error> {
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO s3_entry_state (
+                    journal, diary_date, uploaded_text, delta_count, uploaded_at
+                )
+                VALUES ($journal, $diary_date, $uploaded_text, $delta_count, $uploaded_at)
+                ON CONFLICT (journal, diary_date) DO UPDATE
+                SET uploaded_text=$uploaded_text,
+                    delta_count=$delta_count,
+                    uploaded_at=$uploaded_at
+            "#,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            uploaded_text = self.uploaded_text,
+            delta_count = self.delta_count,
+            uploaded_at = self.uploaded_at,
+        );
         let conn = pool.get().await?;
-        Self::remove_by_datetime_conn(datetime, &conn).await?;
+        query.execute(&conn).await?;
         Ok(())
     }
 
-    async fn remove_by_datetime_conn<C>(datetime: DateTimeWrapper, conn: &C) -> Result<(), Error>
-    where
-        C: GenericClient + Sync,
-    {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(journal: &str, date: Date, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
-            "DELETE FROM diary_conflict WHERE sync_datetime = $datetime",
-            datetime = datetime,
+            "DELETE FROM s3_entry_state WHERE journal = $journal AND diary_date = $date",
+            journal = journal,
+            date = date,
         );
-        query.execute(conn).await?;
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
         Ok(())
     }
+}
 
-    async fn insert_conflict_conn<C>(&self, conn: &C) -> Result<(), Error>
-    where
-        C: GenericClient + Sync,
-    {
+impl SyncWatermark {
+    #[must_use]
+    pub fn new(
+        journal: impl Into<StackString>,
+        diary_date: Date,
+        backend: SyncBackend,
+        hash: impl Into<StackString>,
+        modified: OffsetDateTime,
+    ) -> Self {
+        Self {
+            journal: journal.into(),
+            diary_date,
+            backend: backend.to_str().into(),
+            hash: hash.into(),
+            modified: modified.into(),
+            synced_at: DateTimeWrapper::now(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_map(
+        journal: &str,
+        backend: SyncBackend,
+        pool: &PgPool,
+    ) -> Result<HashMap<Date, Self>, Error> {
+        let query = query!(
+            "SELECT * FROM sync_watermarks WHERE journal = $journal AND backend = $backend",
+            journal = journal,
+            backend = backend.to_str(),
+        );
+        let conn = pool.get().await?;
+        query
+            .fetch_streaming(&conn)
+            .await?
+            .map_ok(|w: Self| (w.diary_date, w))
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
             r#"
-                INSERT INTO diary_conflict (
-                    id, sync_datetime, diary_date, diff_type, diff_text, sequence
+                INSERT INTO sync_watermarks (
+                    journal, diary_date, backend, hash, modified, synced_at
+                )
+                VALUES ($journal, $diary_date, $backend, $hash, $modified, $synced_at)
+                ON CONFLICT (journal, diary_date, backend) DO UPDATE
+                SET hash=$hash,
+                    modified=$modified,
+                    synced_at=$synced_at
+            "#,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            backend = self.backend,
+            hash = self.hash,
+            modified = self.modified,
+            synced_at = self.synced_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(
+        journal: &str,
+        date: Date,
+        backend: SyncBackend,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                DELETE FROM sync_watermarks
+                WHERE journal = $journal AND diary_date = $date AND backend = $backend
+            "#,
+            journal = journal,
+            date = date,
+            backend = backend.to_str(),
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+impl DiaryAudit {
+    #[must_use]
+    pub fn new(
+        email: Option<&str>,
+        action: AuditAction,
+        journal: impl Into<StackString>,
+        diary_date: Option<Date>,
+        detail: impl Into<StackString>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            created_at: DateTimeWrapper::now(),
+            email: email.map(Into::into),
+            action: action.to_str().into(),
+            journal: journal.into(),
+            diary_date,
+            detail: detail.into(),
+            source: None,
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO diary_audit (
+                    id, created_at, email, action, journal, diary_date, detail, source
                 ) VALUES (
-                    $id, $sync_datetime, $diary_date, $diff_type, $diff_text, $sequence
+                    $id, $created_at, $email, $action, $journal, $diary_date, $detail, $source
                 )
             "#,
             id = self.id,
-            sync_datetime = self.sync_datetime,
+            created_at = self.created_at,
+            email = self.email,
+            action = self.action,
+            journal = self.journal,
             diary_date = self.diary_date,
-            diff_type = self.diff_type,
-            diff_text = self.diff_text,
-            sequence = self.sequence,
+            detail = self.detail,
+            source = self.source,
         );
-        query.execute(conn).await?;
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
         Ok(())
     }
 
-    async fn insert_from_changeset<C>(
-        diary_date: Date,
-        changeset: Changeset,
-        conn: &C,
-    ) -> Result<Option<OffsetDateTime>, Error>
-    where
-        C: GenericClient + Sync,
-    {
-        let sync_datetime = OffsetDateTime::now_utc();
-        let removed_lines: Vec<_> = changeset
-            .diffs
-            .into_iter()
-            .enumerate()
-            .map(|(sequence, entry)| match entry {
-                Difference::Same(s) => {
-                    DiaryConflict::new(sync_datetime, diary_date, "same", s, sequence as i32)
-                }
-                Difference::Rem(s) => {
-                    DiaryConflict::new(sync_datetime, diary_date, "rem", s, sequence as i32)
-                }
-                Difference::Add(s) => {
-                    DiaryConflict::new(sync_datetime, diary_date, "add", s, sequence as i32)
-                }
-            })
-            .collect();
+    /// Most recent first, unset filters match everything, `min_date` and
+    /// `max_date` bound the time the write happened
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_filtered(
+        journal: &str,
+        email: Option<&str>,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+        limit: usize,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let limit = i64::try_from(limit)?;
+        let query = query!(
+            r#"
+                SELECT * FROM diary_audit
+                WHERE journal = $journal
+                  AND ($email::text IS NULL OR email = $email)
+                  AND ($min_date::date IS NULL OR created_at::date >= $min_date)
+                  AND ($max_date::date IS NULL OR created_at::date <= $max_date)
+                ORDER BY created_at DESC
+                LIMIT $limit
+            "#,
+            journal = journal,
+            email = email,
+            min_date = min_date,
+            max_date = max_date,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+}
 
-        let n_removed_lines: usize = removed_lines
-            .iter()
-            .filter(|x| &x.diff_type == "rem")
-            .count();
+/// Kind of write recorded in the activity log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Insert,
+    Replace,
+    Append,
+    Patch,
+    Sync,
+    ResolveConflict,
+    Delete,
+    Restore,
+    Purge,
+    Redact,
+    Schedule,
+    ReplaceEncrypted,
+    MobileSync,
+    PeerPush,
+    UpdateMetadata,
+    Star,
+    TogglePrivate,
+}
 
-        if n_removed_lines > 0 {
-            debug!("update_entry {:?}", removed_lines);
-            debug!("difference {}", n_removed_lines);
-            for conflict in &removed_lines {
-                conflict.insert_conflict_conn(conn).await?;
-            }
-            Ok(Some(sync_datetime))
-        } else {
-            Ok(None)
+impl AuditAction {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Replace => "replace",
+            Self::Append => "append",
+            Self::Patch => "patch",
+            Self::Sync => "sync",
+            Self::ResolveConflict => "resolve_conflict",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+            Self::Purge => "purge",
+            Self::Redact => "redact",
+            Self::Schedule => "schedule",
+            Self::ReplaceEncrypted => "replace_encrypted",
+            Self::MobileSync => "mobile_sync",
+            Self::PeerPush => "peer_push",
+            Self::UpdateMetadata => "update_metadata",
+            Self::Star => "star",
+            Self::TogglePrivate => "toggle_private",
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Storage a date is synced with, each keeps its own [`SyncWatermark`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncBackend {
+    Local,
+    S3,
+    Ssh,
+}
+
+impl SyncBackend {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::S3 => "s3",
+            Self::Ssh => "ssh",
+        }
+    }
+}
+
+impl fmt::Display for SyncBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Constraints of dynamic queries on the optional bounds of `diary_date`,
+/// bound as `$min_date` and `$max_date`
+fn date_range_bindings<'a>(
+    min_date: &'a Option<Date>,
+    max_date: &'a Option<Date>,
+    constraints: &mut Vec<&'static str>,
+    bindings: &mut Vec<(&'static str, Parameter<'a>)>,
+) {
+    if let Some(min_date) = min_date {
+        constraints.push("diary_date >= $min_date");
+        bindings.push(("min_date", min_date));
+    }
+    if let Some(max_date) = max_date {
+        constraints.push("diary_date <= $max_date");
+        bindings.push(("max_date", max_date));
+    }
+}
+
+/// `LIKE` pattern matching text containing `search_text` as is, in NFC like
+/// the stored text, with the wildcards and the escape character escaped
+fn like_pattern(search_text: &str) -> StackString {
+    let mut pattern = String::from("%");
+    for c in search_text.nfc() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern.into()
+}
+
+/// Interval over which [`MetadataStats`] are aggregated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatsPeriod {
+    Day,
+    Week,
+    #[default]
+    Month,
+    Year,
+}
+
+impl StatsPeriod {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+            Self::Year => "year",
+        }
+    }
+
+    /// Last day of the period starting on `start`
+    #[must_use]
+    pub fn last_day(self, start: Date) -> Date {
+        let (year, month) = (start.year(), start.month());
+        match self {
+            Self::Day => start,
+            Self::Week => start + Duration::days(6),
+            Self::Month => Date::from_calendar_date(year, month, days_in_year_month(year, month))
+                .unwrap_or(start),
+            Self::Year => Date::from_calendar_date(year, Month::December, 31).unwrap_or(start),
+        }
+    }
+}
+
+impl fmt::Display for StatsPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for StatsPeriod {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Month),
+            "year" => Ok(Self::Year),
+            _ => Err(format_err!("Invalid period {s}")),
         }
     }
 }
 
-impl DiaryEntries {
-    pub fn new(diary_date: Date, diary_text: impl Into<StackString>) -> Self {
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MetadataStats {
+    /// First day of the period
+    pub period: Date,
+    pub count: i64,
+    pub total: f64,
+    pub average: f64,
+    pub minimum: f64,
+    pub maximum: f64,
+}
+
+impl MetadataStats {
+    /// Aggregate the numeric values of metadata `key` per `period`, entries
+    /// where the key is missing or not a number are skipped
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_key(
+        journal: &str,
+        key: &str,
+        period: StatsPeriod,
+        min_date: Option<Date>,
+        max_date: Option<Date>,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let period = period.to_str();
+        let query = query!(
+            r#"
+                SELECT date_trunc($period, diary_date::timestamp)::date as period,
+                       count(*) as count,
+                       sum(value::double precision) as total,
+                       avg(value::double precision) as average,
+                       min(value::double precision) as minimum,
+                       max(value::double precision) as maximum
+                FROM (
+                    SELECT diary_date, metadata->$key as value
+                    FROM diary_entries
+                    WHERE journal = $journal
+                        AND deleted_at IS NULL
+                        AND diary_date >= COALESCE($min_date::date, diary_date)
+                        AND diary_date <= COALESCE($max_date::date, diary_date)
+                ) t
+                WHERE jsonb_typeof(value) = 'number'
+                GROUP BY 1
+                ORDER BY 1
+            "#,
+            period = period,
+            key = key,
+            journal = journal,
+            min_date = min_date,
+            max_date = max_date,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+}
+
+/// Cache entry merged into the entry of `diary_date`, kept so the entry can
+/// be shown as a timeline of the day
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiarySubentry {
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub diary_datetime: DateTimeWrapper,
+    pub diary_text: StackString,
+    pub source: Option<StackString>,
+    pub author: Option<StackString>,
+}
+
+impl DiarySubentry {
+    #[must_use]
+    pub fn from_cache(cache: &DiaryCache, diary_date: Date) -> Self {
         Self {
+            journal: cache.journal.clone(),
             diary_date,
-            diary_text: diary_text.into(),
-            last_modified: DateTimeWrapper::now(),
+            diary_datetime: cache.diary_datetime,
+            diary_text: cache.diary_text.clone(),
+            source: cache.source.clone(),
+            author: cache.author.clone(),
         }
     }
 
-    async fn insert_entry_impl<C>(&self, conn: &C) -> Result<(), Error>
+    /// Insert the subentry unless it was inserted before, does nothing if
+    /// there is no entry for `diary_date` in the database
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let conn = pool.get().await?;
+        self.insert_impl(&conn).await
+    }
+
+    async fn insert_impl<C>(&self, conn: &C) -> Result<(), Error>
     where
         C: GenericClient + Sync,
     {
         let query = query!(
             r#"
-                INSERT INTO diary_entries (diary_date, diary_text, last_modified)
-                VALUES ($diary_date, $diary_text, now())
+                INSERT INTO diary_subentries (
+                    journal, diary_date, diary_datetime, diary_text, source, author
+                )
+                SELECT $journal, $diary_date, $diary_datetime, $diary_text, $source, $author
+                FROM diary_entries
+                WHERE journal = $journal AND diary_date = $diary_date
+                ON CONFLICT DO NOTHING
             "#,
+            journal = self.journal,
             diary_date = self.diary_date,
+            diary_datetime = self.diary_datetime,
             diary_text = self.diary_text,
+            source = self.source,
+            author = self.author,
         );
         query.execute(conn).await?;
         Ok(())
     }
 
-    /// # Errors
-    /// Return error if db query fails
-    pub async fn insert_entry(&self, pool: &PgPool) -> Result<(), Error> {
-        let conn = pool.get().await?;
-        self.insert_entry_impl(&conn).await?;
-        Ok(())
-    }
-
-    async fn update_entry_impl<C>(
-        &self,
-        conn: &C,
-        insert_new: bool,
-    ) -> Result<Option<OffsetDateTime>, Error>
+    async fn get_by_date_impl<C>(journal: &str, date: Date, conn: &C) -> Result<Vec<Self>, Error>
     where
         C: GenericClient + Sync,
     {
-        let changeset = self
-            .get_difference_impl(conn, insert_new)
-            .await?
-            .ok_or_else(|| format_err!("Not found"))?;
-
-        let conflict_opt = if changeset.distance > 0 {
-            DiaryConflict::insert_from_changeset(self.diary_date, changeset, conn).await?
-        } else {
-            None
-        };
-
-        if insert_new {
-            let query = query!(
-                r#"
-                    UPDATE diary_entries
-                    SET diary_text=$diary_text,last_modified=now()
-                    WHERE diary_date = $diary_date
-                "#,
-                diary_date = self.diary_date,
-                diary_text = self.diary_text,
-            );
-            query.execute(conn).await?;
-            Ok(conflict_opt)
-        } else {
-            Ok(None)
-        }
+        let query = query!(
+            r#"
+                SELECT * FROM diary_subentries
+                WHERE journal = $journal AND diary_date = $date
+                ORDER BY diary_datetime
+            "#,
+            journal = journal,
+            date = date,
+        );
+        query.fetch(conn).await.map_err(Into::into)
     }
 
+    /// Subentries of every journal written since `since`
     /// # Errors
     /// Return error if db query fails
-    pub async fn update_entry(
-        &self,
-        pool: &PgPool,
-        insert_new: bool,
-    ) -> Result<Option<OffsetDateTime>, Error> {
+    pub async fn get_since(since: OffsetDateTime, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM diary_subentries
+                WHERE diary_datetime >= $since
+                ORDER BY diary_datetime
+            "#,
+            since = since,
+        );
         let conn = pool.get().await?;
-        self.update_entry_impl(&conn, insert_new)
-            .await
-            .map_err(Into::into)
+        query.fetch(&conn).await.map_err(Into::into)
     }
 
+    /// Subentries of the entry for `date` in the order they were written,
+    /// empty if the entry is in the trash or private and `hide_private` is
+    /// set
     /// # Errors
     /// Return error if db query fails
-    pub async fn upsert_entry(
-        &self,
+    pub async fn get_by_date(
+        journal: &str,
+        date: Date,
+        hide_private: bool,
         pool: &PgPool,
-        insert_new: bool,
-    ) -> Result<Option<OffsetDateTime>, Error> {
-        let mut conn = pool.get().await?;
-        let tran = conn.transaction().await?;
-        let conn: &PgTransaction = &tran;
-        let existing = Self::_get_by_date(self.diary_date, conn).await?;
-        let output = if existing.is_some() {
-            self.update_entry_impl(conn, insert_new).await?
-        } else {
-            self.insert_entry_impl(conn).await?;
-            None
-        };
-        tran.commit().await?;
-        Ok(output)
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT s.*
+                FROM diary_subentries s
+                JOIN diary_entries e ON e.journal = s.journal AND e.diary_date = s.diary_date
+                WHERE s.journal = $journal
+                    AND s.diary_date = $date
+                    AND e.deleted_at IS NULL
+                    AND NOT ($hide_private AND e.visibility = $private)
+                ORDER BY s.diary_datetime
+            "#,
+            journal = journal,
+            date = date,
+            hide_private = hide_private,
+            private = PRIVATE_VISIBILITY,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
     }
+}
+
+/// Time a cache entry merged into the entry of `diary_date` was written
+#[derive(FromSqlRow, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WrittenAt {
+    pub diary_date: Date,
+    pub written_at: DateTimeWrapper,
+}
 
+impl WrittenAt {
+    /// Timestamps kept in the `written_at` metadata of the entries in
+    /// `range`, private entries are skipped if `hide_private` is set
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_modified_map(
+    pub async fn get_by_range(
+        journal: &str,
+        range: DateRange,
+        hide_private: bool,
         pool: &PgPool,
-        min_date: Option<Date>,
-        max_date: Option<Date>,
-    ) -> Result<HashMap<Date, OffsetDateTime>, Error> {
-        let mut query: StackString = "SELECT diary_date, last_modified FROM diary_entries".into();
-        let mut constraints = Vec::new();
-        if let Some(min_date) = min_date {
-            constraints.push(format_sstr!("diary_date >= '{min_date}'"));
-        }
-        if let Some(max_date) = max_date {
-            constraints.push(format_sstr!("diary_date <= '{max_date}'"));
-        }
-        if !constraints.is_empty() {
-            query.push_str(&format_sstr!(" WHERE {}", constraints.join(" AND ")));
-        }
-        let query = query_dyn!(&query)?;
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT e.diary_date, w.value::timestamptz as written_at
+                FROM diary_entries e,
+                    jsonb_array_elements_text(
+                        CASE WHEN jsonb_typeof(e.metadata->$key) = 'array'
+                            THEN e.metadata->$key
+                            ELSE '[]'::jsonb
+                        END
+                    ) AS w(value)
+                WHERE e.journal = $journal
+                    AND e.deleted_at IS NULL
+                    AND NOT ($hide_private AND e.visibility = $private)
+                    AND e.diary_date >= COALESCE($since::date, e.diary_date)
+                    AND e.diary_date <= COALESCE($until::date, e.diary_date)
+                    AND w.value ~ '^\d{4}-\d{2}-\d{2}T'
+                ORDER BY 2
+            "#,
+            key = WRITTEN_AT_KEY,
+            journal = journal,
+            hide_private = hide_private,
+            private = PRIVATE_VISIBILITY,
+            since = range.since,
+            until = range.until,
+        );
         let conn = pool.get().await?;
-        query
-            .query_streaming(&conn)
-            .await?
-            .map_err(Into::into)
-            .and_then(|row| async move {
-                let diary_date: Date = row.try_get("diary_date")?;
-                let last_modified: OffsetDateTime = row.try_get("last_modified")?;
-                Ok((diary_date, last_modified))
-            })
-            .try_collect()
-            .await
+        query.fetch(&conn).await.map_err(Into::into)
     }
+}
+
+/// Occurrences of a term in the entries of a date range
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiaryTerm {
+    pub term: StackString,
+    pub count: i64,
+}
 
-    async fn _get_by_date<C>(date: Date, conn: &C) -> Result<Option<Self>, Error>
+impl DiaryTerm {
+    /// Replace the term counts of an entry, called whenever its text is
+    /// written
+    async fn replace_impl<C>(journal: &str, date: Date, text: &str, conn: &C) -> Result<(), Error>
     where
         C: GenericClient + Sync,
     {
         let query = query!(
-            "SELECT * FROM diary_entries WHERE diary_date = $date",
-            date = date
+            "DELETE FROM diary_terms WHERE journal = $journal AND diary_date = $date",
+            journal = journal,
+            date = date,
         );
-        query.fetch_opt(conn).await.map_err(Into::into)
+        query.execute(conn).await?;
+        let (terms, counts): (Vec<StackString>, Vec<i64>) = term_counts(text).into_iter().unzip();
+        if terms.is_empty() {
+            return Ok(());
+        }
+        let query = query!(
+            r#"
+                INSERT INTO diary_terms (journal, diary_date, term, count)
+                SELECT $journal, $date, term, count
+                FROM unnest($terms::text[], $counts::bigint[]) AS t(term, count)
+            "#,
+            journal = journal,
+            date = date,
+            terms = terms,
+            counts = counts,
+        );
+        query.execute(conn).await?;
+        Ok(())
     }
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_by_date(date: Date, pool: &PgPool) -> Result<Option<Self>, Error> {
+    pub async fn replace(
+        journal: &str,
+        date: Date,
+        text: &str,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
         let conn = pool.get().await?;
-        Self::_get_by_date(date, &conn).await.map_err(Into::into)
+        Self::replace_impl(journal, date, text, &conn).await
     }
 
+    /// The `limit` most frequent terms of the entries in `range`, private
+    /// entries are skipped if `hide_private` is set
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_by_text(
-        search_text: impl AsRef<str>,
+    pub async fn get_top(
+        journal: &str,
+        range: DateRange,
+        hide_private: bool,
+        limit: i64,
         pool: &PgPool,
-    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
-        let search_text: StackString = search_text
-            .as_ref()
-            .chars()
-            .filter(|c| char::is_alphanumeric(*c) || *c == '-' || *c == '_')
-            .collect();
-        let query = format_sstr!(
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
             r#"
-                SELECT * FROM diary_entries
-                WHERE diary_text like '%{search_text}%'
-                ORDER BY diary_date
-            "#
+                SELECT t.term, sum(t.count)::bigint as count
+                FROM diary_terms t
+                JOIN diary_entries e ON e.journal = t.journal AND e.diary_date = t.diary_date
+                WHERE t.journal = $journal
+                    AND e.deleted_at IS NULL
+                    AND NOT ($hide_private AND e.visibility = $private)
+                    AND t.diary_date >= COALESCE($since::date, t.diary_date)
+                    AND t.diary_date <= COALESCE($until::date, t.diary_date)
+                GROUP BY t.term
+                ORDER BY 2 DESC, 1
+                LIMIT $limit
+            "#,
+            journal = journal,
+            hide_private = hide_private,
+            private = PRIVATE_VISIBILITY,
+            since = range.since,
+            until = range.until,
+            limit = limit,
         );
-        let query = query_dyn!(&query)?;
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        query.fetch(&conn).await.map_err(Into::into)
     }
+}
 
-    async fn get_difference_impl<C>(
-        &self,
-        conn: &C,
-        insert_new: bool,
-    ) -> Result<Option<Changeset>, Error>
-    where
-        C: GenericClient + Sync,
-    {
-        Self::_get_by_date(self.diary_date, conn).await.map(|opt| {
-            opt.map(|original| {
-                if insert_new {
-                    Changeset::new(&original.diary_text, &self.diary_text, "\n")
-                } else {
-                    Changeset::new(&self.diary_text, &original.diary_text, "\n")
-                }
-            })
-        })
-    }
+/// Entries written in a year and the characters they hold
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct YearSize {
+    pub year: i32,
+    pub entries: i64,
+    pub characters: i64,
+}
 
+impl YearSize {
+    /// Sizes of the entries of `journal` by year, encrypted entries are
+    /// counted by the bytes of their ciphertext
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_difference(&self, pool: &PgPool) -> Result<Option<Changeset>, Error> {
+    pub async fn get_by_year(journal: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT extract(year FROM diary_date)::integer as year,
+                       count(*) as entries,
+                       sum(
+                           CASE WHEN is_encrypted
+                               THEN COALESCE(octet_length(diary_ciphertext), 0)
+                               ELSE length(diary_text)
+                           END
+                       )::bigint as characters
+                FROM diary_entries
+                WHERE journal = $journal AND deleted_at IS NULL
+                GROUP BY 1
+                ORDER BY 1
+            "#,
+            journal = journal,
+        );
         let conn = pool.get().await?;
-        self.get_difference_impl(&conn, true)
-            .await
-            .map_err(Into::into)
+        query.fetch(&conn).await.map_err(Into::into)
     }
+}
+
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EntrySize {
+    pub diary_date: Date,
+    pub characters: i64,
+}
 
+impl EntrySize {
+    /// The `limit` longest entries of `journal`, longest first
     /// # Errors
     /// Return error if db query fails
-    pub async fn delete_entry(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn get_biggest(journal: &str, limit: i64, pool: &PgPool) -> Result<Vec<Self>, Error> {
         let query = query!(
-            "DELETE FROM diary_entries WHERE diary_date = $diary_date",
-            diary_date = self.diary_date
+            r#"
+                SELECT diary_date,
+                       (
+                           CASE WHEN is_encrypted
+                               THEN COALESCE(octet_length(diary_ciphertext), 0)
+                               ELSE length(diary_text)
+                           END
+                       )::bigint as characters
+                FROM diary_entries
+                WHERE journal = $journal AND deleted_at IS NULL
+                ORDER BY 2 DESC, diary_date
+                LIMIT $limit
+            "#,
+            journal = journal,
+            limit = limit,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
-        Ok(())
+        query.fetch(&conn).await.map_err(Into::into)
     }
 }
 
-impl DiaryCache {
+/// Entries of a period of the date list
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DateGroup {
+    /// First day of the period
+    pub period: Date,
+    pub entries: i64,
+    /// Encrypted entries have no words to count
+    pub words: i64,
+}
+
+impl DateGroup {
+    /// Entries and words per `period` newest first, leaving out scheduled
+    /// entries until `today` reaches them and private entries if
+    /// `hide_private` is set
     /// # Errors
     /// Return error if db query fails
-    pub async fn insert_entry(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn get_by_period(
+        journal: &str,
+        period: StatsPeriod,
+        today: Date,
+        hide_private: bool,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
         let query = query!(
             r#"
-                INSERT INTO diary_cache (diary_datetime, diary_text)
-                VALUES ($diary_datetime, $diary_text)
+                SELECT date_trunc($period, e.diary_date::timestamp)::date as period,
+                       count(*) as entries,
+                       COALESCE(sum(w.words), 0)::bigint as words
+                FROM diary_entries e
+                CROSS JOIN LATERAL (
+                    SELECT count(*) as words FROM regexp_matches(e.diary_text, '\S+', 'g')
+                ) w
+                WHERE e.journal = $journal
+                    AND e.deleted_at IS NULL
+                    AND NOT (e.scheduled AND e.diary_date > $today)
+                    AND NOT ($hide_private AND e.visibility = $private)
+                GROUP BY 1
+                ORDER BY 1 DESC
             "#,
-            diary_datetime = self.diary_datetime,
-            diary_text = self.diary_text,
+            period = period.to_str(),
+            journal = journal,
+            today = today,
+            hide_private = hide_private,
+            private = PRIVATE_VISIBILITY,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
-        Ok(())
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+    use serde_json::json;
+    use time::{macros::date, OffsetDateTime};
+
+    use crate::{
+        line_diff::LineDiff,
+        models::{
+            like_pattern, parse_metadata_value, DateRange, DiaryConflict, DiaryEntries, SortOrder,
+            StatsPeriod, DEFAULT_JOURNAL, PRIVATE_VISIBILITY,
+        },
+    };
+
+    #[test]
+    fn test_parse_metadata_value() {
+        assert_eq!(parse_metadata_value("7"), json!(7));
+        assert_eq!(parse_metadata_value(" 7.5 "), json!(7.5));
+        assert_eq!(parse_metadata_value("true"), json!(true));
+        assert_eq!(parse_metadata_value("NaN"), json!("NaN"));
+        assert_eq!(parse_metadata_value("ibuprofen"), json!("ibuprofen"));
+    }
+
+    #[test]
+    fn test_like_pattern() {
+        assert_eq!(like_pattern("caf\u{e9} \u{1f389}"), "%caf\u{e9} \u{1f389}%");
+        assert_eq!(like_pattern("cafe\u{301}"), "%caf\u{e9}%");
+        assert_eq!(like_pattern("100%_\\"), "%100\\%\\_\\\\%");
+        assert_eq!(like_pattern(""), "%%");
+    }
+
+    #[test]
+    fn test_date_range() {
+        let range = DateRange::new(Some(date!(2024 - 03 - 01)), Some(date!(2024 - 03 - 31)));
+        assert!(range.contains(date!(2024 - 03 - 01)));
+        assert!(range.contains(date!(2024 - 03 - 31)));
+        assert!(!range.contains(date!(2024 - 02 - 29)));
+        assert!(!range.contains(date!(2024 - 04 - 01)));
+        assert!(DateRange::default().contains(date!(1999 - 12 - 31)));
+        assert!(DateRange::new(Some(date!(2024 - 03 - 01)), None).contains(date!(2030 - 01 - 01)));
+    }
+
+    #[test]
+    fn test_stats_period() {
+        for period in ["day", "week", "month", "year"] {
+            let p: StatsPeriod = period.parse().unwrap();
+            assert_eq!(p.to_str(), period);
+        }
+        assert!("decade".parse::<StatsPeriod>().is_err());
+        let start = date!(2024 - 02 - 01);
+        assert_eq!(StatsPeriod::Month.last_day(start), date!(2024 - 02 - 29));
+        assert_eq!(StatsPeriod::Week.last_day(start), date!(2024 - 02 - 07));
+        assert_eq!(StatsPeriod::Year.last_day(start), date!(2024 - 12 - 31));
+    }
+
+    #[test]
+    fn test_sort_order() {
+        for order in ["asc", "desc"] {
+            let o: SortOrder = order.parse().unwrap();
+            assert_eq!(o.to_str(), order);
+        }
+        assert_eq!(SortOrder::default(), SortOrder::Desc);
+        assert!("random".parse::<SortOrder>().is_err());
+    }
+
+    #[test]
+    fn test_entry_visibility() {
+        let mut entry = DiaryEntries::new(date!(2024 - 03 - 01), "went hiking");
+        assert!(!entry.is_private());
+        entry.visibility = PRIVATE_VISIBILITY.into();
+        assert!(entry.is_private());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_conflict_rows_rebuild_both_texts(
+            original in vec("[a-c]{1,2}", 1..8),
+            updated in vec("[a-c]{1,2}", 1..8),
+        ) {
+            let (original, updated) = (original.join("\n"), updated.join("\n"));
+            let diff = LineDiff::new(&original, &updated);
+            let rows = DiaryConflict::from_diff(
+                DEFAULT_JOURNAL,
+                date!(2024 - 03 - 01),
+                OffsetDateTime::now_utc(),
+                diff.clone(),
+            );
+            let rebuild = |skipped: &str| {
+                rows.iter()
+                    .filter(|r| r.diff_type.as_str() != skipped)
+                    .map(|r| r.diff_text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            prop_assert_eq!(rebuild("add"), original);
+            prop_assert_eq!(rebuild("rem"), updated);
+            for (sequence, row) in rows.iter().enumerate() {
+                prop_assert_eq!(row.sequence, sequence as i32);
+                prop_assert_eq!(row.conflict_group, rows[0].conflict_group);
+            }
+            prop_assert_eq!(&DiaryConflict::hunks(&rows), &diff.hunks);
+            // rows recorded before line numbers were stored
+            let legacy: Vec<_> = rows
+                .iter()
+                .map(|r| DiaryConflict {
+                    old_line: None,
+                    new_line: None,
+                    ..r.clone()
+                })
+                .collect();
+            prop_assert_eq!(&DiaryConflict::hunks(&legacy), &diff.hunks);
+        }
+    }
+}
+
+/// Text of merged cache entries still to be appended to a daily file, the row
+/// is written in the transaction removing the cache entries and deleted once
+/// the file is written, so an interrupted merge is finished by the next one
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiaryPendingAppend {
+    pub id: Uuid,
+    pub journal: StackString,
+    pub diary_date: Date,
+    pub diary_text: StackString,
+    pub created_at: DateTimeWrapper,
+}
+
+impl DiaryPendingAppend {
+    #[must_use]
+    pub fn new(
+        journal: impl Into<StackString>,
+        diary_date: Date,
+        diary_text: impl Into<StackString>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            journal: journal.into(),
+            diary_date,
+            diary_text: diary_text.into(),
+            created_at: DateTimeWrapper::now(),
+        }
     }
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_cache_entries(
-        pool: &PgPool,
-    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
-        let query = query!("SELECT * FROM diary_cache");
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        self.insert_impl(&conn).await
     }
 
-    /// # Errors
-    /// Return error if db query fails
-    pub async fn get_by_text(
-        search_text: impl AsRef<str>,
-        pool: &PgPool,
-    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
-        let search_text: StackString = search_text
-            .as_ref()
-            .chars()
-            .filter(|c| char::is_alphanumeric(*c) || *c == '-' || *c == '_')
-            .collect();
-        let query = format_sstr!(
+    async fn insert_impl<C>(&self, conn: &C) -> Result<(), Error>
+    where
+        C: GenericClient + Sync,
+    {
+        let query = query!(
             r#"
-                SELECT * FROM diary_cache
-                WHERE diary_text like '%{search_text}%'
-            "#
+                INSERT INTO diary_pending_appends (
+                    id, journal, diary_date, diary_text, created_at
+                ) VALUES (
+                    $id, $journal, $diary_date, $diary_text, $created_at
+                )
+            "#,
+            id = self.id,
+            journal = self.journal,
+            diary_date = self.diary_date,
+            diary_text = self.diary_text,
+            created_at = self.created_at,
         );
-        let query = query_dyn!(&query)?;
+        query.execute(conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM diary_pending_appends ORDER BY created_at");
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        query.fetch(&conn).await.map_err(Into::into)
     }
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn delete_entry(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
-            "DELETE FROM diary_cache WHERE diary_datetime = $diary_datetime",
-            diary_datetime = self.diary_datetime
+            "DELETE FROM diary_pending_appends WHERE id = $id",
+            id = self.id
         );
         let conn = pool.get().await?;
         query.execute(&conn).await?;
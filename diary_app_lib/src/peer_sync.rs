@@ -0,0 +1,376 @@
+use anyhow::Error;
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::{header::AUTHORIZATION, Client};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::collections::{HashMap, HashSet};
+use time::Date;
+use url::Url;
+
+use crate::{
+    config::Config,
+    date_time_wrapper::DateTimeWrapper,
+    models::{default_journal, DiaryCache, DiaryEntries, DiaryTombstone, PEER_SOURCE},
+    pgpool::PgPool,
+};
+
+/// Summary of an entry used to decide what needs to be exchanged with a peer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EntryHash {
+    pub diary_date: Date,
+    pub hash: StackString,
+    pub last_modified: DateTimeWrapper,
+}
+
+impl From<&DiaryEntries> for EntryHash {
+    fn from(entry: &DiaryEntries) -> Self {
+        Self {
+            diary_date: entry.diary_date,
+            hash: entry.get_hash(),
+            last_modified: entry.last_modified,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PeerPullRequest {
+    /// Journal being exchanged, peers without journals only know "diary"
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    /// Hashes of every entry held by the requesting peer
+    pub hashes: Vec<EntryHash>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PeerPullResponse {
+    pub cache: Vec<DiaryCache>,
+    /// Entries the requesting peer is missing or holds an older version of
+    pub entries: Vec<DiaryEntries>,
+    /// Hashes of every entry held by the responding peer
+    pub hashes: Vec<EntryHash>,
+    /// Deletions which must not be undone by older copies of an entry
+    #[serde(default)]
+    pub tombstones: Vec<DiaryTombstone>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PeerPushRequest {
+    #[serde(default = "default_journal")]
+    pub journal: StackString,
+    pub entries: Vec<DiaryEntries>,
+    /// Cache entries received in the preceding pull, removed from the
+    /// responding peer so they are only merged once
+    pub pulled_cache: Vec<DateTimeWrapper>,
+    #[serde(default)]
+    pub tombstones: Vec<DiaryTombstone>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PeerPushResponse {
+    pub updated: Vec<Date>,
+    pub cache_cleared: usize,
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn get_entry_hashes(
+    pool: &PgPool,
+    journal: &str,
+) -> Result<HashMap<Date, EntryHash>, Error> {
+    DiaryEntries::get_all(journal, pool)
+        .await?
+        .map_err(Into::into)
+        .map_ok(|entry| (entry.diary_date, EntryHash::from(&entry)))
+        .try_collect()
+        .await
+}
+
+/// Dates held in `local` which are missing from `remote` or differ from
+/// and are more recent than the `remote` version
+#[must_use]
+pub fn newer_dates(local: &HashMap<Date, EntryHash>, remote: &[EntryHash]) -> HashSet<Date> {
+    let remote: HashMap<_, _> = remote.iter().map(|h| (h.diary_date, h)).collect();
+    local
+        .values()
+        .filter(|l| match remote.get(&l.diary_date) {
+            Some(r) => l.hash != r.hash && l.last_modified > r.last_modified,
+            None => true,
+        })
+        .map(|l| l.diary_date)
+        .collect()
+}
+
+async fn get_entries(
+    pool: &PgPool,
+    journal: &str,
+    dates: &HashSet<Date>,
+) -> Result<Vec<DiaryEntries>, Error> {
+    let futures = dates
+        .iter()
+        .map(|date| DiaryEntries::get_by_date(journal, *date, pool));
+    let entries: Vec<_> = stream::iter(futures)
+        .buffer_unordered(10)
+        .try_collect()
+        .await?;
+    Ok(entries.into_iter().flatten().collect())
+}
+
+/// Entries which changed on the other side are upserted, recording
+/// conflicts for removed lines the same way the s3 and local imports do
+/// # Errors
+/// Return error if db query fails
+pub async fn apply_entries(pool: &PgPool, entries: &[DiaryEntries]) -> Result<Vec<Date>, Error> {
+    let mut updated = Vec::new();
+    for entry in entries {
+        entry
+            .clone()
+            .with_source(Some(PEER_SOURCE))
+            .upsert_entry(pool, true)
+            .await?;
+        updated.push(entry.diary_date);
+    }
+    Ok(updated)
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn get_tombstones(pool: &PgPool, journal: &str) -> Result<Vec<DiaryTombstone>, Error> {
+    DiaryTombstone::get_all(pool)
+        .await?
+        .try_filter(|t| {
+            let matches = t.journal == journal;
+            async move { matches }
+        })
+        .try_collect()
+        .await
+        .map_err(Into::into)
+}
+
+/// Record the peer's tombstones and move local entries which were deleted
+/// on the other side, and not modified since, to the trash
+/// # Errors
+/// Return error if db query fails
+pub async fn apply_tombstones(
+    pool: &PgPool,
+    tombstones: &[DiaryTombstone],
+) -> Result<Vec<Date>, Error> {
+    let mut deleted = Vec::new();
+    for tombstone in tombstones {
+        tombstone.upsert(pool).await?;
+        if let Some(entry) =
+            DiaryEntries::get_by_date(&tombstone.journal, tombstone.diary_date, pool).await?
+        {
+            if entry.last_modified <= tombstone.deleted_at {
+                entry.delete_entry(pool).await?;
+                deleted.push(entry.diary_date);
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+/// Cache entries of `journal` not yet merged into entries
+async fn get_cache(pool: &PgPool, journal: &str) -> Result<Vec<DiaryCache>, Error> {
+    DiaryCache::get_cache_entries(pool)
+        .await?
+        .try_filter(|entry| {
+            let matches = entry.journal == journal;
+            async move { matches }
+        })
+        .try_collect()
+        .await
+        .map_err(Into::into)
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn handle_pull(pool: &PgPool, req: PeerPullRequest) -> Result<PeerPullResponse, Error> {
+    let local = get_entry_hashes(pool, &req.journal).await?;
+    let dates = newer_dates(&local, &req.hashes);
+    let entries = get_entries(pool, &req.journal, &dates).await?;
+    let cache = get_cache(pool, &req.journal).await?;
+    let tombstones = get_tombstones(pool, &req.journal).await?;
+    Ok(PeerPullResponse {
+        cache,
+        entries,
+        hashes: local.into_values().collect(),
+        tombstones,
+    })
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn handle_push(pool: &PgPool, req: PeerPushRequest) -> Result<PeerPushResponse, Error> {
+    apply_tombstones(pool, &req.tombstones).await?;
+    let updated = apply_entries(pool, &req.entries).await?;
+    let pulled: HashSet<_> = req.pulled_cache.into_iter().collect();
+    let cache: Vec<_> = get_cache(pool, &req.journal)
+        .await?
+        .into_iter()
+        .filter(|entry| pulled.contains(&entry.diary_datetime))
+        .collect();
+    for entry in &cache {
+        entry.delete_entry(pool).await?;
+    }
+    Ok(PeerPushResponse {
+        updated,
+        cache_cleared: cache.len(),
+    })
+}
+
+/// Client for the `/api/peer/pull` and `/api/peer/push` endpoints of another
+/// diary_app instance
+#[derive(Clone, Debug)]
+pub struct PeerClient {
+    client: Client,
+    url: Url,
+    token: StackString,
+}
+
+impl PeerClient {
+    /// Returns `None` unless both `peer_url` and `peer_token` are set
+    #[must_use]
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let url = config.peer_url.as_ref()?.parse().ok()?;
+        let token = config.peer_token.clone()?;
+        Some(Self {
+            client: Client::new(),
+            url,
+            token,
+        })
+    }
+
+    #[must_use]
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    async fn post<T: Serialize, U: DeserializeOwned>(
+        &self,
+        path: &str,
+        data: &T,
+    ) -> Result<U, Error> {
+        let url = self.url.join(path)?;
+        self.client
+            .post(url)
+            .header(
+                AUTHORIZATION,
+                format_sstr!("Bearer {}", self.token).as_str(),
+            )
+            .json(data)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if the request fails
+    pub async fn pull(&self, req: &PeerPullRequest) -> Result<PeerPullResponse, Error> {
+        self.post("api/peer/pull", req).await
+    }
+
+    /// # Errors
+    /// Return error if the request fails
+    pub async fn push(&self, req: &PeerPushRequest) -> Result<PeerPushResponse, Error> {
+        self.post("api/peer/push", req).await
+    }
+}
+
+/// Pull changes to `journal` from the peer then push back whatever the peer
+/// is missing, returns a line per exchanged item
+/// # Errors
+/// Return error if db query or a peer request fails
+pub async fn sync_with_peer(
+    pool: &PgPool,
+    peer: &PeerClient,
+    journal: &str,
+) -> Result<Vec<StackString>, Error> {
+    let mut output = Vec::new();
+    let local = get_entry_hashes(pool, journal).await?;
+    let resp = peer
+        .pull(&PeerPullRequest {
+            journal: journal.into(),
+            hashes: local.values().cloned().collect(),
+        })
+        .await?;
+    for date in apply_tombstones(pool, &resp.tombstones).await? {
+        output.push(format_sstr!("peer delete {date}"));
+    }
+
+    let cache_set: HashSet<_> = DiaryCache::get_cache_entries(pool)
+        .await?
+        .map_ok(|entry| entry.diary_datetime)
+        .try_collect()
+        .await?;
+    let mut pulled_cache = Vec::new();
+    for entry in &resp.cache {
+        if !cache_set.contains(&entry.diary_datetime) {
+            entry.insert_entry(pool).await?;
+            output.push(format_sstr!("peer cache {}", entry.diary_datetime));
+        }
+        pulled_cache.push(entry.diary_datetime);
+    }
+    for date in apply_entries(pool, &resp.entries).await? {
+        output.push(format_sstr!("peer pull {date}"));
+    }
+
+    let pulled: HashSet<_> = resp.entries.iter().map(|e| e.diary_date).collect();
+    let dates: HashSet<_> = newer_dates(&local, &resp.hashes)
+        .difference(&pulled)
+        .copied()
+        .collect();
+    let entries = get_entries(pool, journal, &dates).await?;
+    let tombstones = get_tombstones(pool, journal).await?;
+    let result = peer
+        .push(&PeerPushRequest {
+            journal: journal.into(),
+            entries,
+            pulled_cache,
+            tombstones,
+        })
+        .await?;
+    for date in result.updated {
+        output.push(format_sstr!("peer push {date}"));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use time::macros::{date, datetime};
+
+    use crate::{
+        date_time_wrapper::DateTimeWrapper,
+        models::DiaryEntries,
+        peer_sync::{newer_dates, EntryHash},
+    };
+
+    #[test]
+    fn test_newer_dates() {
+        let mut old = DiaryEntries::new(date!(2024 - 01 - 01), "old text");
+        old.last_modified = DateTimeWrapper::from_offsetdatetime(datetime!(2024-01-01 00:00 UTC));
+        let new = DiaryEntries::new(date!(2024 - 01 - 01), "new text");
+        let other = DiaryEntries::new(date!(2024 - 01 - 02), "other text");
+
+        let local: HashMap<_, _> = [&new, &other]
+            .iter()
+            .map(|e| (e.diary_date, EntryHash::from(*e)))
+            .collect();
+        let remote = vec![EntryHash::from(&old)];
+        let dates = newer_dates(&local, &remote);
+        assert_eq!(dates.len(), 2);
+
+        let local: HashMap<_, _> = vec![(old.diary_date, EntryHash::from(&old))]
+            .into_iter()
+            .collect();
+        let remote = vec![EntryHash::from(&new)];
+        assert!(newer_dates(&local, &remote).is_empty());
+
+        let remote = vec![EntryHash::from(&old)];
+        assert!(newer_dates(&local, &remote).is_empty());
+    }
+}
@@ -8,11 +8,14 @@ pub use tokio_postgres::Transaction as PgTransaction;
 
 use stack_string::StackString;
 
+use crate::retry::RetryPolicy;
+
 #[derive(Clone, Deref)]
 pub struct PgPool {
     pgurl: Arc<StackString>,
     #[deref]
     pool: Pool,
+    retry_policy: RetryPolicy,
 }
 
 impl fmt::Debug for PgPool {
@@ -49,12 +52,24 @@ impl PgPool {
         Ok(Self {
             pgurl: Arc::new(pgurl.into()),
             pool,
+            retry_policy: RetryPolicy::connect(),
         })
     }
 
+    /// Retry getting connections with `retry_policy` rather than
+    /// [`RetryPolicy::connect`], for batch processes like the cli sync which
+    /// would rather wait for the database to come back
+    #[must_use]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     /// # Errors
     /// Return error if getting client fail
     pub async fn get(&self) -> Result<Client, Error> {
-        self.pool.get().await.map_err(Into::into)
+        self.retry_policy
+            .retry(|| async { self.pool.get().await.map_err(Into::into) })
+            .await
     }
 }
@@ -0,0 +1,164 @@
+use rand::{seq::SliceRandom, Rng};
+use stack_string::{format_sstr, StackString};
+use time::{Date, Month};
+
+use crate::config::ConfigInner;
+
+/// Number of days either side of an anniversary which still counts as close
+const NEARBY_DAYS: i64 = 3;
+
+/// Relative probability of resurfacing an entry depending on how close its
+/// date falls to the same day of the year as today
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResurfaceWeights {
+    pub anniversary: f64,
+    pub nearby: f64,
+    pub other: f64,
+    /// Entries more recent than this are never resurfaced
+    pub min_age_days: i64,
+}
+
+impl From<&ConfigInner> for ResurfaceWeights {
+    fn from(config: &ConfigInner) -> Self {
+        Self {
+            anniversary: config.resurface_anniversary_weight,
+            nearby: config.resurface_nearby_weight,
+            other: config.resurface_other_weight,
+            min_age_days: config.resurface_min_age_days,
+        }
+    }
+}
+
+/// `date` moved to `year`, Feb 29 becomes Feb 28 outside of leap years
+fn same_day_in_year(date: Date, year: i32) -> Date {
+    date.replace_year(year)
+        .or_else(|_| Date::from_calendar_date(year, Month::February, 28))
+        .unwrap_or(date)
+}
+
+/// Days between the day of the year of `date` and of `today`, wrapping
+/// around the new year
+fn days_from_anniversary(date: Date, today: Date) -> i64 {
+    [today.year() - 1, today.year(), today.year() + 1]
+        .iter()
+        .map(|year| (same_day_in_year(date, *year) - today).whole_days().abs())
+        .min()
+        .unwrap_or(i64::MAX)
+}
+
+#[must_use]
+pub fn resurface_weight(date: Date, today: Date, weights: &ResurfaceWeights) -> f64 {
+    if (today - date).whole_days() < weights.min_age_days {
+        return 0.0;
+    }
+    match days_from_anniversary(date, today) {
+        0 => weights.anniversary,
+        d if d <= NEARBY_DAYS => weights.nearby,
+        _ => weights.other,
+    }
+}
+
+/// Pick one of `dates` at random according to `weights`, `None` if no date
+/// is eligible
+pub fn choose_date<R: Rng>(
+    dates: &[Date],
+    today: Date,
+    weights: &ResurfaceWeights,
+    rng: &mut R,
+) -> Option<Date> {
+    dates
+        .choose_weighted(rng, |d| resurface_weight(*d, today, weights))
+        .ok()
+        .copied()
+}
+
+/// Fill in `{date}`, `{years}` and `{text}` in the resurfacing template
+#[must_use]
+pub fn render_template(template: &str, date: Date, today: Date, text: &str) -> StackString {
+    let years = today.year() - date.year();
+    template
+        .replace("{date}", &format_sstr!("{date}"))
+        .replace("{years}", &format_sstr!("{years}"))
+        .replace("{text}", text)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::thread_rng;
+    use time::macros::date;
+
+    use crate::resurface::{choose_date, render_template, resurface_weight, ResurfaceWeights};
+
+    const WEIGHTS: ResurfaceWeights = ResurfaceWeights {
+        anniversary: 20.0,
+        nearby: 5.0,
+        other: 1.0,
+        min_age_days: 30,
+    };
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_resurface_weight() {
+        let today = date!(2024 - 03 - 01);
+        assert_eq!(
+            resurface_weight(date!(2019 - 03 - 01), today, &WEIGHTS),
+            20.0
+        );
+        assert_eq!(
+            resurface_weight(date!(2019 - 03 - 03), today, &WEIGHTS),
+            5.0
+        );
+        assert_eq!(
+            resurface_weight(date!(2020 - 02 - 28), today, &WEIGHTS),
+            5.0
+        );
+        assert_eq!(
+            resurface_weight(date!(2019 - 07 - 14), today, &WEIGHTS),
+            1.0
+        );
+        assert_eq!(
+            resurface_weight(date!(2024 - 02 - 20), today, &WEIGHTS),
+            0.0
+        );
+
+        let today = date!(2023 - 02 - 28);
+        assert_eq!(
+            resurface_weight(date!(2020 - 02 - 29), today, &WEIGHTS),
+            20.0
+        );
+
+        let today = date!(2024 - 01 - 02);
+        assert_eq!(
+            resurface_weight(date!(2022 - 12 - 31), today, &WEIGHTS),
+            5.0
+        );
+    }
+
+    #[test]
+    fn test_choose_date() {
+        let today = date!(2024 - 03 - 01);
+        let mut rng = thread_rng();
+        assert_eq!(choose_date(&[], today, &WEIGHTS, &mut rng), None);
+        assert_eq!(
+            choose_date(&[date!(2024 - 02 - 25)], today, &WEIGHTS, &mut rng),
+            None
+        );
+        let dates = [date!(2024 - 02 - 25), date!(2021 - 08 - 01)];
+        assert_eq!(
+            choose_date(&dates, today, &WEIGHTS, &mut rng),
+            Some(date!(2021 - 08 - 01))
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let output = render_template(
+            "{years} years ago, {date}:\n{text}",
+            date!(2020 - 03 - 01),
+            date!(2024 - 03 - 01),
+            "hello",
+        );
+        assert_eq!(output, "4 years ago, 2020-03-01:\nhello");
+    }
+}
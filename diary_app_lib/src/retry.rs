@@ -0,0 +1,364 @@
+use anyhow::Error;
+use log::debug;
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+use tokio::time::sleep;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    S3,
+    Ssh,
+    Peer,
+    Database,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::S3 => "s3",
+            Self::Ssh => "ssh",
+            Self::Peer => "peer",
+            Self::Database => "database",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Failure of a remote backend, classified by whether retrying could help
+#[derive(ThisError, Debug)]
+pub enum BackendError {
+    #[error("{backend} transient error: {source}")]
+    Transient { backend: Backend, source: Error },
+    #[error("{backend} permanent error: {source}")]
+    Permanent { backend: Backend, source: Error },
+    #[error("{backend} disabled after repeated failures")]
+    CircuitOpen { backend: Backend },
+}
+
+impl BackendError {
+    pub fn new(backend: Backend, transient: bool, source: impl Into<Error>) -> Self {
+        let source = source.into();
+        if transient {
+            Self::Transient { backend, source }
+        } else {
+            Self::Permanent { backend, source }
+        }
+    }
+
+    /// Keep an already classified error, otherwise attribute `err` to
+    /// `backend` as a transient failure
+    #[must_use]
+    pub fn from_error(backend: Backend, err: Error) -> Self {
+        match err.downcast::<Self>() {
+            Ok(e) => e,
+            Err(source) => Self::Transient { backend, source },
+        }
+    }
+
+    #[must_use]
+    pub fn backend(&self) -> Backend {
+        match self {
+            Self::Transient { backend, .. }
+            | Self::Permanent { backend, .. }
+            | Self::CircuitOpen { backend } => *backend,
+        }
+    }
+
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient { .. })
+    }
+}
+
+/// Errors which aren't a [`BackendError`] are assumed to be transient
+#[must_use]
+pub fn is_transient(err: &Error) -> bool {
+    err.downcast_ref::<BackendError>()
+        .is_none_or(BackendError::is_transient)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JitterStrategy {
+    /// Sleep exactly the exponential delay
+    None,
+    /// Sleep a random duration between zero and the exponential delay
+    Full,
+    /// Sleep half the exponential delay plus a random duration up to the
+    /// other half
+    #[default]
+    Equal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: JitterStrategy,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(64),
+            jitter: JitterStrategy::Equal,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Short policy for getting a database connection, requests fail within
+    /// a second rather than waiting out a database outage
+    #[must_use]
+    pub fn connect() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(400),
+            jitter: JitterStrategy::Equal,
+        }
+    }
+
+    /// Delay to wait after the `attempt`-th failure (starting at zero)
+    #[must_use]
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let factor = 1u32.checked_shl(attempt as u32).unwrap_or(u32::MAX);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let millis = delay.as_millis() as u64;
+        match self.jitter {
+            JitterStrategy::None => delay,
+            JitterStrategy::Full => Duration::from_millis(thread_rng().gen_range(0..=millis)),
+            JitterStrategy::Equal => {
+                Duration::from_millis(millis / 2 + thread_rng().gen_range(0..=millis / 2))
+            }
+        }
+    }
+
+    /// Permanent errors (see [`is_transient`]) are returned immediately
+    /// # Errors
+    /// Return error if closure fails `max_attempts` times or fails with a
+    /// permanent error
+    pub async fn retry<T, U, F>(&self, f: T) -> Result<U, Error>
+    where
+        T: Fn() -> F,
+        F: Future<Output = Result<U, Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(resp) => return Ok(resp),
+                Err(err) => {
+                    attempt += 1;
+                    if attempt >= self.max_attempts || !is_transient(&err) {
+                        return Err(err);
+                    }
+                    let delay = self.delay(attempt - 1);
+                    debug!("attempt {attempt} failed with {err}, retrying in {delay:?}");
+                    sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: usize,
+    opened_at: Option<Instant>,
+}
+
+/// Stops calling a backend after `failure_threshold` consecutive failures,
+/// allowing a single trial call once `reset_timeout` has elapsed
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    backend: Backend,
+    failure_threshold: usize,
+    reset_timeout: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    #[must_use]
+    pub fn new(backend: Backend, failure_threshold: usize, reset_timeout: Duration) -> Self {
+        Self {
+            backend,
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    #[must_use]
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        let state = self.state.lock();
+        match state.opened_at {
+            Some(opened_at) => opened_at.elapsed() < self.reset_timeout,
+            None => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock();
+        state.failures = 0;
+        state.opened_at = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock();
+        state.failures += 1;
+        if state.failures >= self.failure_threshold {
+            state.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// # Errors
+    /// Return error if the circuit is open or if the future fails
+    pub async fn call<U, F>(&self, f: F) -> Result<U, BackendError>
+    where
+        F: Future<Output = Result<U, Error>>,
+    {
+        if self.is_open() {
+            return Err(BackendError::CircuitOpen {
+                backend: self.backend,
+            });
+        }
+        match f.await {
+            Ok(resp) => {
+                self.record_success();
+                Ok(resp)
+            }
+            Err(err) => {
+                self.record_failure();
+                Err(BackendError::from_error(self.backend, err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{format_err, Error};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::retry::{Backend, BackendError, CircuitBreaker, JitterStrategy, RetryPolicy};
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: JitterStrategy::None,
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(10), Duration::from_millis(1000));
+        assert_eq!(policy.delay(100), Duration::from_millis(1000));
+
+        let policy = RetryPolicy {
+            jitter: JitterStrategy::Equal,
+            ..policy
+        };
+        for attempt in 0..10 {
+            let delay = policy.delay(attempt);
+            assert!(delay >= Duration::from_millis(50));
+            assert!(delay <= Duration::from_millis(1000));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_retry() -> Result<(), Error> {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+            jitter: JitterStrategy::Full,
+        };
+        let counter = AtomicUsize::new(0);
+        let result: Result<(), Error> = policy
+            .retry(|| async {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(format_err!("failure"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+
+        let counter = AtomicUsize::new(0);
+        let result = policy
+            .retry(|| async {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(format_err!("failure"))
+                } else {
+                    Ok(1)
+                }
+            })
+            .await?;
+        assert_eq!(result, 1);
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+        let counter = AtomicUsize::new(0);
+        let result: Result<(), Error> = policy
+            .retry(|| async {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(BackendError::new(Backend::S3, false, format_err!("403")).into())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() -> Result<(), Error> {
+        let breaker = CircuitBreaker::new(Backend::S3, 2, Duration::from_secs(60));
+        let err = breaker
+            .call(async { Err::<(), _>(format_err!("fail")) })
+            .await
+            .unwrap_err();
+        assert!(err.is_transient());
+        assert_eq!(err.backend(), Backend::S3);
+        assert!(!breaker.is_open());
+        let err = breaker
+            .call(async {
+                Err::<(), _>(BackendError::new(Backend::S3, false, format_err!("404")).into())
+            })
+            .await
+            .unwrap_err();
+        assert!(!err.is_transient());
+        assert!(breaker.is_open());
+        let err = breaker.call(async { Ok(()) }).await.unwrap_err();
+        assert!(matches!(err, BackendError::CircuitOpen { .. }));
+        assert_eq!(&err.to_string(), "s3 disabled after repeated failures");
+
+        let breaker = CircuitBreaker::new(Backend::Ssh, 1, Duration::from_secs(0));
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+        breaker.call(async { Ok(()) }).await?;
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use stack_string::{format_sstr, StackString};
+
+use crate::{
+    archive::Compression,
+    line_diff::{DiffTag, LineDiff},
+};
+
+/// Suffix of delta keys, `{date}.txt.0001.delta` is the first delta applied
+/// on top of the snapshot `{date}.txt`
+const DELTA_SUFFIX: &str = ".delta";
+
+#[must_use]
+pub fn text_hash(text: &str) -> StackString {
+    format_sstr!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// Key of the `seq`th delta of the snapshot `key`
+#[must_use]
+pub fn delta_key(key: &str, seq: u32) -> StackString {
+    format_sstr!("{key}.{seq:04}{DELTA_SUFFIX}")
+}
+
+/// Snapshot key and sequence number of a delta key
+#[must_use]
+pub fn parse_delta_key(key: &str) -> Option<(&str, u32)> {
+    let (key, seq) = key.strip_suffix(DELTA_SUFFIX)?.rsplit_once('.')?;
+    Some((key, seq.parse().ok()?))
+}
+
+/// Lines `start..end` of the base text replaced by `lines`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeltaOp {
+    pub start: usize,
+    pub end: usize,
+    pub lines: Vec<StackString>,
+}
+
+/// Line changes uploaded instead of the whole text of long entries, only
+/// applies to the text hashed as `base_hash`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct S3Delta {
+    pub base_hash: StackString,
+    pub hash: StackString,
+    pub ops: Vec<DeltaOp>,
+}
+
+impl S3Delta {
+    #[must_use]
+    pub fn new(base: &str, text: &str) -> Self {
+        let mut ops: Vec<DeltaOp> = Vec::new();
+        for hunk in LineDiff::new(base, text).hunks {
+            let idx = hunk.old_line - 1;
+            match hunk.tag {
+                DiffTag::Same => {}
+                DiffTag::Rem => {
+                    let n = hunk.nlines();
+                    match ops.last_mut() {
+                        Some(op) if op.end == idx => op.end += n,
+                        _ => ops.push(DeltaOp {
+                            start: idx,
+                            end: idx + n,
+                            lines: Vec::new(),
+                        }),
+                    }
+                }
+                DiffTag::Add => {
+                    let lines = hunk.text.split('\n').map(Into::into);
+                    match ops.last_mut() {
+                        Some(op) if op.end == idx => op.lines.extend(lines),
+                        _ => ops.push(DeltaOp {
+                            start: idx,
+                            end: idx,
+                            lines: lines.collect(),
+                        }),
+                    }
+                }
+            }
+        }
+        Self {
+            base_hash: text_hash(base),
+            hash: text_hash(text),
+            ops,
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// # Errors
+    /// Return error if `base` isn't the text the delta was made from
+    pub fn apply(&self, base: &str) -> Result<String, Error> {
+        if text_hash(base) != self.base_hash {
+            return Err(format_err!("delta doesn't apply to this text"));
+        }
+        let mut lines: Vec<&str> = base.split('\n').collect();
+        // apply from the end so earlier ops keep their line numbers
+        for op in self.ops.iter().rev() {
+            if op.start > op.end || op.end > lines.len() {
+                return Err(format_err!("op {}..{} out of range", op.start, op.end));
+            }
+            lines.splice(op.start..op.end, op.lines.iter().map(StackString::as_str));
+        }
+        let text = lines.join("\n");
+        if text_hash(&text) != self.hash {
+            return Err(format_err!("delta produced the wrong text"));
+        }
+        Ok(text)
+    }
+
+    /// # Errors
+    /// Return error if serialization fails
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        Compression::Zstd.compress(&serde_json::to_vec(self)?)
+    }
+
+    /// # Errors
+    /// Return error if `data` isn't an encoded delta
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(&Compression::Zstd.decompress(data)?).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::s3_delta::{delta_key, parse_delta_key, S3Delta};
+
+    #[test]
+    fn test_delta_key() {
+        let key = delta_key("dreams/2024-03-01.txt", 12);
+        assert_eq!(key, "dreams/2024-03-01.txt.0012.delta");
+        assert_eq!(parse_delta_key(&key), Some(("dreams/2024-03-01.txt", 12)));
+        assert_eq!(parse_delta_key("2024-03-01.txt"), None);
+    }
+
+    #[test]
+    fn test_s3_delta() -> Result<(), Error> {
+        let pairs = [
+            ("a\nb\nc", "a\nB\nc\nd"),
+            ("a\nb\nc\nd", "b\nc\nx\ny"),
+            ("same", "same"),
+        ];
+        for (base, text) in pairs {
+            let delta = S3Delta::new(base, text);
+            assert_eq!(delta.apply(base)?, text);
+            let decoded = S3Delta::decode(&delta.encode()?)?;
+            assert_eq!(decoded, delta);
+        }
+        assert!(S3Delta::new("same", "same").is_empty());
+        assert!(S3Delta::new("a", "b").apply("c").is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_s3_delta_round_trip(
+            base in vec("[a-c]{1,2}", 1..8),
+            text in vec("[a-c]{1,2}", 1..8),
+        ) {
+            let (base, text) = (base.join("\n"), text.join("\n"));
+            let delta = S3Delta::new(&base, &text);
+            prop_assert_eq!(delta.apply(&base).ok(), Some(text));
+        }
+    }
+}
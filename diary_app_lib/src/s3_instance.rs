@@ -1,21 +1,65 @@
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use aws_config::SdkConfig;
 use aws_sdk_s3::{
+    config::{http::HttpResponse, Builder as S3ConfigBuilder, Region},
+    error::SdkError,
     operation::list_objects::ListObjectsOutput,
-    types::{Bucket, Object},
+    types::{
+        Bucket, BucketLifecycleConfiguration, BucketVersioningStatus, ExpirationStatus,
+        LifecycleRule, LifecycleRuleFilter, NoncurrentVersionExpiration,
+        NoncurrentVersionTransition, Object, ObjectVersion, TransitionStorageClass,
+        VersioningConfiguration,
+    },
     Client as S3Client,
 };
 use bytes::Bytes;
 use std::fmt;
-use time::OffsetDateTime;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::io::AsyncReadExt;
 
-use crate::exponential_retry;
+use crate::{
+    archive::Compression,
+    retry::{Backend, BackendError, RetryPolicy},
+};
+
+/// User metadata key (sent as `x-amz-meta-entry-modified`) holding the
+/// `last_modified` of the diary entry at the time it was uploaded
+pub const ENTRY_MODIFIED_KEY: &str = "entry-modified";
+
+/// Id of the lifecycle rule managed by `set_version_lifecycle`
+const VERSION_LIFECYCLE_RULE: &str = "diary-noncurrent-versions";
+
+/// Throttling, timeouts, server errors and network failures are worth
+/// retrying, anything else (403, 404, invalid requests) is not
+fn s3_error<E>(err: SdkError<E, HttpResponse>) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let transient = match &err {
+        SdkError::ConstructionFailure(_) => false,
+        _ => err.raw_response().is_none_or(|resp| {
+            let status = resp.status();
+            status.is_server_error() || matches!(status.as_u16(), 408 | 429)
+        }),
+    };
+    BackendError::new(Backend::S3, transient, err).into()
+}
+
+/// Body of an object as stored, compressed as given by its
+/// `Content-Encoding`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Object {
+    pub data: Vec<u8>,
+    pub compression: Compression,
+    pub entry_modified: OffsetDateTime,
+}
 
 #[derive(Clone)]
 pub struct S3Instance {
     s3_client: S3Client,
     max_keys: Option<i32>,
+    retry_policy: RetryPolicy,
+    compression: Compression,
 }
 
 impl fmt::Debug for S3Instance {
@@ -30,6 +74,22 @@ impl S3Instance {
         Self {
             s3_client: S3Client::from_conf(sdk_config.into()),
             max_keys: None,
+            retry_policy: RetryPolicy::default(),
+            compression: Compression::None,
+        }
+    }
+
+    /// Client for buckets in `region` instead of the configured one
+    #[must_use]
+    pub fn new_in_region(sdk_config: &SdkConfig, region: &str) -> Self {
+        let s3_config = S3ConfigBuilder::from(sdk_config)
+            .region(Region::new(region.to_string()))
+            .build();
+        Self {
+            s3_client: S3Client::from_conf(s3_config),
+            max_keys: None,
+            retry_policy: RetryPolicy::default(),
+            compression: Compression::None,
         }
     }
 
@@ -39,18 +99,32 @@ impl S3Instance {
         self
     }
 
+    #[must_use]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Compression of text uploaded by `upload_from_string`
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
     /// # Errors
     /// Return error if s3 api fails
     pub async fn get_list_of_buckets(&self) -> Result<Vec<Bucket>, Error> {
-        exponential_retry(|| async move {
-            self.s3_client
-                .list_buckets()
-                .send()
-                .await
-                .map(|l| l.buckets.unwrap_or_default())
-                .map_err(Into::into)
-        })
-        .await
+        self.retry_policy
+            .retry(|| async move {
+                self.s3_client
+                    .list_buckets()
+                    .send()
+                    .await
+                    .map(|l| l.buckets.unwrap_or_default())
+                    .map_err(s3_error)
+            })
+            .await
     }
 
     /// # Errors
@@ -60,22 +134,106 @@ impl S3Instance {
         input_str: &str,
         bucket_name: &str,
         key_name: &str,
+        entry_modified: Option<OffsetDateTime>,
     ) -> Result<(), Error> {
-        exponential_retry(|| async move {
-            let body = Bytes::copy_from_slice(input_str.as_bytes()).into();
-            self.s3_client
-                .put_object()
-                .bucket(bucket_name)
-                .key(key_name)
-                .body(body)
-                .send()
-                .await
-                .map(|_| ())
-                .map_err(Into::into)
-        })
+        let data = self.compression.compress(input_str.as_bytes())?;
+        self.put_object(
+            &data,
+            self.compression,
+            bucket_name,
+            key_name,
+            entry_modified,
+        )
         .await
     }
 
+    /// Upload `data` as is, without compression
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn upload_bytes(
+        &self,
+        data: &[u8],
+        bucket_name: &str,
+        key_name: &str,
+        entry_modified: Option<OffsetDateTime>,
+    ) -> Result<(), Error> {
+        self.put_object(
+            data,
+            Compression::None,
+            bucket_name,
+            key_name,
+            entry_modified,
+        )
+        .await
+    }
+
+    /// Upload an object downloaded by `get_object` unchanged
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn upload_object(
+        &self,
+        obj: &S3Object,
+        bucket_name: &str,
+        key_name: &str,
+    ) -> Result<(), Error> {
+        self.put_object(
+            &obj.data,
+            obj.compression,
+            bucket_name,
+            key_name,
+            Some(obj.entry_modified),
+        )
+        .await
+    }
+
+    async fn put_object(
+        &self,
+        data: &[u8],
+        compression: Compression,
+        bucket_name: &str,
+        key_name: &str,
+        entry_modified: Option<OffsetDateTime>,
+    ) -> Result<(), Error> {
+        let entry_modified = entry_modified.and_then(|d| d.format(&Rfc3339).ok());
+        let entry_modified = entry_modified.as_ref();
+        self.retry_policy
+            .retry(|| async move {
+                let body = Bytes::copy_from_slice(data).into();
+                let mut builder = self
+                    .s3_client
+                    .put_object()
+                    .bucket(bucket_name)
+                    .key(key_name)
+                    .set_content_encoding(compression.content_encoding().map(Into::into))
+                    .body(body);
+                if let Some(entry_modified) = entry_modified {
+                    builder = builder.metadata(ENTRY_MODIFIED_KEY, entry_modified);
+                }
+                builder.send().await.map(|_| ()).map_err(s3_error)
+            })
+            .await
+    }
+
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn delete_key(&self, bucket_name: &str, key_name: &str) -> Result<(), Error> {
+        self.retry_policy
+            .retry(|| async move {
+                self.s3_client
+                    .delete_object()
+                    .bucket(bucket_name)
+                    .key(key_name)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(s3_error)
+            })
+            .await
+    }
+
+    /// Returns the object body along with the entry modification time stored
+    /// in the object metadata, falling back to the object's `LastModified`
+    /// for keys uploaded without it
     /// # Errors
     /// Return error if s3 api fails
     pub async fn download_to_string(
@@ -83,24 +241,108 @@ impl S3Instance {
         bucket_name: &str,
         key_name: &str,
     ) -> Result<(String, OffsetDateTime), Error> {
-        exponential_retry(|| async move {
-            let resp = self
-                .s3_client
-                .get_object()
-                .bucket(bucket_name)
-                .key(key_name)
-                .send()
-                .await?;
-            let last_modified = resp
-                .last_modified
-                .and_then(|t| OffsetDateTime::from_unix_timestamp(t.as_secs_f64() as i64).ok())
-                .unwrap_or_else(OffsetDateTime::now_utc);
-
-            let mut buf = String::new();
-            resp.body.into_async_read().read_to_string(&mut buf).await?;
-            Ok((buf, last_modified))
-        })
-        .await
+        self.download_version(bucket_name, key_name, None).await
+    }
+
+    /// Same as `download_to_string` for a previous version of the object,
+    /// or the current one if `version_id` is `None`
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn download_version(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        version_id: Option<&str>,
+    ) -> Result<(String, OffsetDateTime), Error> {
+        let (data, last_modified) = self
+            .download_bytes(bucket_name, key_name, version_id)
+            .await?;
+        Ok((String::from_utf8(data)?, last_modified))
+    }
+
+    /// Body of the object, decompressed if it was uploaded compressed, along
+    /// with its entry modification time
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn download_bytes(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        version_id: Option<&str>,
+    ) -> Result<(Vec<u8>, OffsetDateTime), Error> {
+        let obj = self.get_object(bucket_name, key_name, version_id).await?;
+        let data = obj.compression.decompress(&obj.data)?;
+        Ok((data, obj.entry_modified))
+    }
+
+    /// Upload the object again with the configured compression, returns
+    /// `false` if it is already compressed that way
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn recompress_key(&self, bucket_name: &str, key_name: &str) -> Result<bool, Error> {
+        let obj = self.get_object(bucket_name, key_name, None).await?;
+        if obj.compression == self.compression {
+            return Ok(false);
+        }
+        let text = String::from_utf8(obj.compression.decompress(&obj.data)?)?;
+        self.upload_from_string(&text, bucket_name, key_name, Some(obj.entry_modified))
+            .await?;
+        Ok(true)
+    }
+
+    /// Body of the object as stored, plain keys uploaded before compression
+    /// was configured have no `Content-Encoding`
+    /// # Errors
+    /// Return error if s3 api fails or the encoding is unknown
+    pub async fn get_object(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        version_id: Option<&str>,
+    ) -> Result<S3Object, Error> {
+        self.retry_policy
+            .retry(|| async move {
+                let resp = self
+                    .s3_client
+                    .get_object()
+                    .bucket(bucket_name)
+                    .key(key_name)
+                    .set_version_id(version_id.map(Into::into))
+                    .send()
+                    .await
+                    .map_err(s3_error)?;
+                let entry_modified = resp
+                    .metadata
+                    .as_ref()
+                    .and_then(|m| m.get(ENTRY_MODIFIED_KEY))
+                    .and_then(|s| OffsetDateTime::parse(s, &Rfc3339).ok());
+                let last_modified = entry_modified
+                    .or_else(|| {
+                        resp.last_modified.and_then(|t| {
+                            OffsetDateTime::from_unix_timestamp(t.as_secs_f64() as i64).ok()
+                        })
+                    })
+                    .unwrap_or_else(OffsetDateTime::now_utc);
+
+                let compression = match resp.content_encoding.as_deref() {
+                    Some(encoding) => {
+                        Compression::from_content_encoding(encoding).ok_or_else(|| {
+                            let err = format_err!("Unknown encoding {encoding}");
+                            BackendError::new(Backend::S3, false, err)
+                        })?
+                    }
+                    None => Compression::None,
+                };
+
+                let mut data = Vec::new();
+                resp.body.into_async_read().read_to_end(&mut data).await?;
+                Ok(S3Object {
+                    data,
+                    compression,
+                    entry_modified: last_modified,
+                })
+            })
+            .await
     }
 
     async fn list_keys(
@@ -120,7 +362,7 @@ impl S3Instance {
         if let Some(max_keys) = max_keys {
             builder = builder.max_keys(max_keys);
         }
-        builder.send().await.map_err(Into::into)
+        builder.send().await.map_err(s3_error)
     }
 
     /// # Errors
@@ -130,31 +372,142 @@ impl S3Instance {
         bucket: &str,
         prefix: Option<&str>,
     ) -> Result<Vec<Object>, Error> {
-        exponential_retry(|| async move {
-            let mut marker: Option<String> = None;
-            let mut list_of_keys = Vec::new();
-            let mut max_keys = self.max_keys;
-            loop {
-                let mut output = self
-                    .list_keys(bucket, prefix, marker.as_ref(), max_keys)
-                    .await?;
-                if let Some(contents) = output.contents.take() {
-                    if let Some(last) = contents.last() {
-                        if let Some(key) = &last.key {
-                            marker.replace(key.into());
+        self.retry_policy
+            .retry(|| async move {
+                let mut marker: Option<String> = None;
+                let mut list_of_keys = Vec::new();
+                let mut max_keys = self.max_keys;
+                loop {
+                    let mut output = self
+                        .list_keys(bucket, prefix, marker.as_ref(), max_keys)
+                        .await?;
+                    if let Some(contents) = output.contents.take() {
+                        if let Some(last) = contents.last() {
+                            if let Some(key) = &last.key {
+                                marker.replace(key.into());
+                            }
                         }
+                        if let Some(n) = max_keys {
+                            max_keys.replace(n - contents.len() as i32);
+                        }
+                        list_of_keys.extend_from_slice(&contents);
                     }
-                    if let Some(n) = max_keys {
-                        max_keys.replace(n - contents.len() as i32);
+                    if output.is_truncated == Some(false) || output.is_truncated.is_none() {
+                        break;
                     }
-                    list_of_keys.extend_from_slice(&contents);
                 }
-                if output.is_truncated == Some(false) || output.is_truncated.is_none() {
-                    break;
+                Ok(list_of_keys)
+            })
+            .await
+    }
+
+    /// Versions of `key_name`, most recent first
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn get_list_of_versions(
+        &self,
+        bucket: &str,
+        key_name: &str,
+    ) -> Result<Vec<ObjectVersion>, Error> {
+        self.retry_policy
+            .retry(|| async move {
+                let mut key_marker: Option<String> = None;
+                let mut version_marker: Option<String> = None;
+                let mut versions = Vec::new();
+                loop {
+                    let output = self
+                        .s3_client
+                        .list_object_versions()
+                        .bucket(bucket)
+                        .prefix(key_name)
+                        .set_key_marker(key_marker.take())
+                        .set_version_id_marker(version_marker.take())
+                        .send()
+                        .await
+                        .map_err(s3_error)?;
+                    versions.extend(
+                        output
+                            .versions
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter(|v| v.key.as_deref() == Some(key_name)),
+                    );
+                    if output.is_truncated != Some(true) {
+                        break;
+                    }
+                    key_marker = output.next_key_marker;
+                    version_marker = output.next_version_id_marker;
                 }
-            }
-            Ok(list_of_keys)
-        })
-        .await
+                Ok(versions)
+            })
+            .await
+    }
+
+    /// Keep previous versions of objects when they are overwritten or
+    /// deleted
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn enable_versioning(&self, bucket: &str) -> Result<(), Error> {
+        let versioning = VersioningConfiguration::builder()
+            .status(BucketVersioningStatus::Enabled)
+            .build();
+        let versioning = &versioning;
+        self.retry_policy
+            .retry(|| async move {
+                self.s3_client
+                    .put_bucket_versioning()
+                    .bucket(bucket)
+                    .versioning_configuration(versioning.clone())
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(s3_error)
+            })
+            .await
+    }
+
+    /// Move previous versions to glacier `transition_days` after they were
+    /// replaced and delete them after `expiration_days` if given, replaces
+    /// any other lifecycle rules of the bucket
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn set_version_lifecycle(
+        &self,
+        bucket: &str,
+        transition_days: i32,
+        expiration_days: Option<i32>,
+    ) -> Result<(), Error> {
+        let transition = NoncurrentVersionTransition::builder()
+            .noncurrent_days(transition_days)
+            .storage_class(TransitionStorageClass::Glacier)
+            .build();
+        let expiration = expiration_days.map(|days| {
+            NoncurrentVersionExpiration::builder()
+                .noncurrent_days(days)
+                .build()
+        });
+        let rule = LifecycleRule::builder()
+            .id(VERSION_LIFECYCLE_RULE)
+            .status(ExpirationStatus::Enabled)
+            .filter(LifecycleRuleFilter::builder().prefix("").build())
+            .noncurrent_version_transitions(transition)
+            .set_noncurrent_version_expiration(expiration)
+            .build()?;
+        let lifecycle = BucketLifecycleConfiguration::builder()
+            .rules(rule)
+            .build()?;
+        let lifecycle = &lifecycle;
+        self.retry_policy
+            .retry(|| async move {
+                self.s3_client
+                    .put_bucket_lifecycle_configuration()
+                    .bucket(bucket)
+                    .lifecycle_configuration(lifecycle.clone())
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(s3_error)
+            })
+            .await
     }
 }
@@ -1,30 +1,66 @@
 use anyhow::{format_err, Error};
+use async_trait::async_trait;
 use aws_config::SdkConfig;
-use aws_sdk_s3::types::Object;
-use futures::{stream::FuturesUnordered, TryStreamExt};
-use log::debug;
+use aws_sdk_s3::types::{Object, ObjectVersion};
+use futures::{stream, StreamExt, TryStreamExt};
+use log::{debug, error};
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use stack_string::{format_sstr, StackString};
 use std::{
     collections::HashMap,
     convert::{TryFrom, TryInto},
     sync::Arc,
+    time::Duration,
 };
 use time::{macros::format_description, Date, OffsetDateTime};
 use tokio::sync::RwLock;
 
-use crate::{config::Config, models::DiaryEntries, pgpool::PgPool, s3_instance::S3Instance};
+use crate::{
+    config::Config,
+    date_sync::sync_hash,
+    models::{
+        default_metadata, default_visibility, DateRange, DiaryEntries, S3EntryState, SyncBackend,
+        SyncWatermark, DEFAULT_JOURNAL,
+    },
+    pgpool::PgPool,
+    retry::{JitterStrategy, RetryPolicy},
+    s3_delta::{delta_key, parse_delta_key, S3Delta},
+    s3_instance::S3Instance,
+    sync_engine::{PgEntryStore, SyncEngine, SyncStore},
+    sync_progress::ProgressReporter,
+};
+
+/// Listing of each bucket and key prefix, tenants sharing a bucket have a
+/// listing of their own
+static KEY_CACHE: Lazy<RwLock<HashMap<StackString, Arc<[KeyMetaData]>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
 
-const TIME_BUFFER: i64 = 60;
+/// Backoff of the uploads waiting in the s3 outbox, `max_attempts` isn't
+/// used as uploads are retried until they succeed
+pub const OUTBOX_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 0,
+    base_delay: Duration::from_secs(60),
+    max_delay: Duration::from_secs(3600),
+    jitter: JitterStrategy::Equal,
+};
 
-static KEY_CACHE: Lazy<RwLock<(OffsetDateTime, Arc<[KeyMetaData]>)>> =
-    Lazy::new(|| RwLock::new((OffsetDateTime::now_utc(), Arc::new([]))));
+/// Keys of the default journal are `{date}.txt`, other journals are kept
+/// under `{journal}/{date}.txt`
+fn parse_key(key: &str) -> Result<(StackString, Date), Error> {
+    let (journal, filename) = key.split_once('/').unwrap_or((DEFAULT_JOURNAL, key));
+    let date = Date::parse(filename, format_description!("[year]-[month]-[day].txt"))?;
+    Ok((journal.into(), date))
+}
 
 #[derive(Debug, Clone)]
 struct KeyMetaData {
+    journal: StackString,
     date: Date,
     last_modified: OffsetDateTime,
     size: i64,
+    /// Sequence number of delta keys
+    delta: Option<u32>,
 }
 
 impl TryFrom<Object> for KeyMetaData {
@@ -35,267 +71,709 @@ impl TryFrom<Object> for KeyMetaData {
             .as_ref()
             .ok_or_else(|| format_err!("No Key"))?
             .into();
-        let date = Date::parse(&key, format_description!("[year]-[month]-[day].txt"))?;
+        let (journal, date, delta) = match parse_delta_key(&key) {
+            Some((snapshot_key, seq)) => {
+                let (journal, date) = parse_key(snapshot_key)?;
+                (journal, date, Some(seq))
+            }
+            None => {
+                let (journal, date) = parse_key(&key)?;
+                (journal, date, None)
+            }
+        };
         let last_modified = obj
             .last_modified
             .and_then(|d| OffsetDateTime::from_unix_timestamp(d.as_secs_f64() as i64).ok())
             .unwrap_or_else(OffsetDateTime::now_utc);
         let size = obj.size.ok_or_else(|| format_err!("No size"))?;
         Ok(Self {
+            journal,
             date,
             last_modified,
             size,
+            delta,
         })
     }
 }
 
+/// Snapshot of a date in s3 along with the deltas uploaded on top of it
+#[derive(Debug, Clone, PartialEq)]
+struct S3Entry {
+    /// Upload time of the most recent snapshot or delta
+    last_modified: OffsetDateTime,
+    /// Size of the snapshot, only the size of the entry when there are no
+    /// deltas
+    size: i64,
+    deltas: Vec<u32>,
+}
+
+fn get_s3_entries(keys: &[KeyMetaData], journal: &str) -> HashMap<Date, S3Entry> {
+    let mut entries: HashMap<Date, S3Entry> = HashMap::new();
+    for obj in keys.iter().filter(|obj| obj.journal == journal) {
+        let entry = entries.entry(obj.date).or_insert_with(|| S3Entry {
+            last_modified: obj.last_modified,
+            size: 0,
+            deltas: Vec::new(),
+        });
+        entry.last_modified = entry.last_modified.max(obj.last_modified);
+        match obj.delta {
+            Some(seq) => entry.deltas.push(seq),
+            None => entry.size = obj.size,
+        }
+    }
+    for entry in entries.values_mut() {
+        entry.deltas.sort_unstable();
+    }
+    entries
+}
+
+/// Objects of a journal in `diary_bucket` and the bytes they take up, not
+/// counting previous versions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct S3Storage {
+    pub objects: usize,
+    pub snapshot_bytes: i64,
+    pub delta_bytes: i64,
+}
+
+fn get_s3_storage(keys: &[KeyMetaData], journal: &str) -> S3Storage {
+    let mut storage = S3Storage::default();
+    for obj in keys.iter().filter(|obj| obj.journal == journal) {
+        storage.objects += 1;
+        match obj.delta {
+            Some(_) => storage.delta_bytes += obj.size,
+            None => storage.snapshot_bytes += obj.size,
+        }
+    }
+    storage
+}
+
 #[derive(Clone, Debug)]
 pub struct S3Interface {
     config: Config,
     s3_client: S3Instance,
     pool: PgPool,
+    progress: ProgressReporter,
+    journal: StackString,
+    date_range: DateRange,
 }
 
 impl S3Interface {
     #[must_use]
     pub fn new(config: Config, sdk_config: &SdkConfig, pool: PgPool) -> Self {
         Self {
-            s3_client: S3Instance::new(sdk_config),
+            s3_client: S3Instance::new(sdk_config)
+                .with_retry_policy(config.retry_policy())
+                .with_compression(config.s3_compression),
             pool,
             config,
+            progress: ProgressReporter::new(),
+            journal: DEFAULT_JOURNAL.into(),
+            date_range: DateRange::default(),
         }
     }
 
+    #[must_use]
+    pub fn with_progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    #[must_use]
+    pub fn with_journal(mut self, journal: impl Into<StackString>) -> Self {
+        self.journal = journal.into();
+        self
+    }
+
+    /// Limit `import_from_s3` and `export_to_s3` to the dates in `date_range`
+    #[must_use]
+    pub fn with_date_range(mut self, date_range: DateRange) -> Self {
+        self.date_range = date_range;
+        self
+    }
+
+    fn get_key(&self, date: Date) -> StackString {
+        format_sstr!("{}{date}.txt", self.config.journal_prefix(&self.journal))
+    }
+
+    fn cache_key(&self) -> StackString {
+        format_sstr!(
+            "{}/{}",
+            self.config.diary_bucket,
+            self.config.s3_key_prefix()
+        )
+    }
+
     async fn fill_cache(&self) -> Result<(), Error> {
+        let prefix = self.config.s3_key_prefix();
         let list_of_keys = self
             .s3_client
-            .get_list_of_keys(&self.config.diary_bucket, None)
+            .get_list_of_keys(
+                &self.config.diary_bucket,
+                Some(prefix).filter(|p| !p.is_empty()),
+            )
             .await?;
-        *KEY_CACHE.write().await = (
-            OffsetDateTime::now_utc(),
-            list_of_keys
-                .into_iter()
-                .filter_map(|obj| obj.try_into().ok())
-                .collect(),
-        );
+        // keys are parsed relative to the prefix
+        let keys = list_of_keys
+            .into_iter()
+            .filter_map(|mut obj| {
+                let key = obj.key.as_deref()?.strip_prefix(prefix)?.to_string();
+                obj.key = Some(key);
+                obj.try_into().ok()
+            })
+            .collect();
+        KEY_CACHE.write().await.insert(self.cache_key(), keys);
         Ok(())
     }
 
+    async fn cached_keys(&self) -> Arc<[KeyMetaData]> {
+        KEY_CACHE
+            .read()
+            .await
+            .get(&self.cache_key())
+            .cloned()
+            .unwrap_or_else(|| Arc::new([]))
+    }
+
+    /// Refresh the cached listing of the bucket, returns the number of keys
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn warm_cache(&self) -> Result<usize, Error> {
+        self.fill_cache().await?;
+        Ok(self.cached_keys().await.len())
+    }
+
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn get_storage(&self) -> Result<S3Storage, Error> {
+        self.fill_cache().await?;
+        Ok(get_s3_storage(&self.cached_keys().await, &self.journal))
+    }
+
+    fn sync_engine<'a>(&'a self, db: &'a PgEntryStore) -> SyncEngine<'a> {
+        SyncEngine::new(db, self)
+            .with_date_range(self.date_range)
+            .with_concurrency(self.config.sync_concurrency)
+            .with_progress(self.progress.clone())
+    }
+
     /// # Errors
     /// Return error if s3 api fails
     pub async fn export_to_s3(&self) -> Result<Vec<DiaryEntries>, Error> {
-        {
-            let key_cache = KEY_CACHE.read().await;
-            if (OffsetDateTime::now_utc() - key_cache.0).whole_seconds() > 5 * TIME_BUFFER {
-                self.fill_cache().await?;
-            }
-        }
-        let s3_key_map: HashMap<Date, (OffsetDateTime, i64)> = KEY_CACHE
-            .read()
-            .await
-            .1
-            .iter()
-            .map(|obj| (obj.date, (obj.last_modified, obj.size)))
-            .collect();
-        let s3_key_map = Arc::new(s3_key_map);
-        {
-            let mut key_cache = KEY_CACHE.write().await;
-            key_cache.1 = Arc::new([]);
-        }
+        let db = PgEntryStore::new(&self.journal, self.pool.clone());
+        self.sync_engine(&db).export().await
+    }
 
-        let futures: FuturesUnordered<_> = DiaryEntries::get_modified_map(&self.pool, None, None)
-            .await?
+    /// Dates changed since they were last uploaded according to the
+    /// watermarks, found without reaching s3
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn unexported_dates(&self) -> Result<Vec<Date>, Error> {
+        let watermarks = SyncWatermark::get_map(&self.journal, SyncBackend::S3, &self.pool).await?;
+        let modified_map = DiaryEntries::get_modified_map(
+            &self.journal,
+            &self.pool,
+            self.date_range.since,
+            self.date_range.until,
+        )
+        .await?;
+        let mut dates: Vec<Date> = modified_map
             .into_iter()
-            .map(|(diary_date, last_modified)| {
-                let s3_key_map = s3_key_map.clone();
-                async move {
-                    let should_update = match s3_key_map.get(&diary_date) {
-                        Some((lm, s3_size)) => {
-                            if (last_modified - *lm).whole_seconds() > 0 {
-                                if let Some(entry) =
-                                    DiaryEntries::get_by_date(diary_date, &self.pool).await?
-                                {
-                                    let db_size = entry.diary_text.len() as i64;
-                                    if *s3_size != db_size {
-                                        debug!(
-                                            "last_modified {} {} {} {} {}",
-                                            diary_date, *lm, last_modified, s3_size, db_size
-                                        );
-                                    }
-                                    *s3_size < db_size
-                                } else {
-                                    false
-                                }
-                            } else {
-                                (last_modified - *lm).whole_seconds() > 0
-                            }
-                        }
-                        None => true,
-                    };
-                    if should_update {
-                        return self.upload_entry(diary_date).await;
-                    }
-                    Ok(None)
-                }
+            .filter(|(date, last_modified)| {
+                watermarks
+                    .get(date)
+                    .map_or(true, |w| *last_modified > OffsetDateTime::from(w.synced_at))
             })
+            .map(|(date, _)| date)
             .collect();
-        futures
-            .try_filter_map(|x| async move { Ok(x) })
-            .try_collect()
-            .await
+        dates.sort();
+        Ok(dates)
     }
 
     /// # Errors
     /// Return error if s3 api fails
     pub async fn upload_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
-        let Some(entry) = DiaryEntries::get_by_date(date, &self.pool).await? else {
+        let Some(entry) = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await? else {
             return Ok(None);
         };
-        if entry.diary_text.trim().is_empty() {
+        if entry.is_encrypted || entry.diary_text.trim().is_empty() {
             return Ok(None);
         }
+        if !self.upload_diary_entry(&entry).await? {
+            return Ok(None);
+        }
+        self.set_watermark(&entry).await?;
+        Ok(Some(entry))
+    }
+
+    /// Upload a delta or a snapshot of the entry, returns `false` if it's
+    /// unchanged since the last upload
+    async fn upload_diary_entry(&self, entry: &DiaryEntries) -> Result<bool, Error> {
+        let date = entry.diary_date;
         debug!(
-            "export s3 date {} lines {}",
-            entry.diary_date,
+            "export s3 date {date} lines {}",
             entry.diary_text.matches('\n').count()
         );
-        let key = format_sstr!("{}.txt", entry.diary_date);
+        let key = self.get_key(date);
+        let state = S3EntryState::get_by_date(&self.journal, date, &self.pool).await?;
+        let use_delta = self.use_delta(entry);
+        if let Some(state) = state
+            .as_ref()
+            .filter(|s| use_delta && (s.delta_count as u32) < self.config.s3_snapshot_every)
+        {
+            let delta = S3Delta::new(&state.uploaded_text, &entry.diary_text);
+            if delta.is_empty() {
+                return Ok(false);
+            }
+            // fall back to a snapshot rather than upload a delta which
+            // doesn't reproduce the entry
+            if delta.apply(&state.uploaded_text).is_ok() {
+                let seq = state.delta_count + 1;
+                self.s3_client
+                    .upload_bytes(
+                        &delta.encode()?,
+                        &self.config.diary_bucket,
+                        &delta_key(&key, seq as u32),
+                        Some(entry.last_modified.into()),
+                    )
+                    .await?;
+                S3EntryState::new(&self.journal, date, entry.diary_text.clone(), seq)
+                    .upsert(&self.pool)
+                    .await?;
+                return Ok(true);
+            }
+        }
+        let old_deltas: Vec<u32> = (1..=state.map_or(0, |s| s.delta_count as u32)).collect();
+        self.upload_snapshot(entry, &old_deltas).await?;
+        Ok(true)
+    }
+
+    /// Upload a snapshot of the entry whatever is in s3, replacing any deltas
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn force_upload_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+        let Some(entry) = DiaryEntries::get_by_date(&self.journal, date, &self.pool).await? else {
+            return Ok(None);
+        };
+        if entry.is_encrypted || entry.diary_text.trim().is_empty() {
+            return Ok(None);
+        }
+        self.replace_diary_entry(&entry).await?;
+        self.set_watermark(&entry).await?;
+        Ok(Some(entry))
+    }
+
+    async fn replace_diary_entry(&self, entry: &DiaryEntries) -> Result<(), Error> {
+        let (_, old_deltas) = self.list_date_keys(entry.diary_date).await?;
+        self.upload_snapshot(entry, &old_deltas).await
+    }
+
+    fn use_delta(&self, entry: &DiaryEntries) -> bool {
+        self.config
+            .s3_delta_min_size
+            .is_some_and(|min_size| entry.diary_text.len() >= min_size)
+    }
+
+    /// Upload the whole entry and remove the deltas made obsolete by it
+    async fn upload_snapshot(&self, entry: &DiaryEntries, old_deltas: &[u32]) -> Result<(), Error> {
+        let key = self.get_key(entry.diary_date);
         self.s3_client
-            .upload_from_string(&entry.diary_text, &self.config.diary_bucket, &key)
+            .upload_from_string(
+                &entry.diary_text,
+                &self.config.diary_bucket,
+                &key,
+                Some(entry.last_modified.into()),
+            )
             .await?;
-        Ok(Some(entry))
+        for seq in old_deltas {
+            self.s3_client
+                .delete_key(&self.config.diary_bucket, &delta_key(&key, *seq))
+                .await?;
+        }
+        if self.use_delta(entry) {
+            S3EntryState::new(&self.journal, entry.diary_date, entry.diary_text.clone(), 0)
+                .upsert(&self.pool)
+                .await
+        } else {
+            S3EntryState::delete(&self.journal, entry.diary_date, &self.pool).await
+        }
     }
 
+    /// Record that s3 now holds the text of `entry`, the key was modified no
+    /// later than now
+    async fn set_watermark(&self, entry: &DiaryEntries) -> Result<(), Error> {
+        let hash = sync_hash(&entry.diary_text);
+        let now = OffsetDateTime::now_utc();
+        SyncWatermark::new(&self.journal, entry.diary_date, SyncBackend::S3, hash, now)
+            .upsert(&self.pool)
+            .await
+    }
+
+    /// Whether the snapshot of `date` exists, along with the sequence
+    /// numbers of its deltas
+    async fn list_date_keys(&self, date: Date) -> Result<(bool, Vec<u32>), Error> {
+        let key = self.get_key(date);
+        let keys = self
+            .s3_client
+            .get_list_of_keys(&self.config.diary_bucket, Some(&key))
+            .await?;
+        let mut has_snapshot = false;
+        let mut deltas = Vec::new();
+        for obj_key in keys.iter().filter_map(|obj| obj.key.as_deref()) {
+            if obj_key == key {
+                has_snapshot = true;
+            } else if let Some((snapshot_key, seq)) = parse_delta_key(obj_key) {
+                if snapshot_key == key {
+                    deltas.push(seq);
+                }
+            }
+        }
+        deltas.sort_unstable();
+        Ok((has_snapshot, deltas))
+    }
+
+    /// Entry reconstructed from the snapshot and deltas of `date`, `None` if
+    /// there is no snapshot
     /// # Errors
     /// Return error if s3 api fails
     pub async fn download_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
-        let key = format_sstr!("{date}.txt");
+        let (has_snapshot, deltas) = self.list_date_keys(date).await?;
+        if !has_snapshot {
+            return Ok(None);
+        }
+        let entry = self.download_with_deltas(date, &deltas).await?;
+        Ok(entry.map(|(entry, _)| entry))
+    }
+
+    /// Snapshot of `date` with `deltas` applied in order, along with whether
+    /// all of them applied
+    async fn download_with_deltas(
+        &self,
+        date: Date,
+        deltas: &[u32],
+    ) -> Result<Option<(DiaryEntries, bool)>, Error> {
+        let Some(mut entry) = self.download_version(date, None).await? else {
+            return Ok(None);
+        };
+        let key = self.get_key(date);
+        let mut text = entry.diary_text.to_string();
+        let mut complete = true;
+        for seq in deltas {
+            let (data, last_modified) = self
+                .s3_client
+                .download_bytes(&self.config.diary_bucket, &delta_key(&key, *seq), None)
+                .await?;
+            match S3Delta::decode(&data).and_then(|delta| delta.apply(&text)) {
+                Ok(new_text) => {
+                    text = new_text;
+                    entry.last_modified = last_modified.into();
+                }
+                Err(e) => {
+                    error!("delta {seq} of {date} doesn't apply {e}");
+                    complete = false;
+                    break;
+                }
+            }
+        }
+        entry.diary_text = text.into();
+        Ok(Some((entry, complete)))
+    }
+
+    /// Entry as stored in a previous version of its object, the current one
+    /// if `version_id` is `None`
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn download_version(
+        &self,
+        date: Date,
+        version_id: Option<&str>,
+    ) -> Result<Option<DiaryEntries>, Error> {
+        let key = self.get_key(date);
         let (text, last_modified) = self
             .s3_client
-            .download_to_string(&self.config.diary_bucket, &key)
+            .download_version(&self.config.diary_bucket, &key, version_id)
             .await?;
         if text.trim().is_empty() {
             return Ok(None);
         }
         let entry = DiaryEntries {
+            journal: self.journal.clone(),
             diary_date: date,
             diary_text: text.into(),
             last_modified: last_modified.into(),
+            is_encrypted: false,
+            diary_ciphertext: None,
+            diary_nonce: None,
+            deleted_at: None,
+            starred: false,
+            metadata: default_metadata(),
+            scheduled: false,
+            visibility: default_visibility(),
+            source: Some(SyncBackend::S3.to_str().into()),
         };
         Ok(Some(entry))
     }
 
+    /// Versions kept of the object of `date`, most recent first
     /// # Errors
     /// Return error if s3 api fails
-    pub async fn import_from_s3(&self) -> Result<Vec<DiaryEntries>, Error> {
-        let existing_map = Arc::new(DiaryEntries::get_modified_map(&self.pool, None, None).await?);
+    pub async fn get_versions(&self, date: Date) -> Result<Vec<ObjectVersion>, Error> {
+        let key = self.get_key(date);
+        self.s3_client
+            .get_list_of_versions(&self.config.diary_bucket, &key)
+            .await
+    }
 
-        debug!("{}", self.config.diary_bucket);
-        self.fill_cache().await?;
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn enable_versioning(&self) -> Result<(), Error> {
+        self.s3_client
+            .enable_versioning(&self.config.diary_bucket)
+            .await
+    }
 
-        let key_cache = KEY_CACHE.read().await.1.clone();
+    /// Apply `s3_glacier_transition_days` and `s3_noncurrent_expiration_days`
+    /// to the previous versions in the bucket
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn set_version_lifecycle(&self) -> Result<(), Error> {
+        self.s3_client
+            .set_version_lifecycle(
+                &self.config.diary_bucket,
+                self.config.s3_glacier_transition_days,
+                self.config.s3_noncurrent_expiration_days,
+            )
+            .await
+    }
 
-        let futures: FuturesUnordered<_> = key_cache
-            .iter()
-            .map(|obj| {
-                let existing_map = existing_map.clone();
-                async move {
-                    let mut insert_new = true;
-                    let should_modify = match existing_map.get(&obj.date) {
-                        Some(current_modified) => {
-                            insert_new =
-                                (*current_modified - obj.last_modified).whole_seconds() < 0;
-                            if (*current_modified - obj.last_modified).whole_seconds() < 0 {
-                                if let Some(entry) =
-                                    DiaryEntries::get_by_date(obj.date, &self.pool).await?
-                                {
-                                    let db_size = entry.diary_text.len() as i64;
-                                    if obj.size != db_size {
-                                        debug!(
-                                            "last_modified {} {} {} {} {}",
-                                            obj.date,
-                                            *current_modified,
-                                            obj.last_modified,
-                                            obj.size,
-                                            db_size
-                                        );
-                                    }
-                                    obj.size != db_size
-                                } else {
-                                    false
-                                }
-                            } else {
-                                (*current_modified - obj.last_modified).whole_seconds() < 0
-                            }
-                        }
-                        None => true,
-                    };
-                    if obj.size > 0 && should_modify {
-                        if let Some(entry) = self.download_entry(obj.date).await? {
-                            debug!(
-                                "import s3 date {} lines {}",
-                                entry.diary_date,
-                                entry.diary_text.matches('\n').count()
-                            );
-                            entry.upsert_entry(&self.pool, insert_new).await?;
-                            return Ok(Some(entry));
-                        }
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn delete_entry(&self, date: Date) -> Result<(), Error> {
+        let key = self.get_key(date);
+        let (_, deltas) = self.list_date_keys(date).await?;
+        for seq in deltas {
+            self.s3_client
+                .delete_key(&self.config.diary_bucket, &delta_key(&key, seq))
+                .await?;
+        }
+        self.s3_client
+            .delete_key(&self.config.diary_bucket, &key)
+            .await?;
+        SyncWatermark::delete(&self.journal, date, SyncBackend::S3, &self.pool).await?;
+        S3EntryState::delete(&self.journal, date, &self.pool).await
+    }
+
+    /// Upload every snapshot not compressed as configured by
+    /// `s3_compression` again, returns the recompressed dates
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn recompress_s3(&self) -> Result<Vec<Date>, Error> {
+        self.fill_cache().await?;
+        let s3_entries = get_s3_entries(&self.cached_keys().await, &self.journal);
+        let stage = self.progress.start("s3 recompress", s3_entries.len());
+        let futures: Vec<_> = s3_entries
+            .into_keys()
+            .map(|date| async move {
+                let key = self.get_key(date);
+                let recompressed = self
+                    .s3_client
+                    .recompress_key(&self.config.diary_bucket, &key)
+                    .await?;
+                Ok(recompressed.then_some(date))
+            })
+            .collect();
+        let mut dates: Vec<Date> = stream::iter(futures)
+            .buffer_unordered(self.config.sync_concurrency.max(1))
+            .inspect(|_| stage.increment())
+            .try_filter_map(|x| async move { Ok(x) })
+            .try_collect()
+            .await?;
+        dates.sort();
+        Ok(dates)
+    }
+
+    /// Replace the deltas of every date with a snapshot of the entry they
+    /// reconstruct, dates where a delta doesn't apply are left alone
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn compact_s3(&self) -> Result<Vec<Date>, Error> {
+        self.fill_cache().await?;
+        let s3_entries = get_s3_entries(&self.cached_keys().await, &self.journal);
+        let futures: Vec<_> = s3_entries
+            .into_iter()
+            .filter(|(_, s3_entry)| s3_entry.size > 0 && !s3_entry.deltas.is_empty())
+            .map(|(date, s3_entry)| async move {
+                match self.download_with_deltas(date, &s3_entry.deltas).await? {
+                    Some((entry, true)) => {
+                        self.upload_snapshot(&entry, &s3_entry.deltas).await?;
+                        Ok(Some(date))
                     }
-                    Ok(None)
+                    _ => Ok(None),
                 }
             })
             .collect();
-        futures
+        let mut dates: Vec<Date> = stream::iter(futures)
+            .buffer_unordered(self.config.sync_concurrency.max(1))
             .try_filter_map(|x| async move { Ok(x) })
             .try_collect()
-            .await
+            .await?;
+        dates.sort();
+        Ok(dates)
+    }
+
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn import_from_s3(&self) -> Result<Vec<DiaryEntries>, Error> {
+        debug!("{}", self.config.diary_bucket);
+        let db = PgEntryStore::new(&self.journal, self.pool.clone());
+        self.sync_engine(&db).import().await
     }
 
     /// # Errors
     /// Return error if s3 api fails
     pub async fn validate_s3(&self) -> Result<Vec<(Date, usize, usize)>, Error> {
         self.fill_cache().await?;
-        let s3_key_map: HashMap<Date, usize> = KEY_CACHE
-            .read()
-            .await
-            .1
-            .iter()
-            .map(|obj| (obj.date, obj.size as usize))
-            .collect();
+        let s3_entries = get_s3_entries(&self.cached_keys().await, &self.journal);
 
-        let futures: FuturesUnordered<_> = s3_key_map
-            .iter()
-            .map(|(date, backup_len)| {
+        let futures: Vec<_> = s3_entries
+            .into_iter()
+            .map(|(date, s3_entry)| {
                 let pool = self.pool.clone();
+                let journal = self.journal.clone();
                 async move {
-                    let entry = DiaryEntries::get_by_date(*date, &pool)
+                    let entry = DiaryEntries::get_by_date(&journal, date, &pool)
                         .await?
                         .ok_or_else(|| format_err!("Date should exist {date}"))?;
                     let diary_len = entry.diary_text.len();
-                    if diary_len.abs_diff(*backup_len) <= 1 {
+                    let mut backup_len = s3_entry.size as usize;
+                    // the listed size is that of the stored object, which
+                    // differs for compressed objects and entries with deltas
+                    if !s3_entry.deltas.is_empty() || diary_len.abs_diff(backup_len) > 1 {
+                        backup_len = self
+                            .download_with_deltas(date, &s3_entry.deltas)
+                            .await?
+                            .map_or(0, |(entry, _)| entry.diary_text.len());
+                    }
+                    if diary_len.abs_diff(backup_len) <= 1 {
                         Ok(None)
                     } else {
-                        Ok(Some((*date, *backup_len, diary_len)))
+                        Ok(Some((date, backup_len, diary_len)))
                     }
                 }
             })
             .collect();
-        futures
+        stream::iter(futures)
+            .buffer_unordered(self.config.sync_concurrency.max(1))
             .try_filter_map(|x| async move { Ok(x) })
             .try_collect()
             .await
     }
 }
 
+#[async_trait]
+impl SyncStore for S3Interface {
+    fn backend(&self) -> SyncBackend {
+        SyncBackend::S3
+    }
+
+    /// s3 holds copies of the database, which wins
+    fn keeps_own_copy(&self) -> bool {
+        false
+    }
+
+    async fn list(&self) -> Result<HashMap<Date, OffsetDateTime>, Error> {
+        self.fill_cache().await?;
+        let s3_entries = get_s3_entries(&self.cached_keys().await, &self.journal);
+        Ok(s3_entries
+            .into_iter()
+            .filter(|(_, obj)| obj.size > 0)
+            .map(|(date, obj)| (date, obj.last_modified))
+            .collect())
+    }
+
+    async fn read(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+        self.download_entry(date).await
+    }
+
+    async fn write(&self, entry: &DiaryEntries) -> Result<OffsetDateTime, Error> {
+        self.upload_diary_entry(entry).await?;
+        Ok(OffsetDateTime::now_utc())
+    }
+
+    async fn replace(&self, entry: &DiaryEntries) -> Result<OffsetDateTime, Error> {
+        self.replace_diary_entry(entry).await?;
+        Ok(OffsetDateTime::now_utc())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
+    use aws_sdk_s3::{primitives::DateTime, types::Object};
     use log::debug;
+    use std::convert::TryFrom;
+    use time::macros::{date, datetime};
 
     use crate::{
-        config::Config, pgpool::PgPool, s3_instance::S3Instance, s3_interface::S3Interface,
+        config::Config,
+        pgpool::PgPool,
+        s3_instance::S3Instance,
+        s3_interface::{
+            get_s3_entries, get_s3_storage, parse_key, KeyMetaData, S3Interface, S3Storage,
+        },
     };
 
+    #[test]
+    fn test_parse_key() -> Result<(), Error> {
+        let (journal, d) = parse_key("2024-01-02.txt")?;
+        assert_eq!(journal, "diary");
+        assert_eq!(d, date!(2024 - 01 - 02));
+        let (journal, d) = parse_key("dreams/2024-01-03.txt")?;
+        assert_eq!(journal, "dreams");
+        assert_eq!(d, date!(2024 - 01 - 03));
+        assert!(parse_key("dreams/notes.txt").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_s3_entries() -> Result<(), Error> {
+        let objects = [
+            ("2024-01-02.txt", 1_700_000_000, 100),
+            ("2024-01-02.txt.0002.delta", 1_700_000_300, 20),
+            ("2024-01-02.txt.0001.delta", 1_700_000_200, 10),
+            ("2024-01-03.txt", 1_700_000_100, 50),
+            ("dreams/2024-01-02.txt", 1_700_000_400, 30),
+        ];
+        let keys: Vec<KeyMetaData> = objects
+            .into_iter()
+            .map(|(key, last_modified, size)| {
+                let obj = Object::builder()
+                    .key(key)
+                    .last_modified(DateTime::from_secs(last_modified))
+                    .size(size)
+                    .build();
+                KeyMetaData::try_from(obj)
+            })
+            .collect::<Result<_, _>>()?;
+        assert_eq!(keys[1].delta, Some(2));
+        let entries = get_s3_entries(&keys, "diary");
+        assert_eq!(entries.len(), 2);
+        let entry = &entries[&date!(2024 - 01 - 02)];
+        assert_eq!(entry.size, 100);
+        assert_eq!(entry.deltas, vec![1, 2]);
+        assert_eq!(entry.last_modified, datetime!(2023-11-14 22:18:20 UTC));
+        assert!(entries[&date!(2024 - 01 - 03)].deltas.is_empty());
+        assert_eq!(
+            get_s3_storage(&keys, "diary"),
+            S3Storage {
+                objects: 4,
+                snapshot_bytes: 150,
+                delta_bytes: 30,
+            }
+        );
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[ignore]
     async fn test_validate_s3() -> Result<(), Error> {
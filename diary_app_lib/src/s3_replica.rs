@@ -0,0 +1,184 @@
+use anyhow::{format_err, Error};
+use aws_config::SdkConfig;
+use aws_sdk_s3::types::Object;
+use futures::{stream, StreamExt, TryStreamExt};
+use log::debug;
+use stack_string::StackString;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{config::Config, s3_instance::S3Instance};
+
+/// Difference between a key of `diary_bucket` and its copy in
+/// `replica_bucket`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplicaMismatch {
+    /// Key missing from the replica
+    Missing(StackString),
+    /// Key whose content (etag) differs in the replica
+    Changed(StackString),
+    /// Key only in the replica, reported but left alone
+    Extra(StackString),
+}
+
+impl ReplicaMismatch {
+    #[must_use]
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Missing(key) | Self::Changed(key) | Self::Extra(key) => key,
+        }
+    }
+}
+
+impl fmt::Display for ReplicaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing(key) => write!(f, "missing {key}"),
+            Self::Changed(key) => write!(f, "changed {key}"),
+            Self::Extra(key) => write!(f, "extra {key}"),
+        }
+    }
+}
+
+/// Compare the key listings of the primary and replica buckets by etag,
+/// sorted by key
+#[must_use]
+pub fn compare_keys(primary: &[Object], replica: &[Object]) -> Vec<ReplicaMismatch> {
+    let replica_map: HashMap<&str, Option<&str>> = replica
+        .iter()
+        .filter_map(|obj| Some((obj.key.as_deref()?, obj.e_tag.as_deref())))
+        .collect();
+    let mut mismatches: Vec<_> = primary
+        .iter()
+        .filter_map(|obj| {
+            let key = obj.key.as_deref()?;
+            match replica_map.get(key) {
+                None => Some(ReplicaMismatch::Missing(key.into())),
+                Some(e_tag) if *e_tag != obj.e_tag.as_deref() => {
+                    Some(ReplicaMismatch::Changed(key.into()))
+                }
+                Some(_) => None,
+            }
+        })
+        .collect();
+    let primary_keys: HashSet<&str> = primary
+        .iter()
+        .filter_map(|obj| obj.key.as_deref())
+        .collect();
+    mismatches.extend(
+        replica_map
+            .keys()
+            .filter(|key| !primary_keys.contains(*key))
+            .map(|key| ReplicaMismatch::Extra((*key).into())),
+    );
+    mismatches.sort_by(|a, b| a.key().cmp(b.key()));
+    mismatches
+}
+
+/// Keeps `replica_bucket` a complete copy of `diary_bucket` so that losing a
+/// region or the primary bucket doesn't lose the only backup
+#[derive(Clone, Debug)]
+pub struct S3Replica {
+    config: Config,
+    primary: S3Instance,
+    replica: S3Instance,
+    replica_bucket: StackString,
+}
+
+impl S3Replica {
+    /// # Errors
+    /// Return error if `replica_bucket` isn't configured
+    pub fn new(config: Config, sdk_config: &SdkConfig) -> Result<Self, Error> {
+        let replica_bucket = config
+            .replica_bucket
+            .clone()
+            .ok_or_else(|| format_err!("replica_bucket is not set"))?;
+        let replica = match &config.replica_region {
+            Some(region) => S3Instance::new_in_region(sdk_config, region),
+            None => S3Instance::new(sdk_config),
+        };
+        Ok(Self {
+            primary: S3Instance::new(sdk_config).with_retry_policy(config.retry_policy()),
+            replica: replica.with_retry_policy(config.retry_policy()),
+            replica_bucket,
+            config,
+        })
+    }
+
+    /// Compare every key of the two buckets, keys missing from or changed
+    /// in the replica are copied over from the primary if `repair` is set
+    /// # Errors
+    /// Return error if s3 api fails
+    pub async fn validate(&self, repair: bool) -> Result<Vec<ReplicaMismatch>, Error> {
+        let primary = self
+            .primary
+            .get_list_of_keys(&self.config.diary_bucket, None)
+            .await?;
+        let replica = self
+            .replica
+            .get_list_of_keys(&self.replica_bucket, None)
+            .await?;
+        debug!("primary {} replica {}", primary.len(), replica.len());
+        let mismatches = compare_keys(&primary, &replica);
+        if repair {
+            let futures = mismatches
+                .iter()
+                .filter(|m| !matches!(m, ReplicaMismatch::Extra(_)))
+                .map(|m| self.copy_key(m.key()));
+            stream::iter(futures)
+                .buffer_unordered(self.config.sync_concurrency.max(1))
+                .try_collect::<()>()
+                .await?;
+        }
+        Ok(mismatches)
+    }
+
+    /// Copies the stored bytes so compressed objects and deltas keep their
+    /// encoding and e-tag
+    async fn copy_key(&self, key: &str) -> Result<(), Error> {
+        let obj = self
+            .primary
+            .get_object(&self.config.diary_bucket, key, None)
+            .await?;
+        self.replica
+            .upload_object(&obj, &self.replica_bucket, key)
+            .await
+            .map_err(|e| format_err!("failed to copy {key}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::types::Object;
+
+    use crate::s3_replica::{compare_keys, ReplicaMismatch};
+
+    fn object(key: &str, e_tag: &str) -> Object {
+        Object::builder().key(key).e_tag(e_tag).build()
+    }
+
+    #[test]
+    fn test_compare_keys() {
+        let primary = [
+            object("2024-03-01.txt", "a"),
+            object("2024-03-02.txt", "b"),
+            object("dreams/2024-03-01.txt", "c"),
+        ];
+        let replica = [
+            object("2024-03-01.txt", "a"),
+            object("dreams/2024-03-01.txt", "x"),
+            object("old.txt", "d"),
+        ];
+        assert_eq!(
+            compare_keys(&primary, &replica),
+            vec![
+                ReplicaMismatch::Missing("2024-03-02.txt".into()),
+                ReplicaMismatch::Changed("dreams/2024-03-01.txt".into()),
+                ReplicaMismatch::Extra("old.txt".into()),
+            ]
+        );
+        assert!(compare_keys(&primary, &primary).is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+
+/// Lines starting with this split an entry into sections, e.g. `## Morning`
+pub const SECTION_PREFIX: &str = "## ";
+
+/// Part of an entry from one header to the next, text before the first
+/// header is a section with an empty title and slug
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub title: StackString,
+    /// Lowercase title with runs of other characters than letters and
+    /// digits replaced by `-`, suffixed with a number if the title repeats
+    pub slug: StackString,
+    /// Zero based line of the header
+    pub line: usize,
+    /// Lines below the header
+    pub text: StackString,
+}
+
+#[must_use]
+pub fn section_slug(title: &str) -> StackString {
+    let mut slug = StackString::new();
+    for word in title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_lowercase());
+    }
+    slug
+}
+
+fn section_title(line: &str) -> Option<&str> {
+    line.strip_prefix(SECTION_PREFIX).map(str::trim)
+}
+
+/// Sections of `text`, empty if it has no headers
+#[must_use]
+pub fn parse_sections(text: &str) -> Vec<Section> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let starts: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, line)| section_title(line).map(|_| idx))
+        .collect();
+    let Some(first) = starts.first() else {
+        return Vec::new();
+    };
+    let mut sections = Vec::new();
+    let preamble = lines[..*first].join("\n");
+    if !preamble.trim().is_empty() {
+        sections.push(Section {
+            title: StackString::new(),
+            slug: StackString::new(),
+            line: 0,
+            text: preamble.into(),
+        });
+    }
+    for (idx, start) in starts.iter().enumerate() {
+        let end = starts.get(idx + 1).copied().unwrap_or(lines.len());
+        let title = section_title(lines[*start]).unwrap_or_default();
+        let mut slug = section_slug(title);
+        let repeats = sections.iter().filter(|s| s.title == title).count();
+        if repeats > 0 {
+            slug = format_sstr!("{slug}-{}", repeats + 1);
+        }
+        sections.push(Section {
+            title: title.into(),
+            slug,
+            line: *start,
+            text: lines[start + 1..end].join("\n").into(),
+        });
+    }
+    sections
+}
+
+/// Section of `text` whose slug is `slug`
+#[must_use]
+pub fn find_section(text: &str, slug: &str) -> Option<Section> {
+    parse_sections(text).into_iter().find(|s| s.slug == slug)
+}
+
+/// First section of `text` containing `needle`, ignoring case
+#[must_use]
+pub fn section_containing(text: &str, needle: &str) -> Option<Section> {
+    let needle = needle.to_lowercase();
+    parse_sections(text)
+        .into_iter()
+        .find(|s| s.text.to_lowercase().contains(&needle))
+}
+
+/// `text` with the lines below the header of section `slug` replaced by
+/// `body`, `None` if there is no such section
+#[must_use]
+pub fn replace_section(text: &str, slug: &str, body: &str) -> Option<StackString> {
+    let sections = parse_sections(text);
+    let idx = sections.iter().position(|s| s.slug == slug)?;
+    let lines: Vec<&str> = text.split('\n').collect();
+    let section = &sections[idx];
+    // the preamble has no header line
+    let start = if section.title.is_empty() {
+        0
+    } else {
+        section.line + 1
+    };
+    let end = sections.get(idx + 1).map_or(lines.len(), |s| s.line);
+    let mut output: Vec<&str> = lines[..start].to_vec();
+    output.extend(body.trim_end_matches('\n').split('\n'));
+    output.extend_from_slice(&lines[end..]);
+    Some(output.join("\n").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sections::{
+        find_section, parse_sections, replace_section, section_containing, section_slug,
+    };
+
+    const TEXT: &str =
+        "woke up early\n## Morning\nran 5k\n\n## Evening Plans\nread\n## Morning\nnap";
+
+    #[test]
+    fn test_parse_sections() {
+        assert_eq!(section_slug("Evening Plans!"), "evening-plans");
+        let sections = parse_sections(TEXT);
+        let summary: Vec<_> = sections
+            .iter()
+            .map(|s| (s.title.as_str(), s.slug.as_str(), s.line, s.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("", "", 0, "woke up early"),
+                ("Morning", "morning", 1, "ran 5k\n"),
+                ("Evening Plans", "evening-plans", 4, "read"),
+                ("Morning", "morning-2", 6, "nap"),
+            ]
+        );
+        assert!(parse_sections("no headers\nat all").is_empty());
+        assert_eq!(find_section(TEXT, "morning-2").unwrap().text, "nap");
+        assert_eq!(
+            section_containing(TEXT, "READ").unwrap().slug,
+            "evening-plans"
+        );
+    }
+
+    #[test]
+    fn test_replace_section() {
+        let text = replace_section(TEXT, "evening-plans", "watched a movie\n").unwrap();
+        assert_eq!(
+            text,
+            concat!(
+                "woke up early\n## Morning\nran 5k\n\n",
+                "## Evening Plans\nwatched a movie\n## Morning\nnap"
+            )
+        );
+        let text = replace_section(TEXT, "morning-2", "slept in").unwrap();
+        assert!(text.ends_with("## Morning\nslept in"));
+        assert!(replace_section(TEXT, "night", "").is_none());
+    }
+}
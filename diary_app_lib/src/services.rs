@@ -0,0 +1,986 @@
+use anyhow::{format_err, Error};
+use futures::TryStreamExt;
+use regex::Regex;
+use serde_json::{Map, Value};
+use stack_string::{format_sstr, StackString};
+use std::collections::HashSet;
+use thiserror::Error as ThisError;
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    date_time_wrapper::DateTimeWrapper,
+    diary_app_interface::DiaryAppInterface,
+    entry_patch::EntryPatch,
+    i18n::Locale,
+    mobile_sync::{sync_client, ClientEntryState, ServerEntryState},
+    models::{
+        parse_metadata_value, AuditAction, CacheItem, ConflictKey, DateGroup, DateListQuery,
+        DatePage, DateRange, DiaryCache, DiaryConflict, DiaryEntries, Journal, MetadataStats,
+        SortOrder, StatsPeriod, UserSettings,
+    },
+    sections::Section,
+};
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum EntryError {
+    #[error("No entry for {0}")]
+    NotFound(Date),
+}
+
+/// Text search, or the entry of a single date
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub text: Option<StackString>,
+    pub date: Option<Date>,
+    /// Only search starred entries
+    pub starred: bool,
+}
+
+/// Matching entries and cached text, one `date\ntext` item each
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchResult {
+    pub entries: Vec<StackString>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ListQuery {
+    pub min_date: Option<Date>,
+    pub max_date: Option<Date>,
+    pub start: Option<usize>,
+    pub limit: Option<usize>,
+    pub starred: bool,
+    pub order: SortOrder,
+    /// Only dates past the `next_cursor` of the previous page
+    pub cursor: Option<Date>,
+    pub min_words: Option<usize>,
+    pub max_words: Option<usize>,
+    pub has_conflict: Option<bool>,
+}
+
+/// Replace or append to the text of a date
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryUpdate {
+    pub date: Date,
+    pub text: StackString,
+}
+
+#[derive(Clone, Debug)]
+pub struct PatchUpdate {
+    pub date: Date,
+    pub patch: EntryPatch,
+    /// Hash of the text the patch was made against
+    pub base_hash: Option<StackString>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionUpdate {
+    pub date: Date,
+    pub slug: StackString,
+    pub text: StackString,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionQuery {
+    pub date: Date,
+    pub slug: StackString,
+}
+
+/// Replace matches of `regex` with `placeholder`, the configured placeholder
+/// if `None`, the original text is kept encrypted unless `permanent` is set
+#[derive(Clone, Debug)]
+pub struct RedactQuery {
+    pub regex: Regex,
+    pub placeholder: Option<StackString>,
+    pub min_date: Option<Date>,
+    pub max_date: Option<Date>,
+    pub permanent: bool,
+}
+
+/// Entries an offline client holds, see [`sync_client`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MobileSyncUpdate {
+    pub client_id: StackString,
+    pub entries: Vec<ClientEntryState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewJournal {
+    pub journal_name: StackString,
+    pub shared: bool,
+}
+
+/// Dates with conflicts, or the syncs which recorded the conflicts of a date
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConflictList {
+    Dates(Vec<Date>),
+    Syncs(Vec<DateTimeWrapper>),
+}
+
+/// Conflict a write recorded, enough for a client to link to resolving it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConflictSummary {
+    pub group: Uuid,
+    pub datetime: DateTimeWrapper,
+    /// Unresolved conflicts of the date, this one included
+    pub count: usize,
+}
+
+/// Mark a conflict line as added or removed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflictUpdate {
+    pub id: Uuid,
+    /// `add` or `rem`
+    pub diff_type: StackString,
+}
+
+/// Set a metadata key of a date, `None` removes it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataUpdate {
+    pub date: Date,
+    pub key: StackString,
+    pub value: Option<StackString>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataStatsQuery {
+    pub key: StackString,
+    pub period: StatsPeriod,
+    pub min_date: Option<Date>,
+    pub max_date: Option<Date>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingsUpdate {
+    pub email: StackString,
+    pub resurface: bool,
+    pub locale: Option<Locale>,
+}
+
+/// Frontpage summary of a journal
+#[derive(Clone, Debug)]
+pub struct DashboardSummary {
+    pub recent: Vec<DiaryEntries>,
+    pub streak: usize,
+    pub conflict_dates: usize,
+    pub last_sync: Option<DateTimeWrapper>,
+}
+
+/// Operations of the api requests with typed results, writes are recorded in
+/// the activity log.  Formatting the results is left to the frontend.
+#[derive(Clone, Copy)]
+pub struct DiaryService<'a> {
+    dapp: &'a DiaryAppInterface,
+}
+
+impl<'a> DiaryService<'a> {
+    #[must_use]
+    pub fn new(dapp: &'a DiaryAppInterface) -> Self {
+        Self { dapp }
+    }
+
+    /// Entry of `date`, hidden entries don't exist for viewers
+    /// # Errors
+    /// Return error if there is no entry or db query fails
+    pub async fn entry(self, date: Date) -> Result<DiaryEntries, Error> {
+        self.dapp
+            .get_entry(date)
+            .await?
+            .ok_or_else(|| EntryError::NotFound(date).into())
+    }
+
+    /// A date without an entry finds nothing
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn search(self, query: SearchQuery) -> Result<SearchResult, Error> {
+        let entries = if let Some(text) = query.text {
+            if query.starred {
+                self.dapp.search_starred(&text).await?
+            } else {
+                self.dapp.search_text(&text).await?
+            }
+        } else if let Some(date) = query.date {
+            self.dapp
+                .get_entry(date)
+                .await?
+                .map(|entry| entry.diary_text)
+                .into_iter()
+                .collect()
+        } else {
+            vec!["".into()]
+        };
+        Ok(SearchResult { entries })
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list(self, query: ListQuery) -> Result<DatePage, Error> {
+        self.dapp
+            .get_date_page(DateListQuery {
+                range: DateRange::new(query.min_date, query.max_date),
+                order: query.order,
+                cursor: query.cursor,
+                offset: query.start,
+                limit: query.limit,
+                starred: query.starred,
+                min_words: query.min_words,
+                max_words: query.max_words,
+                has_conflict: query.has_conflict,
+            })
+            .await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list_groups(self, period: StatsPeriod) -> Result<Vec<DateGroup>, Error> {
+        self.dapp.get_date_groups(period).await
+    }
+
+    /// Entries within `range`, for printing
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn print(self, range: DateRange) -> Result<Vec<DiaryEntries>, Error> {
+        self.dapp
+            .get_entries_in_range(range.since, range.until)
+            .await
+    }
+
+    /// # Errors
+    /// Return error if the text is too long or db query fails
+    pub async fn insert(self, item: CacheItem) -> Result<DiaryCache, Error> {
+        let cache = self.dapp.cache_item(item).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Insert,
+                None,
+                format_sstr!("cached {}", cache.diary_datetime),
+            )
+            .await;
+        Ok(cache)
+    }
+
+    /// Cache `text` as of now
+    /// # Errors
+    /// Return error if the text is too long or db query fails
+    pub async fn insert_text(self, text: &str) -> Result<DiaryCache, Error> {
+        let cache = self.dapp.cache_text(text).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Insert,
+                None,
+                format_sstr!("cached {}", cache.diary_datetime),
+            )
+            .await;
+        Ok(cache)
+    }
+
+    /// Cache each item on its own, items which fail are reported with their
+    /// error instead of failing the batch
+    /// # Errors
+    /// Return error if any text is too long or db query fails
+    pub async fn insert_batch(
+        self,
+        items: Vec<CacheItem>,
+    ) -> Result<Vec<Result<DiaryCache, StackString>>, Error> {
+        let results: Vec<_> = self
+            .dapp
+            .cache_text_batch(items)
+            .await?
+            .into_iter()
+            .map(|result| result.map_err(|e| format_sstr!("{e}")))
+            .collect();
+        let cached = results.iter().filter(|r| r.is_ok()).count();
+        self.dapp
+            .record_activity(
+                AuditAction::Insert,
+                None,
+                format_sstr!("cached {cached} of {}", results.len()),
+            )
+            .await;
+        Ok(results)
+    }
+
+    /// # Errors
+    /// Return `SyncLockError::Held` if another process is syncing, or error
+    /// if db query fails
+    pub async fn sync(self) -> Result<Vec<StackString>, Error> {
+        let output = self.dapp.sync_everything().await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Sync,
+                None,
+                format_sstr!("{} changes", output.len()),
+            )
+            .await;
+        Ok(output)
+    }
+
+    /// The entry with the new text, and the conflict recorded if the
+    /// replacement dropped lines
+    /// # Errors
+    /// Return error if the text is too long or db query fails
+    pub async fn replace(
+        self,
+        update: EntryUpdate,
+    ) -> Result<(DiaryEntries, Option<ConflictSummary>), Error> {
+        let (entry, conflict) = self.dapp.replace_text(update.date, &update.text).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Replace,
+                Some(update.date),
+                format_sstr!("{} bytes", entry.diary_text.len()),
+            )
+            .await;
+        let conflict = self.conflict_summary(entry.diary_date, conflict).await?;
+        Ok((entry, conflict))
+    }
+
+    async fn conflict_summary(
+        self,
+        date: Date,
+        sync_datetime: Option<OffsetDateTime>,
+    ) -> Result<Option<ConflictSummary>, Error> {
+        let Some(sync_datetime) = sync_datetime else {
+            return Ok(None);
+        };
+        let pool = &self.dapp.pool;
+        let conflicts: Vec<_> = DiaryConflict::get_by_key(&self.dapp.journal, sync_datetime, pool)
+            .await?
+            .try_collect()
+            .await?;
+        let Some(first) = conflicts.first() else {
+            return Ok(None);
+        };
+        let count = DiaryConflict::get_by_date(&self.dapp.journal, date, pool)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?
+            .len();
+        Ok(Some(ConflictSummary {
+            group: first.conflict_group,
+            datetime: first.sync_datetime,
+            count,
+        }))
+    }
+
+    /// # Errors
+    /// Return error if the text is too long or db query fails
+    pub async fn append(self, update: EntryUpdate) -> Result<DiaryEntries, Error> {
+        let entry = self.dapp.append_text(update.date, &update.text).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Append,
+                Some(update.date),
+                format_sstr!("appended {} bytes", update.text.len()),
+            )
+            .await;
+        Ok(entry)
+    }
+
+    /// # Errors
+    /// Return `PatchError` if the patch doesn't apply, or error if db query
+    /// fails
+    pub async fn patch(self, update: PatchUpdate) -> Result<DiaryEntries, Error> {
+        let (entry, _) = self
+            .dapp
+            .patch_entry(update.date, &update.patch, update.base_hash.as_deref())
+            .await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Patch,
+                Some(update.date),
+                format_sstr!("{} bytes", entry.diary_text.len()),
+            )
+            .await;
+        Ok(entry)
+    }
+
+    /// `None` if the entry is hidden or has no such section
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn section(self, query: SectionQuery) -> Result<Option<Section>, Error> {
+        self.dapp.get_section(query.date, &query.slug).await
+    }
+
+    /// # Errors
+    /// Return error if there is no such section or db query fails
+    pub async fn replace_section(self, update: SectionUpdate) -> Result<DiaryEntries, Error> {
+        let (entry, _) = self
+            .dapp
+            .replace_section(update.date, &update.slug, &update.text)
+            .await?;
+        self.dapp
+            .record_activity(
+                AuditAction::Replace,
+                Some(update.date),
+                format_sstr!("section {}, {} bytes", update.slug, entry.diary_text.len()),
+            )
+            .await;
+        Ok(entry)
+    }
+
+    /// Returns the redacted dates
+    /// # Errors
+    /// Return error if the redaction key can't be loaded or db query fails
+    pub async fn redact(self, query: RedactQuery) -> Result<Vec<Date>, Error> {
+        self.dapp
+            .redact(
+                &query.regex,
+                query.placeholder.as_deref(),
+                query.min_date,
+                query.max_date,
+                query.permanent,
+            )
+            .await
+    }
+
+    /// Entry hidden until `update.date` arrives
+    /// # Errors
+    /// Return error if the date isn't in the future, there already is an
+    /// entry, the text is too long or db query fails
+    pub async fn schedule(self, update: EntryUpdate) -> Result<DiaryEntries, Error> {
+        self.dapp.schedule_entry(update.date, &update.text).await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list_conflicts(self, date: Option<Date>) -> Result<ConflictList, Error> {
+        let dapp = self.dapp;
+        if let Some(date) = date {
+            if !dapp.can_read_date(date).await? {
+                return Ok(ConflictList::Syncs(Vec::new()));
+            }
+            let mut syncs: Vec<_> = DiaryConflict::get_by_date(&dapp.journal, date, &dapp.pool)
+                .await?
+                .try_collect()
+                .await?;
+            syncs.sort();
+            syncs.dedup();
+            Ok(ConflictList::Syncs(syncs))
+        } else {
+            let mut dates: Vec<_> = DiaryConflict::get_all_dates(&dapp.journal, &dapp.pool)
+                .await?
+                .try_collect()
+                .await?;
+            let unreadable = dapp.get_unreadable_dates().await?;
+            dates.retain(|date| !unreadable.contains(date));
+            dates.sort();
+            dates.dedup();
+            Ok(ConflictList::Dates(dates))
+        }
+    }
+
+    /// Lines of the conflict with group `key` or recorded by the sync at it,
+    /// nothing if the date of the conflict is hidden
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn show_conflict(
+        self,
+        key: impl Into<ConflictKey>,
+    ) -> Result<Vec<DiaryConflict>, Error> {
+        let dapp = self.dapp;
+        let conflicts: Vec<DiaryConflict> =
+            DiaryConflict::get_by_key(&dapp.journal, key, &dapp.pool)
+                .await?
+                .try_collect()
+                .await?;
+        let dates: HashSet<Date> = conflicts.iter().map(|c| c.diary_date).collect();
+        for date in dates {
+            if !dapp.can_read_date(date).await? {
+                return Ok(Vec::new());
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn remove_conflict(self, key: impl Into<ConflictKey>) -> Result<(), Error> {
+        let key = key.into();
+        DiaryConflict::remove_by_key(key, &self.dapp.pool).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::ResolveConflict,
+                None,
+                format_sstr!("removed conflict {key}"),
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Remove every conflict of `date`, returns the syncs which recorded them
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn clean_conflicts(self, date: Date) -> Result<Vec<DateTimeWrapper>, Error> {
+        let dapp = self.dapp;
+        let removed: Vec<_> = DiaryConflict::get_by_date(&dapp.journal, date, &dapp.pool)
+            .await?
+            .map_err(Into::into)
+            .and_then(|datetime| async move {
+                DiaryConflict::remove_by_key(datetime, &dapp.pool).await?;
+                Ok::<_, Error>(datetime)
+            })
+            .try_collect()
+            .await?;
+        dapp.record_activity(
+            AuditAction::ResolveConflict,
+            Some(date),
+            format_sstr!("removed {} conflicts", removed.len()),
+        )
+        .await;
+        Ok(removed)
+    }
+
+    /// # Errors
+    /// Return error if the diff type isn't `add` or `rem` or db query fails
+    pub async fn update_conflict(self, update: ConflictUpdate) -> Result<(), Error> {
+        let diff_type = match update.diff_type.as_str() {
+            "rem" => "rem",
+            "add" => "add",
+            _ => return Err(format_err!("Bad diff type {}", update.diff_type)),
+        };
+        DiaryConflict::update_by_id(update.id, diff_type, &self.dapp.pool).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::ResolveConflict,
+                None,
+                format_sstr!("marked {} {diff_type}", update.id),
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Replace the entry with the added and unchanged lines of the conflict,
+    /// and the conflict recorded if that dropped lines
+    /// # Errors
+    /// Return error if there is no such conflict or db query fails
+    pub async fn commit_conflict(
+        self,
+        key: impl Into<ConflictKey>,
+    ) -> Result<(DiaryEntries, Option<ConflictSummary>), Error> {
+        let key = key.into();
+        let (entry, conflict) = self.dapp.commit_conflict(key).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::ResolveConflict,
+                Some(entry.diary_date),
+                format_sstr!("committed conflict {key}"),
+            )
+            .await;
+        let conflict = self.conflict_summary(entry.diary_date, conflict).await?;
+        Ok((entry, conflict))
+    }
+
+    /// Restore the entry to the removed and unchanged lines of the conflict,
+    /// the text from before the sync
+    /// # Errors
+    /// Return error if there is no such conflict or db query fails
+    pub async fn revert_conflict(self, key: impl Into<ConflictKey>) -> Result<DiaryEntries, Error> {
+        let key = key.into();
+        let (entry, _) = self.dapp.revert_conflict(key).await?;
+        self.dapp
+            .record_activity(
+                AuditAction::ResolveConflict,
+                Some(entry.diary_date),
+                format_sstr!("reverted conflict {key}"),
+            )
+            .await;
+        Ok(entry)
+    }
+
+    /// Store an entry encrypted in the browser as is
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn replace_encrypted(self, entry: DiaryEntries) -> Result<DiaryEntries, Error> {
+        let entry = entry
+            .with_journal(self.dapp.journal.clone())
+            .with_source(self.dapp.source.clone());
+        entry.upsert_entry(&self.dapp.pool, true).await?;
+        let length = entry.diary_ciphertext.as_ref().map_or(0, Vec::len);
+        self.dapp
+            .record_activity(
+                AuditAction::ReplaceEncrypted,
+                Some(entry.diary_date),
+                format_sstr!("{length} encrypted bytes"),
+            )
+            .await;
+        Ok(entry)
+    }
+
+    /// Oldest first, only the date range and `start` and `limit` of `query`
+    /// apply
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list_encrypted(self, query: ListQuery) -> Result<Vec<DiaryEntries>, Error> {
+        let entries: Vec<DiaryEntries> = DiaryEntries::get_encrypted(
+            &self.dapp.journal,
+            &self.dapp.pool,
+            query.min_date,
+            query.max_date,
+        )
+        .await?
+        .try_collect()
+        .await?;
+        Ok(entries
+            .into_iter()
+            .skip(query.start.unwrap_or(0))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Returns whether the entry is now starred
+    /// # Errors
+    /// Return error if there is no entry or db query fails
+    pub async fn toggle_star(self, date: Date) -> Result<bool, Error> {
+        let starred = DiaryEntries::toggle_starred(&self.dapp.journal, date, &self.dapp.pool)
+            .await?
+            .ok_or(EntryError::NotFound(date))?;
+        let detail = if starred { "starred" } else { "unstarred" };
+        self.dapp
+            .record_activity(AuditAction::Star, Some(date), detail)
+            .await;
+        Ok(starred)
+    }
+
+    /// Returns whether the entry is now private
+    /// # Errors
+    /// Return error if there is no entry or db query fails
+    pub async fn toggle_private(self, date: Date) -> Result<bool, Error> {
+        let private = DiaryEntries::toggle_private(&self.dapp.journal, date, &self.dapp.pool)
+            .await?
+            .ok_or(EntryError::NotFound(date))?;
+        let detail = if private { "private" } else { "public" };
+        self.dapp
+            .record_activity(AuditAction::TogglePrivate, Some(date), detail)
+            .await;
+        Ok(private)
+    }
+
+    /// Move the entry to the trash, returns the copies removed
+    /// # Errors
+    /// Return error if there is no entry or a backend fails
+    pub async fn delete(self, date: Date) -> Result<Vec<StackString>, Error> {
+        self.dapp.delete_entry(date).await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list_trash(self) -> Result<Vec<DiaryEntries>, Error> {
+        DiaryEntries::get_deleted(&self.dapp.journal, &self.dapp.pool)
+            .await?
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if there is no deleted entry or db query fails
+    pub async fn restore_trash(self, date: Date) -> Result<(), Error> {
+        if DiaryEntries::restore_entry(&self.dapp.journal, date, &self.dapp.pool).await? == 0 {
+            return Err(format_err!("No deleted entry for {date}"));
+        }
+        self.dapp
+            .record_activity(AuditAction::Restore, Some(date), "restored from trash")
+            .await;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if there is no deleted entry or db query fails
+    pub async fn purge_trash(self, date: Date) -> Result<(), Error> {
+        let entry = DiaryEntries::get_deleted(&self.dapp.journal, &self.dapp.pool)
+            .await?
+            .try_filter(|entry| {
+                let matches = entry.diary_date == date;
+                async move { matches }
+            })
+            .try_next()
+            .await?
+            .ok_or_else(|| format_err!("No deleted entry for {date}"))?;
+        entry.purge_entry(&self.dapp.pool).await?;
+        self.dapp
+            .record_activity(AuditAction::Purge, Some(date), "purged from trash")
+            .await;
+        Ok(())
+    }
+
+    /// Returns the metadata after the update
+    /// # Errors
+    /// Return error if there is no entry or db query fails
+    pub async fn update_metadata(self, update: MetadataUpdate) -> Result<Value, Error> {
+        let mut patch = Map::new();
+        patch.insert(
+            update.key.to_string(),
+            update
+                .value
+                .map_or(Value::Null, |v| parse_metadata_value(&v)),
+        );
+        let metadata = DiaryEntries::update_metadata(
+            &self.dapp.journal,
+            update.date,
+            &Value::Object(patch),
+            &self.dapp.pool,
+        )
+        .await?
+        .ok_or(EntryError::NotFound(update.date))?;
+        self.dapp
+            .record_activity(
+                AuditAction::UpdateMetadata,
+                Some(update.date),
+                format_sstr!("set {}", update.key),
+            )
+            .await;
+        Ok(metadata)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn metadata_stats(
+        self,
+        query: MetadataStatsQuery,
+    ) -> Result<Vec<MetadataStats>, Error> {
+        MetadataStats::get_by_key(
+            &self.dapp.journal,
+            &query.key,
+            query.period,
+            query.min_date,
+            query.max_date,
+            &self.dapp.pool,
+        )
+        .await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn dashboard(self, recent: usize) -> Result<DashboardSummary, Error> {
+        Ok(DashboardSummary {
+            recent: self.dapp.get_recent_entries(recent).await?,
+            streak: self.dapp.get_streak().await?,
+            conflict_dates: self.dapp.count_conflict_dates().await?,
+            last_sync: self.dapp.get_last_sync(),
+        })
+    }
+
+    /// Settings of `email`, the defaults until they're first changed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn settings(self, email: &str) -> Result<UserSettings, Error> {
+        Ok(UserSettings::get_by_email(email, &self.dapp.pool)
+            .await?
+            .unwrap_or_else(|| UserSettings::new(email)))
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update_settings(self, update: SettingsUpdate) -> Result<UserSettings, Error> {
+        let mut settings = self.settings(&update.email).await?;
+        settings.resurface = update.resurface;
+        if let Some(locale) = update.locale {
+            settings.locale = Some(locale.as_str().into());
+        }
+        settings.upsert(&self.dapp.pool).await?;
+        Ok(settings)
+    }
+
+    /// Entries the client needs to update
+    /// # Errors
+    /// Return error if client text is too long or db query fails
+    pub async fn mobile_sync(
+        self,
+        update: MobileSyncUpdate,
+    ) -> Result<Vec<ServerEntryState>, Error> {
+        sync_client(self.dapp, &update.client_id, update.entries).await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn list_journals(self) -> Result<Vec<StackString>, Error> {
+        self.dapp.get_journals().await
+    }
+
+    /// # Errors
+    /// Return error if the name is invalid or db query fails
+    pub async fn create_journal(self, journal: NewJournal) -> Result<Journal, Error> {
+        self.dapp
+            .create_journal(&journal.journal_name, journal.shared)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::date;
+
+    use crate::{
+        config::Config,
+        diary_app_interface::DiaryAppInterface,
+        models::SortOrder,
+        services::{ConflictList, DiaryService, EntryError, EntryUpdate, ListQuery, SearchQuery},
+        test_db::{ConflictFixture, EntryFixture, TestDb},
+    };
+
+    async fn get_dap() -> Result<(TestDb, DiaryAppInterface), Error> {
+        let config = Config::init_config()?;
+        let sdk_config = aws_config::load_from_env().await;
+        let db = TestDb::new().await?;
+        let dap = DiaryAppInterface::new(config, &sdk_config, db.pool.clone());
+        Ok((db, dap))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_service_entries() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let service = DiaryService::new(&dap);
+        EntryFixture::insert_days(date!(2011 - 05 - 23), 3, &dap.pool).await?;
+
+        let query = ListQuery {
+            limit: Some(2),
+            ..ListQuery::default()
+        };
+        let page = service.list(query).await?;
+        assert_eq!(page.dates, [date!(2011 - 05 - 25), date!(2011 - 05 - 24)]);
+        assert_eq!(page.next_cursor, Some(date!(2011 - 05 - 24)));
+        let query = ListQuery {
+            limit: Some(2),
+            cursor: page.next_cursor,
+            ..ListQuery::default()
+        };
+        let page = service.list(query).await?;
+        assert_eq!(page.dates, [date!(2011 - 05 - 23)]);
+        assert_eq!(page.next_cursor, None);
+        let entry = service
+            .append(EntryUpdate {
+                date: date!(2011 - 05 - 24),
+                text: "appended".into(),
+            })
+            .await?;
+        assert!(entry.diary_text.ends_with("appended"));
+
+        let query = SearchQuery {
+            date: Some(date!(2011 - 05 - 24)),
+            ..SearchQuery::default()
+        };
+        assert_eq!(service.search(query).await?.entries, [entry.diary_text]);
+        let missing = date!(2011 - 06 - 01);
+        let err = service.entry(missing).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&EntryError::NotFound(missing)));
+        let query = SearchQuery {
+            date: Some(missing),
+            ..SearchQuery::default()
+        };
+        assert!(service.search(query).await?.entries.is_empty());
+        assert!(service.toggle_star(missing).await.is_err());
+
+        assert!(service.toggle_star(date!(2011 - 05 - 23)).await?);
+        assert!(!service.toggle_star(date!(2011 - 05 - 23)).await?);
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_service_list_filters() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let service = DiaryService::new(&dap);
+        EntryFixture::insert_days(date!(2011 - 05 - 23), 3, &dap.pool).await?;
+        EntryFixture::new(date!(2011 - 05 - 26), "a much longer entry of seven words")
+            .insert(&dap.pool)
+            .await?;
+        ConflictFixture::new(date!(2011 - 05 - 24), "a\nb", "a\nc")
+            .insert(&dap.pool)
+            .await?;
+
+        let query = ListQuery {
+            order: SortOrder::Asc,
+            limit: Some(2),
+            ..ListQuery::default()
+        };
+        let page = service.list(query).await?;
+        assert_eq!(page.dates, [date!(2011 - 05 - 23), date!(2011 - 05 - 24)]);
+        let query = ListQuery {
+            cursor: page.next_cursor,
+            ..query
+        };
+        let page = service.list(query).await?;
+        assert_eq!(page.dates, [date!(2011 - 05 - 25), date!(2011 - 05 - 26)]);
+
+        let query = ListQuery {
+            max_words: Some(5),
+            ..ListQuery::default()
+        };
+        assert_eq!(service.list(query).await?.dates.len(), 3);
+        let query = ListQuery {
+            min_words: Some(5),
+            ..ListQuery::default()
+        };
+        assert_eq!(service.list(query).await?.dates, [date!(2011 - 05 - 26)]);
+        let query = ListQuery {
+            has_conflict: Some(true),
+            ..ListQuery::default()
+        };
+        assert_eq!(service.list(query).await?.dates, [date!(2011 - 05 - 24)]);
+        let query = ListQuery {
+            has_conflict: Some(false),
+            ..ListQuery::default()
+        };
+        assert_eq!(service.list(query).await?.dates.len(), 3);
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_service_conflicts() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let service = DiaryService::new(&dap);
+        let datetime = ConflictFixture::new(date!(2011 - 05 - 23), "a\nb", "a\nc")
+            .insert(&dap.pool)
+            .await?;
+
+        assert_eq!(
+            service.list_conflicts(None).await?,
+            ConflictList::Dates(vec![date!(2011 - 05 - 23)])
+        );
+        assert_eq!(
+            service.list_conflicts(Some(date!(2011 - 05 - 23))).await?,
+            ConflictList::Syncs(vec![datetime])
+        );
+        assert!(!service.show_conflict(datetime).await?.is_empty());
+        assert_eq!(
+            service.clean_conflicts(date!(2011 - 05 - 23)).await?,
+            [datetime]
+        );
+        assert_eq!(
+            service.list_conflicts(None).await?,
+            ConflictList::Dates(Vec::new())
+        );
+        db.cleanup().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_service_replace_conflict() -> Result<(), Error> {
+        let (db, dap) = get_dap().await?;
+        let service = DiaryService::new(&dap);
+        let date = date!(2011 - 05 - 23);
+        let update = |text: &str| EntryUpdate {
+            date,
+            text: text.into(),
+        };
+        let (_, conflict) = service.replace(update("a\nb")).await?;
+        assert!(conflict.is_none());
+        let (entry, conflict) = service.replace(update("a\nc")).await?;
+        assert_eq!(entry.diary_text, "a\nc");
+        let conflict = conflict.unwrap();
+        assert_eq!(conflict.count, 1);
+        let conflicts = service.show_conflict(conflict.group).await?;
+        assert_eq!(conflicts[0].sync_datetime, conflict.datetime);
+
+        let (_, second) = service.replace(update("a")).await?;
+        let second = second.unwrap();
+        assert_eq!(second.count, 2);
+        assert_ne!(second.group, conflict.group);
+
+        // committing the first conflict only adds back the line the second dropped
+        let (entry, recorded) = service.commit_conflict(conflict.group).await?;
+        assert_eq!(entry.diary_text, "a\nc");
+        assert!(recorded.is_none());
+        db.cleanup().await
+    }
+}
@@ -1,25 +1,110 @@
 use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use futures::{stream, Stream, TryStreamExt};
 use log::debug;
 use once_cell::sync::Lazy;
-use smallvec::{smallvec, SmallVec};
-use std::{collections::HashMap, fmt::Display, process::Stdio};
+use russh::{
+    client::{self, Handle, Handler},
+    ChannelMsg, Disconnect,
+};
+use russh_keys::{
+    agent::client::AgentClient, check_known_hosts, check_known_hosts_path, key::PublicKey,
+    learn_known_hosts, learn_known_hosts_path, load_secret_key,
+};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 use tokio::{
-    io::{stdout, AsyncBufReadExt, AsyncWriteExt, BufReader},
-    process::Command,
+    io::{stdout, AsyncWriteExt},
     sync::{Mutex, RwLock},
+    time::timeout,
 };
 use url::Url;
 
 use stack_string::{format_sstr, StackString};
 
+use crate::{
+    config::Config,
+    retry::{Backend, BackendError, RetryPolicy},
+};
+
 static LOCK_CACHE: Lazy<RwLock<HashMap<StackString, Mutex<()>>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
+/// Authentication and host verification options for the ssh transport
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SSHOptions {
+    /// Private key to authenticate with, the ssh agent is used if unset
+    pub identity_file: Option<PathBuf>,
+    pub known_hosts_file: Option<PathBuf>,
+    /// Value of `StrictHostKeyChecking` (`yes`, `no` or `accept-new`)
+    pub strict_host_key_checking: Option<StackString>,
+    pub connect_timeout: Option<Duration>,
+}
+
+impl SSHOptions {
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            identity_file: config.ssh_identity_file.clone(),
+            known_hosts_file: config.ssh_known_hosts_file.clone(),
+            strict_host_key_checking: config.ssh_strict_host_key_checking.clone(),
+            connect_timeout: Some(Duration::from_secs(config.ssh_connect_timeout)),
+        }
+    }
+}
+
+/// Verifies the server key against known_hosts
+struct ClientHandler {
+    host: StackString,
+    port: u16,
+    options: SSHOptions,
+}
+
+#[async_trait]
+impl Handler for ClientHandler {
+    type Error = Error;
+
+    async fn check_server_key(&mut self, server_public_key: &PublicKey) -> Result<bool, Error> {
+        let strict = self
+            .options
+            .strict_host_key_checking
+            .as_ref()
+            .map_or("yes", StackString::as_str);
+        if strict == "no" {
+            return Ok(true);
+        }
+        let known = match &self.options.known_hosts_file {
+            Some(path) => check_known_hosts_path(&self.host, self.port, server_public_key, path)?,
+            None => check_known_hosts(&self.host, self.port, server_public_key)?,
+        };
+        if !known && strict == "accept-new" {
+            debug!("adding {} to known hosts", self.host);
+            match &self.options.known_hosts_file {
+                Some(path) => {
+                    learn_known_hosts_path(&self.host, self.port, server_public_key, path)?;
+                }
+                None => learn_known_hosts(&self.host, self.port, server_public_key)?,
+            }
+            return Ok(true);
+        }
+        Ok(known)
+    }
+}
+
+fn transient(err: impl Into<Error>) -> BackendError {
+    BackendError::new(Backend::Ssh, true, err)
+}
+
+fn permanent(err: impl Into<Error>) -> BackendError {
+    BackendError::new(Backend::Ssh, false, err)
+}
+
 #[derive(Debug, Clone)]
 pub struct SSHInstance {
     pub user: StackString,
     pub host: StackString,
     pub port: u16,
+    pub retry_policy: RetryPolicy,
+    pub options: SSHOptions,
 }
 
 impl SSHInstance {
@@ -37,9 +122,23 @@ impl SSHInstance {
             user: user.into(),
             host,
             port,
+            retry_policy: RetryPolicy::default(),
+            options: SSHOptions::default(),
         }
     }
 
+    #[must_use]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    #[must_use]
+    pub fn with_options(mut self, options: SSHOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     pub async fn from_url(url: &Url) -> Option<Self> {
         let host = url.host_str()?;
         let port = url.port().unwrap_or(22);
@@ -47,101 +146,168 @@ impl SSHInstance {
         Some(Self::new(user, host, port).await)
     }
 
-    pub fn get_ssh_str(&self, path: impl Display) -> StackString {
-        if self.port == 22 {
-            format_sstr!("{}@{}:{}", self.user, self.host, path)
+    /// Open an authenticated session, using `identity_file` if configured
+    /// and the ssh agent otherwise
+    async fn connect(&self) -> Result<Handle<ClientHandler>, BackendError> {
+        let config = Arc::new(client::Config::default());
+        let handler = ClientHandler {
+            host: self.host.clone(),
+            port: self.port,
+            options: self.options.clone(),
+        };
+        let connect = client::connect(config, (self.host.as_str(), self.port), handler);
+        let mut session = match self.options.connect_timeout {
+            Some(connect_timeout) => timeout(connect_timeout, connect)
+                .await
+                .map_err(transient)?
+                .map_err(transient)?,
+            None => connect.await.map_err(transient)?,
+        };
+        let user = self.user.as_str();
+        let authenticated = if let Some(identity_file) = &self.options.identity_file {
+            let key = load_secret_key(identity_file, None).map_err(permanent)?;
+            session
+                .authenticate_publickey(user, Arc::new(key))
+                .await
+                .map_err(transient)?
+        } else {
+            let mut agent = AgentClient::connect_env().await.map_err(permanent)?;
+            let mut authenticated = false;
+            for key in agent.request_identities().await.map_err(permanent)? {
+                let (returned_agent, result) = session.authenticate_future(user, key, agent).await;
+                agent = returned_agent;
+                if result.map_err(|e| transient(format_err!("{e:?}")))? {
+                    authenticated = true;
+                    break;
+                }
+            }
+            authenticated
+        };
+        if authenticated {
+            Ok(session)
         } else {
-            format_sstr!("-p {} {}@{}:{}", self.port, self.user, self.host, path)
+            Err(permanent(format_err!(
+                "Authentication failed for {}@{}",
+                self.user,
+                self.host
+            )))
         }
     }
 
-    #[must_use]
-    pub fn get_ssh_username_host(&self) -> SmallVec<[StackString; 3]> {
-        let user_host = format_sstr!("{}@{}", self.user, self.host);
-        if self.port == 22 {
-            smallvec![user_host]
-        } else {
-            let port = StackString::from_display(self.port);
-            smallvec!["-p".into(), port, user_host]
-        }
+    /// Run `cmd` on the remote host, yielding stdout line by line as it
+    /// arrives, a non-zero exit status is returned as the final item
+    /// # Errors
+    /// Returns error if connecting or starting the command fails
+    pub async fn run_command_stream(
+        &self,
+        cmd: &str,
+    ) -> Result<impl Stream<Item = Result<StackString, Error>>, Error> {
+        debug!("run_command_stream cmd {}", cmd);
+        let session = self.connect().await?;
+        let mut channel = session.channel_open_session().await.map_err(transient)?;
+        channel.exec(true, cmd).await.map_err(transient)?;
+        let cmd: StackString = cmd.into();
+        let state = (session, channel, Vec::new(), false);
+        Ok(stream::unfold(
+            state,
+            move |(session, mut channel, mut buf, mut done)| {
+                let cmd = cmd.clone();
+                async move {
+                    loop {
+                        if let Some(pos) = buf.iter().position(|c| *c == b'\n') {
+                            let line: Vec<u8> = buf.drain(..=pos).collect();
+                            let line = StackString::from_utf8(&line[..pos]).map_err(Into::into);
+                            return Some((line, (session, channel, buf, done)));
+                        }
+                        if done {
+                            if buf.is_empty() {
+                                session
+                                    .disconnect(Disconnect::ByApplication, "", "English")
+                                    .await
+                                    .ok();
+                                return None;
+                            }
+                            let line = StackString::from_utf8(&buf).map_err(Into::into);
+                            buf.clear();
+                            return Some((line, (session, channel, buf, done)));
+                        }
+                        match channel.wait().await {
+                            Some(ChannelMsg::Data { data }) => buf.extend_from_slice(&data),
+                            Some(ChannelMsg::ExitStatus { exit_status }) if exit_status != 0 => {
+                                done = true;
+                                let err = permanent(format_err!(
+                                    "{cmd} failed with exit status {exit_status}"
+                                ));
+                                return Some((Err(err.into()), (session, channel, buf, done)));
+                            }
+                            None => done = true,
+                            Some(_) => {}
+                        }
+                    }
+                }
+            },
+        ))
     }
 
     /// # Errors
-    /// Returns error if spawn fails or if output is not utf8
+    /// Returns error if connection fails or if output is not utf8
     pub async fn run_command_stream_stdout(&self, cmd: &str) -> Result<Vec<StackString>, Error> {
         if let Some(host_lock) = LOCK_CACHE.read().await.get(&self.host) {
             let _guard = host_lock.lock().await;
             debug!("run_command_stream_stdout cmd {}", cmd);
-            let user_host = self.get_ssh_username_host();
-            let mut args: SmallVec<[&str; 4]> = user_host.iter().map(StackString::as_str).collect();
-            args.push(cmd);
-            let results = Command::new("ssh").args(&args).output().await?;
-            if results.stdout.is_empty() {
-                Ok(Vec::new())
-            } else {
-                results
-                    .stdout
-                    .split(|c| *c == b'\n')
-                    .map(|s| StackString::from_utf8(s).map_err(Into::into))
-                    .collect()
-            }
+            self.retry_policy
+                .retry(|| async {
+                    let lines: Vec<StackString> =
+                        self.run_command_stream(cmd).await?.try_collect().await?;
+                    Ok(lines)
+                })
+                .await
         } else {
             Err(format_err!("Failed to acquire lock"))
         }
     }
 
     /// # Errors
-    /// Returns error if spawn fails or if output is not utf8
+    /// Returns error if connection fails or if output is not utf8
     pub async fn run_command_print_stdout(&self, cmd: &str) -> Result<(), Error> {
         if let Some(host_lock) = LOCK_CACHE.read().await.get(&self.host) {
-            let _guard = host_lock.lock();
+            let _guard = host_lock.lock().await;
             debug!("run_command_print_stdout cmd {}", cmd);
-            let user_host = self.get_ssh_username_host();
-            let mut args: SmallVec<[&str; 4]> = user_host.iter().map(StackString::as_str).collect();
-            args.push(cmd);
-            let mut command = Command::new("ssh")
-                .args(&args)
-                .stdout(Stdio::piped())
-                .spawn()?;
-
-            let stdout_handle = command
-                .stdout
-                .take()
-                .ok_or_else(|| format_err!("No stdout"))?;
-            let mut reader = BufReader::new(stdout_handle);
-
-            let mut line = String::new();
+            let user_host = format_sstr!("{}@{}", self.user, self.host);
             let mut stdout = stdout();
-            while let Ok(bytes) = reader.read_line(&mut line).await {
-                if bytes > 0 {
-                    let user_host = &user_host[user_host.len() - 1];
-                    let write_line = format_sstr!("ssh://{user_host}{line}");
-                    stdout.write_all(write_line.as_bytes()).await?;
-                } else {
-                    break;
-                }
+            let mut lines = Box::pin(self.run_command_stream(cmd).await?);
+            while let Some(line) = lines.try_next().await? {
+                let write_line = format_sstr!("ssh://{user_host}{line}\n");
+                stdout.write_all(write_line.as_bytes()).await?;
             }
-            command.wait().await?;
         }
         Ok(())
     }
 
     /// # Errors
-    /// Returns error if spawn fails or if output is not utf8
+    /// Returns error if connection fails or the command exits with non-zero
+    /// status
     pub async fn run_command_ssh(&self, cmd: &str) -> Result<(), Error> {
-        let user_host = self.get_ssh_username_host();
-        let mut args: SmallVec<[&str; 4]> = user_host.iter().map(StackString::as_str).collect();
-        args.push(cmd);
         if let Some(host_lock) = LOCK_CACHE.read().await.get(&self.host) {
             let _guard = host_lock.lock().await;
             debug!("run_command_ssh cmd {}", cmd);
-            if Command::new("ssh").args(&args).status().await?.success() {
-                Ok(())
-            } else {
-                Err(format_err!("{cmd} failed"))
-            }
+            self.retry_policy
+                .retry(|| async {
+                    let mut lines = Box::pin(self.run_command_stream(cmd).await?);
+                    while lines.try_next().await?.is_some() {}
+                    Ok(())
+                })
+                .await
         } else {
             Err(format_err!("Failed to acquire lock"))
         }
     }
+
+    /// Run a no-op command to verify that the host is reachable, that its
+    /// key is known and that authentication works
+    /// # Errors
+    /// Returns error if the connection or authentication fails
+    pub async fn check_connection(&self) -> Result<(), Error> {
+        self.run_command_ssh("true").await
+    }
 }
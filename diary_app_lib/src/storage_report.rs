@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+
+use crate::{
+    models::{EntrySize, YearSize},
+    s3_interface::S3Storage,
+};
+
+/// Number of entries listed as the biggest in the storage report
+pub const BIGGEST_ENTRIES: i64 = 10;
+
+/// Size of a journal in the database and in s3, to help pick compression
+/// and archival settings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageReport {
+    pub years: Vec<YearSize>,
+    pub biggest: Vec<EntrySize>,
+    pub s3: S3Storage,
+}
+
+impl StorageReport {
+    #[must_use]
+    pub fn entries(&self) -> i64 {
+        self.years.iter().map(|y| y.entries).sum()
+    }
+
+    #[must_use]
+    pub fn characters(&self) -> i64 {
+        self.years.iter().map(|y| y.characters).sum()
+    }
+
+    /// Change of the characters written in each year relative to the year
+    /// before, `None` for the first year
+    #[must_use]
+    pub fn growth(&self) -> Vec<Option<i64>> {
+        let mut previous = None;
+        self.years
+            .iter()
+            .map(|year| {
+                let growth = previous.map(|p| year.characters - p);
+                previous = Some(year.characters);
+                growth
+            })
+            .collect()
+    }
+
+    /// Report as printed by the `stats` command
+    #[must_use]
+    pub fn lines(&self) -> Vec<StackString> {
+        let mut lines = vec![format_sstr!(
+            "{} entries {} characters",
+            self.entries(),
+            self.characters()
+        )];
+        for (year, growth) in self.years.iter().zip(self.growth()) {
+            let growth = growth.map_or_else(StackString::new, |g| format_sstr!(" ({g:+})"));
+            lines.push(format_sstr!(
+                "{} {} entries {} characters{growth}",
+                year.year,
+                year.entries,
+                year.characters
+            ));
+        }
+        for entry in &self.biggest {
+            lines.push(format_sstr!(
+                "biggest {} {} characters",
+                entry.diary_date,
+                entry.characters
+            ));
+        }
+        lines.push(format_sstr!(
+            "s3 {} objects {} snapshot bytes {} delta bytes",
+            self.s3.objects,
+            self.s3.snapshot_bytes,
+            self.s3.delta_bytes
+        ));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use crate::{
+        models::{EntrySize, YearSize},
+        s3_interface::S3Storage,
+        storage_report::StorageReport,
+    };
+
+    #[test]
+    fn test_storage_report() {
+        let year = |year, entries, characters| YearSize {
+            year,
+            entries,
+            characters,
+        };
+        let report = StorageReport {
+            years: vec![year(2022, 300, 90_000), year(2023, 350, 120_000)],
+            biggest: vec![EntrySize {
+                diary_date: date!(2023 - 06 - 01),
+                characters: 4_000,
+            }],
+            s3: S3Storage {
+                objects: 660,
+                snapshot_bytes: 150_000,
+                delta_bytes: 2_000,
+            },
+        };
+        assert_eq!(report.entries(), 650);
+        assert_eq!(report.characters(), 210_000);
+        assert_eq!(report.growth(), vec![None, Some(30_000)]);
+        assert_eq!(
+            report.lines(),
+            vec![
+                "650 entries 210000 characters",
+                "2022 300 entries 90000 characters",
+                "2023 350 entries 120000 characters (+30000)",
+                "biggest 2023-06-01 4000 characters",
+                "s3 660 objects 150000 snapshot bytes 2000 delta bytes",
+            ]
+        );
+    }
+}
@@ -0,0 +1,624 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use futures::{stream, StreamExt, TryStreamExt};
+use log::debug;
+use stack_string::{format_sstr, StackString};
+use std::collections::HashMap;
+use time::{Date, OffsetDateTime};
+
+use crate::{
+    date_sync::{import_action, sync_hash, ImportAction},
+    models::{DateRange, DiaryEntries, SyncBackend, SyncWatermark},
+    pgpool::PgPool,
+    sync_progress::ProgressReporter,
+};
+
+/// The database side of a sync, entries of one journal
+#[async_trait]
+pub trait EntryStore: Send + Sync {
+    fn journal(&self) -> &str;
+
+    /// When each entry in `date_range` was last modified
+    async fn modified_map(
+        &self,
+        date_range: DateRange,
+    ) -> Result<HashMap<Date, OffsetDateTime>, Error>;
+
+    async fn get_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error>;
+
+    /// Replace the entry of the date with `entry`, or with `keep_existing`
+    /// record `entry` as a conflict of the existing entry
+    async fn upsert_entry(&self, entry: &DiaryEntries, keep_existing: bool) -> Result<(), Error>;
+
+    async fn get_watermarks(
+        &self,
+        backend: SyncBackend,
+    ) -> Result<HashMap<Date, SyncWatermark>, Error>;
+
+    async fn set_watermark(&self, watermark: &SyncWatermark) -> Result<(), Error>;
+}
+
+/// Storage entries are copied to and from, one copy per date
+#[async_trait]
+pub trait SyncStore: Send + Sync {
+    fn backend(&self) -> SyncBackend;
+
+    /// Whether the copy in this store or the database entry is kept when
+    /// both changed since the last sync
+    fn keeps_own_copy(&self) -> bool;
+
+    /// When each copy was last modified, by the clock of the store
+    async fn list(&self) -> Result<HashMap<Date, OffsetDateTime>, Error>;
+
+    async fn read(&self, date: Date) -> Result<Option<DiaryEntries>, Error>;
+
+    /// Write a copy of `entry`, returns when it was modified as `list` will
+    /// report it
+    async fn write(&self, entry: &DiaryEntries) -> Result<OffsetDateTime, Error>;
+
+    /// Overwrite a copy which was changed since the last sync
+    async fn replace(&self, entry: &DiaryEntries) -> Result<OffsetDateTime, Error> {
+        self.write(entry).await
+    }
+}
+
+/// Compares the entries of a journal against the copies in a store, copies
+/// whichever side changed since the last sync and records a conflict when
+/// both did
+pub struct SyncEngine<'a> {
+    db: &'a dyn EntryStore,
+    store: &'a dyn SyncStore,
+    date_range: DateRange,
+    concurrency: usize,
+    progress: ProgressReporter,
+}
+
+impl<'a> SyncEngine<'a> {
+    #[must_use]
+    pub fn new(db: &'a dyn EntryStore, store: &'a dyn SyncStore) -> Self {
+        Self {
+            db,
+            store,
+            date_range: DateRange::default(),
+            concurrency: 1,
+            progress: ProgressReporter::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_date_range(mut self, date_range: DateRange) -> Self {
+        self.date_range = date_range;
+        self
+    }
+
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    #[must_use]
+    pub fn with_progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Import every copy in the store modified since the last sync
+    /// # Errors
+    /// Return error if the store or the database fail
+    pub async fn import(&self) -> Result<Vec<DiaryEntries>, Error> {
+        let listing = self.store.list().await?;
+        self.import_listing(listing).await
+    }
+
+    /// Import the copies in `listing` modified since the last sync
+    /// # Errors
+    /// Return error if the store or the database fail
+    pub async fn import_listing(
+        &self,
+        mut listing: HashMap<Date, OffsetDateTime>,
+    ) -> Result<Vec<DiaryEntries>, Error> {
+        let backend = self.store.backend();
+        let watermarks = self.db.get_watermarks(backend).await?;
+        // copies which haven't been modified since the last sync hold the
+        // text of the watermark
+        listing.retain(|date, modified| {
+            self.date_range.contains(*date)
+                && watermarks
+                    .get(date)
+                    .is_none_or(|w| *modified > OffsetDateTime::from(w.modified))
+        });
+        let stage = self
+            .progress
+            .start(&format_sstr!("{backend} import"), listing.len());
+        let watermarks = &watermarks;
+        stream::iter(listing)
+            .map(|(date, modified)| async move {
+                self.import_date(date, modified, watermarks.get(&date))
+                    .await
+            })
+            .buffer_unordered(self.concurrency)
+            .inspect(|_| stage.increment())
+            .try_filter_map(|x| async move { Ok(x) })
+            .try_collect()
+            .await
+    }
+
+    async fn import_date(
+        &self,
+        date: Date,
+        modified: OffsetDateTime,
+        watermark: Option<&SyncWatermark>,
+    ) -> Result<Option<DiaryEntries>, Error> {
+        let Some(entry) = self
+            .store
+            .read(date)
+            .await?
+            .filter(|e| !e.diary_text.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let current = self
+            .db
+            .get_entry(date)
+            .await?
+            .map(|e| sync_hash(&e.diary_text));
+        let hash = sync_hash(&entry.diary_text);
+        let action = import_action(
+            watermark.map(|w| w.hash.as_str()),
+            current.as_deref(),
+            &hash,
+        );
+        debug!(
+            "import {} date {date} lines {} {action:?}",
+            self.store.backend(),
+            entry.diary_text.matches('\n').count()
+        );
+        let keep_existing = action == ImportAction::Conflict && !self.store.keeps_own_copy();
+        if action != ImportAction::Skip {
+            self.db.upsert_entry(&entry, keep_existing).await?;
+        }
+        self.set_watermark(date, hash, modified).await?;
+        if keep_existing {
+            // the copy is kept as a conflict, replace it with the entry
+            if let Some(current) = self.db.get_entry(date).await? {
+                let modified = self.store.replace(&current).await?;
+                self.set_watermark(date, sync_hash(&current.diary_text), modified)
+                    .await?;
+            }
+        }
+        Ok((action != ImportAction::Skip).then_some(entry))
+    }
+
+    /// Write the entries modified since the last sync to the store,
+    /// encrypted and empty entries are never exported
+    /// # Errors
+    /// Return error if the store or the database fail
+    pub async fn export(&self) -> Result<Vec<DiaryEntries>, Error> {
+        let backend = self.store.backend();
+        let listing = self.store.list().await?;
+        let watermarks = self.db.get_watermarks(backend).await?;
+        let modified_map = self.db.modified_map(self.date_range).await?;
+        let stage = self
+            .progress
+            .start(&format_sstr!("{backend} export"), modified_map.len());
+        let (listing, watermarks) = (&listing, &watermarks);
+        stream::iter(modified_map)
+            .map(|(date, last_modified)| async move {
+                let watermark = watermarks.get(&date);
+                let listed = listing.contains_key(&date);
+                // entries untouched since the last sync still hold the text
+                // of the watermark
+                if listed
+                    && watermark.is_some_and(|w| last_modified <= OffsetDateTime::from(w.synced_at))
+                {
+                    return Ok(None);
+                }
+                let Some(entry) = self.db.get_entry(date).await? else {
+                    return Ok(None);
+                };
+                if entry.is_encrypted || entry.diary_text.trim().is_empty() {
+                    return Ok(None);
+                }
+                let hash = sync_hash(&entry.diary_text);
+                if listed && watermark.is_some_and(|w| w.hash == hash) {
+                    return Ok(None);
+                }
+                let modified = self.store.write(&entry).await?;
+                self.set_watermark(date, hash, modified).await?;
+                Ok(Some(entry))
+            })
+            .buffer_unordered(self.concurrency)
+            .inspect(|_| stage.increment())
+            .try_filter_map(|x| async move { Ok(x) })
+            .try_collect()
+            .await
+    }
+
+    async fn set_watermark(
+        &self,
+        date: Date,
+        hash: StackString,
+        modified: OffsetDateTime,
+    ) -> Result<(), Error> {
+        let watermark = SyncWatermark::new(
+            self.db.journal(),
+            date,
+            self.store.backend(),
+            hash,
+            modified,
+        );
+        self.db.set_watermark(&watermark).await
+    }
+}
+
+/// Entries of `journal` in postgres
+#[derive(Clone)]
+pub struct PgEntryStore {
+    pub journal: StackString,
+    pub pool: PgPool,
+}
+
+impl PgEntryStore {
+    #[must_use]
+    pub fn new(journal: impl Into<StackString>, pool: PgPool) -> Self {
+        Self {
+            journal: journal.into(),
+            pool,
+        }
+    }
+}
+
+#[async_trait]
+impl EntryStore for PgEntryStore {
+    fn journal(&self) -> &str {
+        &self.journal
+    }
+
+    async fn modified_map(
+        &self,
+        date_range: DateRange,
+    ) -> Result<HashMap<Date, OffsetDateTime>, Error> {
+        DiaryEntries::get_modified_map(
+            &self.journal,
+            &self.pool,
+            date_range.since,
+            date_range.until,
+        )
+        .await
+    }
+
+    async fn get_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+        DiaryEntries::get_by_date(&self.journal, date, &self.pool).await
+    }
+
+    async fn upsert_entry(&self, entry: &DiaryEntries, keep_existing: bool) -> Result<(), Error> {
+        entry.upsert_entry(&self.pool, !keep_existing).await?;
+        Ok(())
+    }
+
+    async fn get_watermarks(
+        &self,
+        backend: SyncBackend,
+    ) -> Result<HashMap<Date, SyncWatermark>, Error> {
+        SyncWatermark::get_map(&self.journal, backend, &self.pool).await
+    }
+
+    async fn set_watermark(&self, watermark: &SyncWatermark) -> Result<(), Error> {
+        watermark.upsert(&self.pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use proptest::{collection::vec, option, prelude::*};
+    use stack_string::StackString;
+    use std::collections::{HashMap, HashSet};
+    use time::{macros::date, Date, Duration, OffsetDateTime};
+
+    use crate::{
+        models::{DateRange, DiaryEntries, SyncBackend, SyncWatermark, DEFAULT_JOURNAL},
+        sync_engine::{EntryStore, SyncEngine, SyncStore},
+    };
+
+    #[derive(Default)]
+    struct FakeDb {
+        entries: Mutex<HashMap<Date, DiaryEntries>>,
+        conflicts: Mutex<Vec<(Date, StackString)>>,
+        watermarks: Mutex<HashMap<Date, SyncWatermark>>,
+        deleted: Mutex<HashSet<Date>>,
+    }
+
+    impl FakeDb {
+        fn text(&self, date: Date) -> Option<StackString> {
+            self.entries.lock().get(&date).map(|e| e.diary_text.clone())
+        }
+
+        fn edit(&self, date: Date, text: &str) {
+            self.entries
+                .lock()
+                .insert(date, DiaryEntries::new(date, text));
+        }
+
+        fn delete(&self, date: Date) {
+            self.entries.lock().remove(&date);
+            self.deleted.lock().insert(date);
+        }
+    }
+
+    #[async_trait]
+    impl EntryStore for FakeDb {
+        fn journal(&self) -> &str {
+            DEFAULT_JOURNAL
+        }
+
+        async fn modified_map(
+            &self,
+            date_range: DateRange,
+        ) -> Result<HashMap<Date, OffsetDateTime>, Error> {
+            Ok(self
+                .entries
+                .lock()
+                .values()
+                .filter(|e| date_range.contains(e.diary_date))
+                .map(|e| (e.diary_date, e.last_modified.into()))
+                .collect())
+        }
+
+        async fn get_entry(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+            Ok(self.entries.lock().get(&date).cloned())
+        }
+
+        async fn upsert_entry(
+            &self,
+            entry: &DiaryEntries,
+            keep_existing: bool,
+        ) -> Result<(), Error> {
+            // like the tombstones of the database, deleted entries stay
+            // deleted
+            if self.deleted.lock().contains(&entry.diary_date) {
+                return Ok(());
+            }
+            let mut entries = self.entries.lock();
+            if let Some(existing) = entries.get(&entry.diary_date) {
+                if existing.diary_text != entry.diary_text {
+                    let lost = if keep_existing {
+                        &entry.diary_text
+                    } else {
+                        &existing.diary_text
+                    };
+                    self.conflicts.lock().push((entry.diary_date, lost.clone()));
+                }
+                if keep_existing {
+                    return Ok(());
+                }
+            }
+            entries.insert(entry.diary_date, entry.clone());
+            Ok(())
+        }
+
+        async fn get_watermarks(
+            &self,
+            _: SyncBackend,
+        ) -> Result<HashMap<Date, SyncWatermark>, Error> {
+            Ok(self.watermarks.lock().clone())
+        }
+
+        async fn set_watermark(&self, watermark: &SyncWatermark) -> Result<(), Error> {
+            self.watermarks
+                .lock()
+                .insert(watermark.diary_date, watermark.clone());
+            Ok(())
+        }
+    }
+
+    /// Store with its own clock, `skew` ahead of the database
+    struct FakeStore {
+        copies: Mutex<HashMap<Date, (StackString, OffsetDateTime)>>,
+        keeps_own_copy: bool,
+        skew: Duration,
+    }
+
+    impl FakeStore {
+        fn new(keeps_own_copy: bool, skew: Duration) -> Self {
+            Self {
+                copies: Mutex::new(HashMap::new()),
+                keeps_own_copy,
+                skew,
+            }
+        }
+
+        fn text(&self, date: Date) -> Option<StackString> {
+            self.copies.lock().get(&date).map(|(t, _)| t.clone())
+        }
+
+        /// Edit the copy as another client would, a second later than any
+        /// sync so far
+        fn edit(&self, date: Date, text: &str) {
+            let modified = OffsetDateTime::now_utc() + self.skew + Duration::seconds(1);
+            self.copies.lock().insert(date, (text.into(), modified));
+        }
+    }
+
+    #[async_trait]
+    impl SyncStore for FakeStore {
+        fn backend(&self) -> SyncBackend {
+            SyncBackend::S3
+        }
+
+        fn keeps_own_copy(&self) -> bool {
+            self.keeps_own_copy
+        }
+
+        async fn list(&self) -> Result<HashMap<Date, OffsetDateTime>, Error> {
+            Ok(self
+                .copies
+                .lock()
+                .iter()
+                .map(|(date, (_, modified))| (*date, *modified))
+                .collect())
+        }
+
+        async fn read(&self, date: Date) -> Result<Option<DiaryEntries>, Error> {
+            Ok(self
+                .copies
+                .lock()
+                .get(&date)
+                .map(|(text, _)| DiaryEntries::new(date, text.clone())))
+        }
+
+        async fn write(&self, entry: &DiaryEntries) -> Result<OffsetDateTime, Error> {
+            let modified = OffsetDateTime::now_utc() + self.skew;
+            self.copies
+                .lock()
+                .insert(entry.diary_date, (entry.diary_text.clone(), modified));
+            Ok(modified)
+        }
+    }
+
+    const DATE: Date = date!(2024 - 03 - 01);
+
+    /// Database and store both holding `text` and synced
+    async fn synced(text: &str, keeps_own_copy: bool, skew: Duration) -> (FakeDb, FakeStore) {
+        let db = FakeDb::default();
+        let store = FakeStore::new(keeps_own_copy, skew);
+        db.edit(DATE, text);
+        SyncEngine::new(&db, &store).export().await.unwrap();
+        (db, store)
+    }
+
+    #[tokio::test]
+    async fn test_import_new_copy() -> Result<(), Error> {
+        let db = FakeDb::default();
+        let store = FakeStore::new(false, Duration::ZERO);
+        store.edit(DATE, "went hiking");
+        let engine = SyncEngine::new(&db, &store);
+        assert_eq!(engine.import().await?.len(), 1);
+        assert_eq!(db.text(DATE).as_deref(), Some("went hiking"));
+        // nothing changed since
+        assert!(engine.import().await?.is_empty());
+        assert!(engine.export().await?.is_empty());
+        assert!(db.conflicts.lock().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_same_size_edit() -> Result<(), Error> {
+        let (db, store) = synced("went hiking", false, Duration::ZERO).await;
+        store.edit(DATE, "went biking");
+        let engine = SyncEngine::new(&db, &store);
+        assert_eq!(engine.import().await?.len(), 1);
+        assert_eq!(db.text(DATE).as_deref(), Some("went biking"));
+        assert!(db.conflicts.lock().is_empty());
+
+        db.edit(DATE, "went hikinG");
+        assert_eq!(engine.import().await?.len(), 0);
+        assert_eq!(engine.export().await?.len(), 1);
+        assert_eq!(store.text(DATE).as_deref(), Some("went hikinG"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_both_changed() -> Result<(), Error> {
+        let (db, store) = synced("went hiking", false, Duration::ZERO).await;
+        db.edit(DATE, "went hiking\nsaw a deer");
+        store.edit(DATE, "went hiking\nsaw a bear");
+        SyncEngine::new(&db, &store).import().await?;
+        // the database entry is kept and replaces the copy
+        assert_eq!(db.text(DATE).as_deref(), Some("went hiking\nsaw a deer"));
+        assert_eq!(store.text(DATE).as_deref(), Some("went hiking\nsaw a deer"));
+        assert_eq!(
+            db.conflicts.lock().as_slice(),
+            [(DATE, StackString::from("went hiking\nsaw a bear"))]
+        );
+
+        let (db, store) = synced("went hiking", true, Duration::ZERO).await;
+        db.edit(DATE, "went hiking\nsaw a deer");
+        store.edit(DATE, "went hiking\nsaw a bear");
+        SyncEngine::new(&db, &store).import().await?;
+        assert_eq!(db.text(DATE).as_deref(), Some("went hiking\nsaw a bear"));
+        assert_eq!(
+            db.conflicts.lock().as_slice(),
+            [(DATE, StackString::from("went hiking\nsaw a deer"))]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew() -> Result<(), Error> {
+        for skew in [Duration::hours(-1), Duration::hours(1)] {
+            let (db, store) = synced("went hiking", false, skew).await;
+            let engine = SyncEngine::new(&db, &store);
+            assert!(engine.import().await?.is_empty());
+            assert!(engine.export().await?.is_empty());
+
+            store.edit(DATE, "went hiking\nsaw a bear");
+            assert_eq!(engine.import().await?.len(), 1);
+            assert_eq!(db.text(DATE).as_deref(), Some("went hiking\nsaw a bear"));
+            assert!(engine.export().await?.is_empty());
+            assert!(db.conflicts.lock().is_empty());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deletions() -> Result<(), Error> {
+        // a copy removed from the store is written again
+        let (db, store) = synced("went hiking", false, Duration::ZERO).await;
+        store.copies.lock().clear();
+        let engine = SyncEngine::new(&db, &store);
+        assert_eq!(engine.export().await?.len(), 1);
+        assert_eq!(store.text(DATE).as_deref(), Some("went hiking"));
+
+        // an entry deleted from the database isn't imported again from its
+        // unchanged copy
+        db.delete(DATE);
+        db.deleted.lock().clear();
+        assert!(engine.import().await?.is_empty());
+        assert_eq!(db.text(DATE), None);
+        Ok(())
+    }
+
+    fn text() -> impl Strategy<Value = String> {
+        vec("[a-c]{1,3}", 1..4).prop_map(|lines| lines.join("\n"))
+    }
+
+    proptest! {
+        #[test]
+        fn prop_sync_converges_without_losing_text(
+            base in text(),
+            db_edit in option::of(text()),
+            store_edit in option::of(text()),
+            keeps_own_copy in any::<bool>(),
+        ) {
+            let rt = tokio::runtime::Runtime::new()?;
+            let (db, store) = rt.block_on(async {
+                let (db, store) = synced(&base, keeps_own_copy, Duration::ZERO).await;
+                if let Some(db_edit) = &db_edit {
+                    db.edit(DATE, db_edit);
+                }
+                if let Some(store_edit) = &store_edit {
+                    store.edit(DATE, store_edit);
+                }
+                let engine = SyncEngine::new(&db, &store);
+                engine.import().await.unwrap();
+                engine.export().await.unwrap();
+                (db, store)
+            });
+            let current = db.text(DATE);
+            prop_assert!(current.is_some());
+            prop_assert_eq!(&current, &store.text(DATE));
+            // every edit is either the entry or kept as a conflict
+            let conflicts = db.conflicts.lock().clone();
+            for edit in [db_edit, store_edit].into_iter().flatten() {
+                prop_assert!(
+                    current.as_deref() == Some(edit.as_str())
+                        || conflicts.iter().any(|(_, t)| t.as_str() == edit)
+                );
+            }
+        }
+    }
+}
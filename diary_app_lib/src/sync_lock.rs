@@ -0,0 +1,186 @@
+use anyhow::Error;
+use log::{error, info};
+use stack_string::{format_sstr, StackString};
+use std::{process, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::{
+    task::{spawn, JoinHandle},
+    time::{interval, sleep},
+};
+use uuid::Uuid;
+
+use crate::{date_time_wrapper::DateTimeWrapper, models::SyncLease, pgpool::PgPool};
+
+/// Lease shared by every process syncing the database, the cli, the api and
+/// its file watcher
+pub const SYNC_LEASE: &str = "sync";
+
+/// A holder that stops renewing loses the lease after this long
+const LEASE_TTL: Duration = Duration::from_secs(120);
+
+/// How often the holder pushes back the expiry of the lease
+const RENEW_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often a waiting sync checks whether the lease is free
+const WAIT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum SyncLockError {
+    #[error("A sync is already running in {holder} since {acquired_at}")]
+    Held {
+        holder: StackString,
+        acquired_at: DateTimeWrapper,
+    },
+}
+
+/// What to do when another process holds the lease
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncLockMode {
+    /// Give up with `SyncLockError::Held`
+    #[default]
+    Fail,
+    /// Wait until the lease is released or expires
+    Wait,
+    /// Take the lease over, the previous holder finds out when it renews
+    Force,
+}
+
+/// Name of this process in the lease, `source` tells the cli from the api
+#[must_use]
+pub fn sync_holder(source: Option<&str>) -> StackString {
+    let id = Uuid::new_v4().simple().to_string();
+    format_sstr!(
+        "{} pid {} {}",
+        source.unwrap_or("unknown"),
+        process::id(),
+        &id[..8]
+    )
+}
+
+/// Held lease, renewed in the background until released
+pub struct SyncLock {
+    lease: SyncLease,
+    pool: PgPool,
+    renewal: JoinHandle<()>,
+}
+
+impl SyncLock {
+    /// # Errors
+    /// Return `SyncLockError::Held` if another process holds the lease in
+    /// `SyncLockMode::Fail`, or error if db query fails
+    pub async fn acquire(pool: &PgPool, holder: &str, mode: SyncLockMode) -> Result<Self, Error> {
+        let ttl_secs = LEASE_TTL.as_secs_f64();
+        let force = mode == SyncLockMode::Force;
+        let mut waiting = false;
+        let lease = loop {
+            if let Some(lease) =
+                SyncLease::try_acquire(SYNC_LEASE, holder, ttl_secs, force, pool).await?
+            {
+                break lease;
+            }
+            let Some(current) = SyncLease::get_by_name(SYNC_LEASE, pool).await? else {
+                // released or expired since, try again straight away
+                continue;
+            };
+            if mode == SyncLockMode::Fail {
+                return Err(SyncLockError::Held {
+                    holder: current.holder,
+                    acquired_at: current.acquired_at,
+                }
+                .into());
+            }
+            if !waiting {
+                info!("waiting for the sync running in {}", current.holder);
+                waiting = true;
+            }
+            sleep(WAIT_INTERVAL).await;
+        };
+        if force {
+            info!("{holder} took over the sync lease");
+        }
+        let renewal = spawn({
+            let lease = lease.clone();
+            let pool = pool.clone();
+            async move {
+                let mut i = interval(RENEW_INTERVAL);
+                i.tick().await;
+                loop {
+                    i.tick().await;
+                    match lease.renew(ttl_secs, &pool).await {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            error!("{} lost the sync lease", lease.holder);
+                            break;
+                        }
+                        Err(e) => error!("failed to renew the sync lease {e}"),
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            lease,
+            pool: pool.clone(),
+            renewal,
+        })
+    }
+
+    #[must_use]
+    pub fn lease(&self) -> &SyncLease {
+        &self.lease
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn release(self) -> Result<(), Error> {
+        self.renewal.abort();
+        self.lease.release(&self.pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::{
+        models::SyncLease,
+        sync_lock::{sync_holder, SyncLock, SyncLockError, SyncLockMode, SYNC_LEASE},
+        test_db::TestDb,
+    };
+
+    #[test]
+    fn test_sync_holder() {
+        let holder = sync_holder(Some("cli"));
+        assert!(holder.starts_with("cli pid "));
+        assert_ne!(holder, sync_holder(Some("cli")));
+        assert!(sync_holder(None).starts_with("unknown pid "));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_lock() -> Result<(), Error> {
+        let db = TestDb::new().await?;
+        let lock = SyncLock::acquire(&db.pool, "cli", SyncLockMode::Fail).await?;
+        assert_eq!(lock.lease().holder, "cli");
+
+        let e = SyncLock::acquire(&db.pool, "api", SyncLockMode::Fail)
+            .await
+            .err()
+            .unwrap();
+        let e = e.downcast::<SyncLockError>()?;
+        assert!(matches!(e, SyncLockError::Held { holder, .. } if holder == "cli"));
+
+        let forced = SyncLock::acquire(&db.pool, "api", SyncLockMode::Force).await?;
+        assert!(!lock.lease().renew(60.0, &db.pool).await?);
+        // releasing a lease taken over leaves the new holder alone
+        lock.release().await?;
+        let current = SyncLease::get_by_name(SYNC_LEASE, &db.pool).await?.unwrap();
+        assert_eq!(current.holder, "api");
+
+        forced.release().await?;
+        assert!(SyncLease::get_by_name(SYNC_LEASE, &db.pool)
+            .await?
+            .is_none());
+        let lock = SyncLock::acquire(&db.pool, "cli", SyncLockMode::Wait).await?;
+        lock.release().await?;
+        db.cleanup().await
+    }
+}
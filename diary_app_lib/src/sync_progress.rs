@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{fmt, sync::Arc};
+use tokio::sync::watch::{channel, Receiver, Sender};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageProgress {
+    pub stage: StackString,
+    pub processed: usize,
+    pub total: usize,
+}
+
+impl StageProgress {
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.processed >= self.total
+    }
+}
+
+impl fmt::Display for StageProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}/{}", self.stage, self.processed, self.total)
+    }
+}
+
+/// Progress of each running stage, the local and s3 stages of a sync run
+/// concurrently and count their dates separately
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncProgress {
+    /// In the order they were started
+    pub stages: Vec<StageProgress>,
+}
+
+impl SyncProgress {
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.stages.iter().all(StageProgress::is_finished)
+    }
+
+    #[must_use]
+    pub fn stage(&self, stage: &str) -> Option<&StageProgress> {
+        self.stages.iter().find(|s| s.stage == stage)
+    }
+
+    /// The stage started last
+    #[must_use]
+    pub fn latest(&self) -> Option<&StageProgress> {
+        self.stages.last()
+    }
+}
+
+impl fmt::Display for SyncProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, stage) in self.stages.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{stage}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared handle used by the sync routines to publish how many dates have
+/// been processed, frontends subscribe to it to display progress
+#[derive(Clone, Debug)]
+pub struct ProgressReporter(Arc<Sender<SyncProgress>>);
+
+impl Default for ProgressReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressReporter {
+    #[must_use]
+    pub fn new() -> Self {
+        let (send, _) = channel(SyncProgress::default());
+        Self(Arc::new(send))
+    }
+
+    /// Start counting `stage`, finished stages are dropped while the ones
+    /// still running are left alone
+    #[must_use]
+    pub fn start(&self, stage: &str, total: usize) -> ProgressStage {
+        self.0.send_modify(|p| {
+            p.stages.retain(|s| s.stage != stage && !s.is_finished());
+            p.stages.push(StageProgress {
+                stage: stage.into(),
+                processed: 0,
+                total,
+            });
+        });
+        ProgressStage {
+            send: self.0.clone(),
+            stage: stage.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn current(&self) -> SyncProgress {
+        self.0.borrow().clone()
+    }
+
+    #[must_use]
+    pub fn subscribe(&self) -> Receiver<SyncProgress> {
+        self.0.subscribe()
+    }
+}
+
+/// Counts the processed dates of one stage, returned by
+/// [`ProgressReporter::start`]
+#[derive(Clone, Debug)]
+pub struct ProgressStage {
+    send: Arc<Sender<SyncProgress>>,
+    stage: StackString,
+}
+
+impl ProgressStage {
+    pub fn increment(&self) {
+        self.send.send_modify(|p| {
+            if let Some(stage) = p.stages.iter_mut().find(|s| s.stage == self.stage) {
+                stage.processed += 1;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::sync_progress::{ProgressReporter, StageProgress};
+
+    #[test]
+    fn test_progress_reporter() -> Result<(), Error> {
+        let progress = ProgressReporter::new();
+        let mut recv = progress.subscribe();
+        assert!(progress.current().is_finished());
+
+        let stage = progress.start("s3 import", 2);
+        stage.increment();
+        assert!(recv.has_changed()?);
+        let current = recv.borrow_and_update().clone();
+        assert_eq!(
+            current.stage("s3 import"),
+            Some(&StageProgress {
+                stage: "s3 import".into(),
+                processed: 1,
+                total: 2,
+            })
+        );
+        assert!(!current.is_finished());
+        assert_eq!(&current.to_string(), "s3 import 1/2");
+
+        stage.increment();
+        assert!(progress.current().is_finished());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_stages() {
+        let progress = ProgressReporter::new();
+        let local = progress.start("local import", 3);
+        local.increment();
+        let s3 = progress.start("s3 import", 2);
+        s3.increment();
+        local.increment();
+        s3.increment();
+
+        let current = progress.current();
+        assert_eq!(&current.to_string(), "local import 2/3, s3 import 2/2");
+        assert!(!current.is_finished());
+        assert_eq!(
+            current.latest().map(|s| s.stage.as_str()),
+            Some("s3 import")
+        );
+
+        local.increment();
+        assert!(progress.current().is_finished());
+
+        // finished stages make way for the next ones
+        let export = progress.start("local export", 1);
+        let current = progress.current();
+        assert_eq!(&current.to_string(), "local export 0/1");
+        export.increment();
+        assert!(progress.current().is_finished());
+    }
+}
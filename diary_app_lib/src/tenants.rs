@@ -0,0 +1,312 @@
+use anyhow::{format_err, Error};
+use serde::Deserialize;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error as ThisError;
+
+use crate::{
+    config::{Config, ConfigInner},
+    models::Journal,
+};
+
+/// Tenants without a `diary_path` or bucket of their own are kept under
+/// this directory of `diary_path` and key prefix of `diary_bucket`, the dot
+/// keeps it apart from journal names
+pub const TENANT_DIR: &str = ".tenants";
+
+/// Path prefix selecting a tenant, `/t/smith/api/list`
+pub const TENANT_PATH_PREFIX: &str = "/t/";
+
+/// Name selecting the default diary in the path, `/t/default/api/list`
+pub const DEFAULT_TENANT: &str = "default";
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum TenantError {
+    #[error("Invalid tenant name {0}")]
+    InvalidName(StackString),
+    #[error("Tenant {0} is listed more than once")]
+    DuplicateName(StackString),
+    #[error("Domain {0} is listed for more than one tenant")]
+    DuplicateDomain(StackString),
+}
+
+/// Diary hosted next to the default one with its own database, and so its
+/// own users, its own files and its own s3 keys
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    pub name: StackString,
+    /// Hosts served as this tenant, e.g. `smith.example.com`
+    #[serde(default)]
+    pub domains: Vec<StackString>,
+    pub database_url: StackString,
+    /// Defaults to the shared `diary_bucket` with keys under
+    /// `.tenants/{name}/`
+    pub diary_bucket: Option<StackString>,
+    pub s3_prefix: Option<StackString>,
+    /// Defaults to `.tenants/{name}` in the shared `diary_path`
+    pub diary_path: Option<PathBuf>,
+}
+
+impl Tenant {
+    /// Config of the tenant, everything not isolated per tenant comes from
+    /// `base` while the sync targets, bots and tokens of the default diary
+    /// are left out
+    #[must_use]
+    pub fn config(&self, base: &ConfigInner) -> Config {
+        let mut config = base.clone();
+        config.database_url.clone_from(&self.database_url);
+        config.s3_prefix = match (&self.diary_bucket, &self.s3_prefix) {
+            (_, Some(prefix)) => Some(prefix.clone()),
+            (Some(_), None) => None,
+            (None, None) => Some(format_sstr!("{TENANT_DIR}/{}/", self.name)),
+        };
+        if let Some(bucket) = &self.diary_bucket {
+            config.diary_bucket.clone_from(bucket);
+        }
+        config.diary_path = self
+            .diary_path
+            .clone()
+            .unwrap_or_else(|| base.diary_path.join(TENANT_DIR).join(self.name.as_str()));
+        config.telegram_bot_token = StackString::new();
+        config.telegram_webhook_url = None;
+        config.telegram_webhook_in_api = false;
+        config.matrix_homeserver = None;
+        config.matrix_username = None;
+        config.matrix_password = None;
+        config.ssh_url = None;
+        config.peer_url = None;
+        config.peer_token = None;
+        config.unlock_pin = None;
+        config.unlock_totp_secret = None;
+        config.guestbook_enabled = false;
+        config.guestbook_tokens = None;
+        config.kiosk_tokens = None;
+        config.replica_bucket = None;
+        config.into()
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        let host = host.split_once(':').map_or(host, |(host, _)| host);
+        self.domains.iter().any(|d| d.eq_ignore_ascii_case(host))
+    }
+}
+
+/// Read the json list of tenants in `path`
+/// # Errors
+/// Return error if the file can't be read or parsed, or lists an invalid
+/// tenant
+pub fn load_tenants(path: &Path) -> Result<Vec<Tenant>, Error> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format_err!("Failed to read {}: {e}", path.display()))?;
+    let tenants: Vec<Tenant> = serde_json::from_str(&data)?;
+    validate_tenants(&tenants)?;
+    Ok(tenants)
+}
+
+/// Tenant names become a directory and key prefix, and every domain picks
+/// a single tenant
+/// # Errors
+/// Return error if a name is invalid or a name or domain is repeated
+pub fn validate_tenants(tenants: &[Tenant]) -> Result<(), TenantError> {
+    let mut names = HashSet::new();
+    let mut domains = HashSet::new();
+    for tenant in tenants {
+        if !Journal::is_valid_name(&tenant.name) || tenant.name == DEFAULT_TENANT {
+            return Err(TenantError::InvalidName(tenant.name.clone()));
+        }
+        if !names.insert(&tenant.name) {
+            return Err(TenantError::DuplicateName(tenant.name.clone()));
+        }
+        for domain in &tenant.domains {
+            if !domains.insert(domain.to_ascii_lowercase()) {
+                return Err(TenantError::DuplicateDomain(domain.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tenant serving a request, by host first, then by a `/t/{name}` path
+/// prefix and last by the tenant remembered in a cookie, `None` for the
+/// default diary.  The path to route is returned when the prefix has to be
+/// stripped, `/t/default` picks the default diary.
+#[must_use]
+pub fn resolve_tenant<'a>(
+    tenants: &'a [Tenant],
+    host: Option<&str>,
+    path: &str,
+    cookie: Option<&str>,
+) -> (Option<&'a Tenant>, Option<StackString>) {
+    if let Some(host) = host {
+        if let Some(tenant) = tenants.iter().find(|t| t.matches_host(host)) {
+            return (Some(tenant), None);
+        }
+    }
+    if let Some(rest) = path.strip_prefix(TENANT_PATH_PREFIX) {
+        let (name, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        if name == DEFAULT_TENANT {
+            return (None, Some(format_sstr!("/{rest}")));
+        }
+        if let Some(tenant) = tenants.iter().find(|t| t.name == name) {
+            return (Some(tenant), Some(format_sstr!("/{rest}")));
+        }
+    }
+    let tenant = cookie.and_then(|name| tenants.iter().find(|t| t.name == name));
+    (tenant, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        config::ConfigInner,
+        tenants::{resolve_tenant, validate_tenants, Tenant, TenantError},
+    };
+
+    fn tenant(name: &str, domain: &str) -> Tenant {
+        Tenant {
+            name: name.into(),
+            domains: vec![domain.into()],
+            database_url: format!("postgresql://localhost/diary_{name}").into(),
+            diary_bucket: None,
+            s3_prefix: None,
+            diary_path: None,
+        }
+    }
+
+    #[test]
+    fn test_tenant_config() {
+        let base = ConfigInner {
+            diary_path: "/srv/diary".into(),
+            diary_bucket: "diary_bucket".into(),
+            ssh_url: Some("ssh://user@host/diary".into()),
+            peer_token: Some("secret".into()),
+            ..ConfigInner::default()
+        };
+        let smith = tenant("smith", "smith.example.com");
+        let config = smith.config(&base);
+        assert_eq!(config.database_url, "postgresql://localhost/diary_smith");
+        assert_eq!(config.diary_bucket, "diary_bucket");
+        assert_eq!(config.diary_path, Path::new("/srv/diary/.tenants/smith"));
+        assert_eq!(config.journal_prefix("diary"), ".tenants/smith/");
+        assert_eq!(config.journal_prefix("dreams"), ".tenants/smith/dreams/");
+        assert_eq!(config.ssh_url, None);
+        assert_eq!(config.peer_token, None);
+
+        let jones = Tenant {
+            diary_bucket: Some("jones_bucket".into()),
+            diary_path: Some("/srv/jones".into()),
+            ..tenant("jones", "jones.example.com")
+        };
+        let config = jones.config(&base);
+        assert_eq!(config.diary_bucket, "jones_bucket");
+        assert_eq!(config.diary_path, Path::new("/srv/jones"));
+        assert_eq!(config.journal_prefix("diary"), "");
+    }
+
+    #[test]
+    fn test_validate_tenants() {
+        let tenants = [
+            tenant("smith", "smith.example.com"),
+            tenant("jones", "jones.example.com"),
+        ];
+        assert_eq!(validate_tenants(&tenants), Ok(()));
+
+        let tenants = [tenant("../smith", "smith.example.com")];
+        assert_eq!(
+            validate_tenants(&tenants),
+            Err(TenantError::InvalidName("../smith".into()))
+        );
+        let tenants = [tenant("default", "smith.example.com")];
+        assert_eq!(
+            validate_tenants(&tenants),
+            Err(TenantError::InvalidName("default".into()))
+        );
+        let tenants = [
+            tenant("smith", "smith.example.com"),
+            tenant("smith", "jones.example.com"),
+        ];
+        assert_eq!(
+            validate_tenants(&tenants),
+            Err(TenantError::DuplicateName("smith".into()))
+        );
+        let tenants = [
+            tenant("smith", "smith.example.com"),
+            tenant("jones", "Smith.example.com"),
+        ];
+        assert_eq!(
+            validate_tenants(&tenants),
+            Err(TenantError::DuplicateDomain("Smith.example.com".into()))
+        );
+    }
+
+    #[test]
+    fn test_resolve_tenant() {
+        let tenants = [
+            tenant("smith", "smith.example.com"),
+            tenant("jones", "jones.example.com"),
+        ];
+        let name = |(t, path): (Option<&Tenant>, _)| (t.map(|t| t.name.as_str()), path);
+
+        assert_eq!(
+            name(resolve_tenant(
+                &tenants,
+                Some("smith.example.com:443"),
+                "/api/list",
+                None
+            )),
+            (Some("smith"), None)
+        );
+        assert_eq!(
+            name(resolve_tenant(
+                &tenants,
+                Some("diary.example.com"),
+                "/t/jones/api/list",
+                None
+            )),
+            (Some("jones"), Some("/api/list".into()))
+        );
+        assert_eq!(
+            name(resolve_tenant(
+                &tenants,
+                Some("diary.example.com"),
+                "/api/list",
+                Some("jones")
+            )),
+            (Some("jones"), None)
+        );
+        assert_eq!(
+            name(resolve_tenant(
+                &tenants,
+                None,
+                "/t/other/api/list",
+                Some("other")
+            )),
+            (None, None)
+        );
+        assert_eq!(
+            name(resolve_tenant(
+                &tenants,
+                None,
+                "/t/default/api/list",
+                Some("jones")
+            )),
+            (None, Some("/api/list".into()))
+        );
+        // the host wins over the path and cookie
+        assert_eq!(
+            name(resolve_tenant(
+                &tenants,
+                Some("smith.example.com"),
+                "/t/jones/api",
+                None
+            )),
+            (Some("smith"), None)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{format_err, Error};
+use log::error;
+use stack_string::{format_sstr, StackString};
+use std::env::var;
+use time::{Date, Duration, OffsetDateTime};
+use tokio::spawn;
+use tokio_postgres::{connect, NoTls};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    date_time_wrapper::DateTimeWrapper,
+    db_migrations::run_migrations,
+    line_diff::LineDiff,
+    models::{DiaryConflict, DiaryEntries, DEFAULT_JOURNAL},
+    pgpool::PgPool,
+};
+
+/// Database created for a single test with the migrations applied, tests
+/// never see the entries of the configured database.  The server is taken
+/// from `TEST_DATABASE_URL`, falling back to `database_url` of the config,
+/// the user needs the `CREATEDB` privilege.
+pub struct TestDb {
+    admin_url: StackString,
+    name: StackString,
+    pub pool: PgPool,
+}
+
+impl TestDb {
+    /// # Errors
+    /// Return error if creating or migrating the database fails
+    pub async fn new() -> Result<Self, Error> {
+        let admin_url: StackString = match var("TEST_DATABASE_URL") {
+            Ok(url) => url.into(),
+            Err(_) => Config::init_config()?.database_url.clone(),
+        };
+        let name = format_sstr!("diary_app_test_{}", Uuid::new_v4().simple());
+        Self::execute(&admin_url, &format_sstr!("CREATE DATABASE {name}")).await?;
+
+        let mut url: Url = admin_url.parse()?;
+        url.set_path(&name);
+        let pool = PgPool::new(url.as_str())?;
+        run_migrations(&pool).await?;
+        Ok(Self {
+            admin_url,
+            name,
+            pool,
+        })
+    }
+
+    async fn execute(url: &str, statement: &str) -> Result<(), Error> {
+        let (client, connection) = connect(url, NoTls).await?;
+        spawn(async move {
+            if let Err(e) = connection.await {
+                error!("connection error {e}");
+            }
+        });
+        client.batch_execute(statement).await?;
+        Ok(())
+    }
+
+    /// Drop the database, a test which fails before calling this leaves a
+    /// `diary_app_test_*` database behind
+    /// # Errors
+    /// Return error if dropping the database fails
+    pub async fn cleanup(self) -> Result<(), Error> {
+        self.pool.close();
+        let statement = format_sstr!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.name);
+        Self::execute(&self.admin_url, &statement).await
+    }
+}
+
+/// Builder of an entry inserted by a test
+#[derive(Clone, Debug)]
+pub struct EntryFixture(DiaryEntries);
+
+impl EntryFixture {
+    #[must_use]
+    pub fn new(date: Date, text: &str) -> Self {
+        Self(DiaryEntries::new(date, text))
+    }
+
+    #[must_use]
+    pub fn journal(self, journal: &str) -> Self {
+        Self(self.0.with_journal(journal))
+    }
+
+    #[must_use]
+    pub fn modified(mut self, modified: OffsetDateTime) -> Self {
+        self.0.last_modified = modified.into();
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> DiaryEntries {
+        self.0
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(self, pool: &PgPool) -> Result<DiaryEntries, Error> {
+        self.0.insert_entry(pool).await?;
+        Ok(self.0)
+    }
+
+    /// Insert an entry for each of `ndays` consecutive days from `start`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert_days(
+        start: Date,
+        ndays: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<DiaryEntries>, Error> {
+        let mut entries = Vec::new();
+        for i in 0..ndays {
+            let date = start + Duration::days(i);
+            let text = format_sstr!("entry for {date}");
+            entries.push(Self::new(date, &text).insert(pool).await?);
+        }
+        Ok(entries)
+    }
+}
+
+/// Builder of a conflict between two versions of an entry
+#[derive(Clone, Debug)]
+pub struct ConflictFixture {
+    journal: StackString,
+    date: Date,
+    original: StackString,
+    updated: StackString,
+    sync_datetime: OffsetDateTime,
+}
+
+impl ConflictFixture {
+    #[must_use]
+    pub fn new(date: Date, original: &str, updated: &str) -> Self {
+        Self {
+            journal: DEFAULT_JOURNAL.into(),
+            date,
+            original: original.into(),
+            updated: updated.into(),
+            sync_datetime: OffsetDateTime::now_utc(),
+        }
+    }
+
+    #[must_use]
+    pub fn journal(mut self, journal: &str) -> Self {
+        self.journal = journal.into();
+        self
+    }
+
+    #[must_use]
+    pub fn sync_datetime(mut self, sync_datetime: OffsetDateTime) -> Self {
+        self.sync_datetime = sync_datetime;
+        self
+    }
+
+    #[must_use]
+    pub fn build(&self) -> Vec<DiaryConflict> {
+        let diff = LineDiff::new(&self.original, &self.updated);
+        DiaryConflict::from_diff(&self.journal, self.date, self.sync_datetime, diff)
+    }
+
+    /// Insert the conflict rows, returns their `sync_datetime`
+    /// # Errors
+    /// Return error if db query fails or the versions don't differ
+    pub async fn insert(self, pool: &PgPool) -> Result<DateTimeWrapper, Error> {
+        let conflicts = self.build();
+        if conflicts.iter().all(|c| c.diff_type.as_str() == "same") {
+            return Err(format_err!("original and updated text are the same"));
+        }
+        for conflict in &conflicts {
+            conflict.insert_conflict(pool).await?;
+        }
+        Ok(self.sync_datetime.into())
+    }
+}
@@ -0,0 +1,61 @@
+use stack_string::StackString;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+/// Text of an entry as it is stored, so the same text written from macOS,
+/// Linux or Telegram compares equal: `\n` line endings, NFC, no trailing
+/// whitespace on lines nor at the end
+#[must_use]
+pub fn normalize_text(text: &str) -> StackString {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let lines: Vec<&str> = text.split('\n').map(str::trim_end).collect();
+    let text = lines.join("\n");
+    let text = text.trim_end();
+    if is_nfc(text) {
+        text.into()
+    } else {
+        text.nfc().collect::<String>().into()
+    }
+}
+
+/// Whether `normalize_text` leaves `text` as is
+#[must_use]
+pub fn is_normalized(text: &str) -> bool {
+    !text.contains('\r')
+        && text.len() == text.trim_end().len()
+        && text
+            .split('\n')
+            .all(|line| line.len() == line.trim_end().len())
+        && is_nfc(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::text_normalize::{is_normalized, normalize_text};
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("a  \r\nb\t\rc\n\n"), "a\nb\nc");
+        assert_eq!(
+            normalize_text("  indented\n\n\nkept"),
+            "  indented\n\n\nkept"
+        );
+        // e followed by a combining acute accent as typed on macOS
+        assert_eq!(normalize_text("cafe\u{301}"), "caf\u{e9}");
+        assert!(is_normalized("caf\u{e9}\n\nok"));
+        assert!(!is_normalized("cafe\u{301}"));
+        assert!(!is_normalized("a \nb"));
+        assert!(!is_normalized("a\r\nb"));
+        assert!(!is_normalized("a\n"));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_normalize_text_idempotent(text in "\\PC*( |\r|\n|\u{301})*\\PC*") {
+            let normalized = normalize_text(&text);
+            prop_assert!(is_normalized(&normalized));
+            prop_assert_eq!(normalize_text(&normalized), normalized);
+        }
+    }
+}
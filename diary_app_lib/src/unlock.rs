@@ -0,0 +1,341 @@
+use hmac::{Hmac, Mac};
+use parking_lot::RwLock;
+use rand::{thread_rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    sync::Arc,
+};
+use subtle::ConstantTimeEq;
+use thiserror::Error as ThisError;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+const TOTP_STEP: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// Wrong codes a session may try before it has to wait
+const MAX_FAILURES: u32 = 5;
+/// Wait after `MAX_FAILURES` wrong codes, doubled with every further one
+const LOCKOUT_SECS: i64 = 60;
+const MAX_LOCKOUT_SECS: i64 = 3600;
+
+#[derive(ThisError, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockError {
+    #[error("Invalid unlock code")]
+    InvalidCode,
+    #[error("Too many invalid unlock codes, retry after {0}")]
+    TooManyAttempts(OffsetDateTime),
+}
+
+/// Decode an RFC 4648 base32 string (as used for TOTP secrets), ignoring
+/// padding, whitespace and case
+#[must_use]
+pub fn decode_base32(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for c in input.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(output)
+}
+
+/// RFC 6238 code for the given time step counter
+#[must_use]
+pub fn totp_code(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    code % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Accept the code for the current time step and its immediate neighbours
+/// to allow for clock drift
+#[must_use]
+pub fn verify_totp(secret: &[u8], code: &str, now: OffsetDateTime) -> bool {
+    let Ok(code) = code.trim().parse::<u32>() else {
+        return false;
+    };
+    let counter = now.unix_timestamp() / TOTP_STEP;
+    // check every step rather than stopping at the first match
+    (counter - 1..=counter + 1)
+        .filter_map(|c| u64::try_from(c).ok())
+        .fold(false, |ok, c| {
+            ok | bool::from(totp_code(secret, c).ct_eq(&code))
+        })
+}
+
+/// Server side unlock state, keyed by session id, gating access to entry
+/// text in the web UI even for logged in users
+#[derive(Clone, Debug, Default)]
+pub struct UnlockSessions {
+    /// Random per process, the PIN only lives in the config
+    pin_salt: [u8; 32],
+    pin_hash: Option<Vec<u8>>,
+    totp_secret: Option<Vec<u8>>,
+    timeout: Duration,
+    sessions: Arc<RwLock<HashMap<Uuid, OffsetDateTime>>>,
+    failures: Arc<RwLock<HashMap<Uuid, Failures>>>,
+}
+
+/// Wrong codes entered by a session since its last unlock
+#[derive(Clone, Copy, Debug, Default)]
+struct Failures {
+    count: u32,
+    last_failure: Option<OffsetDateTime>,
+    locked_until: Option<OffsetDateTime>,
+}
+
+impl Failures {
+    /// Forgotten `MAX_LOCKOUT_SECS` after the last wrong code or the end of
+    /// the lockout, whichever is later, so waits still double in between
+    fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.last_failure
+            .max(self.locked_until)
+            .is_none_or(|last| last + Duration::seconds(MAX_LOCKOUT_SECS) <= now)
+    }
+}
+
+fn hash_pin(salt: &[u8], pin: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(pin.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+impl UnlockSessions {
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        let mut pin_salt = [0u8; 32];
+        thread_rng().fill_bytes(&mut pin_salt);
+        Self {
+            pin_salt,
+            pin_hash: config
+                .unlock_pin
+                .as_ref()
+                .map(|pin| hash_pin(&pin_salt, pin)),
+            totp_secret: config
+                .unlock_totp_secret
+                .as_ref()
+                .and_then(|s| decode_base32(s)),
+            timeout: Duration::seconds(config.unlock_timeout.try_into().unwrap_or(i64::MAX)),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            failures: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The gate is only active if a PIN or TOTP secret is configured
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.pin_hash.is_some() || self.totp_secret.is_some()
+    }
+
+    #[must_use]
+    pub fn verify_code(&self, code: &str, now: OffsetDateTime) -> bool {
+        let pin_ok = self
+            .pin_hash
+            .as_ref()
+            .is_some_and(|pin_hash| hash_pin(&self.pin_salt, code.trim()).ct_eq(pin_hash).into());
+        let totp_ok = self
+            .totp_secret
+            .as_ref()
+            .is_some_and(|secret| verify_totp(secret, code, now));
+        pin_ok || totp_ok
+    }
+
+    /// Unlock `session` if `code` is valid, returning the expiry time.
+    /// After `MAX_FAILURES` wrong codes the session has to wait before
+    /// trying again, longer with every further wrong code.
+    /// # Errors
+    /// Return `UnlockError` if the code is wrong or the session has to wait
+    pub fn unlock(
+        &self,
+        session: Uuid,
+        code: &str,
+        now: OffsetDateTime,
+    ) -> Result<OffsetDateTime, UnlockError> {
+        let mut failures = self.failures.write();
+        failures.retain(|_, f| !f.is_expired(now));
+        if let Some(locked_until) = failures.get(&session).and_then(|f| f.locked_until) {
+            if locked_until > now {
+                return Err(UnlockError::TooManyAttempts(locked_until));
+            }
+        }
+        if !self.verify_code(code, now) {
+            let entry = failures.entry(session).or_default();
+            entry.count += 1;
+            entry.last_failure = Some(now);
+            if entry.count >= MAX_FAILURES {
+                let doublings = (entry.count - MAX_FAILURES).min(6);
+                let lockout = (LOCKOUT_SECS << doublings).min(MAX_LOCKOUT_SECS);
+                entry.locked_until = Some(now + Duration::seconds(lockout));
+            }
+            return Err(UnlockError::InvalidCode);
+        }
+        failures.remove(&session);
+        let expires_at = now + self.timeout;
+        let mut sessions = self.sessions.write();
+        sessions.retain(|_, expiry| *expiry > now);
+        sessions.insert(session, expires_at);
+        Ok(expires_at)
+    }
+
+    pub fn lock(&self, session: Uuid) {
+        self.sessions.write().remove(&session);
+    }
+
+    #[must_use]
+    pub fn is_unlocked(&self, session: Uuid) -> bool {
+        self.is_unlocked_at(session, OffsetDateTime::now_utc())
+    }
+
+    fn is_unlocked_at(&self, session: Uuid, now: OffsetDateTime) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        self.sessions
+            .read()
+            .get(&session)
+            .is_some_and(|expiry| *expiry > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+    use uuid::Uuid;
+
+    use crate::unlock::{
+        decode_base32, hash_pin, totp_code, verify_totp, UnlockError, UnlockSessions,
+    };
+
+    #[test]
+    fn test_totp() {
+        // RFC 6238 test vector for SHA1
+        let secret = decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(secret, b"12345678901234567890");
+        assert_eq!(totp_code(&secret, 59 / 30), 287_082);
+        assert_eq!(totp_code(&secret, 1_111_111_109 / 30), 81_804);
+
+        let now = datetime!(2005-03-18 01:58:29 UTC);
+        assert!(verify_totp(&secret, "081804", now));
+        assert!(!verify_totp(&secret, "081805", now));
+        assert!(!verify_totp(&secret, "abc", now));
+    }
+
+    #[test]
+    fn test_unlock_sessions() {
+        let pin_salt = [7u8; 32];
+        let sessions = UnlockSessions {
+            pin_salt,
+            pin_hash: Some(hash_pin(&pin_salt, "1234")),
+            timeout: Duration::minutes(5),
+            ..UnlockSessions::default()
+        };
+        let session = Uuid::new_v4();
+        let now = datetime!(2024-01-01 12:00 UTC);
+        assert!(!sessions.is_unlocked_at(session, now));
+
+        let expires_at = sessions.unlock(session, " 1234 ", now).unwrap();
+        assert_eq!(expires_at, now + Duration::minutes(5));
+        assert!(sessions.is_unlocked_at(session, now + Duration::minutes(4)));
+        assert!(!sessions.is_unlocked_at(session, expires_at));
+        assert!(!sessions.is_unlocked_at(Uuid::new_v4(), now));
+
+        sessions.unlock(session, "1234", now).unwrap();
+        sessions.lock(session);
+        assert!(!sessions.is_unlocked_at(session, now));
+    }
+
+    #[test]
+    fn test_unlock_lockout() {
+        let pin_salt = [7u8; 32];
+        let sessions = UnlockSessions {
+            pin_salt,
+            pin_hash: Some(hash_pin(&pin_salt, "1234")),
+            timeout: Duration::minutes(5),
+            ..UnlockSessions::default()
+        };
+        let session = Uuid::new_v4();
+        let now = datetime!(2024-01-01 12:00 UTC);
+        for _ in 0..5 {
+            assert_eq!(
+                sessions.unlock(session, "0000", now),
+                Err(UnlockError::InvalidCode)
+            );
+        }
+        // even the right PIN is refused until the lockout ends
+        let locked_until = now + Duration::seconds(60);
+        assert_eq!(
+            sessions.unlock(session, "1234", now),
+            Err(UnlockError::TooManyAttempts(locked_until))
+        );
+        // other sessions aren't affected
+        assert!(sessions.unlock(Uuid::new_v4(), "1234", now).is_ok());
+
+        // the next wrong code doubles the wait
+        assert_eq!(
+            sessions.unlock(session, "0000", locked_until),
+            Err(UnlockError::InvalidCode)
+        );
+        let later = locked_until + Duration::seconds(60);
+        assert!(matches!(
+            sessions.unlock(session, "1234", later),
+            Err(UnlockError::TooManyAttempts(_))
+        ));
+        let later = locked_until + Duration::seconds(120);
+        assert!(sessions.unlock(session, "1234", later).is_ok());
+        assert!(sessions.unlock(session, "0000", later).is_err());
+        assert!(sessions.unlock(session, "1234", later).is_ok());
+    }
+
+    #[test]
+    fn test_unlock_failures_pruned() {
+        let pin_salt = [7u8; 32];
+        let sessions = UnlockSessions {
+            pin_salt,
+            pin_hash: Some(hash_pin(&pin_salt, "1234")),
+            timeout: Duration::minutes(5),
+            ..UnlockSessions::default()
+        };
+        let now = datetime!(2024-01-01 12:00 UTC);
+        let abandoned = Uuid::new_v4();
+        for _ in 0..5 {
+            assert!(sessions.unlock(abandoned, "0000", now).is_err());
+        }
+        let other = Uuid::new_v4();
+        assert!(sessions.unlock(other, "0000", now).is_err());
+        assert_eq!(sessions.failures.read().len(), 2);
+
+        // a lockout is remembered for longer than a single wrong code
+        let later = now + Duration::minutes(60) + Duration::seconds(30);
+        assert!(sessions.unlock(Uuid::new_v4(), "1234", later).is_ok());
+        assert_eq!(sessions.failures.read().len(), 1);
+
+        let later = now + Duration::hours(2);
+        assert!(sessions.unlock(Uuid::new_v4(), "1234", later).is_ok());
+        assert!(sessions.failures.read().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use rand::Rng;
+use stack_string::StackString;
+use thiserror::Error as ThisError;
+
+use crate::models::{OWNER_ROLE, VIEWER_ROLE};
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum UserError {
+    #[error("No user {0}")]
+    NotFound(StackString),
+    #[error("Invalid email {0}")]
+    InvalidEmail(StackString),
+    #[error("Role must be {OWNER_ROLE} or {VIEWER_ROLE}, not {0}")]
+    InvalidRole(StackString),
+    #[error("Unknown or expired link code")]
+    InvalidLinkCode,
+}
+
+/// Characters of link codes, without ones easily mistaken for each other
+const LINK_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_CODE_LENGTH: usize = 8;
+
+/// Code typed into a chat with the bot to link a messenger account
+pub fn generate_link_code<R: Rng>(rng: &mut R) -> StackString {
+    (0..LINK_CODE_LENGTH)
+        .map(|_| char::from(LINK_CODE_CHARS[rng.gen_range(0..LINK_CODE_CHARS.len())]))
+        .collect()
+}
+
+/// Emails are compared as given when logging in, so only reject ones which
+/// can't be addresses at all
+/// # Errors
+/// Return `UserError::InvalidEmail` if `email` isn't `user@domain`
+pub fn validate_email(email: &str) -> Result<&str, UserError> {
+    let email = email.trim();
+    match email.split_once('@') {
+        Some((user, domain))
+            if !user.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !email.contains(char::is_whitespace) =>
+        {
+            Ok(email)
+        }
+        _ => Err(UserError::InvalidEmail(email.into())),
+    }
+}
+
+/// # Errors
+/// Return `UserError::InvalidRole` if `role` isn't owner or viewer
+pub fn validate_role(role: &str) -> Result<&'static str, UserError> {
+    match role.trim().to_lowercase().as_str() {
+        OWNER_ROLE => Ok(OWNER_ROLE),
+        VIEWER_ROLE => Ok(VIEWER_ROLE),
+        _ => Err(UserError::InvalidRole(role.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::thread_rng;
+
+    use crate::users::{
+        generate_link_code, validate_email, validate_role, UserError, LINK_CODE_CHARS,
+    };
+
+    #[test]
+    fn test_validate_email() {
+        assert_eq!(validate_email(" amy@example.com "), Ok("amy@example.com"));
+        assert_eq!(
+            validate_email("amy"),
+            Err(UserError::InvalidEmail("amy".into()))
+        );
+        assert!(validate_email("@example.com").is_err());
+        assert!(validate_email("amy@").is_err());
+        assert!(validate_email("amy@bob@example.com").is_err());
+        assert!(validate_email("amy smith@example.com").is_err());
+    }
+
+    #[test]
+    fn test_validate_role() {
+        assert_eq!(validate_role("Viewer"), Ok("viewer"));
+        assert_eq!(validate_role("owner"), Ok("owner"));
+        assert_eq!(
+            validate_role("admin"),
+            Err(UserError::InvalidRole("admin".into()))
+        );
+    }
+
+    #[test]
+    fn test_generate_link_code() {
+        let mut rng = thread_rng();
+        let code = generate_link_code(&mut rng);
+        assert_eq!(code.len(), 8);
+        assert!(code.bytes().all(|c| LINK_CODE_CHARS.contains(&c)));
+        assert_ne!(code, generate_link_code(&mut rng));
+    }
+}
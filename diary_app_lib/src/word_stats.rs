@@ -0,0 +1,235 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::collections::HashMap;
+use time::{macros::format_description, util::days_in_year_month, Date, Month};
+
+use crate::models::DateRange;
+
+/// Number of terms returned by `/api/stats/words` unless asked otherwise
+pub const TOP_TERMS: i64 = 50;
+
+/// Common english words left out of the term counts
+const STOPWORDS: &[&str] = &[
+    "about",
+    "above",
+    "after",
+    "again",
+    "against",
+    "all",
+    "also",
+    "and",
+    "any",
+    "are",
+    "aren't",
+    "because",
+    "been",
+    "before",
+    "being",
+    "below",
+    "between",
+    "both",
+    "but",
+    "can",
+    "can't",
+    "cannot",
+    "could",
+    "couldn't",
+    "did",
+    "didn't",
+    "does",
+    "doesn't",
+    "doing",
+    "don't",
+    "down",
+    "during",
+    "each",
+    "few",
+    "for",
+    "from",
+    "further",
+    "got",
+    "had",
+    "hadn't",
+    "has",
+    "hasn't",
+    "have",
+    "haven't",
+    "having",
+    "he'd",
+    "he'll",
+    "he's",
+    "her",
+    "here",
+    "here's",
+    "hers",
+    "herself",
+    "him",
+    "himself",
+    "his",
+    "how",
+    "how's",
+    "i'd",
+    "i'll",
+    "i'm",
+    "i've",
+    "into",
+    "isn't",
+    "it's",
+    "its",
+    "itself",
+    "just",
+    "let's",
+    "more",
+    "most",
+    "mustn't",
+    "myself",
+    "nor",
+    "not",
+    "now",
+    "off",
+    "once",
+    "only",
+    "other",
+    "ought",
+    "our",
+    "ours",
+    "ourselves",
+    "out",
+    "over",
+    "own",
+    "same",
+    "shan't",
+    "she",
+    "she'd",
+    "she'll",
+    "she's",
+    "should",
+    "shouldn't",
+    "some",
+    "such",
+    "than",
+    "that",
+    "that's",
+    "the",
+    "their",
+    "theirs",
+    "them",
+    "themselves",
+    "then",
+    "there",
+    "there's",
+    "these",
+    "they",
+    "they'd",
+    "they'll",
+    "they're",
+    "they've",
+    "this",
+    "those",
+    "through",
+    "too",
+    "under",
+    "until",
+    "very",
+    "was",
+    "wasn't",
+    "we'd",
+    "we'll",
+    "we're",
+    "we've",
+    "were",
+    "weren't",
+    "what",
+    "what's",
+    "when",
+    "when's",
+    "where",
+    "where's",
+    "which",
+    "while",
+    "who",
+    "who's",
+    "whom",
+    "why",
+    "why's",
+    "will",
+    "with",
+    "won't",
+    "would",
+    "wouldn't",
+    "you",
+    "you'd",
+    "you'll",
+    "you're",
+    "you've",
+    "your",
+    "yours",
+    "yourself",
+    "yourselves",
+];
+
+/// Count the terms of an entry, words are lowercased and words shorter than
+/// three letters, containing digits or in `STOPWORDS` are skipped
+#[must_use]
+pub fn term_counts(text: &str) -> HashMap<StackString, i64> {
+    let mut counts = HashMap::new();
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '\'') {
+        let word = word.trim_matches('\'').to_lowercase();
+        if word.chars().count() < 3
+            || word.chars().any(|c| c.is_numeric())
+            || STOPWORDS.contains(&word.as_str())
+        {
+            continue;
+        }
+        *counts.entry(word.into()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Dates of a `range` given as a year (`2024`) or a month (`2024-03`)
+/// # Errors
+/// Return error if `range` is neither
+pub fn parse_term_range(range: &str) -> Result<DateRange, Error> {
+    if let Ok(year) = range.parse::<i32>() {
+        let since = Date::from_calendar_date(year, Month::January, 1)?;
+        let until = Date::from_calendar_date(year, Month::December, 31)?;
+        return Ok(DateRange::new(Some(since), Some(until)));
+    }
+    let since = Date::parse(
+        &format_sstr!("{range}-01"),
+        format_description!("[year]-[month]-[day]"),
+    )
+    .map_err(|_| format_err!("range must be a year or a month, not {range}"))?;
+    let until = since.replace_day(days_in_year_month(since.year(), since.month()))?;
+    Ok(DateRange::new(Some(since), Some(until)))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use crate::word_stats::{parse_term_range, term_counts};
+
+    #[test]
+    fn test_term_counts() {
+        let counts =
+            term_counts("Went hiking with Sam.\nThe hiking trail was muddy, we'll go in 2025");
+        assert_eq!(counts["hiking"], 2);
+        assert_eq!(counts["went"], 1);
+        assert_eq!(counts["sam"], 1);
+        for skipped in ["with", "the", "we'll", "go", "in", "2025"] {
+            assert!(!counts.contains_key(skipped), "{skipped}");
+        }
+    }
+
+    #[test]
+    fn test_parse_term_range() {
+        let range = parse_term_range("2024").unwrap();
+        assert_eq!(range.since, Some(date!(2024 - 01 - 01)));
+        assert_eq!(range.until, Some(date!(2024 - 12 - 31)));
+        let range = parse_term_range("2024-02").unwrap();
+        assert_eq!(range.since, Some(date!(2024 - 02 - 01)));
+        assert_eq!(range.until, Some(date!(2024 - 02 - 29)));
+        assert!(parse_term_range("last week").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+/// Metadata key holding the timestamps of the cache entries merged into an
+/// entry
+pub const WRITTEN_AT_KEY: &str = "written_at";
+
+/// When entries get written, from the timestamps of cache entries
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WritingHabits {
+    /// Number of writes in each hour of the day, local time
+    pub hours: Vec<i64>,
+    pub writes: i64,
+    /// Average hours between the start of the day an entry is about and the
+    /// time it was written, `None` without any writes
+    pub average_lag_hours: Option<f64>,
+}
+
+impl WritingHabits {
+    /// Summarize `times`, pairs of the date of an entry and the time a cache
+    /// entry was written for it, hours are those of `tz`
+    #[must_use]
+    pub fn new(times: impl IntoIterator<Item = (Date, OffsetDateTime)>, tz: &Tz) -> Self {
+        let mut hours = vec![0; 24];
+        let mut writes = 0;
+        let mut lag_seconds = 0.0;
+        for (date, written) in times {
+            let written = written.to_timezone(tz);
+            hours[usize::from(written.hour())] += 1;
+            writes += 1;
+            let lag = PrimitiveDateTime::new(written.date(), written.time())
+                - PrimitiveDateTime::new(date, Time::MIDNIGHT);
+            lag_seconds += lag.as_seconds_f64();
+        }
+        let average_lag_hours = if writes > 0 {
+            Some(lag_seconds / 3600.0 / writes as f64)
+        } else {
+            None
+        };
+        Self {
+            hours,
+            writes,
+            average_lag_hours,
+        }
+    }
+
+    /// Hour of the day with the most writes
+    #[must_use]
+    pub fn peak_hour(&self) -> Option<usize> {
+        if self.writes == 0 {
+            return None;
+        }
+        self.hours
+            .iter()
+            .enumerate()
+            .max_by_key(|(hour, count)| (**count, std::cmp::Reverse(*hour)))
+            .map(|(hour, _)| hour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, datetime};
+    use time_tz::timezones::db::{america::NEW_YORK, UTC};
+
+    use crate::writing_habits::WritingHabits;
+
+    #[test]
+    fn test_writing_habits() {
+        let habits = WritingHabits::new(
+            [
+                (date!(2024 - 03 - 01), datetime!(2024-03-01 21:30 UTC)),
+                (date!(2024 - 03 - 01), datetime!(2024-03-01 22:30 UTC)),
+                (date!(2024 - 03 - 01), datetime!(2024-03-02 09:00 UTC)),
+                (date!(2024 - 03 - 02), datetime!(2024-03-02 21:00 UTC)),
+            ],
+            UTC,
+        );
+        assert_eq!(habits.writes, 4);
+        assert_eq!(habits.hours[21], 2);
+        assert_eq!(habits.hours[22], 1);
+        assert_eq!(habits.hours[9], 1);
+        assert_eq!(habits.hours.iter().sum::<i64>(), 4);
+        assert_eq!(habits.peak_hour(), Some(21));
+        assert_eq!(
+            habits.average_lag_hours,
+            Some((21.5 + 22.5 + 33.0 + 21.0) / 4.0)
+        );
+
+        let habits = WritingHabits::new(
+            [(date!(2024 - 03 - 01), datetime!(2024-03-02 02:00 UTC))],
+            NEW_YORK,
+        );
+        assert_eq!(habits.hours[21], 1);
+        assert_eq!(habits.average_lag_hours, Some(21.0));
+
+        let habits = WritingHabits::new([], UTC);
+        assert_eq!(habits.hours, vec![0; 24]);
+        assert_eq!(habits.peak_hour(), None);
+        assert_eq!(habits.average_lag_hours, None);
+    }
+}
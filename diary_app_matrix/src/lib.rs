@@ -0,0 +1,4 @@
+#![allow(clippy::too_many_lines)]
+#![allow(clippy::module_name_repetitions)]
+
+pub mod matrix_bot;
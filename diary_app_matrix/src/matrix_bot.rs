@@ -0,0 +1,223 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use futures::future::join;
+use log::{debug, error};
+use matrix_sdk::{
+    config::SyncSettings,
+    ruma::events::room::{
+        member::StrippedRoomMemberEvent,
+        message::{MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+    },
+    Client, Room, RoomState,
+};
+use once_cell::sync::Lazy;
+use stack_string::{format_sstr, StackString};
+use tokio::{
+    sync::mpsc::{channel, Receiver},
+    task::{spawn, JoinHandle},
+    time::{sleep, Duration},
+};
+
+use diary_app_bot::failure_count::FailureCount;
+use diary_app_lib::{
+    bot_core::{BotCore, BotReply, IncomingMessage, Messenger},
+    config::Config,
+    diary_app_interface::DiaryAppInterface,
+    error_reporting,
+    models::AuthorizedUsers,
+    pgpool::PgPool,
+    users::UserError,
+};
+
+static FAILURE_COUNT: Lazy<FailureCount> = Lazy::new(|| FailureCount::new(5));
+
+#[derive(Clone)]
+struct MatrixLogin {
+    homeserver: StackString,
+    username: StackString,
+    password: StackString,
+}
+
+impl MatrixLogin {
+    fn from_config(config: &Config) -> Result<Self, Error> {
+        match (
+            &config.matrix_homeserver,
+            &config.matrix_username,
+            &config.matrix_password,
+        ) {
+            (Some(homeserver), Some(username), Some(password)) => Ok(Self {
+                homeserver: homeserver.clone(),
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => Err(format_err!(
+                "matrix_homeserver, matrix_username and matrix_password must be set"
+            )),
+        }
+    }
+}
+
+/// matrix-sdk pushes events to handlers, they are forwarded over a channel
+/// so that `BotCore` can pull them
+struct MatrixMessenger {
+    recv: Receiver<IncomingMessage<Room>>,
+    sync_task: JoinHandle<Result<(), matrix_sdk::Error>>,
+}
+
+impl MatrixMessenger {
+    async fn login(login: &MatrixLogin, core: BotCore) -> Result<Self, Error> {
+        let client = Client::builder()
+            .homeserver_url(login.homeserver.as_str())
+            .build()
+            .await?;
+        client
+            .matrix_auth()
+            .login_username(login.username.as_str(), login.password.as_str())
+            .initial_device_display_name("diary-app-matrix")
+            .await?;
+        // skip messages sent while the bot was offline
+        let response = client.sync_once(SyncSettings::default()).await?;
+
+        let (send, recv) = channel(16);
+        client.add_event_handler(
+            move |event: OriginalSyncRoomMessageEvent, room: Room, client: Client| {
+                let send = send.clone();
+                async move {
+                    if room.state() != RoomState::Joined
+                        || client.user_id() == Some(event.sender.as_ref())
+                    {
+                        return;
+                    }
+                    let MessageType::Text(content) = event.content.msgtype else {
+                        return;
+                    };
+                    debug!("{} {}", event.sender, content.body);
+                    let message = IncomingMessage {
+                        sender: event.sender.as_str().into(),
+                        sender_name: event.sender.localpart().into(),
+                        text: content.body.into(),
+                        chat: room,
+                    };
+                    if send.send(message).await.is_err() {
+                        error!("matrix bot stopped receiving messages");
+                    }
+                }
+            },
+        );
+        client.add_event_handler(
+            move |event: StrippedRoomMemberEvent, client: Client, room: Room| {
+                let core = core.clone();
+                async move {
+                    // join rooms authorized users invite the bot to
+                    if client.user_id() != Some(event.state_key.as_ref())
+                        || room.state() != RoomState::Invited
+                        || core.get_user(event.sender.as_str()).await.is_none()
+                    {
+                        return;
+                    }
+                    if let Err(e) = room.join().await {
+                        error!("failed to join {} {e}", room.room_id());
+                    }
+                }
+            },
+        );
+        let sync_task = spawn(async move {
+            client
+                .sync(SyncSettings::default().token(response.next_batch))
+                .await
+        });
+        Ok(Self { recv, sync_task })
+    }
+}
+
+impl Drop for MatrixMessenger {
+    fn drop(&mut self) {
+        self.sync_task.abort();
+    }
+}
+
+#[async_trait]
+impl Messenger for MatrixMessenger {
+    type Chat = Room;
+
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    fn user_id(user: &AuthorizedUsers) -> Option<StackString> {
+        user.matrix_userid.clone()
+    }
+
+    async fn receive(&mut self) -> Result<Option<IncomingMessage<Room>>, Error> {
+        match self.recv.recv().await {
+            Some(message) => Ok(Some(message)),
+            None => Err(format_err!("matrix sync stopped")),
+        }
+    }
+
+    async fn send(&self, chat: &Room, reply: &BotReply) -> Result<(), Error> {
+        // no buttons in plain matrix messages, list the commands they'd send
+        let mut text = reply.text.clone();
+        for (label, command) in reply.buttons.iter().chain(reply.links.iter()) {
+            text.push_str(&format_sstr!("\n{label}: {command}"));
+        }
+        chat.send(RoomMessageEventContent::text_plain(text.as_str()))
+            .await?;
+        Ok(())
+    }
+
+    async fn link_user(dapp: &DiaryAppInterface, email: &str, sender: &str) -> Result<(), Error> {
+        if !AuthorizedUsers::set_matrix_userid(email, Some(sender), &dapp.pool).await? {
+            return Err(UserError::NotFound(email.into()).into());
+        }
+        Ok(())
+    }
+}
+
+async fn matrix_worker(core: BotCore, login: MatrixLogin) -> Result<(), Error> {
+    loop {
+        FAILURE_COUNT.check()?;
+        let result = match MatrixMessenger::login(&login, core.clone()).await {
+            Ok(messenger) => core.run(messenger).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => FAILURE_COUNT.reset()?,
+            Err(e) => {
+                error!("matrix bot failed {e}");
+                FAILURE_COUNT.increment()?;
+                sleep(Duration::from_secs(10)).await;
+            }
+        }
+    }
+}
+
+async fn fill_matrix_user_ids(core: BotCore) -> Result<(), Error> {
+    loop {
+        FAILURE_COUNT.check()?;
+        if core.refresh_users::<MatrixMessenger>().await.is_ok() {
+            FAILURE_COUNT.reset()?;
+        } else {
+            FAILURE_COUNT.increment()?;
+        }
+        sleep(Duration::from_secs(60)).await;
+    }
+}
+
+/// # Errors
+/// Returns error if config fails or bot fails
+pub async fn run_bot() -> Result<(), Error> {
+    let config = Config::init_config()?;
+    let _reporting = error_reporting::init(&config, "diary-app-matrix");
+    let login = MatrixLogin::from_config(&config)?;
+    let pool = PgPool::new(&config.database_url)?.with_retry_policy(config.retry_policy());
+    let sdk_config = aws_config::load_from_env().await;
+    let dapp = DiaryAppInterface::new(config, &sdk_config, pool);
+    let core = BotCore::new(dapp);
+
+    let userid_handle = fill_matrix_user_ids(core.clone());
+    let matrix_handle = matrix_worker(core, login);
+
+    let (r0, r1) = join(userid_handle, matrix_handle).await;
+    r0.and(r1)
+}
@@ -0,0 +1,9 @@
+CREATE TABLE sync_state (
+    client_id TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    client_version BIGINT NOT NULL,
+    server_modified TIMESTAMP WITH TIME ZONE NOT NULL,
+    hash TEXT NOT NULL,
+    last_sync TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (client_id, diary_date)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE diary_entries ADD COLUMN is_encrypted BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE diary_entries ADD COLUMN diary_ciphertext BYTEA;
+ALTER TABLE diary_entries ADD COLUMN diary_nonce BYTEA;
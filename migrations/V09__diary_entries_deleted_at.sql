@@ -0,0 +1 @@
+ALTER TABLE diary_entries ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,5 @@
+CREATE TABLE diary_tombstones (
+    diary_date DATE NOT NULL PRIMARY KEY,
+    deleted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE diary_entries ADD COLUMN starred BOOLEAN NOT NULL DEFAULT false;
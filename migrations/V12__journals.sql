@@ -0,0 +1,20 @@
+CREATE TABLE journals (
+    journal_name TEXT NOT NULL PRIMARY KEY,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+INSERT INTO journals (journal_name) VALUES ('diary');
+
+ALTER TABLE diary_entries ADD COLUMN journal TEXT NOT NULL DEFAULT 'diary' REFERENCES journals (journal_name);
+ALTER TABLE diary_entries DROP CONSTRAINT diary_entries_pkey;
+ALTER TABLE diary_entries ADD PRIMARY KEY (journal, diary_date);
+
+ALTER TABLE diary_cache ADD COLUMN journal TEXT NOT NULL DEFAULT 'diary' REFERENCES journals (journal_name);
+ALTER TABLE diary_conflict ADD COLUMN journal TEXT NOT NULL DEFAULT 'diary';
+
+ALTER TABLE diary_tombstones ADD COLUMN journal TEXT NOT NULL DEFAULT 'diary';
+ALTER TABLE diary_tombstones DROP CONSTRAINT diary_tombstones_pkey;
+ALTER TABLE diary_tombstones ADD PRIMARY KEY (journal, diary_date);
+
+ALTER TABLE sync_state ADD COLUMN journal TEXT NOT NULL DEFAULT 'diary';
+ALTER TABLE sync_state DROP CONSTRAINT sync_state_pkey;
+ALTER TABLE sync_state ADD PRIMARY KEY (client_id, journal, diary_date);
@@ -0,0 +1 @@
+ALTER TABLE diary_entries ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}'::jsonb;
@@ -0,0 +1 @@
+ALTER TABLE diary_entries ADD COLUMN scheduled BOOLEAN NOT NULL DEFAULT false;
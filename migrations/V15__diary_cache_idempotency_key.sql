@@ -0,0 +1 @@
+ALTER TABLE diary_cache ADD COLUMN idempotency_key UUID UNIQUE;
@@ -0,0 +1,6 @@
+CREATE TABLE user_settings (
+    email TEXT NOT NULL PRIMARY KEY REFERENCES authorized_users (email),
+    resurface BOOLEAN NOT NULL DEFAULT false,
+    last_resurfaced DATE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+CREATE TABLE diary_redactions (
+    id UUID NOT NULL PRIMARY KEY,
+    journal TEXT NOT NULL DEFAULT 'diary',
+    diary_date DATE NOT NULL,
+    ciphertext BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX diary_redactions_date_idx ON diary_redactions (journal, diary_date);
@@ -0,0 +1,3 @@
+ALTER TABLE journals ADD COLUMN shared BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE diary_cache ADD COLUMN author TEXT;
+ALTER TABLE diary_conflict ADD COLUMN author TEXT;
@@ -0,0 +1,12 @@
+ALTER TABLE authorized_users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner';
+
+CREATE TABLE diary_comments (
+    id UUID NOT NULL PRIMARY KEY,
+    journal TEXT NOT NULL DEFAULT 'diary',
+    diary_date DATE NOT NULL,
+    author TEXT NOT NULL,
+    comment_text TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX diary_comments_date_idx ON diary_comments (journal, diary_date);
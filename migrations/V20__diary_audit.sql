@@ -0,0 +1,10 @@
+CREATE TABLE diary_audit (
+    id UUID NOT NULL PRIMARY KEY,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    email TEXT,
+    action TEXT NOT NULL,
+    journal TEXT NOT NULL DEFAULT 'diary',
+    diary_date DATE,
+    detail TEXT NOT NULL DEFAULT ''
+);
+CREATE INDEX diary_audit_created_at_idx ON diary_audit (created_at);
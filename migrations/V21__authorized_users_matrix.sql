@@ -0,0 +1 @@
+ALTER TABLE authorized_users ADD COLUMN matrix_userid TEXT;
@@ -0,0 +1,5 @@
+CREATE TABLE user_link_codes (
+    code TEXT NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE diary_entries ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';
@@ -0,0 +1,8 @@
+CREATE TABLE s3_entry_state (
+    journal TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    uploaded_text TEXT NOT NULL,
+    delta_count INTEGER NOT NULL DEFAULT 0,
+    uploaded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (journal, diary_date)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE sync_watermarks (
+    journal TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    backend TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    modified TIMESTAMP WITH TIME ZONE NOT NULL,
+    synced_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (journal, diary_date, backend)
+);
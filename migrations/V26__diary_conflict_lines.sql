@@ -0,0 +1,2 @@
+ALTER TABLE diary_conflict ADD COLUMN old_line INTEGER;
+ALTER TABLE diary_conflict ADD COLUMN new_line INTEGER;
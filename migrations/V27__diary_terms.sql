@@ -0,0 +1,9 @@
+CREATE TABLE diary_terms (
+    journal TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    term TEXT NOT NULL,
+    count BIGINT NOT NULL,
+    PRIMARY KEY (journal, diary_date, term),
+    FOREIGN KEY (journal, diary_date) REFERENCES diary_entries (journal, diary_date) ON DELETE CASCADE
+);
+CREATE INDEX diary_terms_term ON diary_terms (journal, term);
@@ -0,0 +1,12 @@
+ALTER TABLE diary_cache ADD COLUMN source TEXT;
+CREATE TABLE diary_subentries (
+    journal TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    diary_datetime TIMESTAMP WITH TIME ZONE NOT NULL,
+    diary_text TEXT NOT NULL,
+    source TEXT,
+    author TEXT,
+    PRIMARY KEY (journal, diary_datetime),
+    FOREIGN KEY (journal, diary_date) REFERENCES diary_entries (journal, diary_date) ON DELETE CASCADE
+);
+CREATE INDEX diary_subentries_date ON diary_subentries (journal, diary_date);
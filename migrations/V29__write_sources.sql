@@ -0,0 +1,2 @@
+ALTER TABLE diary_entries ADD COLUMN source TEXT;
+ALTER TABLE diary_audit ADD COLUMN source TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE diary_pending_appends (
+    id UUID NOT NULL PRIMARY KEY,
+    journal TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    diary_text TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
@@ -0,0 +1,29 @@
+CREATE OR REPLACE FUNCTION diary_notify_change() RETURNS trigger AS $$
+DECLARE
+    changed RECORD;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+    PERFORM pg_notify(
+        'diary_changes',
+        json_build_object(
+            'table', TG_TABLE_NAME,
+            'operation', lower(TG_OP),
+            'journal', changed.journal,
+            'diary_date', changed.diary_date
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER diary_entries_notify
+    AFTER INSERT OR UPDATE OR DELETE ON diary_entries
+    FOR EACH ROW EXECUTE FUNCTION diary_notify_change();
+
+CREATE TRIGGER diary_conflict_notify
+    AFTER INSERT OR UPDATE OR DELETE ON diary_conflict
+    FOR EACH ROW EXECUTE FUNCTION diary_notify_change();
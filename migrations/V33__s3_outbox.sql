@@ -0,0 +1,10 @@
+CREATE TABLE s3_outbox (
+    journal TEXT NOT NULL,
+    diary_date DATE NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (journal, diary_date)
+);
+CREATE INDEX s3_outbox_next_attempt_at_idx ON s3_outbox (next_attempt_at);
@@ -0,0 +1,14 @@
+ALTER TABLE diary_conflict ADD COLUMN conflict_group UUID;
+
+UPDATE diary_conflict c
+SET conflict_group = g.conflict_group
+FROM (
+    SELECT journal, sync_datetime, gen_random_uuid() AS conflict_group
+    FROM diary_conflict
+    GROUP BY journal, sync_datetime
+) g
+WHERE c.journal = g.journal AND c.sync_datetime = g.sync_datetime;
+
+ALTER TABLE diary_conflict ALTER COLUMN conflict_group SET NOT NULL;
+
+CREATE INDEX diary_conflict_group_idx ON diary_conflict (conflict_group);
@@ -0,0 +1 @@
+ALTER TABLE sync_state ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT false;
@@ -2,12 +2,16 @@ use anyhow::{format_err, Error};
 use std::{collections::HashSet, fs::read_to_string, path::Path};
 use time::{macros::format_description, Date};
 
-use diary_app_lib::{config::Config, models::DiaryEntries, pgpool::PgPool};
+use diary_app_lib::{
+    config::Config,
+    models::{DiaryEntries, DEFAULT_JOURNAL},
+    pgpool::PgPool,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let config = Config::init_config()?;
-    let pool = PgPool::new(&config.database_url)?;
+    let pool = PgPool::new(&config.database_url)?.with_retry_policy(config.retry_policy());
     let home_dir = dirs::home_dir().ok_or_else(|| format_err!("No HOME directory"))?;
     let diary_dir = home_dir.join("tmp").join("gdrive_diary_parsed");
     let elog_dir = home_dir.join("tmp").join("gdrive_elog_parsed");
@@ -23,7 +27,7 @@ async fn main() -> Result<(), Error> {
         let mut elog_text = String::new();
         let mut elog_length = None;
 
-        if let Some(entry) = DiaryEntries::get_by_date(*date, &pool).await? {
+        if let Some(entry) = DiaryEntries::get_by_date(DEFAULT_JOURNAL, *date, &pool).await? {
             original_length.replace(entry.diary_text.len());
             original_text = entry.diary_text.to_string();
         }
@@ -0,0 +1,9 @@
+#![allow(clippy::semicolon_if_nothing_returned)]
+
+use diary_app_matrix::matrix_bot::run_bot;
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    Box::pin(run_bot()).await.unwrap();
+}